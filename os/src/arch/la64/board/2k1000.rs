@@ -1,11 +1,27 @@
 use crate::config::HIGH_BASE_EIGHT;
+use crate::drivers::block::Storage;
 
 pub const MMIO: &[(usize, usize)] = &[];
 
-use crate::drivers::block::MemBlockWrapper;
-pub type BlockDeviceImpl = MemBlockWrapper;
+/// The SATA disk is preferred, the image loaded with the kernel is the last resort
+pub const STORAGE: &[Storage] = &[
+    Storage::Ahci,
+    Storage::Sdhci,
+    Storage::Virtio,
+    Storage::RamDisk,
+];
 
 pub const ROOT_BASE_ADDR: usize = 0x00e0_0000;
 pub const BLOCK_SZ: usize = 2048;
 pub const UART_BASE: usize = 0x1FE2_0000 + HIGH_BASE_EIGHT;
 pub const ACPI_BASE: usize = 0x1FE2_7000;
+/// The RTC block, at `0x100D_0100` on the LS7A bridge of QEMU's `virt` machine instead
+pub const RTC_BASE: usize = 0x1FE2_7800 + HIGH_BASE_EIGHT;
+/// Configuration space of the internal PCI bus, where the SATA controller sits
+pub const PCI_CONFIG_BASE: usize = 0xFE_0000_0000 + HIGH_BASE_EIGHT;
+/// The device number starts at bit 11 of a configuration space address, the function at bit 8
+pub const PCI_DEVICE_SHIFT: usize = 11;
+/// (base, length) of the PCI memory space, the firmware assigns the internal devices already
+pub const PCI_MEM_WINDOW: (usize, usize) = (0x4000_0000, 0x4000_0000);
+/// The legacy I/O interrupt controller, the registers of its first bank of 32 sources
+pub const LIOINTC_BASE: usize = 0x1FE0_1400 + HIGH_BASE_EIGHT;
@@ -0,0 +1,31 @@
+//! QEMU's `ls2k` machine, which models the 2K1000.
+//! `-hda` attaches a disk to its AHCI controller,
+//! `-device sdhci-pci -device sd-card,drive=<id>` adds an SD card to the PCI bus,
+//! `-device virtio-blk-pci,drive=<id>` a virtio disk, which is tried first.
+use crate::config::HIGH_BASE_EIGHT;
+use crate::drivers::block::Storage;
+
+pub const MMIO: &[(usize, usize)] = &[];
+
+/// Without a virtio disk, `-hda` or an SD card the image loaded with the kernel is used
+pub const STORAGE: &[Storage] = &[
+    Storage::Virtio,
+    Storage::Ahci,
+    Storage::Sdhci,
+    Storage::RamDisk,
+];
+
+pub const ROOT_BASE_ADDR: usize = 0x00e0_0000;
+pub const BLOCK_SZ: usize = 2048;
+pub const UART_BASE: usize = 0x1FE2_0000 + HIGH_BASE_EIGHT;
+pub const ACPI_BASE: usize = 0x1FE2_7000;
+/// The RTC block, at `0x100D_0100` on the LS7A bridge of QEMU's `virt` machine instead
+pub const RTC_BASE: usize = 0x1FE2_7800 + HIGH_BASE_EIGHT;
+/// Configuration space of the PCI bus, laid out as on the 2K1000
+pub const PCI_CONFIG_BASE: usize = 0xFE_0000_0000 + HIGH_BASE_EIGHT;
+/// The device number starts at bit 11 of a configuration space address, the function at bit 8
+pub const PCI_DEVICE_SHIFT: usize = 11;
+/// (base, length) of the PCI memory space, devices added with `-device` are placed here
+pub const PCI_MEM_WINDOW: (usize, usize) = (0x4000_0000, 0x4000_0000);
+/// The legacy I/O interrupt controller, the registers of its first bank of 32 sources
+pub const LIOINTC_BASE: usize = 0x1FE0_1400 + HIGH_BASE_EIGHT;
@@ -19,6 +19,13 @@ pub const KSTACK_PG_NUM_SHIFT: usize = 2usize.trailing_zeros() as usize;
 
 pub const KERNEL_STACK_SIZE: usize = PAGE_SIZE << KSTACK_PG_NUM_SHIFT;
 pub const KERNEL_HEAP_SIZE: usize = PAGE_SIZE * 0x3000;
+/// Number of cores we try to bring up. Keep `entry.asm` (boot stacks) in sync.
+#[cfg(feature = "board_2k1000")]
+pub const MAX_CPU_NUM: usize = 2;
+#[cfg(not(feature = "board_2k1000"))]
+pub const MAX_CPU_NUM: usize = 4;
+/// Each core runs its idle loop on its own boot stack.
+pub const BOOT_STACK_SIZE: usize = PAGE_SIZE * 16;
 
 // Addresses
 /// Maximum length of a physical address
@@ -75,6 +82,8 @@ pub const USR_MMAP_END: usize = TRAP_CONTEXT_BASE - PAGE_SIZE;
 pub const USR_MMAP_BASE: usize = USR_MMAP_END - USR_SPACE_LEN / 8;
 pub const TASK_SIZE: usize = USR_MMAP_BASE - USR_SPACE_LEN / 8;
 pub const ELF_DYN_BASE: usize = (((TASK_SIZE - LA_START) / 3 * 2) | LA_START) & (!(PAGE_SIZE - 1));
+/// Where flat binaries are loaded, the usual base of LoongArch executables
+pub const FLAT_LOAD_BASE: usize = LA_START;
 
 pub const MMAP_BASE: usize = 0xFFFF_FF80_0000_0000;
 pub const MMAP_END: usize = 0xFFFF_FFFF_FFFF_0000;
@@ -120,6 +129,8 @@ macro_rules! def_cpu_cfg {
     };
 }
 def_cpu_cfg!(CPUCfg0, 0);
+def_cpu_cfg!(CPUCfg1, 1);
+def_cpu_cfg!(CPUCfg2, 2);
 def_cpu_cfg!(CPUCfg4, 4);
 def_cpu_cfg!(CPUCfg5, 5);
 impl CPUCfg0 {
@@ -130,6 +141,18 @@ impl CPUCfg0 {
         (self.get_bits(4, 11) + 1) as usize
     }
 }
+impl CPUCfg1 {
+    /// 0: LA32R, 1: LA32, 2: LA64
+    pub fn get_arch(&self) -> u32 {
+        self.get_bits(0, 1)
+    }
+    pub fn get_palen(&self) -> usize {
+        (self.get_bits(4, 11) + 1) as usize
+    }
+    pub fn get_valen(&self) -> usize {
+        (self.get_bits(12, 19) + 1) as usize
+    }
+}
 #[macro_export]
 macro_rules! newline {
     () => {
@@ -21,12 +21,25 @@ _start:
     sub.d       $t0,    $t0,    $t0
     csrwr       $t0,    0x181
     sub.d       $t0,    $t0,    $t0
-    la.global $sp, boot_stack_top
+    # 每个核使用自己的启动栈: sp = boot_stack_top - cpuid * BOOT_STACK_SIZE
+    csrrd       $t1,    0x20    # CPUID
+    la.global   $sp,    boot_stack_top
+    slli.d      $t2,    $t1,    16
+    sub.d       $sp,    $sp,    $t2
+    beqz        $t1,    2f
+    # 从核: 在 mailbox 0 上自旋, 直到主核完成初始化后写入启动地址
+    lu12i.w     $t2,    0x1
+    ori         $t2,    $t2,    0x20    # LOONGARCH_IOCSR_MBUF0
+1:
+    iocsrrd.d   $t3,    $t2
+    beqz        $t3,    1b
+    bl          rust_main_secondary
+2:
     bl          rust_main
 
     .section .bss.stack
     .globl boot_stack
 boot_stack:
-    .space 4096 * 16
+    .space 4096 * 16 * 4    # BOOT_STACK_SIZE * MAX_CPU_NUM
     .globl boot_stack_top
 boot_stack_top:
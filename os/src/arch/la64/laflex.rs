@@ -1,4 +1,7 @@
-use super::{tlb::tlb_invalidate, tlb_global_invalidate};
+use super::{
+    tlb::{tlb_invalidate, tlb_invalidate_local},
+    tlb_global_invalidate,
+};
 use crate::{
     config::{
         MEMORY_HIGH_BASE, MEMORY_HIGH_BASE_VPN, MEMORY_SIZE, PAGE_SIZE, PAGE_SIZE_BITS, PALEN,
@@ -252,7 +255,8 @@ impl LAFlexPageTable {
         self.find_pte_refmut(vpn).map(|i| &*i)
     }
     pub fn set_dirty_bit(&mut self, vpn: VirtPageNum) -> Result<(), ()> {
-        tlb_invalidate();
+        // granting D only, stale entries on other cores just fault once more
+        tlb_invalidate_local();
         if self.is_ident_map(vpn) {
             unsafe {
                 DIRTY[vpn.0 & VA_MASK] = true;
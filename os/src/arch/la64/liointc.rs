@@ -0,0 +1,89 @@
+//! The legacy I/O interrupt controller of the 2K1000, at `board::LIOINTC_BASE`.
+//! 64 sources in two banks of 32, each routed to a set of cores and one of the INT0-3 outputs,
+//! which are the HWI0-3 lines of a core. Everything enabled here goes to HWI0 of core 0 as a
+//! level triggered source, so a device is acknowledged by its driver and not by the controller.
+//! 路由寄存器的布局参照 Linux 的 irq-loongson-liointc.c, QEMU 的 ls2k 是否同样连线未经验证.
+use super::board::LIOINTC_BASE;
+use crate::drivers::block::handle_block_irq;
+use core::ptr::{read_volatile, write_volatile};
+
+pub const SOURCES: usize = 64;
+const SOURCES_PER_BANK: usize = 32;
+const BANK_SIZE: usize = 0x40;
+
+mod offsets {
+    /// One byte per source, the cores in bits 0-3 and the output in bits 4-7
+    pub const ROUTE: usize = 0x00;
+    /// The enabled sources which are asserted
+    pub const STATUS: usize = 0x20;
+    /// Write 1 to enable a source
+    pub const ENABLE: usize = 0x28;
+    /// Write 1 to disable a source
+    pub const DISABLE: usize = 0x2c;
+    /// Set for an active low source
+    pub const POL: usize = 0x30;
+    /// Set for an edge triggered source
+    pub const EDGE: usize = 0x34;
+}
+
+mod masks {
+    pub const ROUTE_CORE0: u8 = 1 << 0;
+    pub const ROUTE_INT0: u8 = 1 << 4;
+}
+
+fn bank(irq: usize) -> (usize, u32) {
+    (
+        LIOINTC_BASE + irq / SOURCES_PER_BANK * BANK_SIZE,
+        1 << (irq % SOURCES_PER_BANK),
+    )
+}
+
+fn read_reg(bank: usize, offset: usize) -> u32 {
+    unsafe { read_volatile((bank + offset) as *const u32) }
+}
+
+fn write_reg(bank: usize, offset: usize, value: u32) {
+    unsafe { write_volatile((bank + offset) as *mut u32, value) }
+}
+
+/// Route source `irq` to HWI0 of core 0 as active high and level triggered, then unmask it
+pub fn enable(irq: usize) {
+    assert!(irq < SOURCES);
+    let (bank, mask) = bank(irq);
+    unsafe {
+        write_volatile(
+            (bank + offsets::ROUTE + irq % SOURCES_PER_BANK) as *mut u8,
+            masks::ROUTE_CORE0 | masks::ROUTE_INT0,
+        )
+    };
+    write_reg(bank, offsets::EDGE, read_reg(bank, offsets::EDGE) & !mask);
+    write_reg(bank, offsets::POL, read_reg(bank, offsets::POL) & !mask);
+    write_reg(bank, offsets::ENABLE, mask);
+}
+
+pub fn disable(irq: usize) {
+    let (bank, mask) = bank(irq);
+    write_reg(bank, offsets::DISABLE, mask);
+}
+
+/// The enabled sources which are asserted, bit `n` for source `n`
+pub fn pending() -> u64 {
+    let low = read_reg(bank(0).0, offsets::STATUS) as u64;
+    let high = read_reg(bank(SOURCES_PER_BANK).0, offsets::STATUS) as u64;
+    low | high << 32
+}
+
+/// Serve the asserted sources, called from HWI0 and by idle cores since the kernel runs with
+/// interrupts off. A source no driver claims is disabled, being level triggered it would
+/// trap again as soon as we return.
+pub fn handle_irqs() {
+    let mut pending = pending();
+    while pending != 0 {
+        let irq = pending.trailing_zeros() as usize;
+        pending &= pending - 1;
+        if !handle_block_irq(irq) {
+            log::warn!("[liointc] no handler for source {}, disabled", irq);
+            disable(irq);
+        }
+    }
+}
@@ -1,17 +1,21 @@
-#[path = "board/2k1000.rs"]
+#[cfg_attr(feature = "board_2k1000", path = "board/2k1000.rs")]
+#[cfg_attr(not(feature = "board_2k1000"), path = "board/laqemu.rs")]
 pub mod board;
 pub mod config;
 pub mod laflex;
+pub mod liointc;
 #[macro_use]
 mod mem_reg_macro;
 mod acpi;
 mod sbi;
+pub mod smp;
 pub mod switch;
 pub mod time;
 pub mod trap;
 pub type KernelPageTableImpl = laflex::LAFlexPageTable;
 pub type PageTableImpl = laflex::LAFlexPageTable;
 pub use sbi::{console_flush, console_getchar, console_putchar, shutdown};
+pub use smp::{cpu_id, cpu_online_mask, send_ipi_others, start_secondary_cpus, IpiAction};
 pub use switch::__switch;
 pub use tlb::{tlb_global_invalidate, tlb_invalidate};
 pub mod syscall_id;
@@ -27,6 +31,7 @@ use crate::{
 };
 
 use self::{time::get_timer_freq_first_time, trap::strampoline};
+use alloc::string::String;
 pub use board::BLOCK_SZ;
 pub use kern_stack::{trap_cx_bottom_from_tid, ustack_bottom_from_tid, KernelStack};
 pub use register::*;
@@ -59,18 +64,80 @@ pub fn machine_init() {
     println!("{:?}", Misc::read());
     println!("{:?}", RVACfg::read());
     println!("[machine_init] MMAP_BASE: {:#x}", MMAP_BASE);
+    smp::init();
     trap::enable_timer_interrupt();
 }
+/// Per-core part of `machine_init`, run by the secondary cores.
+pub fn machine_init_secondary() {
+    trap::init();
+    smp::init();
+    trap::enable_timer_interrupt();
+}
+/// Content of `/proc/cpuinfo`, decoded from CPUCFG of the current core.
+/// All cores are assumed to be identical.
+pub fn cpuinfo() -> String {
+    // (CPUCFG word, bit, name)
+    const FEATURES: [(usize, usize, &str); 9] = [
+        (2, 22, "lam"),
+        (1, 20, "ual"),
+        (2, 0, "fpu"),
+        (2, 6, "lsx"),
+        (2, 7, "lasx"),
+        (1, 25, "crc32"),
+        (2, 8, "complex"),
+        (2, 9, "crypto"),
+        (2, 10, "lvz"),
+    ];
+    let prid = config::CPUCfg0::read().get_bits(0, 31);
+    let cfg1 = config::CPUCfg1::read();
+    let cfg2 = config::CPUCfg2::read();
+    let isa = match cfg1.get_arch() {
+        2 => "loongarch32 loongarch64",
+        1 => "loongarch32",
+        _ => "loongarch32r",
+    };
+    let features = FEATURES
+        .iter()
+        .filter(|(word, bit, _)| match word {
+            1 => cfg1.get_bit(*bit),
+            _ => cfg2.get_bit(*bit),
+        })
+        .fold(String::from("cpucfg"), |mut features, (_, _, name)| {
+            features.push(' ');
+            features.push_str(name);
+            features
+        });
+    let online = smp::cpu_online_mask();
+    let mut info = String::from("system type\t\t: generic-loongson-machine\n\n");
+    for cpu in (0..config::MAX_CPU_NUM).filter(|cpu| online & (1 << cpu) != 0) {
+        info.push_str(&alloc::format!(
+            "processor\t\t: {}\n\
+             package\t\t\t: 0\n\
+             core\t\t\t: {}\n\
+             CPU Family\t\t: Loongson-64bit\n\
+             CPU Revision\t\t: {:#04x}\n\
+             CPU MHz\t\t\t: {}.00\n\
+             Address Sizes\t\t: {} bits physical, {} bits virtual\n\
+             ISA\t\t\t: {}\n\
+             Features\t\t: {}\n\n",
+            cpu,
+            cpu,
+            prid & 0xff,
+            time::get_clock_freq() / 1_000_000,
+            cfg1.get_palen(),
+            cfg1.get_valen(),
+            isa,
+            features
+        ));
+    }
+    info
+}
 pub fn pre_start_init() {
     EEntry::empty().set_exception_entry(strampoline as usize);
 }
 pub fn bootstrap_init() {
-    #[cfg(feature = "board_2k1000")]
-    if CPUId::read().get_core_id() != 0 {
-         loop {}
-    };
     ECfg::empty()
-        .set_line_based_interrupt_vector(LineBasedInterrupt::TIMER)
+        .set_line_based_interrupt_vector(LineBasedInterrupt::TIMER | LineBasedInterrupt::IPI)
         .write();
     EUEn::read().set_float_point_stat(true).write();
     // Timer & other Interrupts
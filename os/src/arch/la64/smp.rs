@@ -0,0 +1,119 @@
+//! Secondary core bring-up and inter-processor interrupts.
+//! 龙芯的核间通信通过 IOCSR 空间中每个核私有的 IPI 寄存器组和 mailbox 完成.
+use super::config::MAX_CPU_NUM;
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use register::CPUId;
+
+const IOCSR_IPI_STATUS: usize = 0x1000;
+const IOCSR_IPI_EN: usize = 0x1004;
+const IOCSR_IPI_CLEAR: usize = 0x100c;
+const IOCSR_MBUF_SEND: usize = 0x1048;
+const IOCSR_IPI_SEND: usize = 0x1040;
+
+const IPI_SEND_BLOCKING: usize = 1 << 31;
+const IPI_SEND_CPU_SHIFT: usize = 16;
+const MBUF_SEND_BLOCKING: usize = 1 << 31;
+const MBUF_SEND_BOX_SHIFT: usize = 2;
+const MBUF_SEND_CPU_SHIFT: usize = 16;
+const MBUF_SEND_BUF_SHIFT: usize = 32;
+const MBUF_SEND_H32_MASK: usize = 0xFFFF_FFFF_0000_0000;
+
+/// Action bits carried by an IPI, one bit of `IPI_STATUS` each.
+#[derive(Clone, Copy, Debug)]
+pub enum IpiAction {
+    /// Wake a parked core, only consumed by the firmware or `entry.asm`.
+    Boot = 0,
+    /// Drop the user mappings cached in the TLB.
+    TlbFlush = 1,
+    /// Force the core to trap, so that it notices its pending signals.
+    Kick = 2,
+}
+
+/// Bitmap of the cores that have finished their own initialization.
+static CPU_ONLINE: AtomicUsize = AtomicUsize::new(0);
+
+#[inline(always)]
+pub fn cpu_id() -> usize {
+    CPUId::read().get_core_id()
+}
+
+pub fn cpu_online_mask() -> usize {
+    CPU_ONLINE.load(Ordering::Acquire)
+}
+
+#[inline(always)]
+fn iocsr_read32(reg: usize) -> u32 {
+    let val: u32;
+    unsafe { asm!("iocsrrd.w {0}, {1}", out(reg) val, in(reg) reg) };
+    val
+}
+
+#[inline(always)]
+fn iocsr_write32(reg: usize, val: u32) {
+    unsafe { asm!("iocsrwr.w {0}, {1}", in(reg) val, in(reg) reg) };
+}
+
+#[inline(always)]
+fn iocsr_write64(reg: usize, val: usize) {
+    unsafe { asm!("iocsrwr.d {0}, {1}", in(reg) val, in(reg) reg) };
+}
+
+/// Write `data` into `mailbox` of `cpu`, 32 bits at a time.
+/// The low half goes last, as the firmware expects.
+fn mail_send(cpu: usize, mailbox: usize, data: usize) {
+    let hi = MBUF_SEND_BLOCKING
+        | (((mailbox << 1) + 1) << MBUF_SEND_BOX_SHIFT)
+        | (cpu << MBUF_SEND_CPU_SHIFT)
+        | (data & MBUF_SEND_H32_MASK);
+    iocsr_write64(IOCSR_MBUF_SEND, hi);
+    let lo = MBUF_SEND_BLOCKING
+        | ((mailbox << 1) << MBUF_SEND_BOX_SHIFT)
+        | (cpu << MBUF_SEND_CPU_SHIFT)
+        | (data << MBUF_SEND_BUF_SHIFT);
+    iocsr_write64(IOCSR_MBUF_SEND, lo);
+}
+
+pub fn send_ipi(cpu: usize, action: IpiAction) {
+    iocsr_write32(
+        IOCSR_IPI_SEND,
+        (IPI_SEND_BLOCKING | (cpu << IPI_SEND_CPU_SHIFT) | action as usize) as u32,
+    );
+}
+
+/// Send `action` to every online core except the current one.
+pub fn send_ipi_others(action: IpiAction) {
+    let mask = cpu_online_mask() & !(1 << cpu_id());
+    (0..MAX_CPU_NUM)
+        .filter(|cpu| mask & (1 << cpu) != 0)
+        .for_each(|cpu| send_ipi(cpu, action));
+}
+
+/// Enable IPIs on the current core and mark it online.
+pub fn init() {
+    iocsr_write32(IOCSR_IPI_CLEAR, iocsr_read32(IOCSR_IPI_STATUS));
+    iocsr_write32(IOCSR_IPI_EN, u32::MAX);
+    CPU_ONLINE.fetch_or(1 << cpu_id(), Ordering::AcqRel);
+}
+
+/// Release the secondary cores, they either wait in the firmware for an IPI
+/// and jump to mailbox 0, or spin on mailbox 0 in `entry.asm`.
+pub fn start_secondary_cpus() {
+    extern "C" {
+        fn _start();
+    }
+    for cpu in (0..MAX_CPU_NUM).filter(|cpu| *cpu != cpu_id()) {
+        mail_send(cpu, 0, _start as usize);
+        send_ipi(cpu, IpiAction::Boot);
+    }
+}
+
+/// Acknowledge all pending IPIs of the current core.
+/// # Note
+/// `__alltraps` already flushes the TLB on every trap from user mode,
+/// so a `TlbFlush` needs no extra work here.
+pub fn handle_ipi() {
+    let status = iocsr_read32(IOCSR_IPI_STATUS);
+    iocsr_write32(IOCSR_IPI_CLEAR, status);
+    log::trace!("[handle_ipi] cpu: {}, status: {:#x}", cpu_id(), status);
+}
@@ -1,11 +1,16 @@
 pub const SYSCALL_GETCWD: usize = 17;
-pub const SYSCALL_DUP3: usize = 20;
+pub const SYSCALL_EVENTFD2: usize = 19;
+pub const SYSCALL_EPOLL_CREATE1: usize = 20;
+pub const SYSCALL_EPOLL_CTL: usize = 21;
+pub const SYSCALL_EPOLL_PWAIT: usize = 22;
 pub const SYSCALL_DUP: usize = 23;
-pub const SYSCALL_DUP2: usize = 24;
+pub const SYSCALL_DUP3: usize = 24;
 pub const SYSCALL_FCNTL: usize = 25;
 pub const SYSCALL_IOCTL: usize = 29;
+pub const SYSCALL_MKNODAT: usize = 33;
 pub const SYSCALL_MKDIRAT: usize = 34;
 pub const SYSCALL_UNLINKAT: usize = 35;
+pub const SYSCALL_SYMLINKAT: usize = 36;
 pub const SYSCALL_LINKAT: usize = 37;
 pub const SYSCALL_UMOUNT2: usize = 39;
 pub const SYSCALL_MOUNT: usize = 40;
@@ -13,6 +18,10 @@ pub const SYSCALL_STATFS: usize = 43;
 pub const SYSCALL_FTRUNCATE: usize = 46;
 pub const SYSCALL_FACCESSAT: usize = 48;
 pub const SYSCALL_CHDIR: usize = 49;
+pub const SYSCALL_FCHMOD: usize = 52;
+pub const SYSCALL_FCHMODAT: usize = 53;
+pub const SYSCALL_FCHOWNAT: usize = 54;
+pub const SYSCALL_FCHOWN: usize = 55;
 pub const SYSCALL_OPENAT: usize = 56;
 pub const SYSCALL_CLOSE: usize = 57;
 pub const SYSCALL_PIPE2: usize = 59;
@@ -27,11 +36,15 @@ pub const SYSCALL_PWRITE: usize = 68;
 pub const SYSCALL_SENDFILE: usize = 71;
 pub const SYSCALL_PSELECT6: usize = 72;
 pub const SYSCALL_PPOLL: usize = 73;
+pub const SYSCALL_SIGNALFD4: usize = 74;
 pub const SYSCALL_SPLICE: usize = 76;
 pub const SYSCALL_READLINKAT: usize = 78;
 pub const SYSCALL_FSTATAT: usize = 79;
 pub const SYSCALL_FSTAT: usize = 80;
 pub const SYSCALL_FSYNC: usize = 82;
+pub const SYSCALL_TIMERFD_CREATE: usize = 85;
+pub const SYSCALL_TIMERFD_SETTIME: usize = 86;
+pub const SYSCALL_TIMERFD_GETTIME: usize = 87;
 pub const SYSCALL_UTIMENSAT: usize = 88;
 pub const SYSCALL_EXIT: usize = 93;
 pub const SYSCALL_EXIT_GROUP: usize = 94;
@@ -42,22 +55,57 @@ pub const SYSCALL_GET_ROBUST_LIST: usize = 100;
 pub const SYSCALL_NANOSLEEP: usize = 101;
 pub const SYSCALL_GETITIMER: usize = 102;
 pub const SYSCALL_SETITIMER: usize = 103;
+pub const SYSCALL_TIMER_CREATE: usize = 107;
+pub const SYSCALL_TIMER_GETTIME: usize = 108;
+pub const SYSCALL_TIMER_GETOVERRUN: usize = 109;
+pub const SYSCALL_TIMER_SETTIME: usize = 110;
+pub const SYSCALL_TIMER_DELETE: usize = 111;
+pub const SYSCALL_CLOCK_SETTIME: usize = 112;
 pub const SYSCALL_CLOCK_GETTIME: usize = 113;
+pub const SYSCALL_CLOCK_GETRES: usize = 114;
+pub const SYSCALL_CLOCK_NANOSLEEP: usize = 115;
 pub const SYSCALL_SYSLOG: usize = 116;
+pub const SYSCALL_SCHED_SETPARAM: usize = 118;
+pub const SYSCALL_SCHED_SETSCHEDULER: usize = 119;
+pub const SYSCALL_SCHED_GETSCHEDULER: usize = 120;
+pub const SYSCALL_SCHED_GETPARAM: usize = 121;
 pub const SYSCALL_YIELD: usize = 124;
+pub const SYSCALL_SCHED_GET_PRIORITY_MAX: usize = 125;
+pub const SYSCALL_SCHED_GET_PRIORITY_MIN: usize = 126;
+pub const SYSCALL_SCHED_RR_GET_INTERVAL: usize = 127;
 pub const SYSCALL_KILL: usize = 129;
 pub const SYSCALL_TKILL: usize = 130;
+pub const SYSCALL_TGKILL: usize = 131;
+pub const SYSCALL_SIGALTSTACK: usize = 132;
+pub const SYSCALL_SIGSUSPEND: usize = 133;
 pub const SYSCALL_SIGACTION: usize = 134;
 pub const SYSCALL_SIGPROCMASK: usize = 135;
+pub const SYSCALL_SIGPENDING: usize = 136;
 pub const SYSCALL_SIGTIMEDWAIT: usize = 137;
+pub const SYSCALL_SIGQUEUEINFO: usize = 138;
 pub const SYSCALL_SIGRETURN: usize = 139;
+pub const SYSCALL_SETPRIORITY: usize = 140;
+pub const SYSCALL_GETPRIORITY: usize = 141;
+pub const SYSCALL_SETREGID: usize = 143;
+pub const SYSCALL_SETGID: usize = 144;
+pub const SYSCALL_SETREUID: usize = 145;
+pub const SYSCALL_SETUID: usize = 146;
+pub const SYSCALL_SETRESUID: usize = 147;
+pub const SYSCALL_GETRESUID: usize = 148;
+pub const SYSCALL_SETRESGID: usize = 149;
+pub const SYSCALL_GETRESGID: usize = 150;
 pub const SYSCALL_TIMES: usize = 153;
 pub const SYSCALL_SETPGID: usize = 154;
 pub const SYSCALL_GETPGID: usize = 155;
+pub const SYSCALL_GETSID: usize = 156;
+pub const SYSCALL_SETSID: usize = 157;
+pub const SYSCALL_GETGROUPS: usize = 158;
+pub const SYSCALL_SETGROUPS: usize = 159;
 pub const SYSCALL_UNAME: usize = 160;
 pub const SYSCALL_GETRUSAGE: usize = 165;
 pub const SYSCALL_UMASK: usize = 166;
 pub const SYSCALL_GET_TIME_OF_DAY: usize = 169;
+pub const SYSCALL_SETTIMEOFDAY: usize = 170;
 pub const SYSCALL_GETPID: usize = 172;
 pub const SYSCALL_GETPPID: usize = 173;
 pub const SYSCALL_GETUID: usize = 174;
@@ -67,6 +115,7 @@ pub const SYSCALL_GETEGID: usize = 177;
 pub const SYSCALL_GETTID: usize = 178;
 pub const SYSCALL_SYSINFO: usize = 179;
 pub const SYSCALL_SOCKET: usize = 198;
+pub const SYSCALL_SOCKETPAIR: usize = 199;
 pub const SYSCALL_BIND: usize = 200;
 pub const SYSCALL_LISTEN: usize = 201;
 pub const SYSCALL_ACCEPT: usize = 202;
@@ -76,6 +125,10 @@ pub const SYSCALL_GETPEERNAME: usize = 205;
 pub const SYSCALL_SENDTO: usize = 206;
 pub const SYSCALL_RECVFROM: usize = 207;
 pub const SYSCALL_SETSOCKOPT: usize = 208;
+pub const SYSCALL_GETSOCKOPT: usize = 209;
+pub const SYSCALL_SOCKET_SHUTDOWN: usize = 210;
+pub const SYSCALL_SENDMSG: usize = 211;
+pub const SYSCALL_RECVMSG: usize = 212;
 pub const SYSCALL_SBRK: usize = 213;
 pub const SYSCALL_BRK: usize = 214;
 pub const SYSCALL_MUNMAP: usize = 215;
@@ -85,6 +138,8 @@ pub const SYSCALL_EXECVE: usize = 221;
 pub const SYSCALL_MMAP: usize = 222;
 pub const SYSCALL_MPROTECT: usize = 226;
 pub const SYSCALL_MSYNC: usize = 227;
+pub const SYSCALL_TGSIGQUEUEINFO: usize = 240;
+pub const SYSCALL_ACCEPT4: usize = 242;
 pub const SYSCALL_WAIT4: usize = 260; // wait is implemented as wait4(pid, status, options, 0) in pub lib.
 pub const SYSCALL_PRLIMIT: usize = 261;
 pub const SYSCALL_RENAMEAT2: usize = 276;
@@ -1,6 +1,7 @@
 use register::{ASId, TLBEHi, TLBIdx, TLBEL, TLBELO0, TLBELO1};
 use register::mm::address::{PhysPageNum, VirtPageNum};
 use core::arch::asm;
+use super::smp::{send_ipi_others, IpiAction};
 pub const USR_ASID: usize = 0;
 pub const KERN_ASID: usize = (1 << 10) - 1;
 #[inline(always)]
@@ -25,12 +26,19 @@ pub fn tlb_addr_allow_write(vpn: VirtPageNum, ppn: PhysPageNum) -> Result<(), ()
     }
 }
 #[inline(always)]
-/// Invalidate non-global TLB entries
-pub fn tlb_invalidate() {
+/// Invalidate non-global TLB entries of the current core
+pub fn tlb_invalidate_local() {
     unsafe {
         asm!("invtlb 0x3,$zero, $zero");
     }
 }
+/// Invalidate non-global TLB entries on every online core.
+/// Other cores are not waited for: the kernel runs with interrupts masked,
+/// and the IPI is taken (and the TLB flushed by `__alltraps`) before they run any user code.
+pub fn tlb_invalidate() {
+    tlb_invalidate_local();
+    send_ipi_others(IpiAction::TlbFlush);
+}
 #[inline(always)]
 pub fn tlb_global_invalidate() {
     unsafe {
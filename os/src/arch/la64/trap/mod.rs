@@ -3,16 +3,19 @@ mod mem_access;
 use self::context::GeneralRegs;
 
 use register::{self, Exception, Interrupt, Trap, ERA};
-use super::{pre_start_init};
+use super::{liointc::handle_irqs, pre_start_init, smp::handle_ipi};
 use crate::arch::la64::laflex::LAFlexPageTable;
 use register::{CrMd, ECfg, LineBasedInterrupt, PrMd, TCfg, TIClr,MErrEntry, DMW, DMW1};
 use crate::arch::la64::trap::mem_access::Instruction;
 use crate::arch::{get_clock_freq, TICKS_PER_SEC};
+use crate::drivers::block::poll_block_devices;
+use crate::fs::TTY;
 use crate::mm::{copy_from_user, copy_to_user, frame_reserve, MemoryError, PageTable, VirtAddr};
+use crate::random::add_interrupt_randomness;
 use crate::syscall::syscall;
 use crate::task::{
     current_task, current_trap_cx, current_user_token, do_signal, do_wake_expired,
-    suspend_current_and_run_next, Signals,
+    posix_timer::expire_cpu_timers, scheduler_tick, SigInfo, Signals,
 };
 use core::arch::{asm, global_asm};
 use core::ptr::{addr_of, addr_of_mut};
@@ -153,7 +156,9 @@ pub fn enable_timer_interrupt() {
         .set_init_val(timer_freq / TICKS_PER_SEC)
         .write();
     ECfg::empty()
-        .set_line_based_interrupt_vector(LineBasedInterrupt::TIMER)
+        .set_line_based_interrupt_vector(
+            LineBasedInterrupt::TIMER | LineBasedInterrupt::IPI | LineBasedInterrupt::HWI0,
+        )
         .write();
 }
 #[link_section = ".text.trap_handler"]
@@ -164,13 +169,15 @@ pub fn trap_handler() -> ! {
     }
     set_kernel_trap_entry();
 
+    let cause = get_exception_cause();
     {
         let task = current_task().unwrap();
         let mut inner = task.acquire_inner_lock();
         inner.update_process_times_enter_trap();
+        // `do_signal()` restarts interrupted syscalls only
+        inner.in_syscall = cause.is_syscall();
     }
 
-    let cause = get_exception_cause();
     let stval = get_bad_addr();
     let badi = get_bad_instruction();
     log::info!("[trap_handler]Cause:{:?}", cause);
@@ -213,15 +220,21 @@ pub fn trap_handler() -> ! {
             frame_reserve(3);
             let mut mset_lock = task.vm.lock();
             match mset_lock.do_page_fault(addr) {
-                Err(error) => match error {
-                    MemoryError::BeyondEOF => {
-                        inner.add_signal(Signals::SIGBUS);
-                    }
-                    MemoryError::NoPermission | MemoryError::BadAddress => {
-                        inner.add_signal(Signals::SIGSEGV);
-                    }
-                    _ => unreachable!(),
-                },
+                Err(error) => {
+                    let (signal, si_code) = match error {
+                        MemoryError::BeyondEOF => (Signals::SIGBUS, SigInfo::BUS_ADRERR),
+                        // there is no OOM killer, the faulting task gets the signal instead
+                        MemoryError::OutOfMemory => (Signals::SIGBUS, SigInfo::BUS_ADRERR),
+                        MemoryError::IoError => (Signals::SIGBUS, SigInfo::BUS_ADRERR),
+                        MemoryError::NoPermission => (Signals::SIGSEGV, SigInfo::SEGV_ACCERR),
+                        MemoryError::BadAddress => (Signals::SIGSEGV, SigInfo::SEGV_MAPERR),
+                        _ => unreachable!(),
+                    };
+                    let signum = signal.to_signum().unwrap();
+                    inner
+                        .queue_signal(SigInfo::from_fault(signum, si_code, addr.0))
+                        .unwrap();
+                }
                 Ok(_) => {
                     //tlb_addr_allow_write(addr.floor(), _paddr.floor()).unwrap();
                     drop(mset_lock);
@@ -243,10 +256,21 @@ pub fn trap_handler() -> ! {
             inner.add_signal(Signals::SIGILL);
         }
         Trap::Interrupt(Interrupt::Timer) => {
+            add_interrupt_randomness(LineBasedInterrupt::TIMER.bits() as usize);
             do_wake_expired();
+            TTY.poll();
+            poll_block_devices();
             TIClr::read().clear_timer().write();
             enable_timer_interrupt();
-            suspend_current_and_run_next();
+            scheduler_tick();
+        }
+        Trap::Interrupt(Interrupt::HWI0) => {
+            add_interrupt_randomness(LineBasedInterrupt::HWI0.bits() as usize);
+            handle_irqs();
+        }
+        Trap::Interrupt(Interrupt::IPI) => {
+            add_interrupt_randomness(LineBasedInterrupt::IPI.bits() as usize);
+            handle_ipi();
         }
         Trap::Exception(Exception::Breakpoint) => {
             read_bp();
@@ -313,11 +337,7 @@ pub fn trap_handler() -> ! {
                 );
             }
         }
-        Trap::Interrupt(Interrupt::IPI)
-        | Trap::MachineError(_)
-        | Trap::Unknown
-        | Trap::Exception(Exception::AddressError)
-        | _ => {
+        Trap::MachineError(_) | Trap::Unknown | Trap::Exception(Exception::AddressError) | _ => {
             panic!(
                 "Unsupported trap {:?}, stval = {:#x}, BadI = {:#x}!",
                 cause, stval, badi
@@ -328,6 +348,9 @@ pub fn trap_handler() -> ! {
         let task = current_task().unwrap();
         let mut inner = task.acquire_inner_lock();
         inner.update_process_times_leave_trap(cause);
+        task.check_rlimit_cpu(&mut inner);
+        drop(inner);
+        expire_cpu_timers(&task);
     }
     trap_return();
 }
@@ -3,11 +3,13 @@ mod la64;
 #[cfg(feature = "la64")]
 pub use la64::{
     board,
-    board::BlockDeviceImpl,
     board::MMIO,
     bootstrap_init, config,
     config::BUFFER_CACHE_NUM,
-    console_flush, console_getchar, console_putchar, machine_init, shutdown,
+    console_flush, console_getchar, console_putchar, cpu_id, cpu_online_mask, cpuinfo,
+    machine_init, machine_init_secondary, send_ipi_others, shutdown, start_secondary_cpus,
+    IpiAction,
+    liointc,
     time::{get_clock_freq, get_time, TICKS_PER_SEC},
     KernelPageTableImpl, PageTableImpl, __switch, syscall_id, tlb_global_invalidate,
     tlb_invalidate,
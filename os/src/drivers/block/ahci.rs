@@ -0,0 +1,291 @@
+use super::{wait_for, BlockDevice, BlockOp, BlockRequest, SECTOR_SZ};
+use crate::arch::BLOCK_SZ;
+use crate::mm::{frame_alloc, FrameTracker, PhysAddr};
+use crate::syscall::errno::{EIO, ENODEV};
+use alloc::{sync::Arc, vec::Vec};
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, Ordering};
+use spin::Mutex;
+
+/// Pages of the bounce buffer, which bounds the sectors moved by one command
+const DMA_PAGES: usize = 16;
+const PAGE_SIZE: usize = crate::config::PAGE_SIZE;
+/// Generous for a disk spinning up, a command normally takes milliseconds
+const COMMAND_TIMEOUT_MS: usize = 5000;
+
+mod offsets {
+    // HBA registers
+    pub const GHC: usize = 0x04;
+    pub const PI: usize = 0x0c;
+    pub const PORT_BASE: usize = 0x100;
+    pub const PORT_SIZE: usize = 0x80;
+    // port registers
+    pub const CLB: usize = 0x00;
+    pub const CLBU: usize = 0x04;
+    pub const FB: usize = 0x08;
+    pub const FBU: usize = 0x0c;
+    pub const IS: usize = 0x10;
+    pub const CMD: usize = 0x18;
+    pub const TFD: usize = 0x20;
+    pub const SIG: usize = 0x24;
+    pub const SSTS: usize = 0x28;
+    pub const SERR: usize = 0x30;
+    pub const CI: usize = 0x38;
+    // layout of the DMA page, the command list must be 1K aligned and the received FIS 256 aligned
+    pub const COMMAND_LIST: usize = 0x000;
+    pub const RECEIVED_FIS: usize = 0x400;
+    pub const COMMAND_TABLE: usize = 0x800;
+    pub const PRDT: usize = COMMAND_TABLE + 0x80;
+}
+
+mod masks {
+    pub const GHC_AE: u32 = 1 << 31;
+    pub const CMD_ST: u32 = 1 << 0;
+    pub const CMD_FRE: u32 = 1 << 4;
+    pub const CMD_FR: u32 = 1 << 14;
+    pub const CMD_CR: u32 = 1 << 15;
+    pub const TFD_ERR: u32 = 1 << 0;
+    pub const TFD_DRQ: u32 = 1 << 3;
+    pub const TFD_BSY: u32 = 1 << 7;
+    pub const IS_TFES: u32 = 1 << 30;
+    pub const SSTS_DET: u32 = 0xf;
+    /// A device is present and the PHY is up
+    pub const SSTS_DET_PRESENT: u32 = 3;
+    pub const SIG_ATA: u32 = 0x0000_0101;
+    /// Command header flags
+    pub const HEADER_WRITE: u32 = 1 << 6;
+}
+
+mod ata {
+    pub const FIS_TYPE_REG_H2D: u8 = 0x27;
+    pub const FIS_COMMAND: u8 = 1 << 7;
+    pub const DEVICE_LBA: u8 = 1 << 6;
+    pub const IDENTIFY: u8 = 0xec;
+    pub const READ_DMA_EXT: u8 = 0x25;
+    pub const WRITE_DMA_EXT: u8 = 0x35;
+    pub const FLUSH_CACHE_EXT: u8 = 0xea;
+}
+
+/// One port of an AHCI controller driven with a single command slot.
+/// Data goes through a bounce buffer, the caller's buffer may not be physically contiguous.
+struct AhciPort {
+    port: usize,
+    /// Command list, received FIS and command table
+    command_page: Arc<FrameTracker>,
+    bounce: Vec<Arc<FrameTracker>>,
+    sectors: usize,
+}
+
+pub struct AhciBlock(Mutex<AhciPort>);
+
+impl AhciPort {
+    fn read_reg(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.port + offset) as *const u32) }
+    }
+    fn write_reg(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.port + offset) as *mut u32, value) }
+    }
+    fn dma_addr(&self, offset: usize) -> usize {
+        PhysAddr::from(self.command_page.ppn).0 + offset
+    }
+    fn command_page(&self) -> &'static mut [u8] {
+        self.command_page.ppn.get_bytes_array()
+    }
+    /// Stop the command engine and the FIS receiver, they must be idle when their buffers are set
+    fn stop(&self) -> Result<(), isize> {
+        let cmd = self.read_reg(offsets::CMD);
+        self.write_reg(offsets::CMD, cmd & !(masks::CMD_ST | masks::CMD_FRE));
+        wait_for(COMMAND_TIMEOUT_MS, || {
+            self.read_reg(offsets::CMD) & (masks::CMD_CR | masks::CMD_FR) == 0
+        })
+    }
+    fn start(&self) -> Result<(), isize> {
+        self.write_reg(offsets::SERR, u32::MAX);
+        self.write_reg(offsets::IS, u32::MAX);
+        let cmd = self.read_reg(offsets::CMD);
+        self.write_reg(offsets::CMD, cmd | masks::CMD_FRE);
+        wait_for(COMMAND_TIMEOUT_MS, || {
+            self.read_reg(offsets::TFD) & (masks::TFD_BSY | masks::TFD_DRQ) == 0
+        })?;
+        let cmd = self.read_reg(offsets::CMD);
+        self.write_reg(offsets::CMD, cmd | masks::CMD_ST);
+        Ok(())
+    }
+    fn init(&mut self) -> Result<(), isize> {
+        self.stop()?;
+        self.command_page().fill(0);
+        let command_list = self.dma_addr(offsets::COMMAND_LIST);
+        let received_fis = self.dma_addr(offsets::RECEIVED_FIS);
+        self.write_reg(offsets::CLB, command_list as u32);
+        self.write_reg(offsets::CLBU, (command_list >> 32) as u32);
+        self.write_reg(offsets::FB, received_fis as u32);
+        self.write_reg(offsets::FBU, (received_fis >> 32) as u32);
+        self.start()?;
+
+        self.command(ata::IDENTIFY, 0, 1, false)?;
+        let identify = &self.bounce[0].ppn.get_bytes_array()[..SECTOR_SZ];
+        // words 100-103 hold the number of sectors addressable with LBA48
+        self.sectors = identify[200..208]
+            .iter()
+            .rev()
+            .fold(0, |sectors, byte| (sectors << 8) | *byte as usize);
+        if self.sectors == 0 {
+            return Err(ENODEV);
+        }
+        Ok(())
+    }
+    /// Issue `command` on `count` sectors from `lba` and wait for it,
+    /// data moves between the device and the head of the bounce buffer.
+    fn command(&self, command: u8, lba: usize, count: usize, write: bool) -> Result<(), isize> {
+        let page = self.command_page();
+        let bytes = count * SECTOR_SZ;
+        let entries = (bytes + PAGE_SIZE - 1) / PAGE_SIZE;
+        // command header of slot 0: FIS length in dwords and the number of PRDT entries
+        let mut flags = 5 | ((entries as u32) << 16);
+        if write {
+            flags |= masks::HEADER_WRITE;
+        }
+        let command_table = self.dma_addr(offsets::COMMAND_TABLE);
+        let header = &mut page[offsets::COMMAND_LIST..offsets::COMMAND_LIST + 32];
+        header.fill(0);
+        header[0..4].copy_from_slice(&flags.to_le_bytes());
+        header[8..12].copy_from_slice(&(command_table as u32).to_le_bytes());
+        header[12..16].copy_from_slice(&((command_table >> 32) as u32).to_le_bytes());
+
+        let fis = &mut page[offsets::COMMAND_TABLE..offsets::PRDT];
+        fis.fill(0);
+        fis[0] = ata::FIS_TYPE_REG_H2D;
+        fis[1] = ata::FIS_COMMAND;
+        fis[2] = command;
+        fis[4..7].copy_from_slice(&lba.to_le_bytes()[0..3]);
+        fis[7] = ata::DEVICE_LBA;
+        fis[8..11].copy_from_slice(&lba.to_le_bytes()[3..6]);
+        fis[12..14].copy_from_slice(&(count as u16).to_le_bytes());
+
+        for (i, frame) in self.bounce[..entries].iter().enumerate() {
+            let addr = PhysAddr::from(frame.ppn).0;
+            let len = (bytes - i * PAGE_SIZE).min(PAGE_SIZE);
+            let entry = &mut page[offsets::PRDT + i * 16..offsets::PRDT + (i + 1) * 16];
+            entry[0..4].copy_from_slice(&(addr as u32).to_le_bytes());
+            entry[4..8].copy_from_slice(&((addr >> 32) as u32).to_le_bytes());
+            entry[8..12].fill(0);
+            entry[12..16].copy_from_slice(&(len as u32 - 1).to_le_bytes());
+        }
+
+        wait_for(COMMAND_TIMEOUT_MS, || {
+            self.read_reg(offsets::TFD) & (masks::TFD_BSY | masks::TFD_DRQ) == 0
+        })?;
+        self.write_reg(offsets::IS, u32::MAX);
+        fence(Ordering::SeqCst);
+        self.write_reg(offsets::CI, 1);
+        wait_for(COMMAND_TIMEOUT_MS, || {
+            self.read_reg(offsets::CI) & 1 == 0 || self.read_reg(offsets::IS) & masks::IS_TFES != 0
+        })?;
+        fence(Ordering::SeqCst);
+        if self.read_reg(offsets::IS) & masks::IS_TFES != 0
+            || self.read_reg(offsets::TFD) & masks::TFD_ERR != 0
+        {
+            log::error!(
+                "[ahci] command {:#x} on lba {:#x} failed, tfd: {:#x}",
+                command,
+                lba,
+                self.read_reg(offsets::TFD)
+            );
+            // restarting the command engine clears the error
+            self.stop()?;
+            self.start()?;
+            return Err(EIO);
+        }
+        Ok(())
+    }
+    fn read(&self, mut lba: usize, buf: &mut [u8]) -> Result<(), isize> {
+        for chunk in buf.chunks_mut(DMA_PAGES * PAGE_SIZE) {
+            let count = chunk.len() / SECTOR_SZ;
+            self.command(ata::READ_DMA_EXT, lba, count, false)?;
+            for (frame, chunk) in self.bounce.iter().zip(chunk.chunks_mut(PAGE_SIZE)) {
+                chunk.copy_from_slice(&frame.ppn.get_bytes_array()[..chunk.len()]);
+            }
+            lba += count;
+        }
+        Ok(())
+    }
+    fn write(&self, mut lba: usize, buf: &[u8]) -> Result<(), isize> {
+        for chunk in buf.chunks(DMA_PAGES * PAGE_SIZE) {
+            let count = chunk.len() / SECTOR_SZ;
+            for (frame, chunk) in self.bounce.iter().zip(chunk.chunks(PAGE_SIZE)) {
+                frame.ppn.get_bytes_array()[..chunk.len()].copy_from_slice(chunk);
+            }
+            self.command(ata::WRITE_DMA_EXT, lba, count, true)?;
+            lba += count;
+        }
+        Ok(())
+    }
+}
+
+impl AhciBlock {
+    /// Bring up the first port with a SATA disk on the controller whose registers are at `abar`
+    pub fn new(abar: usize) -> Option<Self> {
+        let ghc = unsafe { read_volatile((abar + offsets::GHC) as *const u32) };
+        unsafe { write_volatile((abar + offsets::GHC) as *mut u32, ghc | masks::GHC_AE) };
+        let implemented = unsafe { read_volatile((abar + offsets::PI) as *const u32) };
+        (0..32)
+            .filter(|port| implemented & (1 << port) != 0)
+            .map(|port| abar + offsets::PORT_BASE + port * offsets::PORT_SIZE)
+            .find_map(|port| {
+                let mut ahci_port = AhciPort {
+                    port,
+                    command_page: frame_alloc()?,
+                    bounce: (0..DMA_PAGES)
+                        .map(|_| frame_alloc())
+                        .collect::<Option<_>>()?,
+                    sectors: 0,
+                };
+                if ahci_port.read_reg(offsets::SSTS) & masks::SSTS_DET != masks::SSTS_DET_PRESENT
+                    || ahci_port.read_reg(offsets::SIG) != masks::SIG_ATA
+                {
+                    return None;
+                }
+                match ahci_port.init() {
+                    Ok(()) => {
+                        log::info!(
+                            "[ahci] port {:#x}: {} MiB",
+                            port,
+                            (ahci_port.sectors * SECTOR_SZ) >> 20
+                        );
+                        Some(Self(Mutex::new(ahci_port)))
+                    }
+                    Err(errno) => {
+                        log::warn!("[ahci] port {:#x} failed to start: {}", port, errno);
+                        None
+                    }
+                }
+            })
+    }
+}
+
+impl BlockDevice for AhciBlock {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        assert_eq!(buf.len() % SECTOR_SZ, 0);
+        self.0
+            .lock()
+            .read(block_id * (BLOCK_SZ / SECTOR_SZ), buf)
+            .expect("Error when reading AHCI disk");
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        assert_eq!(buf.len() % SECTOR_SZ, 0);
+        self.0
+            .lock()
+            .write(block_id * (BLOCK_SZ / SECTOR_SZ), buf)
+            .expect("Error when writing AHCI disk");
+    }
+    /// Commands are carried out one at a time, only a flush needs a command of its own
+    fn submit(&self, request: BlockRequest) {
+        match request.op {
+            BlockOp::Flush => {
+                let result = self.0.lock().command(ata::FLUSH_CACHE_EXT, 0, 0, false);
+                request.complete(result);
+            }
+            _ => request.execute_sync(self),
+        }
+    }
+}
@@ -1,6 +1,15 @@
 use core::any::Any;
+use core::sync::atomic::{AtomicBool, AtomicIsize, Ordering};
 
 use crate::arch::BLOCK_SZ;
+use crate::syscall::errno::EINVAL;
+use alloc::{sync::Arc, vec, vec::Vec};
+
+/// Bytes in a sector, the unit of `BlockRequest::sector`
+pub const SECTOR_SZ: usize = 512;
+/// Sectors in a block, the unit of `read_block()` and `write_block()`
+pub const SECTORS_PER_BLOCK: usize = BLOCK_SZ / SECTOR_SZ;
+
 /// We should regulate the behavior of this trait on FAILURE
 /// e.g. What if buf.len()>BLOCK_SZ for read_block?
 /// e.g. Does read_block clean the rest part of the block to be zero for buf.len()!=BLOCK_SZ in write_block() & read_block()
@@ -35,4 +44,227 @@ pub trait BlockDevice: Send + Sync + Any {
             self.write_block(i, &[num; BLOCK_SZ]);
         }
     }
+
+    /// Queue `request`, its status is set once it completes, which may be before this returns.
+    /// Devices without a queue of their own carry it out right away with `BlockRequest::execute_sync()`.
+    fn submit(&self, request: BlockRequest) {
+        request.execute_sync(self);
+    }
+
+    /// Reap completed requests and start queued ones.
+    /// This is the interrupt handler of the device, waiters call it as well.
+    fn poll(&self) {}
+
+    /// The interrupt controller source the device raises when requests complete.
+    /// Devices without one are polled from the timer interrupt instead.
+    fn irq(&self) -> Option<usize> {
+        None
+    }
+
+    /// Wait for a submitted request to complete.
+    /// The device is polled while waiting unless its interrupt completes the request:
+    /// the caller can't sleep as it may hold spin locks, the page fault handler its task's.
+    fn wait(&self, status: &RequestStatus) -> Result<(), isize> {
+        loop {
+            if let Some(result) = status.result() {
+                return result;
+            }
+            self.poll();
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Submit `request` and wait for it
+    fn submit_wait(&self, request: BlockRequest) -> Result<(), isize> {
+        let status = request.status();
+        self.submit(request);
+        self.wait(&status)
+    }
+
+    /// Make the completed writes durable
+    fn flush(&self) -> Result<(), isize> {
+        self.submit_wait(BlockRequest::flush())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockOp {
+    Read,
+    Write,
+    /// Write back the volatile cache of the device
+    Flush,
+    /// The sectors are no longer used, their content becomes undefined
+    Discard,
+}
+
+bitflags! {
+    pub struct RequestFlags: u32 {
+        /// The write is durable once it completes, not only after the next flush
+        const FUA = 1 << 0;
+    }
+}
+
+/// A piece of memory a request reads into or writes from, at a kernel address.
+/// The submitter keeps it alive until the request completes.
+#[derive(Clone, Copy, Debug)]
+pub struct Segment {
+    pub addr: usize,
+    pub len: usize,
+}
+
+impl Segment {
+    pub fn from_slice(buf: &[u8]) -> Self {
+        Self {
+            addr: buf.as_ptr() as usize,
+            len: buf.len(),
+        }
+    }
+}
+
+/// Completion of a request, shared by the submitter and the device
+pub struct RequestStatus {
+    done: AtomicBool,
+    result: AtomicIsize,
+}
+
+impl RequestStatus {
+    fn new() -> Arc<Self> {
+        Arc::new(Self {
+            done: AtomicBool::new(false),
+            result: AtomicIsize::new(0),
+        })
+    }
+    /// `None` while the request is in progress
+    pub fn result(&self) -> Option<Result<(), isize>> {
+        if !self.done.load(Ordering::Acquire) {
+            return None;
+        }
+        match self.result.load(Ordering::Relaxed) {
+            0 => Some(Ok(())),
+            errno => Some(Err(errno)),
+        }
+    }
+    fn complete(&self, result: Result<(), isize>) {
+        self.result
+            .store(result.err().unwrap_or(0), Ordering::Relaxed);
+        self.done.store(true, Ordering::Release);
+    }
+}
+
+/// A request on consecutive sectors, the data is scattered over `segments`
+pub struct BlockRequest {
+    pub op: BlockOp,
+    pub flags: RequestFlags,
+    /// First sector, `0` for a flush
+    pub sector: usize,
+    /// Number of sectors, the total length of the segments for reads and writes
+    pub sectors: usize,
+    pub segments: Vec<Segment>,
+    /// Completions of this request and those merged into it
+    statuses: Vec<Arc<RequestStatus>>,
+}
+
+impl BlockRequest {
+    /// A read or write of `segments`, each of them a multiple of `SECTOR_SZ`
+    pub fn new(op: BlockOp, sector: usize, segments: Vec<Segment>) -> Self {
+        let sectors = segments.iter().map(|segment| segment.len).sum::<usize>() / SECTOR_SZ;
+        Self {
+            op,
+            flags: RequestFlags::empty(),
+            sector,
+            sectors,
+            segments,
+            statuses: vec![RequestStatus::new()],
+        }
+    }
+    pub fn read(sector: usize, buf: &mut [u8]) -> Self {
+        Self::new(BlockOp::Read, sector, vec![Segment::from_slice(buf)])
+    }
+    pub fn write(sector: usize, buf: &[u8]) -> Self {
+        Self::new(BlockOp::Write, sector, vec![Segment::from_slice(buf)])
+    }
+    pub fn flush() -> Self {
+        Self::new(BlockOp::Flush, 0, Vec::new())
+    }
+    pub fn discard(sector: usize, sectors: usize) -> Self {
+        let mut request = Self::new(BlockOp::Discard, sector, Vec::new());
+        request.sectors = sectors;
+        request
+    }
+    pub fn with_flags(mut self, flags: RequestFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+    /// Status of the request as submitted
+    pub fn status(&self) -> Arc<RequestStatus> {
+        self.statuses[0].clone()
+    }
+    /// The sector after the last one
+    pub fn end(&self) -> usize {
+        self.sector + self.sectors
+    }
+    /// Whether `next` starts where this one ends and does the same
+    pub fn can_merge(&self, next: &Self) -> bool {
+        self.op == next.op
+            && self.flags == next.flags
+            && self.op != BlockOp::Flush
+            && self.end() == next.sector
+    }
+    /// Append `next`, which `can_merge()`
+    pub fn merge(&mut self, mut next: Self) {
+        self.sectors += next.sectors;
+        self.segments.append(&mut next.segments);
+        self.statuses.append(&mut next.statuses);
+    }
+    /// A flush completing this request, which makes a write with `FUA` durable
+    pub fn into_flush(self) -> Self {
+        Self {
+            statuses: self.statuses,
+            ..Self::flush()
+        }
+    }
+    pub fn complete(self, result: Result<(), isize>) {
+        for status in self.statuses {
+            status.complete(result);
+        }
+    }
+    /// Carry out the request through `read_block()` and `write_block()`, which address whole blocks.
+    /// Nothing is cached on such devices and discarding is only a hint, so both succeed right away.
+    pub fn execute_sync<D: BlockDevice + ?Sized>(self, block_device: &D) {
+        let aligned = self.sector % SECTORS_PER_BLOCK == 0
+            && self
+                .segments
+                .iter()
+                .all(|segment| segment.len % BLOCK_SZ == 0);
+        let mut result = match self.op {
+            BlockOp::Read | BlockOp::Write if !aligned => Err(EINVAL),
+            BlockOp::Read => {
+                let mut block_id = self.sector / SECTORS_PER_BLOCK;
+                for segment in self.segments.iter() {
+                    let buf = unsafe {
+                        core::slice::from_raw_parts_mut(segment.addr as *mut u8, segment.len)
+                    };
+                    block_device.read_block(block_id, buf);
+                    block_id += segment.len / BLOCK_SZ;
+                }
+                Ok(())
+            }
+            BlockOp::Write => {
+                let mut block_id = self.sector / SECTORS_PER_BLOCK;
+                for segment in self.segments.iter() {
+                    let buf = unsafe {
+                        core::slice::from_raw_parts(segment.addr as *const u8, segment.len)
+                    };
+                    block_device.write_block(block_id, buf);
+                    block_id += segment.len / BLOCK_SZ;
+                }
+                Ok(())
+            }
+            BlockOp::Flush | BlockOp::Discard => Ok(()),
+        };
+        if result.is_ok() && self.flags.contains(RequestFlags::FUA) {
+            result = block_device.flush();
+        }
+        self.complete(result);
+    }
 }
@@ -0,0 +1,140 @@
+//! Deadline I/O scheduler for devices with a request queue.
+//!
+//! Pending requests are dispatched in ascending sector order, sweeping like an elevator
+//! and going back to the lowest sector at the end, unless the oldest one has waited past its deadline.
+//! A request is merged with the pending ones it continues or is continued by.
+//! A flush is a barrier: requests queued after it wait until everything before it completed
+//! and it has been dispatched.
+use super::{BlockOp, BlockRequest};
+use crate::timer::get_time_ms;
+use alloc::collections::{BTreeMap, VecDeque};
+
+const READ_EXPIRE_MS: usize = 500;
+const WRITE_EXPIRE_MS: usize = 5000;
+
+pub struct Elevator {
+    /// Pending requests by (first sector, sequence number), with their deadlines
+    sorted: BTreeMap<(usize, usize), (usize, BlockRequest)>,
+    /// First sector of the pending requests by (deadline, sequence number)
+    expiry: BTreeMap<(usize, usize), usize>,
+    /// The oldest flush and everything queued after it
+    held: VecDeque<BlockRequest>,
+    /// Where the last dispatched request ended
+    head: usize,
+    next_seq: usize,
+    /// Merged requests stay within these limits of the device
+    max_sectors: usize,
+    max_segments: usize,
+}
+
+impl Elevator {
+    pub fn new(max_sectors: usize, max_segments: usize) -> Self {
+        Self {
+            sorted: BTreeMap::new(),
+            expiry: BTreeMap::new(),
+            held: VecDeque::new(),
+            head: 0,
+            next_seq: 0,
+            max_sectors,
+            max_segments,
+        }
+    }
+    pub fn add(&mut self, request: BlockRequest) {
+        if request.op == BlockOp::Flush || !self.held.is_empty() {
+            self.held.push_back(request);
+        } else {
+            let deadline = get_time_ms() + Self::expire_ms(&request);
+            self.insert(request, deadline);
+        }
+    }
+    /// Put back a dispatched request the device had no room for, it goes first
+    pub fn requeue(&mut self, request: BlockRequest) {
+        if request.op == BlockOp::Flush {
+            self.held.push_front(request);
+        } else {
+            self.insert(request, 0);
+        }
+    }
+    /// The next request to start, `idle` tells whether the device has nothing in flight
+    pub fn dispatch(&mut self, idle: bool) -> Option<BlockRequest> {
+        if let Some((&(deadline, seq), &sector)) = self.expiry.iter().next() {
+            let key = if deadline <= get_time_ms() {
+                (sector, seq)
+            } else {
+                *self
+                    .sorted
+                    .range((self.head, 0)..)
+                    .next()
+                    .or_else(|| self.sorted.iter().next())
+                    .unwrap()
+                    .0
+            };
+            let (_, request) = self.remove(key);
+            self.head = request.end();
+            return Some(request);
+        }
+        // the flush goes once everything before it completed, the requests after it are released
+        if !idle || self.held.is_empty() {
+            return None;
+        }
+        let flush = self.held.pop_front().unwrap();
+        while let Some(request) = self.held.pop_front() {
+            if request.op == BlockOp::Flush {
+                self.held.push_front(request);
+                break;
+            }
+            let deadline = get_time_ms() + Self::expire_ms(&request);
+            self.insert(request, deadline);
+        }
+        Some(flush)
+    }
+    fn expire_ms(request: &BlockRequest) -> usize {
+        match request.op {
+            BlockOp::Read => READ_EXPIRE_MS,
+            _ => WRITE_EXPIRE_MS,
+        }
+    }
+    fn mergeable(&self, first: &BlockRequest, second: &BlockRequest) -> bool {
+        first.can_merge(second)
+            && first.sectors + second.sectors <= self.max_sectors
+            && first.segments.len() + second.segments.len() <= self.max_segments
+    }
+    fn remove(&mut self, key: (usize, usize)) -> (usize, BlockRequest) {
+        let (deadline, request) = self.sorted.remove(&key).unwrap();
+        self.expiry.remove(&(deadline, key.1));
+        (deadline, request)
+    }
+    /// Insert `request`, merged with the pending request it continues and the one continuing it
+    fn insert(&mut self, mut request: BlockRequest, mut deadline: usize) {
+        let prev = self
+            .sorted
+            .range(..=(request.sector, usize::MAX))
+            .next_back()
+            .map(|(key, _)| *key);
+        if let Some(key) = prev {
+            if self.mergeable(&self.sorted[&key].1, &request) {
+                let (prev_deadline, mut prev) = self.remove(key);
+                prev.merge(request);
+                request = prev;
+                deadline = deadline.min(prev_deadline);
+            }
+        }
+        let next = self
+            .sorted
+            .range((request.end(), 0)..)
+            .next()
+            .map(|(key, _)| *key);
+        if let Some(key) = next {
+            if self.mergeable(&request, &self.sorted[&key].1) {
+                let (next_deadline, next) = self.remove(key);
+                request.merge(next);
+                deadline = deadline.min(next_deadline);
+            }
+        }
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.expiry.insert((deadline, seq), request.sector);
+        self.sorted
+            .insert((request.sector, seq), (deadline, request));
+    }
+}
@@ -1,16 +1,202 @@
+mod ahci;
 mod block_dev;
+mod elevator;
 mod mem_blk;
+mod sdhci;
 mod virtio_blk;
-pub use block_dev::BlockDevice;
+pub use ahci::AhciBlock;
+pub use block_dev::{
+    BlockDevice, BlockOp, BlockRequest, RequestFlags, RequestStatus, Segment, SECTORS_PER_BLOCK,
+    SECTOR_SZ,
+};
 pub use mem_blk::MemBlockWrapper;
+pub use sdhci::SdhciBlock;
 pub use virtio_blk::VirtIOBlock;
 
-use crate::arch::{BlockDeviceImpl, BLOCK_SZ};
-use alloc::sync::Arc;
+use elevator::Elevator;
+
+use super::pci::{self, find_devices, find_function, PciFunction};
+use crate::arch::{board::STORAGE, liointc, BLOCK_SZ};
+use crate::syscall::errno::ETIMEDOUT;
+use crate::timer::get_time_ms;
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicBool, Ordering};
 use lazy_static::*;
+use spin::RwLock;
+
+/// Name of the boot device in `BLOCK_DEVICE_TABLE`
+pub const ROOT_DEVICE_NAME: &str = "vda";
+
+/// Storage a board lists in `board::STORAGE`, in the order they are tried for the root device
+pub enum Storage {
+    /// The first SATA disk on the AHCI controller found on PCI
+    Ahci,
+    /// The SD card or eMMC on the SD host controller found on PCI
+    Sdhci,
+    /// Every virtio-blk function found on PCI, named vda, vdb and so on in the order of the bus.
+    /// Each completes requests from its INTx if the firmware routed it, polled otherwise
+    Virtio,
+    /// The file system image loaded with the kernel, writes are lost at reboot
+    RamDisk,
+}
+
+impl Storage {
+    /// Bring up the devices, returns them with their names under `/dev`
+    fn probe(&self) -> Vec<(String, Arc<dyn BlockDevice>)> {
+        match self {
+            Storage::Ahci => Self::probe_ahci()
+                .map(|block_device| ("sda".to_string(), block_device))
+                .into_iter()
+                .collect(),
+            Storage::Sdhci => Self::probe_sdhci()
+                .map(|block_device| ("mmcblk0".to_string(), block_device))
+                .into_iter()
+                .collect(),
+            Storage::Virtio => find_devices(pci::id::VIRTIO_BLK)
+                .iter()
+                .filter_map(Self::probe_virtio)
+                .zip(b'a'..=b'z')
+                .map(|(block_device, letter)| (format!("vd{}", letter as char), block_device))
+                .collect(),
+            Storage::RamDisk => {
+                let block_device: Arc<dyn BlockDevice> = Arc::new(MemBlockWrapper::new());
+                alloc::vec![("ram0".to_string(), block_device)]
+            }
+        }
+    }
+    fn probe_ahci() -> Option<Arc<dyn BlockDevice>> {
+        let function = find_function(pci::class::AHCI)?;
+        function.enable();
+        // the registers are at BAR5, some integrated controllers use BAR0 instead
+        let abar = function.map_bar(5).or_else(|| function.map_bar(0))?;
+        Some(Arc::new(AhciBlock::new(abar)?))
+    }
+    fn probe_sdhci() -> Option<Arc<dyn BlockDevice>> {
+        let function = find_function(pci::class::SDHCI)?;
+        function.enable();
+        Some(Arc::new(SdhciBlock::new(function.map_bar(0)?)?))
+    }
+    fn probe_virtio(function: &PciFunction) -> Option<Arc<dyn BlockDevice>> {
+        function.enable();
+        let irq = function
+            .interrupt_line()
+            .filter(|irq| *irq < liointc::SOURCES);
+        let block_device = VirtIOBlock::new(function, irq)?;
+        if let Some(irq) = irq {
+            liointc::enable(irq);
+        }
+        Some(Arc::new(block_device))
+    }
+}
+
+/// Whether the first block holds a FAT boot sector or an ext4 super block
+fn has_file_system(block_device: &Arc<dyn BlockDevice>) -> bool {
+    let mut block = [0u8; BLOCK_SZ];
+    block_device.read_block(0, &mut block);
+    block[510..512] == [0x55, 0xaa] || block[1024 + 0x38..1024 + 0x3a] == [0x53, 0xef]
+}
+
+/// Set once `PROBED_DEVICES` is built, interrupts must not be the ones probing
+static DEVICES_READY: AtomicBool = AtomicBool::new(false);
 
 lazy_static! {
-    pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice> = Arc::new(BlockDeviceImpl::new());
+    /// The devices found from `board::STORAGE`
+    static ref PROBED_DEVICES: Vec<(String, Arc<dyn BlockDevice>)> = {
+        let devices = STORAGE
+            .iter()
+            .flat_map(|storage| storage.probe())
+            .inspect(|(name, _)| log::info!("[block] found /dev/{}", name))
+            .collect();
+        DEVICES_READY.store(true, Ordering::Release);
+        devices
+    };
+    /// The first device found with a file system, or the first one found if none has
+    pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice> = {
+        let (name, block_device) = PROBED_DEVICES
+            .iter()
+            .find(|(_, block_device)| has_file_system(block_device))
+            .or_else(|| PROBED_DEVICES.first())
+            .expect("no block device found");
+        log::info!("[block] root device: /dev/{}", name);
+        block_device.clone()
+    };
+    /// Block devices which can be used as the source of `mount`, indexed by their names under `/dev`
+    static ref BLOCK_DEVICE_TABLE: RwLock<BTreeMap<String, Arc<dyn BlockDevice>>> = {
+        let mut table = BTreeMap::<String, Arc<dyn BlockDevice>>::new();
+        table.insert(ROOT_DEVICE_NAME.to_string(), BLOCK_DEVICE.clone());
+        RwLock::new(table)
+    };
+}
+
+/// Poll `ready` until it holds, `ETIMEDOUT` after `timeout_ms` milliseconds
+fn wait_for(timeout_ms: usize, mut ready: impl FnMut() -> bool) -> Result<(), isize> {
+    let deadline = get_time_ms() + timeout_ms;
+    while !ready() {
+        if get_time_ms() > deadline {
+            return Err(ETIMEDOUT);
+        }
+        core::hint::spin_loop();
+    }
+    Ok(())
+}
+
+/// Reap completions and start queued requests on the probed devices without an interrupt,
+/// called from the timer interrupt and by idle cores
+pub fn poll_block_devices() {
+    if !DEVICES_READY.load(Ordering::Acquire) {
+        return;
+    }
+    for (_, block_device) in PROBED_DEVICES.iter() {
+        if block_device.irq().is_none() {
+            block_device.poll();
+        }
+    }
+}
+
+/// Poll the devices raising `irq`, returns whether there is one
+pub fn handle_block_irq(irq: usize) -> bool {
+    // the sources are enabled while probing, their devices are listed right after
+    if !DEVICES_READY.load(Ordering::Acquire) {
+        return true;
+    }
+    let mut handled = false;
+    for (_, block_device) in PROBED_DEVICES.iter() {
+        if block_device.irq() == Some(irq) {
+            block_device.poll();
+            handled = true;
+        }
+    }
+    handled
+}
+
+/// Probe the storage of the board and register every device found, so that they can be mounted
+pub fn init() {
+    for (name, block_device) in PROBED_DEVICES.iter() {
+        // the name is taken by the root device
+        if name == ROOT_DEVICE_NAME && !Arc::ptr_eq(block_device, &*BLOCK_DEVICE) {
+            log::warn!("[block] /dev/{} is taken by the root device", name);
+            continue;
+        }
+        register_block_device(name, block_device.clone());
+    }
+}
+
+pub fn register_block_device(name: &str, block_device: Arc<dyn BlockDevice>) {
+    BLOCK_DEVICE_TABLE
+        .write()
+        .insert(name.to_string(), block_device);
+}
+
+/// Look up a block device by its path, e.g. `/dev/vda`
+pub fn find_block_device(path: &str) -> Option<Arc<dyn BlockDevice>> {
+    let name = path.strip_prefix("/dev/").unwrap_or(path);
+    BLOCK_DEVICE_TABLE.read().get(name).cloned()
 }
 
 #[allow(unused)]
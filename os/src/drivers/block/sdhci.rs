@@ -0,0 +1,407 @@
+use super::{wait_for, BlockDevice, SECTOR_SZ};
+use crate::arch::BLOCK_SZ;
+use crate::syscall::errno::{EIO, ETIMEDOUT};
+use core::ptr::{read_volatile, write_volatile};
+use spin::Mutex;
+
+const COMMAND_TIMEOUT_MS: usize = 1000;
+/// Cards may take up to a second to leave the busy state after power up
+const POWER_UP_TIMEOUT_MS: usize = 1000;
+const IDENTIFICATION_CLOCK: usize = 400_000;
+const TRANSFER_CLOCK: usize = 25_000_000;
+/// Used when the capabilities don't tell the base clock
+const DEFAULT_BASE_CLOCK: usize = 50_000_000;
+
+mod offsets {
+    pub const BLOCK_SIZE: usize = 0x04;
+    pub const BLOCK_COUNT: usize = 0x06;
+    pub const ARGUMENT: usize = 0x08;
+    pub const TRANSFER_MODE: usize = 0x0c;
+    pub const COMMAND: usize = 0x0e;
+    pub const RESPONSE: usize = 0x10;
+    pub const BUFFER_DATA_PORT: usize = 0x20;
+    pub const PRESENT_STATE: usize = 0x24;
+    pub const POWER_CONTROL: usize = 0x29;
+    pub const CLOCK_CONTROL: usize = 0x2c;
+    pub const TIMEOUT_CONTROL: usize = 0x2e;
+    pub const SOFTWARE_RESET: usize = 0x2f;
+    pub const NORMAL_INT_STATUS: usize = 0x30;
+    pub const ERROR_INT_STATUS: usize = 0x32;
+    pub const NORMAL_INT_ENABLE: usize = 0x34;
+    pub const ERROR_INT_ENABLE: usize = 0x36;
+    pub const CAPABILITIES: usize = 0x40;
+    pub const HOST_VERSION: usize = 0xfe;
+}
+
+mod masks {
+    pub const CMD_INHIBIT: u32 = 1 << 0;
+    pub const DAT_INHIBIT: u32 = 1 << 1;
+    pub const CARD_INSERTED: u32 = 1 << 16;
+    pub const POWER_ON_3V3: u8 = (0b111 << 1) | 1;
+    pub const INTERNAL_CLOCK_ENABLE: u16 = 1 << 0;
+    pub const INTERNAL_CLOCK_STABLE: u16 = 1 << 1;
+    pub const SD_CLOCK_ENABLE: u16 = 1 << 2;
+    pub const RESET_ALL: u8 = 1 << 0;
+    pub const RESET_CMD: u8 = 1 << 1;
+    pub const RESET_DAT: u8 = 1 << 2;
+    pub const INT_COMMAND_COMPLETE: u16 = 1 << 0;
+    pub const INT_TRANSFER_COMPLETE: u16 = 1 << 1;
+    pub const INT_BUFFER_WRITE_READY: u16 = 1 << 4;
+    pub const INT_BUFFER_READ_READY: u16 = 1 << 5;
+    pub const INT_ERROR: u16 = 1 << 15;
+    pub const ERROR_COMMAND_TIMEOUT: u16 = 1 << 0;
+    pub const TRANSFER_BLOCK_COUNT_ENABLE: u16 = 1 << 1;
+    pub const TRANSFER_AUTO_CMD12: u16 = 1 << 2;
+    pub const TRANSFER_READ: u16 = 1 << 4;
+    pub const TRANSFER_MULTI_BLOCK: u16 = 1 << 5;
+    pub const COMMAND_CRC_CHECK: u16 = 1 << 3;
+    pub const COMMAND_INDEX_CHECK: u16 = 1 << 4;
+    pub const COMMAND_DATA_PRESENT: u16 = 1 << 5;
+    /// Set in the OCR once the card finished powering up
+    pub const OCR_READY: u32 = 1 << 31;
+    /// Card capacity status of SD, sector access mode of eMMC
+    pub const OCR_HIGH_CAPACITY: u32 = 1 << 30;
+    /// 2.7-3.6V
+    pub const OCR_VOLTAGE: u32 = 0x00ff_8000;
+}
+
+mod cmd {
+    pub const GO_IDLE_STATE: u16 = 0;
+    pub const SEND_OP_COND: u16 = 1;
+    pub const ALL_SEND_CID: u16 = 2;
+    pub const SET_RELATIVE_ADDR: u16 = 3;
+    pub const SELECT_CARD: u16 = 7;
+    pub const SEND_IF_COND: u16 = 8;
+    pub const SET_BLOCKLEN: u16 = 16;
+    pub const READ_SINGLE_BLOCK: u16 = 17;
+    pub const READ_MULTIPLE_BLOCK: u16 = 18;
+    pub const WRITE_BLOCK: u16 = 24;
+    pub const WRITE_MULTIPLE_BLOCK: u16 = 25;
+    pub const SD_SEND_OP_COND: u16 = 41;
+    pub const APP_CMD: u16 = 55;
+    /// Voltage 2.7-3.6V and the check pattern of `SEND_IF_COND`
+    pub const IF_COND: u32 = 0x1aa;
+}
+
+#[derive(Clone, Copy)]
+enum Response {
+    None,
+    /// R2, the CID or CSD
+    R136,
+    /// R1, R6 and R7
+    R48,
+    /// R1b
+    R48Busy,
+    /// R3, the OCR comes without CRC or index
+    Ocr,
+}
+
+impl Response {
+    fn command_flags(self) -> u16 {
+        let check = masks::COMMAND_CRC_CHECK | masks::COMMAND_INDEX_CHECK;
+        match self {
+            Response::None => 0,
+            Response::R136 => 1 | masks::COMMAND_CRC_CHECK,
+            Response::R48 => 2 | check,
+            Response::R48Busy => 3 | check,
+            Response::Ocr => 2,
+        }
+    }
+}
+
+/// An SD card or eMMC behind a standard SD host controller.
+/// Data moves through the buffer data port, there is no DMA.
+struct Sdhci {
+    base: usize,
+    /// `0` for spec 1.00, `2` for 3.00
+    version: u8,
+    base_clock: usize,
+    /// Whether the card is addressed in sectors (SDHC/SDXC and large eMMC) rather than bytes
+    sector_addressing: bool,
+}
+
+pub struct SdhciBlock(Mutex<Sdhci>);
+
+impl Sdhci {
+    fn read8(&self, offset: usize) -> u8 {
+        unsafe { read_volatile((self.base + offset) as *const u8) }
+    }
+    fn write8(&self, offset: usize, value: u8) {
+        unsafe { write_volatile((self.base + offset) as *mut u8, value) }
+    }
+    fn read16(&self, offset: usize) -> u16 {
+        unsafe { read_volatile((self.base + offset) as *const u16) }
+    }
+    fn write16(&self, offset: usize, value: u16) {
+        unsafe { write_volatile((self.base + offset) as *mut u16, value) }
+    }
+    fn read32(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.base + offset) as *const u32) }
+    }
+    fn write32(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.base + offset) as *mut u32, value) }
+    }
+    fn reset(&self, mask: u8) -> Result<(), isize> {
+        self.write8(offsets::SOFTWARE_RESET, mask);
+        wait_for(COMMAND_TIMEOUT_MS, || {
+            self.read8(offsets::SOFTWARE_RESET) & mask == 0
+        })
+    }
+    fn set_clock(&self, clock: usize) -> Result<(), isize> {
+        self.write16(offsets::CLOCK_CONTROL, 0);
+        // SDCLK is the base clock divided by twice the divisor, or the base clock for 0
+        let mut divisor = (self.base_clock + 2 * clock - 1) / (2 * clock);
+        if self.version < 2 {
+            // 8 bits and only powers of two before 3.00
+            divisor = divisor.next_power_of_two().min(0x80);
+        } else {
+            divisor = divisor.min(0x3ff);
+        }
+        let divisor = divisor as u16;
+        let control = ((divisor & 0xff) << 8) | ((divisor >> 8) << 6);
+        self.write16(
+            offsets::CLOCK_CONTROL,
+            control | masks::INTERNAL_CLOCK_ENABLE,
+        );
+        wait_for(COMMAND_TIMEOUT_MS, || {
+            self.read16(offsets::CLOCK_CONTROL) & masks::INTERNAL_CLOCK_STABLE != 0
+        })?;
+        self.write16(
+            offsets::CLOCK_CONTROL,
+            control | masks::INTERNAL_CLOCK_ENABLE | masks::SD_CLOCK_ENABLE,
+        );
+        Ok(())
+    }
+    /// Wait for any of `mask` in the normal interrupt status and acknowledge it
+    fn wait_interrupt(&self, mask: u16) -> Result<(), isize> {
+        let result = wait_for(COMMAND_TIMEOUT_MS, || {
+            self.read16(offsets::NORMAL_INT_STATUS) & (mask | masks::INT_ERROR) != 0
+        });
+        let error = self.read16(offsets::ERROR_INT_STATUS);
+        if result.is_err() || error != 0 {
+            self.write16(offsets::ERROR_INT_STATUS, error);
+            self.write16(offsets::NORMAL_INT_STATUS, u16::MAX);
+            // the lines stay inhibited after an error until they are reset
+            self.reset(masks::RESET_CMD | masks::RESET_DAT)?;
+            let timed_out = result.is_err() || error & masks::ERROR_COMMAND_TIMEOUT != 0;
+            return Err(if timed_out { ETIMEDOUT } else { EIO });
+        }
+        self.write16(offsets::NORMAL_INT_STATUS, mask);
+        Ok(())
+    }
+    /// Send a command and return the first word of its response,
+    /// `data` is the number of blocks to transfer and the direction, the transfer itself is left to the caller.
+    fn command(
+        &self,
+        index: u16,
+        argument: u32,
+        response: Response,
+        data: Option<(usize, bool)>,
+    ) -> Result<u32, isize> {
+        let mut inhibit = masks::CMD_INHIBIT;
+        if data.is_some() || matches!(response, Response::R48Busy) {
+            inhibit |= masks::DAT_INHIBIT;
+        }
+        wait_for(COMMAND_TIMEOUT_MS, || {
+            self.read32(offsets::PRESENT_STATE) & inhibit == 0
+        })?;
+        self.write16(offsets::NORMAL_INT_STATUS, u16::MAX);
+        self.write16(offsets::ERROR_INT_STATUS, u16::MAX);
+        let mut command = (index << 8) | response.command_flags();
+        if let Some((blocks, read)) = data {
+            let mut mode = masks::TRANSFER_BLOCK_COUNT_ENABLE;
+            if read {
+                mode |= masks::TRANSFER_READ;
+            }
+            if blocks > 1 {
+                mode |= masks::TRANSFER_MULTI_BLOCK | masks::TRANSFER_AUTO_CMD12;
+            }
+            self.write16(offsets::BLOCK_SIZE, SECTOR_SZ as u16);
+            self.write16(offsets::BLOCK_COUNT, blocks as u16);
+            self.write16(offsets::TRANSFER_MODE, mode);
+            command |= masks::COMMAND_DATA_PRESENT;
+        } else {
+            self.write16(offsets::TRANSFER_MODE, 0);
+        }
+        self.write32(offsets::ARGUMENT, argument);
+        // writing the command register issues the command
+        self.write16(offsets::COMMAND, command);
+        self.wait_interrupt(masks::INT_COMMAND_COMPLETE)?;
+        if matches!(response, Response::R48Busy) {
+            wait_for(COMMAND_TIMEOUT_MS, || {
+                self.read32(offsets::PRESENT_STATE) & masks::DAT_INHIBIT == 0
+            })?;
+        }
+        Ok(self.read32(offsets::RESPONSE))
+    }
+    /// The power up command of SD or eMMC, returns the OCR
+    fn send_op_cond(&self, is_sd: bool, argument: u32) -> Result<u32, isize> {
+        if is_sd {
+            self.command(cmd::APP_CMD, 0, Response::R48, None)?;
+            self.command(cmd::SD_SEND_OP_COND, argument, Response::Ocr, None)
+        } else {
+            self.command(cmd::SEND_OP_COND, argument, Response::Ocr, None)
+        }
+    }
+    /// Repeat `send_op_cond()` until the card is ready
+    fn wait_power_up(&self, is_sd: bool, argument: u32) -> Result<u32, isize> {
+        let mut ocr = Err(ETIMEDOUT);
+        wait_for(POWER_UP_TIMEOUT_MS, || {
+            ocr = self.send_op_cond(is_sd, argument);
+            matches!(ocr, Ok(ocr) if ocr & masks::OCR_READY != 0)
+        })?;
+        ocr
+    }
+    fn init(&mut self) -> Result<(), isize> {
+        self.reset(masks::RESET_ALL)?;
+        self.version = self.read16(offsets::HOST_VERSION) as u8;
+        let capabilities = self.read32(offsets::CAPABILITIES);
+        let base_clock_mhz = if self.version < 2 {
+            (capabilities >> 8) & 0x3f
+        } else {
+            (capabilities >> 8) & 0xff
+        } as usize;
+        self.base_clock = match base_clock_mhz {
+            0 => DEFAULT_BASE_CLOCK,
+            mhz => mhz * 1_000_000,
+        };
+        self.write8(offsets::POWER_CONTROL, masks::POWER_ON_3V3);
+        self.write16(offsets::NORMAL_INT_ENABLE, u16::MAX);
+        self.write16(offsets::ERROR_INT_ENABLE, u16::MAX);
+        // the longest data timeout
+        self.write8(offsets::TIMEOUT_CONTROL, 0xe);
+        self.set_clock(IDENTIFICATION_CLOCK)?;
+
+        self.command(cmd::GO_IDLE_STATE, 0, Response::None, None)?;
+        // only SD cards of version 2.00 or later answer, they may be high capacity
+        let sd_v2 = self
+            .command(cmd::SEND_IF_COND, cmd::IF_COND, Response::R48, None)
+            .map_or(false, |response| response & 0xfff == cmd::IF_COND);
+        let mut argument = masks::OCR_VOLTAGE;
+        if sd_v2 {
+            argument |= masks::OCR_HIGH_CAPACITY;
+        }
+        // eMMC doesn't know application commands
+        let is_sd = self.send_op_cond(true, argument).is_ok();
+        if !is_sd {
+            self.command(cmd::GO_IDLE_STATE, 0, Response::None, None)?;
+            argument = masks::OCR_VOLTAGE | masks::OCR_HIGH_CAPACITY;
+        }
+        let ocr = self.wait_power_up(is_sd, argument)?;
+        self.sector_addressing = ocr & masks::OCR_HIGH_CAPACITY != 0;
+
+        self.command(cmd::ALL_SEND_CID, 0, Response::R136, None)?;
+        // SD cards pick their address, eMMC is given one
+        let rca = if is_sd {
+            self.command(cmd::SET_RELATIVE_ADDR, 0, Response::R48, None)? >> 16
+        } else {
+            self.command(cmd::SET_RELATIVE_ADDR, 1 << 16, Response::R48, None)?;
+            1
+        };
+        self.command(cmd::SELECT_CARD, rca << 16, Response::R48Busy, None)?;
+        if !self.sector_addressing {
+            self.command(cmd::SET_BLOCKLEN, SECTOR_SZ as u32, Response::R48, None)?;
+        }
+        self.set_clock(TRANSFER_CLOCK)?;
+        log::info!(
+            "[sdhci] {} at {:#x}, {} addressing",
+            if is_sd { "SD card" } else { "eMMC" },
+            self.base,
+            if self.sector_addressing {
+                "sector"
+            } else {
+                "byte"
+            }
+        );
+        Ok(())
+    }
+    fn address(&self, sector: usize) -> u32 {
+        if self.sector_addressing {
+            sector as u32
+        } else {
+            (sector * SECTOR_SZ) as u32
+        }
+    }
+    fn read(&self, mut sector: usize, buf: &mut [u8]) -> Result<(), isize> {
+        for chunk in buf.chunks_mut(u16::MAX as usize * SECTOR_SZ) {
+            let blocks = chunk.len() / SECTOR_SZ;
+            let index = if blocks > 1 {
+                cmd::READ_MULTIPLE_BLOCK
+            } else {
+                cmd::READ_SINGLE_BLOCK
+            };
+            let argument = self.address(sector);
+            self.command(index, argument, Response::R48, Some((blocks, true)))?;
+            for block in chunk.chunks_mut(SECTOR_SZ) {
+                self.wait_interrupt(masks::INT_BUFFER_READ_READY)?;
+                for word in block.chunks_mut(4) {
+                    word.copy_from_slice(&self.read32(offsets::BUFFER_DATA_PORT).to_le_bytes());
+                }
+            }
+            self.wait_interrupt(masks::INT_TRANSFER_COMPLETE)?;
+            sector += blocks;
+        }
+        Ok(())
+    }
+    fn write(&self, mut sector: usize, buf: &[u8]) -> Result<(), isize> {
+        for chunk in buf.chunks(u16::MAX as usize * SECTOR_SZ) {
+            let blocks = chunk.len() / SECTOR_SZ;
+            let index = if blocks > 1 {
+                cmd::WRITE_MULTIPLE_BLOCK
+            } else {
+                cmd::WRITE_BLOCK
+            };
+            let argument = self.address(sector);
+            self.command(index, argument, Response::R48, Some((blocks, false)))?;
+            for block in chunk.chunks(SECTOR_SZ) {
+                self.wait_interrupt(masks::INT_BUFFER_WRITE_READY)?;
+                for word in block.chunks(4) {
+                    let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+                    self.write32(offsets::BUFFER_DATA_PORT, word);
+                }
+            }
+            self.wait_interrupt(masks::INT_TRANSFER_COMPLETE)?;
+            sector += blocks;
+        }
+        Ok(())
+    }
+}
+
+impl SdhciBlock {
+    /// Bring up the card in the slot of the host controller whose registers are at `base`
+    pub fn new(base: usize) -> Option<Self> {
+        let mut sdhci = Sdhci {
+            base,
+            version: 0,
+            base_clock: DEFAULT_BASE_CLOCK,
+            sector_addressing: false,
+        };
+        if sdhci.read32(offsets::PRESENT_STATE) & masks::CARD_INSERTED == 0 {
+            return None;
+        }
+        match sdhci.init() {
+            Ok(()) => Some(Self(Mutex::new(sdhci))),
+            Err(errno) => {
+                log::warn!("[sdhci] card at {:#x} failed to start: {}", base, errno);
+                None
+            }
+        }
+    }
+}
+
+impl BlockDevice for SdhciBlock {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        assert_eq!(buf.len() % SECTOR_SZ, 0);
+        self.0
+            .lock()
+            .read(block_id * (BLOCK_SZ / SECTOR_SZ), buf)
+            .expect("Error when reading SD card");
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        assert_eq!(buf.len() % SECTOR_SZ, 0);
+        self.0
+            .lock()
+            .write(block_id * (BLOCK_SZ / SECTOR_SZ), buf)
+            .expect("Error when writing SD card");
+    }
+}
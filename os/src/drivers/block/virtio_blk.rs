@@ -1,49 +1,562 @@
-use super::BlockDevice;
+use super::{BlockDevice, BlockOp, BlockRequest, Elevator, RequestFlags, SECTOR_SZ};
+use crate::arch::BLOCK_SZ;
+use crate::config::PAGE_SIZE;
+use crate::drivers::pci::PciFunction;
 use crate::mm::{
     frame_alloc, frame_dealloc, kernel_token, FrameTracker, PageTable, PageTableImpl, PhysAddr,
     PhysPageNum, StepByOne, VirtAddr,
 };
-use alloc::{sync::Arc, vec::Vec};
+use crate::syscall::errno::{EINVAL, EIO, EOPNOTSUPP};
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, Ordering};
 use lazy_static::*;
 use spin::Mutex;
-use virtio_drivers::{VirtIOBlk, VirtIOHeader};
-const VIRT_IO_BLOCK_SZ: usize = 512;
-#[allow(unused)]
-const VIRTIO0: usize = 0x1000_1000;
+/// Descriptors we ask for, the device may offer fewer
+const QUEUE_SIZE: u16 = 64;
+/// No MSI-X vector, the device raises INTx instead
+const NO_VECTOR: u16 = 0xffff;
+/// Limits of a merged request, lowered by `request_limits()` for a small queue
+const MAX_REQUEST_SECTORS: usize = 256;
+const MAX_REQUEST_SEGMENTS: usize = 16;
+/// Descriptors of a request besides its data: the header, the status and a discard range
+const REQUEST_EXTRA_DESCRIPTORS: usize = 3;
 
-pub struct VirtIOBlock(Mutex<VirtIOBlk<'static>>);
+mod features {
+    pub const FLUSH: u64 = 1 << 9;
+    pub const DISCARD: u64 = 1 << 13;
+    /// The device follows virtio 1.0, required by the PCI transport we implement
+    pub const VERSION_1: u64 = 1 << 32;
+}
+
+/// The vendor capabilities of a virtio-pci function, which locate its register blocks
+mod cap {
+    pub const VENDOR: u8 = 0x09;
+    // fields of a capability
+    pub const CFG_TYPE: usize = 3;
+    pub const BAR: usize = 4;
+    pub const OFFSET: usize = 8;
+    pub const NOTIFY_OFF_MULTIPLIER: usize = 16;
+    // kinds of register blocks
+    pub const COMMON: u8 = 1;
+    pub const NOTIFY: u8 = 2;
+    pub const ISR: u8 = 3;
+    pub const DEVICE: u8 = 4;
+}
+
+/// Registers of the common configuration block
+mod common {
+    pub const DEVICE_FEATURE_SELECT: usize = 0x00;
+    pub const DEVICE_FEATURE: usize = 0x04;
+    pub const DRIVER_FEATURE_SELECT: usize = 0x08;
+    pub const DRIVER_FEATURE: usize = 0x0c;
+    pub const MSIX_CONFIG: usize = 0x10;
+    pub const DEVICE_STATUS: usize = 0x14;
+    pub const QUEUE_SELECT: usize = 0x16;
+    pub const QUEUE_SIZE: usize = 0x18;
+    pub const QUEUE_MSIX_VECTOR: usize = 0x1a;
+    pub const QUEUE_ENABLE: usize = 0x1c;
+    pub const QUEUE_NOTIFY_OFF: usize = 0x1e;
+    pub const QUEUE_DESC: usize = 0x20;
+    pub const QUEUE_DRIVER: usize = 0x28;
+    pub const QUEUE_DEVICE: usize = 0x30;
+}
+
+mod device_status {
+    pub const ACKNOWLEDGE: u8 = 1;
+    pub const DRIVER: u8 = 2;
+    pub const DRIVER_OK: u8 = 4;
+    pub const FEATURES_OK: u8 = 8;
+    pub const FAILED: u8 = 128;
+}
+
+mod req_type {
+    pub const IN: u32 = 0;
+    pub const OUT: u32 = 1;
+    pub const FLUSH: u32 = 4;
+    pub const DISCARD: u32 = 11;
+}
+
+mod status {
+    pub const OK: u8 = 0;
+    pub const UNSUPP: u8 = 2;
+}
+
+mod desc_flags {
+    pub const NEXT: u16 = 1;
+    pub const WRITE: u16 = 2;
+}
+
+/// Where the per-request structures live in `request_page`, indexed by the head descriptor
+mod offsets {
+    pub const HEADER: usize = 0x000;
+    pub const STATUS: usize = 0x400;
+    pub const DISCARD: usize = 0x800;
+}
+
+/// Kernel addresses of the register blocks of a virtio-pci function
+struct Transport {
+    common: usize,
+    /// The notification register of queue 0 once it is set up
+    notify: usize,
+    notify_off_multiplier: usize,
+    isr: usize,
+    device: usize,
+}
+
+impl Transport {
+    /// Find the register blocks from the vendor capabilities, the first of each kind is used.
+    /// `None` for a legacy only device, its I/O port registers are not supported.
+    fn new(function: &PciFunction) -> Option<Self> {
+        let (mut common, mut notify, mut isr, mut device) = (None, None, None, None);
+        let mut notify_off_multiplier = 0;
+        for (id, offset) in function.capabilities() {
+            if id != cap::VENDOR {
+                continue;
+            }
+            let bar = function.read8(offset + cap::BAR) as usize;
+            let addr = match function.map_bar(bar) {
+                Some(base) => base + function.read32(offset + cap::OFFSET) as usize,
+                None => continue,
+            };
+            match function.read8(offset + cap::CFG_TYPE) {
+                cap::COMMON => common = common.or(Some(addr)),
+                cap::NOTIFY if notify.is_none() => {
+                    notify = Some(addr);
+                    notify_off_multiplier =
+                        function.read32(offset + cap::NOTIFY_OFF_MULTIPLIER) as usize;
+                }
+                cap::ISR => isr = isr.or(Some(addr)),
+                cap::DEVICE => device = device.or(Some(addr)),
+                _ => {}
+            }
+        }
+        Some(Self {
+            common: common?,
+            notify: notify?,
+            notify_off_multiplier,
+            isr: isr?,
+            device: device?,
+        })
+    }
+    fn read<T>(&self, addr: usize) -> T {
+        unsafe { read_volatile(addr as *const T) }
+    }
+    fn write<T>(&self, addr: usize, value: T) {
+        unsafe { write_volatile(addr as *mut T, value) }
+    }
+    fn read_common<T>(&self, offset: usize) -> T {
+        self.read(self.common + offset)
+    }
+    fn write_common<T>(&self, offset: usize, value: T) {
+        self.write(self.common + offset, value)
+    }
+    /// 64-bit registers are accessed as two halves, low first
+    fn write_common64(&self, offset: usize, value: u64) {
+        self.write_common(offset, value as u32);
+        self.write_common(offset + 4, (value >> 32) as u32);
+    }
+    fn status(&self) -> u8 {
+        self.read_common(common::DEVICE_STATUS)
+    }
+    fn set_status(&self, status: u8) {
+        self.write_common(common::DEVICE_STATUS, status);
+    }
+    fn device_features(&self) -> u64 {
+        let mut features = 0;
+        for select in 0..2u32 {
+            self.write_common(common::DEVICE_FEATURE_SELECT, select);
+            features |= (self.read_common::<u32>(common::DEVICE_FEATURE) as u64) << (32 * select);
+        }
+        features
+    }
+    fn set_driver_features(&self, features: u64) {
+        for select in 0..2u32 {
+            self.write_common(common::DRIVER_FEATURE_SELECT, select);
+            self.write_common(common::DRIVER_FEATURE, (features >> (32 * select)) as u32);
+        }
+    }
+    /// Reading the ISR status acknowledges the interrupt and deasserts INTx
+    fn ack_interrupt(&self) {
+        self.read::<u8>(self.isr);
+    }
+    /// Tell the device queue 0 has new buffers
+    fn notify(&self) {
+        self.write(self.notify, 0u16);
+    }
+    fn capacity(&self) -> u64 {
+        self.read::<u32>(self.device) as u64 | (self.read::<u32>(self.device + 4) as u64) << 32
+    }
+}
+
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+/// A split virtqueue in one physically contiguous piece: the descriptor table and the available ring,
+/// then the used ring on the next page boundary
+struct VirtQueue {
+    /// Kernel address of the physically contiguous pages
+    base: usize,
+    size: u16,
+    used_offset: usize,
+    free_head: u16,
+    num_free: usize,
+    avail_idx: u16,
+    last_used_idx: u16,
+}
+
+impl VirtQueue {
+    fn layout(size: u16) -> (usize, usize) {
+        let size = size as usize;
+        let avail_end = 16 * size + 2 * (3 + size);
+        let used_offset = (avail_end + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        let used_end = used_offset + 2 * 3 + 8 * size;
+        (used_offset, (used_end + PAGE_SIZE - 1) / PAGE_SIZE)
+    }
+    /// Set up queue 0 of `transport`, which must be selected
+    fn new(transport: &Transport, size: u16) -> Self {
+        let (used_offset, pages) = Self::layout(size);
+        let pa = virtio_dma_alloc(pages);
+        unsafe { core::slice::from_raw_parts_mut(pa.0 as *mut u8, pages * PAGE_SIZE).fill(0) };
+        transport.write_common(common::QUEUE_SIZE, size);
+        transport.write_common(common::QUEUE_MSIX_VECTOR, NO_VECTOR);
+        transport.write_common64(common::QUEUE_DESC, pa.0 as u64);
+        transport.write_common64(common::QUEUE_DRIVER, (pa.0 + 16 * size as usize) as u64);
+        transport.write_common64(common::QUEUE_DEVICE, (pa.0 + used_offset) as u64);
+        transport.write_common(common::QUEUE_ENABLE, 1u16);
+        let queue = Self {
+            base: virtio_phys_to_virt(pa).0,
+            size,
+            used_offset,
+            free_head: 0,
+            num_free: size as usize,
+            avail_idx: 0,
+            last_used_idx: 0,
+        };
+        for i in 0..size - 1 {
+            queue.desc(i).next = i + 1;
+        }
+        queue
+    }
+    fn desc(&self, index: u16) -> &'static mut Descriptor {
+        unsafe { &mut *((self.base + 16 * index as usize) as *mut Descriptor) }
+    }
+    /// The available ring: flags, idx, then the ring
+    fn avail(&self, index: usize) -> *mut u16 {
+        (self.base + 16 * self.size as usize + 2 * index) as *mut u16
+    }
+    /// The used ring: flags and idx, then elements of (id: u32, len: u32)
+    fn used(&self, offset: usize) -> usize {
+        self.base + self.used_offset + offset
+    }
+    /// Chain `buffers` of (physical address, length, device writes) and make it available,
+    /// returns the head descriptor
+    fn add(&mut self, buffers: &[(usize, usize, bool)]) -> u16 {
+        assert!(buffers.len() <= self.num_free);
+        let head = self.free_head;
+        let mut last = head;
+        for (addr, len, device_writes) in buffers.iter() {
+            let desc = self.desc(self.free_head);
+            desc.addr = *addr as u64;
+            desc.len = *len as u32;
+            desc.flags = desc_flags::NEXT;
+            if *device_writes {
+                desc.flags |= desc_flags::WRITE;
+            }
+            last = self.free_head;
+            self.free_head = desc.next;
+        }
+        self.desc(last).flags &= !desc_flags::NEXT;
+        self.num_free -= buffers.len();
+        let slot = 2 + (self.avail_idx % self.size) as usize;
+        unsafe { write_volatile(self.avail(slot), head) };
+        // the ring entry must be visible before the index
+        fence(Ordering::SeqCst);
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        unsafe { write_volatile(self.avail(1), self.avail_idx) };
+        head
+    }
+    /// Take a chain the device is done with and recycle its descriptors, returns its head
+    fn pop_used(&mut self) -> Option<u16> {
+        let used_idx = unsafe { read_volatile(self.used(2) as *const u16) };
+        if used_idx == self.last_used_idx {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+        let slot = (self.last_used_idx % self.size) as usize;
+        let head = unsafe { read_volatile(self.used(4 + 8 * slot) as *const u32) } as u16;
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+        let mut index = head;
+        loop {
+            self.num_free += 1;
+            let desc = self.desc(index);
+            if desc.flags & desc_flags::NEXT == 0 {
+                desc.next = self.free_head;
+                break;
+            }
+            index = desc.next;
+        }
+        self.free_head = head;
+        Some(head)
+    }
+}
+
+struct VirtIOBlockInner {
+    transport: Transport,
+    queue: VirtQueue,
+    /// Request headers, status bytes and discard ranges
+    request_page: Arc<FrameTracker>,
+    features: u64,
+    elevator: Elevator,
+    /// Started requests by their head descriptors
+    in_flight: BTreeMap<u16, BlockRequest>,
+}
+
+/// A virtio-blk device on PCI, requests are queued, merged and dispatched by an `Elevator`
+/// and complete in `poll()`, the handler of its interrupt, or of the timer without one.
+pub struct VirtIOBlock {
+    inner: Mutex<VirtIOBlockInner>,
+    /// The interrupt controller source of its INTx
+    irq: Option<usize>,
+    /// Limits of a request, so that its descriptors always fit in the queue
+    max_sectors: usize,
+    max_segments: usize,
+}
+
+/// Most sectors and segments of a request whose descriptors fit in a queue of `size`,
+/// `None` if the queue is too small for any. A segment takes one descriptor per page it touches,
+/// which is at most its pages of data plus two when it starts and ends inside a page.
+fn request_limits(size: u16) -> Option<(usize, usize)> {
+    let data = (size as usize).checked_sub(REQUEST_EXTRA_DESCRIPTORS)?;
+    let segments = MAX_REQUEST_SEGMENTS.min(data / 4).max(1);
+    let pages = data.checked_sub(2 * segments).filter(|pages| *pages > 0)?;
+    let sectors = MAX_REQUEST_SECTORS.min(pages * PAGE_SIZE / SECTOR_SZ);
+    Some((sectors, segments))
+}
 
 lazy_static! {
     static ref QUEUE_FRAMES: Mutex<Vec<Arc<FrameTracker>>> = Mutex::new(Vec::new());
 }
 
+impl VirtIOBlockInner {
+    fn request_bytes(&self, offset: usize, len: usize) -> &'static mut [u8] {
+        &mut self.request_page.ppn.get_bytes_array()[offset..offset + len]
+    }
+    /// Put `request` on the queue, it's given back if there are not enough free descriptors.
+    /// Returns whether the device has to be notified, some requests complete right away.
+    fn start(&mut self, request: BlockRequest) -> Result<bool, BlockRequest> {
+        let type_ = match request.op {
+            BlockOp::Read => req_type::IN,
+            BlockOp::Write => req_type::OUT,
+            // without a volatile write cache there is nothing to flush
+            BlockOp::Flush if self.features & features::FLUSH == 0 => {
+                request.complete(Ok(()));
+                return Ok(false);
+            }
+            BlockOp::Flush => req_type::FLUSH,
+            BlockOp::Discard if self.features & features::DISCARD == 0 => {
+                request.complete(Err(EOPNOTSUPP));
+                return Ok(false);
+            }
+            BlockOp::Discard => req_type::DISCARD,
+        };
+        let head = self.queue.free_head as usize;
+        let page = PhysAddr::from(self.request_page.ppn).0;
+        let mut buffers = Vec::with_capacity(request.segments.len() + 3);
+        buffers.push((page + offsets::HEADER + 16 * head, 16, false));
+        for segment in request.segments.iter() {
+            // contiguous in the kernel address space only, translate page by page
+            let mut addr = segment.addr;
+            let end = segment.addr + segment.len;
+            while addr < end {
+                let len = (PAGE_SIZE - addr % PAGE_SIZE).min(end - addr);
+                let pa = virtio_virt_to_phys(VirtAddr(addr)).0;
+                buffers.push((pa, len, request.op == BlockOp::Read));
+                addr += len;
+            }
+        }
+        if request.op == BlockOp::Discard {
+            buffers.push((page + offsets::DISCARD + 16 * head, 16, false));
+        }
+        buffers.push((page + offsets::STATUS + head, 1, true));
+        if buffers.len() > self.queue.num_free {
+            return Err(request);
+        }
+
+        let sector = if request.op == BlockOp::Flush {
+            0
+        } else {
+            request.sector as u64
+        };
+        let header = self.request_bytes(offsets::HEADER + 16 * head, 16);
+        header[0..4].copy_from_slice(&type_.to_le_bytes());
+        header[4..8].fill(0);
+        header[8..16].copy_from_slice(&sector.to_le_bytes());
+        if request.op == BlockOp::Discard {
+            let range = self.request_bytes(offsets::DISCARD + 16 * head, 16);
+            range[0..8].copy_from_slice(&(request.sector as u64).to_le_bytes());
+            range[8..12].copy_from_slice(&(request.sectors as u32).to_le_bytes());
+            range[12..16].fill(0);
+        }
+        self.request_bytes(offsets::STATUS + head, 1)[0] = u8::MAX;
+        let head = self.queue.add(&buffers);
+        self.in_flight.insert(head, request);
+        Ok(true)
+    }
+    /// Start queued requests until the elevator runs dry or the queue is full
+    fn kick(&mut self, mut notify: bool) {
+        while let Some(request) = self.elevator.dispatch(self.in_flight.is_empty()) {
+            match self.start(request) {
+                Ok(queued) => notify |= queued,
+                Err(request) => {
+                    self.elevator.requeue(request);
+                    break;
+                }
+            }
+        }
+        if notify {
+            self.transport.notify();
+        }
+    }
+    /// Complete the requests the device is done with and start more
+    fn reap(&mut self) {
+        self.transport.ack_interrupt();
+        let mut notify = false;
+        while let Some(head) = self.queue.pop_used() {
+            let request = self.in_flight.remove(&head).unwrap();
+            let result = match self.request_bytes(offsets::STATUS + head as usize, 1)[0] {
+                status::OK => Ok(()),
+                status::UNSUPP => Err(EOPNOTSUPP),
+                _ => Err(EIO),
+            };
+            // there is no FUA in virtio, a flush follows the write instead
+            if result.is_ok()
+                && request.flags.contains(RequestFlags::FUA)
+                && self.features & features::FLUSH != 0
+            {
+                match self.start(request.into_flush()) {
+                    Ok(queued) => notify |= queued,
+                    Err(flush) => self.elevator.requeue(flush),
+                }
+            } else {
+                request.complete(result);
+            }
+        }
+        self.kick(notify);
+    }
+}
+
 impl BlockDevice for VirtIOBlock {
-    fn read_block(&self, mut block_id: usize, buf: &mut [u8]) {
-        for buf in buf.chunks_mut(VIRT_IO_BLOCK_SZ) {
-            self.0
-                .lock()
-                .read_block(block_id, buf)
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        let mut sector = block_id * (BLOCK_SZ / SECTOR_SZ);
+        for buf in buf.chunks_mut(self.max_sectors * SECTOR_SZ) {
+            self.submit_wait(BlockRequest::read(sector, buf))
                 .expect("Error when reading VirtIOBlk");
-            block_id += 1;
+            sector += buf.len() / SECTOR_SZ;
         }
     }
-    fn write_block(&self, mut block_id: usize, buf: &[u8]) {
-        for buf in buf.chunks(VIRT_IO_BLOCK_SZ) {
-            self.0
-                .lock()
-                .write_block(block_id, buf)
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        let mut sector = block_id * (BLOCK_SZ / SECTOR_SZ);
+        for buf in buf.chunks(self.max_sectors * SECTOR_SZ) {
+            self.submit_wait(BlockRequest::write(sector, buf))
                 .expect("Error when writing VirtIOBlk");
-            block_id += 1;
+            sector += buf.len() / SECTOR_SZ;
         }
     }
+    fn submit(&self, request: BlockRequest) {
+        let data_len = request
+            .segments
+            .iter()
+            .map(|segment| segment.len)
+            .sum::<usize>();
+        if request.segments.len() > self.max_segments
+            || (request.op != BlockOp::Discard && request.sectors > self.max_sectors)
+            || data_len % SECTOR_SZ != 0
+        {
+            request.complete(Err(EINVAL));
+            return;
+        }
+        let mut inner = self.inner.lock();
+        inner.elevator.add(request);
+        inner.kick(false);
+    }
+    fn poll(&self) {
+        // may be called in the interrupt of a core holding the lock
+        if let Some(mut inner) = self.inner.try_lock() {
+            inner.reap();
+        }
+    }
+    fn irq(&self) -> Option<usize> {
+        self.irq
+    }
 }
 
 impl VirtIOBlock {
-    #[allow(unused)]
-    pub fn new() -> Self {
-        Self(Mutex::new(
-            VirtIOBlk::new(unsafe { &mut *(VIRTIO0 as *mut VirtIOHeader) }).unwrap(),
-        ))
+    /// Initialize the virtio-blk `function`, which raises `irq` if it is routed
+    pub fn new(function: &PciFunction, irq: Option<usize>) -> Option<Self> {
+        let mut transport = match Transport::new(function) {
+            Some(transport) => transport,
+            None => {
+                log::warn!("[virtio_blk] legacy only device, not supported");
+                return None;
+            }
+        };
+        transport.set_status(0);
+        while transport.status() != 0 {
+            core::hint::spin_loop();
+        }
+        transport.set_status(device_status::ACKNOWLEDGE | device_status::DRIVER);
+        let negotiated = transport.device_features()
+            & (features::VERSION_1 | features::FLUSH | features::DISCARD);
+        transport.set_driver_features(negotiated);
+        transport.set_status(transport.status() | device_status::FEATURES_OK);
+        if negotiated & features::VERSION_1 == 0
+            || transport.status() & device_status::FEATURES_OK == 0
+        {
+            transport.set_status(device_status::FAILED);
+            log::warn!("[virtio_blk] features {:#x} refused", negotiated);
+            return None;
+        }
+        transport.write_common(common::MSIX_CONFIG, NO_VECTOR);
+        transport.write_common(common::QUEUE_SELECT, 0u16);
+        let size = QUEUE_SIZE.min(transport.read_common::<u16>(common::QUEUE_SIZE));
+        let (max_sectors, max_segments) = match request_limits(size) {
+            Some(limits) => limits,
+            None => {
+                transport.set_status(device_status::FAILED);
+                log::warn!("[virtio_blk] queue of {} descriptors is too small", size);
+                return None;
+            }
+        };
+        let queue = VirtQueue::new(&transport, size);
+        let notify_off = transport.read_common::<u16>(common::QUEUE_NOTIFY_OFF) as usize;
+        transport.notify += notify_off * transport.notify_off_multiplier;
+        transport.set_status(transport.status() | device_status::DRIVER_OK);
+        log::info!(
+            "[virtio_blk] {} MiB, features: {:#x}, irq: {:?}, queue: {}",
+            (transport.capacity() as usize * SECTOR_SZ) >> 20,
+            negotiated,
+            irq,
+            size
+        );
+        Some(Self {
+            inner: Mutex::new(VirtIOBlockInner {
+                transport,
+                queue,
+                request_page: frame_alloc().unwrap(),
+                features: negotiated,
+                elevator: Elevator::new(max_sectors, max_segments),
+                in_flight: BTreeMap::new(),
+            }),
+            irq,
+            max_sectors,
+            max_segments,
+        })
     }
 }
 
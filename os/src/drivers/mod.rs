@@ -1,4 +1,6 @@
 pub mod block;
+pub mod pci;
+pub mod rtc;
 pub mod serial;
 
 pub use block::BLOCK_DEVICE;
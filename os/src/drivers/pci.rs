@@ -0,0 +1,211 @@
+//! Just enough PCI to find the storage controllers on bus 0.
+//! The configuration space is memory mapped at `board::PCI_CONFIG_BASE`,
+//! memory BARs left unassigned by the firmware are placed in `board::PCI_MEM_WINDOW`.
+use crate::arch::board::{PCI_CONFIG_BASE, PCI_DEVICE_SHIFT, PCI_MEM_WINDOW};
+use crate::config::HIGH_BASE_EIGHT;
+use alloc::vec::Vec;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// (class, subclass, programming interface) of the controllers we have drivers for
+pub mod class {
+    pub const AHCI: (u8, u8, Option<u8>) = (0x01, 0x06, Some(0x01));
+    pub const SDHCI: (u8, u8, Option<u8>) = (0x08, 0x05, None);
+}
+
+/// (vendor, devices) of the functions found by their IDs, which have no class of their own
+pub mod id {
+    /// Transitional and virtio 1.0 only IDs of a virtio-blk function
+    pub const VIRTIO_BLK: (u16, &[u16]) = (0x1af4, &[0x1001, 0x1042]);
+}
+
+mod offsets {
+    pub const VENDOR_ID: usize = 0x00;
+    pub const DEVICE_ID: usize = 0x02;
+    pub const COMMAND: usize = 0x04;
+    pub const STATUS: usize = 0x06;
+    pub const CLASS_REVISION: usize = 0x08;
+    pub const HEADER_TYPE: usize = 0x0e;
+    pub const BAR0: usize = 0x10;
+    pub const CAPABILITIES: usize = 0x34;
+    pub const INTERRUPT_LINE: usize = 0x3c;
+}
+
+mod masks {
+    pub const MEMORY_SPACE: u16 = 1 << 1;
+    pub const BUS_MASTER: u16 = 1 << 2;
+    pub const INTX_DISABLE: u16 = 1 << 10;
+    pub const CAPABILITIES_LIST: u16 = 1 << 4;
+    pub const MULTI_FUNCTION: u8 = 1 << 7;
+    pub const BAR_IO: u32 = 1;
+    pub const BAR_64BIT: u32 = 0b10 << 1;
+    pub const BAR_TYPE: u32 = 0b11 << 1;
+    pub const BAR_ADDR: u32 = !0xf;
+}
+
+const DEVICES_PER_BUS: usize = 32;
+const FUNCTIONS_PER_DEVICE: usize = 8;
+
+/// Next free address in `PCI_MEM_WINDOW`, `0` before the first assignment
+static NEXT_MEM: AtomicUsize = AtomicUsize::new(0);
+
+/// A function on bus 0, addressed through its configuration space
+pub struct PciFunction {
+    config: usize,
+}
+
+impl PciFunction {
+    fn new(device: usize, function: usize) -> Self {
+        Self {
+            config: PCI_CONFIG_BASE
+                + (device << PCI_DEVICE_SHIFT)
+                + (function << (PCI_DEVICE_SHIFT - 3)),
+        }
+    }
+    pub fn read32(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.config + offset) as *const u32) }
+    }
+    fn write32(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.config + offset) as *mut u32, value) }
+    }
+    fn read16(&self, offset: usize) -> u16 {
+        (self.read32(offset & !3) >> ((offset & 3) * 8)) as u16
+    }
+    pub fn read8(&self, offset: usize) -> u8 {
+        (self.read32(offset & !3) >> ((offset & 3) * 8)) as u8
+    }
+    fn present(&self) -> bool {
+        self.read16(offsets::VENDOR_ID) != 0xffff
+    }
+    fn class(&self) -> (u8, u8, u8) {
+        let class = self.read32(offsets::CLASS_REVISION);
+        ((class >> 24) as u8, (class >> 16) as u8, (class >> 8) as u8)
+    }
+    fn id(&self) -> (u16, u16) {
+        (
+            self.read16(offsets::VENDOR_ID),
+            self.read16(offsets::DEVICE_ID),
+        )
+    }
+    /// Turn on memory decoding, DMA and INTx
+    pub fn enable(&self) {
+        let command = self.read32(offsets::COMMAND) & !(masks::INTX_DISABLE as u32);
+        self.write32(
+            offsets::COMMAND,
+            command | (masks::MEMORY_SPACE | masks::BUS_MASTER) as u32,
+        );
+    }
+    /// (id, offset) of the entries in the capability list
+    pub fn capabilities(&self) -> Vec<(u8, usize)> {
+        let mut capabilities = Vec::new();
+        if self.read16(offsets::STATUS) & masks::CAPABILITIES_LIST == 0 {
+            return capabilities;
+        }
+        let mut offset = (self.read8(offsets::CAPABILITIES) & !3) as usize;
+        // a broken list could loop, there is room for 48 entries at most
+        while offset != 0 && capabilities.len() < 48 {
+            capabilities.push((self.read8(offset), offset));
+            offset = (self.read8(offset + 1) & !3) as usize;
+        }
+        capabilities
+    }
+    /// The interrupt source INTx is routed to, as written by the firmware.
+    /// `None` if it was left unset, the device has to be polled then.
+    pub fn interrupt_line(&self) -> Option<usize> {
+        match self.read8(offsets::INTERRUPT_LINE) {
+            0 | 0xff => None,
+            line => Some(line as usize),
+        }
+    }
+    /// Kernel address of the memory BAR `index`, assigned from `PCI_MEM_WINDOW` if the firmware didn't.
+    /// `None` if there is no such BAR or the window is used up.
+    pub fn map_bar(&self, index: usize) -> Option<usize> {
+        let offset = offsets::BAR0 + index * 4;
+        let bar = self.read32(offset);
+        if bar & masks::BAR_IO != 0 {
+            return None;
+        }
+        let is_64bit = bar & masks::BAR_TYPE == masks::BAR_64BIT;
+        let mut addr = (bar & masks::BAR_ADDR) as usize;
+        if is_64bit {
+            addr |= (self.read32(offset + 4) as usize) << 32;
+        }
+        if addr == 0 {
+            // the size is the lowest bit that sticks when all ones are written
+            self.write32(offset, u32::MAX);
+            let size = (!(self.read32(offset) & masks::BAR_ADDR)).wrapping_add(1) as usize;
+            self.write32(offset, bar);
+            if size == 0 {
+                return None;
+            }
+            addr = self.alloc_mem(size)?;
+            self.write32(offset, addr as u32 | (bar & !masks::BAR_ADDR));
+            if is_64bit {
+                self.write32(offset + 4, (addr >> 32) as u32);
+            }
+        }
+        Some(addr | HIGH_BASE_EIGHT)
+    }
+    fn alloc_mem(&self, size: usize) -> Option<usize> {
+        let (base, len) = PCI_MEM_WINDOW;
+        let mut addr = 0;
+        NEXT_MEM
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |next| {
+                addr = (next.max(base) + size - 1) & !(size - 1);
+                if addr + size > base + len {
+                    None
+                } else {
+                    Some(addr + size)
+                }
+            })
+            .ok()?;
+        Some(addr)
+    }
+}
+
+/// The first function on bus 0 of `class`, a `None` programming interface matches any
+pub fn find_function((class, subclass, prog_if): (u8, u8, Option<u8>)) -> Option<PciFunction> {
+    find(|function| {
+        let (found_class, found_subclass, found_prog_if) = function.class();
+        found_class == class
+            && found_subclass == subclass
+            && prog_if.map_or(true, |prog_if| prog_if == found_prog_if)
+    })
+}
+
+/// Every function on bus 0 made by `vendor` with one of `devices`, in the order of the bus
+pub fn find_devices((vendor, devices): (u16, &[u16])) -> Vec<PciFunction> {
+    find_all(|function| {
+        let (found_vendor, found_device) = function.id();
+        found_vendor == vendor && devices.contains(&found_device)
+    })
+}
+
+fn find(matches: impl Fn(&PciFunction) -> bool) -> Option<PciFunction> {
+    find_all(matches).into_iter().next()
+}
+
+/// Functions behind bridges are not seen, their buses are not scanned
+fn find_all(matches: impl Fn(&PciFunction) -> bool) -> Vec<PciFunction> {
+    let mut found = Vec::new();
+    for device in 0..DEVICES_PER_BUS {
+        for function in 0..FUNCTIONS_PER_DEVICE {
+            let pci_function = PciFunction::new(device, function);
+            if !pci_function.present() {
+                if function == 0 {
+                    break;
+                }
+                continue;
+            }
+            let single_function = function == 0
+                && pci_function.read8(offsets::HEADER_TYPE) & masks::MULTI_FUNCTION == 0;
+            if matches(&pci_function) {
+                found.push(pci_function);
+            }
+            if single_function {
+                break;
+            }
+        }
+    }
+    found
+}
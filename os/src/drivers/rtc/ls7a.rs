@@ -0,0 +1,98 @@
+use super::{Rtc, RtcTime};
+use crate::syscall::errno::EINVAL;
+use core::ptr::{read_volatile, write_volatile};
+
+/// The RTC block of the Loongson 2K1000 and LS7A bridge, QEMU's `ls7a_rtc` models the same registers.
+/// Only the TOY (time of year) counter is used, it counts in calendar fields rather than ticks.
+pub struct Ls7aRtc {
+    base: usize,
+}
+
+mod offsets {
+    pub const TOY_WRITE0: usize = 0x24;
+    pub const TOY_WRITE1: usize = 0x28;
+    pub const TOY_READ0: usize = 0x2c;
+    pub const TOY_READ1: usize = 0x30;
+    pub const RTC_CTRL: usize = 0x40;
+}
+
+mod masks {
+    /// TOY_READ0/TOY_WRITE0 fields as (shift, width), the year lives alone in TOY_READ1/TOY_WRITE1
+    pub const MON: (u32, u32) = (26, 6);
+    pub const DAY: (u32, u32) = (21, 5);
+    pub const HOUR: (u32, u32) = (16, 5);
+    pub const MIN: (u32, u32) = (10, 6);
+    pub const SEC: (u32, u32) = (4, 6);
+    pub const TOY_ENABLE: u32 = 1 << 11;
+    pub const OSC_ENABLE: u32 = 1 << 8;
+}
+
+fn get_field(value: u32, (shift, width): (u32, u32)) -> i32 {
+    ((value >> shift) & ((1 << width) - 1)) as i32
+}
+
+fn set_field(value: i32, (shift, width): (u32, u32)) -> u32 {
+    (value as u32 & ((1 << width) - 1)) << shift
+}
+
+impl Ls7aRtc {
+    pub fn new(base: usize) -> Self {
+        Self { base }
+    }
+    fn read_reg(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.base + offset) as *const u32) }
+    }
+    fn write_reg(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.base + offset) as *mut u32, value) }
+    }
+    fn enabled(&self) -> bool {
+        let enable = masks::TOY_ENABLE | masks::OSC_ENABLE;
+        self.read_reg(offsets::RTC_CTRL) & enable == enable
+    }
+}
+
+impl Rtc for Ls7aRtc {
+    fn read_time(&self) -> Option<RtcTime> {
+        if !self.enabled() {
+            return None;
+        }
+        // the counter may carry between the two reads, read again if the seconds changed
+        let (toy0, toy1) = loop {
+            let toy0 = self.read_reg(offsets::TOY_READ0);
+            let toy1 = self.read_reg(offsets::TOY_READ1);
+            if self.read_reg(offsets::TOY_READ0) == toy0 {
+                break (toy0, toy1);
+            }
+        };
+        let time = RtcTime {
+            tm_sec: get_field(toy0, masks::SEC),
+            tm_min: get_field(toy0, masks::MIN),
+            tm_hour: get_field(toy0, masks::HOUR),
+            tm_mday: get_field(toy0, masks::DAY),
+            tm_mon: get_field(toy0, masks::MON) - 1,
+            tm_year: toy1 as i32,
+            ..Default::default()
+        };
+        // fill in the weekday and the day of year
+        time.to_unix().map(RtcTime::from_unix)
+    }
+
+    fn set_time(&self, time: &RtcTime) -> Result<(), isize> {
+        if time.to_unix().is_none() {
+            return Err(EINVAL);
+        }
+        let toy0 = set_field(time.tm_sec, masks::SEC)
+            | set_field(time.tm_min, masks::MIN)
+            | set_field(time.tm_hour, masks::HOUR)
+            | set_field(time.tm_mday, masks::DAY)
+            | set_field(time.tm_mon + 1, masks::MON);
+        self.write_reg(offsets::TOY_WRITE0, toy0);
+        self.write_reg(offsets::TOY_WRITE1, time.tm_year as u32);
+        let ctrl = self.read_reg(offsets::RTC_CTRL);
+        self.write_reg(
+            offsets::RTC_CTRL,
+            ctrl | masks::TOY_ENABLE | masks::OSC_ENABLE,
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+mod ls7a;
+pub use ls7a::Ls7aRtc;
+
+use crate::arch::board::RTC_BASE;
+use alloc::sync::Arc;
+use lazy_static::*;
+
+const SECS_PER_DAY: usize = 86400;
+
+/// Broken-down UTC time, the layout of `struct rtc_time` used by the `RTC_*` ioctls
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct RtcTime {
+    pub tm_sec: i32,
+    pub tm_min: i32,
+    pub tm_hour: i32,
+    /// 1-31
+    pub tm_mday: i32,
+    /// 0-11
+    pub tm_mon: i32,
+    /// years since 1900
+    pub tm_year: i32,
+    /// days since Sunday, ignored when setting the clock
+    pub tm_wday: i32,
+    /// days since January 1st, ignored when setting the clock
+    pub tm_yday: i32,
+    pub tm_isdst: i32,
+}
+
+fn is_leap_year(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+/// `month` is 0-based like `tm_mon`
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        1 => {
+            if is_leap_year(year) {
+                29
+            } else {
+                28
+            }
+        }
+        3 | 5 | 8 | 10 => 30,
+        _ => 31,
+    }
+}
+
+/// Days from 1970-01-01 to the given date of the proleptic Gregorian calendar, `month` is 1-based
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Inverse of `days_from_civil`, returns (year, month, day) with a 1-based month
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = if days >= 0 { days } else { days - 146096 } / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+impl RtcTime {
+    pub fn from_unix(secs: usize) -> Self {
+        let days = (secs / SECS_PER_DAY) as i64;
+        let rem = (secs % SECS_PER_DAY) as i32;
+        let (year, month, day) = civil_from_days(days);
+        Self {
+            tm_sec: rem % 60,
+            tm_min: rem / 60 % 60,
+            tm_hour: rem / 3600,
+            tm_mday: day as i32,
+            tm_mon: month as i32 - 1,
+            tm_year: (year - 1900) as i32,
+            // 1970-01-01 was a Thursday
+            tm_wday: ((days + 4) % 7) as i32,
+            tm_yday: (days - days_from_civil(year, 1, 1)) as i32,
+            tm_isdst: 0,
+        }
+    }
+    /// Seconds since the epoch, `None` if a field is out of range or the time is before 1970
+    pub fn to_unix(&self) -> Option<usize> {
+        let year = self.tm_year as i64 + 1900;
+        let month = self.tm_mon as i64 + 1;
+        if year < 1970
+            || !(1..=12).contains(&month)
+            || self.tm_mday < 1
+            || self.tm_mday as i64 > days_in_month(year, month - 1)
+            || !(0..24).contains(&self.tm_hour)
+            || !(0..60).contains(&self.tm_min)
+            || !(0..60).contains(&self.tm_sec)
+        {
+            return None;
+        }
+        let days = days_from_civil(year, month, self.tm_mday as i64);
+        Some(
+            days as usize * SECS_PER_DAY
+                + self.tm_hour as usize * 3600
+                + self.tm_min as usize * 60
+                + self.tm_sec as usize,
+        )
+    }
+}
+
+/// A battery backed clock keeping the wall time with a resolution of one second
+pub trait Rtc: Send + Sync {
+    /// `None` if the clock hasn't been set or holds garbage
+    fn read_time(&self) -> Option<RtcTime>;
+    fn set_time(&self, time: &RtcTime) -> Result<(), isize>;
+}
+
+lazy_static! {
+    pub static ref RTC: Arc<dyn Rtc> = Arc::new(Ls7aRtc::new(RTC_BASE));
+}
@@ -1,10 +1,21 @@
+//! Buffer caches of device blocks, used for FAT32 metadata, and page caches of file content.
+//!
+//! Both are managed by the CLOCK policy, an approximation of LRU: a hit sets the referenced bit
+//! of an entry, and the hand going round the entries clears the bits it passes and evicts
+//! the first entry found unreferenced, so an entry survives a full round after its last use.
+//! Dirty caches are written back when evicted, and also by idle cores
+//! every `dirty_writeback_centisecs` (see `writeback_tick()`).
 use super::BlockDevice;
 use crate::arch::BLOCK_SZ;
 use crate::arch::BUFFER_CACHE_NUM;
 use crate::config::{MEMORY_HIGH_BASE, PAGE_SIZE, PAGE_SIZE_BITS};
+use crate::drivers::block::{BlockRequest, SECTORS_PER_BLOCK};
 use crate::mm::{frame_alloc, FrameTracker, KERNEL_SPACE};
-use alloc::sync::Arc;
+use crate::timer::get_time_ms;
+use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::*;
 use spin::Mutex;
 
 pub trait Cache {
@@ -22,10 +33,9 @@ pub trait Cache {
     /// # Argument
     /// + `block_ids`: block ids in this cache
     /// + `block_device`: The pointer to the block_device.
-    fn sync(&self, _block_ids: Vec<usize>, _block_device: &Arc<dyn BlockDevice>) {}
+    fn sync(&mut self, _block_ids: Vec<usize>, _block_device: &Arc<dyn BlockDevice>) {}
 }
 
-const PRIORITY_UPPERBOUND: usize = 1;
 const BUFFER_SIZE: usize = BLOCK_SZ;
 const PAGE_BUFFERS: usize = PAGE_SIZE / BUFFER_SIZE;
 
@@ -39,11 +49,164 @@ const CACHEPOOLPAGE: usize = if (CACHEPOOLSIZE >> 3) > 1 {
     1
 };
 
+/// Counters of the caches, shown in `/proc/vmstat`
+pub struct CacheStats {
+    pub buffer_hits: AtomicUsize,
+    pub buffer_misses: AtomicUsize,
+    pub buffer_evictions: AtomicUsize,
+    /// Buffers holding a block
+    pub buffers: AtomicUsize,
+    pub page_hits: AtomicUsize,
+    pub page_misses: AtomicUsize,
+    pub page_evictions: AtomicUsize,
+    /// Pages of file content held by page cache managers
+    pub cached_pages: AtomicUsize,
+    /// Blocks read into and written back from the caches
+    pub blocks_read: AtomicUsize,
+    pub blocks_written: AtomicUsize,
+    /// Rounds of the background writeback
+    pub writeback_runs: AtomicUsize,
+}
+
+pub static CACHE_STATS: CacheStats = CacheStats {
+    buffer_hits: AtomicUsize::new(0),
+    buffer_misses: AtomicUsize::new(0),
+    buffer_evictions: AtomicUsize::new(0),
+    buffers: AtomicUsize::new(0),
+    page_hits: AtomicUsize::new(0),
+    page_misses: AtomicUsize::new(0),
+    page_evictions: AtomicUsize::new(0),
+    cached_pages: AtomicUsize::new(0),
+    blocks_read: AtomicUsize::new(0),
+    blocks_written: AtomicUsize::new(0),
+    writeback_runs: AtomicUsize::new(0),
+};
+
+/// Add `n` to a counter of `CACHE_STATS`
+fn count(counter: &AtomicUsize, n: usize) {
+    counter.fetch_add(n, Ordering::Relaxed);
+}
+
+/// Interval of the background writeback in centiseconds, `0` disables it.
+/// Tunable through `/proc/sys/vm/dirty_writeback_centisecs`.
+pub static DIRTY_WRITEBACK_CENTISECS: AtomicUsize = AtomicUsize::new(500);
+/// When the background writeback last ran, in milliseconds
+static LAST_WRITEBACK: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+    /// Buffer caches and their devices, for the background writeback
+    static ref BLOCK_CACHES: Mutex<Vec<(Weak<Mutex<BlockCacheManager>>, Weak<dyn BlockDevice>)>> =
+        Mutex::new(Vec::new());
+}
+
+/// Let the background writeback flush `manager`, which caches blocks of `block_device`
+pub fn register_block_cache(
+    manager: &Arc<Mutex<BlockCacheManager>>,
+    block_device: &Arc<dyn BlockDevice>,
+) {
+    BLOCK_CACHES
+        .lock()
+        .push((Arc::downgrade(manager), Arc::downgrade(block_device)));
+}
+
+/// Called by idle cores, write back dirty caches if `dirty_writeback_centisecs` has passed.
+/// Locks are only tried, anything busy is left for the next round.
+pub fn writeback_tick() {
+    let interval = DIRTY_WRITEBACK_CENTISECS.load(Ordering::Relaxed) * 10;
+    let last = LAST_WRITEBACK.load(Ordering::Relaxed);
+    let now = get_time_ms();
+    if interval == 0 || now < last + interval {
+        return;
+    }
+    // only one core does the round
+    if LAST_WRITEBACK
+        .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+        .is_err()
+    {
+        return;
+    }
+    let pages = super::directory_tree::writeback();
+    let mut blocks = 0;
+    if let Some(mut caches) = BLOCK_CACHES.try_lock() {
+        caches.retain(|(manager, block_device)| {
+            match (manager.upgrade(), block_device.upgrade()) {
+                (Some(manager), Some(block_device)) => {
+                    if let Some(manager) = manager.try_lock() {
+                        blocks += manager.sync(&block_device);
+                    }
+                    // the written buffers may still sit in the cache of the disk
+                    if let Err(errno) = block_device.flush() {
+                        log::warn!("[writeback_tick] flush failed: {}", errno);
+                    }
+                    true
+                }
+                _ => false,
+            }
+        });
+    }
+    count(&CACHE_STATS.writeback_runs, 1);
+    log::trace!("[writeback_tick] pages: {}, buffers: {}", pages, blocks);
+}
+
+/// CLOCK hand over the entries of a cache, see the module documentation
+#[derive(Default)]
+struct Clock {
+    hand: usize,
+}
+
+impl Clock {
+    /// Move the hand over `len` entries, at most `rounds` times round,
+    /// until `visit` returns `true`. `visit` skips entries in use,
+    /// clears the referenced bit of the others and evicts them if it was already clear.
+    fn sweep(&mut self, len: usize, rounds: usize, mut visit: impl FnMut(usize) -> bool) {
+        for _ in 0..len * rounds {
+            let index = self.hand % len;
+            self.hand = (index + 1) % len;
+            if visit(index) {
+                return;
+            }
+        }
+    }
+}
+
+/// Hash table from block id to the slot of its buffer, with a fixed number of buckets
+struct BlockIndex {
+    buckets: Vec<Vec<(usize, usize)>>,
+    shift: u32,
+}
+
+impl BlockIndex {
+    fn new(capacity: usize) -> Self {
+        let len = capacity.next_power_of_two();
+        Self {
+            buckets: (0..len).map(|_| Vec::new()).collect(),
+            shift: usize::BITS - len.trailing_zeros(),
+        }
+    }
+    /// Fibonacci hashing, consecutive blocks land in different buckets
+    fn bucket(&self, block_id: usize) -> usize {
+        block_id
+            .wrapping_mul(0x9E37_79B9_7F4A_7C15)
+            .checked_shr(self.shift)
+            .unwrap_or(0)
+    }
+    fn get(&self, block_id: usize) -> Option<usize> {
+        self.buckets[self.bucket(block_id)]
+            .iter()
+            .find(|(id, _)| *id == block_id)
+            .map(|(_, slot)| *slot)
+    }
+    fn insert(&mut self, block_id: usize, slot: usize) {
+        let bucket = self.bucket(block_id);
+        self.buckets[bucket].push((block_id, slot));
+    }
+    fn remove(&mut self, block_id: usize) {
+        let bucket = self.bucket(block_id);
+        self.buckets[bucket].retain(|(id, _)| *id != block_id);
+    }
+}
+
 pub struct BufferCache {
-    /// Every time kernel tried to alloc this buffer this number will increase 1(at most 3)
-    /// When no free cache lefted this number will decrease 1(at least 0)
-    /// When it's 0 and Arc's strong count is 1, this buffer will be writed back
-    priority: usize,
     /// ***If block_id is usize::Max***, we assume it is an unused buffer.
     block_id: usize,
     dirty: bool,
@@ -81,16 +244,51 @@ impl BufferCache {
     pub fn new(buffer_ptr: *mut [u8; BUFFER_SIZE]) -> Self {
         let buffer = unsafe { buffer_ptr.as_mut().unwrap() };
         Self {
-            priority: 0,
             block_id: usize::MAX,
             dirty: false,
             buffer,
         }
     }
-    pub fn read_block(&mut self, block_id: usize, block_device: &Arc<dyn BlockDevice>) {
+    /// Read `block_id` into the buffer, which is left unused if the read fails
+    pub fn read_block(
+        &mut self,
+        block_id: usize,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Result<(), isize> {
+        let request = BlockRequest::read(block_id * SECTORS_PER_BLOCK, self.buffer.as_mut());
+        if let Err(errno) = block_device.submit_wait(request) {
+            log::error!("[buffer_cache] reading block {}: {}", block_id, errno);
+            self.block_id = usize::MAX;
+            self.dirty = false;
+            return Err(errno);
+        }
         self.block_id = block_id;
-        let buf = self.buffer.as_mut();
-        block_device.read_block(block_id, buf);
+        self.dirty = false;
+        count(&CACHE_STATS.blocks_read, 1);
+        Ok(())
+    }
+    fn needs_write_back(&self) -> bool {
+        self.dirty && self.block_id != usize::MAX
+    }
+    fn write_request(&self) -> BlockRequest {
+        BlockRequest::write(self.block_id * SECTORS_PER_BLOCK, self.buffer.as_ref())
+    }
+    /// Write the buffer back if it is dirty, returns whether it was written
+    pub fn write_back(&mut self, block_device: &Arc<dyn BlockDevice>) -> bool {
+        if !self.needs_write_back() {
+            return false;
+        }
+        self.written(block_device.submit_wait(self.write_request()))
+    }
+    /// Clear the dirty bit if the write back succeeded
+    fn written(&mut self, result: Result<(), isize>) -> bool {
+        if let Err(errno) = result {
+            log::error!("[buffer_cache] writing block {}: {}", self.block_id, errno);
+            return false;
+        }
+        self.dirty = false;
+        count(&CACHE_STATS.blocks_written, 1);
+        true
     }
 }
 
@@ -98,37 +296,45 @@ pub struct BlockCacheManager {
     /// just hold all pages alloced
     _hold: Vec<Arc<FrameTracker>>,
     cache_pool: Vec<Arc<Mutex<BufferCache>>>,
+    /// Slot in `cache_pool` of each cached block
+    index: BlockIndex,
+    /// Referenced bits of `cache_pool`
+    referenced: Vec<bool>,
+    /// Slots never used yet
+    free: Vec<usize>,
+    clock: Clock,
 }
 
 impl BlockCacheManager {
-    pub fn oom(&self, block_device: &Arc<dyn BlockDevice>) {
-        for buffer_cache in &self.cache_pool {
-            if Arc::strong_count(buffer_cache) > 1 {
-                continue;
-            }
-            let mut locked = buffer_cache.lock();
-            if locked.priority > 0 {
-                locked.priority -= 1;
-            } else {
-                let block_id = locked.block_id;
-                let buf = locked.buffer.as_ref();
-                if locked.dirty {
-                    block_device.write_block(block_id, buf);
-                    locked.dirty = false;
-                }
-                locked.block_id = usize::MAX;
-            }
+    /// Take a free slot, or evict the buffer picked by the CLOCK hand,
+    /// a buffer is in use if someone else holds it.
+    fn alloc_slot(&mut self, block_device: &Arc<dyn BlockDevice>) -> usize {
+        if let Some(slot) = self.free.pop() {
+            count(&CACHE_STATS.buffers, 1);
+            return slot;
         }
-    }
-    fn alloc_buffer_cache(&self, block_device: &Arc<dyn BlockDevice>) -> Arc<Mutex<BufferCache>> {
         loop {
-            for buffer_cache in &self.cache_pool {
-                let locked = buffer_cache.lock();
-                if locked.block_id == usize::MAX {
-                    return buffer_cache.clone();
+            let (cache_pool, referenced) = (&self.cache_pool, &mut self.referenced);
+            let mut victim = None;
+            self.clock.sweep(cache_pool.len(), 2, |slot| {
+                if Arc::strong_count(&cache_pool[slot]) > 1
+                    || core::mem::replace(&mut referenced[slot], false)
+                {
+                    return false;
                 }
+                victim = Some(slot);
+                true
+            });
+            if let Some(slot) = victim {
+                let mut locked = self.cache_pool[slot].lock();
+                locked.write_back(block_device);
+                self.index.remove(locked.block_id);
+                locked.block_id = usize::MAX;
+                count(&CACHE_STATS.buffer_evictions, 1);
+                return slot;
             }
-            self.oom(block_device);
+            // every buffer is in use, wait for one to be released
+            core::hint::spin_loop();
         }
     }
 }
@@ -147,53 +353,76 @@ impl BlockCacheManager {
                 cache_pool.push(Arc::new(Mutex::new(BufferCache::new(buffer_ptr))))
             }
         }
+        let len = cache_pool.len();
         Self {
             _hold: hold,
             cache_pool,
+            index: BlockIndex::new(len),
+            referenced: alloc::vec![false; len],
+            free: (0..len).rev().collect(),
+            clock: Clock::default(),
         }
     }
-    pub fn try_get_block_cache(&self, block_id: usize) -> Option<Arc<Mutex<BufferCache>>> {
-        for buffer_cache in &self.cache_pool {
-            let mut locked = buffer_cache.lock();
-            if locked.block_id == block_id {
-                if locked.priority < PRIORITY_UPPERBOUND {
-                    locked.priority += 1;
-                }
-                return Some(buffer_cache.clone());
-            }
-        }
-        None
+    pub fn try_get_block_cache(&mut self, block_id: usize) -> Option<Arc<Mutex<BufferCache>>> {
+        let slot = self.index.get(block_id)?;
+        self.referenced[slot] = true;
+        count(&CACHE_STATS.buffer_hits, 1);
+        Some(self.cache_pool[slot].clone())
     }
 
+    /// Returns the error of reading the block in, the slot taken for it is freed then.
     pub fn get_block_cache(
-        &self,
+        &mut self,
         block_id: usize,
         block_device: &Arc<dyn BlockDevice>,
-    ) -> Arc<Mutex<BufferCache>> {
+    ) -> Result<Arc<Mutex<BufferCache>>, isize> {
         match self.try_get_block_cache(block_id) {
-            Some(block_cache) => block_cache,
+            Some(block_cache) => Ok(block_cache),
             None => {
-                let buffer_cache = self.alloc_buffer_cache(block_device);
-                let mut locked = buffer_cache.lock();
-                locked.read_block(block_id, block_device);
-                if locked.priority < PRIORITY_UPPERBOUND {
-                    locked.priority += 1;
+                count(&CACHE_STATS.buffer_misses, 1);
+                let slot = self.alloc_slot(block_device);
+                let buffer_cache = self.cache_pool[slot].clone();
+                if let Err(errno) = buffer_cache.lock().read_block(block_id, block_device) {
+                    self.free.push(slot);
+                    CACHE_STATS.buffers.fetch_sub(1, Ordering::Relaxed);
+                    return Err(errno);
                 }
-                drop(locked);
-                buffer_cache
+                self.index.insert(block_id, slot);
+                self.referenced[slot] = true;
+                Ok(buffer_cache)
             }
         }
     }
+
+    /// Write back the dirty buffers, those locked by others are skipped.
+    /// The writes are submitted together, so the device merges adjacent buffers.
+    /// Returns the number of buffers written.
+    pub fn sync(&self, block_device: &Arc<dyn BlockDevice>) -> usize {
+        let mut dirty: Vec<_> = self
+            .cache_pool
+            .iter()
+            .filter_map(|buffer_cache| buffer_cache.try_lock())
+            .filter(|locked| locked.needs_write_back())
+            .collect();
+        let requests = dirty.iter().map(|locked| locked.write_request()).collect();
+        submit_all(block_device, requests)
+            .into_iter()
+            .zip(dirty.iter_mut())
+            .map(|(result, locked)| locked.written(result))
+            .filter(|written| *written)
+            .count()
+    }
 }
 
 /// PageCache is used for kernel.
 /// Each PageCache contains PAGE_BUFFERS(8) BufferCache.
 pub struct PageCache {
-    /// Priority is used for out of memory
-    /// Every time kernel tried to alloc this pagecache this number will increase 1(at most 1)
-    /// Every time out of memory occurred this number will decrease 1(at least 0)
-    /// When it's 0 and Arc's strong count is 1(one in inode) this PageCache will be dropped
-    priority: usize,
+    /// Referenced bit of the CLOCK policy, set every time the page cache is got.
+    /// Out of memory, page caches found unreferenced and used only by their manager are dropped.
+    referenced: bool,
+    /// Set when the page is written through a shared mapping,
+    /// the kernel can't see those writes in its own page table.
+    dirty: bool,
     page_ptr: &'static mut [u8; PAGE_SIZE],
     tracker: Arc<FrameTracker>,
 }
@@ -223,17 +452,22 @@ impl Cache for PageCache {
         })
     }
 
-    fn sync(&self, block_ids: Vec<usize>, block_device: &Arc<dyn BlockDevice>) {
-        let lock = KERNEL_SPACE.try_lock();
-        match lock {
-            Some(lock) => {
-                if !lock.is_dirty(self.tracker.ppn).unwrap() {
-                    return;
-                }
+    fn sync(&mut self, block_ids: Vec<usize>, block_device: &Arc<dyn BlockDevice>) {
+        // writes through the kernel mapping set the dirty bit,
+        // which is cleared before writing back so later writes set it again
+        let kernel_dirty = match KERNEL_SPACE.try_lock() {
+            Some(mut lock) => {
+                lock.is_dirty(self.tracker.ppn).unwrap()
+                    && lock
+                        .clear_dirty_bit((self.tracker.ppn.0 | MEMORY_HIGH_BASE).into())
+                        .is_ok()
             }
-            None => {}
+            None => true,
+        };
+        if self.dirty || kernel_dirty {
+            self.write_back(block_ids, block_device);
+            self.dirty = false;
         }
-        self.write_back(block_ids, block_device)
     }
 }
 
@@ -243,7 +477,21 @@ impl PageCache {
         let page_ptr = (tracker.ppn.0 << PAGE_SIZE_BITS) as *mut [u8; PAGE_SIZE];
         let page_ptr = unsafe { page_ptr.as_mut().unwrap() };
         Self {
-            priority: 0,
+            referenced: false,
+            dirty: false,
+            page_ptr,
+            tracker,
+        }
+    }
+
+    /// Wrap a page owned by someone else, so it can be mapped like a page cache,
+    /// e.g. a page of a tmpfs file
+    pub fn from_tracker(tracker: Arc<FrameTracker>) -> Self {
+        let page_ptr = (tracker.ppn.0 << PAGE_SIZE_BITS) as *mut [u8; PAGE_SIZE];
+        let page_ptr = unsafe { page_ptr.as_mut().unwrap() };
+        Self {
+            referenced: false,
+            dirty: false,
             page_ptr,
             tracker,
         }
@@ -252,46 +500,51 @@ impl PageCache {
     pub fn get_tracker(&self) -> Arc<FrameTracker> {
         self.tracker.clone()
     }
-    pub fn read_in(&mut self, block_ids: Vec<usize>, block_device: &Arc<dyn BlockDevice>) {
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+    pub fn clear_dirty(&mut self) {
+        self.dirty = false;
+    }
+    /// Returns the error of the first run of blocks failed to read,
+    /// the page holds no valid content then.
+    pub fn read_in(
+        &mut self,
+        block_ids: Vec<usize>,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Result<(), isize> {
         if block_ids.is_empty() {
-            return;
+            return Ok(());
         }
         assert!(block_ids.len() <= PAGE_BUFFERS);
 
-        let mut start_block_id = usize::MAX;
-        let mut con_length = 0;
-        let mut start_buf_id = 0;
-        for block_id in block_ids.iter() {
-            if con_length == 0 {
-                start_block_id = *block_id;
-                con_length = 1;
-            } else if *block_id != start_block_id + con_length {
-                let buf = unsafe {
-                    core::slice::from_raw_parts_mut(
-                        self.page_ptr.as_mut_ptr().add(start_buf_id * BUFFER_SIZE),
-                        con_length * BUFFER_SIZE,
-                    )
-                };
-                block_device.read_block(start_block_id, buf);
-                start_buf_id += con_length;
-                start_block_id = *block_id;
-                con_length = 1;
-            } else {
-                con_length += 1;
+        let page_ptr = &mut self.page_ptr;
+        let runs = runs(&block_ids);
+        let requests = runs
+            .iter()
+            .map(|&(block_id, buf_id, len)| {
+                let buf = &mut page_ptr[buf_id * BUFFER_SIZE..(buf_id + len) * BUFFER_SIZE];
+                BlockRequest::read(block_id * SECTORS_PER_BLOCK, buf)
+            })
+            .collect();
+        let mut failed = Ok(());
+        for ((block_id, _, _), result) in runs.iter().zip(submit_all(block_device, requests)) {
+            if let Err(errno) = result {
+                log::error!("[page_cache] reading block {}: {}", block_id, errno);
+                failed = failed.and(Err(errno));
             }
         }
-        let buf = unsafe {
-            core::slice::from_raw_parts_mut(
-                self.page_ptr.as_mut_ptr().add(start_buf_id * BUFFER_SIZE),
-                con_length * BUFFER_SIZE,
-            )
-        };
-        block_device.read_block(start_block_id, buf);
+        failed?;
+        count(&CACHE_STATS.blocks_read, block_ids.len());
         self.page_ptr[block_ids.len() * BUFFER_SIZE..].fill(0);
         KERNEL_SPACE
             .lock()
             .clear_dirty_bit((self.tracker.ppn.0 | MEMORY_HIGH_BASE).into())
             .unwrap();
+        Ok(())
     }
 
     pub fn write_back(&self, block_ids: Vec<usize>, block_device: &Arc<dyn BlockDevice>) {
@@ -299,42 +552,56 @@ impl PageCache {
             return;
         }
 
-        let mut start_block_id = usize::MAX;
-        let mut con_length = 0;
-        let mut start_buf_id = 0;
-        for block_id in block_ids.iter() {
-            if con_length == 0 {
-                start_block_id = *block_id;
-                con_length = 1;
-            } else if *block_id != start_block_id + con_length {
-                let buf = unsafe {
-                    core::slice::from_raw_parts(
-                        self.page_ptr.as_ptr().add(start_buf_id * BUFFER_SIZE),
-                        con_length * BUFFER_SIZE,
-                    )
-                };
-                block_device.write_block(start_block_id, buf);
-
-                start_buf_id += con_length;
-                start_block_id = *block_id;
-                con_length = 1;
-            } else {
-                con_length += 1;
+        let runs = runs(&block_ids);
+        let requests = runs
+            .iter()
+            .map(|&(block_id, buf_id, len)| {
+                let buf = &self.page_ptr[buf_id * BUFFER_SIZE..(buf_id + len) * BUFFER_SIZE];
+                BlockRequest::write(block_id * SECTORS_PER_BLOCK, buf)
+            })
+            .collect();
+        for ((block_id, _, _), result) in runs.iter().zip(submit_all(block_device, requests)) {
+            if let Err(errno) = result {
+                log::error!("[page_cache] writing block {}: {}", block_id, errno);
             }
         }
-        let buf = unsafe {
-            core::slice::from_raw_parts(
-                self.page_ptr.as_ptr().add(start_buf_id * BUFFER_SIZE),
-                con_length * BUFFER_SIZE,
-            )
-        };
-        block_device.write_block(start_block_id, buf);
+        count(&CACHE_STATS.blocks_written, block_ids.len());
     }
 }
 
+/// Split `block_ids` into runs of consecutive blocks: (first block, first buffer, length)
+fn runs(block_ids: &[usize]) -> Vec<(usize, usize, usize)> {
+    let mut runs: Vec<(usize, usize, usize)> = Vec::new();
+    for (buf_id, block_id) in block_ids.iter().enumerate() {
+        match runs.last_mut() {
+            Some((start, _, len)) if *start + *len == *block_id => *len += 1,
+            _ => runs.push((*block_id, buf_id, 1)),
+        }
+    }
+    runs
+}
+
+/// Submit `requests` together, so that the device can merge and reorder them,
+/// then wait for each of them. Returns their results in order.
+fn submit_all(
+    block_device: &Arc<dyn BlockDevice>,
+    requests: Vec<BlockRequest>,
+) -> Vec<Result<(), isize>> {
+    let statuses: Vec<_> = requests.iter().map(|request| request.status()).collect();
+    for request in requests {
+        block_device.submit(request);
+    }
+    statuses
+        .iter()
+        .map(|status| block_device.wait(status))
+        .collect()
+}
+
 pub struct PageCacheManager {
     cache_pool: Mutex<Vec<Option<Arc<Mutex<PageCache>>>>>,
     allocated_cache: Mutex<Vec<usize>>,
+    /// Hand over `allocated_cache`
+    clock: Mutex<Clock>,
 }
 
 impl PageCacheManager {
@@ -344,6 +611,7 @@ impl PageCacheManager {
         Self {
             cache_pool: Mutex::new(Vec::new()),
             allocated_cache: Mutex::new(Vec::new()),
+            clock: Mutex::new(Clock::default()),
         }
     }
 
@@ -355,10 +623,8 @@ impl PageCacheManager {
         }
         let page_cache = lock[inner_cache_id].clone();
         if let Some(page_cache) = &page_cache {
-            let mut locked = page_cache.lock();
-            if locked.priority < PRIORITY_UPPERBOUND {
-                locked.priority += 1;
-            }
+            page_cache.lock().referenced = true;
+            count(&CACHE_STATS.page_hits, 1);
         }
         page_cache
     }
@@ -368,7 +634,7 @@ impl PageCacheManager {
         inner_cache_id: usize,
         neighbor: FUNC,
         block_device: &Arc<dyn BlockDevice>,
-    ) -> Arc<Mutex<PageCache>>
+    ) -> Result<Arc<Mutex<PageCache>>, isize>
     where
         FUNC: Fn() -> Vec<usize>,
     {
@@ -378,57 +644,103 @@ impl PageCacheManager {
             lock.push(None);
         }
         let page_cache = match &lock[inner_cache_id] {
-            Some(page_cache) => page_cache.clone(),
+            Some(page_cache) => {
+                count(&CACHE_STATS.page_hits, 1);
+                page_cache.clone()
+            }
             None => {
+                // a page failed to read is dropped, the next access reads it again
                 let mut new_page_cache = PageCache::new();
-                new_page_cache.read_in(neighbor(), &block_device);
+                new_page_cache.read_in(neighbor(), &block_device)?;
                 let new_page_cache = Arc::new(Mutex::new(new_page_cache));
                 lock[inner_cache_id] = Some(new_page_cache.clone());
                 self.allocated_cache.lock().push(inner_cache_id);
+                count(&CACHE_STATS.page_misses, 1);
+                count(&CACHE_STATS.cached_pages, 1);
                 new_page_cache
             }
         };
-        let mut inner_lock = page_cache.lock();
-        if inner_lock.priority < PRIORITY_UPPERBOUND {
-            inner_lock.priority += 1;
-        }
-        drop(inner_lock);
-        page_cache
+        page_cache.lock().referenced = true;
+        Ok(page_cache)
     }
 
+    /// Go round the page caches once, dropping those unreferenced and used only here.
+    /// Returns the number of dropped pages.
     pub fn oom<FUNC>(&self, neighbor: FUNC, block_device: &Arc<dyn BlockDevice>) -> usize
     where
         FUNC: Fn(usize) -> Vec<usize>,
     {
         let mut lock = self.cache_pool.lock();
+        let mut allocated_cache = self.allocated_cache.lock();
         let mut dropped = 0;
-        let mut new_allocated_cache = Vec::<usize>::new();
-
-        for inner_cache_id in self.allocated_cache.lock().iter() {
-            let inner_cache_id = *inner_cache_id;
+        self.clock.lock().sweep(allocated_cache.len(), 1, |index| {
+            let inner_cache_id = allocated_cache[index];
             let inner = lock[inner_cache_id].as_ref().unwrap();
             if Arc::strong_count(inner) > 1 {
-                new_allocated_cache.push(inner_cache_id);
-                continue;
+                return false;
             }
             let mut inner_lock = inner.lock();
-            if Arc::strong_count(&inner_lock.tracker) > 1 {
-                new_allocated_cache.push(inner_cache_id);
-            } else if inner_lock.priority > 0 {
-                inner_lock.priority -= 1;
-                new_allocated_cache.push(inner_cache_id);
-            } else {
-                let block_ids = neighbor(inner_cache_id);
-                inner_lock.sync(block_ids, block_device);
-                dropped += 1;
-                drop(inner_lock);
-                lock[inner_cache_id] = None;
+            if Arc::strong_count(&inner_lock.tracker) > 1
+                || core::mem::replace(&mut inner_lock.referenced, false)
+            {
+                return false;
             }
-        }
-        *self.allocated_cache.lock() = new_allocated_cache;
+            inner_lock.sync(neighbor(inner_cache_id), block_device);
+            drop(inner_lock);
+            lock[inner_cache_id] = None;
+            dropped += 1;
+            false
+        });
+        allocated_cache.retain(|inner_cache_id| lock[*inner_cache_id].is_some());
+        CACHE_STATS
+            .cached_pages
+            .fetch_sub(dropped, Ordering::Relaxed);
+        count(&CACHE_STATS.page_evictions, dropped);
         dropped
     }
 
+    /// Write back the caches marked dirty by `PageCache::mark_dirty()`
+    pub fn sync<FUNC>(&self, neighbor: FUNC, block_device: &Arc<dyn BlockDevice>)
+    where
+        FUNC: Fn(usize) -> Vec<usize>,
+    {
+        let lock = self.cache_pool.lock();
+        for inner_cache_id in self.allocated_cache.lock().iter() {
+            if let Some(inner) = lock[*inner_cache_id].as_ref() {
+                let mut inner_lock = inner.lock();
+                if inner_lock.dirty {
+                    inner_lock.write_back(neighbor(*inner_cache_id), block_device);
+                    inner_lock.dirty = false;
+                }
+            }
+        }
+    }
+
+    /// Write back all dirty caches for the background writeback,
+    /// nothing is waited for, busy caches are left for the next round.
+    /// Returns the number of caches looked at.
+    pub fn writeback<FUNC>(&self, neighbor: FUNC, block_device: &Arc<dyn BlockDevice>) -> usize
+    where
+        FUNC: Fn(usize) -> Vec<usize>,
+    {
+        let lock = match self.cache_pool.try_lock() {
+            Some(lock) => lock,
+            None => return 0,
+        };
+        let allocated_cache = match self.allocated_cache.try_lock() {
+            Some(allocated_cache) => allocated_cache,
+            None => return 0,
+        };
+        allocated_cache
+            .iter()
+            .filter_map(|inner_cache_id| {
+                let mut inner_lock = lock[*inner_cache_id].as_ref()?.try_lock()?;
+                inner_lock.sync(neighbor(*inner_cache_id), block_device);
+                Some(())
+            })
+            .count()
+    }
+
     pub fn notify_new_size(&self, new_size: usize) {
         let mut lock = self.cache_pool.lock();
         let new_pages = (new_size + PAGE_SIZE - 1) / PAGE_SIZE;
@@ -437,6 +749,7 @@ impl PageCacheManager {
                 if Arc::strong_count(&cache) > 1 {
                     panic!("page cache was used by others");
                 }
+                CACHE_STATS.cached_pages.fetch_sub(1, Ordering::Relaxed);
             });
         }
         lock.shrink_to_fit();
@@ -446,3 +759,12 @@ impl PageCacheManager {
             .retain(|cache_id| *cache_id < new_pages);
     }
 }
+
+impl Drop for PageCacheManager {
+    fn drop(&mut self) {
+        let cached = self.cache_pool.get_mut().iter().flatten().count();
+        CACHE_STATS
+            .cached_pages
+            .fetch_sub(cached, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,288 @@
+use crate::fs::DiskInodeType;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+
+use crate::{
+    fs::{directory_tree::DirectoryTreeNode, file_trait::File, layout::Stat},
+    mm::UserBuffer,
+    syscall::errno::{EAGAIN, EINVAL, ENOTDIR, ERESTART, ESPIPE},
+    task::{block_current_and_run_next, current_task, WaitQueue},
+};
+
+/// A 64-bit counter made by `eventfd2()`.
+/// Reads take the whole count, or one in semaphore mode, and block while it is zero.
+/// Writes add to the count and block while it would exceed `0xffff_ffff_ffff_fffe`.
+pub struct EventFd {
+    selfptr: Mutex<Weak<Self>>,
+    semaphore: bool,
+    nonblock: AtomicBool,
+    count: Mutex<u64>,
+    /// Woken whenever the count changes
+    wait_queue: Arc<Mutex<WaitQueue>>,
+}
+
+impl EventFd {
+    pub const EFD_SEMAPHORE: u32 = 1;
+    pub const EFD_NONBLOCK: u32 = 0o4000;
+    pub const EFD_CLOEXEC: u32 = 0o2000000;
+    const MAX_COUNT: u64 = u64::MAX - 1;
+    pub fn new(initval: u32, semaphore: bool) -> Arc<Self> {
+        let eventfd = Arc::new(Self {
+            selfptr: Mutex::new(Weak::new()),
+            semaphore,
+            nonblock: AtomicBool::new(false),
+            count: Mutex::new(initval as u64),
+            wait_queue: Arc::new(Mutex::new(WaitQueue::new())),
+        });
+        *eventfd.selfptr.lock() = Arc::downgrade(&eventfd);
+        eventfd
+    }
+    /// Sleep till the count changes
+    fn wait(&self) -> Result<(), isize> {
+        let task = current_task().unwrap();
+        if task.signal_pending() {
+            return Err(ERESTART);
+        }
+        let waiter = Arc::downgrade(&task);
+        self.wait_queue.lock().add_task(waiter.clone());
+        drop(task);
+        block_current_and_run_next();
+        self.wait_queue.lock().remove(&waiter);
+        Ok(())
+    }
+    /// Take the whole count, or one in semaphore mode, blocking while it is zero
+    fn take(&self) -> Result<u64, isize> {
+        loop {
+            let mut count = self.count.lock();
+            if *count > 0 {
+                let value = if self.semaphore { 1 } else { *count };
+                *count -= value;
+                drop(count);
+                self.wait_queue.lock().wake_all();
+                return Ok(value);
+            }
+            drop(count);
+            if self.nonblock.load(Ordering::Relaxed) {
+                return Err(EAGAIN);
+            }
+            self.wait()?;
+        }
+    }
+    /// Add `value` to the count, blocking while it would exceed `MAX_COUNT`
+    fn add(&self, value: u64) -> Result<(), isize> {
+        if value > EventFd::MAX_COUNT {
+            return Err(EINVAL);
+        }
+        loop {
+            let mut count = self.count.lock();
+            if EventFd::MAX_COUNT - *count >= value {
+                *count += value;
+                drop(count);
+                self.wait_queue.lock().wake_all();
+                return Ok(());
+            }
+            drop(count);
+            if self.nonblock.load(Ordering::Relaxed) {
+                return Err(EAGAIN);
+            }
+            self.wait()?;
+        }
+    }
+}
+
+#[allow(unused)]
+impl File for EventFd {
+    fn deep_clone(&self) -> Arc<dyn File> {
+        self.selfptr.lock().upgrade().unwrap()
+    }
+
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        true
+    }
+
+    fn read(&self, offset: Option<&mut usize>, buf: &mut [u8]) -> usize {
+        if offset.is_some() {
+            return ESPIPE as usize;
+        }
+        if buf.len() < 8 {
+            return EINVAL as usize;
+        }
+        match self.take() {
+            Ok(value) => {
+                buf[..8].copy_from_slice(&value.to_ne_bytes());
+                8
+            }
+            Err(errno) => errno as usize,
+        }
+    }
+
+    fn write(&self, offset: Option<&mut usize>, buf: &[u8]) -> usize {
+        if offset.is_some() {
+            return ESPIPE as usize;
+        }
+        if buf.len() < 8 {
+            return EINVAL as usize;
+        }
+        let mut value = [0u8; 8];
+        value.copy_from_slice(&buf[..8]);
+        match self.add(u64::from_ne_bytes(value)) {
+            Ok(()) => 8,
+            Err(errno) => errno as usize,
+        }
+    }
+
+    fn r_ready(&self) -> bool {
+        *self.count.lock() > 0
+    }
+
+    fn w_ready(&self) -> bool {
+        *self.count.lock() < EventFd::MAX_COUNT
+    }
+
+    fn read_user(&self, offset: Option<usize>, mut buf: UserBuffer) -> usize {
+        if offset.is_some() {
+            return ESPIPE as usize;
+        }
+        if buf.len() < 8 {
+            return EINVAL as usize;
+        }
+        match self.take() {
+            Ok(value) => buf.write(&value.to_ne_bytes()),
+            Err(errno) => errno as usize,
+        }
+    }
+
+    fn write_user(&self, offset: Option<usize>, buf: UserBuffer) -> usize {
+        if offset.is_some() {
+            return ESPIPE as usize;
+        }
+        if buf.len() < 8 {
+            return EINVAL as usize;
+        }
+        let mut value = [0u8; 8];
+        buf.read(&mut value);
+        match self.add(u64::from_ne_bytes(value)) {
+            Ok(()) => 8,
+            Err(errno) => errno as usize,
+        }
+    }
+
+    fn get_size(&self) -> usize {
+        0
+    }
+
+    fn get_stat(&self) -> Stat {
+        Stat::new(
+            crate::makedev!(0, 13),
+            1,
+            // anonymous inodes have no file type
+            0o600,
+            1,
+            0,
+            0,
+            0,
+            0,
+            0,
+        )
+    }
+
+    fn get_file_type(&self) -> DiskInodeType {
+        DiskInodeType::File
+    }
+
+    fn info_dirtree_node(
+        &self,
+        dirnode_ptr: alloc::sync::Weak<crate::fs::directory_tree::DirectoryTreeNode>,
+    ) {
+    }
+
+    fn get_dirtree_node(&self) -> Option<Arc<DirectoryTreeNode>> {
+        None
+    }
+
+    fn open(
+        &self,
+        flags: crate::fs::layout::OpenFlags,
+        special_use: bool,
+    ) -> Result<Arc<dyn File>, isize> {
+        Ok(self.deep_clone())
+    }
+
+    fn open_subfile(
+        &self,
+    ) -> Result<alloc::vec::Vec<(alloc::string::String, alloc::sync::Arc<dyn File>)>, isize> {
+        Err(ENOTDIR)
+    }
+
+    fn create(&self, name: &str, file_type: DiskInodeType) -> Result<Arc<dyn File>, isize> {
+        Err(ENOTDIR)
+    }
+
+    fn link_child(&self, name: &str, child: &Self) -> Result<(), isize>
+    where
+        Self: Sized,
+    {
+        Err(ENOTDIR)
+    }
+
+    fn unlink(&self, delete: bool) -> Result<(), isize> {
+        Err(ENOTDIR)
+    }
+
+    fn get_dirent(&self, count: usize) -> alloc::vec::Vec<crate::fs::layout::Dirent> {
+        Vec::new()
+    }
+
+    fn lseek(&self, offset: isize, whence: crate::fs::SeekWhence) -> Result<usize, isize> {
+        Err(ESPIPE)
+    }
+
+    fn modify_size(&self, diff: isize) -> Result<(), isize> {
+        Err(EINVAL)
+    }
+
+    fn truncate_size(&self, new_size: usize) -> Result<(), isize> {
+        Err(EINVAL)
+    }
+
+    fn set_timestamp(&self, ctime: Option<usize>, atime: Option<usize>, mtime: Option<usize>) {}
+
+    fn get_single_cache(
+        &self,
+        offset: usize,
+    ) -> Result<Arc<spin::Mutex<crate::fs::PageCache>>, ()> {
+        Err(())
+    }
+
+    fn get_all_caches(
+        &self,
+    ) -> Result<alloc::vec::Vec<Arc<spin::Mutex<crate::fs::PageCache>>>, ()> {
+        Err(())
+    }
+
+    fn oom(&self) -> usize {
+        0
+    }
+
+    fn hang_up(&self) -> bool {
+        false
+    }
+
+    fn wait_queues(&self) -> Vec<Arc<Mutex<WaitQueue>>> {
+        alloc::vec![self.wait_queue.clone()]
+    }
+
+    fn set_nonblock(&self, nonblock: bool) {
+        self.nonblock.store(nonblock, Ordering::Relaxed);
+    }
+
+    fn fcntl(&self, cmd: u32, arg: u32) -> isize {
+        EINVAL
+    }
+}
@@ -1,54 +1,77 @@
 use crate::{
-    fs::{file_trait::File, DiskInodeType},
-    syscall::errno::{ENOTDIR, SUCCESS},
+    drivers::rtc::{RtcTime, RTC},
+    fs::{file_trait::File, layout::Stat, DiskInodeType, StatMode},
+    mm::{copy_from_user, copy_to_user},
+    syscall::errno::{EFAULT, EINVAL, ENOTDIR, ENOTTY, ESPIPE, SUCCESS},
+    task::current_user_token,
 };
+use alloc::sync::Arc;
 
+/// The RTC, only its time can be read and set with `ioctl`, alarms and update interrupts are not supported
 pub struct Hwclock;
 
+impl Hwclock {
+    /// `ioctl` reading the RTC into a `struct rtc_time`
+    pub const RTC_RD_TIME: u32 = 0x80247009;
+    /// `ioctl` setting the RTC from a `struct rtc_time`
+    pub const RTC_SET_TIME: u32 = 0x4024700a;
+}
+
 #[allow(unused)]
 impl File for Hwclock {
     fn deep_clone(&self) -> alloc::sync::Arc<dyn File> {
-        todo!()
+        Arc::new(Hwclock {})
     }
 
     fn readable(&self) -> bool {
-        todo!()
+        true
     }
 
     fn writable(&self) -> bool {
-        todo!()
+        false
     }
 
     fn read(&self, offset: Option<&mut usize>, buf: &mut [u8]) -> usize {
-        todo!()
+        0
     }
 
     fn write(&self, offset: Option<&mut usize>, buf: &[u8]) -> usize {
-        todo!()
+        0
     }
 
     fn r_ready(&self) -> bool {
-        todo!()
+        false
     }
 
     fn w_ready(&self) -> bool {
-        todo!()
+        false
     }
 
     fn read_user(&self, offset: Option<usize>, buf: crate::mm::UserBuffer) -> usize {
-        todo!()
+        // reads would wait for an update interrupt
+        EINVAL as usize
     }
 
     fn write_user(&self, offset: Option<usize>, buf: crate::mm::UserBuffer) -> usize {
-        todo!()
+        EINVAL as usize
     }
 
     fn get_size(&self) -> usize {
-        todo!()
+        0
     }
 
     fn get_stat(&self) -> crate::fs::Stat {
-        todo!()
+        Stat::new(
+            crate::makedev!(0, 5),
+            1,
+            StatMode::S_IFCHR.bits() | 0o644,
+            1,
+            crate::makedev!(254, 0),
+            0,
+            0,
+            0,
+            0,
+        )
     }
 
     fn get_file_type(&self) -> DiskInodeType {
@@ -64,11 +87,15 @@ impl File for Hwclock {
     fn get_dirtree_node(
         &self,
     ) -> Option<alloc::sync::Arc<crate::fs::directory_tree::DirectoryTreeNode>> {
-        todo!()
+        None
     }
 
-    fn open(&self, flags: crate::fs::OpenFlags, special_use: bool) -> alloc::sync::Arc<dyn File> {
-        alloc::sync::Arc::new(Hwclock {})
+    fn open(
+        &self,
+        flags: crate::fs::OpenFlags,
+        special_use: bool,
+    ) -> Result<alloc::sync::Arc<dyn File>, isize> {
+        Ok(alloc::sync::Arc::new(Hwclock {}))
     }
 
     fn open_subfile(
@@ -82,51 +109,49 @@ impl File for Hwclock {
         name: &str,
         file_type: DiskInodeType,
     ) -> Result<alloc::sync::Arc<dyn File>, isize> {
-        todo!()
+        Err(ENOTDIR)
     }
 
     fn link_child(&self, name: &str, child: &Self) -> Result<(), isize>
     where
         Self: Sized,
     {
-        todo!()
+        Err(ENOTDIR)
     }
 
     fn unlink(&self, delete: bool) -> Result<(), isize> {
-        todo!()
+        Err(ENOTDIR)
     }
 
     fn get_dirent(&self, count: usize) -> alloc::vec::Vec<crate::fs::Dirent> {
-        todo!()
+        alloc::vec::Vec::new()
     }
 
     fn lseek(&self, offset: isize, whence: crate::fs::SeekWhence) -> Result<usize, isize> {
-        todo!()
+        Err(ESPIPE)
     }
 
     fn modify_size(&self, diff: isize) -> Result<(), isize> {
-        todo!()
+        Err(EINVAL)
     }
 
     fn truncate_size(&self, new_size: usize) -> Result<(), isize> {
-        todo!()
+        Err(EINVAL)
     }
 
-    fn set_timestamp(&self, ctime: Option<usize>, atime: Option<usize>, mtime: Option<usize>) {
-        todo!()
-    }
+    fn set_timestamp(&self, ctime: Option<usize>, atime: Option<usize>, mtime: Option<usize>) {}
 
     fn get_single_cache(
         &self,
         offset: usize,
     ) -> Result<alloc::sync::Arc<spin::Mutex<crate::fs::PageCache>>, ()> {
-        todo!()
+        Err(())
     }
 
     fn get_all_caches(
         &self,
     ) -> Result<alloc::vec::Vec<alloc::sync::Arc<spin::Mutex<crate::fs::PageCache>>>, ()> {
-        todo!()
+        Err(())
     }
 
     fn oom(&self) -> usize {
@@ -134,14 +159,37 @@ impl File for Hwclock {
     }
 
     fn hang_up(&self) -> bool {
-        todo!()
+        false
     }
 
     fn fcntl(&self, cmd: u32, arg: u32) -> isize {
-        todo!()
-    }
-
-    fn ioctl(&self, _cmd: u32, _argp: usize) -> isize {
-        SUCCESS
+        EINVAL
+    }
+
+    fn ioctl(&self, cmd: u32, argp: usize) -> isize {
+        let token = current_user_token();
+        match cmd {
+            Hwclock::RTC_RD_TIME => {
+                let time = match RTC.read_time() {
+                    Some(time) => time,
+                    None => return EINVAL,
+                };
+                if copy_to_user(token, &time, argp as *mut RtcTime).is_err() {
+                    return EFAULT;
+                }
+                SUCCESS
+            }
+            Hwclock::RTC_SET_TIME => {
+                let mut time = RtcTime::default();
+                if copy_from_user(token, argp as *const RtcTime, &mut time).is_err() {
+                    return EFAULT;
+                }
+                match RTC.set_time(&time) {
+                    Ok(()) => SUCCESS,
+                    Err(errno) => errno,
+                }
+            }
+            _ => ENOTTY,
+        }
     }
 }
@@ -1,7 +1,11 @@
+pub mod eventfd;
 pub mod hwclock;
 pub mod null;
 pub mod pipe;
+pub mod random;
+pub mod signalfd;
 pub mod socket;
+pub mod timerfd;
 pub mod tty;
 pub mod zero;
 
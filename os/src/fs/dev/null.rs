@@ -78,11 +78,15 @@ impl File for Null {
     }
 
     fn get_dirtree_node(&self) -> Option<Arc<DirectoryTreeNode>> {
-        todo!()
+        None
     }
 
-    fn open(&self, flags: crate::fs::layout::OpenFlags, special_use: bool) -> Arc<dyn File> {
-        Arc::new(Null {})
+    fn open(
+        &self,
+        flags: crate::fs::layout::OpenFlags,
+        special_use: bool,
+    ) -> Result<Arc<dyn File>, isize> {
+        Ok(Arc::new(Null {}))
     }
 
     fn open_subfile(
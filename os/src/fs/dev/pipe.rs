@@ -5,13 +5,13 @@ use crate::fs::StatMode;
 use crate::syscall::errno::*;
 use crate::task::block_current_and_run_next;
 use crate::task::current_task;
-use crate::task::wait_with_timeout;
-use crate::timer::TimeSpec;
+use crate::task::WaitQueue;
 use crate::{fs::file_trait::File, mm::UserBuffer};
 use alloc::boxed::Box;
 use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
 use core::ptr::copy_nonoverlapping;
-use spin::Mutex;
+use spin::{Mutex, MutexGuard};
 
 pub struct Pipe {
     readable: bool,
@@ -34,6 +34,22 @@ impl Pipe {
             buffer,
         }
     }
+    /// Sleep till the peer reads, writes, opens or closes its end, `ring` is unlocked meanwhile.
+    /// Returns `Err(ERESTART)` if a signal arrives.
+    fn wait(ring: MutexGuard<PipeRingBuffer>) -> Result<(), isize> {
+        let task = current_task().unwrap();
+        if task.signal_pending() {
+            return Err(ERESTART);
+        }
+        let waiter = Arc::downgrade(&task);
+        let wait_queue = ring.wait_queue.clone();
+        wait_queue.lock().add_task(waiter.clone());
+        drop(ring);
+        drop(task);
+        block_current_and_run_next();
+        wait_queue.lock().remove(&waiter);
+        Ok(())
+    }
 }
 
 #[cfg(feature = "board_fu740")]
@@ -55,6 +71,8 @@ pub struct PipeRingBuffer {
     status: RingBufferStatus,
     write_end: Option<Weak<Pipe>>,
     read_end: Option<Weak<Pipe>>,
+    /// Woken when data is read or written, and when an end is closed
+    wait_queue: Arc<Mutex<WaitQueue>>,
 }
 
 impl PipeRingBuffer {
@@ -70,6 +88,7 @@ impl PipeRingBuffer {
             status: RingBufferStatus::EMPTY,
             write_end: None,
             read_end: None,
+            wait_queue: Arc::new(Mutex::new(WaitQueue::new())),
         }
     }
     #[allow(unused)]
@@ -137,11 +156,18 @@ impl PipeRingBuffer {
     fn set_read_end(&mut self, read_end: &Arc<Pipe>) {
         self.read_end = Some(Arc::downgrade(read_end));
     }
+    /// A FIFO opened with `O_NONBLOCK` may not have the other end yet.
+    /// The ends are never upgraded here, the last reference would drop under the ring lock
+    /// and `Pipe::drop` takes it again.
     fn all_write_ends_closed(&self) -> bool {
-        self.write_end.as_ref().unwrap().upgrade().is_none()
+        self.write_end
+            .as_ref()
+            .map_or(true, |write_end| write_end.strong_count() == 0)
     }
     fn all_read_ends_closed(&self) -> bool {
-        self.read_end.as_ref().unwrap().upgrade().is_none()
+        self.read_end
+            .as_ref()
+            .map_or(true, |read_end| read_end.strong_count() == 0)
     }
 }
 
@@ -156,6 +182,76 @@ pub fn make_pipe() -> (Arc<Pipe>, Arc<Pipe>) {
     (read_end, write_end)
 }
 
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        // the peer sees the end closed
+        self.buffer.lock().wait_queue.lock().wake_all();
+    }
+}
+
+/// Named pipe, every `open()` of it joins the same pipe while any end is open
+pub struct Fifo {
+    buffer: Mutex<Weak<Mutex<PipeRingBuffer>>>,
+}
+
+impl Fifo {
+    pub fn new() -> Self {
+        Self {
+            buffer: Mutex::new(Weak::new()),
+        }
+    }
+    /// Open the read end if `readable`, otherwise the write end.
+    /// Like Linux, wait until the other end is opened unless `nonblock`.
+    /// Returns `Err(ERESTART)` if a signal arrives meanwhile.
+    pub fn open(&self, readable: bool, nonblock: bool) -> Result<Arc<Pipe>, isize> {
+        let mut lock = self.buffer.lock();
+        let buffer = match lock.upgrade() {
+            Some(buffer) => buffer,
+            None => {
+                let buffer = Arc::new(Mutex::new(PipeRingBuffer::new()));
+                *lock = Arc::downgrade(&buffer);
+                buffer
+            }
+        };
+        drop(lock);
+        let mut ring = buffer.lock();
+        let opened = match readable {
+            true => ring.read_end.as_ref(),
+            false => ring.write_end.as_ref(),
+        }
+        .and_then(|end| end.upgrade());
+        // all readers (or writers) share one end
+        let end = match opened {
+            Some(end) => end,
+            None if readable => {
+                let end = Arc::new(Pipe::read_end_with_buffer(buffer.clone()));
+                ring.set_read_end(&end);
+                end
+            }
+            None => {
+                let end = Arc::new(Pipe::write_end_with_buffer(buffer.clone()));
+                ring.set_write_end(&end);
+                end
+            }
+        };
+        // the openers of the other end wait for this one
+        ring.wait_queue.lock().wake_all();
+        drop(ring);
+        while !nonblock {
+            let ring = buffer.lock();
+            let peer_closed = match readable {
+                true => ring.all_write_ends_closed(),
+                false => ring.all_read_ends_closed(),
+            };
+            if !peer_closed {
+                break;
+            }
+            Pipe::wait(ring)?;
+        }
+        Ok(end)
+    }
+}
+
 #[allow(unused)]
 impl File for Pipe {
     fn deep_clone(&self) -> Arc<dyn File> {
@@ -176,27 +272,19 @@ impl File for Pipe {
         }
         let mut read_size = 0usize;
         loop {
-            let task = current_task().unwrap();
-            let inner = task.acquire_inner_lock();
-            if !inner.sigpending.difference(inner.sigmask).is_empty() {
-                return ERESTART as usize;
-            }
-            drop(inner);
-            drop(task);
             let mut ring = self.buffer.lock();
             if ring.status == RingBufferStatus::EMPTY {
                 if ring.all_write_ends_closed() {
                     return read_size;
                 }
-                drop(ring);
-                let task = current_task().unwrap();
-                wait_with_timeout(Arc::downgrade(&task), TimeSpec::now());
-                drop(task);
-                block_current_and_run_next();
-                // suspend_current_and_run_next();
+                if let Err(errno) = Pipe::wait(ring) {
+                    return errno as usize;
+                }
                 continue;
             }
            // println!("pipe333");
+            // the waiters look at the ring once it is unlocked
+            ring.wait_queue.lock().wake_all();
             // We guarantee that this operation will read at least one byte
             while read_size < buf.len() {
                 let read_bytes = ring.buffer_read(&mut buf[read_size..]);
@@ -219,26 +307,18 @@ impl File for Pipe {
         let mut write_size = 0usize;
 
         loop {
-            let task = current_task().unwrap();
-            let inner = task.acquire_inner_lock();
-            if !inner.sigpending.difference(inner.sigmask).is_empty() {
-                return ERESTART as usize;
-            }
-            drop(inner);
-            drop(task);
             let mut ring = self.buffer.lock();
             if ring.status == RingBufferStatus::FULL {
                 if ring.all_read_ends_closed() {
                     return write_size;
                 }
-                drop(ring);
-                let task = current_task().unwrap();
-                wait_with_timeout(Arc::downgrade(&task), TimeSpec::now());
-                drop(task);
-                block_current_and_run_next();
-                // suspend_current_and_run_next();
+                if let Err(errno) = Pipe::wait(ring) {
+                    return errno as usize;
+                }
                 continue;
             }
+            // the waiters look at the ring once it is unlocked
+            ring.wait_queue.lock().wake_all();
             // We guarantee that this operation will write at least one byte
             // So we modify status first
             while write_size < buf.len() {
@@ -270,26 +350,18 @@ impl File for Pipe {
         }
         let mut read_size = 0usize;
         loop {
-            let task = current_task().unwrap();
-            let inner = task.acquire_inner_lock();
-            // if !inner.sigpending.difference(inner.sigmask).is_empty() {  // 注释掉该返回值，pipe测例通过，跟读出pipe内容有关
-            //     return ERESTART as usize;
-            // }
-            drop(inner);
-            drop(task);
             let mut ring = self.buffer.lock();
             if ring.status == RingBufferStatus::EMPTY {
                 if ring.all_write_ends_closed() {
                     return read_size;
                 }
-                drop(ring);
-                let task = current_task().unwrap();
-                wait_with_timeout(Arc::downgrade(&task), TimeSpec::now());
-                drop(task);
-                block_current_and_run_next();
-                // suspend_current_and_run_next();
+                if let Err(errno) = Pipe::wait(ring) {
+                    return errno as usize;
+                }
                 continue;
             }
+            // the waiters look at the ring once it is unlocked
+            ring.wait_queue.lock().wake_all();
             // We guarantee that this operation will read at least one byte
             // So we modify status first
             for buf in buf.buffers {
@@ -316,26 +388,18 @@ impl File for Pipe {
         }
         let mut write_size = 0usize;
         loop {
-            let task = current_task().unwrap();
-            let inner = task.acquire_inner_lock();
-            if !inner.sigpending.difference(inner.sigmask).is_empty() {
-                return ERESTART as usize;
-            }
-            drop(inner);
-            drop(task);
             let mut ring = self.buffer.lock();
             if ring.status == RingBufferStatus::FULL {
                 if ring.all_read_ends_closed() {
                     return write_size;
                 }
-                drop(ring);
-                let task = current_task().unwrap();
-                wait_with_timeout(Arc::downgrade(&task), TimeSpec::now());
-                drop(task);
-                block_current_and_run_next();
-                // suspend_current_and_run_next();
+                if let Err(errno) = Pipe::wait(ring) {
+                    return errno as usize;
+                }
                 continue;
             }
+            // the waiters look at the ring once it is unlocked
+            ring.wait_queue.lock().wake_all();
             // We guarantee that this operation will write at least one byte
             // So we modify status first
             for buf in buf.buffers {
@@ -383,10 +447,14 @@ impl File for Pipe {
     }
 
     fn get_dirtree_node(&self) -> Option<Arc<DirectoryTreeNode>> {
-        todo!()
+        None
     }
 
-    fn open(&self, flags: crate::fs::layout::OpenFlags, special_use: bool) -> Arc<dyn File> {
+    fn open(
+        &self,
+        flags: crate::fs::layout::OpenFlags,
+        special_use: bool,
+    ) -> Result<Arc<dyn File>, isize> {
         todo!()
     }
 
@@ -443,6 +511,10 @@ impl File for Pipe {
         0
     }
 
+    fn wait_queues(&self) -> Vec<Arc<Mutex<WaitQueue>>> {
+        alloc::vec![self.buffer.lock().wait_queue.clone()]
+    }
+
     fn hang_up(&self) -> bool {
         // The peer has closed its end.
         // Or maybe you should only check whether both ends have been closed by the peer.
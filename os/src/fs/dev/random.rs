@@ -0,0 +1,205 @@
+use crate::fs::DiskInodeType;
+use alloc::sync::Arc;
+
+use crate::{
+    fs::{directory_tree::DirectoryTreeNode, file_trait::File, layout::Stat, StatMode},
+    mm::{copy_to_user, UserBuffer},
+    random::{add_device_randomness, entropy_avail, get_random_bytes, wait_for_random_bytes},
+    syscall::errno::{EFAULT, EINVAL, ENOTDIR, ENOTTY, ESPIPE, SUCCESS},
+    task::current_user_token,
+};
+
+/// Kernel random number generator
+/// Reads from `/dev/random` block until the generator has been seeded, `/dev/urandom` never blocks.
+/// Data written to either of them is mixed into the entropy pool but not credited.
+pub struct Random {
+    blocking: bool,
+}
+
+impl Random {
+    /// `ioctl` returning the entropy count of the input pool
+    pub const RNDGETENTCNT: u32 = 0x80045200;
+    pub fn new(blocking: bool) -> Self {
+        Self { blocking }
+    }
+    pub fn path(&self) -> &'static str {
+        if self.blocking {
+            "/dev/random"
+        } else {
+            "/dev/urandom"
+        }
+    }
+}
+
+#[allow(unused)]
+impl File for Random {
+    fn deep_clone(&self) -> Arc<dyn File> {
+        Arc::new(Random::new(self.blocking))
+    }
+
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        true
+    }
+
+    fn read(&self, offset: Option<&mut usize>, buf: &mut [u8]) -> usize {
+        get_random_bytes(buf);
+        buf.len()
+    }
+
+    fn write(&self, offset: Option<&mut usize>, buf: &[u8]) -> usize {
+        add_device_randomness(buf);
+        buf.len()
+    }
+
+    fn r_ready(&self) -> bool {
+        true
+    }
+
+    fn w_ready(&self) -> bool {
+        true
+    }
+
+    fn get_size(&self) -> usize {
+        0
+    }
+
+    fn get_stat(&self) -> Stat {
+        Stat::new(
+            crate::makedev!(0, 5),
+            1,
+            StatMode::S_IFCHR.bits() | 0o666,
+            1,
+            if self.blocking {
+                crate::makedev!(1, 8)
+            } else {
+                crate::makedev!(1, 9)
+            },
+            0,
+            0,
+            0,
+            0,
+        )
+    }
+
+    fn read_user(&self, offset: Option<usize>, mut buf: UserBuffer) -> usize {
+        if self.blocking {
+            if let Err(errno) = wait_for_random_bytes(false) {
+                return errno as usize;
+            }
+        }
+        for buffer in buf.buffers.iter_mut() {
+            get_random_bytes(buffer);
+        }
+        buf.len()
+    }
+
+    fn write_user(&self, offset: Option<usize>, buf: UserBuffer) -> usize {
+        for buffer in buf.buffers.iter() {
+            add_device_randomness(buffer);
+        }
+        buf.len()
+    }
+
+    fn get_file_type(&self) -> DiskInodeType {
+        DiskInodeType::File
+    }
+
+    fn info_dirtree_node(
+        &self,
+        dirnode_ptr: alloc::sync::Weak<crate::fs::directory_tree::DirectoryTreeNode>,
+    ) {
+    }
+
+    fn get_dirtree_node(&self) -> Option<Arc<DirectoryTreeNode>> {
+        None
+    }
+
+    fn open(
+        &self,
+        flags: crate::fs::layout::OpenFlags,
+        special_use: bool,
+    ) -> Result<Arc<dyn File>, isize> {
+        Ok(Arc::new(Random::new(self.blocking)))
+    }
+
+    fn open_subfile(
+        &self,
+    ) -> Result<alloc::vec::Vec<(alloc::string::String, alloc::sync::Arc<dyn File>)>, isize> {
+        Err(ENOTDIR)
+    }
+
+    fn create(&self, name: &str, file_type: DiskInodeType) -> Result<Arc<dyn File>, isize> {
+        Err(ENOTDIR)
+    }
+
+    fn link_child(&self, name: &str, child: &Self) -> Result<(), isize>
+    where
+        Self: Sized,
+    {
+        Err(ENOTDIR)
+    }
+
+    fn unlink(&self, delete: bool) -> Result<(), isize> {
+        Err(ENOTDIR)
+    }
+
+    fn get_dirent(&self, count: usize) -> alloc::vec::Vec<crate::fs::layout::Dirent> {
+        alloc::vec::Vec::new()
+    }
+
+    fn lseek(&self, offset: isize, whence: crate::fs::SeekWhence) -> Result<usize, isize> {
+        Err(ESPIPE)
+    }
+
+    fn modify_size(&self, diff: isize) -> Result<(), isize> {
+        Err(EINVAL)
+    }
+
+    fn truncate_size(&self, new_size: usize) -> Result<(), isize> {
+        Err(EINVAL)
+    }
+
+    fn set_timestamp(&self, ctime: Option<usize>, atime: Option<usize>, mtime: Option<usize>) {}
+
+    fn get_single_cache(
+        &self,
+        offset: usize,
+    ) -> Result<Arc<spin::Mutex<crate::fs::PageCache>>, ()> {
+        Err(())
+    }
+
+    fn get_all_caches(
+        &self,
+    ) -> Result<alloc::vec::Vec<Arc<spin::Mutex<crate::fs::PageCache>>>, ()> {
+        Err(())
+    }
+
+    fn oom(&self) -> usize {
+        0
+    }
+
+    fn hang_up(&self) -> bool {
+        false
+    }
+
+    fn ioctl(&self, cmd: u32, argp: usize) -> isize {
+        match cmd {
+            Random::RNDGETENTCNT => {
+                let count = entropy_avail() as i32;
+                if copy_to_user(current_user_token(), &count, argp as *mut i32).is_err() {
+                    return EFAULT;
+                }
+                SUCCESS
+            }
+            _ => ENOTTY,
+        }
+    }
+
+    fn fcntl(&self, cmd: u32, arg: u32) -> isize {
+        EINVAL
+    }
+}
@@ -0,0 +1,326 @@
+use crate::fs::DiskInodeType;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+
+use crate::{
+    fs::{directory_tree::DirectoryTreeNode, file_trait::File, layout::Stat},
+    mm::UserBuffer,
+    syscall::errno::{EAGAIN, EINVAL, ENOTDIR, ERESTART, ESPIPE},
+    task::{block_current_and_run_next, current_task, SigInfo, Signals},
+};
+
+/// `struct signalfd_siginfo`
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct SignalFdSigInfo {
+    pub ssi_signo: u32,
+    pub ssi_errno: i32,
+    pub ssi_code: i32,
+    pub ssi_pid: u32,
+    pub ssi_uid: u32,
+    pub ssi_fd: i32,
+    pub ssi_tid: u32,
+    pub ssi_band: u32,
+    pub ssi_overrun: u32,
+    pub ssi_trapno: u32,
+    pub ssi_status: i32,
+    pub ssi_int: i32,
+    pub ssi_ptr: u64,
+    pub ssi_utime: u64,
+    pub ssi_stime: u64,
+    pub ssi_addr: u64,
+    pub ssi_addr_lsb: u16,
+    __pad: [u8; 46],
+}
+
+impl From<SigInfo> for SignalFdSigInfo {
+    /// Unpack the fields `SigInfo` shares between the members of the union
+    fn from(info: SigInfo) -> Self {
+        let mut ssi = Self {
+            ssi_signo: info.si_signo,
+            ssi_errno: info.si_errno as i32,
+            ssi_code: info.si_code as i32,
+            ssi_pid: 0,
+            ssi_uid: 0,
+            ssi_fd: 0,
+            ssi_tid: 0,
+            ssi_band: 0,
+            ssi_overrun: 0,
+            ssi_trapno: 0,
+            ssi_status: 0,
+            ssi_int: info.si_value as i32,
+            ssi_ptr: info.si_value as u64,
+            ssi_utime: 0,
+            ssi_stime: 0,
+            ssi_addr: 0,
+            ssi_addr_lsb: 0,
+            __pad: [0; 46],
+        };
+        let fault = [
+            Signals::SIGILL,
+            Signals::SIGFPE,
+            Signals::SIGSEGV,
+            Signals::SIGBUS,
+        ];
+        let signal = Signals::from_signum(info.si_signo as usize).unwrap_or(Signals::empty());
+        if info.si_code == SigInfo::SI_TIMER {
+            ssi.ssi_tid = info.si_pid;
+            ssi.ssi_overrun = info.si_uid;
+        } else if fault.contains(&signal) && info.si_code != SigInfo::SI_KERNEL {
+            ssi.ssi_addr = info.si_pid as u64 | (info.si_uid as u64) << 32;
+            ssi.ssi_int = 0;
+            ssi.ssi_ptr = 0;
+        } else {
+            ssi.ssi_pid = info.si_pid;
+            ssi.ssi_uid = info.si_uid;
+            if signal == Signals::SIGCHLD {
+                ssi.ssi_status = info.si_value as i32;
+            }
+        }
+        ssi
+    }
+}
+
+/// A descriptor made by `signalfd4()`, reads take the pending signals in `mask` out of the queue of the reader.
+/// The signals should be blocked, or they may be delivered before they are read.
+pub struct SignalFd {
+    selfptr: Mutex<Weak<Self>>,
+    mask: Mutex<Signals>,
+    nonblock: AtomicBool,
+}
+
+impl SignalFd {
+    pub const SFD_NONBLOCK: u32 = 0o4000;
+    pub const SFD_CLOEXEC: u32 = 0o2000000;
+    pub fn new(mask: Signals) -> Arc<Self> {
+        let signalfd = Arc::new(Self {
+            selfptr: Mutex::new(Weak::new()),
+            mask: Mutex::new(mask - (Signals::SIGKILL | Signals::SIGSTOP)),
+            nonblock: AtomicBool::new(false),
+        });
+        *signalfd.selfptr.lock() = Arc::downgrade(&signalfd);
+        signalfd
+    }
+    /// `signalfd4()` on an existing descriptor replaces its mask
+    pub fn set_mask(&self, mask: Signals) {
+        *self.mask.lock() = mask - (Signals::SIGKILL | Signals::SIGSTOP);
+    }
+    /// Take at most `count` pending signals in the mask, blocking till there is one
+    fn dequeue(&self, count: usize) -> Result<Vec<SignalFdSigInfo>, isize> {
+        loop {
+            let mask = *self.mask.lock();
+            let task = current_task().unwrap();
+            let mut inner = task.acquire_inner_lock();
+            let mut infos = Vec::new();
+            while infos.len() < count {
+                match inner.dequeue_signal(!mask) {
+                    Some(info) => infos.push(SignalFdSigInfo::from(info)),
+                    None => break,
+                }
+            }
+            if !infos.is_empty() {
+                return Ok(infos);
+            }
+            if self.nonblock.load(Ordering::Relaxed) {
+                return Err(EAGAIN);
+            }
+            drop(inner);
+            if task.signal_pending() {
+                return Err(ERESTART);
+            }
+            // woken by `send_signal()`
+            drop(task);
+            block_current_and_run_next();
+        }
+    }
+}
+
+impl SignalFdSigInfo {
+    fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self as *const Self as *const u8, size_of::<Self>()) }
+    }
+}
+
+#[allow(unused)]
+impl File for SignalFd {
+    fn deep_clone(&self) -> Arc<dyn File> {
+        self.selfptr.lock().upgrade().unwrap()
+    }
+
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+
+    fn read(&self, offset: Option<&mut usize>, buf: &mut [u8]) -> usize {
+        if offset.is_some() {
+            return ESPIPE as usize;
+        }
+        let count = buf.len() / size_of::<SignalFdSigInfo>();
+        if count == 0 {
+            return EINVAL as usize;
+        }
+        match self.dequeue(count) {
+            Ok(infos) => {
+                for (info, chunk) in infos
+                    .iter()
+                    .zip(buf.chunks_exact_mut(size_of::<SignalFdSigInfo>()))
+                {
+                    chunk.copy_from_slice(info.as_bytes());
+                }
+                infos.len() * size_of::<SignalFdSigInfo>()
+            }
+            Err(errno) => errno as usize,
+        }
+    }
+
+    fn write(&self, offset: Option<&mut usize>, buf: &[u8]) -> usize {
+        EINVAL as usize
+    }
+
+    fn r_ready(&self) -> bool {
+        let mask = *self.mask.lock();
+        let task = current_task().unwrap();
+        let inner = task.acquire_inner_lock();
+        inner.sigpending.intersects(mask)
+    }
+
+    fn w_ready(&self) -> bool {
+        false
+    }
+
+    fn read_user(&self, offset: Option<usize>, mut buf: UserBuffer) -> usize {
+        if offset.is_some() {
+            return ESPIPE as usize;
+        }
+        let count = buf.len() / size_of::<SignalFdSigInfo>();
+        if count == 0 {
+            return EINVAL as usize;
+        }
+        match self.dequeue(count) {
+            Ok(infos) => infos.iter().fold(0, |read_size, info| {
+                read_size + buf.write_at(read_size, info.as_bytes())
+            }),
+            Err(errno) => errno as usize,
+        }
+    }
+
+    fn write_user(&self, offset: Option<usize>, buf: UserBuffer) -> usize {
+        EINVAL as usize
+    }
+
+    fn get_size(&self) -> usize {
+        0
+    }
+
+    fn get_stat(&self) -> Stat {
+        Stat::new(
+            crate::makedev!(0, 13),
+            1,
+            // anonymous inodes have no file type
+            0o600,
+            1,
+            0,
+            0,
+            0,
+            0,
+            0,
+        )
+    }
+
+    fn get_file_type(&self) -> DiskInodeType {
+        DiskInodeType::File
+    }
+
+    fn info_dirtree_node(
+        &self,
+        dirnode_ptr: alloc::sync::Weak<crate::fs::directory_tree::DirectoryTreeNode>,
+    ) {
+    }
+
+    fn get_dirtree_node(&self) -> Option<Arc<DirectoryTreeNode>> {
+        None
+    }
+
+    fn open(
+        &self,
+        flags: crate::fs::layout::OpenFlags,
+        special_use: bool,
+    ) -> Result<Arc<dyn File>, isize> {
+        Ok(self.deep_clone())
+    }
+
+    fn open_subfile(
+        &self,
+    ) -> Result<alloc::vec::Vec<(alloc::string::String, alloc::sync::Arc<dyn File>)>, isize> {
+        Err(ENOTDIR)
+    }
+
+    fn create(&self, name: &str, file_type: DiskInodeType) -> Result<Arc<dyn File>, isize> {
+        Err(ENOTDIR)
+    }
+
+    fn link_child(&self, name: &str, child: &Self) -> Result<(), isize>
+    where
+        Self: Sized,
+    {
+        Err(ENOTDIR)
+    }
+
+    fn unlink(&self, delete: bool) -> Result<(), isize> {
+        Err(ENOTDIR)
+    }
+
+    fn get_dirent(&self, count: usize) -> alloc::vec::Vec<crate::fs::layout::Dirent> {
+        Vec::new()
+    }
+
+    fn lseek(&self, offset: isize, whence: crate::fs::SeekWhence) -> Result<usize, isize> {
+        Err(ESPIPE)
+    }
+
+    fn modify_size(&self, diff: isize) -> Result<(), isize> {
+        Err(EINVAL)
+    }
+
+    fn truncate_size(&self, new_size: usize) -> Result<(), isize> {
+        Err(EINVAL)
+    }
+
+    fn set_timestamp(&self, ctime: Option<usize>, atime: Option<usize>, mtime: Option<usize>) {}
+
+    fn get_single_cache(
+        &self,
+        offset: usize,
+    ) -> Result<Arc<spin::Mutex<crate::fs::PageCache>>, ()> {
+        Err(())
+    }
+
+    fn get_all_caches(
+        &self,
+    ) -> Result<alloc::vec::Vec<Arc<spin::Mutex<crate::fs::PageCache>>>, ()> {
+        Err(())
+    }
+
+    fn oom(&self) -> usize {
+        0
+    }
+
+    fn hang_up(&self) -> bool {
+        false
+    }
+
+    fn set_nonblock(&self, nonblock: bool) {
+        self.nonblock.store(nonblock, Ordering::Relaxed);
+    }
+
+    fn fcntl(&self, cmd: u32, arg: u32) -> isize {
+        EINVAL
+    }
+}
@@ -0,0 +1,248 @@
+use alloc::{string::String, vec::Vec};
+use core::mem::size_of;
+
+use crate::{
+    mm::{copy_from_user_array, copy_to_user, copy_to_user_array, get_from_user},
+    syscall::errno::*,
+};
+
+pub const AF_UNIX: u16 = 1;
+pub const AF_INET: u16 = 2;
+pub const AF_INET6: u16 = 10;
+
+const UNIX_PATH_MAX: usize = 108;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SockAddrIn {
+    family: u16,
+    /// network byte order
+    port: [u8; 2],
+    addr: [u8; 4],
+    zero: [u8; 8],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SockAddrIn6 {
+    family: u16,
+    /// network byte order
+    port: [u8; 2],
+    flowinfo: u32,
+    addr: [u8; 16],
+    scope_id: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SockAddrUn {
+    family: u16,
+    path: [u8; UNIX_PATH_MAX],
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SocketAddr {
+    Inet {
+        addr: [u8; 4],
+        port: u16,
+    },
+    Inet6 {
+        addr: [u8; 16],
+        port: u16,
+    },
+    /// Abstract addresses keep their leading `'\0'`
+    Unix(String),
+}
+
+/// Key of a bound socket in the socket table.
+/// There is only a loopback interface, so the port alone identifies an inet endpoint.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Endpoint {
+    Port(u16),
+    Path(String),
+}
+
+impl SocketAddr {
+    /// Read a `sockaddr` of `addrlen` bytes from user space
+    pub fn from_user(token: usize, addr: *const u8, addrlen: u32) -> Result<Self, isize> {
+        let addrlen = addrlen as usize;
+        if addr.is_null() {
+            return Err(EFAULT);
+        }
+        if addrlen < size_of::<u16>() {
+            return Err(EINVAL);
+        }
+        let family = get_from_user(token, addr as *const u16)?;
+        match family {
+            AF_INET => {
+                if addrlen < size_of::<SockAddrIn>() {
+                    return Err(EINVAL);
+                }
+                let raw = get_from_user(token, addr as *const SockAddrIn)?;
+                Ok(Self::Inet {
+                    addr: raw.addr,
+                    port: u16::from_be_bytes(raw.port),
+                })
+            }
+            AF_INET6 => {
+                if addrlen < size_of::<SockAddrIn6>() {
+                    return Err(EINVAL);
+                }
+                let raw = get_from_user(token, addr as *const SockAddrIn6)?;
+                Ok(Self::Inet6 {
+                    addr: raw.addr,
+                    port: u16::from_be_bytes(raw.port),
+                })
+            }
+            AF_UNIX => {
+                let len = addrlen.min(size_of::<SockAddrUn>());
+                let mut buf = [0u8; size_of::<SockAddrUn>()];
+                copy_from_user_array(token, addr, buf.as_mut_ptr(), len)?;
+                let path = &buf[size_of::<u16>()..len];
+                let path = match path.first() {
+                    // abstract namespace, the name is not null terminated
+                    Some(0) => path,
+                    _ => match path.iter().position(|ch| *ch == 0) {
+                        Some(end) => &path[..end],
+                        None => path,
+                    },
+                };
+                if path.is_empty() {
+                    return Err(EINVAL);
+                }
+                Ok(Self::Unix(String::from_utf8_lossy(path).into()))
+            }
+            _ => Err(EAFNOSUPPORT),
+        }
+    }
+    /// Write to user space like `getsockname()`,
+    /// `addrlen` holds the size of `addr` and is set to the real size of the address.
+    /// The address is truncated if `addr` is too small.
+    pub fn to_user(&self, token: usize, addr: *mut u8, addrlen: *mut u32) -> Result<(), isize> {
+        if addr.is_null() || addrlen.is_null() {
+            return Ok(());
+        }
+        let bytes = self.to_bytes();
+        let len = (get_from_user(token, addrlen)? as usize).min(bytes.len());
+        if len > 0 {
+            copy_to_user_array(token, bytes.as_ptr(), addr, len)?;
+        }
+        copy_to_user(token, &(bytes.len() as u32), addrlen)
+    }
+    fn to_bytes(&self) -> Vec<u8> {
+        fn as_bytes<T>(raw: &T) -> Vec<u8> {
+            unsafe { core::slice::from_raw_parts(raw as *const T as *const u8, size_of::<T>()) }
+                .to_vec()
+        }
+        match self {
+            Self::Inet { addr, port } => as_bytes(&SockAddrIn {
+                family: AF_INET,
+                port: port.to_be_bytes(),
+                addr: *addr,
+                zero: [0; 8],
+            }),
+            Self::Inet6 { addr, port } => as_bytes(&SockAddrIn6 {
+                family: AF_INET6,
+                port: port.to_be_bytes(),
+                flowinfo: 0,
+                addr: *addr,
+                scope_id: 0,
+            }),
+            Self::Unix(path) => {
+                let mut bytes = as_bytes(&AF_UNIX);
+                bytes.extend_from_slice(path.as_bytes());
+                // only pathname sockets are null terminated, unnamed sockets have only the family
+                if !path.is_empty() && !path.starts_with('\0') {
+                    bytes.push(0);
+                }
+                bytes
+            }
+        }
+    }
+    /// Address of an unbound socket
+    pub fn unspecified(family: u16) -> Self {
+        match family {
+            AF_INET => Self::Inet {
+                addr: [0; 4],
+                port: 0,
+            },
+            AF_INET6 => Self::Inet6 {
+                addr: [0; 16],
+                port: 0,
+            },
+            _ => Self::Unix(String::new()),
+        }
+    }
+    pub fn family(&self) -> u16 {
+        match self {
+            Self::Inet { .. } => AF_INET,
+            Self::Inet6 { .. } => AF_INET6,
+            Self::Unix(_) => AF_UNIX,
+        }
+    }
+    pub fn port(&self) -> Option<u16> {
+        match self {
+            Self::Inet { port, .. } | Self::Inet6 { port, .. } => Some(*port),
+            Self::Unix(_) => None,
+        }
+    }
+    pub fn with_port(&self, new_port: u16) -> Self {
+        match self {
+            Self::Inet { addr, .. } => Self::Inet {
+                addr: *addr,
+                port: new_port,
+            },
+            Self::Inet6 { addr, .. } => Self::Inet6 {
+                addr: *addr,
+                port: new_port,
+            },
+            Self::Unix(path) => Self::Unix(path.clone()),
+        }
+    }
+    /// The loopback address of the same family
+    pub fn loopback(&self) -> Self {
+        match self {
+            Self::Inet { port, .. } => Self::Inet {
+                addr: [127, 0, 0, 1],
+                port: *port,
+            },
+            Self::Inet6 { port, .. } => {
+                let mut addr = [0; 16];
+                addr[15] = 1;
+                Self::Inet6 { addr, port: *port }
+            }
+            Self::Unix(path) => Self::Unix(path.clone()),
+        }
+    }
+    /// Whether this is the wildcard address `INADDR_ANY` or `in6addr_any`
+    pub fn is_unspecified(&self) -> bool {
+        match self {
+            Self::Inet { addr, .. } => *addr == [0; 4],
+            Self::Inet6 { addr, .. } => *addr == [0; 16],
+            Self::Unix(_) => false,
+        }
+    }
+    /// Whether the address belongs to this host.
+    /// We only have a loopback interface, the wildcard address is treated as loopback as Linux does.
+    pub fn is_local(&self) -> bool {
+        match self {
+            Self::Inet { addr, .. } => addr[0] == 127 || *addr == [0; 4],
+            Self::Inet6 { addr, .. } => {
+                let (prefix, last) = addr.split_at(12);
+                let v4_mapped = prefix[..10].iter().all(|byte| *byte == 0)
+                    && prefix[10] == 0xff
+                    && prefix[11] == 0xff;
+                (prefix.iter().all(|byte| *byte == 0)
+                    && (last == [0, 0, 0, 0] || last == [0, 0, 0, 1]))
+                    || (v4_mapped && last[0] == 127)
+            }
+            Self::Unix(_) => true,
+        }
+    }
+    pub fn endpoint(&self) -> Endpoint {
+        match self {
+            Self::Inet { port, .. } | Self::Inet6 { port, .. } => Endpoint::Port(*port),
+            Self::Unix(path) => Endpoint::Path(path.clone()),
+        }
+    }
+}
@@ -0,0 +1,1023 @@
+mod addr;
+mod unix;
+
+pub use addr::{SocketAddr, AF_INET, AF_INET6, AF_UNIX};
+pub use unix::SocketInode;
+
+use addr::Endpoint;
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::*;
+use spin::Mutex;
+
+use crate::{
+    fs::{file_trait::File, DiskInodeType, FileDescriptor, StatMode},
+    mm::UserBuffer,
+    syscall::errno::*,
+    task::{block_current_and_run_next, current_task, Signals, WaitQueue},
+};
+
+/// Capacity of the receive buffer of a socket, also the largest datagram we accept
+pub const SOCKET_BUFFER_SIZE: usize = 64 * 1024;
+const SOMAXCONN: usize = 4096;
+/// Ports allocated to sockets bound to port 0, same as Linux `ip_local_port_range`
+const EPHEMERAL_PORT_START: u16 = 32768;
+const EPHEMERAL_PORT_END: u16 = 60999;
+
+pub const SOCK_STREAM: u32 = 1;
+pub const SOCK_DGRAM: u32 = 2;
+pub const SOCK_NONBLOCK: u32 = 0o4000;
+pub const SOCK_CLOEXEC: u32 = 0o2000000;
+
+const IPPROTO_IP: u32 = 0;
+const IPPROTO_TCP: u32 = 6;
+const IPPROTO_UDP: u32 = 17;
+const IPPROTO_IPV6: u32 = 41;
+
+pub const SOL_SOCKET: u32 = 1;
+const SO_TYPE: u32 = 3;
+const SO_ERROR: u32 = 4;
+const SO_SNDBUF: u32 = 7;
+const SO_RCVBUF: u32 = 8;
+const SO_ACCEPTCONN: u32 = 30;
+const SO_DOMAIN: u32 = 39;
+const SO_PROTOCOL: u32 = 38;
+
+pub const SHUT_RD: u32 = 0;
+pub const SHUT_WR: u32 = 1;
+pub const SHUT_RDWR: u32 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SocketType {
+    Stream,
+    Datagram,
+}
+
+lazy_static! {
+    /// Bound inet sockets and unix sockets in the abstract namespace,
+    /// unix sockets bound to a path are found through the directory tree.
+    static ref SOCKET_TABLE: Mutex<BTreeMap<(SocketType, Endpoint), Weak<Socket>>> =
+        Mutex::new(BTreeMap::new());
+    static ref NEXT_EPHEMERAL_PORT: Mutex<u16> = Mutex::new(EPHEMERAL_PORT_START);
+}
+
+fn is_path(addr: &SocketAddr) -> bool {
+    match addr {
+        SocketAddr::Unix(path) => !path.starts_with('\0'),
+        _ => false,
+    }
+}
+
+fn lookup_socket(socket_type: SocketType, addr: &SocketAddr) -> Result<Arc<Socket>, isize> {
+    let socket = match addr {
+        SocketAddr::Unix(path) if is_path(addr) => unix::lookup(path)?,
+        _ => match SOCKET_TABLE
+            .lock()
+            .get(&(socket_type, addr.endpoint()))
+            .and_then(|socket| socket.upgrade())
+        {
+            Some(socket) => socket,
+            None => return Err(ECONNREFUSED),
+        },
+    };
+    if socket.socket_type != socket_type {
+        return Err(EPROTOTYPE);
+    }
+    Ok(socket)
+}
+
+/// One direction of a stream connection
+struct StreamBuffer {
+    data: VecDeque<u8>,
+    /// Stream position of the first byte in `data`
+    read_pos: usize,
+    /// Files passed by `SCM_RIGHTS` and the stream position of the data sent with them
+    rights: VecDeque<(usize, Vec<FileDescriptor>)>,
+    /// The writer has shut down or closed, readers see EOF after draining `data`
+    write_closed: bool,
+    /// The reader has shut down or closed, writers get `EPIPE`
+    read_closed: bool,
+    /// Woken when data is sent or received, and when either side shuts down
+    wait_queue: Arc<Mutex<WaitQueue>>,
+}
+
+impl StreamBuffer {
+    fn new() -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self {
+            data: VecDeque::new(),
+            read_pos: 0,
+            rights: VecDeque::new(),
+            write_closed: false,
+            read_closed: false,
+            wait_queue: Arc::new(Mutex::new(WaitQueue::new())),
+        }))
+    }
+}
+
+struct Datagram {
+    src: SocketAddr,
+    data: Vec<u8>,
+    rights: Vec<FileDescriptor>,
+}
+
+/// What `recv_msg()` got
+pub struct Message {
+    /// Bytes copied to the buffer
+    pub len: usize,
+    /// Length of the whole datagram, larger than `len` if it is truncated
+    pub full_len: usize,
+    pub src: Option<SocketAddr>,
+    /// Files passed by `SCM_RIGHTS`
+    pub rights: Vec<FileDescriptor>,
+}
+
+enum SocketState {
+    Unconnected,
+    Listening {
+        backlog: usize,
+        /// Connections established but not accepted yet
+        pending: VecDeque<Arc<Socket>>,
+    },
+    Connected {
+        rx: Arc<Mutex<StreamBuffer>>,
+        tx: Arc<Mutex<StreamBuffer>>,
+    },
+}
+
+struct SocketInner {
+    local: Option<SocketAddr>,
+    peer: Option<SocketAddr>,
+    /// Peer of a connected unix datagram socket, it is resolved by `connect()`
+    /// because the peer of `socketpair()` has no name.
+    peer_socket: Option<Weak<Socket>>,
+    state: SocketState,
+    /// Received datagrams with their source address
+    datagrams: VecDeque<Datagram>,
+    /// Options set by `setsockopt()` that don't change our behavior
+    options: BTreeMap<(u32, u32), u32>,
+}
+
+/// A socket of the in-kernel loopback stack.
+/// Stream sockets are connected by a pair of `StreamBuffer`s,
+/// datagrams are delivered to the receive queue of the bound socket directly.
+pub struct Socket {
+    family: u16,
+    socket_type: SocketType,
+    protocol: u32,
+    /// Used by `read`/`write`, the socket syscalls take it from the `FileDescriptor`
+    nonblock: AtomicBool,
+    selfptr: Mutex<Weak<Self>>,
+    inner: Mutex<SocketInner>,
+    /// Woken when a connection is pending or a datagram arrives,
+    /// connected stream sockets are woken by their `StreamBuffer`s
+    wait_queue: Arc<Mutex<WaitQueue>>,
+}
+
+/// Sleep on `wait_queue` till the peer makes progress, return `Err(ERESTART)` if a signal arrives
+fn wait_for_peer(wait_queue: &Arc<Mutex<WaitQueue>>) -> Result<(), isize> {
+    let task = current_task().unwrap();
+    if task.signal_pending() {
+        return Err(ERESTART);
+    }
+    let waiter = Arc::downgrade(&task);
+    wait_queue.lock().add_task(waiter.clone());
+    drop(task);
+    block_current_and_run_next();
+    wait_queue.lock().remove(&waiter);
+    Ok(())
+}
+
+fn parse_type(family: u16, socket_type: u32, protocol: u32) -> Result<(SocketType, u32), isize> {
+    let socket_type = match socket_type & !(SOCK_NONBLOCK | SOCK_CLOEXEC) {
+        SOCK_STREAM => SocketType::Stream,
+        SOCK_DGRAM => SocketType::Datagram,
+        _ => return Err(ESOCKTNOSUPPORT),
+    };
+    let protocol = match (family, socket_type, protocol) {
+        (AF_INET | AF_INET6, SocketType::Stream, IPPROTO_IP | IPPROTO_TCP) => IPPROTO_TCP,
+        (AF_INET | AF_INET6, SocketType::Datagram, IPPROTO_IP | IPPROTO_UDP) => IPPROTO_UDP,
+        (AF_UNIX, _, 0) => 0,
+        (AF_INET | AF_INET6 | AF_UNIX, _, _) => return Err(EPROTONOSUPPORT),
+        _ => return Err(EAFNOSUPPORT),
+    };
+    Ok((socket_type, protocol))
+}
+
+impl Socket {
+    pub fn new(family: u16, socket_type: u32, protocol: u32) -> Result<Arc<Self>, isize> {
+        let (socket_type, protocol) = parse_type(family, socket_type, protocol)?;
+        Ok(Self::with_state(
+            family,
+            socket_type,
+            protocol,
+            None,
+            None,
+            SocketState::Unconnected,
+        ))
+    }
+    /// Create a pair of connected unix sockets
+    pub fn new_pair(
+        family: u16,
+        socket_type: u32,
+        protocol: u32,
+    ) -> Result<(Arc<Self>, Arc<Self>), isize> {
+        if family != AF_UNIX {
+            return Err(EOPNOTSUPP);
+        }
+        let (socket_type, protocol) = parse_type(family, socket_type, protocol)?;
+        let unnamed = SocketAddr::unspecified(AF_UNIX);
+        let (state0, state1) = match socket_type {
+            SocketType::Stream => {
+                let buffer0 = StreamBuffer::new();
+                let buffer1 = StreamBuffer::new();
+                (
+                    SocketState::Connected {
+                        rx: buffer0.clone(),
+                        tx: buffer1.clone(),
+                    },
+                    SocketState::Connected {
+                        rx: buffer1,
+                        tx: buffer0,
+                    },
+                )
+            }
+            SocketType::Datagram => (SocketState::Unconnected, SocketState::Unconnected),
+        };
+        let socket0 = Self::with_state(
+            family,
+            socket_type,
+            protocol,
+            None,
+            Some(unnamed.clone()),
+            state0,
+        );
+        let socket1 = Self::with_state(family, socket_type, protocol, None, Some(unnamed), state1);
+        if socket_type == SocketType::Datagram {
+            socket0.inner.lock().peer_socket = Some(Arc::downgrade(&socket1));
+            socket1.inner.lock().peer_socket = Some(Arc::downgrade(&socket0));
+        }
+        Ok((socket0, socket1))
+    }
+    fn with_state(
+        family: u16,
+        socket_type: SocketType,
+        protocol: u32,
+        local: Option<SocketAddr>,
+        peer: Option<SocketAddr>,
+        state: SocketState,
+    ) -> Arc<Self> {
+        let socket = Arc::new(Self {
+            family,
+            socket_type,
+            protocol,
+            nonblock: AtomicBool::new(false),
+            selfptr: Mutex::new(Weak::new()),
+            inner: Mutex::new(SocketInner {
+                local,
+                peer,
+                peer_socket: None,
+                state,
+                datagrams: VecDeque::new(),
+                options: BTreeMap::new(),
+            }),
+            wait_queue: Arc::new(Mutex::new(WaitQueue::new())),
+        });
+        *socket.selfptr.lock() = Arc::downgrade(&socket);
+        socket
+    }
+    pub fn socket_type(&self) -> SocketType {
+        self.socket_type
+    }
+    fn check_family(&self, addr: &SocketAddr) -> Result<(), isize> {
+        if addr.family() != self.family {
+            return Err(EAFNOSUPPORT);
+        }
+        Ok(())
+    }
+    /// Insert `self` into `SOCKET_TABLE`, port 0 means any free ephemeral port.
+    /// A unix socket bound to a path creates the socket file instead.
+    fn do_bind(&self, inner: &mut SocketInner, addr: SocketAddr) -> Result<(), isize> {
+        if inner.local.is_some() {
+            return Err(EINVAL);
+        }
+        if !addr.is_local() {
+            return Err(EADDRNOTAVAIL);
+        }
+        if let SocketAddr::Unix(path) = &addr {
+            if is_path(&addr) {
+                unix::bind(path, self.selfptr.lock().clone())?;
+                inner.local = Some(addr);
+                return Ok(());
+            }
+        }
+        let mut table = SOCKET_TABLE.lock();
+        let addr = match addr.port() {
+            Some(0) => {
+                let mut next_port = NEXT_EPHEMERAL_PORT.lock();
+                let range = (EPHEMERAL_PORT_END - EPHEMERAL_PORT_START) as usize + 1;
+                let mut found = None;
+                for _ in 0..range {
+                    let port = *next_port;
+                    *next_port = if port == EPHEMERAL_PORT_END {
+                        EPHEMERAL_PORT_START
+                    } else {
+                        port + 1
+                    };
+                    let candidate = addr.with_port(port);
+                    match table.get(&(self.socket_type, candidate.endpoint())) {
+                        Some(socket) if socket.strong_count() > 0 => continue,
+                        _ => {
+                            found = Some(candidate);
+                            break;
+                        }
+                    }
+                }
+                match found {
+                    Some(addr) => addr,
+                    None => return Err(EADDRINUSE),
+                }
+            }
+            _ => addr,
+        };
+        let key = (self.socket_type, addr.endpoint());
+        // `upgrade()` here may drop the last reference and deadlock in `drop()`
+        if let Some(socket) = table.get(&key) {
+            if socket.strong_count() > 0 {
+                return Err(EADDRINUSE);
+            }
+        }
+        table.insert(key, self.selfptr.lock().clone());
+        inner.local = Some(addr);
+        Ok(())
+    }
+    /// Bind to an ephemeral port on loopback if not bound yet,
+    /// return the local address.
+    fn auto_bind(&self, inner: &mut SocketInner) -> Result<SocketAddr, isize> {
+        if inner.local.is_none() {
+            // unix sockets stay unnamed
+            if self.family == AF_UNIX {
+                return Ok(SocketAddr::unspecified(AF_UNIX));
+            }
+            let addr = SocketAddr::unspecified(self.family).loopback();
+            self.do_bind(inner, addr)?;
+        }
+        Ok(inner.local.clone().unwrap())
+    }
+    pub fn bind(&self, addr: SocketAddr) -> Result<(), isize> {
+        self.check_family(&addr)?;
+        let mut inner = self.inner.lock();
+        self.do_bind(&mut inner, addr)
+    }
+    pub fn listen(&self, backlog: usize) -> Result<(), isize> {
+        if self.socket_type != SocketType::Stream {
+            return Err(EOPNOTSUPP);
+        }
+        let backlog = backlog.max(1).min(SOMAXCONN);
+        let mut inner = self.inner.lock();
+        match &mut inner.state {
+            SocketState::Unconnected => {}
+            SocketState::Listening {
+                backlog: old_backlog,
+                ..
+            } => {
+                *old_backlog = backlog;
+                return Ok(());
+            }
+            SocketState::Connected { .. } => return Err(EINVAL),
+        }
+        self.auto_bind(&mut inner)?;
+        inner.state = SocketState::Listening {
+            backlog,
+            pending: VecDeque::new(),
+        };
+        Ok(())
+    }
+    pub fn accept(&self, nonblock: bool) -> Result<Arc<Socket>, isize> {
+        loop {
+            let mut inner = self.inner.lock();
+            match &mut inner.state {
+                SocketState::Listening { pending, .. } => {
+                    if let Some(socket) = pending.pop_front() {
+                        drop(inner);
+                        // connectors may wait for room in the backlog
+                        self.wait_queue.lock().wake_all();
+                        return Ok(socket);
+                    }
+                }
+                _ => return Err(EINVAL),
+            }
+            drop(inner);
+            if nonblock {
+                return Err(EAGAIN);
+            }
+            wait_for_peer(&self.wait_queue)?;
+        }
+    }
+    pub fn connect(&self, addr: SocketAddr, nonblock: bool) -> Result<(), isize> {
+        self.check_family(&addr)?;
+        if !addr.is_local() {
+            return Err(ENETUNREACH);
+        }
+        // connecting to the wildcard address means loopback
+        let addr = if addr.is_unspecified() {
+            addr.loopback()
+        } else {
+            addr
+        };
+        if self.socket_type == SocketType::Datagram {
+            let peer_socket = if self.family == AF_UNIX {
+                Some(Arc::downgrade(&lookup_socket(SocketType::Datagram, &addr)?))
+            } else {
+                None
+            };
+            let mut inner = self.inner.lock();
+            self.auto_bind(&mut inner)?;
+            inner.peer = Some(addr);
+            inner.peer_socket = peer_socket;
+            return Ok(());
+        }
+        let mut inner = self.inner.lock();
+        match inner.state {
+            SocketState::Unconnected => {}
+            SocketState::Listening { .. } => return Err(EINVAL),
+            SocketState::Connected { .. } => return Err(EISCONN),
+        }
+        let local = self.auto_bind(&mut inner)?;
+        drop(inner);
+
+        let listener = lookup_socket(SocketType::Stream, &addr)?;
+        let client_to_server = StreamBuffer::new();
+        let server_to_client = StreamBuffer::new();
+        let server = Self::with_state(
+            listener.family,
+            SocketType::Stream,
+            listener.protocol,
+            Some(addr.clone()),
+            Some(local),
+            SocketState::Connected {
+                rx: client_to_server.clone(),
+                tx: server_to_client.clone(),
+            },
+        );
+        loop {
+            let mut listener_inner = listener.inner.lock();
+            match &mut listener_inner.state {
+                SocketState::Listening { backlog, pending } => {
+                    if pending.len() < *backlog {
+                        pending.push_back(server);
+                        drop(listener_inner);
+                        listener.wait_queue.lock().wake_all();
+                        break;
+                    }
+                }
+                _ => return Err(ECONNREFUSED),
+            }
+            drop(listener_inner);
+            if nonblock {
+                return Err(EAGAIN);
+            }
+            wait_for_peer(&listener.wait_queue)?;
+        }
+        let mut inner = self.inner.lock();
+        inner.peer = Some(addr);
+        inner.state = SocketState::Connected {
+            rx: server_to_client,
+            tx: client_to_server,
+        };
+        Ok(())
+    }
+    /// Send `buf` to `dest`, or to the peer if `dest` is `None`.
+    /// # Return
+    /// Bytes sent, a stream socket sends at least one byte unless it fails.
+    pub fn send(
+        &self,
+        buf: &[u8],
+        dest: Option<SocketAddr>,
+        nonblock: bool,
+    ) -> Result<usize, isize> {
+        self.send_msg(buf, dest, Vec::new(), nonblock)
+    }
+    /// Like `send()`, and pass `rights` to the receiver
+    pub fn send_msg(
+        &self,
+        buf: &[u8],
+        dest: Option<SocketAddr>,
+        rights: Vec<FileDescriptor>,
+        nonblock: bool,
+    ) -> Result<usize, isize> {
+        if !rights.is_empty() && self.family != AF_UNIX {
+            return Err(EINVAL);
+        }
+        match self.socket_type {
+            SocketType::Stream => self.stream_send(buf, rights, nonblock),
+            SocketType::Datagram => self.datagram_send(buf, dest, rights),
+        }
+    }
+    fn stream_send(
+        &self,
+        buf: &[u8],
+        rights: Vec<FileDescriptor>,
+        nonblock: bool,
+    ) -> Result<usize, isize> {
+        let tx = match &self.inner.lock().state {
+            SocketState::Connected { tx, .. } => tx.clone(),
+            _ => return Err(ENOTCONN),
+        };
+        // files can't be passed without data on a stream
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let mut tx = tx.lock();
+            if tx.write_closed || tx.read_closed {
+                return Err(EPIPE);
+            }
+            let len = (SOCKET_BUFFER_SIZE - tx.data.len()).min(buf.len());
+            if len > 0 {
+                if !rights.is_empty() {
+                    let write_pos = tx.read_pos + tx.data.len();
+                    tx.rights.push_back((write_pos, rights));
+                }
+                tx.data.extend(buf[..len].iter());
+                tx.wait_queue.lock().wake_all();
+                return Ok(len);
+            }
+            let wait_queue = tx.wait_queue.clone();
+            drop(tx);
+            if nonblock {
+                return Err(EAGAIN);
+            }
+            wait_for_peer(&wait_queue)?;
+        }
+    }
+    fn datagram_send(
+        &self,
+        buf: &[u8],
+        dest: Option<SocketAddr>,
+        rights: Vec<FileDescriptor>,
+    ) -> Result<usize, isize> {
+        if buf.len() > SOCKET_BUFFER_SIZE {
+            return Err(EMSGSIZE);
+        }
+        let mut inner = self.inner.lock();
+        let (dest, peer_socket) = match dest {
+            Some(dest) => (dest, None),
+            None => match &inner.peer {
+                Some(peer) => (peer.clone(), inner.peer_socket.clone()),
+                None => return Err(EDESTADDRREQ),
+            },
+        };
+        self.check_family(&dest)?;
+        if !dest.is_local() {
+            return Err(ENETUNREACH);
+        }
+        let src = self.auto_bind(&mut inner)?;
+        drop(inner);
+        let target = match peer_socket {
+            Some(peer_socket) => peer_socket.upgrade().ok_or(ECONNREFUSED),
+            None => lookup_socket(SocketType::Datagram, &dest),
+        };
+        let target = match target {
+            Ok(target) => target,
+            // like UDP, datagrams to nowhere are dropped silently
+            Err(_) if self.family != AF_UNIX => return Ok(buf.len()),
+            Err(errno) => return Err(errno),
+        };
+        let mut target_inner = target.inner.lock();
+        let queued: usize = target_inner
+            .datagrams
+            .iter()
+            .map(|datagram| datagram.data.len())
+            .sum();
+        if queued + buf.len() <= SOCKET_BUFFER_SIZE {
+            target_inner.datagrams.push_back(Datagram {
+                src,
+                data: buf.to_vec(),
+                rights,
+            });
+            drop(target_inner);
+            target.wait_queue.lock().wake_all();
+        } else if self.family == AF_UNIX {
+            // unix datagrams are reliable
+            return Err(EAGAIN);
+        }
+        Ok(buf.len())
+    }
+    /// Receive into `buf`, the data is kept in the socket if `peek` is set.
+    /// # Return
+    /// Bytes received and the address of the sender
+    pub fn recv(
+        &self,
+        buf: &mut [u8],
+        peek: bool,
+        nonblock: bool,
+    ) -> Result<(usize, Option<SocketAddr>), isize> {
+        // files passed to a plain read are closed, like Linux
+        let message = self.recv_msg(buf, peek, nonblock)?;
+        Ok((message.len, message.src))
+    }
+    /// Like `recv()`, and take the files passed along with the data
+    pub fn recv_msg(&self, buf: &mut [u8], peek: bool, nonblock: bool) -> Result<Message, isize> {
+        match self.socket_type {
+            SocketType::Stream => self.stream_recv(buf, peek, nonblock),
+            SocketType::Datagram => self.datagram_recv(buf, peek, nonblock),
+        }
+    }
+    fn stream_recv(&self, buf: &mut [u8], peek: bool, nonblock: bool) -> Result<Message, isize> {
+        let inner = self.inner.lock();
+        let rx = match &inner.state {
+            SocketState::Connected { rx, .. } => rx.clone(),
+            _ => return Err(ENOTCONN),
+        };
+        let src = inner.peer.clone();
+        drop(inner);
+        loop {
+            let mut rx = rx.lock();
+            if !rx.data.is_empty() {
+                let start = rx.read_pos;
+                let mut len = rx.data.len().min(buf.len());
+                // a read never crosses into the data sent with other files
+                if let Some(next) = rx
+                    .rights
+                    .iter()
+                    .map(|(pos, _)| *pos)
+                    .find(|pos| *pos > start)
+                {
+                    len = len.min(next - start);
+                }
+                for (dst, byte) in buf[..len].iter_mut().zip(rx.data.iter()) {
+                    *dst = *byte;
+                }
+                let mut rights = Vec::new();
+                if !peek {
+                    rx.data.drain(..len);
+                    rx.read_pos += len;
+                    if rx.rights.front().map_or(false, |(pos, _)| *pos == start) {
+                        rights = rx.rights.pop_front().unwrap().1;
+                    }
+                    rx.wait_queue.lock().wake_all();
+                }
+                return Ok(Message {
+                    len,
+                    full_len: len,
+                    src,
+                    rights,
+                });
+            }
+            if rx.write_closed || rx.read_closed || buf.is_empty() {
+                return Ok(Message {
+                    len: 0,
+                    full_len: 0,
+                    src,
+                    rights: Vec::new(),
+                });
+            }
+            let wait_queue = rx.wait_queue.clone();
+            drop(rx);
+            if nonblock {
+                return Err(EAGAIN);
+            }
+            wait_for_peer(&wait_queue)?;
+        }
+    }
+    fn datagram_recv(&self, buf: &mut [u8], peek: bool, nonblock: bool) -> Result<Message, isize> {
+        loop {
+            let mut inner = self.inner.lock();
+            let datagram = if peek {
+                inner.datagrams.front().map(|datagram| Datagram {
+                    src: datagram.src.clone(),
+                    data: datagram.data.clone(),
+                    rights: Vec::new(),
+                })
+            } else {
+                inner.datagrams.pop_front()
+            };
+            if let Some(datagram) = datagram {
+                // the rest of a datagram is discarded
+                let len = datagram.data.len().min(buf.len());
+                buf[..len].copy_from_slice(&datagram.data[..len]);
+                return Ok(Message {
+                    len,
+                    full_len: datagram.data.len(),
+                    src: Some(datagram.src),
+                    rights: datagram.rights,
+                });
+            }
+            drop(inner);
+            if nonblock {
+                return Err(EAGAIN);
+            }
+            wait_for_peer(&self.wait_queue)?;
+        }
+    }
+    pub fn shutdown(&self, how: u32) -> Result<(), isize> {
+        if how > SHUT_RDWR {
+            return Err(EINVAL);
+        }
+        let inner = self.inner.lock();
+        match &inner.state {
+            SocketState::Connected { rx, tx } => {
+                if how != SHUT_WR {
+                    let mut rx = rx.lock();
+                    rx.read_closed = true;
+                    rx.wait_queue.lock().wake_all();
+                }
+                if how != SHUT_RD {
+                    let mut tx = tx.lock();
+                    tx.write_closed = true;
+                    tx.wait_queue.lock().wake_all();
+                }
+                Ok(())
+            }
+            _ => Err(ENOTCONN),
+        }
+    }
+    pub fn local_addr(&self) -> SocketAddr {
+        match &self.inner.lock().local {
+            Some(addr) => addr.clone(),
+            None => SocketAddr::unspecified(self.family),
+        }
+    }
+    pub fn peer_addr(&self) -> Result<SocketAddr, isize> {
+        match &self.inner.lock().peer {
+            Some(addr) => Ok(addr.clone()),
+            None => Err(ENOTCONN),
+        }
+    }
+    pub fn set_option(&self, level: u32, name: u32, value: u32) -> Result<(), isize> {
+        match level {
+            SOL_SOCKET | IPPROTO_IP | IPPROTO_TCP | IPPROTO_UDP | IPPROTO_IPV6 => {
+                self.inner.lock().options.insert((level, name), value);
+                Ok(())
+            }
+            _ => Err(ENOPROTOOPT),
+        }
+    }
+    pub fn get_option(&self, level: u32, name: u32) -> Result<u32, isize> {
+        let inner = self.inner.lock();
+        match (level, name) {
+            (SOL_SOCKET, SO_TYPE) => Ok(match self.socket_type {
+                SocketType::Stream => SOCK_STREAM,
+                SocketType::Datagram => SOCK_DGRAM,
+            }),
+            (SOL_SOCKET, SO_ERROR) => Ok(0),
+            (SOL_SOCKET, SO_SNDBUF) | (SOL_SOCKET, SO_RCVBUF) => Ok(SOCKET_BUFFER_SIZE as u32),
+            (SOL_SOCKET, SO_ACCEPTCONN) => {
+                Ok(matches!(inner.state, SocketState::Listening { .. }) as u32)
+            }
+            (SOL_SOCKET, SO_DOMAIN) => Ok(self.family as u32),
+            (SOL_SOCKET, SO_PROTOCOL) => Ok(self.protocol),
+            (SOL_SOCKET, _)
+            | (IPPROTO_IP, _)
+            | (IPPROTO_TCP, _)
+            | (IPPROTO_UDP, _)
+            | (IPPROTO_IPV6, _) => Ok(*inner.options.get(&(level, name)).unwrap_or(&0)),
+            _ => Err(ENOPROTOOPT),
+        }
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        let inner = self.inner.get_mut();
+        if let SocketState::Connected { rx, tx } = &inner.state {
+            let mut rx = rx.lock();
+            rx.read_closed = true;
+            rx.wait_queue.lock().wake_all();
+            let mut tx = tx.lock();
+            tx.write_closed = true;
+            tx.wait_queue.lock().wake_all();
+        }
+        match &inner.local {
+            Some(local) if !is_path(local) => {
+                let mut table = SOCKET_TABLE.lock();
+                let key = (self.socket_type, local.endpoint());
+                // the entry may belong to a newer socket bound to the same address
+                if let Some(socket) = table.get(&key) {
+                    if socket.strong_count() == 0 {
+                        table.remove(&key);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Raise `SIGPIPE` on the current task, like writing to a broken pipe
+pub fn send_sigpipe() {
+    current_task()
+        .unwrap()
+        .acquire_inner_lock()
+        .add_signal(Signals::SIGPIPE);
+}
+
+#[allow(unused)]
+impl File for Socket {
+    fn deep_clone(&self) -> Arc<dyn File> {
+        // a socket is shared by all of its descriptors
+        self.selfptr.lock().upgrade().unwrap()
+    }
+
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        true
+    }
+
+    fn read(&self, offset: Option<&mut usize>, buf: &mut [u8]) -> usize {
+        if offset.is_some() {
+            return ESPIPE as usize;
+        }
+        match self.recv(buf, false, self.nonblock.load(Ordering::Relaxed)) {
+            Ok((len, _)) => len,
+            Err(errno) => errno as usize,
+        }
+    }
+
+    fn write(&self, offset: Option<&mut usize>, buf: &[u8]) -> usize {
+        if offset.is_some() {
+            return ESPIPE as usize;
+        }
+        let result = self.send(buf, None, self.nonblock.load(Ordering::Relaxed));
+        match result {
+            Ok(len) => len,
+            Err(EPIPE) => {
+                send_sigpipe();
+                EPIPE as usize
+            }
+            Err(errno) => errno as usize,
+        }
+    }
+
+    fn r_ready(&self) -> bool {
+        let inner = self.inner.lock();
+        match &inner.state {
+            SocketState::Listening { pending, .. } => !pending.is_empty(),
+            SocketState::Connected { rx, .. } => {
+                let rx = rx.lock();
+                !rx.data.is_empty() || rx.write_closed || rx.read_closed
+            }
+            SocketState::Unconnected => !inner.datagrams.is_empty(),
+        }
+    }
+
+    fn w_ready(&self) -> bool {
+        match &self.inner.lock().state {
+            SocketState::Connected { tx, .. } => {
+                let tx = tx.lock();
+                tx.data.len() < SOCKET_BUFFER_SIZE || tx.read_closed
+            }
+            SocketState::Listening { .. } => false,
+            SocketState::Unconnected => self.socket_type == SocketType::Datagram,
+        }
+    }
+
+    fn read_user(&self, offset: Option<usize>, mut buf: UserBuffer) -> usize {
+        if offset.is_some() {
+            return ESPIPE as usize;
+        }
+        let mut kernel_buf = alloc::vec![0u8; buf.len().min(SOCKET_BUFFER_SIZE)];
+        let nonblock = self.nonblock.load(Ordering::Relaxed);
+        match self.recv(&mut kernel_buf, false, nonblock) {
+            Ok((len, _)) => buf.write(&kernel_buf[..len]),
+            Err(errno) => errno as usize,
+        }
+    }
+
+    fn write_user(&self, offset: Option<usize>, buf: UserBuffer) -> usize {
+        if offset.is_some() {
+            return ESPIPE as usize;
+        }
+        // a stream socket never takes more than its buffer at once
+        if self.socket_type == SocketType::Datagram && buf.len() > SOCKET_BUFFER_SIZE {
+            return EMSGSIZE as usize;
+        }
+        let mut kernel_buf = alloc::vec![0u8; buf.len().min(SOCKET_BUFFER_SIZE)];
+        buf.read(&mut kernel_buf);
+        self.write(None, &kernel_buf)
+    }
+
+    fn get_size(&self) -> usize {
+        0
+    }
+
+    fn get_stat(&self) -> crate::fs::Stat {
+        crate::fs::Stat::new(
+            crate::makedev!(0, 8),
+            1,
+            StatMode::S_IFSOCK.bits() | 0o777,
+            1,
+            0,
+            0,
+            0,
+            0,
+            0,
+        )
+    }
+
+    fn get_file_type(&self) -> DiskInodeType {
+        DiskInodeType::File
+    }
+
+    fn info_dirtree_node(&self, dirnode_ptr: Weak<crate::fs::directory_tree::DirectoryTreeNode>) {}
+
+    fn get_dirtree_node(&self) -> Option<Arc<crate::fs::directory_tree::DirectoryTreeNode>> {
+        None
+    }
+
+    fn open(&self, flags: crate::fs::OpenFlags, special_use: bool) -> Result<Arc<dyn File>, isize> {
+        Ok(self.deep_clone())
+    }
+
+    fn open_subfile(
+        &self,
+    ) -> Result<alloc::vec::Vec<(alloc::string::String, alloc::sync::Arc<dyn File>)>, isize> {
+        Err(ENOTDIR)
+    }
+
+    fn create(&self, name: &str, file_type: DiskInodeType) -> Result<Arc<dyn File>, isize> {
+        Err(ENOTDIR)
+    }
+
+    fn link_child(&self, name: &str, child: &Self) -> Result<(), isize>
+    where
+        Self: Sized,
+    {
+        Err(ENOTDIR)
+    }
+
+    fn unlink(&self, delete: bool) -> Result<(), isize> {
+        Err(ENOTDIR)
+    }
+
+    fn get_dirent(&self, count: usize) -> alloc::vec::Vec<crate::fs::Dirent> {
+        Vec::new()
+    }
+
+    fn lseek(&self, offset: isize, whence: crate::fs::SeekWhence) -> Result<usize, isize> {
+        Err(ESPIPE)
+    }
+
+    fn modify_size(&self, diff: isize) -> Result<(), isize> {
+        Err(EINVAL)
+    }
+
+    fn truncate_size(&self, new_size: usize) -> Result<(), isize> {
+        Err(EINVAL)
+    }
+
+    fn set_timestamp(&self, ctime: Option<usize>, atime: Option<usize>, mtime: Option<usize>) {}
+
+    fn get_single_cache(
+        &self,
+        offset: usize,
+    ) -> Result<Arc<spin::Mutex<crate::fs::PageCache>>, ()> {
+        Err(())
+    }
+
+    fn get_all_caches(
+        &self,
+    ) -> Result<alloc::vec::Vec<Arc<spin::Mutex<crate::fs::PageCache>>>, ()> {
+        Err(())
+    }
+
+    fn oom(&self) -> usize {
+        0
+    }
+
+    fn wait_queues(&self) -> Vec<Arc<Mutex<WaitQueue>>> {
+        let mut wait_queues = alloc::vec![self.wait_queue.clone()];
+        if let SocketState::Connected { rx, tx } = &self.inner.lock().state {
+            wait_queues.push(rx.lock().wait_queue.clone());
+            wait_queues.push(tx.lock().wait_queue.clone());
+        }
+        wait_queues
+    }
+
+    fn hang_up(&self) -> bool {
+        match &self.inner.lock().state {
+            // both directions are closed
+            SocketState::Connected { rx, tx } => {
+                let rx = rx.lock();
+                let tx = tx.lock();
+                (rx.write_closed || rx.read_closed) && (tx.read_closed || tx.write_closed)
+            }
+            _ => false,
+        }
+    }
+
+    fn set_nonblock(&self, nonblock: bool) {
+        self.nonblock.store(nonblock, Ordering::Relaxed);
+    }
+
+    fn fcntl(&self, cmd: u32, arg: u32) -> isize {
+        EINVAL
+    }
+}
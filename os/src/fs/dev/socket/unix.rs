@@ -0,0 +1,189 @@
+use alloc::{
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use spin::Mutex;
+
+use super::Socket;
+use crate::{
+    fs::{
+        directory_tree::DirectoryTreeNode, file_trait::File, DiskInodeType, FileDescriptor,
+        OpenFlags, SeekWhence, Stat, StatMode,
+    },
+    mm::UserBuffer,
+    syscall::errno::*,
+    task::current_task,
+};
+
+/// The file a unix socket is bound to.
+/// Its name is created on the underlying file system so it shows up in the parent directory,
+/// but the socket itself only lives in memory.
+///
+/// `DiskInodeType` has no socket type, so the name is stored as an empty regular file.
+/// Only the cached `DirectoryTreeNode` knows it is a socket: once that node is gone,
+/// e.g. after a reboot, the name is left behind as a plain file that `connect()` refuses
+/// with `ECONNREFUSED` until it is unlinked.
+pub struct SocketInode {
+    file: Arc<dyn File>,
+    socket: Weak<Socket>,
+}
+
+fn working_inode() -> FileDescriptor {
+    current_task()
+        .unwrap()
+        .fs
+        .lock()
+        .working_inode
+        .as_ref()
+        .clone()
+}
+
+/// Create the socket file at `path`
+pub(super) fn bind(path: &str, socket: Weak<Socket>) -> Result<(), isize> {
+    match working_inode().mknod(path, |file| Arc::new(SocketInode { file, socket })) {
+        Ok(_) => Ok(()),
+        Err(EEXIST) => Err(EADDRINUSE),
+        Err(errno) => Err(errno),
+    }
+}
+
+/// Find the socket bound to `path`
+pub(super) fn lookup(path: &str) -> Result<Arc<Socket>, isize> {
+    let file = working_inode().open(path, OpenFlags::O_PATH, false)?.file;
+    match file.downcast_arc::<SocketInode>() {
+        // the file stays after the socket is closed, like Linux
+        Ok(inode) => inode.socket.upgrade().ok_or(ECONNREFUSED),
+        Err(_) => Err(ECONNREFUSED),
+    }
+}
+
+#[allow(unused)]
+impl File for SocketInode {
+    fn deep_clone(&self) -> Arc<dyn File> {
+        Arc::new(Self {
+            file: self.file.deep_clone(),
+            socket: self.socket.clone(),
+        })
+    }
+
+    fn readable(&self) -> bool {
+        false
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+
+    fn read(&self, offset: Option<&mut usize>, buf: &mut [u8]) -> usize {
+        ENXIO as usize
+    }
+
+    fn write(&self, offset: Option<&mut usize>, buf: &[u8]) -> usize {
+        ENXIO as usize
+    }
+
+    fn r_ready(&self) -> bool {
+        false
+    }
+
+    fn w_ready(&self) -> bool {
+        false
+    }
+
+    fn read_user(&self, offset: Option<usize>, buf: UserBuffer) -> usize {
+        ENXIO as usize
+    }
+
+    fn write_user(&self, offset: Option<usize>, buf: UserBuffer) -> usize {
+        ENXIO as usize
+    }
+
+    fn get_size(&self) -> usize {
+        0
+    }
+
+    fn get_stat(&self) -> Stat {
+        let mut stat = self.file.get_stat();
+        stat.set_file_type(StatMode::S_IFSOCK);
+        stat
+    }
+
+    fn get_file_type(&self) -> DiskInodeType {
+        DiskInodeType::File
+    }
+
+    fn info_dirtree_node(&self, dirnode_ptr: Weak<DirectoryTreeNode>) {
+        self.file.info_dirtree_node(dirnode_ptr)
+    }
+
+    fn get_dirtree_node(&self) -> Option<Arc<DirectoryTreeNode>> {
+        self.file.get_dirtree_node()
+    }
+
+    fn open(&self, flags: OpenFlags, special_use: bool) -> Result<Arc<dyn File>, isize> {
+        Ok(Arc::new(Self {
+            file: self.file.open(flags, special_use)?,
+            socket: self.socket.clone(),
+        }))
+    }
+
+    fn open_subfile(&self) -> Result<Vec<(String, Arc<dyn File>)>, isize> {
+        Err(ENOTDIR)
+    }
+
+    fn create(&self, name: &str, file_type: DiskInodeType) -> Result<Arc<dyn File>, isize> {
+        Err(ENOTDIR)
+    }
+
+    fn link_child(&self, name: &str, child: &Self) -> Result<(), isize>
+    where
+        Self: Sized,
+    {
+        Err(ENOTDIR)
+    }
+
+    fn unlink(&self, delete: bool) -> Result<(), isize> {
+        self.file.unlink(delete)
+    }
+
+    fn get_dirent(&self, count: usize) -> Vec<crate::fs::Dirent> {
+        Vec::new()
+    }
+
+    fn lseek(&self, offset: isize, whence: SeekWhence) -> Result<usize, isize> {
+        Err(ESPIPE)
+    }
+
+    fn modify_size(&self, diff: isize) -> Result<(), isize> {
+        Err(EINVAL)
+    }
+
+    fn truncate_size(&self, new_size: usize) -> Result<(), isize> {
+        Err(EINVAL)
+    }
+
+    fn set_timestamp(&self, ctime: Option<usize>, atime: Option<usize>, mtime: Option<usize>) {
+        self.file.set_timestamp(ctime, atime, mtime)
+    }
+
+    fn get_single_cache(&self, offset: usize) -> Result<Arc<Mutex<crate::fs::PageCache>>, ()> {
+        Err(())
+    }
+
+    fn get_all_caches(&self) -> Result<Vec<Arc<Mutex<crate::fs::PageCache>>>, ()> {
+        Err(())
+    }
+
+    fn oom(&self) -> usize {
+        0
+    }
+
+    fn hang_up(&self) -> bool {
+        false
+    }
+
+    fn fcntl(&self, cmd: u32, arg: u32) -> isize {
+        EINVAL
+    }
+}
@@ -0,0 +1,344 @@
+use crate::fs::DiskInodeType;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::*;
+use spin::Mutex;
+
+use crate::{
+    fs::{directory_tree::DirectoryTreeNode, file_trait::File, layout::Stat},
+    mm::UserBuffer,
+    syscall::errno::{EAGAIN, EINVAL, ENOTDIR, ERESTART, ESPIPE},
+    task::{block_current_and_run_next, current_task, posix_timer::ClockId, WaitQueue},
+    timer::{ITimerSpec, TimeSpec, NSEC_PER_SEC},
+};
+
+struct TimerFdInner {
+    /// Next expiration, `None` while disarmed
+    expires: Option<TimeSpec>,
+    interval: TimeSpec,
+    /// Expirations since the last read
+    ticks: u64,
+}
+
+impl TimerFdInner {
+    /// Count the expirations till `now`, and rearm the timer if it's periodic
+    fn update(&mut self, now: TimeSpec) {
+        let expires = match self.expires {
+            Some(expires) if expires <= now => expires,
+            _ => return,
+        };
+        if self.interval.is_zero() {
+            self.expires = None;
+            self.ticks += 1;
+        } else {
+            let interval = self.interval.to_ns();
+            let overrun = (now - expires).to_ns() / interval;
+            self.expires = Some(expires + TimeSpec::from_ns(interval * (overrun + 1)));
+            self.ticks += overrun as u64 + 1;
+        }
+    }
+}
+
+/// A timer made by `timerfd_create()`, reads return the number of expirations and block till there is one.
+/// The timer is checked on every timer interrupt to wake the blocked readers.
+pub struct TimerFd {
+    selfptr: Mutex<Weak<Self>>,
+    /// Wall or monotonic clock
+    clock: ClockId,
+    nonblock: AtomicBool,
+    inner: Mutex<TimerFdInner>,
+    /// Readers blocked until the timer expires
+    wait_queue: Arc<Mutex<WaitQueue>>,
+}
+
+lazy_static! {
+    /// Every timerfd alive, checked by `expire_timerfds()`
+    static ref TIMERFDS: Mutex<Vec<Weak<TimerFd>>> = Mutex::new(Vec::new());
+}
+
+impl TimerFd {
+    pub const TFD_NONBLOCK: u32 = 0o4000;
+    pub const TFD_CLOEXEC: u32 = 0o2000000;
+    pub const TFD_TIMER_ABSTIME: u32 = 1;
+    /// Reads of an absolute wall clock timer would fail with `ECANCELED` when the clock is set,
+    /// accepted but never happens here
+    pub const TFD_TIMER_CANCEL_ON_SET: u32 = 2;
+    pub fn new(clock: ClockId) -> Arc<Self> {
+        let timerfd = Arc::new(Self {
+            selfptr: Mutex::new(Weak::new()),
+            clock,
+            nonblock: AtomicBool::new(false),
+            inner: Mutex::new(TimerFdInner {
+                expires: None,
+                interval: TimeSpec::new(),
+                ticks: 0,
+            }),
+            wait_queue: Arc::new(Mutex::new(WaitQueue::new())),
+        });
+        *timerfd.selfptr.lock() = Arc::downgrade(&timerfd);
+        TIMERFDS.lock().push(Arc::downgrade(&timerfd));
+        timerfd
+    }
+    fn now(&self) -> TimeSpec {
+        // only clocks that never go away are allowed
+        self.clock.now().unwrap()
+    }
+    /// Remaining time till the next expiration and the interval, all zero if disarmed
+    pub fn gettime(&self) -> ITimerSpec {
+        let now = self.now();
+        let mut inner = self.inner.lock();
+        inner.update(now);
+        let mut curr = ITimerSpec::new();
+        curr.it_interval = inner.interval;
+        if let Some(expires) = inner.expires {
+            curr.it_value = expires - now;
+        }
+        curr
+    }
+    /// Arm or disarm the timer and return its old setting, expirations not read yet are discarded
+    pub fn settime(&self, flags: u32, new: ITimerSpec) -> Result<ITimerSpec, isize> {
+        if new.it_value.tv_nsec >= NSEC_PER_SEC || new.it_interval.tv_nsec >= NSEC_PER_SEC {
+            return Err(EINVAL);
+        }
+        let old = self.gettime();
+        let now = self.now();
+        let mut inner = self.inner.lock();
+        inner.ticks = 0;
+        inner.interval = new.it_interval;
+        inner.expires = if new.it_value.is_zero() {
+            None
+        } else if flags & TimerFd::TFD_TIMER_ABSTIME != 0 {
+            Some(new.it_value)
+        } else {
+            Some(now + new.it_value)
+        };
+        Ok(old)
+    }
+    /// Take the expirations, blocking till there is one
+    fn take_ticks(&self) -> Result<u64, isize> {
+        loop {
+            let task = current_task().unwrap();
+            let weak = Arc::downgrade(&task);
+            let now = self.now();
+            self.wait_queue.lock().remove(&weak);
+            let mut inner = self.inner.lock();
+            inner.update(now);
+            if inner.ticks > 0 {
+                return Ok(core::mem::take(&mut inner.ticks));
+            }
+            if self.nonblock.load(Ordering::Relaxed) {
+                return Err(EAGAIN);
+            }
+            drop(inner);
+            if task.signal_pending() {
+                return Err(ERESTART);
+            }
+            self.wait_queue.lock().add_task(weak);
+            drop(task);
+            block_current_and_run_next();
+        }
+    }
+}
+
+/// Called on timer interrupts, wake the readers of every timerfd that has expired
+pub fn expire_timerfds() {
+    let mut timerfds = match TIMERFDS.try_lock() {
+        Some(timerfds) => timerfds,
+        None => return,
+    };
+    let mut expired = Vec::new();
+    timerfds.retain(|timerfd| match timerfd.upgrade() {
+        Some(timerfd) => {
+            let now = timerfd.now();
+            let mut inner = timerfd.inner.lock();
+            inner.update(now);
+            if inner.ticks > 0 {
+                expired.push(timerfd.wait_queue.clone());
+            }
+            true
+        }
+        None => false,
+    });
+    drop(timerfds);
+    // a reader about to block won't sleep, the expirations stay till it reads
+    for wait_queue in expired {
+        wait_queue.lock().wake_all();
+    }
+}
+
+#[allow(unused)]
+impl File for TimerFd {
+    fn deep_clone(&self) -> Arc<dyn File> {
+        self.selfptr.lock().upgrade().unwrap()
+    }
+
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+
+    fn read(&self, offset: Option<&mut usize>, buf: &mut [u8]) -> usize {
+        if offset.is_some() {
+            return ESPIPE as usize;
+        }
+        if buf.len() < 8 {
+            return EINVAL as usize;
+        }
+        match self.take_ticks() {
+            Ok(ticks) => {
+                buf[..8].copy_from_slice(&ticks.to_ne_bytes());
+                8
+            }
+            Err(errno) => errno as usize,
+        }
+    }
+
+    fn write(&self, offset: Option<&mut usize>, buf: &[u8]) -> usize {
+        EINVAL as usize
+    }
+
+    fn r_ready(&self) -> bool {
+        let now = self.now();
+        let mut inner = self.inner.lock();
+        inner.update(now);
+        inner.ticks > 0
+    }
+
+    fn w_ready(&self) -> bool {
+        false
+    }
+
+    fn read_user(&self, offset: Option<usize>, mut buf: UserBuffer) -> usize {
+        if offset.is_some() {
+            return ESPIPE as usize;
+        }
+        if buf.len() < 8 {
+            return EINVAL as usize;
+        }
+        match self.take_ticks() {
+            Ok(ticks) => buf.write(&ticks.to_ne_bytes()),
+            Err(errno) => errno as usize,
+        }
+    }
+
+    fn write_user(&self, offset: Option<usize>, buf: UserBuffer) -> usize {
+        EINVAL as usize
+    }
+
+    fn get_size(&self) -> usize {
+        0
+    }
+
+    fn get_stat(&self) -> Stat {
+        Stat::new(
+            crate::makedev!(0, 13),
+            1,
+            // anonymous inodes have no file type
+            0o600,
+            1,
+            0,
+            0,
+            0,
+            0,
+            0,
+        )
+    }
+
+    fn get_file_type(&self) -> DiskInodeType {
+        DiskInodeType::File
+    }
+
+    fn info_dirtree_node(
+        &self,
+        dirnode_ptr: alloc::sync::Weak<crate::fs::directory_tree::DirectoryTreeNode>,
+    ) {
+    }
+
+    fn get_dirtree_node(&self) -> Option<Arc<DirectoryTreeNode>> {
+        None
+    }
+
+    fn open(
+        &self,
+        flags: crate::fs::layout::OpenFlags,
+        special_use: bool,
+    ) -> Result<Arc<dyn File>, isize> {
+        Ok(self.deep_clone())
+    }
+
+    fn open_subfile(
+        &self,
+    ) -> Result<alloc::vec::Vec<(alloc::string::String, alloc::sync::Arc<dyn File>)>, isize> {
+        Err(ENOTDIR)
+    }
+
+    fn create(&self, name: &str, file_type: DiskInodeType) -> Result<Arc<dyn File>, isize> {
+        Err(ENOTDIR)
+    }
+
+    fn link_child(&self, name: &str, child: &Self) -> Result<(), isize>
+    where
+        Self: Sized,
+    {
+        Err(ENOTDIR)
+    }
+
+    fn unlink(&self, delete: bool) -> Result<(), isize> {
+        Err(ENOTDIR)
+    }
+
+    fn get_dirent(&self, count: usize) -> alloc::vec::Vec<crate::fs::layout::Dirent> {
+        Vec::new()
+    }
+
+    fn lseek(&self, offset: isize, whence: crate::fs::SeekWhence) -> Result<usize, isize> {
+        Err(ESPIPE)
+    }
+
+    fn modify_size(&self, diff: isize) -> Result<(), isize> {
+        Err(EINVAL)
+    }
+
+    fn truncate_size(&self, new_size: usize) -> Result<(), isize> {
+        Err(EINVAL)
+    }
+
+    fn set_timestamp(&self, ctime: Option<usize>, atime: Option<usize>, mtime: Option<usize>) {}
+
+    fn get_single_cache(
+        &self,
+        offset: usize,
+    ) -> Result<Arc<spin::Mutex<crate::fs::PageCache>>, ()> {
+        Err(())
+    }
+
+    fn get_all_caches(
+        &self,
+    ) -> Result<alloc::vec::Vec<Arc<spin::Mutex<crate::fs::PageCache>>>, ()> {
+        Err(())
+    }
+
+    fn oom(&self) -> usize {
+        0
+    }
+
+    fn hang_up(&self) -> bool {
+        false
+    }
+
+    fn wait_queues(&self) -> Vec<Arc<Mutex<WaitQueue>>> {
+        alloc::vec![self.wait_queue.clone()]
+    }
+
+    fn set_nonblock(&self, nonblock: bool) {
+        self.nonblock.store(nonblock, Ordering::Relaxed);
+    }
+
+    fn fcntl(&self, cmd: u32, arg: u32) -> isize {
+        EINVAL
+    }
+}
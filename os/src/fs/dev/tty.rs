@@ -6,9 +6,13 @@ use crate::fs::DiskInodeType;
 use crate::fs::StatMode;
 use crate::mm::{copy_from_user, copy_to_user};
 use crate::mm::{translated_ref, translated_refmut, UserBuffer};
+use crate::random::add_input_randomness;
 use crate::syscall::errno::*;
+use crate::task::signal::{send_signal_to_pgrp, SigInfo, Signals};
+use crate::task::{all_tasks, block_current_and_run_next, current_task, WaitQueue};
 
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use lazy_static::lazy_static;
 use log::{info, warn};
 use num_enum::FromPrimitive;
@@ -40,6 +44,8 @@ impl Default for WinSize {
 
 pub struct TeletypeInner {
     last_char: u8,
+    /// the session this is the controlling terminal of, 0 if none
+    session: usize,
     foreground_pgid: u32,
     winsize: WinSize,
     termios: Termios,
@@ -49,6 +55,7 @@ impl Default for TeletypeInner {
     fn default() -> Self {
         Self {
             last_char: 255,
+            session: 0,
             foreground_pgid: Default::default(),
             winsize: WinSize::default(),
             termios: Termios::default(),
@@ -56,15 +63,108 @@ impl Default for TeletypeInner {
     }
 }
 
+/// Signal process group `pgid` on behalf of the terminal
+fn signal_pgrp(pgid: usize, signal: Signals) {
+    let info = SigInfo::new(signal.to_signum().unwrap(), 0, SigInfo::SI_KERNEL as usize);
+    send_signal_to_pgrp(pgid, info).ok();
+}
+
+impl TeletypeInner {
+    /// Fill `last_char` from the console if it is empty.
+    /// With `ISIG` set, `VINTR`, `VQUIT` and `VSUSP` are consumed here
+    /// and signal the foreground process group instead.
+    fn fill(&mut self) {
+        if self.last_char != 255 {
+            return;
+        }
+        let ch = console_getchar() as u8;
+        if ch == 255 {
+            return;
+        }
+        add_input_randomness(ch as usize);
+        if self.termios.lflag & LocalModes::ISIG.bits() != 0 && ch != 0 {
+            let cc = &self.termios.cc;
+            let signal = if ch == cc[VINTR] {
+                Some(Signals::SIGINT)
+            } else if ch == cc[VQUIT] {
+                Some(Signals::SIGQUIT)
+            } else if ch == cc[VSUSP] {
+                Some(Signals::SIGTSTP)
+            } else {
+                None
+            };
+            if let Some(signal) = signal {
+                let lflag = LocalModes::from_bits_truncate(self.termios.lflag);
+                if lflag.contains(LocalModes::ECHO | LocalModes::ECHOCTL) {
+                    print!("^{}", (ch ^ 0x40) as char);
+                }
+                if self.session != 0 {
+                    signal_pgrp(self.foreground_pgid as usize, signal);
+                }
+                return;
+            }
+        }
+        self.last_char = ch;
+    }
+    /// Whether the current task is in the session of this terminal
+    fn is_controlling(&self) -> bool {
+        self.session != 0 && current_task().unwrap().getsid() == self.session
+    }
+    /// Background process groups of our session get `signal` (`SIGTTIN` or `SIGTTOU`) and stop,
+    /// instead of reading the terminal or changing it.
+    /// # Return Conditions
+    /// Returns `ERESTART` if the process group is signaled,
+    /// `EIO` if it ignores or blocks `SIGTTIN`, while writing with `SIGTTOU` ignored or blocked is allowed.
+    fn check_foreground(&self, signal: Signals) -> Result<(), isize> {
+        let task = current_task().unwrap();
+        let pgid = task.getpgid();
+        if !self.is_controlling() || pgid == self.foreground_pgid as usize {
+            return Ok(());
+        }
+        let blocked = task.acquire_inner_lock().sigmask.contains(signal);
+        let ignored = Signals::ignored(&task.sighand.lock()).contains(signal);
+        if blocked || ignored {
+            return if signal == Signals::SIGTTIN {
+                Err(EIO)
+            } else {
+                Ok(())
+            };
+        }
+        signal_pgrp(pgid, signal);
+        Err(ERESTART)
+    }
+}
+
 #[derive(Default)]
 pub struct Teletype {
     inner: Mutex<TeletypeInner>,
+    /// Woken by `poll()` when there is input, the console doesn't interrupt
+    wait_queue: Arc<Mutex<WaitQueue>>,
 }
 
 impl Teletype {
     pub fn new() -> Self {
         Default::default()
     }
+    /// Become the controlling terminal of session `sid`, with `pgid` in the foreground
+    pub fn set_session(&self, sid: usize, pgid: usize) {
+        let mut inner = self.inner.lock();
+        inner.session = sid;
+        inner.foreground_pgid = pgid as u32;
+    }
+    /// Look at the console while nobody reads, so that ^C reaches a busy foreground job,
+    /// and wake the waiters once there is input
+    pub fn poll(&self) {
+        #[cfg(not(any(feature = "board_k210")))]
+        if let Some(mut inner) = self.inner.try_lock() {
+            inner.fill();
+            let ready = inner.last_char != 255;
+            drop(inner);
+            if ready {
+                self.wait_queue.lock().wake_all();
+            }
+        }
+    }
 }
 
 // TODO: independ of rust sbi
@@ -120,7 +220,7 @@ impl File for Teletype {
             true
         // peek next char
         } else {
-            inner.last_char = console_getchar() as u8;
+            inner.fill();
             inner.last_char != 255
         }
     }
@@ -135,6 +235,9 @@ impl File for Teletype {
             return ESPIPE as usize;
         }
         let mut inner = self.inner.lock();
+        if let Err(errno) = inner.check_foreground(Signals::SIGTTIN) {
+            return errno as usize;
+        }
         // block read here, infallible
         unsafe {
             buf.buffers[0]
@@ -161,7 +264,9 @@ impl File for Teletype {
             return ESPIPE as usize;
         }
         let mut inner = self.inner.lock();
-        // todo: check foreground pgid
+        if let Err(errno) = inner.check_foreground(Signals::SIGTTIN) {
+            return errno as usize;
+        }
         let mut count = 0;
         for ptr in buf {
             loop {
@@ -173,9 +278,20 @@ impl File for Teletype {
                 if count > 0 {
                     return count;
                 }
-                //we read no char, suspend the procedure
-                crate::task::suspend_current_and_run_next;
-                inner.last_char = console_getchar() as u8;
+                //give up if a signal wants to be handled, the read restarts afterwards
+                if current_task().unwrap().signal_pending() {
+                    return ERESTART as usize;
+                }
+                //we read no char, sleep till `poll()` finds some on a timer interrupt
+                drop(inner);
+                let task = current_task().unwrap();
+                let waiter = Arc::downgrade(&task);
+                self.wait_queue.lock().add_task(waiter.clone());
+                drop(task);
+                block_current_and_run_next();
+                self.wait_queue.lock().remove(&waiter);
+                inner = self.inner.lock();
+                inner.fill();
             }
             //we can guarantee last_char isn't a illegal char
             unsafe {
@@ -188,7 +304,8 @@ impl File for Teletype {
                     print!("{}", inner.last_char as char);
                 }
             }
-            inner.last_char = console_getchar() as u8;
+            inner.last_char = 255;
+            inner.fill();
             count += 1;
         }
         count
@@ -198,7 +315,12 @@ impl File for Teletype {
         if offset.is_some() {
             return ESPIPE as usize;
         }
-        let _inner = self.inner.lock();
+        let inner = self.inner.lock();
+        if inner.termios.lflag & LocalModes::TOSTOP.bits() != 0 {
+            if let Err(errno) = inner.check_foreground(Signals::SIGTTOU) {
+                return errno as usize;
+            }
+        }
         for buffer in user_buffer.buffers.iter() {
             match core::str::from_utf8(*buffer) {
                 Ok(content) => print!("{}", content),
@@ -237,11 +359,15 @@ impl File for Teletype {
     }
 
     fn get_dirtree_node(&self) -> Option<Arc<DirectoryTreeNode>> {
-        todo!()
+        None
     }
 
-    fn open(&self, flags: crate::fs::layout::OpenFlags, special_use: bool) -> Arc<dyn File> {
-        TTY.clone()
+    fn open(
+        &self,
+        flags: crate::fs::layout::OpenFlags,
+        special_use: bool,
+    ) -> Result<Arc<dyn File>, isize> {
+        Ok(TTY.clone())
     }
 
     fn open_subfile(
@@ -301,6 +427,10 @@ impl File for Teletype {
         false
     }
 
+    fn wait_queues(&self) -> Vec<Arc<Mutex<WaitQueue>>> {
+        alloc::vec![self.wait_queue.clone()]
+    }
+
     fn ioctl(&self, cmd: u32, argp: usize) -> isize {
         info!(
             "[tty_ioctl] cmd: {:?}, arg: {:X}",
@@ -315,23 +445,91 @@ impl File for Teletype {
                 SUCCESS
             }
             TeletypeCommand::TCSETS | TeletypeCommand::TCSETSW | TeletypeCommand::TCSETSF => {
+                if let Err(errno) = inner.check_foreground(Signals::SIGTTOU) {
+                    return errno;
+                }
                 copy_from_user(token, argp as *const Termios, &mut inner.termios);
                 SUCCESS
             }
-            TeletypeCommand::TIOCGPGRP => match translated_refmut(token, argp as *mut u32) {
-                Ok(word) => {
-                    *word = inner.foreground_pgid;
-                    SUCCESS
+            TeletypeCommand::TIOCGPGRP => {
+                if !inner.is_controlling() {
+                    return ENOTTY;
                 }
-                Err(errno) => errno,
-            },
-            TeletypeCommand::TIOCSPGRP => match translated_ref(token, argp as *const u32) {
-                Ok(word) => {
-                    inner.foreground_pgid = *word;
-                    SUCCESS
+                match translated_refmut(token, argp as *mut u32) {
+                    Ok(word) => {
+                        *word = inner.foreground_pgid;
+                        SUCCESS
+                    }
+                    Err(errno) => errno,
                 }
-                Err(errno) => errno,
-            },
+            }
+            TeletypeCommand::TIOCSPGRP => {
+                if !inner.is_controlling() {
+                    return ENOTTY;
+                }
+                if let Err(errno) = inner.check_foreground(Signals::SIGTTOU) {
+                    return errno;
+                }
+                let pgid = match translated_ref(token, argp as *const i32) {
+                    Ok(pgid) => *pgid,
+                    Err(errno) => return errno,
+                };
+                if pgid < 0 {
+                    return EINVAL;
+                }
+                // the process group must be in our session
+                let session = inner.session;
+                if !all_tasks()
+                    .iter()
+                    .any(|task| task.getpgid() == pgid as usize && task.getsid() == session)
+                {
+                    return EPERM;
+                }
+                inner.foreground_pgid = pgid as u32;
+                SUCCESS
+            }
+            TeletypeCommand::TIOCSCTTY => {
+                let task = current_task().unwrap();
+                let sid = task.getsid();
+                if inner.session == sid {
+                    return SUCCESS;
+                }
+                // only a session leader without one may take a terminal,
+                // stealing it from another session needs `argp == 1` (everyone is root)
+                if sid != task.tgid || (inner.session != 0 && argp != 1) {
+                    return EPERM;
+                }
+                inner.session = sid;
+                inner.foreground_pgid = task.getpgid() as u32;
+                SUCCESS
+            }
+            TeletypeCommand::TIOCNOTTY => {
+                if !inner.is_controlling() {
+                    return ENOTTY;
+                }
+                let task = current_task().unwrap();
+                // only the session leader really gives up the terminal, hanging up the foreground job
+                if task.getsid() == task.tgid {
+                    let pgid = inner.foreground_pgid as usize;
+                    signal_pgrp(pgid, Signals::SIGHUP);
+                    signal_pgrp(pgid, Signals::SIGCONT);
+                    inner.session = 0;
+                    inner.foreground_pgid = 0;
+                }
+                SUCCESS
+            }
+            TeletypeCommand::TIOCGSID => {
+                if !inner.is_controlling() {
+                    return ENOTTY;
+                }
+                match translated_refmut(token, argp as *mut u32) {
+                    Ok(word) => {
+                        *word = inner.session as u32;
+                        SUCCESS
+                    }
+                    Err(errno) => errno,
+                }
+            }
             TeletypeCommand::TIOCGWINSZ => {
                 copy_to_user(token, &inner.winsize, argp as *mut WinSize);
                 SUCCESS
@@ -377,6 +575,12 @@ pub enum TeletypeCommand {
     TIOCGPGRP = 0x540F,
     /// Set the foreground process group ID of this terminal.
     TIOCSPGRP = 0x5410,
+    /// Make the given terminal the controlling terminal of the calling process.
+    TIOCSCTTY = 0x540E,
+    /// Give up the controlling terminal.
+    TIOCNOTTY = 0x5422,
+    /// Get the session ID of the terminal.
+    TIOCGSID = 0x5429,
 
     /// Get window size.
     TIOCGWINSZ = 0x5413,
@@ -399,6 +603,13 @@ pub enum TeletypeCommand {
     ILLEAGAL,
 }
 
+/// Index of `VINTR` in `Termios::cc`
+const VINTR: usize = 0;
+/// Index of `VQUIT` in `Termios::cc`
+const VQUIT: usize = 1;
+/// Index of `VSUSP` in `Termios::cc`
+const VSUSP: usize = 10;
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 /// The termios functions describe a general terminal interface that
@@ -10,18 +10,28 @@ use spin::{Mutex, MutexGuard, RwLock, RwLockWriteGuard};
 
 use super::{
     cache::BlockCacheManager,
-    dev::{null::Null, tty::Teletype, zero::Zero},
+    dev::{null::Null, random::Random, socket::SocketInode, tty::Teletype, zero::Zero},
     file_trait::File,
     filesystem::FileSystem,
-    layout::OpenFlags,
+    layout::{Dirent, OpenFlags, StatMode},
+    proc,
+    tmpfs::{self, TmpfsInode},
     Hwclock,
 };
 use crate::{
-    drivers::BLOCK_DEVICE,
+    drivers::{
+        block::{find_block_device, BlockDevice, ROOT_DEVICE_NAME},
+        BLOCK_DEVICE,
+    },
     fs::{
+        ext4::{self, Ext4Inode},
         fat32::inode::{InodeImpl, OSInode},
         filesystem::FS,
     },
+    task::{
+        all_tasks,
+        cred::{current_cred, Credentials, MAY_EXEC, MAY_READ, MAY_WRITE},
+    },
 };
 
 #[cfg(feature = "oom_handler")]
@@ -33,12 +43,14 @@ lazy_static! {
     pub static ref FILE_SYSTEM: Arc<EasyFileSystem> = EasyFileSystem::open(
         BLOCK_DEVICE.clone(),
         Arc::new(Mutex::new(BlockCacheManager::new()))
-    );
+    )
+    .unwrap();
     pub static ref ROOT: Arc<DirectoryTreeNode> = {
+        let (fs_type, root_file) = open_root();
         let inode = DirectoryTreeNode::new(
             "".to_string(),
-            Arc::new(FileSystem::new(FS::Fat32)),
-            OSInode::new(InodeImpl::root_inode(&FILE_SYSTEM)),
+            Arc::new(FileSystem::new(fs_type)),
+            root_file,
             Weak::new(),
         );
         inode.add_special_use();
@@ -48,6 +60,125 @@ lazy_static! {
         Mutex::new((Vec::new(), 0));
     static ref PATH_CACHE: Mutex<(String, Weak<DirectoryTreeNode>)> =
         Mutex::new(("".to_string(), Weak::new()));
+    static ref MOUNT_TABLE: Mutex<Vec<MountEntry>> = Mutex::new(Vec::new());
+}
+
+struct MountEntry {
+    source: String,
+    /// `None` for pseudo file systems
+    device: Option<Arc<dyn BlockDevice>>,
+    /// Root of the mounted file system
+    root: Arc<DirectoryTreeNode>,
+    /// The node covered by `root`, `None` for the root file system
+    covered: Option<Arc<DirectoryTreeNode>>,
+}
+
+fn same_device(a: &Arc<dyn BlockDevice>, b: &Arc<dyn BlockDevice>) -> bool {
+    Arc::as_ptr(a) as *const u8 == Arc::as_ptr(b) as *const u8
+}
+
+/// Open the file system of type `fs_type` on `source`
+/// # Return
+/// The type and the root directory of the file system, and the block device it lives on
+fn open_filesystem(
+    source: &str,
+    fs_type: &str,
+) -> Result<(FS, Arc<dyn File>, Option<Arc<dyn BlockDevice>>), isize> {
+    match fs_type {
+        "vfat" | "fat32" | "ext4" | "ext3" | "ext2" => {
+            let device = match find_block_device(source) {
+                Some(device) => device,
+                None => return Err(ENOENT),
+            };
+            if MOUNT_TABLE.lock().iter().any(|entry| {
+                entry
+                    .device
+                    .as_ref()
+                    .map_or(false, |mounted| same_device(mounted, &device))
+            }) {
+                return Err(EBUSY);
+            }
+            if fs_type == "vfat" || fs_type == "fat32" {
+                if !EasyFileSystem::probe(&device) {
+                    return Err(EINVAL);
+                }
+                let efs = EasyFileSystem::open(
+                    device.clone(),
+                    Arc::new(Mutex::new(BlockCacheManager::new())),
+                )?;
+                Ok((
+                    FS::Fat32,
+                    OSInode::new(InodeImpl::root_inode(&efs)),
+                    Some(device),
+                ))
+            } else {
+                let size = match ext4::probe(&device) {
+                    Some(size) => size,
+                    None => return Err(EINVAL),
+                };
+                Ok((FS::Ext4, ext4::mount(&device, size)?, Some(device)))
+            }
+        }
+        "proc" => Ok((FS::Proc, proc::root(), None)),
+        "tmpfs" => Ok((FS::Tmpfs, tmpfs::root(), None)),
+        _ => Err(ENODEV),
+    }
+}
+
+/// Open the file system on the boot device: ext4, then FAT32,
+/// and an empty tmpfs if neither can be used so that the kernel still comes up
+fn open_root() -> (FS, Arc<dyn File>) {
+    if let Some(size) = ext4::probe(&BLOCK_DEVICE) {
+        match ext4::mount(&BLOCK_DEVICE, size) {
+            Ok(root_file) => {
+                log::info!("[init_fs] root file system: ext4, size: {:#x}", size);
+                return (FS::Ext4, root_file);
+            }
+            Err(errno) => log::error!("[init_fs] failed to mount ext4 root, errno: {}", errno),
+        }
+    }
+    if EasyFileSystem::probe(&BLOCK_DEVICE) {
+        log::info!("[init_fs] root file system: fat32");
+        return (FS::Fat32, OSInode::new(InodeImpl::root_inode(&FILE_SYSTEM)));
+    }
+    log::error!("[init_fs] no usable root file system, falling back to tmpfs");
+    (FS::Tmpfs, tmpfs::root())
+}
+
+/// Content of `/proc/mounts`
+pub fn proc_mounts() -> String {
+    MOUNT_TABLE
+        .lock()
+        .iter()
+        .map(|entry| {
+            alloc::format!(
+                "{} {} {} {} 0 0\n",
+                entry.source,
+                entry.root.get_cwd(),
+                entry.root.filesystem.fs_type.name(),
+                if entry.root.filesystem.is_read_only() {
+                    "ro"
+                } else {
+                    "rw"
+                }
+            )
+        })
+        .collect()
+}
+
+/// Access asked by `open()` with `flags`, `O_PATH` asks for nothing
+fn open_access(flags: OpenFlags) -> u32 {
+    if flags.contains(OpenFlags::O_PATH) {
+        return 0;
+    }
+    let mut access = 0;
+    if !flags.contains(OpenFlags::O_WRONLY) {
+        access |= MAY_READ;
+    }
+    if flags.intersects(OpenFlags::O_WRONLY | OpenFlags::O_RDWR | OpenFlags::O_TRUNC) {
+        access |= MAY_WRITE;
+    }
+    access
 }
 
 fn insert_directory_vec(inode: Weak<DirectoryTreeNode>) {
@@ -85,6 +216,8 @@ pub struct DirectoryTreeNode {
     selfptr: Mutex<Weak<Self>>,
     father: Mutex<Weak<Self>>,
     children: RwLock<Option<BTreeMap<String, Arc<Self>>>>,
+    /// Root of the file system mounted on this node, it covers this node in path lookup
+    mount: Mutex<Option<Arc<Self>>>,
 }
 
 impl Drop for DirectoryTreeNode {
@@ -108,6 +241,7 @@ impl DirectoryTreeNode {
             selfptr: Mutex::new(Weak::new()),
             father: Mutex::new(father),
             children: RwLock::new(None),
+            mount: Mutex::new(None),
         });
         *node.selfptr.lock() = Arc::downgrade(&node);
         node.file.info_dirtree_node(Arc::downgrade(&node));
@@ -166,7 +300,8 @@ impl DirectoryTreeNode {
         &self,
         lock: &mut RwLockWriteGuard<Option<BTreeMap<String, Arc<Self>>>>,
     ) -> Result<(), isize> {
-        if lock.is_some() {
+        // procfs follows the kernel state, its children are listed again on every lookup
+        if lock.is_some() && !matches!(self.filesystem.fs_type, FS::Proc) {
             return Ok(());
         }
         if !self.file.is_dir() {
@@ -176,15 +311,20 @@ impl DirectoryTreeNode {
             Ok(vec) => vec,
             Err(errno) => return Err(errno),
         };
+        // children still listed keep their nodes, they may be in use as cwd
+        let mut cached = lock.take().unwrap_or_default();
         let mut map = BTreeMap::new();
         for (name, file) in vec {
             let key = name.clone();
-            let value = Self::new(
-                key.clone(),
-                self.filesystem.clone(),
-                file.clone(),
-                Arc::downgrade(&self.get_arc()),
-            );
+            let value = match cached.remove(&key) {
+                Some(node) => node,
+                None => Self::new(
+                    key.clone(),
+                    self.filesystem.clone(),
+                    file.clone(),
+                    Arc::downgrade(&self.get_arc()),
+                ),
+            };
             map.insert(key, value);
         }
         **lock = Some(map);
@@ -200,13 +340,107 @@ impl DirectoryTreeNode {
             Err(errno) => return Err(errno),
         };
         match lock.as_ref().unwrap().get(&name.to_string()) {
-            Some(child) => Ok(child.clone()),
+            Some(child) => Ok(child.follow_mount()),
             None => Err(ENOENT),
         }
     }
+    /// Cross the mount points stacked on this node
+    fn follow_mount(&self) -> Arc<Self> {
+        let mut current_inode = self.get_arc();
+        loop {
+            let mounted = current_inode.mount.lock().clone();
+            match mounted {
+                Some(root) => current_inode = root,
+                None => return current_inode,
+            }
+        }
+    }
+    /// Whether the file system keeps owners and permission bits, the others grant any access
+    fn has_modes(&self) -> bool {
+        matches!(self.filesystem.fs_type, FS::Ext4 | FS::Tmpfs)
+    }
+    /// Check `access`, made of `MAY_READ`, `MAY_WRITE` and `MAY_EXEC`, against `cred`
+    pub fn check_access(&self, cred: &Credentials, access: u32) -> Result<(), isize> {
+        if !self.has_modes() || access == 0 {
+            return Ok(());
+        }
+        // spare the stat of root, which may search any directory
+        if cred.is_privileged() && (access & MAY_EXEC == 0 || self.file.is_dir()) {
+            return Ok(());
+        }
+        let stat = self.file.get_stat();
+        if cred.permission(stat.get_mode(), stat.get_uid(), stat.get_gid(), access) {
+            Ok(())
+        } else {
+            Err(EACCES)
+        }
+    }
+    /// Changes to a file system mounted read-only fail with `EROFS`
+    fn check_writable(&self) -> Result<(), isize> {
+        if self.filesystem.is_read_only() {
+            Err(EROFS)
+        } else {
+            Ok(())
+        }
+    }
+    /// Check `access` against the effective IDs of the current task
+    fn permission(&self, access: u32) -> Result<(), isize> {
+        match current_cred() {
+            Some(cred) => self.check_access(&cred, access),
+            None => Ok(()),
+        }
+    }
+    /// In a directory with the sticky bit,
+    /// only the owner of `child` or of the directory may remove or rename `child`
+    fn check_sticky(&self, child: &Self) -> Result<(), isize> {
+        let cred = match current_cred() {
+            Some(cred) => cred,
+            None => return Ok(()),
+        };
+        if !self.has_modes() || cred.is_privileged() {
+            return Ok(());
+        }
+        let stat = self.file.get_stat();
+        if stat.get_mode() & StatMode::S_ISVTX.bits() == 0
+            || stat.get_uid() == cred.euid
+            || child.file.get_stat().get_uid() == cred.euid
+        {
+            Ok(())
+        } else {
+            Err(EPERM)
+        }
+    }
+    /// Give `file` just created in this directory to the current task.
+    /// The group is the one of the directory if it has the set-group-ID bit,
+    /// which new subdirectories inherit.
+    fn init_owner(&self, file: &Arc<dyn File>) -> Result<(), isize> {
+        let cred = match current_cred() {
+            Some(cred) => cred,
+            None => return Ok(()),
+        };
+        if !self.has_modes() {
+            return Ok(());
+        }
+        let stat = self.file.get_stat();
+        let setgid = stat.get_mode() & StatMode::S_ISGID.bits() != 0;
+        let gid = if setgid { stat.get_gid() } else { cred.egid };
+        if setgid && file.is_dir() {
+            let mode = file.get_stat().get_mode();
+            file.chmod(mode | StatMode::S_ISGID.bits())?;
+        }
+        // new files are owned by root already
+        if cred.euid != 0 || gid != 0 {
+            file.chown(cred.euid, gid)?;
+        }
+        Ok(())
+    }
     pub fn cd_comp(&self, components: &Vec<&str>) -> Result<Arc<Self>, isize> {
         let mut current_inode = self.get_arc();
+        let cred = current_cred();
         for component in components {
+            if let Some(cred) = &cred {
+                current_inode.check_access(cred, MAY_EXEC)?;
+            }
             if *component == ".." {
                 let lock = current_inode.father.lock();
                 let par_inode = lock.upgrade();
@@ -283,8 +517,12 @@ impl DirectoryTreeNode {
             &self
         };
 
+        // the cache skips the search permission checks, only root may take it
+        let privileged = current_cred().map_or(true, |cred| cred.is_privileged());
+        let mut created = false;
         let mut path_cache_lock = PATH_CACHE.lock();
-        let inode = if path.starts_with('/')
+        let inode = if privileged
+            && path.starts_with('/')
             && path == path_cache_lock.0
             && path_cache_lock.1.upgrade().is_some()
         {
@@ -309,10 +547,14 @@ impl DirectoryTreeNode {
                         if !flags.contains(OpenFlags::O_CREAT) {
                             return Err(ENOENT);
                         }
+                        inode.check_writable()?;
+                        inode.permission(MAY_WRITE | MAY_EXEC)?;
                         let new_file = match inode.create(last_comp, DiskInodeType::File) {
                             Ok(file) => file,
                             Err(errno) => return Err(errno),
                         };
+                        inode.init_owner(&new_file)?;
+                        created = true;
                         let key = (*last_comp).to_string();
                         let value = Self::new(
                             key.clone(),
@@ -333,6 +575,18 @@ impl DirectoryTreeNode {
             }
         };
 
+        // a file just created may be opened for any access
+        if !created {
+            inode.permission(open_access(flags))?;
+        }
+
+        // device nodes and sockets may still be written on a read-only file system
+        if inode.file.is_file()
+            && (flags.intersects(OpenFlags::O_WRONLY | OpenFlags::O_RDWR | OpenFlags::O_TRUNC))
+        {
+            inode.check_writable()?;
+        }
+
         if flags.contains(OpenFlags::O_TRUNC) {
             match inode.file.truncate_size(0) {
                 Ok(_) => {}
@@ -357,15 +611,20 @@ impl DirectoryTreeNode {
             return Err(ENOTDIR);
         }
 
-        if special_use {
-            *inode.spe_usage.lock() += 1;
-        }
-
-        if path.starts_with('/') && path != path_cache_lock.0 {
+        if path.starts_with('/')
+            && path != path_cache_lock.0
+            && !matches!(inode.filesystem.fs_type, FS::Proc)
+        {
             *path_cache_lock = (path.to_string(), Arc::downgrade(&inode.get_arc()));
         }
+        // opening a FIFO sleeps till the other end is opened, which takes the path cache too
+        drop(path_cache_lock);
+        let file = inode.file.open(flags, special_use)?;
 
-        Ok(inode.file.open(flags, special_use))
+        if special_use {
+            *inode.spe_usage.lock() += 1;
+        }
+        Ok(file)
     }
 
     pub fn mkdir(&self, path: &str) -> Result<(), isize> {
@@ -389,10 +648,13 @@ impl DirectoryTreeNode {
                     return Err(EEXIST);
                 }
                 Err(ENOENT) => {
+                    inode.check_writable()?;
+                    inode.permission(MAY_WRITE | MAY_EXEC)?;
                     let new_file = match inode.create(last_comp, DiskInodeType::Directory) {
                         Ok(file) => file,
                         Err(errno) => return Err(errno),
                     };
+                    inode.init_owner(&new_file)?;
                     let key = (*last_comp).to_string();
                     let value = Self::new(
                         key.clone(),
@@ -415,6 +677,126 @@ impl DirectoryTreeNode {
         Ok(())
     }
 
+    /// Create a special file whose content lives in memory, e.g. a bound unix socket.
+    /// Disk file systems don't support such files, so an empty regular file is created
+    /// to keep the name and `wrap` turns it into the special file.
+    pub fn mknod(
+        &self,
+        path: &str,
+        wrap: impl FnOnce(Arc<dyn File>) -> Arc<dyn File>,
+    ) -> Result<(), isize> {
+        let inode = if path.starts_with("/") {
+            &**ROOT
+        } else {
+            &self
+        };
+
+        let mut components = Self::parse_dir_path(path);
+        let last_comp = match components.pop() {
+            Some(last_comp) => last_comp,
+            None => return Err(EEXIST),
+        };
+        let inode = inode.cd_comp(&components)?;
+
+        let mut lock = inode.children.write();
+        match inode.try_to_open_subfile(last_comp, &mut lock) {
+            Ok(_) => return Err(EEXIST),
+            Err(ENOENT) => {}
+            Err(errno) => return Err(errno),
+        }
+        inode.check_writable()?;
+        inode.permission(MAY_WRITE | MAY_EXEC)?;
+        let new_file = inode.create(last_comp, DiskInodeType::File)?;
+        inode.init_owner(&new_file)?;
+        let key = last_comp.to_string();
+        // a file system of its own, so that `rename()` and `link()` never see it as a disk file
+        let value = Self::new(
+            key.clone(),
+            Arc::new(FileSystem::new(FS::Null)),
+            wrap(new_file),
+            Arc::downgrade(&inode.get_arc()),
+        );
+        lock.as_mut().unwrap().insert(key, value);
+        Ok(())
+    }
+
+    /// Create a symbolic link at `path` pointing to `target`
+    pub fn symlink(&self, target: &str, path: &str) -> Result<(), isize> {
+        self.create_node(path, |dir, name| dir.symlink(name, target))
+    }
+
+    /// Create a FIFO, a device node or a socket at `path`,
+    /// the file system keeps the file type in `mode` itself, unlike `mknod()`
+    pub fn make_special(&self, path: &str, mode: u32, rdev: u64) -> Result<(), isize> {
+        self.create_node(path, |dir, name| dir.mknod(name, mode, rdev))
+    }
+
+    fn create_node(
+        &self,
+        path: &str,
+        create: impl FnOnce(&Arc<dyn File>, &str) -> Result<Arc<dyn File>, isize>,
+    ) -> Result<(), isize> {
+        let inode = if path.starts_with("/") {
+            &**ROOT
+        } else {
+            &self
+        };
+
+        let mut components = Self::parse_dir_path(path);
+        let last_comp = match components.pop() {
+            Some(last_comp) => last_comp,
+            None => return Err(EEXIST),
+        };
+        let inode = inode.cd_comp(&components)?;
+
+        let mut lock = inode.children.write();
+        match inode.try_to_open_subfile(last_comp, &mut lock) {
+            Ok(_) => return Err(EEXIST),
+            Err(ENOENT) => {}
+            Err(errno) => return Err(errno),
+        }
+        inode.check_writable()?;
+        inode.permission(MAY_WRITE | MAY_EXEC)?;
+        let new_file = create(&inode.file, last_comp)?;
+        inode.init_owner(&new_file)?;
+        let key = last_comp.to_string();
+        let value = Self::new(
+            key.clone(),
+            inode.filesystem.clone(),
+            new_file,
+            Arc::downgrade(&inode.get_arc()),
+        );
+        lock.as_mut().unwrap().insert(key, value);
+        Ok(())
+    }
+
+    /// Disk file systems only report regular files and directories,
+    /// fix the type of special files created by `mknod()` in `dirents`.
+    pub fn fix_special_dirents(&self, dirents: &mut Vec<Dirent>) {
+        const DT_SOCK: u8 = 12;
+        let lock = self.children.read();
+        let children = match lock.as_ref() {
+            Some(children) => children,
+            None => return,
+        };
+        for dirent in dirents.iter_mut() {
+            let len = dirent
+                .d_name
+                .iter()
+                .position(|ch| *ch == 0)
+                .unwrap_or(dirent.d_name.len());
+            let name = match core::str::from_utf8(&dirent.d_name[..len]) {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+            if let Some(child) = children.get(name) {
+                if child.file.is::<SocketInode>() {
+                    dirent.d_type = DT_SOCK;
+                }
+            }
+        }
+    }
+
     pub fn delete(&self, path: &str, delete_directory: bool) -> Result<(), isize> {
         if path.split('/').last().map_or(true, |x| x == ".") {
             return Err(EINVAL);
@@ -447,6 +829,9 @@ impl DirectoryTreeNode {
 
         match inode.father.lock().upgrade() {
             Some(par_inode) => {
+                par_inode.check_writable()?;
+                par_inode.permission(MAY_WRITE | MAY_EXEC)?;
+                par_inode.check_sticky(&inode)?;
                 let mut lock = par_inode.children.write();
                 match inode.file.unlink(true) {
                     Ok(_) => {
@@ -518,6 +903,15 @@ impl DirectoryTreeNode {
         if old_inode.filesystem.fs_id != new_par_inode.filesystem.fs_id {
             return Err(EXDEV);
         }
+        // both parents are on the same file system now
+        old_par_inode.check_writable()?;
+        old_par_inode.permission(MAY_WRITE | MAY_EXEC)?;
+        old_par_inode.check_sticky(&old_inode)?;
+        new_par_inode.permission(MAY_WRITE | MAY_EXEC)?;
+        // ".." of a directory moving to another parent is rewritten
+        if old_inode.file.is_dir() && old_comps != new_comps {
+            old_inode.permission(MAY_WRITE)?;
+        }
         let old_key = old_last_comp.to_string();
         let new_key = new_last_comp.to_string();
         match new_par_inode.try_to_open_subfile(new_last_comp, &mut (*new_lock.lock())) {
@@ -531,8 +925,9 @@ impl DirectoryTreeNode {
                 if *new_inode.spe_usage.lock() > 0 {
                     return Err(EBUSY);
                 }
+                new_par_inode.check_sticky(&new_inode)?;
                 // delete
-                match new_par_inode.file.unlink(true) {
+                match new_inode.file.unlink(true) {
                     Ok(_) => {
                         new_lock.lock().as_mut().unwrap().remove(&new_key);
                     }
@@ -552,9 +947,19 @@ impl DirectoryTreeNode {
             FS::Fat32 => {
                 let old_file = old_inode.file.downcast_ref::<OSInode>().unwrap();
                 let new_par_file = new_par_inode.file.downcast_ref::<OSInode>().unwrap();
-                new_par_file.link_child(old_last_comp, old_file)?;
+                new_par_file.link_child(new_last_comp, old_file)?;
+            }
+            FS::Ext4 => {
+                let old_file = old_inode.file.downcast_ref::<Ext4Inode>().unwrap();
+                let new_par_file = new_par_inode.file.downcast_ref::<Ext4Inode>().unwrap();
+                new_par_file.link_child(new_last_comp, old_file)?;
             }
-            FS::Null => return Err(EACCES),
+            FS::Tmpfs => {
+                let old_file = old_inode.file.downcast_ref::<TmpfsInode>().unwrap();
+                let new_par_file = new_par_inode.file.downcast_ref::<TmpfsInode>().unwrap();
+                new_par_file.link_child(new_last_comp, old_file)?;
+            }
+            FS::Null | FS::Proc => return Err(EACCES),
         }
         *value.father.lock() = Arc::downgrade(&new_par_inode.get_arc());
         new_lock.lock().as_mut().unwrap().insert(new_key, value);
@@ -620,6 +1025,8 @@ impl DirectoryTreeNode {
         if old_inode.filesystem.fs_id != new_par_inode.filesystem.fs_id {
             return Err(EXDEV);
         }
+        new_par_inode.check_writable()?;
+        new_par_inode.permission(MAY_WRITE | MAY_EXEC)?;
     
         // 获取旧文件和新文件的键
         let old_key = old_last_comp.to_string();
@@ -645,26 +1052,166 @@ impl DirectoryTreeNode {
         }
     
         // 根据文件系统类型创建硬链接
-        match old_inode.filesystem.fs_type {
+        let value = old_lock.lock().as_mut().unwrap().get(&old_key).unwrap().clone();
+        let new_file = match old_inode.filesystem.fs_type {
             FS::Fat32 => {
                 let old_file = old_inode.file.downcast_ref::<OSInode>().unwrap();
                 let new_par_file = new_par_inode.file.downcast_ref::<OSInode>().unwrap();
                 new_par_file.link_child(new_last_comp, old_file)?;
+                value.file.deep_clone()
             }
-            FS::Null => return Err(EACCES),
-        }
+            FS::Ext4 => {
+                // ext4 inodes are addressed by path, the new link needs its own
+                let old_file = old_inode.file.downcast_ref::<Ext4Inode>().unwrap();
+                let new_par_file = new_par_inode.file.downcast_ref::<Ext4Inode>().unwrap();
+                new_par_file.link_child(new_last_comp, old_file)?;
+                new_par_file.new_child(new_last_comp, old_file.get_file_type())
+            }
+            FS::Tmpfs => {
+                let old_file = old_inode.file.downcast_ref::<TmpfsInode>().unwrap();
+                let new_par_file = new_par_inode.file.downcast_ref::<TmpfsInode>().unwrap();
+                new_par_file.link_child(new_last_comp, old_file)?;
+                new_par_file.new_child(new_last_comp)?
+            }
+            FS::Null | FS::Proc => return Err(EACCES),
+        };
     
         // 更新目录树节点
-        let value = old_lock.lock().as_mut().unwrap().get(&old_key).unwrap().clone();
         let new_value = DirectoryTreeNode::new(
             new_key.clone(),
             new_par_inode.filesystem.clone(),
-            value.file.deep_clone(),
+            new_file,
             Arc::downgrade(&new_par_inode.get_arc()),
         );
         new_lock.lock().as_mut().unwrap().insert(new_key, new_value);
         Ok(())
     }
+    /// Mount the file system of type `fs_type` on `source` at `target`
+    pub fn mount(
+        &self,
+        source: &str,
+        target: &str,
+        fs_type: &str,
+        read_only: bool,
+    ) -> Result<(), isize> {
+        let target_inode = self.cd_path(target)?;
+        if !target_inode.file.is_dir() {
+            return Err(ENOTDIR);
+        }
+        let father = target_inode.father.lock().clone();
+        // mounting over "/" is not supported
+        if father.upgrade().is_none() {
+            return Err(EBUSY);
+        }
+        let (fs, file, device) = open_filesystem(source, fs_type)?;
+        // the root takes the place of `target_inode` in the tree,
+        // so `get_cwd()` and ".." work without knowing about mount points
+        let filesystem = Arc::new(FileSystem::new(fs));
+        filesystem.set_read_only(read_only);
+        let root = Self::new(target_inode.name.clone(), filesystem, file, father);
+        root.add_special_use();
+        target_inode.add_special_use();
+        *target_inode.mount.lock() = Some(root.clone());
+        MOUNT_TABLE.lock().push(MountEntry {
+            source: source.to_string(),
+            device,
+            root,
+            covered: Some(target_inode),
+        });
+        *PATH_CACHE.lock() = ("".to_string(), Weak::new());
+        Ok(())
+    }
+    /// Change the flags of the file system mounted at `target`
+    pub fn remount(&self, target: &str, read_only: bool) -> Result<(), isize> {
+        let root = self.cd_path(target)?;
+        let mut table = MOUNT_TABLE.lock();
+        match table
+            .iter_mut()
+            .find(|entry| Arc::ptr_eq(&entry.root, &root))
+        {
+            Some(entry) => {
+                entry.root.filesystem.set_read_only(read_only);
+                Ok(())
+            }
+            None => Err(EINVAL),
+        }
+    }
+    /// Unmount the file system mounted at `target`.
+    /// If `detach` is set, a busy file system is removed from the tree anyway,
+    /// the processes using it can still access it through their open files.
+    pub fn umount(&self, target: &str, detach: bool) -> Result<(), isize> {
+        let root = self.cd_path(target)?;
+        let busy = Self::is_busy(&root);
+        let mut table = MOUNT_TABLE.lock();
+        let index = match table
+            .iter()
+            .position(|entry| Arc::ptr_eq(&entry.root, &root))
+        {
+            Some(index) => index,
+            None => return Err(EINVAL),
+        };
+        let covered = match &table[index].covered {
+            Some(covered) => covered.clone(),
+            None => return Err(EBUSY),
+        };
+        if busy && !detach {
+            return Err(EBUSY);
+        }
+        table.remove(index);
+        *covered.mount.lock() = None;
+        covered.sub_special_use();
+        root.sub_special_use();
+        // mounts below a detached file system are unreachable now
+        let mut detached = alloc::vec![root.filesystem.fs_id];
+        while let Some(fs_id) = detached.pop() {
+            table.retain(|entry| {
+                let inside = entry
+                    .covered
+                    .as_ref()
+                    .map_or(false, |covered| covered.filesystem.fs_id == fs_id);
+                if inside {
+                    detached.push(entry.root.filesystem.fs_id);
+                }
+                !inside
+            });
+        }
+        drop(table);
+        // an ext4 file system goes with its last inode, see `ext4::MountRef`
+        *PATH_CACHE.lock() = ("".to_string(), Weak::new());
+        Ok(())
+    }
+    /// A mounted file system is busy if any of its nodes is used as cwd, executed,
+    /// or covered by another mount, except the reference held by the mount itself,
+    /// or if any task has a file of it open.
+    fn is_busy(root: &Arc<Self>) -> bool {
+        let fs_id = root.filesystem.fs_id;
+        let mut lock = DIRECTORY_VEC.lock();
+        update_directory_vec(&mut lock);
+        let inodes: Vec<Arc<Self>> = lock.0.iter().filter_map(|inode| inode.upgrade()).collect();
+        drop(lock);
+        let in_use = inodes.iter().any(|inode| {
+            let usage = *inode.spe_usage.lock();
+            inode.filesystem.fs_id == fs_id && usage > if Arc::ptr_eq(inode, root) { 1 } else { 0 }
+        });
+        in_use
+            || all_tasks().iter().any(|task| {
+                task.files.lock().iter().flatten().any(|fd| {
+                    fd.file
+                        .get_dirtree_node()
+                        .map_or(false, |inode| inode.filesystem.fs_id == fs_id)
+                })
+            })
+    }
+}
+/// Write back the dirty caches of open files for `writeback_tick()`,
+/// files are skipped if someone else holds the locks.
+/// Returns the number of caches looked at.
+pub fn writeback() -> usize {
+    let inodes: Vec<Arc<DirectoryTreeNode>> = match DIRECTORY_VEC.try_lock() {
+        Some(lock) => lock.0.iter().filter_map(|inode| inode.upgrade()).collect(),
+        None => return 0,
+    };
+    inodes.iter().map(|inode| inode.file.writeback()).sum()
 }
 #[cfg(feature = "oom_handler")]
 pub fn oom() -> usize {
@@ -692,6 +1239,15 @@ pub fn oom() -> usize {
 }
 
 pub fn init_fs() {
+    MOUNT_TABLE.lock().push(MountEntry {
+        source: alloc::format!("/dev/{}", ROOT_DEVICE_NAME),
+        device: match ROOT.filesystem.fs_type {
+            FS::Tmpfs => None,
+            _ => Some(BLOCK_DEVICE.clone()),
+        },
+        root: ROOT.clone(),
+        covered: None,
+    });
     init_device_directory();
     init_tmp_directory();
     init_proc_directory();
@@ -726,12 +1282,34 @@ fn init_device_directory() {
         Arc::new(Teletype::new()),
         Arc::downgrade(&dev_inode.get_arc()),
     );
+    let random_dev = DirectoryTreeNode::new(
+        "random".to_string(),
+        Arc::new(FileSystem::new(FS::Null)),
+        Arc::new(Random::new(true)),
+        Arc::downgrade(&dev_inode.get_arc()),
+    );
+    let urandom_dev = DirectoryTreeNode::new(
+        "urandom".to_string(),
+        Arc::new(FileSystem::new(FS::Null)),
+        Arc::new(Random::new(false)),
+        Arc::downgrade(&dev_inode.get_arc()),
+    );
     let mut lock = dev_inode.children.write();
     lock.as_mut().unwrap().insert("null".to_string(), null_dev);
     lock.as_mut().unwrap().insert("zero".to_string(), zero_dev);
     lock.as_mut().unwrap().insert("tty".to_string(), tty_dev);
+    lock.as_mut()
+        .unwrap()
+        .insert("random".to_string(), random_dev);
+    lock.as_mut()
+        .unwrap()
+        .insert("urandom".to_string(), urandom_dev);
     drop(lock);
 
+    if let Err(errno) = dev_inode.mount("tmpfs", "shm", "tmpfs", false) {
+        panic!("failed to mount tmpfs on /dev/shm, errno: {}", errno);
+    }
+
     let misc_inode = match dev_inode.cd_path("./misc") {
         Ok(inode) => inode,
         Err(_) => panic!("misc directory doesn't exist"),
@@ -753,15 +1331,15 @@ fn init_tmp_directory() {
     match ROOT.mkdir("/tmp") {
         _ => {}
     }
+    if let Err(errno) = ROOT.mount("tmpfs", "/tmp", "tmpfs", false) {
+        panic!("failed to mount tmpfs on /tmp, errno: {}", errno);
+    }
 }
 fn init_proc_directory() {
     match ROOT.mkdir("/proc") {
         _ => {}
     }
-    match ROOT.open("/proc/meminfo", OpenFlags::O_CREAT, false) {
-        _ => {}
-    }
-    match ROOT.open("/proc/mounts", OpenFlags::O_CREAT, false) {
-        _ => {}
+    if let Err(errno) = ROOT.mount("proc", "/proc", "proc", false) {
+        panic!("failed to mount procfs, errno: {}", errno);
     }
 }
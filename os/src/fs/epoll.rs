@@ -0,0 +1,589 @@
+use crate::fs::DiskInodeType;
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use lazy_static::*;
+use spin::Mutex;
+
+use crate::{
+    fs::{
+        directory_tree::DirectoryTreeNode,
+        file_trait::File,
+        layout::{Stat, StatMode},
+        FileDescriptor,
+    },
+    mm::{copy_to_user_array, get_from_user, MapPermission, UserBuffer},
+    syscall::errno::*,
+    task::{block_current_and_run_next, current_task, wait_with_timeout, Signals, WaitQueue},
+    timer::TimeSpec,
+};
+
+bitflags! {
+    /// Event types of `struct epoll_event`, the ones shared with `poll()` have the same values.
+    pub struct EpollEvents: u32 {
+        const EPOLLIN = 0x001;
+        const EPOLLPRI = 0x002;
+        const EPOLLOUT = 0x004;
+        /// Always reported, needs not be asked for
+        const EPOLLERR = 0x008;
+        /// Always reported, needs not be asked for
+        const EPOLLHUP = 0x010;
+        const EPOLLRDNORM = 0x040;
+        const EPOLLRDBAND = 0x080;
+        const EPOLLWRNORM = 0x100;
+        const EPOLLWRBAND = 0x200;
+        const EPOLLMSG = 0x400;
+        const EPOLLRDHUP = 0x2000;
+        /// Only one of the epolls waiting on the file is woken, accepted but all are woken here
+        const EPOLLEXCLUSIVE = 1 << 28;
+        /// Keeps the system awake, ignored
+        const EPOLLWAKEUP = 1 << 29;
+        /// Disable the item once it is reported, till it is modified
+        const EPOLLONESHOT = 1 << 30;
+        /// Edge-triggered
+        const EPOLLET = 1 << 31;
+    }
+}
+
+/// `struct epoll_event`, packed only on x86_64
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct EpollEvent {
+    pub events: u32,
+    pub data: u64,
+}
+
+/// A file watched by an epoll
+struct EpItem {
+    /// The item goes away once the file is closed by every descriptor
+    file: Weak<dyn File>,
+    events: EpollEvents,
+    data: u64,
+    /// Wakeups of the file when the item was last reported, `None` if not reported since armed.
+    /// An edge-triggered item is reported again only after the file wakes its waiters.
+    generation: Option<usize>,
+    /// A one-shot item that has been reported
+    disabled: bool,
+}
+
+impl EpItem {
+    /// Sum of the wakeups of every wait queue of the file, changes whenever any of them is woken
+    fn generation(file: &Arc<dyn File>) -> usize {
+        file.wait_queues()
+            .iter()
+            .fold(0, |sum, queue| sum.wrapping_add(queue.lock().generation()))
+    }
+    /// The events asked for that the file has now
+    fn poll(&self, file: &Arc<dyn File>) -> EpollEvents {
+        let mut ready = EpollEvents::empty();
+        if file.r_ready() {
+            ready |= EpollEvents::EPOLLIN | EpollEvents::EPOLLRDNORM;
+        }
+        if file.w_ready() {
+            ready |= EpollEvents::EPOLLOUT | EpollEvents::EPOLLWRNORM;
+        }
+        if file.hang_up() {
+            ready |= EpollEvents::EPOLLHUP;
+        }
+        ready & (self.events | EpollEvents::EPOLLERR | EpollEvents::EPOLLHUP)
+    }
+}
+
+lazy_static! {
+    /// Held while an epoll is added to another, like `epmutex` of Linux,
+    /// so that two epolls can't be added to each other at once and the loop checks can't deadlock
+    static ref NEST_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// A descriptor made by `epoll_create1()`.
+/// There are no callbacks on the files, the waiter sleeps on the wait queues of every file it watches
+/// and rescans all of them when woken.
+/// An edge-triggered item is reported when it is ready and its file has woken the wait queues
+/// since the item was last reported, like a new event on the ready list of Linux.
+pub struct EventPoll {
+    selfptr: Mutex<Weak<Self>>,
+    items: Mutex<BTreeMap<usize, EpItem>>,
+    /// Woken when the items change, for the waiters to watch the new files
+    wait_queue: Arc<Mutex<WaitQueue>>,
+}
+
+impl EventPoll {
+    pub const EPOLL_CLOEXEC: u32 = 0o2000000;
+    pub const EPOLL_CTL_ADD: u32 = 1;
+    pub const EPOLL_CTL_DEL: u32 = 2;
+    pub const EPOLL_CTL_MOD: u32 = 3;
+    /// Deepest nesting of epolls watching epolls
+    const MAX_NESTS: usize = 4;
+    pub fn new() -> Arc<Self> {
+        let epoll = Arc::new(Self {
+            selfptr: Mutex::new(Weak::new()),
+            items: Mutex::new(BTreeMap::new()),
+            wait_queue: Arc::new(Mutex::new(WaitQueue::new())),
+        });
+        *epoll.selfptr.lock() = Arc::downgrade(&epoll);
+        epoll
+    }
+    /// Scan the items for at most `maxevents` events.
+    /// The edge-triggered and one-shot items are updated only if `consume`, as the events are taken by the caller.
+    fn scan(&self, maxevents: usize, consume: bool) -> Vec<EpollEvent> {
+        let mut events = Vec::new();
+        let mut items = self.items.lock();
+        items.retain(|_, item| item.file.strong_count() > 0);
+        for item in items.values_mut() {
+            if events.len() == maxevents {
+                break;
+            }
+            let file = match item.file.upgrade() {
+                Some(file) if !item.disabled => file,
+                _ => continue,
+            };
+            // taken before polling, so that an event after the poll is reported next time
+            let generation = EpItem::generation(&file);
+            let report = item.poll(&file);
+            if report.is_empty() {
+                continue;
+            }
+            if item.events.contains(EpollEvents::EPOLLET) {
+                if item.generation == Some(generation) {
+                    continue;
+                }
+                if consume {
+                    item.generation = Some(generation);
+                }
+            }
+            if consume && item.events.contains(EpollEvents::EPOLLONESHOT) {
+                item.disabled = true;
+            }
+            events.push(EpollEvent {
+                events: report.bits(),
+                data: item.data,
+            });
+        }
+        events
+    }
+    /// Fail with `ELOOP` if `epoll` is reachable from the epolls watched by this one, or they nest too deep
+    fn check_loop(&self, epoll: &EventPoll, depth: usize) -> Result<(), isize> {
+        if core::ptr::eq(self, epoll) {
+            return Err(ELOOP);
+        }
+        if depth >= EventPoll::MAX_NESTS {
+            return Err(ELOOP);
+        }
+        let nested: Vec<Arc<EventPoll>> = self
+            .items
+            .lock()
+            .values()
+            .filter_map(|item| item.file.upgrade())
+            .filter_map(|file| file.downcast_arc::<EventPoll>().ok())
+            .collect();
+        for child in nested {
+            child.check_loop(epoll, depth + 1)?;
+        }
+        Ok(())
+    }
+    pub fn ctl(
+        &self,
+        op: u32,
+        fd: usize,
+        file: Arc<dyn File>,
+        event: Option<EpollEvent>,
+    ) -> Result<(), isize> {
+        // regular files and directories are always ready, Linux refuses them as well
+        let file_type = file.get_stat().get_mode() & StatMode::S_IFMT.bits();
+        if file_type == StatMode::S_IFREG.bits() || file_type == StatMode::S_IFDIR.bits() {
+            return Err(EPERM);
+        }
+        let nested = match op {
+            EventPoll::EPOLL_CTL_ADD | EventPoll::EPOLL_CTL_MOD => {
+                file.clone().downcast_arc::<EventPoll>().ok()
+            }
+            _ => None,
+        };
+        let _nest_guard = nested.as_ref().map(|_| NEST_LOCK.lock());
+        if let Some(epoll) = nested.as_ref() {
+            epoll.check_loop(self, 1)?;
+        }
+        // checked and changed under one lock, so that concurrent adds of an fd can't both succeed
+        let mut items = self.items.lock();
+        // the fd may have been closed and reused since it was added
+        let exists = match items.get(&fd).and_then(|item| item.file.upgrade()) {
+            Some(added) => Arc::as_ptr(&added) as *const u8 == Arc::as_ptr(&file) as *const u8,
+            None => false,
+        };
+        match op {
+            EventPoll::EPOLL_CTL_ADD | EventPoll::EPOLL_CTL_MOD => {
+                let event = event.ok_or(EFAULT)?;
+                let events = EpollEvents::from_bits_truncate(event.events);
+                if op == EventPoll::EPOLL_CTL_ADD && exists {
+                    return Err(EEXIST);
+                }
+                if op == EventPoll::EPOLL_CTL_MOD {
+                    if !exists {
+                        return Err(ENOENT);
+                    }
+                    if events.contains(EpollEvents::EPOLLEXCLUSIVE) {
+                        return Err(EINVAL);
+                    }
+                }
+                if events.contains(EpollEvents::EPOLLEXCLUSIVE)
+                    && events.contains(EpollEvents::EPOLLONESHOT)
+                {
+                    return Err(EINVAL);
+                }
+                items.insert(
+                    fd,
+                    EpItem {
+                        file: Arc::downgrade(&file),
+                        events,
+                        data: event.data,
+                        generation: None,
+                        disabled: false,
+                    },
+                );
+            }
+            EventPoll::EPOLL_CTL_DEL => {
+                if !exists {
+                    return Err(ENOENT);
+                }
+                items.remove(&fd);
+            }
+            _ => return Err(EINVAL),
+        }
+        drop(items);
+        self.wait_queue.lock().wake_all();
+        Ok(())
+    }
+}
+
+/// Get the epoll behind `epfd`
+fn get_epoll(epfd: usize) -> Result<Arc<EventPoll>, isize> {
+    let task = current_task().unwrap();
+    let fd_table = task.files.lock();
+    let file_descriptor = fd_table.get_ref(epfd)?;
+    file_descriptor
+        .file
+        .clone()
+        .downcast_arc::<EventPoll>()
+        .map_err(|_| EINVAL)
+}
+
+pub fn epoll_create1(flags: u32) -> isize {
+    if flags & !EventPoll::EPOLL_CLOEXEC != 0 {
+        return EINVAL;
+    }
+    let task = current_task().unwrap();
+    let mut fd_table = task.files.lock();
+    match fd_table.insert(FileDescriptor::new(
+        flags & EventPoll::EPOLL_CLOEXEC != 0,
+        false,
+        EventPoll::new(),
+    )) {
+        Ok(fd) => fd as isize,
+        Err(errno) => errno,
+    }
+}
+
+pub fn epoll_ctl(epfd: usize, op: u32, fd: usize, event: *const EpollEvent) -> isize {
+    let task = current_task().unwrap();
+    let token = task.get_user_token();
+    let file = match task.files.lock().get_ref(fd) {
+        Ok(file_descriptor) => file_descriptor.file.clone(),
+        Err(errno) => return errno,
+    };
+    drop(task);
+    let epoll = match get_epoll(epfd) {
+        Ok(epoll) => epoll,
+        Err(errno) => return errno,
+    };
+    if epfd == fd {
+        return EINVAL;
+    }
+    let event = if op == EventPoll::EPOLL_CTL_DEL {
+        None
+    } else {
+        match get_from_user(token, event) {
+            Ok(event) => Some(event),
+            Err(errno) => return errno,
+        }
+    };
+    log::info!(
+        "[epoll_ctl] epfd: {}, op: {}, fd: {}, event: {:?}",
+        epfd,
+        op,
+        fd,
+        event
+    );
+    match epoll.ctl(op, fd, file, event) {
+        Ok(()) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+/// Wait for the events of the files in `epfd`, `timeout` in milliseconds, negative to wait forever.
+/// The signal mask is replaced by `sigmask` during the wait if it is given,
+/// and stays replaced for the handler if the wait is interrupted.
+pub fn epoll_pwait(
+    epfd: usize,
+    events: *mut EpollEvent,
+    maxevents: usize,
+    timeout: i32,
+    sigmask: *const Signals,
+    sigsetsize: usize,
+) -> isize {
+    let maxevents = maxevents as i32;
+    if maxevents <= 0 || maxevents as usize > i32::MAX as usize / core::mem::size_of::<EpollEvent>()
+    {
+        return EINVAL;
+    }
+    let maxevents = maxevents as usize;
+    let task = current_task().unwrap();
+    let token = task.get_user_token();
+    if !task.vm.lock().contains_valid_buffer(
+        events as usize,
+        maxevents * core::mem::size_of::<EpollEvent>(),
+        MapPermission::W,
+    ) {
+        return EFAULT;
+    }
+    let epoll = match get_epoll(epfd) {
+        Ok(epoll) => epoll,
+        Err(errno) => return errno,
+    };
+    let sigmask = if sigmask.is_null() {
+        None
+    } else {
+        if sigsetsize != core::mem::size_of::<Signals>() {
+            return EINVAL;
+        }
+        match get_from_user(token, sigmask) {
+            Ok(sigmask) => Some(sigmask - (Signals::SIGKILL | Signals::SIGSTOP)),
+            Err(errno) => return errno,
+        }
+    };
+    let old_sigmask =
+        sigmask.map(|sigmask| core::mem::replace(&mut task.acquire_inner_lock().sigmask, sigmask));
+    let deadline = if timeout < 0 {
+        None
+    } else {
+        Some(TimeSpec::now() + TimeSpec::from_ms(timeout as usize))
+    };
+    let weak = Arc::downgrade(&task);
+    drop(task);
+
+    let mut wait_queues: Vec<Arc<Mutex<WaitQueue>>> = Vec::new();
+    let result = loop {
+        // be queued before the scan, so that a change after the scan wakes us up
+        for wait_queue in epoll.wait_queues() {
+            let mut queue = wait_queue.lock();
+            if !queue.contains(&weak) {
+                queue.add_task(weak.clone());
+            }
+            drop(queue);
+            if !wait_queues
+                .iter()
+                .any(|queued| Arc::ptr_eq(queued, &wait_queue))
+            {
+                wait_queues.push(wait_queue);
+            }
+        }
+        let ready = epoll.scan(maxevents, true);
+        if !ready.is_empty() {
+            break Ok(ready);
+        }
+        if let Some(deadline) = deadline {
+            if TimeSpec::now() >= deadline {
+                break Ok(ready);
+            }
+        }
+        let task = current_task().unwrap();
+        if task.signal_pending() {
+            break Err(EINTR);
+        }
+        if let Some(deadline) = deadline {
+            wait_with_timeout(Arc::downgrade(&task), deadline);
+        }
+        drop(task);
+        block_current_and_run_next();
+    };
+    for wait_queue in wait_queues {
+        wait_queue.lock().remove(&weak);
+    }
+
+    let task = current_task().unwrap();
+    if let Some(old_sigmask) = old_sigmask {
+        let mut inner = task.acquire_inner_lock();
+        if matches!(result, Err(EINTR)) {
+            // `do_signal()` restores it after the handler
+            inner.saved_sigmask = Some(old_sigmask);
+        } else {
+            inner.sigmask = old_sigmask;
+        }
+    }
+    match result {
+        Ok(ready) => {
+            log::trace!("[epoll_pwait] epfd: {}, ready: {:?}", epfd, ready);
+            if !ready.is_empty()
+                && copy_to_user_array(token, ready.as_ptr(), events, ready.len()).is_err()
+            {
+                return EFAULT;
+            }
+            ready.len() as isize
+        }
+        Err(errno) => errno,
+    }
+}
+
+#[allow(unused)]
+impl File for EventPoll {
+    fn deep_clone(&self) -> Arc<dyn File> {
+        // every descriptor shares the same interest list
+        self.selfptr.lock().upgrade().unwrap()
+    }
+
+    fn readable(&self) -> bool {
+        false
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+
+    fn read(&self, offset: Option<&mut usize>, buf: &mut [u8]) -> usize {
+        EINVAL as usize
+    }
+
+    fn write(&self, offset: Option<&mut usize>, buf: &[u8]) -> usize {
+        EINVAL as usize
+    }
+
+    fn r_ready(&self) -> bool {
+        !self.scan(1, false).is_empty()
+    }
+
+    fn w_ready(&self) -> bool {
+        false
+    }
+
+    fn read_user(&self, offset: Option<usize>, buf: UserBuffer) -> usize {
+        EINVAL as usize
+    }
+
+    fn write_user(&self, offset: Option<usize>, buf: UserBuffer) -> usize {
+        EINVAL as usize
+    }
+
+    fn get_size(&self) -> usize {
+        0
+    }
+
+    fn get_stat(&self) -> Stat {
+        Stat::new(
+            crate::makedev!(0, 13),
+            1,
+            // anonymous inodes have no file type
+            0o600,
+            1,
+            0,
+            0,
+            0,
+            0,
+            0,
+        )
+    }
+
+    fn get_file_type(&self) -> DiskInodeType {
+        DiskInodeType::File
+    }
+
+    fn info_dirtree_node(&self, dirnode_ptr: Weak<DirectoryTreeNode>) {}
+
+    fn get_dirtree_node(&self) -> Option<Arc<DirectoryTreeNode>> {
+        None
+    }
+
+    fn open(
+        &self,
+        flags: crate::fs::layout::OpenFlags,
+        special_use: bool,
+    ) -> Result<Arc<dyn File>, isize> {
+        Ok(self.deep_clone())
+    }
+
+    fn open_subfile(
+        &self,
+    ) -> Result<alloc::vec::Vec<(alloc::string::String, alloc::sync::Arc<dyn File>)>, isize> {
+        Err(ENOTDIR)
+    }
+
+    fn create(&self, name: &str, file_type: DiskInodeType) -> Result<Arc<dyn File>, isize> {
+        Err(ENOTDIR)
+    }
+
+    fn link_child(&self, name: &str, child: &Self) -> Result<(), isize>
+    where
+        Self: Sized,
+    {
+        Err(ENOTDIR)
+    }
+
+    fn unlink(&self, delete: bool) -> Result<(), isize> {
+        Err(ENOTDIR)
+    }
+
+    fn get_dirent(&self, count: usize) -> alloc::vec::Vec<crate::fs::layout::Dirent> {
+        Vec::new()
+    }
+
+    fn lseek(&self, offset: isize, whence: crate::fs::SeekWhence) -> Result<usize, isize> {
+        Err(ESPIPE)
+    }
+
+    fn modify_size(&self, diff: isize) -> Result<(), isize> {
+        Err(EINVAL)
+    }
+
+    fn truncate_size(&self, new_size: usize) -> Result<(), isize> {
+        Err(EINVAL)
+    }
+
+    fn set_timestamp(&self, ctime: Option<usize>, atime: Option<usize>, mtime: Option<usize>) {}
+
+    fn get_single_cache(
+        &self,
+        offset: usize,
+    ) -> Result<Arc<spin::Mutex<crate::fs::PageCache>>, ()> {
+        Err(())
+    }
+
+    fn get_all_caches(
+        &self,
+    ) -> Result<alloc::vec::Vec<Arc<spin::Mutex<crate::fs::PageCache>>>, ()> {
+        Err(())
+    }
+
+    fn oom(&self) -> usize {
+        0
+    }
+
+    fn hang_up(&self) -> bool {
+        false
+    }
+
+    fn wait_queues(&self) -> Vec<Arc<Mutex<WaitQueue>>> {
+        let mut wait_queues = alloc::vec![self.wait_queue.clone()];
+        let files: Vec<Arc<dyn File>> = self
+            .items
+            .lock()
+            .values()
+            .filter_map(|item| item.file.upgrade())
+            .collect();
+        for file in files {
+            wait_queues.extend(file.wait_queues());
+        }
+        wait_queues
+    }
+
+    fn fcntl(&self, cmd: u32, arg: u32) -> isize {
+        EINVAL
+    }
+}
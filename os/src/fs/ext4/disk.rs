@@ -0,0 +1,97 @@
+use crate::arch::BLOCK_SZ;
+use crate::drivers::block::BlockDevice;
+use alloc::sync::Arc;
+use lwext4_rust::bindings::{SEEK_CUR, SEEK_END, SEEK_SET};
+use lwext4_rust::KernelDevOp;
+
+/// Byte-addressed view of a `BlockDevice` handed to lwext4.
+/// lwext4 always talks in 512-byte sectors while our block devices may use a larger `BLOCK_SZ`,
+/// so unaligned heads and tails go through a bounce buffer.
+pub struct Ext4Disk {
+    block_device: Arc<dyn BlockDevice>,
+    position: usize,
+    size: usize,
+}
+
+impl Ext4Disk {
+    pub fn new(block_device: Arc<dyn BlockDevice>, size: usize) -> Self {
+        Self {
+            block_device,
+            position: 0,
+            size,
+        }
+    }
+}
+
+impl KernelDevOp for Ext4Disk {
+    type DevType = Ext4Disk;
+
+    fn read(dev: &mut Self::DevType, buf: &mut [u8]) -> Result<usize, i32> {
+        let mut block_buf = [0u8; BLOCK_SZ];
+        let mut read_size = 0;
+        while read_size < buf.len() {
+            let block_id = dev.position / BLOCK_SZ;
+            let offset = dev.position % BLOCK_SZ;
+            let remain = buf.len() - read_size;
+            let len = if offset == 0 && remain >= BLOCK_SZ {
+                // read whole blocks directly into the caller's buffer
+                let len = remain - remain % BLOCK_SZ;
+                dev.block_device
+                    .read_block(block_id, &mut buf[read_size..read_size + len]);
+                len
+            } else {
+                let len = (BLOCK_SZ - offset).min(remain);
+                dev.block_device.read_block(block_id, &mut block_buf);
+                buf[read_size..read_size + len].copy_from_slice(&block_buf[offset..offset + len]);
+                len
+            };
+            read_size += len;
+            dev.position += len;
+        }
+        Ok(read_size)
+    }
+
+    fn write(dev: &mut Self::DevType, buf: &[u8]) -> Result<usize, i32> {
+        let mut block_buf = [0u8; BLOCK_SZ];
+        let mut write_size = 0;
+        while write_size < buf.len() {
+            let block_id = dev.position / BLOCK_SZ;
+            let offset = dev.position % BLOCK_SZ;
+            let remain = buf.len() - write_size;
+            let len = if offset == 0 && remain >= BLOCK_SZ {
+                let len = remain - remain % BLOCK_SZ;
+                dev.block_device
+                    .write_block(block_id, &buf[write_size..write_size + len]);
+                len
+            } else {
+                // read-modify-write for partial blocks
+                let len = (BLOCK_SZ - offset).min(remain);
+                dev.block_device.read_block(block_id, &mut block_buf);
+                block_buf[offset..offset + len].copy_from_slice(&buf[write_size..write_size + len]);
+                dev.block_device.write_block(block_id, &block_buf);
+                len
+            };
+            write_size += len;
+            dev.position += len;
+        }
+        Ok(write_size)
+    }
+
+    fn seek(dev: &mut Self::DevType, off: i64, whence: i32) -> Result<i64, i32> {
+        let new_position = match whence as u32 {
+            SEEK_SET => off,
+            SEEK_CUR => dev.position as i64 + off,
+            SEEK_END => dev.size as i64 + off,
+            _ => return Err(-1),
+        };
+        if new_position < 0 || new_position as usize > dev.size {
+            return Err(-1);
+        }
+        dev.position = new_position as usize;
+        Ok(new_position)
+    }
+
+    fn flush(_dev: &mut Self::DevType) -> Result<usize, i32> {
+        Ok(0)
+    }
+}
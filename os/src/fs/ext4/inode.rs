@@ -0,0 +1,832 @@
+use super::{MountRef, EXT4_FS};
+use crate::config::PAGE_SIZE;
+use crate::fs::cache::{Cache, PageCache};
+use crate::fs::directory_tree::DirectoryTreeNode;
+use crate::fs::file_trait::File;
+use crate::fs::*;
+use crate::mm::UserBuffer;
+use crate::syscall::errno::*;
+use alloc::ffi::CString;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use lwext4_rust::bindings::{
+    ext4_atime_set, ext4_ctime_set, ext4_flink, ext4_inode, ext4_mode_set, ext4_mtime_set,
+    ext4_owner_set, ext4_raw_inode_fill, ext4_readlink, EOK, O_CREAT, O_RDONLY, O_RDWR, O_TRUNC,
+    O_WRONLY, SEEK_SET,
+};
+use lwext4_rust::{Ext4File, InodeTypes};
+use spin::Mutex;
+
+/// Longest symbolic link target we read, `PATH_MAX` of Linux
+const PATH_MAX: usize = 4096;
+
+/// The `S_IF*` bits of a directory entry type, `DT_*` is the same value shifted right by 12
+fn entry_file_type(types: &InodeTypes) -> u32 {
+    match types {
+        InodeTypes::EXT4_DE_DIR => StatMode::S_IFDIR,
+        InodeTypes::EXT4_DE_CHRDEV => StatMode::S_IFCHR,
+        InodeTypes::EXT4_DE_BLKDEV => StatMode::S_IFBLK,
+        InodeTypes::EXT4_DE_FIFO => StatMode::S_IFIFO,
+        InodeTypes::EXT4_DE_SOCK => StatMode::S_IFSOCK,
+        InodeTypes::EXT4_DE_SYMLINK => StatMode::S_IFLNK,
+        _ => StatMode::S_IFREG,
+    }
+    .bits()
+}
+
+/// lwext4 returns positive error codes, and -1 for errors of its own
+fn to_errno(rc: i32) -> isize {
+    if rc > 0 {
+        -(rc as isize)
+    } else {
+        EIO
+    }
+}
+
+/// lwext4 addresses every inode by its absolute path inside the file system,
+/// so each inode remembers its name and parent, and the path is rebuilt on demand.
+/// This keeps the paths of the whole subtree valid after a rename.
+pub struct Ext4InodeInner {
+    name: Mutex<String>,
+    parent: Mutex<Option<Arc<Ext4InodeInner>>>,
+    file_type: DiskInodeType,
+    /// The `S_IF*` bits, `file_type` is `File` for anything but a directory
+    mode_type: u32,
+    /// Set by `unlink(false)`, the inode is waiting for `link_child()` to give it a new name
+    detached: Mutex<bool>,
+    page_cache: Mutex<Vec<Option<Arc<Mutex<PageCache>>>>>,
+    /// Only set on the root
+    _mount: Option<MountRef>,
+}
+
+impl Ext4InodeInner {
+    fn new(name: String, parent: Option<Arc<Self>>, mode_type: u32) -> Arc<Self> {
+        let file_type = if mode_type == StatMode::S_IFDIR.bits() {
+            DiskInodeType::Directory
+        } else {
+            DiskInodeType::File
+        };
+        Arc::new(Self {
+            name: Mutex::new(name),
+            parent: Mutex::new(parent),
+            file_type,
+            mode_type,
+            detached: Mutex::new(false),
+            page_cache: Mutex::new(Vec::new()),
+            _mount: None,
+        })
+    }
+    /// The root is named after its lwext4 mount point without the trailing '/'
+    fn new_root(mount: MountRef) -> Arc<Self> {
+        let mut name = mount.mount_point();
+        name.pop();
+        Arc::new(Self {
+            name: Mutex::new(name),
+            parent: Mutex::new(None),
+            file_type: DiskInodeType::Directory,
+            mode_type: StatMode::S_IFDIR.bits(),
+            detached: Mutex::new(false),
+            page_cache: Mutex::new(Vec::new()),
+            _mount: Some(mount),
+        })
+    }
+    fn path(&self) -> String {
+        let mut comps = Vec::<String>::with_capacity(8);
+        comps.push(self.name.lock().clone());
+        let mut parent = self.parent.lock().clone();
+        while let Some(inner) = parent {
+            comps.push(inner.name.lock().clone());
+            parent = inner.parent.lock().clone();
+        }
+        comps.reverse();
+        if comps.len() == 1 {
+            // lwext4 only recognizes the mount point itself with the trailing '/'
+            format!("{}/", comps[0])
+        } else {
+            comps.join("/")
+        }
+    }
+    fn child_path(&self, name: &str) -> String {
+        let path = self.path();
+        if path.ends_with('/') {
+            format!("{}{}", path, name)
+        } else {
+            format!("{}/{}", path, name)
+        }
+    }
+    fn ext4_file(&self, path: &str) -> Ext4File {
+        let types = match self.file_type {
+            DiskInodeType::Directory => InodeTypes::EXT4_DE_DIR,
+            DiskInodeType::File if self.mode_type == StatMode::S_IFLNK.bits() => {
+                InodeTypes::EXT4_DE_SYMLINK
+            }
+            DiskInodeType::File => InodeTypes::EXT4_DE_REG_FILE,
+        };
+        Ext4File::new(path, types)
+    }
+    fn raw_inode(&self) -> Result<(u32, ext4_inode), isize> {
+        let path = CString::new(self.path()).unwrap();
+        let mut ino = 0u32;
+        let mut inode: ext4_inode = unsafe { core::mem::zeroed() };
+        let _fs = EXT4_FS.lock();
+        let rc = unsafe { ext4_raw_inode_fill(path.as_ptr(), &mut ino, &mut inode) };
+        if rc != EOK as i32 {
+            return Err(to_errno(rc));
+        }
+        Ok((ino, inode))
+    }
+    fn size(&self) -> usize {
+        match self.raw_inode() {
+            Ok((_, inode)) => (inode.size_lo as usize) | ((inode.size_hi as usize) << 32),
+            Err(_) => 0,
+        }
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let len = self.read_disk(offset, buf);
+        self.read_page_cache(offset, &mut buf[..len]);
+        len
+    }
+    /// Read from lwext4, bypassing the page cache
+    fn read_disk(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let path = self.path();
+        let _fs = EXT4_FS.lock();
+        let mut file = self.ext4_file(&path);
+        if file.file_open(&path, O_RDONLY).is_err() {
+            return 0;
+        }
+        let size = file.file_size() as usize;
+        let len = if offset >= size {
+            0
+        } else {
+            let len = buf.len().min(size - offset);
+            match file.file_seek(offset as i64, SEEK_SET) {
+                Ok(_) => file.file_read(&mut buf[..len]).unwrap_or(0),
+                Err(_) => 0,
+            }
+        };
+        file.file_close().ok();
+        len
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        let path = self.path();
+        let fs_lock = EXT4_FS.lock();
+        let mut file = self.ext4_file(&path);
+        if file.file_open(&path, O_RDWR).is_err() {
+            return 0;
+        }
+        // `file_seek()` won't go beyond EOF, fill the hole with zero first
+        let mut size = file.file_size() as usize;
+        if offset > size {
+            let zero = [0u8; 512];
+            file.file_seek(size as i64, SEEK_SET).ok();
+            while size < offset {
+                let len = (offset - size).min(zero.len());
+                match file.file_write(&zero[..len]) {
+                    Ok(written) if written > 0 => size += written,
+                    _ => break,
+                }
+            }
+        }
+        let len = match file.file_seek(offset as i64, SEEK_SET) {
+            Ok(_) if size >= offset => file.file_write(buf).unwrap_or(0),
+            _ => 0,
+        };
+        file.file_close().ok();
+        drop(fs_lock);
+        self.update_page_cache(offset, &buf[..len]);
+        len
+    }
+    fn truncate(&self, new_size: usize) -> Result<(), isize> {
+        let old_size = self.size();
+        if new_size > old_size {
+            // lwext4 can't extend a file by `ftruncate`
+            let zero = [0u8; 512];
+            let mut size = old_size;
+            while size < new_size {
+                let len = (new_size - size).min(zero.len());
+                let written = self.write_at(size, &zero[..len]);
+                if written == 0 {
+                    return Err(ENOSPC);
+                }
+                size += written;
+            }
+            return Ok(());
+        }
+        let path = self.path();
+        let fs_lock = EXT4_FS.lock();
+        let mut file = self.ext4_file(&path);
+        if let Err(rc) = file.file_open(&path, O_RDWR) {
+            return Err(to_errno(rc));
+        }
+        let result = file.file_truncate(new_size as u64);
+        file.file_close().ok();
+        drop(fs_lock);
+        if let Err(rc) = result {
+            return Err(to_errno(rc));
+        }
+        self.shrink_page_cache(new_size);
+        Ok(())
+    }
+    /// Keep cached pages consistent with data written through `write_at()`
+    fn update_page_cache(&self, offset: usize, buf: &[u8]) {
+        if buf.is_empty() {
+            return;
+        }
+        let lock = self.page_cache.lock();
+        let start = offset / PAGE_SIZE;
+        let end = (offset + buf.len() - 1) / PAGE_SIZE;
+        for inner_cache_id in start..=end {
+            if let Some(Some(page_cache)) = lock.get(inner_cache_id) {
+                let page_start = inner_cache_id * PAGE_SIZE;
+                let copy_start = offset.max(page_start);
+                let copy_end = (offset + buf.len()).min(page_start + PAGE_SIZE);
+                page_cache.lock().modify(0, |page: &mut [u8; PAGE_SIZE]| {
+                    page[copy_start - page_start..copy_end - page_start]
+                        .copy_from_slice(&buf[copy_start - offset..copy_end - offset]);
+                });
+            }
+        }
+    }
+    /// Cached pages may hold data written through shared mappings but not synced yet,
+    /// they take precedence over the disk.
+    fn read_page_cache(&self, offset: usize, buf: &mut [u8]) {
+        if buf.is_empty() {
+            return;
+        }
+        let lock = self.page_cache.lock();
+        let start = offset / PAGE_SIZE;
+        let end = (offset + buf.len() - 1) / PAGE_SIZE;
+        for inner_cache_id in start..=end {
+            if let Some(Some(page_cache)) = lock.get(inner_cache_id) {
+                let page_start = inner_cache_id * PAGE_SIZE;
+                let copy_start = offset.max(page_start);
+                let copy_end = (offset + buf.len()).min(page_start + PAGE_SIZE);
+                page_cache.lock().read(0, |page: &[u8; PAGE_SIZE]| {
+                    buf[copy_start - offset..copy_end - offset]
+                        .copy_from_slice(&page[copy_start - page_start..copy_end - page_start]);
+                });
+            }
+        }
+    }
+    /// Write the pages dirtied through shared mappings to the file,
+    /// the part beyond EOF is dropped.
+    fn sync_caches(&self) {
+        let caches: Vec<(usize, Arc<Mutex<PageCache>>)> = self
+            .page_cache
+            .lock()
+            .iter()
+            .enumerate()
+            .filter_map(|(inner_cache_id, page_cache)| {
+                page_cache
+                    .as_ref()
+                    .map(|page_cache| (inner_cache_id, page_cache.clone()))
+            })
+            .collect();
+        let size = self.size();
+        let mut buf = alloc::vec![0u8; PAGE_SIZE];
+        for (inner_cache_id, page_cache) in caches {
+            let page_start = inner_cache_id * PAGE_SIZE;
+            let mut lock = page_cache.lock();
+            if !lock.is_dirty() {
+                continue;
+            }
+            lock.clear_dirty();
+            if page_start >= size {
+                continue;
+            }
+            let len = (size - page_start).min(PAGE_SIZE);
+            lock.read(0, |page: &[u8; PAGE_SIZE]| {
+                buf[..len].copy_from_slice(&page[..len])
+            });
+            // `write_at()` updates the page cache, so it must be unlocked
+            drop(lock);
+            self.write_at(page_start, &buf[..len]);
+        }
+    }
+    fn shrink_page_cache(&self, new_size: usize) {
+        let mut lock = self.page_cache.lock();
+        let pages = (new_size + PAGE_SIZE - 1) / PAGE_SIZE;
+        if lock.len() > pages {
+            lock.truncate(pages);
+        }
+        if new_size % PAGE_SIZE != 0 {
+            if let Some(Some(page_cache)) = lock.get(new_size / PAGE_SIZE) {
+                page_cache.lock().modify(0, |page: &mut [u8; PAGE_SIZE]| {
+                    page[new_size % PAGE_SIZE..].fill(0);
+                });
+            }
+        }
+    }
+    fn get_single_cache(&self, inner_cache_id: usize) -> Arc<Mutex<PageCache>> {
+        crate::mm::frame_reserve(1);
+        let mut lock = self.page_cache.lock();
+        while inner_cache_id >= lock.len() {
+            lock.push(None);
+        }
+        if let Some(page_cache) = &lock[inner_cache_id] {
+            return page_cache.clone();
+        }
+        let mut page_cache = PageCache::new();
+        page_cache.modify(0, |page: &mut [u8; PAGE_SIZE]| {
+            let len = self.read_disk(inner_cache_id * PAGE_SIZE, page);
+            page[len..].fill(0);
+        });
+        let page_cache = Arc::new(Mutex::new(page_cache));
+        lock[inner_cache_id] = Some(page_cache.clone());
+        page_cache
+    }
+    /// Drop the page caches nobody else is using
+    fn oom(&self) -> usize {
+        let mut dropped = 0;
+        for page_cache in self.page_cache.lock().iter_mut() {
+            let droppable = match page_cache {
+                // one in `page_cache`, one returned by `get_tracker()`,
+                // dirty pages are kept until `sync_caches()`
+                Some(inner) => {
+                    let inner_lock = inner.lock();
+                    Arc::strong_count(inner) == 1
+                        && Arc::strong_count(&inner_lock.get_tracker()) == 2
+                        && !inner_lock.is_dirty()
+                }
+                None => false,
+            };
+            if droppable {
+                *page_cache = None;
+                dropped += 1;
+            }
+        }
+        dropped
+    }
+}
+
+pub struct Ext4Inode {
+    readable: bool,
+    writable: bool,
+    /// See `DirectoryTreeNode` for more details
+    special_use: bool,
+    append: bool,
+    inner: Arc<Ext4InodeInner>,
+    offset: Mutex<usize>,
+    dirnode_ptr: Arc<Mutex<Weak<DirectoryTreeNode>>>,
+}
+
+impl Ext4Inode {
+    pub fn root_inode(mount: MountRef) -> Arc<dyn File> {
+        Self::from_inner(Ext4InodeInner::new_root(mount), true)
+    }
+    fn from_inner(inner: Arc<Ext4InodeInner>, special_use: bool) -> Arc<dyn File> {
+        Arc::new(Self {
+            readable: true,
+            writable: true,
+            special_use,
+            append: false,
+            inner,
+            offset: Mutex::new(0),
+            dirnode_ptr: Arc::new(Mutex::new(Weak::new())),
+        })
+    }
+    /// Build the inode of an existing entry `name` in this directory, `mode_type` is its `S_IF*` bits
+    pub fn new_child(&self, name: &str, mode_type: u32) -> Arc<dyn File> {
+        Self::from_inner(
+            Ext4InodeInner::new(name.to_string(), Some(self.inner.clone()), mode_type),
+            false,
+        )
+    }
+    /// Names and `S_IF*` bits of the entries
+    fn dir_entries(&self) -> Result<Vec<(String, u32)>, isize> {
+        if self.inner.file_type != DiskInodeType::Directory {
+            return Err(ENOTDIR);
+        }
+        let path = self.inner.path();
+        let _fs = EXT4_FS.lock();
+        let (names, types) = match self.inner.ext4_file(&path).lwext4_dir_entries() {
+            Ok(entries) => entries,
+            Err(rc) => return Err(to_errno(rc)),
+        };
+        Ok(names
+            .iter()
+            .zip(types.iter())
+            .map(|(name, types)| {
+                // names are NUL-terminated
+                let name = String::from_utf8_lossy(&name[..name.len() - 1]).to_string();
+                (name, entry_file_type(types))
+            })
+            .collect())
+    }
+}
+
+impl Drop for Ext4Inode {
+    fn drop(&mut self) {
+        if self.special_use {
+            let inode = self.get_dirtree_node();
+            match inode {
+                Some(inode) => inode.sub_special_use(),
+                None => {}
+            }
+        }
+    }
+}
+
+#[allow(unused)]
+impl File for Ext4Inode {
+    fn deep_clone(&self) -> Arc<dyn File> {
+        if self.special_use {
+            let inode = self.get_dirtree_node();
+            match inode {
+                Some(inode) => inode.add_special_use(),
+                None => {}
+            }
+        }
+        Arc::new(Self {
+            readable: self.readable,
+            writable: self.writable,
+            special_use: self.special_use,
+            append: self.append,
+            inner: self.inner.clone(),
+            offset: Mutex::new(*self.offset.lock()),
+            dirnode_ptr: self.dirnode_ptr.clone(),
+        })
+    }
+    fn readable(&self) -> bool {
+        self.readable
+    }
+    fn writable(&self) -> bool {
+        self.writable
+    }
+    fn read(&self, offset: Option<&mut usize>, buffer: &mut [u8]) -> usize {
+        match offset {
+            Some(offset) => {
+                let len = self.inner.read_at(*offset, buffer);
+                *offset += len;
+                len
+            }
+            None => {
+                let mut offset = self.offset.lock();
+                let len = self.inner.read_at(*offset, buffer);
+                *offset += len;
+                len
+            }
+        }
+    }
+    fn write(&self, offset: Option<&mut usize>, buffer: &[u8]) -> usize {
+        match offset {
+            Some(offset) => {
+                let len = self.inner.write_at(*offset, buffer);
+                *offset += len;
+                len
+            }
+            None => {
+                let mut offset = self.offset.lock();
+                if self.append {
+                    *offset = self.inner.size();
+                }
+                let len = self.inner.write_at(*offset, buffer);
+                *offset += len;
+                len
+            }
+        }
+    }
+    fn r_ready(&self) -> bool {
+        true
+    }
+    fn w_ready(&self) -> bool {
+        true
+    }
+    fn read_user(&self, offset: Option<usize>, mut buf: UserBuffer) -> usize {
+        let mut total_read_size = 0usize;
+        let mut file_offset = self.offset.lock();
+        let use_file_offset = offset.is_none();
+        let mut offset = match offset {
+            Some(offset) => offset,
+            None => *file_offset,
+        };
+        for slice in buf.buffers.iter_mut() {
+            let read_size = self.inner.read_at(offset, *slice);
+            if read_size == 0 {
+                break;
+            }
+            offset += read_size;
+            total_read_size += read_size;
+        }
+        if use_file_offset {
+            *file_offset = offset;
+        }
+        total_read_size
+    }
+    fn write_user(&self, offset: Option<usize>, buf: UserBuffer) -> usize {
+        let mut total_write_size = 0usize;
+        let mut file_offset = self.offset.lock();
+        let use_file_offset = offset.is_none();
+        let mut offset = match offset {
+            Some(offset) => offset,
+            None if self.append => self.inner.size(),
+            None => *file_offset,
+        };
+        for slice in buf.buffers.iter() {
+            let write_size = self.inner.write_at(offset, *slice);
+            offset += write_size;
+            total_write_size += write_size;
+            if write_size < slice.len() {
+                break;
+            }
+        }
+        if use_file_offset {
+            *file_offset = offset;
+        }
+        total_write_size
+    }
+    fn get_size(&self) -> usize {
+        self.inner.size()
+    }
+    fn get_stat(&self) -> Stat {
+        let (ino, inode) = match self.inner.raw_inode() {
+            Ok(raw) => raw,
+            Err(_) => (0, unsafe { core::mem::zeroed() }),
+        };
+        let size = (inode.size_lo as u64) | ((inode.size_hi as u64) << 32);
+        let st_mod = match inode.mode as u32 & StatMode::S_IFMT.bits() {
+            0 => {
+                self.inner.mode_type
+                    | (StatMode::S_IRWXU | StatMode::S_IRWXG | StatMode::S_IRWXO).bits()
+            }
+            _ => inode.mode as u32,
+        };
+        let mut stat = Stat::new(
+            crate::makedev!(8, 0),
+            ino as u64,
+            st_mod,
+            inode.links_count as u32,
+            0,
+            size as i64,
+            inode.access_time as i64,
+            inode.modification_time as i64,
+            inode.change_inode_time as i64,
+        );
+        // the high halves of the IDs live in the Linux specific part
+        let (uid_high, gid_high) =
+            unsafe { (inode.osd2.linux2.uid_high, inode.osd2.linux2.gid_high) };
+        stat.set_owner(
+            inode.uid as u32 | (uid_high as u32) << 16,
+            inode.gid as u32 | (gid_high as u32) << 16,
+        );
+        stat
+    }
+    fn get_file_type(&self) -> DiskInodeType {
+        self.inner.file_type
+    }
+
+    fn info_dirtree_node(&self, dirnode_ptr: Weak<DirectoryTreeNode>) {
+        *self.dirnode_ptr.lock() = dirnode_ptr;
+    }
+
+    fn get_dirtree_node(&self) -> Option<Arc<DirectoryTreeNode>> {
+        self.dirnode_ptr.lock().upgrade()
+    }
+
+    fn open(&self, flags: OpenFlags, special_use: bool) -> Result<Arc<dyn File>, isize> {
+        Ok(Arc::new(Self {
+            readable: flags.contains(OpenFlags::O_RDONLY) || flags.contains(OpenFlags::O_RDWR),
+            writable: flags.contains(OpenFlags::O_WRONLY) || flags.contains(OpenFlags::O_RDWR),
+            special_use,
+            append: flags.contains(OpenFlags::O_APPEND),
+            inner: self.inner.clone(),
+            offset: Mutex::new(0),
+            dirnode_ptr: self.dirnode_ptr.clone(),
+        }))
+    }
+    fn open_subfile(&self) -> Result<Vec<(String, Arc<dyn File>)>, isize> {
+        Ok(self
+            .dir_entries()?
+            .into_iter()
+            .filter(|(name, _)| name != "." && name != "..")
+            .map(|(name, mode_type)| {
+                let file = self.new_child(&name, mode_type);
+                (name, file)
+            })
+            .collect())
+    }
+    fn create(&self, name: &str, file_type: DiskInodeType) -> Result<Arc<dyn File>, isize> {
+        let path = self.inner.child_path(name);
+        let _fs = EXT4_FS.lock();
+        let mut file = self.inner.ext4_file(&path);
+        let result = match file_type {
+            DiskInodeType::Directory => file.dir_mk(&path),
+            DiskInodeType::File => file
+                .file_open(&path, O_WRONLY | O_CREAT | O_TRUNC)
+                .and_then(|_| file.file_close()),
+        };
+        if let Err(rc) = result {
+            return Err(to_errno(rc));
+        }
+        let mode_type = match file_type {
+            DiskInodeType::Directory => StatMode::S_IFDIR,
+            DiskInodeType::File => StatMode::S_IFREG,
+        };
+        Ok(self.new_child(name, mode_type.bits()))
+    }
+    /// If `child` was detached by `unlink(false)`, it is moved here (rename),
+    /// otherwise a hard link to it is created.
+    fn link_child(&self, name: &str, child: &Self) -> Result<(), isize>
+    where
+        Self: Sized,
+    {
+        let old_path = child.inner.path();
+        let new_path = self.inner.child_path(name);
+        let mut detached = child.inner.detached.lock();
+        let fs_lock = EXT4_FS.lock();
+        let mut file = child.inner.ext4_file(&old_path);
+        if *detached {
+            let result = match child.inner.file_type {
+                DiskInodeType::Directory => file.dir_mv(&old_path, &new_path),
+                DiskInodeType::File => file.file_rename(&old_path, &new_path),
+            };
+            if let Err(rc) = result {
+                return Err(to_errno(rc));
+            }
+            drop(fs_lock);
+            *child.inner.name.lock() = name.to_string();
+            *child.inner.parent.lock() = Some(self.inner.clone());
+            *detached = false;
+        } else {
+            if child.inner.file_type == DiskInodeType::Directory {
+                return Err(EPERM);
+            }
+            let old_path = CString::new(old_path).unwrap();
+            let new_path = CString::new(new_path).unwrap();
+            let rc = unsafe { ext4_flink(old_path.as_ptr(), new_path.as_ptr()) };
+            if rc != EOK as i32 {
+                return Err(to_errno(rc));
+            }
+        }
+        Ok(())
+    }
+    fn unlink(&self, delete: bool) -> Result<(), isize> {
+        if !delete {
+            // the directory entry is kept until `link_child()` moves it
+            *self.inner.detached.lock() = true;
+            return Ok(());
+        }
+        let path = self.inner.path();
+        let result = match self.inner.file_type {
+            DiskInodeType::Directory => {
+                // `dir_rm()` removes recursively
+                if self
+                    .dir_entries()?
+                    .iter()
+                    .any(|(name, _)| name != "." && name != "..")
+                {
+                    return Err(ENOTEMPTY);
+                }
+                let _fs = EXT4_FS.lock();
+                self.inner.ext4_file(&path).dir_rm(&path)
+            }
+            DiskInodeType::File => {
+                let _fs = EXT4_FS.lock();
+                self.inner.ext4_file(&path).file_remove(&path)
+            }
+        };
+        match result {
+            Ok(_) => {
+                self.inner.page_cache.lock().clear();
+                Ok(())
+            }
+            Err(rc) => Err(to_errno(rc)),
+        }
+    }
+    fn get_dirent(&self, count: usize) -> Vec<Dirent> {
+        assert!(self.is_dir());
+        let mut offset = self.offset.lock();
+        let entries = match self.dir_entries() {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        log::debug!(
+            "[get_dirent] tot entries: {}, offset: {}, count: {}",
+            entries.len(),
+            offset,
+            count
+        );
+        let vec: Vec<Dirent> = entries
+            .iter()
+            .enumerate()
+            .skip(*offset)
+            .take(count / core::mem::size_of::<Dirent>())
+            .map(|(index, (name, mode_type))| {
+                let child = Ext4InodeInner::new(name.clone(), Some(self.inner.clone()), *mode_type);
+                let ino = match name.as_str() {
+                    "." | ".." => 0,
+                    _ => child.raw_inode().map_or(0, |(ino, _)| ino as usize),
+                };
+                let d_type = (mode_type >> 12) as u8;
+                Dirent::new(ino, (index + 1) as isize, d_type, name.as_str())
+            })
+            .collect();
+        *offset += vec.len();
+        vec
+    }
+    fn is_append(&self) -> bool {
+        self.append
+    }
+    fn lseek(&self, offset: isize, whence: SeekWhence) -> Result<usize, isize> {
+        let new_offset = match whence {
+            SeekWhence::SEEK_SET => offset,
+            SeekWhence::SEEK_CUR => *self.offset.lock() as isize + offset,
+            SeekWhence::SEEK_END => self.inner.size() as isize + offset,
+            // whence is duplicated
+            _ => return Err(EINVAL),
+        };
+        let new_offset = match new_offset < 0 {
+            true => return Err(EINVAL),
+            false => new_offset as usize,
+        };
+        *self.offset.lock() = new_offset;
+        Ok(new_offset)
+    }
+    fn modify_size(&self, diff: isize) -> Result<(), isize> {
+        let new_size = self.inner.size() as isize + diff;
+        if new_size < 0 {
+            return Err(EINVAL);
+        }
+        self.inner.truncate(new_size as usize)
+    }
+    fn truncate_size(&self, new_size: usize) -> Result<(), isize> {
+        self.inner.truncate(new_size)
+    }
+    fn set_timestamp(&self, ctime: Option<usize>, atime: Option<usize>, mtime: Option<usize>) {
+        let path = CString::new(self.inner.path()).unwrap();
+        let _fs = EXT4_FS.lock();
+        unsafe {
+            if let Some(ctime) = ctime {
+                ext4_ctime_set(path.as_ptr(), ctime as u32);
+            }
+            if let Some(atime) = atime {
+                ext4_atime_set(path.as_ptr(), atime as u32);
+            }
+            if let Some(mtime) = mtime {
+                ext4_mtime_set(path.as_ptr(), mtime as u32);
+            }
+        }
+    }
+    fn chmod(&self, mode: u32) -> Result<(), isize> {
+        let path = CString::new(self.inner.path()).unwrap();
+        let _fs = EXT4_FS.lock();
+        // the file type bits are kept by lwext4
+        let rc = unsafe { ext4_mode_set(path.as_ptr(), mode & 0o7777) };
+        if rc != EOK as i32 {
+            return Err(to_errno(rc));
+        }
+        Ok(())
+    }
+    fn chown(&self, uid: u32, gid: u32) -> Result<(), isize> {
+        let path = CString::new(self.inner.path()).unwrap();
+        let _fs = EXT4_FS.lock();
+        let rc = unsafe { ext4_owner_set(path.as_ptr(), uid, gid) };
+        if rc != EOK as i32 {
+            return Err(to_errno(rc));
+        }
+        Ok(())
+    }
+    fn get_single_cache(&self, offset: usize) -> Result<Arc<Mutex<PageCache>>, ()> {
+        if offset & 0xfff != 0 {
+            return Err(());
+        }
+        Ok(self.inner.get_single_cache(offset >> 12))
+    }
+    fn get_all_caches(&self) -> Result<Vec<Arc<Mutex<PageCache>>>, ()> {
+        let pages = (self.inner.size() + PAGE_SIZE - 1) / PAGE_SIZE;
+        Ok((0..pages)
+            .map(|inner_cache_id| self.inner.get_single_cache(inner_cache_id))
+            .collect())
+    }
+    fn sync_caches(&self) {
+        self.inner.sync_caches()
+    }
+    fn oom(&self) -> usize {
+        self.inner.oom()
+    }
+
+    fn hang_up(&self) -> bool {
+        false
+    }
+    fn read_link(&self) -> Result<String, isize> {
+        if self.inner.mode_type != StatMode::S_IFLNK.bits() {
+            return Err(EINVAL);
+        }
+        let path = CString::new(self.inner.path()).unwrap();
+        let mut buf = alloc::vec![0u8; PATH_MAX];
+        let mut len = 0;
+        let _fs = EXT4_FS.lock();
+        let buf_ptr = buf.as_mut_ptr() as *mut _;
+        let rc = unsafe { ext4_readlink(path.as_ptr(), buf_ptr, buf.len(), &mut len) };
+        if rc != EOK as i32 {
+            return Err(to_errno(rc));
+        }
+        Ok(String::from_utf8_lossy(&buf[..len]).to_string())
+    }
+
+    fn fcntl(&self, cmd: u32, arg: u32) -> isize {
+        EINVAL
+    }
+}
@@ -32,6 +32,16 @@ pub struct Ext4BlockWrapper<K: KernelDevOp> {
 
 impl<K: KernelDevOp> Ext4BlockWrapper<K> {
     pub fn new(block_dev: K::DevType) -> Result<Self, i32> {
+        Self::new_with_name(block_dev, "ext4_fs", "/")
+    }
+
+    /// Register `block_dev` as `name` and mount it on `mount_point`,
+    /// which must end with '/', so that several file systems can be mounted at once.
+    pub fn new_with_name(
+        block_dev: K::DevType,
+        name: &str,
+        mount_point: &str,
+    ) -> Result<Self, i32> {
         // note this ownership
         let devt_user = Box::into_raw(Box::new(block_dev)) as *mut c_void;
         //let devt_user = devt.as_mut() as *mut _ as *mut c_void;
@@ -70,10 +80,10 @@ impl<K: KernelDevOp> Ext4BlockWrapper<K> {
             journal: null_mut(),
         };
 
-        let c_name = CString::new("ext4_fs").expect("CString::new ext4_fs failed");
+        let c_name = CString::new(name).expect("CString::new name failed");
         let c_name = c_name.as_bytes_with_nul(); // + '\0'
                                                  //let c_mountpoint = CString::new("/mp/").unwrap();
-        let c_mountpoint = CString::new("/").unwrap();
+        let c_mountpoint = CString::new(mount_point).unwrap();
         let c_mountpoint = c_mountpoint.as_bytes_with_nul();
 
         let mut name: [u8; 16] = [0; 16];
@@ -0,0 +1,124 @@
+mod disk;
+pub mod inode;
+
+pub use disk::Ext4Disk;
+pub use inode::Ext4Inode;
+
+use crate::drivers::block::BlockDevice;
+use crate::fs::file_trait::File;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+use lwext4_rust::bindings::{CONFIG_EXT4_MOUNTPOINTS_COUNT, SEEK_SET};
+use lwext4_rust::{Ext4BlockWrapper, KernelDevOp};
+use spin::Mutex;
+
+/// Byte offset of the ext2/3/4 superblock on the device
+const SUPER_BLOCK_OFFSET: usize = 1024;
+const SUPER_BLOCK_SIZE: usize = 1024;
+const EXT4_SUPER_MAGIC: u16 = 0xEF53;
+/// `s_feature_incompat` bit that enables `s_blocks_count_hi`
+const EXT4_FEATURE_INCOMPAT_64BIT: u32 = 0x80;
+
+pub struct Ext4FileSystem {
+    _wrapper: Ext4BlockWrapper<Ext4Disk>,
+}
+
+// lwext4 keeps raw pointers in its block device descriptor,
+// every access to it is serialized by `EXT4_FS`.
+unsafe impl Send for Ext4FileSystem {}
+unsafe impl Sync for Ext4FileSystem {}
+
+lazy_static! {
+    /// One slot for each mount point lwext4 has room for, slot `i` is mounted on `/ext4_i/`.
+    /// This lock also serializes all calls into the C library.
+    pub static ref EXT4_FS: Mutex<Vec<Option<Ext4FileSystem>>> = Mutex::new(
+        (0..CONFIG_EXT4_MOUNTPOINTS_COUNT)
+            .map(|_| None)
+            .collect()
+    );
+}
+
+/// Held by the root inode of a mount, which every inode of it keeps alive through its parents.
+/// The file system is unmounted when the last inode goes, so that files still open
+/// after a lazy `umount2()` keep working until they are closed.
+pub struct MountRef(usize);
+
+impl MountRef {
+    pub fn mount_point(&self) -> String {
+        mount_point(self.0)
+    }
+}
+
+/// Where lwext4 mounts the file system in `slot`, every path passed to it starts with this.
+/// None of them is a prefix of another, so no mount shadows the others.
+fn mount_point(slot: usize) -> String {
+    format!("/ext4_{}/", slot)
+}
+
+impl Drop for MountRef {
+    fn drop(&mut self) {
+        // dropping the wrapper flushes and unregisters it in lwext4
+        EXT4_FS.lock()[self.0].take();
+    }
+}
+
+/// Check the superblock magic of `block_device`.
+/// # Return
+/// The size of the file system in bytes if it is ext2/3/4, otherwise `None`.
+pub fn probe(block_device: &Arc<dyn BlockDevice>) -> Option<usize> {
+    let mut disk = Ext4Disk::new(block_device.clone(), SUPER_BLOCK_OFFSET + SUPER_BLOCK_SIZE);
+    let mut super_block = [0u8; SUPER_BLOCK_SIZE];
+    Ext4Disk::seek(&mut disk, SUPER_BLOCK_OFFSET as i64, SEEK_SET as i32).ok()?;
+    Ext4Disk::read(&mut disk, &mut super_block).ok()?;
+
+    let read_u16 = |off: usize| u16::from_le_bytes([super_block[off], super_block[off + 1]]);
+    let read_u32 = |off: usize| {
+        u32::from_le_bytes([
+            super_block[off],
+            super_block[off + 1],
+            super_block[off + 2],
+            super_block[off + 3],
+        ])
+    };
+    if read_u16(0x38) != EXT4_SUPER_MAGIC {
+        return None;
+    }
+    let block_size = 1024usize << read_u32(0x18);
+    let mut blocks_count = read_u32(0x4) as usize;
+    if read_u32(0x60) & EXT4_FEATURE_INCOMPAT_64BIT != 0 {
+        blocks_count |= (read_u32(0x150) as usize) << 32;
+    }
+    Some(blocks_count * block_size)
+}
+
+/// Mount the ext4 file system on `block_device` and return its root directory.
+/// # Warning
+/// Caller should `probe()` first, lwext4 panics if the device doesn't contain an ext4 file system.
+pub fn mount(block_device: &Arc<dyn BlockDevice>, size: usize) -> Result<Arc<dyn File>, isize> {
+    let mut lock = EXT4_FS.lock();
+    let slot = match lock.iter().position(|fs| fs.is_none()) {
+        Some(slot) => slot,
+        None => {
+            log::error!("[ext4] no free mount point, all {} are in use", lock.len());
+            return Err(crate::syscall::errno::EBUSY);
+        }
+    };
+    let wrapper = Ext4BlockWrapper::<Ext4Disk>::new_with_name(
+        Ext4Disk::new(block_device.clone(), size),
+        &format!("ext4_fs{}", slot),
+        &mount_point(slot),
+    );
+    match wrapper {
+        Ok(wrapper) => lock[slot] = Some(Ext4FileSystem { _wrapper: wrapper }),
+        Err(errno) => {
+            log::error!("[ext4] mount failed, rc = {}", errno);
+            return Err(crate::syscall::errno::EIO);
+        }
+    }
+    // `MountRef` takes the lock when dropped
+    drop(lock);
+    Ok(Ext4Inode::root_inode(MountRef(slot)))
+}
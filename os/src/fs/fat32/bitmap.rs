@@ -31,24 +31,24 @@ impl Fat {
     /// + `current_clus_num`: current cluster number
     /// + `block_device`: pointer of block device
     /// # Return value
-    /// Next cluster number
+    /// Next cluster number, `BAD_BLOCK` if the entry can't be read,
+    /// which ends a chain and is never allocated
     pub fn get_next_clus_num(
         &self,
         current_clus_num: u32,
         block_device: &Arc<dyn BlockDevice>,
     ) -> u32 {
-        self.fat_cache_mgr
-            .lock()
-            .get_block_cache(
-                self.this_fat_sec_num(current_clus_num) as usize,
-                block_device,
-            )
-            .lock()
-            .read(
-                self.this_fat_ent_offset(current_clus_num) as usize,
-                |fat_entry: &u32| -> u32 { *fat_entry },
-            )
-            & EOC
+        let block_cache = match self.fat_cache_mgr.lock().get_block_cache(
+            self.this_fat_sec_num(current_clus_num) as usize,
+            block_device,
+        ) {
+            Ok(block_cache) => block_cache,
+            Err(_) => return BAD_BLOCK,
+        };
+        block_cache.lock().read(
+            self.this_fat_ent_offset(current_clus_num) as usize,
+            |fat_entry: &u32| -> u32 { *fat_entry },
+        ) & EOC
     }
     /// Get all cluster numbers after the current cluster number
     /// # Arguments
@@ -130,17 +130,24 @@ impl Fat {
             return;
         }
         let current = current.unwrap();
-        self.fat_cache_mgr
+        let block_cache = match self
+            .fat_cache_mgr
             .lock()
             .get_block_cache(self.this_fat_sec_num(current) as usize, block_device)
-            .lock()
-            .modify(
-                self.this_fat_ent_offset(current as u32),
-                |bitmap_block: &mut u32| {
-                    //println!("[set_next_clus]bitmap_block:{}->{}", *bitmap_block, next);
-                    *bitmap_block = next;
-                },
-            )
+        {
+            Ok(block_cache) => block_cache,
+            Err(_) => {
+                log::error!("[set_next_clus] FAT entry of cluster {} is lost", current);
+                return;
+            }
+        };
+        block_cache.lock().modify(
+            self.this_fat_ent_offset(current as u32),
+            |bitmap_block: &mut u32| {
+                //println!("[set_next_clus]bitmap_block:{}->{}", *bitmap_block, next);
+                *bitmap_block = next;
+            },
+        )
     }
 
     /// Allocate as many clusters (but not greater than alloc_num) as possible.
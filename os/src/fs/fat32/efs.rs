@@ -5,7 +5,7 @@ use core::ptr::addr_of;
 use crate::arch;
 
 use super::{layout::BPB, Cache};
-use super::{BlockCacheManager, BlockDevice, Fat};
+use super::{register_block_cache, BlockCacheManager, BlockDevice, Fat};
 use alloc::{sync::Arc, vec::Vec};
 
 pub struct EasyFileSystem {
@@ -64,42 +64,50 @@ impl EasyFileSystem {
         let offset_blocks = (clus_num - 2) * self.sec_per_clus as u32;
         start_block + offset_blocks
     }
+    /// Check whether `block_device` holds a FAT32 file system that `open()` accepts.
+    pub fn probe(block_device: &Arc<dyn BlockDevice>) -> bool {
+        let mut buffer = [0u8; arch::BLOCK_SZ];
+        block_device.read_block(0, &mut buffer);
+        let super_block = unsafe { (buffer.as_ptr() as *const BPB).read_unaligned() };
+        let byts_per_sec = super_block.byts_per_sec;
+        byts_per_sec as usize == arch::BLOCK_SZ && super_block.is_valid()
+    }
     /// Open the filesystem object.
     /// # Arguments
     /// + `block_device`: pointer of hardware device
     /// + `index_cache_mgr`: fat cache manager
+    /// # Return Value
+    /// The error of reading the boot sector
     pub fn open(
         block_device: Arc<dyn BlockDevice>,
         index_cache_mgr: Arc<spin::Mutex<BlockCacheManager>>,
-    ) -> Arc<Self> {
+    ) -> Result<Arc<Self>, isize> {
+        register_block_cache(&index_cache_mgr, &block_device);
         // read SuperBlock
         let fat_cache_mgr = index_cache_mgr.clone();
-        index_cache_mgr
-            .lock()
-            .get_block_cache(0, &block_device)
-            .lock()
-            .read(0, |super_block: &BPB| {
-                // ***************Do NOT change this LINE!****************
-                let byts_per_sec = super_block.byts_per_sec;
-                debug_assert!(byts_per_sec as usize == arch::BLOCK_SZ);
-                debug_assert!(BlockCacheManager::CACHE_SZ % byts_per_sec as usize == 0);
-                debug_assert!(super_block.is_valid(), "Error loading EFS!");
-                let efs = Self {
-                    block_device,
-                    fat: Fat::new(
-                        super_block.rsvd_sec_cnt as usize,
-                        byts_per_sec as usize,
-                        (super_block.data_sector_count() / super_block.sec_per_clus as u32)
-                            as usize,
-                        fat_cache_mgr,
-                    ),
-                    root_clus: super_block.root_clus,
-                    sec_per_clus: super_block.sec_per_clus,
-                    byts_per_sec,
-                    data_area_start_block: super_block.first_data_sector(),
-                };
-                Arc::new(efs)
-            })
+        let boot_sector = index_cache_mgr.lock().get_block_cache(0, &block_device)?;
+        let efs = boot_sector.lock().read(0, |super_block: &BPB| {
+            // ***************Do NOT change this LINE!****************
+            let byts_per_sec = super_block.byts_per_sec;
+            debug_assert!(byts_per_sec as usize == arch::BLOCK_SZ);
+            debug_assert!(BlockCacheManager::CACHE_SZ % byts_per_sec as usize == 0);
+            debug_assert!(super_block.is_valid(), "Error loading EFS!");
+            let efs = Self {
+                block_device,
+                fat: Fat::new(
+                    super_block.rsvd_sec_cnt as usize,
+                    byts_per_sec as usize,
+                    (super_block.data_sector_count() / super_block.sec_per_clus as u32) as usize,
+                    fat_cache_mgr,
+                ),
+                root_clus: super_block.root_clus,
+                sec_per_clus: super_block.sec_per_clus,
+                byts_per_sec,
+                data_area_start_block: super_block.first_data_sector(),
+            };
+            Arc::new(efs)
+        });
+        Ok(efs)
     }
     pub fn alloc_blocks(&self, blocks: usize) -> Vec<usize> {
         let sec_per_clus = self.sec_per_clus as usize;
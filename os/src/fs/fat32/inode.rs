@@ -3,6 +3,7 @@ use crate::fs::file_trait::File;
 use crate::fs::*;
 use crate::mm::UserBuffer;
 use crate::syscall::errno::*;
+use crate::timer::get_realtime_sec;
 use core::panic;
 
 use super::layout::FATDiskInodeType;
@@ -38,6 +39,10 @@ impl OSInode {
             dirnode_ptr: Arc::new(Mutex::new(Weak::new())),
         })
     }
+    /// Update mtime, the directory entry takes it when the inode is dropped
+    fn modified(&self) {
+        self.inner.time().set_modify_time(get_realtime_sec() as u64);
+    }
 }
 
 impl Drop for OSInode {
@@ -111,6 +116,7 @@ impl File for OSInode {
             Some(offset) => {
                 let len = self.inner.write_at_block_cache(*offset, buffer);
                 *offset += len;
+                self.modified();
                 len
             }
             None => {
@@ -123,6 +129,7 @@ impl File for OSInode {
                     .inner
                     .write_at_block_cache_lock(&inode_lock, *offset, buffer);
                 *offset += len;
+                self.modified();
                 len
             }
         }
@@ -198,6 +205,7 @@ impl File for OSInode {
                 }
             }
         }
+        self.modified();
         total_write_size
     }
     fn get_size(&self) -> usize {
@@ -238,8 +246,8 @@ impl File for OSInode {
         self.dirnode_ptr.lock().upgrade()
     }
 
-    fn open(&self, flags: OpenFlags, special_use: bool) -> Arc<dyn File> {
-        Arc::new(Self {
+    fn open(&self, flags: OpenFlags, special_use: bool) -> Result<Arc<dyn File>, isize> {
+        Ok(Arc::new(Self {
             readable: flags.contains(OpenFlags::O_RDONLY) || flags.contains(OpenFlags::O_RDWR),
             writable: flags.contains(OpenFlags::O_WRONLY) || flags.contains(OpenFlags::O_RDWR),
             special_use,
@@ -247,7 +255,7 @@ impl File for OSInode {
             inner: self.inner.clone(),
             offset: Mutex::new(0),
             dirnode_ptr: self.dirnode_ptr.clone(),
-        })
+        }))
     }
     fn open_subfile(&self) -> Result<Vec<(String, Arc<dyn File>)>, isize> {
         let inode_lock = self.inner.write();
@@ -358,6 +366,9 @@ impl File for OSInode {
             })
             .collect()
     }
+    fn is_append(&self) -> bool {
+        self.append
+    }
     fn lseek(&self, offset: isize, whence: SeekWhence) -> Result<usize, isize> {
         let inode_lock = self.inner.write();
         let new_offset = match whence {
@@ -384,6 +395,7 @@ impl File for OSInode {
         let old_size = self.inner.get_file_size_wlock(&inode_lock);
         self.inner
             .modify_size_lock(&inode_lock, new_size as isize - old_size as isize, true);
+        self.modified();
         Ok(())
     }
     fn set_timestamp(&self, ctime: Option<usize>, atime: Option<usize>, mtime: Option<usize>) {
@@ -404,12 +416,18 @@ impl File for OSInode {
         }
         let inode_lock = self.inner.read();
         let inner_cache_id = offset >> 12;
-        Ok(self
-            .inner
-            .get_single_cache_lock(&inode_lock, inner_cache_id))
+        self.inner
+            .get_single_cache_lock(&inode_lock, inner_cache_id)
+            .map_err(|_| ())
     }
     fn get_all_caches(&self) -> Result<Vec<Arc<Mutex<PageCache>>>, ()> {
-        Ok(self.inner.get_all_cache())
+        self.inner.get_all_cache().map_err(|_| ())
+    }
+    fn sync_caches(&self) {
+        self.inner.sync_caches()
+    }
+    fn writeback(&self) -> usize {
+        self.inner.writeback_caches()
     }
     fn oom(&self) -> usize {
         self.inner.oom()
@@ -51,12 +51,16 @@ impl Inode {
             // read and update read size
             let lock = self.file_content.read();
             let block_read_size = end_current_block - start;
-            self.file_cache_mgr
-                .get_cache(
-                    start_cache,
-                    || -> Vec<usize> { self.get_neighboring_sec(&lock.clus_list, start_cache) },
-                    &self.fs.block_device,
-                )
+            let page_cache = match self.file_cache_mgr.get_cache(
+                start_cache,
+                || -> Vec<usize> { self.get_neighboring_sec(&lock.clus_list, start_cache) },
+                &self.fs.block_device,
+            ) {
+                Ok(page_cache) => page_cache,
+                // stop at a page that can't be read in, the count tells how far it got
+                Err(_) => break,
+            };
+            page_cache
                 .lock()
                 // I know hardcoding 4096 in is bad, but I can't get around Rust's syntax checking...
                 .read(0, |data_block: &[u8; 4096]| {
@@ -99,12 +103,16 @@ impl Inode {
             // read and update read size
             let lock = self.file_content.read();
             let block_read_size = end_current_block - start;
-            self.file_cache_mgr
-                .get_cache(
-                    start_cache,
-                    || -> Vec<usize> { self.get_neighboring_sec(&lock.clus_list, start_cache) },
-                    &self.fs.block_device,
-                )
+            let page_cache = match self.file_cache_mgr.get_cache(
+                start_cache,
+                || -> Vec<usize> { self.get_neighboring_sec(&lock.clus_list, start_cache) },
+                &self.fs.block_device,
+            ) {
+                Ok(page_cache) => page_cache,
+                // stop at a page that can't be read in, the count tells how far it got
+                Err(_) => break,
+            };
+            page_cache
                 .lock()
                 // I know hardcoding 4096 in is bad, but I can't get around Rust's syntax checking...
                 .read(0, |data_block: &[u8; 4096]| {
@@ -176,12 +184,16 @@ impl Inode {
             // write and update write size
             let lock = self.file_content.read();
             let block_write_size = end_current_block - start;
-            self.file_cache_mgr
-                .get_cache(
-                    start_cache,
-                    || -> Vec<usize> { self.get_neighboring_sec(&lock.clus_list, start_cache) },
-                    &self.fs.block_device,
-                )
+            let page_cache = match self.file_cache_mgr.get_cache(
+                start_cache,
+                || -> Vec<usize> { self.get_neighboring_sec(&lock.clus_list, start_cache) },
+                &self.fs.block_device,
+            ) {
+                Ok(page_cache) => page_cache,
+                // stop at a page that can't be read in, the count tells how far it got
+                Err(_) => break,
+            };
+            page_cache
                 .lock()
                 // I know hardcoding 4096 in is bad, but I can't get around Rust's syntax checking...
                 .modify(0, |data_block: &mut [u8; 4096]| {
@@ -225,7 +237,7 @@ impl Inode {
     /// Pointer to page cache
     /// # Warning
     /// This function will lock self's `file_content`, may cause deadlock
-    pub fn get_single_cache(&self, inner_cache_id: usize) -> Arc<Mutex<PageCache>> {
+    pub fn get_single_cache(&self, inner_cache_id: usize) -> Result<Arc<Mutex<PageCache>>, isize> {
         self.get_single_cache_lock(&self.read(), inner_cache_id)
     }
 
@@ -234,12 +246,12 @@ impl Inode {
     /// + `inode_lock`: The lock of inode
     /// + `inner_cache_id`: The index of inner cache
     /// # Return Value
-    /// Pointer to page cache
+    /// Pointer to page cache, or the error of reading it in
     pub fn get_single_cache_lock(
         &self,
         _inode_lock: &RwLockReadGuard<InodeLock>,
         inner_cache_id: usize,
-    ) -> Arc<Mutex<PageCache>> {
+    ) -> Result<Arc<Mutex<PageCache>>, isize> {
         let lock = self.file_content.read();
         self.file_cache_mgr.get_cache(
             inner_cache_id,
@@ -250,17 +262,17 @@ impl Inode {
 
     /// Get all page caches corresponding to file
     /// # Return Value
-    /// List of pointers to the page cache
-    pub fn get_all_cache(&self) -> Vec<Arc<Mutex<PageCache>>> {
+    /// List of pointers to the page cache, or the error of reading one in
+    pub fn get_all_cache(&self) -> Result<Vec<Arc<Mutex<PageCache>>>, isize> {
         let inode_lock = self.read();
         let lock = self.file_content.read();
         let cache_num =
             (lock.size as usize + PageCacheManager::CACHE_SZ - 1) / PageCacheManager::CACHE_SZ;
         let mut cache_list = Vec::<Arc<Mutex<PageCache>>>::with_capacity(cache_num);
         for inner_cache_id in 0..cache_num {
-            cache_list.push(self.get_single_cache_lock(&inode_lock, inner_cache_id));
+            cache_list.push(self.get_single_cache_lock(&inode_lock, inner_cache_id)?);
         }
-        cache_list
+        Ok(cache_list)
     }
 }
 
@@ -500,12 +500,16 @@ impl Inode {
             // write and update write size
             let lock = self.file_content.read();
             let block_write_size = end_current_block - start;
-            self.file_cache_mgr
-                .get_cache(
-                    start_cache,
-                    || -> Vec<usize> { self.get_neighboring_sec(&lock.clus_list, start_cache) },
-                    &self.fs.block_device,
-                )
+            let page_cache = match self.file_cache_mgr.get_cache(
+                start_cache,
+                || -> Vec<usize> { self.get_neighboring_sec(&lock.clus_list, start_cache) },
+                &self.fs.block_device,
+            ) {
+                Ok(page_cache) => page_cache,
+                // stop at a page that can't be read in, the count tells how far it got
+                Err(_) => break,
+            };
+            page_cache
                 .lock()
                 // I know hardcoding 4096 in is bad, but I can't get around Rust's syntax checking...
                 .modify(0, |data_block: &mut [u8; 4096]| {
@@ -535,4 +539,27 @@ impl Inode {
         };
         self.file_cache_mgr.oom(neighbor, &self.fs.block_device)
     }
+
+    /// Write back the page caches modified through shared mappings
+    pub fn sync_caches(&self) {
+        let neighbor = |inner_cache_id| {
+            self.get_neighboring_sec(&self.file_content.read().clus_list, inner_cache_id)
+        };
+        self.file_cache_mgr.sync(neighbor, &self.fs.block_device)
+    }
+
+    /// Write back the dirty page caches for the background writeback,
+    /// nothing is done if the file is being changed.
+    /// # Return Value
+    /// The number of page caches looked at
+    pub fn writeback_caches(&self) -> usize {
+        let file_content = match self.file_content.try_read() {
+            Some(file_content) => file_content,
+            None => return 0,
+        };
+        let neighbor =
+            |inner_cache_id| self.get_neighboring_sec(&file_content.clus_list, inner_cache_id);
+        self.file_cache_mgr
+            .writeback(neighbor, &self.fs.block_device)
+    }
 }
@@ -292,7 +292,7 @@ impl Inode {
     /// # Return Value
     /// Pointer to Inode
     pub fn from_ent(parent_dir: &Arc<Self>, ent: &FATShortDirEnt, offset: u32) -> Arc<Self> {
-        Self::new(
+        let inode = Self::new(
             ent.get_first_clus(),
             if ent.is_dir() {
                 DiskInodeType::Directory
@@ -306,7 +306,14 @@ impl Inode {
             },
             Some((parent_dir.clone(), offset)),
             parent_dir.fs.clone(),
-        )
+        );
+        {
+            let mut time = inode.time();
+            time.set_create_time(ent.create_time());
+            time.set_access_time(ent.access_time());
+            time.set_modify_time(ent.modify_time());
+        }
+        inode
     }
 
     /// Fill out an empty directory with only the '.' & '..' entries.
@@ -1,5 +1,8 @@
 #![allow(unused)]
-use crate::{copy_from_name1, copy_to_name1, lang_items::Bytes};
+use crate::{
+    copy_from_name1, copy_to_name1, drivers::rtc::RtcTime, lang_items::Bytes,
+    timer::get_realtime_sec,
+};
 use alloc::{
     format,
     string::{String, ToString},
@@ -482,6 +485,10 @@ impl FATShortDirEnt {
         short_ent.attr = FATDiskInodeType::AttrArchive;
         short_ent.set_fst_clus(fst_clus);
         short_ent.name.copy_from_slice(&name);
+        let now = get_realtime_sec() as u64;
+        short_ent.set_create_time(now);
+        short_ent.set_access_time(now);
+        short_ent.set_modify_time(now);
         if file_type == DiskInodeType::Directory {
             short_ent.attr = FATDiskInodeType::AttrDirectory;
         } else {
@@ -506,7 +513,62 @@ impl FATShortDirEnt {
             || self.attr == FATDiskInodeType::AttrReadOnly
     }
 }
+/// Seconds since the epoch of a FAT date and time, the date counts years from 1980.
+/// FAT keeps local time, which is taken to be UTC as there is no time zone support.
+fn unix_time(date: u16, time: u16) -> u64 {
+    RtcTime {
+        tm_sec: (time & 0x1f) as i32 * 2,
+        tm_min: (time >> 5 & 0x3f) as i32,
+        tm_hour: (time >> 11) as i32,
+        tm_mday: (date & 0x1f) as i32,
+        tm_mon: (date >> 5 & 0xf) as i32 - 1,
+        tm_year: (date >> 9) as i32 + 80,
+        ..Default::default()
+    }
+    .to_unix()
+    .unwrap_or(0) as u64
+}
+
+/// Inverse of `unix_time()`, clamped to the years FAT can represent, 1980 to 2107
+fn fat_time(secs: u64) -> (u16, u16) {
+    let time = RtcTime::from_unix(secs as usize);
+    if time.tm_year < 80 {
+        // 1980-01-01 00:00:00
+        return ((1 << 5) | 1, 0);
+    }
+    let date = ((time.tm_year - 80).min(127) as u16) << 9
+        | ((time.tm_mon + 1) as u16) << 5
+        | time.tm_mday as u16;
+    let time = (time.tm_hour as u16) << 11 | (time.tm_min as u16) << 5 | (time.tm_sec / 2) as u16;
+    (date, time)
+}
+
 impl FATShortDirEnt {
+    pub fn create_time(&self) -> u64 {
+        unix_time(self.crt_date, self.crt_time) + self.crt_time_teenth as u64 / 100
+    }
+    pub fn set_create_time(&mut self, secs: u64) {
+        let (date, time) = fat_time(secs);
+        self.crt_date = date;
+        self.crt_time = time;
+        // hundredths of a second on top of the 2 second resolution of `crt_time`
+        self.crt_time_teenth = (secs % 2 * 100) as u8;
+    }
+    /// Only the date of the last access is recorded
+    pub fn access_time(&self) -> u64 {
+        unix_time(self.last_acc_date, 0)
+    }
+    pub fn set_access_time(&mut self, secs: u64) {
+        self.last_acc_date = fat_time(secs).0;
+    }
+    pub fn modify_time(&self) -> u64 {
+        unix_time(self.wrt_date, self.wrt_time)
+    }
+    pub fn set_modify_time(&mut self, secs: u64) {
+        let (date, time) = fat_time(secs);
+        self.wrt_date = date;
+        self.wrt_time = time;
+    }
     pub fn name(&self) -> String {
         let basic_name_len = (0..8).find(|i| self.name[*i] == ' ' as u8).unwrap_or(8);
         let ext_name_len = (0..3).find(|i| self.name[8 + *i] == ' ' as u8).unwrap_or(3);
@@ -7,7 +7,9 @@ mod vfs;
 mod inode_file_dir;
 mod inode_del_link_cre;
 mod inode_io_ls_meta;
-pub use super::cache::{BlockCacheManager, BufferCache, Cache, PageCache, PageCacheManager};
+pub use super::cache::{
+    register_block_cache, BlockCacheManager, BufferCache, Cache, PageCache, PageCacheManager,
+};
 pub use crate::drivers::block::BlockDevice;
 use bitmap::Fat;
 pub use efs::EasyFileSystem;
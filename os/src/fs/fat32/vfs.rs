@@ -125,11 +125,22 @@ impl Drop for Inode {
                     .unwrap_or(0),
             );
             // Modify time
-            // todo!
+            {
+                let time = self.time.lock();
+                short_dir_ent.set_create_time(time.create_time);
+                short_dir_ent.set_access_time(time.access_time);
+                short_dir_ent.set_modify_time(time.modify_time);
+            }
             log::debug!("[Inode drop]: new_ent: {:?}", short_dir_ent);
             // Write back
             parent_dir
-                .set_dir_ent(&par_inode_lock, *offset, dir_ent)
+                .set_dir_ent(
+                    &par_inode_lock,
+                    *offset,
+                    FATDirEnt {
+                        short_entry: short_dir_ent,
+                    },
+                )
                 .unwrap();
         }
     }
@@ -1,5 +1,9 @@
 use super::fat32::DiskInodeType;
-use crate::{mm::UserBuffer, syscall::errno::ENOTTY};
+use crate::{
+    mm::UserBuffer,
+    syscall::errno::{EINVAL, ENOTTY, EPERM},
+    task::WaitQueue,
+};
 use __alloc::string::String;
 use alloc::{
     sync::{Arc, Weak},
@@ -32,10 +36,17 @@ pub trait File: DowncastSync {
     fn info_dirtree_node(&self, dirnode_ptr: Weak<DirectoryTreeNode>);
     fn get_dirtree_node(&self) -> Option<Arc<DirectoryTreeNode>>;
     /// open
-    fn open(&self, flags: OpenFlags, special_use: bool) -> Arc<dyn File>;
+    fn open(&self, flags: OpenFlags, special_use: bool) -> Result<Arc<dyn File>, isize>;
     fn open_subfile(&self) -> Result<Vec<(String, Arc<dyn File>)>, isize>;
     /// create
     fn create(&self, name: &str, file_type: DiskInodeType) -> Result<Arc<dyn File>, isize>;
+    /// Symbolic links, FIFOs and device nodes, only for file systems that keep such types
+    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn File>, isize> {
+        Err(EPERM)
+    }
+    fn mknod(&self, _name: &str, _mode: u32, _rdev: u64) -> Result<Arc<dyn File>, isize> {
+        Err(EPERM)
+    }
     fn link_child(&self, name: &str, child: &Self) -> Result<(), isize>
     where
         Self: Sized;
@@ -48,22 +59,52 @@ pub trait File: DowncastSync {
         self.lseek(0, SeekWhence::SEEK_CUR).unwrap()
     }
     fn lseek(&self, offset: isize, whence: SeekWhence) -> Result<usize, isize>;
+    /// Opened with `O_APPEND`, writes without an offset go to the end of file
+    fn is_append(&self) -> bool {
+        false
+    }
     /// size
     fn modify_size(&self, diff: isize) -> Result<(), isize>;
     fn truncate_size(&self, new_size: usize) -> Result<(), isize>;
     // time
     fn set_timestamp(&self, ctime: Option<usize>, atime: Option<usize>, mtime: Option<usize>);
+    /// chmod, only the permission bits of `mode` are used
+    fn chmod(&self, _mode: u32) -> Result<(), isize> {
+        Err(EPERM)
+    }
+    /// chown, both IDs are set
+    fn chown(&self, _uid: u32, _gid: u32) -> Result<(), isize> {
+        Err(EPERM)
+    }
     /// cache
     fn get_single_cache(&self, offset: usize) -> Result<Arc<Mutex<PageCache>>, ()>;
     fn get_all_caches(&self) -> Result<Vec<Arc<Mutex<PageCache>>>, ()>;
+    /// Write the caches dirtied through shared mappings back to the device
+    fn sync_caches(&self) {}
+    /// Write back dirty caches without waiting for any lock, for the background writeback.
+    /// Returns the number of caches looked at.
+    fn writeback(&self) -> usize {
+        0
+    }
     /// memory related
     fn oom(&self) -> usize;
     /// poll, select related
     fn hang_up(&self) -> bool;
+    /// Wait queues woken whenever the file may have become ready or hung up.
+    /// Empty for files which are always ready, or wake their waiters otherwise.
+    fn wait_queues(&self) -> Vec<Arc<Mutex<WaitQueue>>> {
+        Vec::new()
+    }
+    /// `O_NONBLOCK` of the descriptor, for files whose reads and writes block by themselves
+    fn set_nonblock(&self, _nonblock: bool) {}
     /// iotcl
     fn ioctl(&self, _cmd: u32, _argp: usize) -> isize {
         ENOTTY
     }
+    /// readlink, only symbolic links have a target
+    fn read_link(&self) -> Result<String, isize> {
+        Err(EINVAL)
+    }
     /// fcntl
     fn fcntl(&self, cmd: u32, arg: u32) -> isize;
 }
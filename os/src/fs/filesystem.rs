@@ -1,4 +1,5 @@
 use core::ops::AddAssign;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use alloc::sync::Arc;
 use lazy_static::*;
@@ -7,21 +8,51 @@ use spin::Mutex;
 pub enum FS {
     Null,
     Fat32,
+    Ext4,
+    /// Synthetic files generated from kernel state, see `fs::proc`
+    Proc,
+    /// Files in memory only, see `fs::tmpfs`
+    Tmpfs,
 }
 
 pub struct FileSystem {
     pub fs_id: usize,
     pub fs_type: FS,
+    /// Set by `MS_RDONLY`, changes are refused with `EROFS`
+    read_only: AtomicBool,
 }
 
 lazy_static! {
     static ref FS_ID_COUNTER: Arc<Mutex<usize>> = Arc::new(Mutex::new(0));
 }
 
+impl FS {
+    /// Name shown in `/proc/mounts`
+    pub fn name(&self) -> &'static str {
+        match self {
+            FS::Null => "none",
+            FS::Fat32 => "vfat",
+            FS::Ext4 => "ext4",
+            FS::Proc => "proc",
+            FS::Tmpfs => "tmpfs",
+        }
+    }
+}
+
 impl FileSystem {
     pub fn new(fs_type: FS) -> Self {
         FS_ID_COUNTER.lock().add_assign(1);
         let fs_id = *FS_ID_COUNTER.lock();
-        Self { fs_id, fs_type }
+        Self {
+            fs_id,
+            fs_type,
+            read_only: AtomicBool::new(false),
+        }
+    }
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::Relaxed)
+    }
+    pub fn set_read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::Relaxed);
     }
 }
@@ -137,6 +137,24 @@ impl Stat {
     pub fn get_size(&self) -> usize {
         self.st_size as usize
     }
+    /// Replace the file type bits of `st_mode`
+    pub fn set_file_type(&mut self, file_type: StatMode) {
+        self.st_mode = (self.st_mode & !StatMode::S_IFMT.bits()) | file_type.bits();
+    }
+    pub fn get_mode(&self) -> u32 {
+        self.st_mode
+    }
+    pub fn get_uid(&self) -> u32 {
+        self.st_uid
+    }
+    pub fn get_gid(&self) -> u32 {
+        self.st_gid
+    }
+    /// Fill in the owner, `new()` makes every file owned by root
+    pub fn set_owner(&mut self, uid: u32, gid: u32) {
+        self.st_uid = uid;
+        self.st_gid = gid;
+    }
 
     pub fn new(
         st_dev: u64,
@@ -1,17 +1,25 @@
 mod cache;
 mod dev;
 pub mod directory_tree;
+pub mod epoll;
+mod ext4;
 mod fat32;
 pub mod file_trait;
 mod filesystem;
 mod layout;
 pub mod poll;
+mod proc;
 #[cfg(feature = "swap")]
 pub mod swap;
+mod tmpfs;
 
-pub use self::dev::{hwclock::*, null::*, pipe::*, socket::*, tty::*, zero::*};
+pub use self::dev::{
+    eventfd::*, hwclock::*, null::*, pipe::*, random::*, signalfd::*, socket::*, timerfd::*,
+    tty::*, zero::*,
+};
 use core::slice::{Iter, IterMut};
 
+pub use self::cache::writeback_tick;
 pub use self::layout::*;
 
 pub use self::fat32::{BlockDevice, DiskInodeType};
@@ -21,6 +29,12 @@ use crate::{
     config::SYSTEM_FD_LIMIT,
     mm::{Frame, UserBuffer},
     syscall::errno::*,
+    task::{
+        cred::{current_cred, Credentials, MAY_EXEC},
+        current_task,
+        rlimit::{Resource, RLIM_INFINITY},
+        Signals,
+    },
 };
 use alloc::{
     string::{String, ToString},
@@ -49,6 +63,7 @@ pub struct FileDescriptor {
 #[allow(unused)]
 impl FileDescriptor {
     pub fn new(cloexec: bool, nonblock: bool, file: Arc<dyn File>) -> Self {
+        file.set_nonblock(nonblock);
         Self {
             cloexec,
             nonblock,
@@ -65,6 +80,10 @@ impl FileDescriptor {
     pub fn get_nonblock(&self) -> bool {
         self.nonblock
     }
+    pub fn set_nonblock(&mut self, flag: bool) {
+        self.nonblock = flag;
+        self.file.set_nonblock(flag);
+    }
 
     pub fn get_cwd(&self) -> Option<String> {
         let inode = self.file.get_dirtree_node();
@@ -74,13 +93,25 @@ impl FileDescriptor {
         };
         Some(inode.get_cwd())
     }
-    /// Just used for cwd
+    /// Just used for cwd, entering a directory needs search permission only
     pub fn cd(&self, path: &str) -> Result<Arc<Self>, isize> {
-        match self.open(path, OpenFlags::O_DIRECTORY | OpenFlags::O_RDONLY, true) {
-            Ok(fd) => Ok(Arc::new(fd)),
+        match self.open(path, OpenFlags::O_DIRECTORY | OpenFlags::O_PATH, true) {
+            Ok(fd) => {
+                if let Some(cred) = current_cred() {
+                    fd.check_access(&cred, MAY_EXEC)?;
+                }
+                Ok(Arc::new(fd))
+            }
             Err(errno) => Err(errno),
         }
     }
+    /// Check `access` of `cred` to the file, files outside the directory tree grant any access
+    pub fn check_access(&self, cred: &Credentials, access: u32) -> Result<(), isize> {
+        match self.file.get_dirtree_node() {
+            Some(inode) => inode.check_access(cred, access),
+            None => Ok(()),
+        }
+    }
     pub fn readable(&self) -> bool {
         self.file.readable()
     }
@@ -102,9 +133,40 @@ impl FileDescriptor {
     pub fn read_user(&self, offset: Option<usize>, buf: UserBuffer) -> usize {
         self.file.read_user(offset, buf)
     }
-    pub fn write_user(&self, offset: Option<usize>, buf: UserBuffer) -> usize {
+    pub fn write_user(&self, offset: Option<usize>, mut buf: UserBuffer) -> usize {
+        if let Some(limit) = self.fsize_limit() {
+            let pos = match offset {
+                Some(offset) => offset,
+                None if self.file.is_append() => self.file.get_size(),
+                None => self.file.get_offset(),
+            };
+            if pos >= limit && buf.len() != 0 {
+                Self::exceed_fsize();
+                return EFBIG as usize;
+            }
+            // a short write up to the limit
+            buf.truncate(limit.saturating_sub(pos));
+        }
         self.file.write_user(offset, buf)
     }
+    /// `RLIMIT_FSIZE` of the caller, which applies to regular files only
+    fn fsize_limit(&self) -> Option<usize> {
+        if !self.file.is_file() {
+            return None;
+        }
+        let limit = current_task()?.rlimit.lock().get(Resource::FSIZE).rlim_cur;
+        if limit == RLIM_INFINITY {
+            None
+        } else {
+            Some(limit)
+        }
+    }
+    /// The caller tried to grow a file beyond `RLIMIT_FSIZE`
+    fn exceed_fsize() {
+        if let Some(task) = current_task() {
+            task.acquire_inner_lock().add_signal(Signals::SIGXFSZ);
+        }
+    }
     pub fn get_stat(&self) -> Stat {
         self.file.get_stat()
     }
@@ -138,6 +200,82 @@ impl FileDescriptor {
         };
         inode.mkdir(path)
     }
+    pub fn mount(
+        &self,
+        source: &str,
+        target: &str,
+        fs_type: &str,
+        read_only: bool,
+    ) -> Result<(), isize> {
+        if self.file.is_file() && !target.starts_with('/') {
+            return Err(ENOTDIR);
+        }
+        let inode = self.file.get_dirtree_node();
+        let inode = match inode {
+            Some(inode) => inode,
+            None => return Err(ENOENT),
+        };
+        inode.mount(source, target, fs_type, read_only)
+    }
+    pub fn remount(&self, target: &str, read_only: bool) -> Result<(), isize> {
+        if self.file.is_file() && !target.starts_with('/') {
+            return Err(ENOTDIR);
+        }
+        let inode = self.file.get_dirtree_node();
+        let inode = match inode {
+            Some(inode) => inode,
+            None => return Err(ENOENT),
+        };
+        inode.remount(target, read_only)
+    }
+    pub fn umount(&self, target: &str, detach: bool) -> Result<(), isize> {
+        if self.file.is_file() && !target.starts_with('/') {
+            return Err(ENOTDIR);
+        }
+        let inode = self.file.get_dirtree_node();
+        let inode = match inode {
+            Some(inode) => inode,
+            None => return Err(ENOENT),
+        };
+        inode.umount(target, detach)
+    }
+    pub fn mknod(
+        &self,
+        path: &str,
+        wrap: impl FnOnce(Arc<dyn File>) -> Arc<dyn File>,
+    ) -> Result<(), isize> {
+        if self.file.is_file() && !path.starts_with('/') {
+            return Err(ENOTDIR);
+        }
+        let inode = self.file.get_dirtree_node();
+        let inode = match inode {
+            Some(inode) => inode,
+            None => return Err(ENOENT),
+        };
+        inode.mknod(path, wrap)
+    }
+    pub fn symlink(&self, target: &str, path: &str) -> Result<(), isize> {
+        if self.file.is_file() && !path.starts_with('/') {
+            return Err(ENOTDIR);
+        }
+        let inode = self.file.get_dirtree_node();
+        let inode = match inode {
+            Some(inode) => inode,
+            None => return Err(ENOENT),
+        };
+        inode.symlink(target, path)
+    }
+    pub fn make_special(&self, path: &str, mode: u32, rdev: u64) -> Result<(), isize> {
+        if self.file.is_file() && !path.starts_with('/') {
+            return Err(ENOTDIR);
+        }
+        let inode = self.file.get_dirtree_node();
+        let inode = match inode {
+            Some(inode) => inode,
+            None => return Err(ENOENT),
+        };
+        inode.make_special(path, mode, rdev)
+    }
     pub fn delete(&self, path: &str, delete_directory: bool) -> Result<(), isize> {
         if self.file.is_file() && !path.starts_with('/') {
             return Err(ENOTDIR);
@@ -180,7 +318,11 @@ impl FileDescriptor {
         if !self.file.is_dir() {
             return Err(ENOTDIR);
         }
-        Ok(self.file.get_dirent(count))
+        let mut dirents = self.file.get_dirent(count);
+        if let Some(inode) = self.file.get_dirtree_node() {
+            inode.fix_special_dirents(&mut dirents);
+        }
+        Ok(dirents)
     }
     pub fn get_offset(&self) -> usize {
         self.lseek(0, SeekWhence::SEEK_CUR).unwrap()
@@ -198,6 +340,12 @@ impl FileDescriptor {
         if new_size < 0 || !self.writable() {
             return Err(EINVAL);
         }
+        if let Some(limit) = self.fsize_limit() {
+            if new_size as usize > limit {
+                Self::exceed_fsize();
+                return Err(EFBIG);
+            }
+        }
         // todo: support ETXTBSY
         self.file.truncate_size(new_size as usize)
     }
@@ -210,6 +358,57 @@ impl FileDescriptor {
         self.file.set_timestamp(ctime, atime, mtime);
         Ok(())
     }
+    /// Only the owner or root may change the mode,
+    /// the set-group-ID bit is dropped if the caller is not in the group of the file
+    pub fn chmod(&self, mut mode: u32) -> Result<(), isize> {
+        if let Some(cred) = current_cred() {
+            let stat = self.file.get_stat();
+            if !cred.is_privileged() {
+                if cred.euid != stat.get_uid() {
+                    return Err(EPERM);
+                }
+                if !cred.in_group(stat.get_gid()) {
+                    mode &= !StatMode::S_ISGID.bits();
+                }
+            }
+        }
+        self.file.chmod(mode)
+    }
+    /// `u32::MAX` keeps the ID. Only root may give a file away,
+    /// the owner may change the group to one it is in.
+    pub fn chown(&self, uid: u32, gid: u32) -> Result<(), isize> {
+        let stat = self.file.get_stat();
+        let (old_uid, old_gid) = (stat.get_uid(), stat.get_gid());
+        match current_cred() {
+            Some(cred) if !cred.is_privileged() => {
+                let owner = cred.euid == old_uid;
+                if uid != u32::MAX && !(owner && uid == old_uid) {
+                    return Err(EPERM);
+                }
+                if gid != u32::MAX && !(owner && (gid == old_gid || cred.in_group(gid))) {
+                    return Err(EPERM);
+                }
+            }
+            _ => {}
+        }
+        if uid == u32::MAX && gid == u32::MAX {
+            return Ok(());
+        }
+        self.file.chown(
+            if uid == u32::MAX { old_uid } else { uid },
+            if gid == u32::MAX { old_gid } else { gid },
+        )?;
+        // a program changing hands loses its set-user-ID and set-group-ID bits
+        let mode = stat.get_mode();
+        let mut clear = StatMode::S_ISUID.bits();
+        if mode & StatMode::S_IXGRP.bits() != 0 {
+            clear |= StatMode::S_ISGID.bits();
+        }
+        if mode & StatMode::S_IFMT.bits() != StatMode::S_IFDIR.bits() && mode & clear != 0 {
+            self.file.chmod(mode & !clear)?;
+        }
+        Ok(())
+    }
     pub fn get_single_cache(&self, offset: usize) -> Result<Arc<Mutex<PageCache>>, ()> {
         self.file.get_single_cache(offset)
     }
@@ -219,9 +418,9 @@ impl FileDescriptor {
     pub fn ioctl(&self, cmd: u32, argp: usize) -> isize {
         self.file.ioctl(cmd, argp)
     }
-    // for execve
-    pub fn map_to_kernel_space(&self, addr: usize) -> &'static [u8] {
-        let caches = self.get_all_caches().unwrap();
+    // for execve, `EIO` if some page of the file can't be read in
+    pub fn map_to_kernel_space(&self, addr: usize) -> Result<&'static [u8], isize> {
+        let caches = self.get_all_caches().map_err(|_| EIO)?;
         let frames = caches
             .iter()
             .map(|cache| Frame::InMemory(cache.try_lock().unwrap().get_tracker()))
@@ -235,7 +434,7 @@ impl FileDescriptor {
                 frames,
             )
             .unwrap();
-        unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, self.get_size()) }
+        Ok(unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, self.get_size()) })
     }
     pub fn linkat(
         old_fd: &Self,
@@ -1,14 +1,62 @@
 use crate::{
-    mm::try_get_from_user, syscall::errno::EFAULT, task::signal::Signals, timer::TimeSpec,
+    fs::file_trait::File,
+    mm::try_get_from_user,
+    syscall::errno::{EFAULT, EINTR},
+    task::signal::Signals,
+    timer::TimeSpec,
 };
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::ptr::null_mut;
+use spin::Mutex;
 
 use crate::{
     mm::{copy_from_user_array, copy_to_user_array},
-    task::{current_task, sigprocmask, suspend_current_and_run_next, SigMaskHow},
+    task::{
+        block_current_and_run_next, current_task, sigprocmask, wait_with_timeout, SigMaskHow,
+        WaitQueue,
+    },
 };
 
+/// Sleep till one of `files` may have become ready, a signal arrives or `timeout` passes.
+/// Files without wait queues are looked at again on the next timer interrupt.
+/// Returns `Err(EINTR)` if a signal is pending.
+fn wait_for_files(files: &[Arc<dyn File>], timeout: Option<TimeSpec>) -> Result<(), isize> {
+    let task = current_task().unwrap();
+    if task.signal_pending() {
+        return Err(EINTR);
+    }
+    let waiter = Arc::downgrade(&task);
+    let mut timeout = timeout;
+    let mut wait_queues: Vec<Arc<Mutex<WaitQueue>>> = Vec::new();
+    for file in files {
+        let file_queues = file.wait_queues();
+        if file_queues.is_empty() {
+            timeout = Some(TimeSpec::now());
+        }
+        for wait_queue in file_queues {
+            if !wait_queues
+                .iter()
+                .any(|queued| Arc::ptr_eq(queued, &wait_queue))
+            {
+                wait_queues.push(wait_queue);
+            }
+        }
+    }
+    for wait_queue in wait_queues.iter() {
+        wait_queue.lock().add_task(waiter.clone());
+    }
+    if let Some(timeout) = timeout {
+        wait_with_timeout(waiter.clone(), timeout);
+    }
+    drop(task);
+    block_current_and_run_next();
+    for wait_queue in wait_queues.iter() {
+        wait_queue.lock().remove(&waiter);
+    }
+    Ok(())
+}
+
 ///  A scheduling  scheme  whereby  the  local  process  periodically  checks  until  the  pre-specified events (for example, read, write) have occurred.
 /// The PollFd struct in 32-bit style.
 #[repr(C)]
@@ -133,11 +181,13 @@ pub fn ppoll(
         loop {
             let task = current_task().unwrap();
             let fd_table = task.files.lock();
+            let mut files = Vec::with_capacity(nfds);
 
             for poll_fd in poll_fd.iter_mut() {
                 let fd = poll_fd.fd as usize;
                 match fd_table.get_ref(fd) {
                     Ok(file_descriptor) => {
+                        files.push(file_descriptor.file.clone());
                         let mut trigger = 0;
                         if file_descriptor.file.hang_up() {
                             poll_fd.revents |= PollEvent::POLLHUP;
@@ -170,11 +220,16 @@ pub fn ppoll(
             }
             drop(fd_table);
             drop(task);
-            suspend_current_and_run_next();
+            if let Err(errno) = wait_for_files(&files, timeout) {
+                done = errno;
+                break;
+            }
         }
 
         log::trace!("[ppoll] result: {:?}", poll_fd);
-        copy_to_user_array(token, &poll_fd[0], fds, nfds).unwrap();
+        if nfds > 0 {
+            copy_to_user_array(token, &poll_fd[0], fds, nfds).unwrap();
+        }
     } else {
         log::error!(
             "[ppoll] Error copy_from_user_array(_, fds: {:?}, poll_fd.as_mut_ptr():{:?}, _)",
@@ -316,6 +371,7 @@ pub fn pselect(
     loop {
         let task = current_task().unwrap();
         let fd_table = task.files.lock();
+        let mut files = Vec::new();
 
         // check read
         if let Some(ref read_fds) = read_fds {
@@ -324,6 +380,7 @@ pub fn pselect(
                     continue;
                 }
                 if let Ok(fd) = fd_table.get_ref(i) {
+                    files.push(fd.file.clone());
                     if fd.r_ready() {
                         done += 1;
                     }
@@ -337,6 +394,7 @@ pub fn pselect(
                     continue;
                 }
                 if let Ok(fd) = fd_table.get_ref(i) {
+                    files.push(fd.file.clone());
                     if fd.w_ready() {
                         done += 1;
                     }
@@ -357,7 +415,21 @@ pub fn pselect(
 
         drop(fd_table);
         drop(task);
-        suspend_current_and_run_next();
+        if let Err(errno) = wait_for_files(&files, timeout) {
+            // the sets are left as they were
+            done = errno;
+            break;
+        }
+    }
+    if done < 0 {
+        if !sigmask.is_null() {
+            sigprocmask(
+                SigMaskHow::SIG_SETMASK.bits(),
+                oldsig,
+                null_mut::<Signals>(),
+            );
+        }
+        return done;
     }
     let task = current_task().unwrap();
     let fd_table = task.files.lock();
@@ -0,0 +1,227 @@
+use crate::fs::DiskInodeType;
+use alloc::{
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::mem::size_of;
+use spin::Mutex;
+
+use super::ProcLink;
+use crate::{
+    fs::{
+        directory_tree::DirectoryTreeNode,
+        file_trait::File,
+        layout::{Dirent, Stat},
+        OpenFlags, SeekWhence, StatMode,
+    },
+    mm::UserBuffer,
+    syscall::errno::{EACCES, EINVAL, EISDIR},
+};
+
+pub type ProcEntries = Vec<(String, Arc<dyn File>)>;
+
+/// Directory under `/proc`.
+/// Its children come from `generator`, which is called again on every path lookup,
+/// an `Err` means the object behind this directory has gone (e.g. the process exited).
+pub struct ProcDir {
+    generator: Arc<dyn Fn() -> Result<ProcEntries, isize> + Send + Sync>,
+    /// Name and type of the children when the directory was opened, for `getdents()`
+    dirents: Vec<(String, u8)>,
+    offset: Mutex<usize>,
+    dirnode_ptr: Arc<Mutex<Weak<DirectoryTreeNode>>>,
+}
+
+impl ProcDir {
+    pub fn new(
+        generator: impl Fn() -> Result<ProcEntries, isize> + Send + Sync + 'static,
+    ) -> Arc<dyn File> {
+        Arc::new(Self {
+            generator: Arc::new(generator),
+            dirents: Vec::new(),
+            offset: Mutex::new(0),
+            dirnode_ptr: Arc::new(Mutex::new(Weak::new())),
+        })
+    }
+    fn snapshot(&self) -> Self {
+        const DT_DIR: u8 = 4;
+        const DT_REG: u8 = 8;
+        const DT_LNK: u8 = 10;
+        let mut dirents = alloc::vec![(".".to_string(), DT_DIR), ("..".to_string(), DT_DIR)];
+        if let Ok(entries) = (self.generator)() {
+            dirents.extend(entries.into_iter().map(|(name, file)| {
+                let d_type = if file.is::<ProcLink>() {
+                    DT_LNK
+                } else if file.is_dir() {
+                    DT_DIR
+                } else {
+                    DT_REG
+                };
+                (name, d_type)
+            }));
+        }
+        Self {
+            generator: self.generator.clone(),
+            dirents,
+            offset: Mutex::new(0),
+            dirnode_ptr: self.dirnode_ptr.clone(),
+        }
+    }
+}
+
+#[allow(unused)]
+impl File for ProcDir {
+    fn deep_clone(&self) -> Arc<dyn File> {
+        Arc::new(Self {
+            generator: self.generator.clone(),
+            dirents: self.dirents.clone(),
+            offset: Mutex::new(*self.offset.lock()),
+            dirnode_ptr: self.dirnode_ptr.clone(),
+        })
+    }
+
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+
+    fn read(&self, offset: Option<&mut usize>, buf: &mut [u8]) -> usize {
+        0
+    }
+
+    fn write(&self, offset: Option<&mut usize>, buf: &[u8]) -> usize {
+        0
+    }
+
+    fn r_ready(&self) -> bool {
+        true
+    }
+
+    fn w_ready(&self) -> bool {
+        false
+    }
+
+    fn read_user(&self, offset: Option<usize>, buf: UserBuffer) -> usize {
+        0
+    }
+
+    fn write_user(&self, offset: Option<usize>, buf: UserBuffer) -> usize {
+        0
+    }
+
+    fn get_size(&self) -> usize {
+        0
+    }
+
+    fn get_stat(&self) -> Stat {
+        Stat::new(
+            crate::makedev!(0, 4),
+            1,
+            StatMode::S_IFDIR.bits() | 0o555,
+            2,
+            0,
+            0,
+            0,
+            0,
+            0,
+        )
+    }
+
+    fn get_file_type(&self) -> DiskInodeType {
+        DiskInodeType::Directory
+    }
+
+    fn info_dirtree_node(&self, dirnode_ptr: Weak<DirectoryTreeNode>) {
+        *self.dirnode_ptr.lock() = dirnode_ptr;
+    }
+
+    fn get_dirtree_node(&self) -> Option<Arc<DirectoryTreeNode>> {
+        self.dirnode_ptr.lock().upgrade()
+    }
+
+    fn open(&self, flags: OpenFlags, special_use: bool) -> Result<Arc<dyn File>, isize> {
+        Ok(Arc::new(self.snapshot()))
+    }
+
+    fn open_subfile(&self) -> Result<Vec<(String, Arc<dyn File>)>, isize> {
+        (self.generator)()
+    }
+
+    fn create(&self, name: &str, file_type: DiskInodeType) -> Result<Arc<dyn File>, isize> {
+        Err(EACCES)
+    }
+
+    fn link_child(&self, name: &str, child: &Self) -> Result<(), isize>
+    where
+        Self: Sized,
+    {
+        Err(EACCES)
+    }
+
+    fn unlink(&self, delete: bool) -> Result<(), isize> {
+        Err(EACCES)
+    }
+
+    fn get_dirent(&self, count: usize) -> Vec<Dirent> {
+        let mut offset = self.offset.lock();
+        let dirents: Vec<Dirent> = self
+            .dirents
+            .iter()
+            .enumerate()
+            .skip(*offset)
+            .take(count / size_of::<Dirent>())
+            .map(|(idx, (name, d_type))| {
+                Dirent::new(idx + 1, (idx + 1) as isize, *d_type, name.as_str())
+            })
+            .collect();
+        *offset += dirents.len();
+        dirents
+    }
+
+    fn lseek(&self, offset: isize, whence: SeekWhence) -> Result<usize, isize> {
+        let mut dir_offset = self.offset.lock();
+        let new_offset = match whence {
+            SeekWhence::SEEK_SET => offset,
+            SeekWhence::SEEK_CUR => *dir_offset as isize + offset,
+            _ => return Err(EINVAL),
+        };
+        if new_offset < 0 {
+            return Err(EINVAL);
+        }
+        *dir_offset = new_offset as usize;
+        Ok(new_offset as usize)
+    }
+
+    fn modify_size(&self, diff: isize) -> Result<(), isize> {
+        Err(EISDIR)
+    }
+
+    fn truncate_size(&self, new_size: usize) -> Result<(), isize> {
+        Err(EISDIR)
+    }
+
+    fn set_timestamp(&self, ctime: Option<usize>, atime: Option<usize>, mtime: Option<usize>) {}
+
+    fn get_single_cache(&self, offset: usize) -> Result<Arc<Mutex<crate::fs::PageCache>>, ()> {
+        Err(())
+    }
+
+    fn get_all_caches(&self) -> Result<Vec<Arc<Mutex<crate::fs::PageCache>>>, ()> {
+        Err(())
+    }
+
+    fn oom(&self) -> usize {
+        0
+    }
+
+    fn hang_up(&self) -> bool {
+        false
+    }
+
+    fn fcntl(&self, cmd: u32, arg: u32) -> isize {
+        todo!()
+    }
+}
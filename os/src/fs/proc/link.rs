@@ -0,0 +1,166 @@
+use crate::fs::DiskInodeType;
+use alloc::{
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use spin::Mutex;
+
+use crate::{
+    fs::{
+        directory_tree::DirectoryTreeNode,
+        file_trait::File,
+        layout::{Dirent, Stat},
+        OpenFlags, SeekWhence, StatMode,
+    },
+    mm::UserBuffer,
+    syscall::errno::{EACCES, ENOENT, ENOTDIR, ESPIPE},
+};
+
+/// Symbolic link under `/proc`, such as `/proc/<pid>/exe`.
+/// Path lookup doesn't follow it, only `readlink()` does.
+pub struct ProcLink {
+    target: Arc<dyn Fn() -> Option<String> + Send + Sync>,
+}
+
+impl ProcLink {
+    pub fn new(target: impl Fn() -> Option<String> + Send + Sync + 'static) -> Arc<dyn File> {
+        Arc::new(Self {
+            target: Arc::new(target),
+        })
+    }
+}
+
+#[allow(unused)]
+impl File for ProcLink {
+    fn deep_clone(&self) -> Arc<dyn File> {
+        Arc::new(Self {
+            target: self.target.clone(),
+        })
+    }
+
+    fn readable(&self) -> bool {
+        false
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+
+    fn read(&self, offset: Option<&mut usize>, buf: &mut [u8]) -> usize {
+        0
+    }
+
+    fn write(&self, offset: Option<&mut usize>, buf: &[u8]) -> usize {
+        0
+    }
+
+    fn r_ready(&self) -> bool {
+        false
+    }
+
+    fn w_ready(&self) -> bool {
+        false
+    }
+
+    fn read_user(&self, offset: Option<usize>, buf: UserBuffer) -> usize {
+        0
+    }
+
+    fn write_user(&self, offset: Option<usize>, buf: UserBuffer) -> usize {
+        0
+    }
+
+    fn get_size(&self) -> usize {
+        0
+    }
+
+    fn get_stat(&self) -> Stat {
+        Stat::new(
+            crate::makedev!(0, 4),
+            1,
+            StatMode::S_IFLNK.bits() | 0o777,
+            1,
+            0,
+            0,
+            0,
+            0,
+            0,
+        )
+    }
+
+    fn get_file_type(&self) -> DiskInodeType {
+        DiskInodeType::File
+    }
+
+    fn info_dirtree_node(&self, dirnode_ptr: Weak<DirectoryTreeNode>) {}
+
+    fn get_dirtree_node(&self) -> Option<Arc<DirectoryTreeNode>> {
+        None
+    }
+
+    fn open(&self, flags: OpenFlags, special_use: bool) -> Result<Arc<dyn File>, isize> {
+        Ok(self.deep_clone())
+    }
+
+    fn open_subfile(&self) -> Result<Vec<(String, Arc<dyn File>)>, isize> {
+        Err(ENOTDIR)
+    }
+
+    fn create(&self, name: &str, file_type: DiskInodeType) -> Result<Arc<dyn File>, isize> {
+        Err(ENOTDIR)
+    }
+
+    fn link_child(&self, name: &str, child: &Self) -> Result<(), isize>
+    where
+        Self: Sized,
+    {
+        Err(ENOTDIR)
+    }
+
+    fn unlink(&self, delete: bool) -> Result<(), isize> {
+        Err(EACCES)
+    }
+
+    fn get_dirent(&self, count: usize) -> Vec<Dirent> {
+        Vec::new()
+    }
+
+    fn lseek(&self, offset: isize, whence: SeekWhence) -> Result<usize, isize> {
+        Err(ESPIPE)
+    }
+
+    fn modify_size(&self, diff: isize) -> Result<(), isize> {
+        Err(EACCES)
+    }
+
+    fn truncate_size(&self, new_size: usize) -> Result<(), isize> {
+        Err(EACCES)
+    }
+
+    fn set_timestamp(&self, ctime: Option<usize>, atime: Option<usize>, mtime: Option<usize>) {}
+
+    fn get_single_cache(&self, offset: usize) -> Result<Arc<Mutex<crate::fs::PageCache>>, ()> {
+        Err(())
+    }
+
+    fn get_all_caches(&self) -> Result<Vec<Arc<Mutex<crate::fs::PageCache>>>, ()> {
+        Err(())
+    }
+
+    fn oom(&self) -> usize {
+        0
+    }
+
+    fn hang_up(&self) -> bool {
+        false
+    }
+
+    fn fcntl(&self, cmd: u32, arg: u32) -> isize {
+        todo!()
+    }
+
+    fn read_link(&self) -> Result<String, isize> {
+        (self.target)().ok_or(ENOENT)
+    }
+}
@@ -0,0 +1,367 @@
+//! Synthetic file system mounted at `/proc`.
+//! Nothing here is stored, every file is generated from kernel state when it is opened,
+//! and every directory lists its children again on each lookup.
+mod dir;
+mod link;
+mod pid;
+mod sys;
+
+pub use dir::{ProcDir, ProcEntries};
+pub use link::ProcLink;
+use pid::{pid_dir, Process};
+use sys::sys_dir;
+
+use crate::fs::DiskInodeType;
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+use crate::{
+    arch::{cpu_online_mask, cpuinfo, BLOCK_SZ, TICKS_PER_SEC},
+    config::PAGE_SIZE,
+    fs::{
+        cache::CACHE_STATS,
+        directory_tree::{proc_mounts, DirectoryTreeNode},
+        file_trait::File,
+        layout::Stat,
+        OpenFlags, SeekWhence, StatMode,
+    },
+    mm::{total_frames, unallocated_frames, UserBuffer},
+    syscall::errno::{EACCES, EINVAL, ENOTDIR},
+    task::{all_tasks, TaskStatus},
+    timer::get_time_ms,
+};
+
+/// File under `/proc`.
+/// The content is generated by `generator` when the file is opened,
+/// so readers always see a consistent snapshot of kernel state.
+pub struct ProcFile {
+    generator: Arc<dyn Fn() -> String + Send + Sync>,
+    /// Takes every write as a whole, files without it are read-only
+    writer: Option<Arc<dyn Fn(&[u8]) -> Result<(), isize> + Send + Sync>>,
+    content: Vec<u8>,
+    offset: Mutex<usize>,
+}
+
+impl ProcFile {
+    pub fn new(generator: impl Fn() -> String + Send + Sync + 'static) -> Arc<dyn File> {
+        Arc::new(Self {
+            generator: Arc::new(generator),
+            writer: None,
+            content: Vec::new(),
+            offset: Mutex::new(0),
+        })
+    }
+    /// A file that also takes writes, like the control files under `/proc/sys`
+    pub fn new_writable(
+        generator: impl Fn() -> String + Send + Sync + 'static,
+        writer: impl Fn(&[u8]) -> Result<(), isize> + Send + Sync + 'static,
+    ) -> Arc<dyn File> {
+        Arc::new(Self {
+            generator: Arc::new(generator),
+            writer: Some(Arc::new(writer)),
+            content: Vec::new(),
+            offset: Mutex::new(0),
+        })
+    }
+    fn snapshot(&self) -> Self {
+        Self {
+            generator: self.generator.clone(),
+            writer: self.writer.clone(),
+            content: (self.generator)().into_bytes(),
+            offset: Mutex::new(0),
+        }
+    }
+}
+
+/// Root of a procfs instance
+pub fn root() -> Arc<dyn File> {
+    ProcDir::new(|| {
+        let mut entries: ProcEntries = alloc::vec![
+            ("cpuinfo".to_string(), ProcFile::new(cpuinfo)),
+            ("loadavg".to_string(), ProcFile::new(loadavg)),
+            ("meminfo".to_string(), ProcFile::new(meminfo)),
+            ("mounts".to_string(), ProcFile::new(proc_mounts)),
+            ("stat".to_string(), ProcFile::new(stat)),
+            ("self".to_string(), pid_dir(Process::Current)),
+            ("sys".to_string(), sys_dir()),
+            ("uptime".to_string(), ProcFile::new(uptime)),
+            ("vmstat".to_string(), ProcFile::new(vmstat)),
+        ];
+        let mut tgids: Vec<usize> = all_tasks().iter().map(|task| task.tgid).collect();
+        tgids.sort_unstable();
+        tgids.dedup();
+        entries.extend(
+            tgids
+                .into_iter()
+                .map(|tgid| (tgid.to_string(), pid_dir(Process::Tgid(tgid)))),
+        );
+        Ok(entries)
+    })
+}
+
+/// Content of `/proc/meminfo`
+fn meminfo() -> String {
+    const KB_PER_PAGE: usize = PAGE_SIZE / 1024;
+    let total = total_frames() * KB_PER_PAGE;
+    let free = unallocated_frames() * KB_PER_PAGE;
+    let buffers = CACHE_STATS.buffers.load(Ordering::Relaxed) * BLOCK_SZ / 1024;
+    let cached = CACHE_STATS.cached_pages.load(Ordering::Relaxed) * KB_PER_PAGE;
+    [
+        ("MemTotal", total),
+        ("MemFree", free),
+        ("MemAvailable", free),
+        ("Buffers", buffers),
+        ("Cached", cached),
+        ("SwapCached", 0),
+        ("SwapTotal", 0),
+        ("SwapFree", 0),
+        ("Shmem", 0),
+    ]
+    .iter()
+    .map(|(name, kb)| format!("{:<16}{:>8} kB\n", format!("{}:", name), kb))
+    .collect()
+}
+
+/// Content of `/proc/vmstat`, `pgpgin` and `pgpgout` are in KiB like Linux,
+/// the rest are counters of the buffer and page caches
+fn vmstat() -> String {
+    let stats = &CACHE_STATS;
+    let get = |counter: &AtomicUsize| counter.load(Ordering::Relaxed);
+    [
+        ("nr_file_pages", get(&stats.cached_pages)),
+        ("nr_buffers", get(&stats.buffers)),
+        ("pgpgin", get(&stats.blocks_read) * BLOCK_SZ / 1024),
+        ("pgpgout", get(&stats.blocks_written) * BLOCK_SZ / 1024),
+        ("buffer_cache_hit", get(&stats.buffer_hits)),
+        ("buffer_cache_miss", get(&stats.buffer_misses)),
+        ("buffer_cache_evict", get(&stats.buffer_evictions)),
+        ("page_cache_hit", get(&stats.page_hits)),
+        ("page_cache_miss", get(&stats.page_misses)),
+        ("page_cache_evict", get(&stats.page_evictions)),
+        ("writeback_runs", get(&stats.writeback_runs)),
+    ]
+    .iter()
+    .map(|(name, value)| format!("{} {}\n", name, value))
+    .collect()
+}
+
+/// Content of `/proc/uptime`, the second column is the idle time
+fn uptime() -> String {
+    let ms = get_time_ms();
+    format!("{}.{:02} 0.00\n", ms / 1000, ms % 1000 / 10)
+}
+
+/// Content of `/proc/loadavg`, we don't keep load averages
+fn loadavg() -> String {
+    let tasks = all_tasks();
+    let running = tasks
+        .iter()
+        .filter(|task| task.acquire_inner_lock().task_status != TaskStatus::Interruptible)
+        .count();
+    let last_pid = tasks.iter().map(|task| task.pid.0).max().unwrap_or(0);
+    format!("0.00 0.00 0.00 {}/{} {}\n", running, tasks.len(), last_pid)
+}
+
+/// Content of `/proc/stat`, time is in `USER_HZ`, which equals `TICKS_PER_SEC`.
+/// CPU time of all cores is summed up in the `cpu` line.
+fn stat() -> String {
+    let (mut user, mut system, mut running, mut blocked) = (0, 0, 0, 0);
+    for task in all_tasks() {
+        let inner = task.acquire_inner_lock();
+        user += pid::clock_ticks(&inner.rusage.ru_utime);
+        system += pid::clock_ticks(&inner.rusage.ru_stime);
+        match inner.task_status {
+            TaskStatus::Interruptible => blocked += 1,
+            _ => running += 1,
+        }
+    }
+    let cores = cpu_online_mask().count_ones() as usize;
+    let idle = (get_time_ms() * TICKS_PER_SEC / 1000 * cores).saturating_sub(user + system);
+    format!(
+        "cpu  {} 0 {} {} 0 0 0 0 0 0\nprocs_running {}\nprocs_blocked {}\n",
+        user, system, idle, running, blocked
+    )
+}
+
+#[allow(unused)]
+impl File for ProcFile {
+    fn deep_clone(&self) -> Arc<dyn File> {
+        Arc::new(Self {
+            generator: self.generator.clone(),
+            writer: self.writer.clone(),
+            content: self.content.clone(),
+            offset: Mutex::new(*self.offset.lock()),
+        })
+    }
+
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    fn read(&self, offset: Option<&mut usize>, buf: &mut [u8]) -> usize {
+        let mut file_offset = self.offset.lock();
+        let offset = match offset {
+            Some(offset) => offset,
+            None => &mut *file_offset,
+        };
+        if *offset >= self.content.len() {
+            return 0;
+        }
+        let len = buf.len().min(self.content.len() - *offset);
+        buf[..len].copy_from_slice(&self.content[*offset..*offset + len]);
+        *offset += len;
+        len
+    }
+
+    fn write(&self, offset: Option<&mut usize>, buf: &[u8]) -> usize {
+        match &self.writer {
+            Some(writer) => match writer(buf) {
+                Ok(()) => buf.len(),
+                Err(errno) => errno as usize,
+            },
+            None => 0,
+        }
+    }
+
+    fn r_ready(&self) -> bool {
+        true
+    }
+
+    fn w_ready(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    fn read_user(&self, offset: Option<usize>, mut buf: UserBuffer) -> usize {
+        let mut file_offset = self.offset.lock();
+        let start = match offset {
+            Some(offset) => offset,
+            None => *file_offset,
+        };
+        if start >= self.content.len() {
+            return 0;
+        }
+        let len = buf.write(&self.content[start..]);
+        if offset.is_none() {
+            *file_offset += len;
+        }
+        len
+    }
+
+    fn write_user(&self, offset: Option<usize>, buf: UserBuffer) -> usize {
+        let mut data = alloc::vec![0u8; buf.len()];
+        buf.read(&mut data);
+        self.write(None, &data)
+    }
+
+    fn get_size(&self) -> usize {
+        self.content.len()
+    }
+
+    fn get_stat(&self) -> Stat {
+        let mode = if self.writer.is_some() { 0o644 } else { 0o444 };
+        // like Linux, files under /proc report zero size
+        Stat::new(
+            crate::makedev!(0, 4),
+            1,
+            StatMode::S_IFREG.bits() | mode,
+            1,
+            0,
+            0,
+            0,
+            0,
+            0,
+        )
+    }
+
+    fn get_file_type(&self) -> DiskInodeType {
+        DiskInodeType::File
+    }
+
+    fn info_dirtree_node(&self, dirnode_ptr: alloc::sync::Weak<DirectoryTreeNode>) {}
+
+    fn get_dirtree_node(&self) -> Option<Arc<DirectoryTreeNode>> {
+        None
+    }
+
+    fn open(&self, flags: OpenFlags, special_use: bool) -> Result<Arc<dyn File>, isize> {
+        Ok(Arc::new(self.snapshot()))
+    }
+
+    fn open_subfile(&self) -> Result<Vec<(String, Arc<dyn File>)>, isize> {
+        Err(ENOTDIR)
+    }
+
+    fn create(&self, name: &str, file_type: DiskInodeType) -> Result<Arc<dyn File>, isize> {
+        Err(ENOTDIR)
+    }
+
+    fn link_child(&self, name: &str, child: &Self) -> Result<(), isize>
+    where
+        Self: Sized,
+    {
+        Err(ENOTDIR)
+    }
+
+    fn unlink(&self, delete: bool) -> Result<(), isize> {
+        Err(EACCES)
+    }
+
+    fn get_dirent(&self, count: usize) -> Vec<crate::fs::layout::Dirent> {
+        Vec::new()
+    }
+
+    fn lseek(&self, offset: isize, whence: SeekWhence) -> Result<usize, isize> {
+        let mut file_offset = self.offset.lock();
+        let new_offset = match whence {
+            SeekWhence::SEEK_SET => offset,
+            SeekWhence::SEEK_CUR => *file_offset as isize + offset,
+            SeekWhence::SEEK_END => self.content.len() as isize + offset,
+            _ => return Err(EINVAL),
+        };
+        if new_offset < 0 {
+            return Err(EINVAL);
+        }
+        *file_offset = new_offset as usize;
+        Ok(new_offset as usize)
+    }
+
+    fn modify_size(&self, diff: isize) -> Result<(), isize> {
+        Err(EACCES)
+    }
+
+    fn truncate_size(&self, new_size: usize) -> Result<(), isize> {
+        Err(EACCES)
+    }
+
+    fn set_timestamp(&self, ctime: Option<usize>, atime: Option<usize>, mtime: Option<usize>) {}
+
+    fn get_single_cache(&self, offset: usize) -> Result<Arc<Mutex<crate::fs::PageCache>>, ()> {
+        Err(())
+    }
+
+    fn get_all_caches(&self) -> Result<Vec<Arc<Mutex<crate::fs::PageCache>>>, ()> {
+        Err(())
+    }
+
+    fn oom(&self) -> usize {
+        0
+    }
+
+    fn hang_up(&self) -> bool {
+        false
+    }
+
+    fn fcntl(&self, cmd: u32, arg: u32) -> isize {
+        todo!()
+    }
+}
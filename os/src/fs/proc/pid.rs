@@ -0,0 +1,375 @@
+//! `/proc/<pid>`, one directory per thread group.
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+
+use super::{ProcDir, ProcEntries, ProcFile, ProcLink};
+use crate::{
+    arch::{PageTableImpl, TICKS_PER_SEC},
+    config::PAGE_SIZE,
+    fs::{file_trait::File, Null, Pipe, Random, Socket, Teletype, Zero},
+    mm::{Frame, MapPermission, VirtAddr},
+    syscall::errno::ENOENT,
+    task::{
+        all_tasks, current_task, find_task_by_pid, find_task_by_tgid,
+        rlimit::{Resource, RLIM_INFINITY},
+        TaskControlBlock, TaskStatus,
+    },
+    timer::TimeVal,
+};
+use num_enum::FromPrimitive;
+
+/// `TimeVal` to `clock_t`, `USER_HZ` equals `TICKS_PER_SEC`
+pub fn clock_ticks(time: &TimeVal) -> usize {
+    time.tv_sec * TICKS_PER_SEC + time.tv_usec * TICKS_PER_SEC / 1_000_000
+}
+
+/// The thread group leader if it is still alive, otherwise any thread of the group
+fn thread_group(tgid: usize) -> Option<Arc<TaskControlBlock>> {
+    find_task_by_pid(tgid)
+        .filter(|task| task.tgid == tgid)
+        .or_else(|| find_task_by_tgid(tgid))
+}
+
+/// Process shown by a `/proc/<pid>` directory, looked up each time it is used,
+/// so a node in the directory tree always means the same thing
+#[derive(Clone, Copy)]
+pub enum Process {
+    Tgid(usize),
+    /// `/proc/self`, whoever is looking
+    Current,
+}
+
+impl Process {
+    fn task(self) -> Option<Arc<TaskControlBlock>> {
+        match self {
+            Process::Tgid(tgid) => thread_group(tgid),
+            Process::Current => thread_group(current_task()?.tgid),
+        }
+    }
+}
+
+/// Generate the content of a file from `process`,
+/// the file is empty if the process has exited
+fn task_file(process: Process, generator: fn(&Arc<TaskControlBlock>) -> String) -> Arc<dyn File> {
+    ProcFile::new(move || {
+        process
+            .task()
+            .map_or_else(String::new, |task| generator(&task))
+    })
+}
+
+pub fn pid_dir(process: Process) -> Arc<dyn File> {
+    ProcDir::new(move || {
+        if process.task().is_none() {
+            return Err(ENOENT);
+        }
+        let entries: ProcEntries = alloc::vec![
+            ("cmdline".to_string(), task_file(process, cmdline)),
+            (
+                "cwd".to_string(),
+                ProcLink::new(move || {
+                    let task = process.task()?;
+                    let fs = task.fs.lock();
+                    fs.working_inode.get_cwd()
+                }),
+            ),
+            (
+                "exe".to_string(),
+                ProcLink::new(move || {
+                    let task = process.task()?;
+                    let exe = task.exe.lock();
+                    exe.get_cwd()
+                }),
+            ),
+            ("fd".to_string(), fd_dir(process)),
+            ("limits".to_string(), task_file(process, limits)),
+            ("maps".to_string(), task_file(process, maps)),
+            ("stat".to_string(), task_file(process, stat)),
+            ("status".to_string(), task_file(process, status)),
+        ];
+        Ok(entries)
+    })
+}
+
+/// `/proc/<pid>/fd`, a link for each open file
+fn fd_dir(process: Process) -> Arc<dyn File> {
+    ProcDir::new(move || {
+        let task = process.task().ok_or(ENOENT)?;
+        let fd_table = task.files.lock();
+        Ok(fd_table
+            .iter()
+            .enumerate()
+            .filter(|(_, file_descriptor)| file_descriptor.is_some())
+            .map(|(fd, _)| {
+                let link = ProcLink::new(move || {
+                    let task = process.task()?;
+                    let fd_table = task.files.lock();
+                    fd_table
+                        .get_ref(fd)
+                        .ok()
+                        .map(|file_descriptor| fd_target(&file_descriptor.file))
+                });
+                (fd.to_string(), link)
+            })
+            .collect())
+    })
+}
+
+/// What `readlink("/proc/<pid>/fd/<fd>")` shows
+fn fd_target(file: &Arc<dyn File>) -> String {
+    // device files don't know their place in the directory tree
+    if file.is::<Teletype>() {
+        "/dev/tty".to_string()
+    } else if file.is::<Null>() {
+        "/dev/null".to_string()
+    } else if file.is::<Zero>() {
+        "/dev/zero".to_string()
+    } else if let Some(random) = file.downcast_ref::<Random>() {
+        random.path().to_string()
+    } else if file.is::<Pipe>() {
+        "pipe:[0]".to_string()
+    } else if file.is::<Socket>() {
+        "socket:[0]".to_string()
+    } else {
+        match file.get_dirtree_node() {
+            Some(inode) => inode.get_cwd(),
+            None => "anon_inode:[unknown]".to_string(),
+        }
+    }
+}
+
+/// Name of the executable, at most 15 characters like `task_struct.comm`
+fn comm(task: &Arc<TaskControlBlock>) -> String {
+    let path = task.exe.lock().get_cwd().unwrap_or_default();
+    path.rsplit('/')
+        .next()
+        .unwrap_or("")
+        .chars()
+        .take(15)
+        .collect()
+}
+
+fn state(status: TaskStatus, stopped: bool) -> (char, &'static str) {
+    match status {
+        _ if stopped => ('T', "stopped"),
+        TaskStatus::Ready | TaskStatus::Running => ('R', "running"),
+        TaskStatus::Interruptible => ('S', "sleeping"),
+        TaskStatus::Zombie => ('Z', "zombie"),
+    }
+}
+
+/// Virtual size and resident set size, in pages
+fn vm_pages(task: &Arc<TaskControlBlock>) -> (usize, usize) {
+    let vm = task.vm.lock();
+    vm.areas().iter().fold((0, 0), |(size, rss), area| {
+        let pages = area.get_end::<PageTableImpl>().0 - area.get_start::<PageTableImpl>().0;
+        let resident = area
+            .inner
+            .frames
+            .iter()
+            .filter(|frame| matches!(frame, Frame::InMemory(_)))
+            .count();
+        (size + pages, rss + resident)
+    })
+}
+
+fn threads(tgid: usize) -> usize {
+    all_tasks()
+        .iter()
+        .filter(|task| task.tgid == tgid)
+        .count()
+        .max(1)
+}
+
+fn cmdline(task: &Arc<TaskControlBlock>) -> String {
+    task.acquire_inner_lock()
+        .cmdline
+        .iter()
+        .map(|arg| format!("{}\0", arg))
+        .collect()
+}
+
+fn limits(task: &Arc<TaskControlBlock>) -> String {
+    const LIMITS: [(&str, &str); 16] = [
+        ("Max cpu time", "seconds"),
+        ("Max file size", "bytes"),
+        ("Max data size", "bytes"),
+        ("Max stack size", "bytes"),
+        ("Max core file size", "bytes"),
+        ("Max resident set", "bytes"),
+        ("Max processes", "processes"),
+        ("Max open files", "files"),
+        ("Max locked memory", "bytes"),
+        ("Max address space", "bytes"),
+        ("Max file locks", "locks"),
+        ("Max pending signals", "signals"),
+        ("Max msgqueue size", "bytes"),
+        ("Max nice priority", ""),
+        ("Max realtime priority", ""),
+        ("Max realtime timeout", "us"),
+    ];
+    let rlimits = *task.rlimit.lock();
+    let value = |limit: usize| {
+        if limit == RLIM_INFINITY {
+            "unlimited".to_string()
+        } else {
+            limit.to_string()
+        }
+    };
+    let mut content = format!(
+        "{:<25} {:<20} {:<20} {:<10}\n",
+        "Limit", "Soft Limit", "Hard Limit", "Units"
+    );
+    for (resource, (name, units)) in LIMITS.iter().enumerate() {
+        let limit = rlimits.get(Resource::from_primitive(resource as u32));
+        content += &format!(
+            "{:<25} {:<20} {:<20} {:<10}\n",
+            name,
+            value(limit.rlim_cur),
+            value(limit.rlim_max),
+            units
+        );
+    }
+    content
+}
+
+fn maps(task: &Arc<TaskControlBlock>) -> String {
+    let (heap_bottom, heap_pt) = {
+        let inner = task.acquire_inner_lock();
+        (inner.heap_bottom, inner.heap_pt)
+    };
+    let stack_top = task.ustack_base;
+    let vm = task.vm.lock();
+    vm.areas()
+        .iter()
+        .map(|area| {
+            let start = VirtAddr::from(area.get_start::<PageTableImpl>()).0;
+            let end = VirtAddr::from(area.get_end::<PageTableImpl>()).0;
+            let name = match &area.map_file {
+                Some(file) => file
+                    .get_dirtree_node()
+                    .map_or_else(String::new, |inode| inode.get_cwd()),
+                None if start <= heap_bottom && heap_pt <= end && heap_bottom != heap_pt => {
+                    "[heap]".to_string()
+                }
+                None if start < stack_top && stack_top <= end => "[stack]".to_string(),
+                None => String::new(),
+            };
+            let perm = |flag: MapPermission, ch: char| {
+                if area.map_perm.contains(flag) {
+                    ch
+                } else {
+                    '-'
+                }
+            };
+            let line = format!(
+                "{:08x}-{:08x} {}{}{}{} 00000000 00:00 0                          {}",
+                start,
+                end,
+                perm(MapPermission::R, 'r'),
+                perm(MapPermission::W, 'w'),
+                perm(MapPermission::X, 'x'),
+                if area.shared { 's' } else { 'p' },
+                name
+            );
+            format!("{}\n", line.trim_end())
+        })
+        .collect()
+}
+
+/// Content of `/proc/<pid>/stat`, see proc(5) for the fields
+fn stat(task: &Arc<TaskControlBlock>) -> String {
+    let comm = comm(task);
+    let (vm_size, vm_rss) = vm_pages(task);
+    let threads = threads(task.tgid);
+    let (priority, nice) = {
+        let sched = task.sched.lock();
+        if sched.policy.is_realtime() {
+            (-1 - sched.rt_priority as isize, 0)
+        } else {
+            (20 + sched.nice as isize, sched.nice)
+        }
+    };
+    let inner = task.acquire_inner_lock();
+    let ppid = inner
+        .parent
+        .as_ref()
+        .and_then(|parent| parent.upgrade())
+        .map_or(0, |parent| parent.tgid);
+    // fields we don't keep track of are 0, `rsslim` is unlimited
+    format!(
+        "{} ({}) {} {} {} {} 0 -1 0 0 0 0 0 {} {} 0 0 {} {} {} 0 0 {} {} {}{}\n",
+        task.tgid,
+        comm,
+        state(inner.task_status, inner.stopped).0,
+        ppid,
+        inner.pgid,
+        inner.sid,
+        clock_ticks(&inner.rusage.ru_utime),
+        clock_ticks(&inner.rusage.ru_stime),
+        priority,
+        nice,
+        threads,
+        vm_size * PAGE_SIZE,
+        vm_rss,
+        usize::MAX,
+        " 0".repeat(27),
+    )
+}
+
+/// Content of `/proc/<pid>/status`
+fn status(task: &Arc<TaskControlBlock>) -> String {
+    const KB_PER_PAGE: usize = PAGE_SIZE / 1024;
+    let comm = comm(task);
+    let (vm_size, vm_rss) = vm_pages(task);
+    let threads = threads(task.tgid);
+    let fd_size = task.files.lock().iter().count();
+    let cred = task.cred.lock().clone();
+    let groups: Vec<String> = cred.groups.iter().map(|gid| gid.to_string()).collect();
+    let inner = task.acquire_inner_lock();
+    let (state, state_name) = state(inner.task_status, inner.stopped);
+    let ppid = inner
+        .parent
+        .as_ref()
+        .and_then(|parent| parent.upgrade())
+        .map_or(0, |parent| parent.tgid);
+    format!(
+        "Name:\t{}\n\
+         State:\t{} ({})\n\
+         Tgid:\t{}\n\
+         Pid:\t{}\n\
+         PPid:\t{}\n\
+         TracerPid:\t0\n\
+         Uid:\t{}\t{}\t{}\t{}\n\
+         Gid:\t{}\t{}\t{}\t{}\n\
+         FDSize:\t{}\n\
+         Groups:\t{}\n\
+         VmSize:\t{:>8} kB\n\
+         VmRSS:\t{:>8} kB\n\
+         Threads:\t{}\n",
+        comm,
+        state,
+        state_name,
+        task.tgid,
+        task.tgid,
+        ppid,
+        // the file system IDs are always the effective ones
+        cred.uid,
+        cred.euid,
+        cred.suid,
+        cred.euid,
+        cred.gid,
+        cred.egid,
+        cred.sgid,
+        cred.egid,
+        fd_size,
+        groups.join(" "),
+        vm_size * KB_PER_PAGE,
+        vm_rss * KB_PER_PAGE,
+        threads,
+    )
+}
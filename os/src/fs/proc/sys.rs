@@ -0,0 +1,73 @@
+//! `/proc/sys`, only the tunables the kernel has are present.
+use super::{ProcDir, ProcEntries, ProcFile};
+use crate::fs::cache::DIRTY_WRITEBACK_CENTISECS;
+use crate::fs::file_trait::File;
+use crate::syscall::errno::{EINVAL, EPERM};
+use crate::task::binfmt::{misc_control, misc_names, misc_register, misc_status};
+use crate::task::cred::current_cred;
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::Arc,
+};
+use core::sync::atomic::Ordering;
+
+pub fn sys_dir() -> Arc<dyn File> {
+    ProcDir::new(|| {
+        Ok(alloc::vec![
+            (
+                "fs".to_string(),
+                ProcDir::new(|| Ok(alloc::vec![("binfmt_misc".to_string(), binfmt_misc_dir())])),
+            ),
+            ("vm".to_string(), vm_dir()),
+        ])
+    })
+}
+
+/// `/proc/sys/vm`
+fn vm_dir() -> Arc<dyn File> {
+    ProcDir::new(|| {
+        Ok(alloc::vec![(
+            "dirty_writeback_centisecs".to_string(),
+            ProcFile::new_writable(
+                || format!("{}\n", DIRTY_WRITEBACK_CENTISECS.load(Ordering::Relaxed)),
+                |buf| {
+                    if !current_cred().map_or(true, |cred| cred.is_privileged()) {
+                        return Err(EPERM);
+                    }
+                    let centisecs = core::str::from_utf8(buf)
+                        .ok()
+                        .and_then(|buf| buf.trim().parse().ok())
+                        .ok_or(EINVAL)?;
+                    DIRTY_WRITEBACK_CENTISECS.store(centisecs, Ordering::Relaxed);
+                    Ok(())
+                },
+            ),
+        )])
+    })
+}
+
+/// `/proc/sys/fs/binfmt_misc`, with a file for each registered entry
+fn binfmt_misc_dir() -> Arc<dyn File> {
+    ProcDir::new(|| {
+        let mut entries: ProcEntries = alloc::vec![
+            (
+                "register".to_string(),
+                ProcFile::new_writable(String::new, misc_register),
+            ),
+            (
+                "status".to_string(),
+                ProcFile::new_writable(|| misc_status(None), |buf| misc_control(None, buf)),
+            ),
+        ];
+        entries.extend(misc_names().into_iter().map(|name| {
+            let (status, control) = (name.clone(), name.clone());
+            let file = ProcFile::new_writable(
+                move || misc_status(Some(&status)),
+                move |buf| misc_control(Some(&control), buf),
+            );
+            (name, file)
+        }));
+        Ok(entries)
+    })
+}
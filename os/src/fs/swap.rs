@@ -1,9 +1,9 @@
 use alloc::{sync::Arc, vec::Vec};
 use spin::Mutex;
 
-use crate::{arch::BLOCK_SZ, config::PAGE_SIZE, drivers::BLOCK_DEVICE};
+use crate::{arch::BLOCK_SZ, config::PAGE_SIZE, drivers::BLOCK_DEVICE, mm::MemoryError};
 
-use super::directory_tree::FILE_SYSTEM;
+use super::{directory_tree::FILE_SYSTEM, fat32::EasyFileSystem};
 use lazy_static::*;
 
 lazy_static! {
@@ -27,7 +27,17 @@ const BLK_PER_PG: usize = PAGE_SIZE / BLOCK_SZ;
 const SWAP_SIZE: usize = 1024 * 1024;
 impl Swap {
     /// size: the number of megabytes in swap
+    /// # Note
+    /// Swap slots are clusters taken from the FAT32 root, there is no swap on other root file systems,
+    /// whose blocks `FILE_SYSTEM` does not know about.
     pub fn new(size: usize) -> Self {
+        if !EasyFileSystem::probe(&BLOCK_DEVICE) {
+            log::warn!("[swap] root file system is not FAT32, swap disabled");
+            return Self {
+                bitmap: Vec::new(),
+                block_ids: Vec::new(),
+            };
+        }
         let bit = size * (SWAP_SIZE / PAGE_SIZE); // 1MiB = 4KiB*256
         let vec_len = bit / usize::MAX.count_ones() as usize;
         let mut bitmap = Vec::<u64>::with_capacity(vec_len);
@@ -67,11 +77,11 @@ impl Swap {
     pub fn read(&mut self, swap_id: usize, buf: &mut [u8]) {
         Self::read_page(self.get_block_ids(swap_id), buf);
     }
-    pub fn write(&mut self, buf: &[u8]) -> Arc<SwapTracker> {
-        let swap_id = self.alloc_page().unwrap();
+    pub fn write(&mut self, buf: &[u8]) -> Result<Arc<SwapTracker>, MemoryError> {
+        let swap_id = self.alloc_page().ok_or(MemoryError::SwapIsFull)?;
         Self::write_page(self.get_block_ids(swap_id), buf);
         self.set_bit(swap_id);
-        Arc::new(SwapTracker(swap_id))
+        Ok(Arc::new(SwapTracker(swap_id)))
     }
     #[inline(always)]
     pub fn discard(&mut self, swap_id: usize) {
@@ -0,0 +1,754 @@
+use crate::config::PAGE_SIZE;
+use crate::fs::cache::PageCache;
+use crate::fs::directory_tree::DirectoryTreeNode;
+use crate::fs::file_trait::File;
+use crate::fs::*;
+#[cfg(feature = "oom_handler")]
+use crate::mm::MemoryError;
+use crate::mm::{frame_alloc, Frame, FrameTracker, UserBuffer};
+use crate::syscall::errno::*;
+use crate::timer::get_realtime_sec;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+/// Inode numbers are unique among all tmpfs instances
+static NEXT_INO: AtomicU64 = AtomicU64::new(1);
+
+enum Content {
+    /// `Frame::Unallocated` for holes
+    Regular {
+        pages: Vec<Frame>,
+        size: usize,
+    },
+    Directory(BTreeMap<String, Arc<TmpfsInodeInner>>),
+    Symlink(String),
+    Fifo(Arc<Fifo>),
+    /// Character or block device, `rdev` decides the driver behind it
+    Device(u64),
+    Socket,
+}
+
+pub struct TmpfsInodeInner {
+    ino: u64,
+    /// File type and permission bits, as `st_mode`
+    mode: Mutex<u32>,
+    /// (uid, gid) of the owner
+    owner: Mutex<(u32, u32)>,
+    nlink: Mutex<u32>,
+    /// (atime, mtime, ctime) in seconds
+    time: Mutex<(usize, usize, usize)>,
+    content: Mutex<Content>,
+}
+
+/// Make page `index` resident and return it.
+/// A hole gets a new zeroed page if `alloc`, otherwise it is left as is.
+fn resident(pages: &mut [Frame], index: usize, alloc: bool) -> Option<Arc<FrameTracker>> {
+    let frame = &mut pages[index];
+    match frame {
+        Frame::InMemory(_) => {}
+        Frame::Unallocated => {
+            if !alloc {
+                return None;
+            }
+            frame.insert_in_memory(frame_alloc()?).unwrap();
+        }
+        #[cfg(feature = "oom_handler")]
+        Frame::Compressed(_) => {
+            frame.unzip().unwrap();
+        }
+        #[cfg(feature = "oom_handler")]
+        Frame::SwappedOut(_) => {
+            frame.swap_in().unwrap();
+        }
+    }
+    match frame {
+        Frame::InMemory(tracker) => Some(tracker.clone()),
+        _ => unreachable!(),
+    }
+}
+
+/// `major()` and `minor()` of glibc
+fn major(dev: u64) -> u64 {
+    ((dev >> 8) & 0xfff) | ((dev >> 32) & !0xfff)
+}
+fn minor(dev: u64) -> u64 {
+    (dev & 0xff) | ((dev >> 12) & !0xff)
+}
+
+/// The driver of a device node, `None` if there is no such device
+fn device_file(mode: u32, rdev: u64) -> Option<Arc<dyn File>> {
+    if mode & StatMode::S_IFMT.bits() != StatMode::S_IFCHR.bits() {
+        return None;
+    }
+    let device: Arc<dyn File> = match (major(rdev), minor(rdev)) {
+        (1, 3) => Arc::new(Null),
+        (1, 5) => Arc::new(Zero),
+        (5, 0) => TTY.clone(),
+        _ => return None,
+    };
+    Some(device)
+}
+
+impl TmpfsInodeInner {
+    fn new(mode: u32, content: Content) -> Arc<Self> {
+        let now = get_realtime_sec();
+        Arc::new(Self {
+            ino: NEXT_INO.fetch_add(1, Ordering::Relaxed),
+            mode: Mutex::new(mode),
+            owner: Mutex::new((0, 0)),
+            nlink: Mutex::new(1),
+            time: Mutex::new((now, now, now)),
+            content: Mutex::new(content),
+        })
+    }
+    fn file_type(&self) -> u32 {
+        *self.mode.lock() & StatMode::S_IFMT.bits()
+    }
+    fn is_dir(&self) -> bool {
+        self.file_type() == StatMode::S_IFDIR.bits()
+    }
+    /// `IFTODT()`, `d_type` of a dirent is the file type bits of `st_mode`
+    fn d_type(&self) -> u8 {
+        (self.file_type() >> 12) as u8
+    }
+    /// Update mtime and ctime
+    fn modified(&self) {
+        let now = get_realtime_sec();
+        let mut time = self.time.lock();
+        time.1 = now;
+        time.2 = now;
+    }
+    /// Update ctime
+    fn changed(&self) {
+        self.time.lock().2 = get_realtime_sec();
+    }
+    fn size(&self) -> usize {
+        match &*self.content.lock() {
+            Content::Regular { size, .. } => *size,
+            Content::Symlink(target) => target.len(),
+            _ => 0,
+        }
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let mut content = self.content.lock();
+        let (pages, size) = match &mut *content {
+            Content::Regular { pages, size } => (pages, *size),
+            _ => return 0,
+        };
+        if offset >= size {
+            return 0;
+        }
+        let end = size.min(offset + buf.len());
+        let mut pos = offset;
+        while pos < end {
+            let page_offset = pos % PAGE_SIZE;
+            let len = (PAGE_SIZE - page_offset).min(end - pos);
+            let dst = &mut buf[pos - offset..pos - offset + len];
+            match resident(pages, pos / PAGE_SIZE, false) {
+                Some(tracker) => dst.copy_from_slice(
+                    &tracker.ppn.get_bytes_array()[page_offset..page_offset + len],
+                ),
+                None => dst.fill(0),
+            }
+            pos += len;
+        }
+        end - offset
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        let mut content = self.content.lock();
+        let (pages, size) = match &mut *content {
+            Content::Regular { pages, size } => (pages, size),
+            _ => return 0,
+        };
+        let end = offset + buf.len();
+        let page_count = (end + PAGE_SIZE - 1) / PAGE_SIZE;
+        if pages.len() < page_count {
+            pages.resize(page_count, Frame::Unallocated);
+        }
+        let mut pos = offset;
+        while pos < end {
+            let page_offset = pos % PAGE_SIZE;
+            let len = (PAGE_SIZE - page_offset).min(end - pos);
+            let tracker = match resident(pages, pos / PAGE_SIZE, true) {
+                Some(tracker) => tracker,
+                // out of memory, it's a short write
+                None => break,
+            };
+            tracker.ppn.get_bytes_array()[page_offset..page_offset + len]
+                .copy_from_slice(&buf[pos - offset..pos - offset + len]);
+            pos += len;
+        }
+        *size = (*size).max(pos);
+        pages.truncate((*size + PAGE_SIZE - 1) / PAGE_SIZE);
+        drop(content);
+        self.modified();
+        pos - offset
+    }
+    fn truncate(&self, new_size: usize) -> Result<(), isize> {
+        let mut content = self.content.lock();
+        let (pages, size) = match &mut *content {
+            Content::Regular { pages, size } => (pages, size),
+            Content::Directory(_) => return Err(EISDIR),
+            _ => return Err(EINVAL),
+        };
+        pages.resize((new_size + PAGE_SIZE - 1) / PAGE_SIZE, Frame::Unallocated);
+        // the cut off bytes must read as zeros if the file grows again
+        if new_size < *size && new_size % PAGE_SIZE != 0 {
+            let last = pages.len() - 1;
+            if let Some(tracker) = resident(pages, last, false) {
+                tracker.ppn.get_bytes_array()[new_size % PAGE_SIZE..].fill(0);
+            }
+        }
+        *size = new_size;
+        drop(content);
+        self.modified();
+        Ok(())
+    }
+    fn get_page(&self, index: usize) -> Option<Arc<FrameTracker>> {
+        match &mut *self.content.lock() {
+            Content::Regular { pages, .. } if index < pages.len() => resident(pages, index, true),
+            _ => None,
+        }
+    }
+}
+
+/// An inode in tmpfs, looked up by `name` in `parent`.
+/// Hard links share `inner`, each has its own `TmpfsInode`.
+pub struct TmpfsInode {
+    readable: bool,
+    writable: bool,
+    special_use: bool,
+    append: bool,
+    inner: Arc<TmpfsInodeInner>,
+    /// `None` for the root
+    parent: Mutex<Option<Arc<TmpfsInodeInner>>>,
+    name: Mutex<String>,
+    /// Set by `unlink(false)`, the inode is waiting for `link_child()` to give it a new name
+    detached: Mutex<bool>,
+    offset: Mutex<usize>,
+    dirnode_ptr: Arc<Mutex<Weak<DirectoryTreeNode>>>,
+}
+
+impl TmpfsInode {
+    pub fn root(mode: u32) -> Arc<dyn File> {
+        let inner = TmpfsInodeInner::new(mode, Content::Directory(BTreeMap::new()));
+        Self::from_inner(inner, None, String::new())
+    }
+    fn from_inner(
+        inner: Arc<TmpfsInodeInner>,
+        parent: Option<Arc<TmpfsInodeInner>>,
+        name: String,
+    ) -> Arc<dyn File> {
+        Arc::new(Self {
+            readable: true,
+            writable: true,
+            special_use: false,
+            append: false,
+            inner,
+            parent: Mutex::new(parent),
+            name: Mutex::new(name),
+            detached: Mutex::new(false),
+            offset: Mutex::new(0),
+            dirnode_ptr: Arc::new(Mutex::new(Weak::new())),
+        })
+    }
+    /// The inode of an existing entry `name` in this directory
+    pub fn new_child(&self, name: &str) -> Result<Arc<dyn File>, isize> {
+        match &*self.inner.content.lock() {
+            Content::Directory(children) => match children.get(name) {
+                Some(child) => Ok(Self::from_inner(
+                    child.clone(),
+                    Some(self.inner.clone()),
+                    name.to_string(),
+                )),
+                None => Err(ENOENT),
+            },
+            _ => Err(ENOTDIR),
+        }
+    }
+    fn add_child(&self, name: &str, child: Arc<TmpfsInodeInner>) -> Result<(), isize> {
+        match &mut *self.inner.content.lock() {
+            Content::Directory(children) => {
+                if children.contains_key(name) {
+                    return Err(EEXIST);
+                }
+                children.insert(name.to_string(), child);
+            }
+            _ => return Err(ENOTDIR),
+        }
+        self.inner.modified();
+        Ok(())
+    }
+    fn create_child(
+        &self,
+        name: &str,
+        mode: u32,
+        content: Content,
+    ) -> Result<Arc<dyn File>, isize> {
+        let child = TmpfsInodeInner::new(mode, content);
+        self.add_child(name, child.clone())?;
+        Ok(Self::from_inner(
+            child,
+            Some(self.inner.clone()),
+            name.to_string(),
+        ))
+    }
+    /// Remove the entry of this inode from its parent
+    fn remove_from_parent(&self) -> Result<(), isize> {
+        let parent = match &*self.parent.lock() {
+            Some(parent) => parent.clone(),
+            None => return Err(EBUSY),
+        };
+        let name = self.name.lock().clone();
+        match &mut *parent.content.lock() {
+            Content::Directory(children) => {
+                match children.get(&name) {
+                    Some(child) if Arc::ptr_eq(child, &self.inner) => {}
+                    _ => return Err(ENOENT),
+                }
+                children.remove(&name);
+            }
+            _ => return Err(ENOTDIR),
+        }
+        parent.modified();
+        Ok(())
+    }
+}
+
+impl Drop for TmpfsInode {
+    fn drop(&mut self) {
+        if self.special_use {
+            let inode = self.get_dirtree_node();
+            match inode {
+                Some(inode) => inode.sub_special_use(),
+                None => {}
+            }
+        }
+    }
+}
+
+#[allow(unused)]
+impl File for TmpfsInode {
+    fn deep_clone(&self) -> Arc<dyn File> {
+        if self.special_use {
+            let inode = self.get_dirtree_node();
+            match inode {
+                Some(inode) => inode.add_special_use(),
+                None => {}
+            }
+        }
+        Arc::new(Self {
+            readable: self.readable,
+            writable: self.writable,
+            special_use: self.special_use,
+            append: self.append,
+            inner: self.inner.clone(),
+            parent: Mutex::new(self.parent.lock().clone()),
+            name: Mutex::new(self.name.lock().clone()),
+            detached: Mutex::new(*self.detached.lock()),
+            offset: Mutex::new(*self.offset.lock()),
+            dirnode_ptr: self.dirnode_ptr.clone(),
+        })
+    }
+    fn readable(&self) -> bool {
+        self.readable
+    }
+    fn writable(&self) -> bool {
+        self.writable
+    }
+    fn read(&self, offset: Option<&mut usize>, buffer: &mut [u8]) -> usize {
+        match offset {
+            Some(offset) => {
+                let len = self.inner.read_at(*offset, buffer);
+                *offset += len;
+                len
+            }
+            None => {
+                let mut offset = self.offset.lock();
+                let len = self.inner.read_at(*offset, buffer);
+                *offset += len;
+                len
+            }
+        }
+    }
+    fn write(&self, offset: Option<&mut usize>, buffer: &[u8]) -> usize {
+        match offset {
+            Some(offset) => {
+                let len = self.inner.write_at(*offset, buffer);
+                *offset += len;
+                len
+            }
+            None => {
+                let mut offset = self.offset.lock();
+                if self.append {
+                    *offset = self.inner.size();
+                }
+                let len = self.inner.write_at(*offset, buffer);
+                *offset += len;
+                len
+            }
+        }
+    }
+    fn r_ready(&self) -> bool {
+        true
+    }
+    fn w_ready(&self) -> bool {
+        true
+    }
+    fn read_user(&self, offset: Option<usize>, mut buf: UserBuffer) -> usize {
+        let mut total_read_size = 0usize;
+        let mut file_offset = self.offset.lock();
+        let use_file_offset = offset.is_none();
+        let mut offset = match offset {
+            Some(offset) => offset,
+            None => *file_offset,
+        };
+        for slice in buf.buffers.iter_mut() {
+            let read_size = self.inner.read_at(offset, *slice);
+            if read_size == 0 {
+                break;
+            }
+            offset += read_size;
+            total_read_size += read_size;
+        }
+        if use_file_offset {
+            *file_offset = offset;
+        }
+        total_read_size
+    }
+    fn write_user(&self, offset: Option<usize>, buf: UserBuffer) -> usize {
+        let mut total_write_size = 0usize;
+        let mut file_offset = self.offset.lock();
+        let use_file_offset = offset.is_none();
+        let mut offset = match offset {
+            Some(offset) => offset,
+            None if self.append => self.inner.size(),
+            None => *file_offset,
+        };
+        for slice in buf.buffers.iter() {
+            let write_size = self.inner.write_at(offset, *slice);
+            offset += write_size;
+            total_write_size += write_size;
+            if write_size < slice.len() {
+                break;
+            }
+        }
+        if use_file_offset {
+            *file_offset = offset;
+        }
+        total_write_size
+    }
+    fn get_size(&self) -> usize {
+        self.inner.size()
+    }
+    fn get_stat(&self) -> Stat {
+        let (nlink, rdev) = match &*self.inner.content.lock() {
+            // "." and the entry in the parent, plus ".." of each subdirectory
+            Content::Directory(children) => (
+                2 + children.values().filter(|child| child.is_dir()).count() as u32,
+                0,
+            ),
+            Content::Device(rdev) => (*self.inner.nlink.lock(), *rdev),
+            _ => (*self.inner.nlink.lock(), 0),
+        };
+        let (atime, mtime, ctime) = *self.inner.time.lock();
+        let mut stat = Stat::new(
+            crate::makedev!(0, 6),
+            self.inner.ino,
+            *self.inner.mode.lock(),
+            nlink,
+            rdev,
+            self.inner.size() as i64,
+            atime as i64,
+            mtime as i64,
+            ctime as i64,
+        );
+        let (uid, gid) = *self.inner.owner.lock();
+        stat.set_owner(uid, gid);
+        stat
+    }
+    fn get_file_type(&self) -> DiskInodeType {
+        if self.inner.is_dir() {
+            DiskInodeType::Directory
+        } else {
+            DiskInodeType::File
+        }
+    }
+    fn info_dirtree_node(&self, dirnode_ptr: Weak<DirectoryTreeNode>) {
+        *self.dirnode_ptr.lock() = dirnode_ptr;
+    }
+    fn get_dirtree_node(&self) -> Option<Arc<DirectoryTreeNode>> {
+        self.dirnode_ptr.lock().upgrade()
+    }
+    fn open(&self, flags: OpenFlags, special_use: bool) -> Result<Arc<dyn File>, isize> {
+        let readable = !flags.contains(OpenFlags::O_WRONLY);
+        let fifo = match &*self.inner.content.lock() {
+            Content::Fifo(fifo) => Some(fifo.clone()),
+            Content::Device(rdev) => match device_file(*self.inner.mode.lock(), *rdev) {
+                Some(device) => return Ok(device),
+                None => None,
+            },
+            _ => None,
+        };
+        // may block until the other end is opened, so it's done without the content lock,
+        // O_RDWR doesn't wait and gets the read end
+        if let Some(fifo) = fifo {
+            let nonblock = flags.intersects(OpenFlags::O_NONBLOCK | OpenFlags::O_RDWR);
+            return Ok(fifo.open(readable, nonblock)?);
+        }
+        Ok(Arc::new(Self {
+            readable,
+            writable: flags.contains(OpenFlags::O_WRONLY) || flags.contains(OpenFlags::O_RDWR),
+            special_use,
+            append: flags.contains(OpenFlags::O_APPEND),
+            inner: self.inner.clone(),
+            parent: Mutex::new(self.parent.lock().clone()),
+            name: Mutex::new(self.name.lock().clone()),
+            detached: Mutex::new(false),
+            offset: Mutex::new(0),
+            dirnode_ptr: self.dirnode_ptr.clone(),
+        }))
+    }
+    fn open_subfile(&self) -> Result<Vec<(String, Arc<dyn File>)>, isize> {
+        match &*self.inner.content.lock() {
+            Content::Directory(children) => Ok(children
+                .iter()
+                .map(|(name, child)| {
+                    let file =
+                        Self::from_inner(child.clone(), Some(self.inner.clone()), name.clone());
+                    (name.clone(), file)
+                })
+                .collect()),
+            _ => Err(ENOTDIR),
+        }
+    }
+    fn create(&self, name: &str, file_type: DiskInodeType) -> Result<Arc<dyn File>, isize> {
+        match file_type {
+            DiskInodeType::File => self.create_child(
+                name,
+                StatMode::S_IFREG.bits() | 0o644,
+                Content::Regular {
+                    pages: Vec::new(),
+                    size: 0,
+                },
+            ),
+            DiskInodeType::Directory => self.create_child(
+                name,
+                StatMode::S_IFDIR.bits() | 0o755,
+                Content::Directory(BTreeMap::new()),
+            ),
+        }
+    }
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn File>, isize> {
+        self.create_child(
+            name,
+            StatMode::S_IFLNK.bits() | 0o777,
+            Content::Symlink(target.to_string()),
+        )
+    }
+    fn mknod(&self, name: &str, mode: u32, rdev: u64) -> Result<Arc<dyn File>, isize> {
+        let file_type = mode & StatMode::S_IFMT.bits();
+        let content = match StatMode::from_bits_truncate(file_type) {
+            StatMode::S_IFREG => Content::Regular {
+                pages: Vec::new(),
+                size: 0,
+            },
+            StatMode::S_IFIFO => Content::Fifo(Arc::new(Fifo::new())),
+            StatMode::S_IFCHR | StatMode::S_IFBLK => Content::Device(rdev),
+            StatMode::S_IFSOCK => Content::Socket,
+            _ => return Err(EINVAL),
+        };
+        self.create_child(name, file_type | (mode & 0o7777), content)
+    }
+    /// If `child` was detached by `unlink(false)`, it is moved here (rename),
+    /// otherwise a hard link to it is created.
+    fn link_child(&self, name: &str, child: &Self) -> Result<(), isize>
+    where
+        Self: Sized,
+    {
+        let mut detached = child.detached.lock();
+        if *detached {
+            self.add_child(name, child.inner.clone())?;
+            if let Err(errno) = child.remove_from_parent() {
+                // undo, so the inode isn't reachable by two names
+                if let Content::Directory(children) = &mut *self.inner.content.lock() {
+                    children.remove(name);
+                }
+                return Err(errno);
+            }
+            *child.parent.lock() = Some(self.inner.clone());
+            *child.name.lock() = name.to_string();
+            *detached = false;
+        } else {
+            if child.inner.is_dir() {
+                return Err(EPERM);
+            }
+            self.add_child(name, child.inner.clone())?;
+            *child.inner.nlink.lock() += 1;
+        }
+        child.inner.changed();
+        Ok(())
+    }
+    fn unlink(&self, delete: bool) -> Result<(), isize> {
+        if !delete {
+            // the directory entry is kept until `link_child()` moves it
+            *self.detached.lock() = true;
+            return Ok(());
+        }
+        if let Content::Directory(children) = &*self.inner.content.lock() {
+            if !children.is_empty() {
+                return Err(ENOTEMPTY);
+            }
+        }
+        self.remove_from_parent()?;
+        // the content is freed with the last `TmpfsInode`
+        let mut nlink = self.inner.nlink.lock();
+        *nlink = nlink.saturating_sub(1);
+        drop(nlink);
+        self.inner.changed();
+        Ok(())
+    }
+    fn get_dirent(&self, count: usize) -> Vec<Dirent> {
+        let content = self.inner.content.lock();
+        let children = match &*content {
+            Content::Directory(children) => children,
+            _ => return Vec::new(),
+        };
+        let parent_ino = self
+            .parent
+            .lock()
+            .as_ref()
+            .map_or(self.inner.ino, |parent| parent.ino);
+        let mut offset = self.offset.lock();
+        let vec: Vec<Dirent> = [
+            (".", self.inner.ino, self.inner.d_type()),
+            ("..", parent_ino, (StatMode::S_IFDIR.bits() >> 12) as u8),
+        ]
+        .iter()
+        .copied()
+        .chain(
+            children
+                .iter()
+                .map(|(name, child)| (name.as_str(), child.ino, child.d_type())),
+        )
+        .enumerate()
+        .skip(*offset)
+        .take(count / core::mem::size_of::<Dirent>())
+        .map(|(index, (name, ino, d_type))| {
+            Dirent::new(ino as usize, (index + 1) as isize, d_type, name)
+        })
+        .collect();
+        *offset += vec.len();
+        vec
+    }
+    fn is_append(&self) -> bool {
+        self.append
+    }
+    fn lseek(&self, offset: isize, whence: SeekWhence) -> Result<usize, isize> {
+        let new_offset = match whence {
+            SeekWhence::SEEK_SET => offset,
+            SeekWhence::SEEK_CUR => *self.offset.lock() as isize + offset,
+            SeekWhence::SEEK_END => self.inner.size() as isize + offset,
+            // whence is duplicated
+            _ => return Err(EINVAL),
+        };
+        let new_offset = match new_offset < 0 {
+            true => return Err(EINVAL),
+            false => new_offset as usize,
+        };
+        *self.offset.lock() = new_offset;
+        Ok(new_offset)
+    }
+    fn modify_size(&self, diff: isize) -> Result<(), isize> {
+        let new_size = self.inner.size() as isize + diff;
+        if new_size < 0 {
+            return Err(EINVAL);
+        }
+        self.inner.truncate(new_size as usize)
+    }
+    fn truncate_size(&self, new_size: usize) -> Result<(), isize> {
+        self.inner.truncate(new_size)
+    }
+    fn set_timestamp(&self, ctime: Option<usize>, atime: Option<usize>, mtime: Option<usize>) {
+        let mut time = self.inner.time.lock();
+        if let Some(atime) = atime {
+            time.0 = atime;
+        }
+        if let Some(mtime) = mtime {
+            time.1 = mtime;
+        }
+        if let Some(ctime) = ctime {
+            time.2 = ctime;
+        }
+    }
+    fn chmod(&self, mode: u32) -> Result<(), isize> {
+        let mut inode_mode = self.inner.mode.lock();
+        *inode_mode = (*inode_mode & StatMode::S_IFMT.bits()) | (mode & 0o7777);
+        drop(inode_mode);
+        self.inner.changed();
+        Ok(())
+    }
+    fn chown(&self, uid: u32, gid: u32) -> Result<(), isize> {
+        *self.inner.owner.lock() = (uid, gid);
+        self.inner.changed();
+        Ok(())
+    }
+    /// The pages of the file are its page cache
+    fn get_single_cache(&self, offset: usize) -> Result<Arc<Mutex<PageCache>>, ()> {
+        if offset & 0xfff != 0 {
+            return Err(());
+        }
+        match self.inner.get_page(offset / PAGE_SIZE) {
+            Some(tracker) => Ok(Arc::new(Mutex::new(PageCache::from_tracker(tracker)))),
+            None => Err(()),
+        }
+    }
+    fn get_all_caches(&self) -> Result<Vec<Arc<Mutex<PageCache>>>, ()> {
+        let pages = (self.inner.size() + PAGE_SIZE - 1) / PAGE_SIZE;
+        (0..pages)
+            .map(|index| self.get_single_cache(index * PAGE_SIZE))
+            .collect()
+    }
+    #[cfg(feature = "oom_handler")]
+    fn oom(&self) -> usize {
+        // the allocation running out of memory may be our own
+        let mut content = match self.inner.content.try_lock() {
+            Some(content) => content,
+            None => return 0,
+        };
+        let pages = match &mut *content {
+            Content::Regular { pages, .. } => pages,
+            _ => return 0,
+        };
+        let mut compressed = 0;
+        for frame in pages.iter_mut() {
+            match frame.zip() {
+                Ok(_) => compressed += 1,
+                // holes, compressed pages and pages mapped by processes
+                Err(MemoryError::NotInMemory) | Err(MemoryError::SharedPage) => {}
+                Err(_) => break,
+            }
+        }
+        compressed
+    }
+    #[cfg(not(feature = "oom_handler"))]
+    fn oom(&self) -> usize {
+        0
+    }
+    fn hang_up(&self) -> bool {
+        false
+    }
+    fn read_link(&self) -> Result<String, isize> {
+        match &*self.inner.content.lock() {
+            Content::Symlink(target) => Ok(target.clone()),
+            _ => Err(EINVAL),
+        }
+    }
+    fn fcntl(&self, cmd: u32, arg: u32) -> isize {
+        todo!()
+    }
+}
@@ -0,0 +1,15 @@
+//! tmpfs, a file system living in memory only.
+//!
+//! The pages of regular files come from the frame allocator and are handed to zram
+//! when memory runs low, so temporary files never reach the block device.
+mod inode;
+
+pub use inode::TmpfsInode;
+
+use super::{file_trait::File, StatMode};
+use alloc::sync::Arc;
+
+/// Root directory of a new tmpfs instance, writable by everyone like `/tmp`
+pub fn root() -> Arc<dyn File> {
+    TmpfsInode::root((StatMode::S_IFDIR | StatMode::S_ISVTX).bits() | 0o777)
+}
@@ -32,11 +32,12 @@ mod drivers;
 mod fs;
 mod lang_items;
 mod mm;
+mod random;
 mod syscall;
 mod task;
 mod timer;
 
-use crate::arch::{bootstrap_init, machine_init};
+use crate::arch::{bootstrap_init, machine_init, machine_init_secondary};
 // #[cfg(feature = "board_2k1000")]
 use crate::config::DISK_IMAGE_BASE;
 #[cfg(feature = "la64")]
@@ -100,11 +101,15 @@ pub fn rust_main() -> ! {
 
     machine_init();
     println!("[kernel] Hello, Welcome to HPU!");
+    timer::init_realtime();
+    random::init();
 
     //machine independent initialization
+    drivers::block::init();
     fs::directory_tree::init_fs();
     // fs::flush_preload();
     task::add_initproc();
+    arch::start_secondary_cpus();
     // note that in run_tasks(), there is yet *another* pre_start_init(),
     // which is used to turn on interrupts in some archs like LoongArch.
     task::run_tasks();
@@ -112,5 +117,16 @@ pub fn rust_main() -> ! {
     
 }
 
+/// Entry of the secondary cores, reached from `entry.asm` after core 0 released them.
+#[no_mangle]
+pub fn rust_main_secondary() -> ! {
+    bootstrap_init();
+    mm::init_secondary();
+    machine_init_secondary();
+    println!("[kernel] CPU {} online.", arch::cpu_id());
+    task::run_tasks();
+    panic!("Unreachable in rust_main_secondary!");
+}
+
 #[cfg(test)]
 fn test_runner(_tests: &[&dyn Fn()]) {}
@@ -49,6 +49,7 @@ trait FrameAllocator {
 }
 
 pub struct StackFrameAllocator {
+    start: usize,
     current: usize,
     end: usize,
     recycled: Vec<usize>,
@@ -56,6 +57,7 @@ pub struct StackFrameAllocator {
 
 impl StackFrameAllocator {
     pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
+        self.start = l.0;
         self.current = l.0;
         self.end = r.0;
         let last_frames = self.end - self.current;
@@ -65,10 +67,14 @@ impl StackFrameAllocator {
     pub fn unallocated_frames(&self) -> usize {
         self.recycled.len() + self.end - self.current
     }
+    pub fn total_frames(&self) -> usize {
+        self.end - self.start
+    }
 }
 impl FrameAllocator for StackFrameAllocator {
     fn new() -> Self {
         Self {
+            start: 0,
             current: 0,
             end: 0,
             recycled: Vec::new(),
@@ -236,6 +242,10 @@ pub fn unallocated_frames() -> usize {
     FRAME_ALLOCATOR.write().unallocated_frames()
 }
 
+pub fn total_frames() -> usize {
+    FRAME_ALLOCATOR.read().total_frames()
+}
+
 #[allow(unused)]
 pub fn frame_allocator_test() {
     let mut v: Vec<Arc<FrameTracker>> = Vec::new();
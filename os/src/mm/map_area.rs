@@ -9,6 +9,7 @@ use super::linear_map::LinearMap;
 use super::KERNEL_SPACE;
 use super::{frame_alloc, FrameTracker};
 use super::{PhysPageNum, VirtAddr, VirtPageNum};
+use crate::config::PAGE_SIZE;
 use crate::fs::file_trait::File;
 #[cfg(feature = "swap")]
 use crate::fs::swap::{SwapTracker, SWAP_DEVICE};
@@ -66,7 +67,7 @@ impl Frame {
         match self {
             Frame::InMemory(frame_ref) => {
                 if Arc::strong_count(frame_ref) == 1 {
-                    let swap_tracker = SWAP_DEVICE.lock().write(frame_ref.ppn.get_bytes_array());
+                    let swap_tracker = SWAP_DEVICE.lock().write(frame_ref.ppn.get_bytes_array())?;
                     let swap_id = swap_tracker.0;
                     // frame_tracker should be dropped
                     *self = Frame::SwappedOut(swap_tracker);
@@ -85,7 +86,7 @@ impl Frame {
     pub fn force_swap_out(&mut self) -> Result<usize, MemoryError> {
         match self {
             Frame::InMemory(frame_ref) => {
-                let swap_tracker = SWAP_DEVICE.lock().write(frame_ref.ppn.get_bytes_array());
+                let swap_tracker = SWAP_DEVICE.lock().write(frame_ref.ppn.get_bytes_array())?;
                 let swap_id = swap_tracker.0;
                 // frame_tracker should be dropped
                 *self = Frame::SwappedOut(swap_tracker);
@@ -159,6 +160,7 @@ impl Debug for MapArea {
                 "map_file",
                 &if self.map_file.is_some() { "yes" } else { "no" },
             )
+            .field("shared", &self.shared)
             .finish()
     }
 }
@@ -174,6 +176,9 @@ pub struct MapArea {
     /// Permissions which are the or of RWXU, where U stands for user.
     pub map_perm: MapPermission,
     pub map_file: Option<Arc<dyn File>>,
+    /// `MAP_SHARED`, the frames are shared with the page cache or across `fork()`
+    /// instead of being copied on write.
+    pub shared: bool,
 }
 
 impl MapArea {
@@ -198,6 +203,7 @@ impl MapArea {
             map_type,
             map_perm,
             map_file,
+            shared: false,
         }
     }
     /// Copier, but the physical pages are not allocated,
@@ -211,6 +217,7 @@ impl MapArea {
             map_type: another.map_type,
             map_perm: another.map_perm,
             map_file: another.map_file.clone(),
+            shared: another.shared,
         }
     }
     /// Create `MapArea` from `Vec<Arc<FrameTracker>>`. This function should only be used to
@@ -240,6 +247,7 @@ impl MapArea {
             map_type,
             map_perm,
             map_file: None,
+            shared: false,
         }
     }
     #[cfg(not(feature = "oom_handler"))]
@@ -259,6 +267,7 @@ impl MapArea {
             map_type,
             map_perm,
             map_file: None,
+            shared: false,
         }
     }
     /// Map an included page in current area.
@@ -304,12 +313,12 @@ impl MapArea {
         &mut self,
         page_table: &mut T,
         vpn: VirtPageNum,
-    ) -> PhysPageNum {
-        let frame = frame_alloc().unwrap();
+    ) -> Result<PhysPageNum, MemoryError> {
+        let frame = frame_alloc().ok_or(MemoryError::OutOfMemory)?;
         let ppn = frame.ppn;
         self.inner.alloc_in_memory(vpn, frame);
         page_table.map(vpn, ppn, self.map_perm);
-        ppn
+        Ok(ppn)
     }
     /// Unmap a page in current area.
     /// If it is framed, then the physical pages will be removed from the `data_frames` Btree.
@@ -337,6 +346,7 @@ impl MapArea {
     /// Convert map areas to physical pages.
     /// # Of Course...
     /// Since the area is shared, the pages have been allocated.
+    /// Private pages lose W in both page tables for copy on write, shared pages keep it.
     /// # Argument
     /// `dst_page_table`: The destination to be mapped into.
     /// `src_page_table`: The source to be mapped from. This is also the page table where `self` should be included.
@@ -345,9 +355,18 @@ impl MapArea {
         dst_page_table: &mut T,
         src_page_table: &mut T,
     ) -> Result<(), ()> {
-        let map_perm = self.map_perm.difference(MapPermission::W);
+        let map_perm = if self.shared {
+            self.map_perm
+        } else {
+            self.map_perm.difference(MapPermission::W)
+        };
         for vpn in self.inner.vpn_range {
-            if let Some(ppn) = src_page_table.block_and_ret_mut(vpn) {
+            let ppn = if self.shared {
+                src_page_table.translate(vpn)
+            } else {
+                src_page_table.block_and_ret_mut(vpn)
+            };
+            if let Some(ppn) = ppn {
                 if !dst_page_table.is_mapped(vpn) {
                     dst_page_table.map(vpn, ppn, map_perm);
                 } else {
@@ -357,6 +376,35 @@ impl MapArea {
         }
         Ok(())
     }
+    /// Write back the pages in `[start_vpn, end_vpn)` written through a shared file mapping.
+    /// The dirty bits in `page_table` are moved to the page caches of the file.
+    pub fn sync_shared<T: PageTable>(
+        &self,
+        page_table: &mut T,
+        start_vpn: VirtPageNum,
+        end_vpn: VirtPageNum,
+    ) {
+        let file = match &self.map_file {
+            Some(file) if self.shared => file,
+            _ => return,
+        };
+        let area_start_vpn = self.inner.vpn_range.get_start();
+        let mut has_dirty_page = false;
+        for vpn in VPNRange::new(start_vpn, end_vpn) {
+            if page_table.is_dirty(vpn) != Some(true) {
+                continue;
+            }
+            let offset = file.get_offset() + (vpn.0 - area_start_vpn.0) * PAGE_SIZE;
+            if let Ok(page_cache) = file.get_single_cache(offset) {
+                page_cache.lock().mark_dirty();
+                has_dirty_page = true;
+            }
+            page_table.clear_dirty_bit(vpn).unwrap();
+        }
+        if has_dirty_page {
+            file.sync_caches();
+        }
+    }
     pub fn get_start<T: PageTable>(&self) -> VirtPageNum {
         self.inner.vpn_range.get_start()
     }
@@ -568,6 +616,7 @@ impl MapArea {
             map_type: self.map_type,
             map_perm: self.map_perm,
             map_file: second_file,
+            shared: self.shared,
         })
     }
     pub fn into_three(
@@ -575,25 +624,13 @@ impl MapArea {
         first_cut: VirtPageNum,
         second_cut: VirtPageNum,
     ) -> Result<(Self, Self), ()> {
-        if self.map_file.is_some() {
-            warn!("[into_three] break apart file-back MapArea!");
+        if first_cut >= second_cut || second_cut >= self.inner.vpn_range.get_end() {
             return Err(());
         }
-        let (second_frames, third_frames) = self.inner.into_three(first_cut, second_cut)?;
-        Ok((
-            MapArea {
-                inner: second_frames,
-                map_type: self.map_type,
-                map_perm: self.map_perm,
-                map_file: None,
-            },
-            MapArea {
-                inner: third_frames,
-                map_type: self.map_type,
-                map_perm: self.map_perm,
-                map_file: None,
-            },
-        ))
+        // `into_two` moves the file offset of each later piece along with its start
+        let mut second = self.into_two(first_cut)?;
+        let third = second.into_two(second_cut)?;
+        Ok((second, third))
     }
     #[cfg(feature = "oom_handler")]
     pub fn do_oom<T: PageTable>(&mut self, page_table: &mut T) -> usize {
@@ -624,6 +661,10 @@ impl MapArea {
                     continue;
                 }
                 Err(MemoryError::SharedPage) => continue,
+                Err(MemoryError::SwapIsFull) => {
+                    self.inner.active.push_front(idx);
+                    break;
+                }
                 _ => unreachable!(),
             }
         }
@@ -647,6 +688,10 @@ impl MapArea {
                     );
                     continue;
                 }
+                Err(MemoryError::SwapIsFull) => {
+                    self.inner.active.push_front(idx);
+                    break;
+                }
                 _ => unreachable!(),
             }
         }
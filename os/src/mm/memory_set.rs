@@ -4,9 +4,12 @@ use super::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 use crate::arch::TrapContext;
 use crate::arch::{MMIO, TICKS_PER_SEC};
 use crate::fs::SeekWhence;
+use crate::random::get_random_bytes;
 use crate::syscall::errno::*;
+use crate::task::rlimit::Resource;
 use crate::task::{
-    current_task, trap_cx_bottom_from_tid, ustack_bottom_from_tid, AuxvEntry, AuxvType, ELFInfo,
+    binfmt::FlatHeader, cred::Credentials, current_task, trap_cx_bottom_from_tid,
+    ustack_bottom_from_tid, AuxvEntry, AuxvType, ELFInfo, TaskControlBlock,
 };
 use crate::{config::*, should_map_trampoline};
 use alloc::string::String;
@@ -55,6 +58,8 @@ pub enum MemoryError {
     ZramIsFull,
     SwapIsFull,
     BeyondEOF,
+    OutOfMemory,
+    IoError,
 }
 
 /// The memory "space" as in user space or kernel space
@@ -247,6 +252,10 @@ impl<T: PageTable> MemorySet<T> {
     pub fn highest_addr(&self) -> VirtAddr {
         self.areas.last().unwrap().get_end::<T>().into()
     }
+    /// The mapped areas, in the order they were inserted.
+    pub fn areas(&self) -> &[MapArea] {
+        &self.areas
+    }
     pub fn contains_valid_buffer(&self, buf: usize, size: usize, perm: MapPermission) -> bool {
         let start_vpn = VirtAddr::from(buf).floor();
         let end_vpn = VirtAddr::from(buf + size).ceil();
@@ -281,24 +290,27 @@ impl<T: PageTable> MemorySet<T> {
                     if old_offset + offset_in_area > (file.get_size() + PAGE_SIZE - 1) & !0xfff {
                         return Err(MemoryError::BeyondEOF);
                     }
-                    if area.map_perm.contains(MapPermission::W) {
+                    if area.map_perm.contains(MapPermission::W) && !area.shared {
                         let allocated_ppn = area.map_one_unchecked(&mut self.page_table, vpn);
                         file.lseek(offset_in_area as isize, SeekWhence::SEEK_CUR)
                             .unwrap();
-                        file.read(None, unsafe {
+                        let page = unsafe {
                             core::slice::from_raw_parts_mut(
                                 PhysAddr::from(allocated_ppn).0 as *mut u8,
                                 PAGE_SIZE,
                             )
-                        });
+                        };
+                        // the frame is not zeroed, clear what EOF or a failed read leaves
+                        let read_size = file.read(None, page);
+                        page[read_size..].fill(0);
                         file.lseek(old_offset as isize, SeekWhence::SEEK_SET)
                             .unwrap();
                         Ok(allocated_ppn.offset(addr.page_offset()))
-                    // map to phys page directly
+                    // map to phys page directly, writes to a shared mapping go to the page cache
                     } else {
                         let cache_phys_page = file
                             .get_single_cache(old_offset + offset_in_area)
-                            .unwrap()
+                            .map_err(|_| MemoryError::IoError)?
                             .try_lock()
                             .unwrap()
                             .get_tracker();
@@ -314,7 +326,7 @@ impl<T: PageTable> MemorySet<T> {
                         Frame::InMemory(_) => unreachable!(),
                         Frame::Unallocated => {
                             info!("[do_page_fault] addr: {:?}, solution: lazy alloc", addr);
-                            area.map_one_zeroed_unchecked(&mut self.page_table, vpn)
+                            area.map_one_zeroed_unchecked(&mut self.page_table, vpn)?
                         }
                         #[cfg(feature = "oom_handler")]
                         Frame::Compressed(_) => {
@@ -343,7 +355,16 @@ impl<T: PageTable> MemorySet<T> {
                 }
             } else {
                 // mapped before the assignment
-                if area.map_perm.contains(MapPermission::W) {
+                if area.map_perm.contains(MapPermission::W) && area.shared {
+                    // shared pages are never copied, just restore W lost in `mprotect()`
+                    let ppn = self.page_table.translate(vpn).unwrap();
+                    self.page_table.set_pte_flags(vpn, area.map_perm).unwrap();
+                    info!(
+                        "[do_page_fault] addr: {:?}, solution: write shared page",
+                        addr
+                    );
+                    Ok(ppn.offset(addr.page_offset()))
+                } else if area.map_perm.contains(MapPermission::W) {
                     // Whoever triggers this fault shall cause the area to be copied into a new area.
                     let allocated_ppn = area.copy_on_write(&mut self.page_table, vpn)?;
                     info!("[do_page_fault] addr: {:?}, solution: copy on write", addr);
@@ -373,6 +394,7 @@ impl<T: PageTable> MemorySet<T> {
                 start_vpn.0 >= (USR_MMAP_BASE >> PAGE_SIZE_BITS)
                     && start_vpn.0 < (TASK_SIZE >> PAGE_SIZE_BITS)
                     && area.map_file.is_none()
+                    && !area.shared
             })
             .map(|area| area.do_oom(page_table))
             .sum()
@@ -383,7 +405,9 @@ impl<T: PageTable> MemorySet<T> {
         self.areas
             .iter_mut()
             .filter(|area| {
-                area.get_start::<T>().0 < (TASK_SIZE >> PAGE_SIZE_BITS) && area.map_file.is_none()
+                area.get_start::<T>().0 < (TASK_SIZE >> PAGE_SIZE_BITS)
+                    && area.map_file.is_none()
+                    && !area.shared
             })
             .map(|area| {
                 if area.get_start::<T>().0 < USR_MMAP_BASE >> PAGE_SIZE_BITS {
@@ -613,11 +637,61 @@ impl<T: PageTable> MemorySet<T> {
         }
         // map signaltrampoline
         memory_set.map_signaltrampoline();
-        let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
+        let elf = xmas_elf::ElfFile::new(elf_data).map_err(|_| ENOEXEC)?;
         let (program_break, elf_info) = memory_set.map_elf(&elf)?;
 
         Ok((memory_set, program_break, elf_info))
     }
+    /// Include a flat binary at `FLAT_LOAD_BASE` and trampoline,
+    /// returns the program break and entry point like `from_elf()`.
+    pub fn from_flat(data: &[u8], header: &FlatHeader) -> Result<(Self, usize, ELFInfo), isize> {
+        let reloc_end = header.reloc_start + header.reloc_count * 4;
+        if header.data_end > data.len() || reloc_end > data.len() {
+            return Err(ENOEXEC);
+        }
+        let mut image = data[..header.data_end].to_vec();
+        for reloc in data[header.reloc_start..reloc_end].chunks_exact(4) {
+            let offset = u32::from_be_bytes([reloc[0], reloc[1], reloc[2], reloc[3]]) as usize;
+            let word = image
+                .get_mut(offset..offset + core::mem::size_of::<usize>())
+                .ok_or(ENOEXEC)?;
+            let mut value = [0u8; core::mem::size_of::<usize>()];
+            value.copy_from_slice(word);
+            word.copy_from_slice(&(usize::from_ne_bytes(value) + FLAT_LOAD_BASE).to_ne_bytes());
+        }
+        let mut memory_set = Self::new_bare();
+        // map trampoline
+        if should_map_trampoline!() {
+            memory_set.map_trampoline();
+        }
+        // map signaltrampoline
+        memory_set.map_signaltrampoline();
+        let start_va = VirtAddr::from(FLAT_LOAD_BASE);
+        let end_va = VirtAddr::from(FLAT_LOAD_BASE + header.bss_end);
+        // text, data and bss share one area, like a no-MMU loader
+        let map_area = MapArea::new(
+            start_va,
+            end_va,
+            MapType::Framed,
+            MapPermission::R | MapPermission::W | MapPermission::X | MapPermission::U,
+            None,
+        );
+        if memory_set.push_with_offset(map_area, 0, &image).is_err() {
+            return Err(ENOMEM);
+        }
+        Ok((
+            memory_set,
+            VirtAddr::from(end_va.ceil()).0,
+            ELFInfo {
+                entry: FLAT_LOAD_BASE + header.entry,
+                interp_entry: None,
+                base: 0,
+                phnum: 0,
+                phent: 0,
+                phdr: 0,
+            },
+        ))
+    }
     pub fn from_existing_user(user_space: &mut MemorySet<T>) -> MemorySet<T> {
         let mut memory_set = Self::new_bare();
         // map trampoline
@@ -687,8 +761,27 @@ impl<T: PageTable> MemorySet<T> {
     }
     pub fn recycle_data_pages(&mut self) {
         //*self = Self::new_bare();
+        self.sync_shared_areas();
         self.areas.clear();
     }
+    /// Write back all shared file mappings, used before the address space goes away
+    pub fn sync_shared_areas(&mut self) {
+        let page_table = &mut self.page_table;
+        self.areas.iter().for_each(|area| {
+            area.sync_shared(page_table, area.get_start::<T>(), area.get_end::<T>())
+        });
+    }
+    /// Write back shared file mappings in `[start, start + len)`
+    pub fn msync(&mut self, start: usize, len: usize) {
+        let start_vpn = VirtAddr::from(start).floor();
+        let end_vpn = VirtAddr::from(start + len).ceil();
+        let page_table = &mut self.page_table;
+        self.areas.iter().for_each(|area| {
+            if let Some((overlap_start, overlap_end)) = area.check_overlapping(start_vpn, end_vpn) {
+                area.sync_shared(page_table, overlap_start, overlap_end);
+            }
+        });
+    }
     #[allow(unused)]
     // debug use only
     pub fn show_areas(&self) {
@@ -701,6 +794,67 @@ impl<T: PageTable> MemorySet<T> {
             );
         })
     }
+    /// Bytes of the user areas picked by `filter` inside `[start, end)`
+    fn mapped_bytes(
+        &self,
+        start: VirtPageNum,
+        end: VirtPageNum,
+        filter: impl Fn(&MapArea) -> bool,
+    ) -> usize {
+        self.areas
+            .iter()
+            .filter(|area| area.map_perm.contains(MapPermission::U) && filter(area))
+            .map(|area| {
+                let lo = area.get_start::<T>().max(start);
+                let hi = area.get_end::<T>().min(end);
+                if lo < hi {
+                    (hi.0 - lo.0) * PAGE_SIZE
+                } else {
+                    0
+                }
+            })
+            .sum()
+    }
+    /// Private writable memory counted by `RLIMIT_DATA`, which leaves out the user stacks
+    fn is_data(area: &MapArea) -> bool {
+        let end = VirtAddr::from(area.get_end::<T>()).0;
+        let is_stack = end <= USER_STACK_BASE
+            && (USER_STACK_BASE - end) % (PAGE_SIZE + USER_STACK_SIZE) == 0
+            && area.get_end::<T>().0 - area.get_start::<T>().0 == USER_STACK_SIZE / PAGE_SIZE;
+        area.map_perm.contains(MapPermission::W) && !area.shared && !is_stack
+    }
+    /// Check `RLIMIT_AS` and `RLIMIT_DATA` before mapping `len` bytes at `start`,
+    /// what a `MAP_FIXED` mapping replaces is not counted twice.
+    fn check_rlimit_vm(
+        &self,
+        task: &TaskControlBlock,
+        start: usize,
+        len: usize,
+        prot: MapPermission,
+        flags: MapFlags,
+    ) -> Result<(), isize> {
+        let (all_start, all_end) = (VirtPageNum(0), VirtPageNum(usize::MAX));
+        let (start, end) = if flags.contains(MapFlags::MAP_FIXED) {
+            (VirtAddr::from(start).floor(), VirtAddr::from(start + len).ceil())
+        } else {
+            (all_start, all_start)
+        };
+        let len = (len + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        let rlimit = *task.rlimit.lock();
+        let total = self.mapped_bytes(all_start, all_end, |_| true)
+            - self.mapped_bytes(start, end, |_| true);
+        if rlimit.get(Resource::AS).exceeded_by(total + len) {
+            return Err(ENOMEM);
+        }
+        if prot.contains(MapPermission::W) && !flags.contains(MapFlags::MAP_SHARED) {
+            let data = self.mapped_bytes(all_start, all_end, Self::is_data)
+                - self.mapped_bytes(start, end, Self::is_data);
+            if rlimit.get(Resource::DATA).exceeded_by(data + len) {
+                return Err(ENOMEM);
+            }
+        }
+        Ok(())
+    }
     //修改后的sbrk函数
     pub fn sbrk(&mut self, heap_pt: usize, heap_bottom: usize, increment: isize) -> usize {
         let old_pt = heap_pt;
@@ -710,7 +864,7 @@ impl<T: PageTable> MemorySet<T> {
                 warn!("increment too big");
                 return old_pt;
             }else {
-                self.mmap(
+                let ret = self.mmap(
                     old_pt,
                     increment as usize,
                     MapPermission::R | MapPermission::W | MapPermission::U,
@@ -718,6 +872,11 @@ impl<T: PageTable> MemorySet<T> {
                     0,
                     0
                 );
+                // beyond `RLIMIT_AS` or `RLIMIT_DATA`
+                if ret < 0 {
+                    warn!("increment over resource limit");
+                    return old_pt;
+                }
             }
         }else if increment <0 {
             if new_pt < heap_bottom{
@@ -744,6 +903,9 @@ impl<T: PageTable> MemorySet<T> {
         }
         let len = if len == 0 { PAGE_SIZE } else { len };
         let task = current_task().unwrap();
+        if let Err(errno) = self.check_rlimit_vm(&task, start, len, prot, flags) {
+            return errno;
+        }
         let idx = self.last_mmap_area_idx();
         let start_va: VirtAddr = if flags.contains(MapFlags::MAP_FIXED) { //MAP_FIXED使用指定的映射起始地址
             // unmap if exists
@@ -753,7 +915,9 @@ impl<T: PageTable> MemorySet<T> {
             if let Some(idx) = idx {
                 let area = &mut self.areas[idx];
                 if flags.contains(MapFlags::MAP_PRIVATE | MapFlags::MAP_ANONYMOUS)
+                    && !flags.contains(MapFlags::MAP_SHARED)
                     && prot == area.map_perm
+                    && !area.shared
                     && area.map_file.is_none()
                 {
                     debug!("[mmap] merge with previous area, call expand_to");
@@ -796,8 +960,32 @@ impl<T: PageTable> MemorySet<T> {
             if !file.readable(){
                 return EACCES;
             }
+            // writes to a shared mapping reach the file
+            if flags.contains(MapFlags::MAP_SHARED)
+                && prot.contains(MapPermission::W)
+                && !file.writable()
+            {
+                return EACCES;
+            }
             new_area.map_file = Some(file);
         }
+        if flags.contains(MapFlags::MAP_SHARED) {
+            new_area.shared = true;
+            // shared anonymous memory has no page cache behind it,
+            // allocate it now so the frames are shared by `fork()` instead of allocated lazily by each side
+            if new_area.map_file.is_none() {
+                for vpn in new_area.inner.vpn_range {
+                    if new_area
+                        .map_one_zeroed_unchecked(&mut self.page_table, vpn)
+                        .is_err()
+                    {
+                        warn!("[mmap] out of memory for shared anonymous area");
+                        new_area.unmap(&mut self.page_table).ok();
+                        return ENOMEM;
+                    }
+                }
+            }
+        }
         // insert MapArea and keep the order
         if let Some((idx, _)) = self
             .areas
@@ -833,6 +1021,7 @@ impl<T: PageTable> MemorySet<T> {
                 found_area = true;
                 let area_start_vpn: VirtPageNum = area.get_start::<T>();
                 let area_end_vpn = area.get_end::<T>();
+                area.sync_shared(page_table, overlap_start, overlap_end);
                 if overlap_start == area_start_vpn && overlap_end == area_end_vpn { //若完全重叠，取消整个区域映射，记录索引用于删除。
                     trace!("[munmap] unmap whole area, idx: {}", idx);
                     if let Err(_) = area.unmap(page_table) {     //从给定的 PageTable中取消当前内存区域内的所有页面映射
@@ -866,7 +1055,10 @@ impl<T: PageTable> MemorySet<T> {
             self.areas.remove(idx);
         }
         if let Some(idx) = break_apart_idx {    //处理重叠区域在中间的情况，分割该区域为三部分，取消中间部分映射，重新插入分割后的两部分到原位置。
-            let (mut second, third) = self.areas[idx].into_three(start_vpn, end_vpn).unwrap();
+            let (mut second, third) = match self.areas[idx].into_three(start_vpn, end_vpn) {
+                Ok(pieces) => pieces,
+                Err(_) => return Err(EINVAL),
+            };
             if let Err(_) = second.unmap(page_table) {
                 warn!("[munmap] Some pages are already unmapped, is it caused by lazy alloc?");
             }
@@ -923,7 +1115,10 @@ impl<T: PageTable> MemorySet<T> {
                     &mut self.areas[idx + 1]
                 } else {
                     trace!("[mprotect] change prot of internal part, call into_three");
-                    let (second, third) = self.areas[idx].into_three(start_vpn, end_vpn).unwrap();
+                    let (second, third) = match self.areas[idx].into_three(start_vpn, end_vpn) {
+                        Ok(pieces) => pieces,
+                        Err(_) => return Err(EINVAL),
+                    };
                     self.areas.insert(idx + 1, second);
                     self.areas.insert(idx + 2, third);
                     &mut self.areas[idx + 1]
@@ -956,6 +1151,8 @@ impl<T: PageTable> MemorySet<T> {
         argv_vec: &Vec<String>,
         envp_vec: &Vec<String>,
         elf_info: &ELFInfo,
+        cred: &Credentials,
+        secure: bool,
     ) -> usize {
         // go down to the stack page (important!) and align
         user_sp -= 2 * core::mem::size_of::<usize>();
@@ -1002,10 +1199,8 @@ impl<T: PageTable> MemorySet<T> {
         phys_user_sp -= 2 * core::mem::size_of::<usize>();
         // should be virt addr!
         let random_bits_ptr = phys_user_sp + virt_phys_offset;
-        unsafe {
-            *(phys_user_sp as *mut usize) = 0xdeadbeefcafebabe;
-            *(phys_user_sp as *mut usize).add(1) = 0xdeadbeefcafebabe;
-        }
+        // seeds stack protectors and pointer guards of libc, never blocks
+        get_random_bytes(unsafe { core::slice::from_raw_parts_mut(phys_user_sp as *mut u8, 16) });
         // padding
         phys_user_sp -= core::mem::size_of::<usize>();
         unsafe {
@@ -1029,11 +1224,11 @@ impl<T: PageTable> MemorySet<T> {
             AuxvEntry::new(AuxvType::BASE, elf_info.base),
             AuxvEntry::new(AuxvType::FLAGS, 0),
             AuxvEntry::new(AuxvType::ENTRY, elf_info.entry),
-            AuxvEntry::new(AuxvType::UID, 0),
-            AuxvEntry::new(AuxvType::EUID, 0),
-            AuxvEntry::new(AuxvType::GID, 0),
-            AuxvEntry::new(AuxvType::EGID, 0),
-            AuxvEntry::new(AuxvType::SECURE, 0),
+            AuxvEntry::new(AuxvType::UID, cred.uid as usize),
+            AuxvEntry::new(AuxvType::EUID, cred.euid as usize),
+            AuxvEntry::new(AuxvType::GID, cred.gid as usize),
+            AuxvEntry::new(AuxvType::EGID, cred.egid as usize),
+            AuxvEntry::new(AuxvType::SECURE, secure as usize),
             AuxvEntry::new(AuxvType::RANDOM, random_bits_ptr as usize),
             AuxvEntry::new(
                 AuxvType::EXECFN,
@@ -1177,7 +1372,9 @@ pub fn check_page_fault(addr: VirtAddr) -> Result<PhysAddr, isize> {
         Ok(pa) => return Ok(pa),
         Err(MemoryError::BeyondEOF)
         | Err(MemoryError::NoPermission)
-        | Err(MemoryError::BadAddress) => {
+        | Err(MemoryError::BadAddress)
+        | Err(MemoryError::OutOfMemory)
+        | Err(MemoryError::IoError) => {
             return Err(EFAULT);
         }
         _ => unreachable!(),
@@ -13,7 +13,8 @@ use address::VPNRange;
 
 pub use address::{PhysAddr, PhysPageNum, StepByOne, VirtAddr, VirtPageNum};
 pub use frame_allocator::{
-    frame_alloc, frame_alloc_uninit, frame_dealloc, frame_reserve, unallocated_frames, FrameTracker,
+    frame_alloc, frame_alloc_uninit, frame_dealloc, frame_reserve, total_frames,
+    unallocated_frames, FrameTracker,
 };
 pub use map_area::{Frame, MapFlags, MapPermission};
 pub use memory_set::{kernel_token, remap_test, MemoryError, MemorySet, KERNEL_SPACE};
@@ -29,6 +30,10 @@ pub fn init() {
     frame_allocator::init_frame_allocator();
     KERNEL_SPACE.lock().activate();
 }
+/// Load the kernel page table on a secondary core, the allocators are shared.
+pub fn init_secondary() {
+    KERNEL_SPACE.lock().activate();
+}
 pub use crate::arch::tlb_invalidate;
 
 #[macro_export]
@@ -201,6 +201,24 @@ impl UserBuffer {
         self.len
     }
 
+    /// Keep the first `len` bytes only
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+        let mut rest = len;
+        let buffers = core::mem::take(&mut self.buffers);
+        for buffer in buffers {
+            if rest == 0 {
+                break;
+            }
+            let size = buffer.len().min(rest);
+            self.buffers.push(buffer.split_at_mut(size).0);
+            rest -= size;
+        }
+        self.len = len;
+    }
+
     pub fn read(&self, dst: &mut [u8]) -> usize {
         let mut start = 0;
         let dst_len = dst.len();
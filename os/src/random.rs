@@ -0,0 +1,277 @@
+//! Kernel random number generator.
+//!
+//! Entropy from timer jitter, interrupt timing and hardware generators is absorbed into an input pool,
+//! a sponge built on the ChaCha permutation. Once 256 bits have been credited to it,
+//! the pool keys a ChaCha20 CRNG, which is rekeyed from the pool every minute afterwards.
+//! Every request first replaces the CRNG key with output of the old one (fast key erasure),
+//! so what was handed out before can't be reconstructed from the current state.
+use crate::syscall::errno::{EAGAIN, EINTR};
+use crate::task::{current_task, suspend_current_and_run_next};
+use crate::timer::{get_realtime_ns, get_time, get_time_sec};
+use lazy_static::*;
+use log::info;
+use spin::Mutex;
+
+/// Bits to credit before the CRNG is considered seeded
+const POOL_READY_BITS: usize = 256;
+/// The most bits the pool is credited with, it holds 512 bits of state
+const POOL_MAX_BITS: usize = 512;
+/// The CRNG takes a fresh key from the pool this often once seeded
+const RESEED_INTERVAL_SEC: usize = 60;
+/// One bit is credited for this many interrupts
+const INTERRUPTS_PER_BIT: usize = 64;
+/// Timer samples taken by one round of jitter collection
+const JITTER_SAMPLES: usize = 1024;
+
+const CHACHA_CONSTANTS: [u32; 4] = [0x61707865, 0x3320646e, 0x79622d32, 0x6b206574];
+
+fn quarter_round(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(16);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(12);
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(8);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(7);
+}
+
+/// The 20 rounds of ChaCha, without adding the input back
+fn chacha_permute(x: &mut [u32; 16]) {
+    for _ in 0..10 {
+        quarter_round(x, 0, 4, 8, 12);
+        quarter_round(x, 1, 5, 9, 13);
+        quarter_round(x, 2, 6, 10, 14);
+        quarter_round(x, 3, 7, 11, 15);
+        quarter_round(x, 0, 5, 10, 15);
+        quarter_round(x, 1, 6, 11, 12);
+        quarter_round(x, 2, 7, 8, 13);
+        quarter_round(x, 3, 4, 9, 14);
+    }
+}
+
+/// One 64-byte block of the ChaCha20 stream of `key` at `counter`, the nonce is always zero
+fn chacha20_block(key: &[u32; 8], counter: u64) -> [u32; 16] {
+    let mut input = [0u32; 16];
+    input[..4].copy_from_slice(&CHACHA_CONSTANTS);
+    input[4..12].copy_from_slice(key);
+    input[12] = counter as u32;
+    input[13] = (counter >> 32) as u32;
+    let mut block = input;
+    chacha_permute(&mut block);
+    for (word, input) in block.iter_mut().zip(input.iter()) {
+        *word = word.wrapping_add(*input);
+    }
+    block
+}
+
+fn copy_block(block: &[u32], buf: &mut [u8]) {
+    for (word, chunk) in block.iter().zip(buf.chunks_mut(4)) {
+        chunk.copy_from_slice(&word.to_le_bytes()[..chunk.len()]);
+    }
+}
+
+struct InputPool {
+    /// The first 8 words take the input, the rest is never exposed
+    state: [u32; 16],
+    entropy_bits: usize,
+}
+
+impl InputPool {
+    fn mix(&mut self, data: &[u8]) {
+        for chunk in data.chunks(32) {
+            for (index, bytes) in chunk.chunks(4).enumerate() {
+                let mut word = [0u8; 4];
+                word[..bytes.len()].copy_from_slice(bytes);
+                self.state[index] ^= u32::from_le_bytes(word);
+            }
+            chacha_permute(&mut self.state);
+        }
+    }
+    fn credit(&mut self, bits: usize) {
+        self.entropy_bits = (self.entropy_bits + bits).min(POOL_MAX_BITS);
+    }
+    /// A key derived from the pool as it is, for use before it has been credited enough
+    fn peek(&self) -> [u32; 8] {
+        let mut state = self.state;
+        chacha_permute(&mut state);
+        let mut key = [0u32; 8];
+        key.copy_from_slice(&state[..8]);
+        key
+    }
+    /// Squeeze a key out of the pool and clear what it was taken from,
+    /// so the pool can't be wound back to recover it
+    fn extract(&mut self) -> [u32; 8] {
+        chacha_permute(&mut self.state);
+        let mut key = [0u32; 8];
+        key.copy_from_slice(&self.state[..8]);
+        self.state[..8].fill(0);
+        chacha_permute(&mut self.state);
+        self.entropy_bits = 0;
+        key
+    }
+}
+
+struct Random {
+    pool: InputPool,
+    key: [u32; 8],
+    /// The key has been taken from a pool credited with `POOL_READY_BITS` at least
+    ready: bool,
+    seeded_at: usize,
+    interrupts: usize,
+    /// Timer deltas of the last sample, to tell whether the timer moved unpredictably
+    last_time: usize,
+    last_delta: usize,
+}
+
+impl Random {
+    fn reseed(&mut self) {
+        self.key = self.pool.extract();
+        self.seeded_at = get_time_sec();
+        if !self.ready {
+            self.ready = true;
+            info!("[random] crng init done");
+        }
+    }
+    fn credit(&mut self, bits: usize) {
+        self.pool.credit(bits);
+        if !self.ready && self.pool.entropy_bits >= POOL_READY_BITS {
+            self.reseed();
+        }
+    }
+    /// Mix the time of an event, credit a bit if the timer has moved unlike the last time
+    fn add_timer_randomness(&mut self, event: usize) {
+        let now = get_time();
+        let delta = now.wrapping_sub(self.last_time);
+        let delta2 = delta.wrapping_sub(self.last_delta);
+        self.last_time = now;
+        self.last_delta = delta;
+        let mut data = [0u8; 16];
+        data[..8].copy_from_slice(&now.to_le_bytes());
+        data[8..].copy_from_slice(&event.to_le_bytes());
+        self.pool.mix(&data);
+        if delta != 0 && delta2 != 0 {
+            self.credit(1);
+        }
+    }
+    /// The key for a single request, replacing the stored one with the first half of its first block
+    fn take_key(&mut self) -> [u32; 8] {
+        if self.ready && get_time_sec() - self.seeded_at >= RESEED_INTERVAL_SEC {
+            self.reseed();
+        }
+        let key = self.key;
+        let first_block = chacha20_block(&key, 0);
+        self.key.copy_from_slice(&first_block[..8]);
+        key
+    }
+}
+
+lazy_static! {
+    static ref RANDOM: Mutex<Random> = Mutex::new(Random {
+        pool: InputPool {
+            state: [0; 16],
+            entropy_bits: 0,
+        },
+        key: [0; 8],
+        ready: false,
+        seeded_at: 0,
+        interrupts: 0,
+        last_time: 0,
+        last_delta: 0,
+    });
+}
+
+/// Time a piece of work `JITTER_SAMPLES` times, the variation of the timer is the entropy
+fn collect_jitter() {
+    let mut scratch = [0u32; 16];
+    for sample in 0..JITTER_SAMPLES {
+        scratch[0] = scratch[0].wrapping_add(sample as u32);
+        chacha_permute(&mut scratch);
+        RANDOM.lock().add_timer_randomness(scratch[0] as usize);
+    }
+}
+
+/// Seed the pool at boot, the timer frequency and the wall time must be known by now
+pub fn init() {
+    let mut seed = [0u8; 16];
+    seed[..8].copy_from_slice(&get_time().to_le_bytes());
+    seed[8..].copy_from_slice(&get_realtime_ns().to_le_bytes());
+    RANDOM.lock().pool.mix(&seed);
+    collect_jitter();
+    let mut random = RANDOM.lock();
+    if !random.ready {
+        // good enough for `GRND_INSECURE` and `/dev/urandom` until there is enough entropy
+        random.key = random.pool.peek();
+    }
+    info!(
+        "[random] {} bits of entropy at boot, crng ready: {}",
+        random.pool.entropy_bits, random.ready
+    );
+}
+
+/// Mix data which may not be secret at all, like serial numbers or the boot time, without crediting it
+pub fn add_device_randomness(data: &[u8]) {
+    RANDOM.lock().pool.mix(data);
+}
+
+/// Data from a hardware generator, credited with `bits` of entropy
+pub fn add_hwgenerator_randomness(data: &[u8], bits: usize) {
+    let mut random = RANDOM.lock();
+    random.pool.mix(data);
+    random.credit(bits);
+}
+
+/// Called on every interrupt, the sample is dropped rather than spinning on the pool
+pub fn add_interrupt_randomness(irq: usize) {
+    if let Some(mut random) = RANDOM.try_lock() {
+        let now = get_time();
+        let mut data = [0u8; 16];
+        data[..8].copy_from_slice(&now.to_le_bytes());
+        data[8..].copy_from_slice(&irq.to_le_bytes());
+        random.pool.mix(&data);
+        random.interrupts += 1;
+        if random.interrupts % INTERRUPTS_PER_BIT == 0 {
+            random.credit(1);
+        }
+    }
+}
+
+/// Input events such as key strokes, their timing is what is unpredictable
+pub fn add_input_randomness(value: usize) {
+    RANDOM.lock().add_timer_randomness(value);
+}
+
+/// The CRNG has been seeded with enough entropy
+pub fn crng_ready() -> bool {
+    RANDOM.lock().ready
+}
+
+/// Entropy credited to the pool since the last reseed, in bits
+pub fn entropy_avail() -> usize {
+    RANDOM.lock().pool.entropy_bits
+}
+
+/// Block until the CRNG is seeded, collecting timer jitter meanwhile.
+/// # Return Conditions
+/// Returns `EAGAIN` if it is not and `nonblock` is set, `EINTR` if a signal arrives while waiting.
+pub fn wait_for_random_bytes(nonblock: bool) -> Result<(), isize> {
+    while !crng_ready() {
+        if nonblock {
+            return Err(EAGAIN);
+        }
+        if current_task().map_or(false, |task| task.signal_pending()) {
+            return Err(EINTR);
+        }
+        collect_jitter();
+        suspend_current_and_run_next();
+    }
+    Ok(())
+}
+
+/// Fill `buf` from the CRNG, whether it has been seeded or not
+pub fn get_random_bytes(buf: &mut [u8]) {
+    let key = RANDOM.lock().take_key();
+    for (counter, chunk) in buf.chunks_mut(64).enumerate() {
+        copy_block(&chacha20_block(&key, counter as u64 + 1), chunk);
+    }
+}
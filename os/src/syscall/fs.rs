@@ -1,15 +1,21 @@
 use crate::arch::BLOCK_SZ;
+use crate::fs::epoll::{epoll_create1, epoll_ctl, epoll_pwait, EpollEvent};
 use crate::fs::poll::{ppoll, pselect, FdSet, PollFd};
 use crate::fs::*;
 use crate::mm::{
     copy_from_user, copy_from_user_array, copy_to_user, copy_to_user_array, copy_to_user_string,
-    translated_byte_buffer, translated_byte_buffer_append_to_existing_vec, translated_refmut,
-    translated_str, try_get_from_user, MapPermission, UserBuffer, VirtAddr,
+    get_from_user, translated_byte_buffer, translated_byte_buffer_append_to_existing_vec,
+    translated_refmut, translated_str, try_get_from_user, MapPermission, UserBuffer, VirtAddr,
 };
-use crate::task::{current_task, current_user_token};
-use crate::timer::TimeSpec;
+use crate::task::posix_timer::{
+    ClockId, CLOCK_BOOTTIME, CLOCK_BOOTTIME_ALARM, CLOCK_MONOTONIC, CLOCK_REALTIME,
+    CLOCK_REALTIME_ALARM,
+};
+use crate::task::{current_task, current_user_token, Signals};
+use crate::timer::{ITimerSpec, TimeSpec};
 use alloc::boxed::Box;
 use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::mem::size_of;
 use log::{debug, info, trace, warn};
@@ -33,7 +39,8 @@ fn __openat(dirfd: usize, path: &str) -> Result<FileDescriptor, isize> {
             }
         }
     };
-    file_descriptor.open(path, OpenFlags::O_RDONLY, false)
+    // a lookup, no access to the file itself is needed
+    file_descriptor.open(path, OpenFlags::O_PATH, false)
 }
 
 pub fn sys_getcwd(buf: usize, size: usize) -> isize {
@@ -94,9 +101,9 @@ pub fn sys_lseek(fd: usize, offset: isize, whence: u32) -> isize {
 
 pub fn sys_read(fd: usize, buf: usize, count: usize) -> isize {
     let task = current_task().unwrap();
-    let fd_table = task.files.lock();
-    let file_descriptor = match fd_table.get_ref(fd) {
-        Ok(file_descriptor) => file_descriptor,
+    // the fd table is unlocked before reading, the file may block
+    let file_descriptor = match task.files.lock().get_ref(fd) {
+        Ok(file_descriptor) => file_descriptor.clone(),
         Err(errno) => return errno,
     };
     // fd is not open for reading
@@ -117,9 +124,8 @@ pub fn sys_read(fd: usize, buf: usize, count: usize) -> isize {
 
 pub fn sys_write(fd: usize, buf: usize, count: usize) -> isize {
     let task = current_task().unwrap();
-    let fd_table = task.files.lock();
-    let file_descriptor = match fd_table.get_ref(fd) {
-        Ok(file_descriptor) => file_descriptor,
+    let file_descriptor = match task.files.lock().get_ref(fd) {
+        Ok(file_descriptor) => file_descriptor.clone(),
         Err(errno) => return errno,
     };
     if !file_descriptor.writable() {
@@ -139,9 +145,8 @@ pub fn sys_write(fd: usize, buf: usize, count: usize) -> isize {
 
 pub fn sys_pread(fd: usize, buf: usize, count: usize, offset: usize) -> isize {
     let task = current_task().unwrap();
-    let fd_table = task.files.lock();
-    let file_descriptor = match fd_table.get_ref(fd) {
-        Ok(file_descriptor) => file_descriptor,
+    let file_descriptor = match task.files.lock().get_ref(fd) {
+        Ok(file_descriptor) => file_descriptor.clone(),
         Err(errno) => return errno,
     };
     // fd is not open for reading
@@ -162,9 +167,8 @@ pub fn sys_pread(fd: usize, buf: usize, count: usize, offset: usize) -> isize {
 
 pub fn sys_pwrite(fd: usize, buf: usize, count: usize, offset: usize) -> isize {
     let task = current_task().unwrap();
-    let fd_table = task.files.lock();
-    let file_descriptor = match fd_table.get_ref(fd) {
-        Ok(file_descriptor) => file_descriptor,
+    let file_descriptor = match task.files.lock().get_ref(fd) {
+        Ok(file_descriptor) => file_descriptor.clone(),
         Err(errno) => return errno,
     };
     // fd is not open for writing
@@ -185,16 +189,15 @@ pub fn sys_pwrite(fd: usize, buf: usize, count: usize, offset: usize) -> isize {
 
 #[repr(C)]
 #[derive(Clone, Copy)]
-struct IOVec {
-    iov_base: *const u8, /* Starting address */
-    iov_len: usize,      /* Number of bytes to transfer */
+pub struct IOVec {
+    pub iov_base: *const u8, /* Starting address */
+    pub iov_len: usize,      /* Number of bytes to transfer */
 }
 
 pub fn sys_readv(fd: usize, iov: usize, iovcnt: usize) -> isize {
     let task = current_task().unwrap();
-    let fd_table = task.files.lock();
-    let file_descriptor = match fd_table.get_ref(fd) {
-        Ok(file_descriptor) => file_descriptor,
+    let file_descriptor = match task.files.lock().get_ref(fd) {
+        Ok(file_descriptor) => file_descriptor.clone(),
         Err(errno) => return errno,
     };
     // fd is not open for reading
@@ -231,9 +234,8 @@ pub fn sys_readv(fd: usize, iov: usize, iovcnt: usize) -> isize {
 
 pub fn sys_writev(fd: usize, iov: usize, iovcnt: usize) -> isize {
     let task = current_task().unwrap();
-    let fd_table = task.files.lock();
-    let file_descriptor = match fd_table.get_ref(fd) {
-        Ok(file_descriptor) => file_descriptor,
+    let file_descriptor = match task.files.lock().get_ref(fd) {
+        Ok(file_descriptor) => file_descriptor.clone(),
         Err(errno) => return errno,
     };
     // fd is not open for writing
@@ -285,13 +287,14 @@ pub fn sys_sendfile(out_fd: usize, in_fd: usize, offset: *mut usize, count: usiz
     let task = current_task().unwrap();
     let fd_table = task.files.lock();
     let in_file = match fd_table.get_ref(in_fd) {
-        Ok(file_descriptor) => file_descriptor,
+        Ok(file_descriptor) => file_descriptor.clone(),
         Err(errno) => return errno,
     };
     let out_file = match fd_table.get_ref(out_fd) {
-        Ok(file_descriptor) => file_descriptor,
+        Ok(file_descriptor) => file_descriptor.clone(),
         Err(errno) => return errno,
     };
+    drop(fd_table);
     info!("[sys_sendfile] outfd: {}, in_fd: {}", out_fd, in_fd);
     if !in_file.readable() || !out_file.writable() {
         return EBADF;
@@ -385,13 +388,14 @@ pub fn sys_splice(fd_in: usize, off_in: *mut usize, fd_out: usize, off_out: *mut
     let task = current_task().unwrap();
     let fd_table = task.files.lock();
     let in_file = match fd_table.get_ref(fd_in) {
-        Ok(file_descriptor) => file_descriptor,
+        Ok(file_descriptor) => file_descriptor.clone(),
         Err(errno) => return errno,
     };
-    let out_file: &FileDescriptor = match fd_table.get_ref(fd_out) {
-        Ok(file_descriptor) => file_descriptor,
+    let out_file: FileDescriptor = match fd_table.get_ref(fd_out) {
+        Ok(file_descriptor) => file_descriptor.clone(),
         Err(errno) => return errno,
     };
+    drop(fd_table);
     if !in_file.readable() || !out_file.writable() {
         return EBADF;
     }
@@ -537,6 +541,151 @@ pub fn sys_pipe2(pipefd: usize, flags: u32) -> isize {
     SUCCESS
 }
 
+pub fn sys_eventfd2(initval: u32, flags: u32) -> isize {
+    if flags & !(EventFd::EFD_SEMAPHORE | EventFd::EFD_NONBLOCK | EventFd::EFD_CLOEXEC) != 0 {
+        warn!("[sys_eventfd2] invalid flags: {:#o}", flags);
+        return EINVAL;
+    }
+    let eventfd = EventFd::new(initval, flags & EventFd::EFD_SEMAPHORE != 0);
+    let task = current_task().unwrap();
+    let mut fd_table = task.files.lock();
+    let fd = match fd_table.insert(FileDescriptor::new(
+        flags & EventFd::EFD_CLOEXEC != 0,
+        flags & EventFd::EFD_NONBLOCK != 0,
+        eventfd,
+    )) {
+        Ok(fd) => fd,
+        Err(errno) => return errno,
+    };
+    info!(
+        "[sys_eventfd2] fd: {}, initval: {}, flags: {:#o}",
+        fd, initval, flags
+    );
+    fd as isize
+}
+
+/// `fd` of -1 makes a new signalfd, otherwise the mask of signalfd `fd` is replaced
+pub fn sys_signalfd4(fd: usize, mask: *const Signals, sizemask: usize, flags: u32) -> isize {
+    if flags & !(SignalFd::SFD_NONBLOCK | SignalFd::SFD_CLOEXEC) != 0
+        || sizemask != size_of::<Signals>()
+    {
+        return EINVAL;
+    }
+    let task = current_task().unwrap();
+    let mask = match get_from_user(task.get_user_token(), mask) {
+        Ok(mask) => mask,
+        Err(errno) => return errno,
+    };
+    info!(
+        "[sys_signalfd4] fd: {}, mask: {:?}, flags: {:#o}",
+        fd as isize, mask, flags
+    );
+    let mut fd_table = task.files.lock();
+    if fd as isize != -1 {
+        let file_descriptor = match fd_table.get_ref(fd) {
+            Ok(file_descriptor) => file_descriptor,
+            Err(errno) => return errno,
+        };
+        return match file_descriptor.file.clone().downcast_arc::<SignalFd>() {
+            Ok(signalfd) => {
+                signalfd.set_mask(mask);
+                fd as isize
+            }
+            Err(_) => EINVAL,
+        };
+    }
+    match fd_table.insert(FileDescriptor::new(
+        flags & SignalFd::SFD_CLOEXEC != 0,
+        flags & SignalFd::SFD_NONBLOCK != 0,
+        SignalFd::new(mask),
+    )) {
+        Ok(fd) => fd as isize,
+        Err(errno) => errno,
+    }
+}
+
+/// Get the timerfd behind `fd`
+fn get_timerfd(fd: usize) -> Result<Arc<TimerFd>, isize> {
+    let task = current_task().unwrap();
+    let fd_table = task.files.lock();
+    let file_descriptor = fd_table.get_ref(fd)?;
+    file_descriptor
+        .file
+        .clone()
+        .downcast_arc::<TimerFd>()
+        .map_err(|_| EINVAL)
+}
+
+pub fn sys_timerfd_create(clockid: usize, flags: u32) -> isize {
+    if flags & !(TimerFd::TFD_NONBLOCK | TimerFd::TFD_CLOEXEC) != 0 {
+        return EINVAL;
+    }
+    // CPU time clocks are not allowed
+    let clock = match clockid {
+        CLOCK_REALTIME | CLOCK_MONOTONIC | CLOCK_BOOTTIME | CLOCK_REALTIME_ALARM
+        | CLOCK_BOOTTIME_ALARM => ClockId::from_user(clockid).unwrap(),
+        _ => return EINVAL,
+    };
+    let task = current_task().unwrap();
+    let mut fd_table = task.files.lock();
+    let fd = match fd_table.insert(FileDescriptor::new(
+        flags & TimerFd::TFD_CLOEXEC != 0,
+        flags & TimerFd::TFD_NONBLOCK != 0,
+        TimerFd::new(clock),
+    )) {
+        Ok(fd) => fd,
+        Err(errno) => return errno,
+    };
+    info!(
+        "[sys_timerfd_create] fd: {}, clock: {:?}, flags: {:#o}",
+        fd, clock, flags
+    );
+    fd as isize
+}
+
+pub fn sys_timerfd_settime(
+    fd: usize,
+    flags: u32,
+    new_value: *const ITimerSpec,
+    old_value: *mut ITimerSpec,
+) -> isize {
+    if flags & !(TimerFd::TFD_TIMER_ABSTIME | TimerFd::TFD_TIMER_CANCEL_ON_SET) != 0 {
+        return EINVAL;
+    }
+    let timerfd = match get_timerfd(fd) {
+        Ok(timerfd) => timerfd,
+        Err(errno) => return errno,
+    };
+    let token = current_user_token();
+    let new = match get_from_user(token, new_value) {
+        Ok(new) => new,
+        Err(errno) => return errno,
+    };
+    info!(
+        "[sys_timerfd_settime] fd: {}, flags: {}, new_value: {:?}",
+        fd, flags, new
+    );
+    let old = match timerfd.settime(flags, new) {
+        Ok(old) => old,
+        Err(errno) => return errno,
+    };
+    if !old_value.is_null() && copy_to_user(token, &old, old_value).is_err() {
+        return EFAULT;
+    }
+    SUCCESS
+}
+
+pub fn sys_timerfd_gettime(fd: usize, curr_value: *mut ITimerSpec) -> isize {
+    let timerfd = match get_timerfd(fd) {
+        Ok(timerfd) => timerfd,
+        Err(errno) => return errno,
+    };
+    if copy_to_user(current_user_token(), &timerfd.gettime(), curr_value).is_err() {
+        return EFAULT;
+    }
+    SUCCESS
+}
+
 pub fn sys_getdents64(fd: usize, dirp: *mut u8, count: usize) -> isize {
     let task = current_task().unwrap();
     let token = task.get_user_token();
@@ -584,22 +733,6 @@ pub fn sys_dup(oldfd: usize) -> isize {
     info!("[sys_dup] oldfd: {}, newfd: {}", oldfd, newfd);
     newfd as isize
 }
-pub fn sys_dup2(oldfd: usize, newfd: usize) -> isize {
-    info!("[sys_dup2] oldfd: {}, newfd: {}",oldfd,newfd);
-    if oldfd == newfd {
-        return newfd as isize;
-    }
-    let task = current_task().unwrap();
-    let mut fd_table = task.files.lock();
-    let mut file_descriptor = match fd_table.get_ref(oldfd) {
-        Ok(file_descriptor) => file_descriptor.clone(),
-        Err(errno) => return errno,
-    };
-    match fd_table.insert_at(file_descriptor, newfd) {
-        Ok(fd) => fd as isize,
-        Err(errno) => errno,
-    }
-}
 pub fn sys_dup3(oldfd: usize, newfd: usize, flags: u32) -> isize {
     info!(
         "[sys_dup3] oldfd: {}, newfd: {}, flags: {:?}",
@@ -646,21 +779,19 @@ pub fn sys_readlinkat(dirfd: usize, pathname: *const u8, buf: *mut u8, bufsiz: u
         Ok(path) => path,
         Err(errno) => return errno,
     };
-    let real_path = if path.as_str() == "/proc/self/exe" {
-        task.exe.lock().get_cwd().unwrap()
-    } else {
-        match __openat(dirfd, &path) {
-            Ok(_) => {
-                // we don't implement symbolic link, so if we found it...
+    // only procfs provides symbolic links, e.g. `/proc/self/exe`
+    let real_path = match __openat(dirfd, &path) {
+        Ok(file_descriptor) => match file_descriptor.file.read_link() {
+            Ok(target) => target,
+            Err(errno) => {
                 warn!(
                     "[sys_readlinkat] not a symbolic link! dirfd: {}, path: {}",
                     dirfd as isize, path
                 );
-                // The file of `pathname` is not a symbolic link
-                return EINVAL;
+                return errno;
             }
-            Err(errno) => return errno,
-        }
+        },
+        Err(errno) => return errno,
     };
     let len = real_path.len().min(bufsiz - 1);
     // `copy_to_user_string` will add '\0' in the end, so written length is `len + 1`
@@ -716,7 +847,7 @@ pub fn sys_fstatat(dirfd: usize, path: *const u8, buf: *mut u8, flags: u32) -> i
         }
     };
 
-    match file_descriptor.open(&path, OpenFlags::O_RDONLY, false) {//获取打开文件的新的文件描述符
+    match file_descriptor.open(&path, OpenFlags::O_PATH, false) {//获取打开文件的新的文件描述符
         Ok(file_descriptor) => {//调用copy_to_user将文件信息内容拷贝到buf中
             if copy_to_user(token, &file_descriptor.get_stat(), buf as *mut Stat).is_err() {
                 log::error!("[sys_fstatat] Failed to copy to {:?}", buf);
@@ -781,7 +912,7 @@ pub fn sys_statx(dirfd: usize, path: *const u8, flags: u32, mask: u32, buf: *mut
         }
     };
 
-    match file_descriptor.open(&path, OpenFlags::O_RDONLY, false) {//获取打开文件的新的文件描述符
+    match file_descriptor.open(&path, OpenFlags::O_PATH, false) {//获取打开文件的新的文件描述符
         Ok(file_descriptor) => {//调用copy_to_user将文件信息内容拷贝到buf中
             // if copy_to_user(token, &file_descriptor.get_stat(), buf as *mut Stat).is_err() {
             let buf_st_size_ptr = unsafe { buf.offset(40) }; //获得st_size的偏移地址
@@ -884,10 +1015,10 @@ pub fn sys_chdir(path: *const u8) -> isize {
   pub fn sys_openat(dirfd: usize, path: *const u8, flags: u32, mode: u32) -> isize {  
     let task = current_task().unwrap();
     let token = task.get_user_token();
-    let mut fd_table = task.files.lock();
     let file_descriptor = match dirfd {    //根据dirfd获取文件描述符
         AT_FDCWD => task.fs.lock().working_inode.as_ref().clone(),
         fd => {
+            let fd_table = task.files.lock();
             match fd_table.get_ref(fd) {
                 Ok(file_descriptor) => file_descriptor.clone(),
                 Err(errno) => return errno,
@@ -906,6 +1037,8 @@ pub fn sys_chdir(path: *const u8) -> isize {
             Ok(new_file_descriptor) => new_file_descriptor,
             Err(errno) =>return errno,
     };
+    // don't hold the fd table during lookup, listing `/proc/self/fd` needs it
+    let mut fd_table = task.files.lock();
     let new_fd = match fd_table.insert(new_file_descriptor){
         Ok(new_fd) => new_fd,
         Err(errno) =>return errno,
@@ -985,6 +1118,32 @@ pub fn sys_ppoll(fds: usize, nfds: usize, tmo_p: usize, sigmask: usize) -> isize
     )
 }
 
+pub fn sys_epoll_create1(flags: u32) -> isize {
+    epoll_create1(flags)
+}
+
+pub fn sys_epoll_ctl(epfd: usize, op: u32, fd: usize, event: usize) -> isize {
+    epoll_ctl(epfd, op, fd, event as *const EpollEvent)
+}
+
+pub fn sys_epoll_pwait(
+    epfd: usize,
+    events: usize,
+    maxevents: usize,
+    timeout: i32,
+    sigmask: usize,
+    sigsetsize: usize,
+) -> isize {
+    epoll_pwait(
+        epfd,
+        events as *mut EpollEvent,
+        maxevents,
+        timeout,
+        sigmask as *const crate::task::Signals,
+        sigsetsize,
+    )
+}
+
 pub fn sys_mkdirat(dirfd: usize, path: *const u8, mode: u32) -> isize {
     let task = current_task().unwrap();
     let token = task.get_user_token();
@@ -1014,6 +1173,96 @@ pub fn sys_mkdirat(dirfd: usize, path: *const u8, mode: u32) -> isize {
     }
 }
 
+pub fn sys_mknodat(dirfd: usize, path: *const u8, mode: u32, dev: usize) -> isize {
+    let task = current_task().unwrap();
+    let token = task.get_user_token();
+    let path = match translated_str(token, path) {
+        Ok(path) => path,
+        Err(errno) => return errno,
+    };
+    info!(
+        "[sys_mknodat] dirfd: {}, path: {}, mode: {:?}, dev: {:#x}",
+        dirfd as isize,
+        path,
+        StatMode::from_bits(mode),
+        dev
+    );
+    let file_descriptor = match dirfd {
+        AT_FDCWD => task.fs.lock().working_inode.as_ref().clone(),
+        fd => {
+            let fd_table = task.files.lock();
+            match fd_table.get_ref(fd) {
+                Ok(file_descriptor) => file_descriptor.clone(),
+                Err(errno) => return errno,
+            }
+        }
+    };
+    let file_type = StatMode::from_bits_truncate(mode & StatMode::S_IFMT.bits());
+    // only root may make device nodes
+    if (file_type == StatMode::S_IFCHR || file_type == StatMode::S_IFBLK)
+        && !task.cred.lock().is_privileged()
+    {
+        return EPERM;
+    }
+    let result = if file_type.is_empty() || file_type == StatMode::S_IFREG {
+        // a zero file type means a regular file
+        file_descriptor
+            .open(&path, OpenFlags::O_CREAT | OpenFlags::O_EXCL, false)
+            .map(|new_file| {
+                // not every file system keeps permission bits
+                new_file.chmod(mode).ok();
+            })
+    } else if file_type == StatMode::S_IFDIR {
+        Err(EPERM)
+    } else if file_type == StatMode::S_IFIFO
+        || file_type == StatMode::S_IFCHR
+        || file_type == StatMode::S_IFBLK
+        || file_type == StatMode::S_IFSOCK
+    {
+        file_descriptor.make_special(&path, mode, dev as u64)
+    } else {
+        Err(EINVAL)
+    };
+    match result {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_symlinkat(target: *const u8, newdirfd: usize, linkpath: *const u8) -> isize {
+    let task = current_task().unwrap();
+    let token = task.get_user_token();
+    let target = match translated_str(token, target) {
+        Ok(target) => target,
+        Err(errno) => return errno,
+    };
+    let linkpath = match translated_str(token, linkpath) {
+        Ok(path) => path,
+        Err(errno) => return errno,
+    };
+    info!(
+        "[sys_symlinkat] target: {}, newdirfd: {}, linkpath: {}",
+        target, newdirfd as isize, linkpath
+    );
+    if target.is_empty() {
+        return ENOENT;
+    }
+    let file_descriptor = match newdirfd {
+        AT_FDCWD => task.fs.lock().working_inode.as_ref().clone(),
+        fd => {
+            let fd_table = task.files.lock();
+            match fd_table.get_ref(fd) {
+                Ok(file_descriptor) => file_descriptor.clone(),
+                Err(errno) => return errno,
+            }
+        }
+    };
+    match file_descriptor.symlink(&target, &linkpath) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
 bitflags! {
     pub struct UnlinkatFlags: u32 {
         const AT_REMOVEDIR = 0x200;
@@ -1131,8 +1380,15 @@ pub fn sys_umount2(target: *const u8, flags: u32) -> isize {
         None => return EINVAL,
     };
     info!("[sys_umount2] target: {}, flags: {:?}", target, flags);
-    warn!("[sys_umount2] fake implementation!");
-    SUCCESS
+    let task = current_task().unwrap();
+    if !task.cred.lock().is_privileged() {
+        return EPERM;
+    }
+    let file_descriptor = task.fs.lock().working_inode.as_ref().clone();
+    match file_descriptor.umount(&target, flags.contains(UmountFlags::MNT_DETACH)) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
 }
 
 bitflags! {
@@ -1198,8 +1454,25 @@ pub fn sys_mount(
         "[sys_mount] source: {}, target: {}, filesystemtype: {}, mountflags: {:?}, data: {:?}",
         source, target, filesystemtype, mountflags, data
     );
-    warn!("[sys_mount] fake implementation!");
-    SUCCESS
+    if mountflags.intersects(MountFlags::MS_BIND | MountFlags::MS_MOVE) {
+        warn!("[sys_mount] bind mount and move are not supported");
+        return EINVAL;
+    }
+    let task = current_task().unwrap();
+    if !task.cred.lock().is_privileged() {
+        return EPERM;
+    }
+    let file_descriptor = task.fs.lock().working_inode.as_ref().clone();
+    let read_only = mountflags.contains(MountFlags::MS_RDONLY);
+    let result = if mountflags.contains(MountFlags::MS_REMOUNT) {
+        file_descriptor.remount(&target, read_only)
+    } else {
+        file_descriptor.mount(&source, &target, &filesystemtype, read_only)
+    };
+    match result {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
 }
 
 bitflags! {
@@ -1243,7 +1516,7 @@ pub fn sys_utimensat(
         Err(errno) => return errno,
     };
 
-    let now = TimeSpec::now();
+    let now = TimeSpec::now_realtime();
     let timespec = &mut [now; 2];
     let mut atime = Some(now.tv_sec);
     let mut mtime = Some(now.tv_sec);
@@ -1372,6 +1645,15 @@ pub fn sys_fcntl(fd: usize, cmd: u32, arg: usize) -> isize {
             }
             res
         }
+        Fcntl_Command::SETFL => {
+            let file_descriptor = match fd_table.get_refmut(fd) {
+                Ok(file_descriptor) => file_descriptor,
+                Err(errno) => return errno,
+            };
+            // Only O_NONBLOCK can be changed, access mode and creation flags are ignored
+            file_descriptor.set_nonblock((arg & OpenFlags::O_NONBLOCK.bits() as usize) != 0);
+            SUCCESS
+        }
         command => {
             warn!("[fcntl] Unsupported command: {:?}", command);
             SUCCESS
@@ -1498,9 +1780,101 @@ pub fn sys_faccessat2(dirfd: usize, pathname: *const u8, mode: u32, flags: u32)
         dirfd as isize, pathname, mode, flags
     );
 
-    // Do not check user's authority, because user group is not implemented yet.
-    // All existing files can be accessed.
-    match __openat(dirfd, pathname.as_str()) {
+    let file_descriptor = match __openat(dirfd, pathname.as_str()) {
+        Ok(file_descriptor) => file_descriptor,
+        Err(errno) => return errno,
+    };
+    // the real IDs are checked unless AT_EACCESS,
+    // so that a set-user-ID program can tell what its caller may do
+    let mut cred = current_task().unwrap().cred.lock().clone();
+    if !flags.contains(FaccessatFlags::AT_EACCESS) {
+        cred.euid = cred.uid;
+        cred.egid = cred.gid;
+    }
+    match file_descriptor.check_access(&cred, mode.bits()) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_fchmod(fd: usize, mode: u32) -> isize {
+    info!("[sys_fchmod] fd: {}, mode: {:o}", fd, mode);
+    let task = current_task().unwrap();
+    let fd_table = task.files.lock();
+    let file_descriptor = match fd_table.get_ref(fd) {
+        Ok(file_descriptor) => file_descriptor,
+        Err(errno) => return errno,
+    };
+    match file_descriptor.chmod(mode) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_fchmodat(dirfd: usize, pathname: *const u8, mode: u32, flags: u32) -> isize {
+    let token = current_user_token();
+    let pathname = match translated_str(token, pathname) {
+        Ok(path) => path,
+        Err(errno) => return errno,
+    };
+    info!(
+        "[sys_fchmodat] dirfd: {}, pathname: {}, mode: {:o}, flags: {:#x}",
+        dirfd as isize, pathname, mode, flags
+    );
+    // symbolic links are not followed by path lookup, so AT_SYMLINK_NOFOLLOW changes nothing
+    let file_descriptor = match __openat(dirfd, pathname.as_str()) {
+        Ok(file_descriptor) => file_descriptor,
+        Err(errno) => return errno,
+    };
+    match file_descriptor.chmod(mode) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+/// `owner` or `group` being -1 leaves it unchanged
+pub fn sys_fchown(fd: usize, owner: u32, group: u32) -> isize {
+    info!(
+        "[sys_fchown] fd: {}, owner: {}, group: {}",
+        fd, owner, group
+    );
+    let task = current_task().unwrap();
+    let file_descriptor = match task.files.lock().get_ref(fd) {
+        Ok(file_descriptor) => file_descriptor.clone(),
+        Err(errno) => return errno,
+    };
+    match file_descriptor.chown(owner, group) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_fchownat(
+    dirfd: usize,
+    pathname: *const u8,
+    owner: u32,
+    group: u32,
+    flags: u32,
+) -> isize {
+    const AT_EMPTY_PATH: u32 = 0x1000;
+    let token = current_user_token();
+    let pathname = match translated_str(token, pathname) {
+        Ok(path) => path,
+        Err(errno) => return errno,
+    };
+    info!(
+        "[sys_fchownat] dirfd: {}, pathname: {}, owner: {}, group: {}, flags: {:#x}",
+        dirfd as isize, pathname, owner, group, flags
+    );
+    if pathname.is_empty() && flags & AT_EMPTY_PATH == 0 {
+        return ENOENT;
+    }
+    // symbolic links are not followed by path lookup, so AT_SYMLINK_NOFOLLOW changes nothing
+    let file_descriptor = match __openat(dirfd, pathname.as_str()) {
+        Ok(file_descriptor) => file_descriptor,
+        Err(errno) => return errno,
+    };
+    match file_descriptor.chown(owner, group) {
         Ok(_) => SUCCESS,
         Err(errno) => errno,
     }
@@ -1522,18 +1896,20 @@ pub fn sys_msync(addr: usize, length: usize, flags: u32) -> isize {
         Some(flags) => flags,
         None => return EINVAL,
     };
+    if flags.contains(MsyncFlags::MS_ASYNC | MsyncFlags::MS_SYNC) {
+        return EINVAL;
+    }
     let task = current_task().unwrap();
-    if !task
-        .vm
-        .lock()
-        .contains_valid_buffer(addr, length, MapPermission::empty())
-    {
+    let mut vm = task.vm.lock();
+    if !vm.contains_valid_buffer(addr, length, MapPermission::empty()) {
         return ENOMEM;
     }
     info!(
         "[sys_msync] addr: {:X}, length: {:X}, flags: {:?}",
-        addr, flags, flags
+        addr, length, flags
     );
+    // MS_ASYNC is done synchronously, and the page cache is the only copy to invalidate
+    vm.msync(addr, length);
     SUCCESS
 }
 
@@ -14,23 +14,36 @@ use socket::*;
 pub fn syscall_name(id: usize) -> &'static str {
     match id {
         SYSCALL_DUP => "dup",
-        SYSCALL_DUP2 => "dup2",
         SYSCALL_DUP3 => "dup3",
         SYSCALL_OPEN => "open",
         SYSCALL_GET_TIME => "get_time",
         SYSCALL_GETCWD => "getcwd",
         SYSCALL_FCNTL => "fcntl",
         SYSCALL_IOCTL => "ioctl",
+        SYSCALL_MKNODAT => "mknodat",
         SYSCALL_MKDIRAT => "mkdirat",
         SYSCALL_UNLINKAT => "unlinkat",
+        SYSCALL_SYMLINKAT => "symlinkat",
         SYSCALL_LINKAT => "linkat",
         SYSCALL_UMOUNT2 => "umount2",
         SYSCALL_MOUNT => "mount",
         SYSCALL_FACCESSAT => "faccessat",
         SYSCALL_CHDIR => "chdir",
+        SYSCALL_FCHMOD => "fchmod",
+        SYSCALL_FCHMODAT => "fchmodat",
+        SYSCALL_FCHOWNAT => "fchownat",
+        SYSCALL_FCHOWN => "fchown",
         SYSCALL_OPENAT => "openat",
         SYSCALL_CLOSE => "close",
         SYSCALL_PIPE2 => "pipe2",
+        SYSCALL_EVENTFD2 => "eventfd2",
+        SYSCALL_EPOLL_CREATE1 => "epoll_create1",
+        SYSCALL_EPOLL_CTL => "epoll_ctl",
+        SYSCALL_EPOLL_PWAIT => "epoll_pwait",
+        SYSCALL_SIGNALFD4 => "signalfd4",
+        SYSCALL_TIMERFD_CREATE => "timerfd_create",
+        SYSCALL_TIMERFD_SETTIME => "timerfd_settime",
+        SYSCALL_TIMERFD_GETTIME => "timerfd_gettime",
         SYSCALL_GETDENTS64 => "getdents64",
         SYSCALL_LSEEK => "lseek",
         SYSCALL_READ => "read",
@@ -60,31 +73,67 @@ pub fn syscall_name(id: usize) -> &'static str {
         SYSCALL_NANOSLEEP => "nanosleep",
         SYSCALL_GETITIMER => "getitimer",
         SYSCALL_SETITIMER => "setitimer",
+        SYSCALL_TIMER_CREATE => "timer_create",
+        SYSCALL_TIMER_GETTIME => "timer_gettime",
+        SYSCALL_TIMER_GETOVERRUN => "timer_getoverrun",
+        SYSCALL_TIMER_SETTIME => "timer_settime",
+        SYSCALL_TIMER_DELETE => "timer_delete",
+        SYSCALL_CLOCK_SETTIME => "clock_settime",
         SYSCALL_CLOCK_GETTIME => "clock_gettime",
+        SYSCALL_CLOCK_GETRES => "clock_getres",
+        SYSCALL_CLOCK_NANOSLEEP => "clock_nanosleep",
         SYSCALL_SYSLOG => "syslog",
+        SYSCALL_SCHED_SETPARAM => "sched_setparam",
+        SYSCALL_SCHED_SETSCHEDULER => "sched_setscheduler",
+        SYSCALL_SCHED_GETSCHEDULER => "sched_getscheduler",
+        SYSCALL_SCHED_GETPARAM => "sched_getparam",
         SYSCALL_YIELD => "yield",
+        SYSCALL_SCHED_GET_PRIORITY_MAX => "sched_get_priority_max",
+        SYSCALL_SCHED_GET_PRIORITY_MIN => "sched_get_priority_min",
+        SYSCALL_SCHED_RR_GET_INTERVAL => "sched_rr_get_interval",
+        SYSCALL_SETPRIORITY => "setpriority",
+        SYSCALL_GETPRIORITY => "getpriority",
         SYSCALL_KILL => "kill",
         SYSCALL_TKILL => "tkill",
+        SYSCALL_TGKILL => "tgkill",
+        SYSCALL_SIGALTSTACK => "sigaltstack",
+        SYSCALL_SIGSUSPEND => "sigsuspend",
         SYSCALL_SIGACTION => "sigaction",
         SYSCALL_SIGPROCMASK => "sigprocmask",
+        SYSCALL_SIGPENDING => "sigpending",
         SYSCALL_SIGTIMEDWAIT => "sigtimedwait",
+        SYSCALL_SIGQUEUEINFO => "sigqueueinfo",
         SYSCALL_SIGRETURN => "sigreturn",
         SYSCALL_TIMES => "times",
         SYSCALL_SETPGID => "setpgid",
         SYSCALL_GETPGID => "getpgid",
+        SYSCALL_GETSID => "getsid",
+        SYSCALL_SETSID => "setsid",
         SYSCALL_UNAME => "uname",
         SYSCALL_GETRUSAGE => "getrusage",
         SYSCALL_UMASK => "umask",
         SYSCALL_GET_TIME_OF_DAY => "get_time_of_day",
+        SYSCALL_SETTIMEOFDAY => "settimeofday",
         SYSCALL_GETPID => "getpid",
         SYSCALL_GETPPID => "getppid",
         SYSCALL_GETUID => "getuid",
         SYSCALL_GETEUID => "geteuid",
         SYSCALL_GETGID => "getgid",
         SYSCALL_GETEGID => "getegid",
+        SYSCALL_SETREGID => "setregid",
+        SYSCALL_SETGID => "setgid",
+        SYSCALL_SETREUID => "setreuid",
+        SYSCALL_SETUID => "setuid",
+        SYSCALL_SETRESUID => "setresuid",
+        SYSCALL_GETRESUID => "getresuid",
+        SYSCALL_SETRESGID => "setresgid",
+        SYSCALL_GETRESGID => "getresgid",
+        SYSCALL_GETGROUPS => "getgroups",
+        SYSCALL_SETGROUPS => "setgroups",
         SYSCALL_GETTID => "gettid",
         SYSCALL_SYSINFO => "sysinfo",
         SYSCALL_SOCKET => "socket",
+        SYSCALL_SOCKETPAIR => "socketpair",
         SYSCALL_BIND => "bind",
         SYSCALL_LISTEN => "listen",
         SYSCALL_ACCEPT => "accept",
@@ -94,6 +143,10 @@ pub fn syscall_name(id: usize) -> &'static str {
         SYSCALL_SENDTO => "sendto",
         SYSCALL_RECVFROM => "recvfrom",
         SYSCALL_SETSOCKOPT => "setsockopt",
+        SYSCALL_GETSOCKOPT => "getsockopt",
+        SYSCALL_SOCKET_SHUTDOWN => "shutdown",
+        SYSCALL_SENDMSG => "sendmsg",
+        SYSCALL_RECVMSG => "recvmsg",
         SYSCALL_SBRK => "sbrk",
         SYSCALL_BRK => "brk",
         SYSCALL_MUNMAP => "munmap",
@@ -102,6 +155,8 @@ pub fn syscall_name(id: usize) -> &'static str {
         SYSCALL_MMAP => "mmap",
         SYSCALL_MPROTECT => "mprotect",
         SYSCALL_MSYNC => "msync",
+        SYSCALL_TGSIGQUEUEINFO => "tgsigqueueinfo",
+        SYSCALL_ACCEPT4 => "accept4",
         SYSCALL_WAIT4 => "wait4",
         SYSCALL_PRLIMIT => "prlimit",
         SYSCALL_RENAMEAT2 => "renameat2",
@@ -120,8 +175,8 @@ pub fn syscall_name(id: usize) -> &'static str {
 use crate::{
     fs::poll::FdSet,
     syscall::errno::Errno,
-    task::Rusage,
-    timer::{ITimerVal, TimeSpec, Times},
+    task::{posix_timer::SigEvent, rlimit::RLimit, Rusage},
+    timer::{ITimerSpec, ITimerVal, TimeSpec, Times},
 };
 
 pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
@@ -160,12 +215,14 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
     let ret = match syscall_id {
         SYSCALL_GETCWD => sys_getcwd(args[0], args[1]),
         SYSCALL_DUP => sys_dup(args[0]),
-        SYSCALL_DUP2 => sys_dup2(args[0], args[1]),
         SYSCALL_DUP3 => sys_dup3(args[0], args[1], args[2] as u32),
         SYSCALL_FCNTL => sys_fcntl(args[0], args[1] as u32, args[2]),
         SYSCALL_IOCTL => sys_ioctl(args[0], args[1] as u32, args[2]),
+        SYSCALL_MKNODAT => sys_mknodat(args[0], args[1] as *const u8, args[2] as u32, args[3]),
         SYSCALL_MKDIRAT => sys_mkdirat(args[0], args[1] as *const u8, args[2] as u32),
         SYSCALL_UNLINKAT => sys_unlinkat(args[0], args[1] as *const u8, args[2] as u32),
+        SYSCALL_SYMLINKAT => sys_symlinkat(args[0] as *const u8, args[1], args[2] as *const u8),
+        SYSCALL_LINKAT => sys_linkat(args[0], args[1] as *const u8, args[2], args[3] as *const u8),
         SYSCALL_UMOUNT2 => sys_umount2(args[0] as *const u8, args[1] as u32),
         SYSCALL_MOUNT => sys_mount(
             args[0] as *const u8,
@@ -176,6 +233,21 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
         ),
         SYSCALL_FACCESSAT => sys_faccessat2(args[0], args[1] as *const u8, args[2] as u32, 0u32),
         SYSCALL_CHDIR => sys_chdir(args[0] as *const u8),
+        SYSCALL_FCHMOD => sys_fchmod(args[0], args[1] as u32),
+        SYSCALL_FCHMODAT => sys_fchmodat(
+            args[0],
+            args[1] as *const u8,
+            args[2] as u32,
+            args[3] as u32,
+        ),
+        SYSCALL_FCHOWNAT => sys_fchownat(
+            args[0],
+            args[1] as *const u8,
+            args[2] as u32,
+            args[3] as u32,
+            args[4] as u32,
+        ),
+        SYSCALL_FCHOWN => sys_fchown(args[0], args[1] as u32, args[2] as u32),
         SYSCALL_OPEN => sys_openat(AT_FDCWD, args[0] as *const u8, args[1] as u32, 0o777u32),
         SYSCALL_OPENAT => sys_openat(
             args[0],
@@ -186,6 +258,26 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
         SYSCALL_SPLICE => sys_splice(args[0], args[1] as *mut usize, args[2],args[3] as *mut usize, args[4] as usize,args[5]),
         SYSCALL_CLOSE => sys_close(args[0]),
         SYSCALL_PIPE2 => sys_pipe2(args[0], args[1] as u32),
+        SYSCALL_EVENTFD2 => sys_eventfd2(args[0] as u32, args[1] as u32),
+        SYSCALL_EPOLL_CREATE1 => sys_epoll_create1(args[0] as u32),
+        SYSCALL_EPOLL_CTL => sys_epoll_ctl(args[0], args[1] as u32, args[2], args[3]),
+        SYSCALL_EPOLL_PWAIT => {
+            sys_epoll_pwait(args[0], args[1], args[2], args[3] as i32, args[4], args[5])
+        }
+        SYSCALL_SIGNALFD4 => sys_signalfd4(
+            args[0],
+            args[1] as *const crate::task::Signals,
+            args[2],
+            args[3] as u32,
+        ),
+        SYSCALL_TIMERFD_CREATE => sys_timerfd_create(args[0], args[1] as u32),
+        SYSCALL_TIMERFD_SETTIME => sys_timerfd_settime(
+            args[0],
+            args[1] as u32,
+            args[2] as *const ITimerSpec,
+            args[3] as *mut ITimerSpec,
+        ),
+        SYSCALL_TIMERFD_GETTIME => sys_timerfd_gettime(args[0], args[1] as *mut ITimerSpec),
         SYSCALL_GETDENTS64 => sys_getdents64(args[0], args[1] as *mut u8, args[2]),
         SYSCALL_READ => sys_read(args[0], args[1], args[2]),
         SYSCALL_READV => sys_readv(args[0], args[1], args[2]),
@@ -217,14 +309,52 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
         SYSCALL_SHUTDOWN => sys_shutdown(),
         SYSCALL_EXIT => sys_exit(args[0] as u32),
         SYSCALL_EXIT_GROUP => sys_exit_group(args[0] as u32),
+        SYSCALL_TIMER_CREATE => {
+            sys_timer_create(args[0], args[1] as *const SigEvent, args[2] as *mut i32)
+        }
+        SYSCALL_TIMER_GETTIME => sys_timer_gettime(args[0], args[1] as *mut ITimerSpec),
+        SYSCALL_TIMER_GETOVERRUN => sys_timer_getoverrun(args[0]),
+        SYSCALL_TIMER_SETTIME => sys_timer_settime(
+            args[0],
+            args[1] as u32,
+            args[2] as *const ITimerSpec,
+            args[3] as *mut ITimerSpec,
+        ),
+        SYSCALL_TIMER_DELETE => sys_timer_delete(args[0]),
+        SYSCALL_CLOCK_SETTIME => sys_clock_settime(args[0], args[1] as *const TimeSpec),
         SYSCALL_CLOCK_GETTIME => sys_clock_gettime(args[0], args[1] as *mut TimeSpec),
+        SYSCALL_CLOCK_GETRES => sys_clock_getres(args[0], args[1] as *mut TimeSpec),
+        SYSCALL_CLOCK_NANOSLEEP => sys_clock_nanosleep(
+            args[0],
+            args[1] as u32,
+            args[2] as *const TimeSpec,
+            args[3] as *mut TimeSpec,
+        ),
         SYSCALL_KILL => sys_kill(args[0], args[1]),
         SYSCALL_TKILL => sys_tkill(args[0], args[1]),
+        SYSCALL_TGKILL => sys_tgkill(args[0], args[1], args[2]),
+        SYSCALL_SIGALTSTACK => sys_sigaltstack(args[0], args[1]),
+        SYSCALL_SIGSUSPEND => sys_sigsuspend(args[0]),
         SYSCALL_SYSLOG => sys_syslog(args[0] as u32, args[1] as *mut u8, args[2] as u32),
+        SYSCALL_SCHED_SETPARAM => sys_sched_setparam(args[0], args[1] as *const SchedParam),
+        SYSCALL_SCHED_SETSCHEDULER => {
+            sys_sched_setscheduler(args[0], args[1] as u32, args[2] as *const SchedParam)
+        }
+        SYSCALL_SCHED_GETSCHEDULER => sys_sched_getscheduler(args[0]),
+        SYSCALL_SCHED_GETPARAM => sys_sched_getparam(args[0], args[1] as *mut SchedParam),
         SYSCALL_YIELD => sys_yield(),
+        SYSCALL_SCHED_GET_PRIORITY_MAX => sys_sched_get_priority_max(args[0] as u32),
+        SYSCALL_SCHED_GET_PRIORITY_MIN => sys_sched_get_priority_min(args[0] as u32),
+        SYSCALL_SCHED_RR_GET_INTERVAL => {
+            sys_sched_rr_get_interval(args[0], args[1] as *mut TimeSpec)
+        }
+        SYSCALL_SETPRIORITY => sys_setpriority(args[0] as u32, args[1], args[2] as i32),
+        SYSCALL_GETPRIORITY => sys_getpriority(args[0] as u32, args[1]),
         SYSCALL_SIGACTION => sys_sigaction(args[0], args[1], args[2]),
         SYSCALL_SIGPROCMASK => sys_sigprocmask(args[0] as u32, args[1], args[2]),
+        SYSCALL_SIGPENDING => sys_sigpending(args[0]),
         SYSCALL_SIGTIMEDWAIT => sys_sigtimedwait(args[0], args[1], args[2]),
+        SYSCALL_SIGQUEUEINFO => sys_sigqueueinfo(args[0], args[1], args[2]),
         SYSCALL_SIGRETURN => sys_sigreturn(),
         SYSCALL_TIMES => sys_times(args[0] as *mut Times),
 
@@ -244,8 +374,14 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
             args[0] as *mut crate::timer::TimeVal,
             args[1] as *mut crate::timer::TimeZone,
         ),
+        SYSCALL_SETTIMEOFDAY => sys_settimeofday(
+            args[0] as *const crate::timer::TimeVal,
+            args[1] as *const crate::timer::TimeZone,
+        ),
         SYSCALL_SETPGID => sys_setpgid(args[0], args[1]),
         SYSCALL_GETPGID => sys_getpgid(args[0]),
+        SYSCALL_GETSID => sys_getsid(args[0]),
+        SYSCALL_SETSID => sys_setsid(),
         SYSCALL_UNAME => sys_uname(args[0] as *mut u8),
         SYSCALL_GETPID => sys_getpid(),
         SYSCALL_GETPPID => sys_getppid(),
@@ -291,6 +427,24 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
         SYSCALL_GETEUID => sys_geteuid(),
         SYSCALL_GETGID => sys_getgid(),
         SYSCALL_GETEGID => sys_getegid(),
+        SYSCALL_SETREGID => sys_setregid(args[0] as u32, args[1] as u32),
+        SYSCALL_SETGID => sys_setgid(args[0] as u32),
+        SYSCALL_SETREUID => sys_setreuid(args[0] as u32, args[1] as u32),
+        SYSCALL_SETUID => sys_setuid(args[0] as u32),
+        SYSCALL_SETRESUID => sys_setresuid(args[0] as u32, args[1] as u32, args[2] as u32),
+        SYSCALL_GETRESUID => sys_getresuid(
+            args[0] as *mut u32,
+            args[1] as *mut u32,
+            args[2] as *mut u32,
+        ),
+        SYSCALL_SETRESGID => sys_setresgid(args[0] as u32, args[1] as u32, args[2] as u32),
+        SYSCALL_GETRESGID => sys_getresgid(
+            args[0] as *mut u32,
+            args[1] as *mut u32,
+            args[2] as *mut u32,
+        ),
+        SYSCALL_GETGROUPS => sys_getgroups(args[0], args[1] as *mut u32),
+        SYSCALL_SETGROUPS => sys_setgroups(args[0], args[1] as *const u32),
         SYSCALL_GETTID => sys_gettid(),
         SYSCALL_SYSINFO => sys_sysinfo(args[0] as *mut Sysinfo),
         SYSCALL_SBRK => sys_sbrk(args[0] as isize),
@@ -322,14 +476,27 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
             args[4] as u32,
         ),
         SYSCALL_MSYNC => sys_msync(args[0], args[1], args[2] as u32),
+        SYSCALL_TGSIGQUEUEINFO => sys_tgsigqueueinfo(args[0], args[1], args[2], args[3]),
         SYSCALL_STATFS => sys_statfs(args[0] as *const u8, args[1] as *mut Statfs),
         SYSCALL_SOCKET => sys_socket(args[0] as u32, args[1] as u32, args[2] as u32),
+        SYSCALL_SOCKETPAIR => sys_socketpair(
+            args[0] as u32,
+            args[1] as u32,
+            args[2] as u32,
+            args[3] as *mut [i32; 2],
+        ),
         SYSCALL_BIND => sys_bind(args[0], args[1] as *const u8, args[2] as u32),
         SYSCALL_LISTEN => sys_listen(args[0], args[1] as u32),
-        SYSCALL_ACCEPT => sys_accept(args[0], args[1] as *const u8, args[2] as u32),
+        SYSCALL_ACCEPT => sys_accept(args[0], args[1] as *mut u8, args[2] as *mut u32),
+        SYSCALL_ACCEPT4 => sys_accept4(
+            args[0],
+            args[1] as *mut u8,
+            args[2] as *mut u32,
+            args[3] as u32,
+        ),
         SYSCALL_CONNECT => sys_connect(args[0], args[1] as *const u8, args[2] as u32),
-        SYSCALL_GETSOCKNAME => sys_getsockname(args[0], args[1] as *const u8, args[2] as u32),
-        SYSCALL_GETPEERNAME => sys_getpeername(args[0], args[1] as *const u8, args[2] as u32),
+        SYSCALL_GETSOCKNAME => sys_getsockname(args[0], args[1] as *mut u8, args[2] as *mut u32),
+        SYSCALL_GETPEERNAME => sys_getpeername(args[0], args[1] as *mut u8, args[2] as *mut u32),
         SYSCALL_SENDTO => sys_sendto(
             args[0],
             args[1] as *const u8,
@@ -343,8 +510,8 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
             args[1] as *mut u8,
             args[2],
             args[3] as u32,
-            args[4] as *const u8,
-            args[5] as u32,
+            args[4] as *mut u8,
+            args[5] as *mut u32,
         ),
         SYSCALL_SETSOCKOPT => sys_setsockopt(
             args[0],
@@ -353,6 +520,16 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
             args[3] as *const u8,
             args[4] as u32,
         ),
+        SYSCALL_GETSOCKOPT => sys_getsockopt(
+            args[0],
+            args[1] as u32,
+            args[2] as u32,
+            args[3] as *mut u8,
+            args[4] as *mut u32,
+        ),
+        SYSCALL_SOCKET_SHUTDOWN => sys_socket_shutdown(args[0], args[1] as u32),
+        SYSCALL_SENDMSG => sys_sendmsg(args[0], args[1] as *const MsgHdr, args[2] as u32),
+        SYSCALL_RECVMSG => sys_recvmsg(args[0], args[1] as *mut MsgHdr, args[2] as u32),
         SYSCALL_MYCALL => sys_mycall(),
         SYSCALL_PRINT_TCB => sys_printtcb(args[0] as *mut usize),  
         _ => {
@@ -1,25 +1,40 @@
-use crate::arch::{MachineContext, TrapContext};
-use crate::config::{PAGE_SIZE, SYSTEM_TASK_LIMIT, USER_STACK_SIZE};
-use crate::fs::OpenFlags;
+use crate::arch::TICKS_PER_SEC;
+use crate::config::PAGE_SIZE;
 use crate::mm::{
-    copy_from_user, copy_to_user, copy_to_user_string, get_from_user, translated_byte_buffer,
-    translated_ref, translated_refmut, translated_str, try_get_from_user, MapFlags, MapPermission,
-    UserBuffer,
+    copy_from_user, copy_from_user_array, copy_to_user, copy_to_user_array, copy_to_user_string,
+    get_from_user, translated_byte_buffer, translated_ref, translated_refmut, translated_str,
+    try_get_from_user, MapFlags, MapPermission, UserBuffer,
 };
+use crate::random::{get_random_bytes, wait_for_random_bytes};
 use crate::show_frame_consumption;
 use crate::syscall::errno::*;
-use crate::task::threads::{do_futex_wait, FutexCmd};
+use crate::task::threads::{
+    futex_lock_pi, futex_requeue, futex_unlock_pi, futex_wait, futex_wake, futex_wake_op, FutexCmd,
+    FutexKey, FUTEX_BITSET_MATCH_ANY,
+};
+use crate::task::posix_timer::{
+    timer_limit, watch_clock_timers, ClockId, SigEvent, CLOCK_REALTIME, TIMER_ABSTIME,
+};
+use crate::task::binfmt::{exec_binprm, LinuxBinprm};
+use crate::task::cred::NGROUPS_MAX;
+use crate::task::rlimit::{RLimit, Resource};
+use crate::task::sched::{
+    SchedPolicy, MAX_NICE, MAX_RT_PRIO, MIN_NICE, MIN_RT_PRIO, RR_TIMESLICE_TICKS,
+};
 use crate::task::{
-    add_task, block_current_and_run_next, current_task, current_user_token,
+    add_task, all_tasks, block_current_and_run_next, current_task, current_user_token,
     exit_current_and_run_next, exit_group_and_run_next, find_task_by_pid, find_task_by_tgid,
-    procs_count, signal::*, suspend_current_and_run_next, threads, wait_with_timeout,
-    wake_interruptible, Rusage, TaskStatus,
+    procs_count, requeue_task, signal::*, suspend_current_and_run_next, threads, wait_with_timeout,
+    Rusage, TaskControlBlock, INITPROC,
+};
+use crate::timer::{
+    get_time_ms, get_time_sec, set_realtime_ns, ITimerSpec, ITimerVal, TimeSpec, TimeVal, TimeZone,
+    Times, NSEC_PER_SEC, NSEC_PER_USEC, USEC_PER_SEC,
 };
-use crate::timer::{get_time_ms, get_time_sec, ITimerVal, TimeSpec, TimeVal, TimeZone, Times};
-use alloc::boxed::Box;
 use alloc::string::{String, ToString};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::convert::TryFrom;
 use core::mem::size_of;
 use log::{debug, error, info, trace, warn};
 use num_enum::FromPrimitive;
@@ -82,15 +97,254 @@ pub fn sys_syslog(type_: u32, buf: *mut u8, len: u32) -> isize {
 }
 
 pub fn sys_yield() -> isize {
+    current_task().unwrap().sched.lock().set_yielded();
     suspend_current_and_run_next();
     SUCCESS
 }
 
-pub fn sys_kill(pid: usize, sig: usize) -> isize {
-    let signal = match Signals::from_signum(sig) {
-        Ok(signal) => signal,
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct SchedParam {
+    sched_priority: i32,
+}
+
+const SCHED_RESET_ON_FORK: u32 = 0x4000_0000;
+
+/// `pid` here is a thread id, 0 means the calling thread.
+fn find_sched_target(pid: usize) -> Option<Arc<TaskControlBlock>> {
+    if pid == 0 {
+        current_task()
+    } else {
+        find_task_by_pid(pid)
+    }
+}
+
+/// Change policy (kept if `None`) and `sched_priority` of `task`.
+/// Others than root may only change the tasks they own, and may only take a real-time policy
+/// or raise its priority up to `RLIMIT_RTPRIO` of the target.
+fn set_scheduler(
+    task: &Arc<TaskControlBlock>,
+    policy: Option<SchedPolicy>,
+    param: &SchedParam,
+) -> isize {
+    let cred = current_task().unwrap().cred.lock().clone();
+    if !cred.may_reschedule(&task.cred.lock()) {
+        return EPERM;
+    }
+    let rtprio = task.rlimit.lock().get(Resource::RTPRIO);
+    let mut sched = task.sched.lock();
+    let (old_policy, old_prio) = sched.own_policy();
+    let policy = policy.unwrap_or(old_policy);
+    let prio = param.sched_priority;
+    let valid = if policy.is_realtime() {
+        (MIN_RT_PRIO as i32..=MAX_RT_PRIO as i32).contains(&prio)
+    } else {
+        prio == 0
+    };
+    if !valid {
+        return EINVAL;
+    }
+    if policy.is_realtime() && !cred.is_privileged() {
+        if policy != old_policy && rtprio.rlim_cur == 0 {
+            return EPERM;
+        }
+        if prio as u32 > old_prio && rtprio.exceeded_by(prio as usize) {
+            return EPERM;
+        }
+    }
+    sched.set_policy(policy, prio as u32);
+    drop(sched);
+    // it may sit in the queue of another class
+    requeue_task(task);
+    SUCCESS
+}
+
+fn copy_sched_param(param: *const SchedParam) -> Result<SchedParam, isize> {
+    if param.is_null() {
+        return Err(EINVAL);
+    }
+    let mut kparam = SchedParam { sched_priority: 0 };
+    match copy_from_user(current_user_token(), param, &mut kparam) {
+        Ok(_) => Ok(kparam),
+        Err(_) => Err(EFAULT),
+    }
+}
+
+pub fn sys_sched_setscheduler(pid: usize, policy: u32, param: *const SchedParam) -> isize {
+    if (pid as isize) < 0 {
+        return EINVAL;
+    }
+    let policy = match SchedPolicy::try_from(policy & !SCHED_RESET_ON_FORK) {
+        Ok(policy) => policy,
         Err(_) => return EINVAL,
     };
+    let param = match copy_sched_param(param) {
+        Ok(param) => param,
+        Err(errno) => return errno,
+    };
+    match find_sched_target(pid) {
+        Some(task) => set_scheduler(&task, Some(policy), &param),
+        None => ESRCH,
+    }
+}
+
+pub fn sys_sched_getscheduler(pid: usize) -> isize {
+    if (pid as isize) < 0 {
+        return EINVAL;
+    }
+    match find_sched_target(pid) {
+        Some(task) => u32::from(task.sched.lock().policy) as isize,
+        None => ESRCH,
+    }
+}
+
+pub fn sys_sched_setparam(pid: usize, param: *const SchedParam) -> isize {
+    if (pid as isize) < 0 {
+        return EINVAL;
+    }
+    let param = match copy_sched_param(param) {
+        Ok(param) => param,
+        Err(errno) => return errno,
+    };
+    match find_sched_target(pid) {
+        Some(task) => set_scheduler(&task, None, &param),
+        None => ESRCH,
+    }
+}
+
+pub fn sys_sched_getparam(pid: usize, param: *mut SchedParam) -> isize {
+    if (pid as isize) < 0 || param.is_null() {
+        return EINVAL;
+    }
+    let task = match find_sched_target(pid) {
+        Some(task) => task,
+        None => return ESRCH,
+    };
+    let kparam = SchedParam {
+        sched_priority: task.sched.lock().rt_priority as i32,
+    };
+    if copy_to_user(current_user_token(), &kparam, param).is_err() {
+        return EFAULT;
+    }
+    SUCCESS
+}
+
+pub fn sys_sched_get_priority_max(policy: u32) -> isize {
+    match SchedPolicy::try_from(policy) {
+        Ok(policy) if policy.is_realtime() => MAX_RT_PRIO as isize,
+        Ok(_) => 0,
+        Err(_) => EINVAL,
+    }
+}
+
+pub fn sys_sched_get_priority_min(policy: u32) -> isize {
+    match SchedPolicy::try_from(policy) {
+        Ok(policy) if policy.is_realtime() => MIN_RT_PRIO as isize,
+        Ok(_) => 0,
+        Err(_) => EINVAL,
+    }
+}
+
+pub fn sys_sched_rr_get_interval(pid: usize, interval: *mut TimeSpec) -> isize {
+    if (pid as isize) < 0 {
+        return EINVAL;
+    }
+    let task = match find_sched_target(pid) {
+        Some(task) => task,
+        None => return ESRCH,
+    };
+    let timeslice = match task.sched.lock().policy {
+        SchedPolicy::Fifo => TimeSpec::new(),
+        _ => TimeSpec::from_ms(RR_TIMESLICE_TICKS * 1000 / TICKS_PER_SEC),
+    };
+    if copy_to_user(current_user_token(), &timeslice, interval).is_err() {
+        return EFAULT;
+    }
+    SUCCESS
+}
+
+const PRIO_PROCESS: u32 = 0;
+const PRIO_PGRP: u32 = 1;
+const PRIO_USER: u32 = 2;
+
+/// Tasks selected by `which` and `who` of `setpriority()`/`getpriority()`,
+/// `PRIO_USER` selects those of the real user ID `who`.
+fn priority_targets(which: u32, who: usize) -> Result<Vec<Arc<TaskControlBlock>>, isize> {
+    match which {
+        PRIO_PROCESS => Ok(find_sched_target(who).into_iter().collect()),
+        PRIO_PGRP => {
+            let pgid = if who == 0 {
+                current_task().unwrap().getpgid()
+            } else {
+                who
+            };
+            Ok(all_tasks()
+                .into_iter()
+                .filter(|task| task.getpgid() == pgid)
+                .collect())
+        }
+        PRIO_USER => {
+            let uid = if who == 0 {
+                current_task().unwrap().cred.lock().uid
+            } else {
+                who as u32
+            };
+            Ok(all_tasks()
+                .into_iter()
+                .filter(|task| task.cred.lock().uid == uid)
+                .collect())
+        }
+        _ => Err(EINVAL),
+    }
+}
+
+/// Like Linux, the targets the caller may change are changed even if some fail,
+/// and the error of the last failure is returned.
+/// Others than root may only change the tasks they own,
+/// and may only lower `nice` down to `20 - RLIMIT_NICE` of the target.
+pub fn sys_setpriority(which: u32, who: usize, prio: i32) -> isize {
+    let tasks = match priority_targets(which, who) {
+        Ok(tasks) => tasks,
+        Err(errno) => return errno,
+    };
+    if tasks.is_empty() {
+        return ESRCH;
+    }
+    let cred = current_task().unwrap().cred.lock().clone();
+    let nice = prio.max(MIN_NICE).min(MAX_NICE);
+    let mut result = SUCCESS;
+    for task in tasks {
+        if !cred.may_reschedule(&task.cred.lock()) {
+            result = EPERM;
+            continue;
+        }
+        let limit = task.rlimit.lock().get(Resource::NICE);
+        let mut sched = task.sched.lock();
+        if nice < sched.nice && !cred.is_privileged() && limit.exceeded_by((20 - nice) as usize) {
+            result = EACCES;
+            continue;
+        }
+        sched.set_nice(nice);
+    }
+    result
+}
+
+/// Returns `20 - nice` of the highest priority target, as the raw syscall does.
+pub fn sys_getpriority(which: u32, who: usize) -> isize {
+    let tasks = match priority_targets(which, who) {
+        Ok(tasks) => tasks,
+        Err(errno) => return errno,
+    };
+    match tasks.iter().map(|task| task.sched.lock().nice).min() {
+        Some(nice) => (20 - nice) as isize,
+        None => ESRCH,
+    }
+}
+
+pub fn sys_kill(pid: usize, sig: usize) -> isize {
+    if Signals::from_signum(sig).is_err() {
+        return EINVAL;
+    }
     #[cfg(feature = "comp")]
     if pid == 10 {
         return SUCCESS;
@@ -100,58 +354,128 @@ pub fn sys_kill(pid: usize, sig: usize) -> isize {
         // signal will be sent to an arbitrary task with target `pid` (`tgid` more precisely).
         // But manual also require that the target task should not mask this signal.
         if let Some(task) = find_task_by_tgid(pid) {
-            if !signal.is_empty() {
-                let mut inner = task.acquire_inner_lock();
-                inner.add_signal(signal);
-                // wake up target process if it is sleeping
-                if inner.task_status == TaskStatus::Interruptible {
-                    inner.task_status = TaskStatus::Ready;
-                    drop(inner);
-                    wake_interruptible(task);
-                }
+            let sender = current_task().unwrap();
+            let cred = sender.cred.lock().clone();
+            if !cred.may_signal(&task.cred.lock()) {
+                return EPERM;
+            }
+            let info = SigInfo::from_sender(sig, SigInfo::SI_USER, sender.tgid, cred.uid);
+            match send_signal(task, info) {
+                Ok(_) => SUCCESS,
+                Err(errno) => errno,
             }
-            SUCCESS
         } else {
             ESRCH
         }
-    } else if pid == 0 {
-        todo!()
-    } else if (pid as isize) == -1 {
-        todo!()
     } else {
-        // (pid as isize) < -1
-        todo!()
+        let task = current_task().unwrap();
+        let uid = task.cred.lock().uid;
+        let info = SigInfo::from_sender(sig, SigInfo::SI_USER, task.tgid, uid);
+        let result = match pid as isize {
+            0 => send_signal_to_pgrp(task.getpgid(), info),
+            // every process but init and ourselves
+            -1 => send_signal_to_processes(info, |target| {
+                target.tgid != INITPROC.tgid && target.tgid != task.tgid
+            }),
+            pgid => send_signal_to_pgrp(-pgid as usize, info),
+        };
+        match result {
+            Ok(_) => SUCCESS,
+            Err(errno) => errno,
+        }
     }
 }
 
 pub fn sys_tkill(tid: usize, sig: usize) -> isize {
-    let signal = match Signals::from_signum(sig) {
-        Ok(signal) => signal,
-        Err(_) => return EINVAL,
-    };
-    if tid > 0 {
-        if let Some(task) = find_task_by_pid(tid) {
-            if !signal.is_empty() {
-                let mut inner = task.acquire_inner_lock();
-                inner.add_signal(signal);
-                // wake up target process if it is sleeping
-                if inner.task_status == TaskStatus::Interruptible {
-                    inner.task_status = TaskStatus::Ready;
-                    drop(inner);
-                    wake_interruptible(task);
-                }
-            }
-            SUCCESS
-        } else {
-            ESRCH
+    if Signals::from_signum(sig).is_err() {
+        return EINVAL;
+    }
+    if (tid as isize) <= 0 {
+        return EINVAL;
+    }
+    if let Some(task) = find_task_by_pid(tid) {
+        let sender = current_task().unwrap();
+        let cred = sender.cred.lock().clone();
+        if !cred.may_signal(&task.cred.lock()) {
+            return EPERM;
+        }
+        let info = SigInfo::from_sender(sig, SigInfo::SI_TKILL, sender.tgid, cred.uid);
+        match send_signal(task, info) {
+            Ok(_) => SUCCESS,
+            Err(errno) => errno,
         }
-    } else if tid == 0 {
-        todo!()
-    } else if (tid as isize) == -1 {
-        todo!()
     } else {
-        // (pid as isize) < -1
-        todo!()
+        ESRCH
+    }
+}
+
+/// Like `tkill()`, but the thread must belong to thread group `tgid`
+pub fn sys_tgkill(tgid: usize, tid: usize, sig: usize) -> isize {
+    if (tgid as isize) <= 0 || (tid as isize) <= 0 || Signals::from_signum(sig).is_err() {
+        return EINVAL;
+    }
+    match find_task_by_pid(tid) {
+        Some(task) if task.tgid == tgid => sys_tkill(tid, sig),
+        _ => ESRCH,
+    }
+}
+
+/// Send a signal with `*uinfo` to thread group `tgid`, or to thread `tid` of it.
+/// Only the kernel may pretend to be `kill()` or `tkill()` when sending to another process.
+fn queue_siginfo(tgid: usize, tid: Option<usize>, sig: usize, uinfo: *const SigInfo) -> isize {
+    let task = current_task().unwrap();
+    let mut info = match get_from_user(task.get_user_token(), uinfo) {
+        Ok(info) => info,
+        Err(errno) => return errno,
+    };
+    info!(
+        "[queue_siginfo] tgid: {}, tid: {:?}, sig: {}, info: {:?}",
+        tgid, tid, sig, info
+    );
+    if Signals::from_signum(sig).is_err() {
+        return EINVAL;
+    }
+    if ((info.si_code as i32) >= 0 || info.si_code == SigInfo::SI_TKILL) && tgid != task.tgid {
+        return EPERM;
+    }
+    info.si_signo = sig as u32;
+    let target = match tid {
+        Some(tid) => find_task_by_pid(tid).filter(|target| target.tgid == tgid),
+        None => find_task_by_tgid(tgid),
+    };
+    match target {
+        Some(target) => match send_signal(target, info) {
+            Ok(_) => SUCCESS,
+            Err(errno) => errno,
+        },
+        None => ESRCH,
+    }
+}
+
+pub fn sys_sigqueueinfo(tgid: usize, sig: usize, uinfo: usize) -> isize {
+    queue_siginfo(tgid, None, sig, uinfo as *const SigInfo)
+}
+
+pub fn sys_tgsigqueueinfo(tgid: usize, tid: usize, sig: usize, uinfo: usize) -> isize {
+    queue_siginfo(tgid, Some(tid), sig, uinfo as *const SigInfo)
+}
+
+/// Sleep until `end` on the monotonic clock.
+/// # Return Conditions
+/// Returns the time left if a signal arrives first.
+fn sleep_until(end: TimeSpec) -> Result<(), TimeSpec> {
+    loop {
+        let now = TimeSpec::now();
+        if now >= end {
+            return Ok(());
+        }
+        let task = current_task().unwrap();
+        if task.signal_pending() {
+            return Err(end - now);
+        }
+        wait_with_timeout(Arc::downgrade(&task), end);
+        drop(task);
+        block_current_and_run_next();
     }
 }
 
@@ -159,34 +483,64 @@ pub fn sys_nanosleep(req: *const TimeSpec, rem: *mut TimeSpec) -> isize {
     if req.is_null() {
         return EINVAL;
     }
-    let task = current_task().unwrap();
-    let token = task.get_user_token();
+    let token = current_user_token();
     let req = match get_from_user(token, req) {
         Ok(req) => req,
         Err(errno) => return errno,
     };
+    if req.tv_nsec >= NSEC_PER_SEC {
+        return EINVAL;
+    }
+    let left = match sleep_until(TimeSpec::now() + req) {
+        Ok(()) => TimeSpec::new(),
+        Err(left) => left,
+    };
+    if !rem.is_null() && copy_to_user(token, &left, rem).is_err() {
+        return EFAULT;
+    }
+    if left.is_zero() {
+        SUCCESS
+    } else {
+        EINTR
+    }
+}
 
-    let end = TimeSpec::now() + req;
-    wait_with_timeout(Arc::downgrade(&task), end);
-    drop(task);
-
-    block_current_and_run_next();
-    let task = current_task().unwrap();
-    let inner = task.acquire_inner_lock();
+/// Sleeping on a CPU clock is not supported.
+/// The wall time to sleep until is taken as a monotonic time, the wall clock being set meanwhile doesn't matter.
+pub fn sys_clock_nanosleep(
+    clk_id: usize,
+    flags: u32,
+    req: *const TimeSpec,
+    rem: *mut TimeSpec,
+) -> isize {
+    let clock = match ClockId::from_user(clk_id) {
+        Ok(clock) if !clock.is_cpu_clock() => clock,
+        _ => return EINVAL,
+    };
+    let token = current_user_token();
+    let req = match get_from_user(token, req) {
+        Ok(req) => req,
+        Err(errno) => return errno,
+    };
+    if req.tv_nsec >= NSEC_PER_SEC {
+        return EINVAL;
+    }
+    let abstime = flags & TIMER_ABSTIME != 0;
     let now = TimeSpec::now();
-    // this is a little different with manual (do not consider sigmask)
-    // but now we have to compromise
-    if inner.sigpending.is_empty() {
-        assert!(end <= now);
-        if !rem.is_null() {
-            copy_to_user(token, &TimeSpec::new(), rem).unwrap();
-        }
-        SUCCESS
+    let end = if abstime {
+        now + (req - clock.now().unwrap())
     } else {
-        if !rem.is_null() {
-            copy_to_user(token, &(end - now), rem).unwrap();
+        now + req
+    };
+    match sleep_until(end) {
+        Ok(()) => SUCCESS,
+        Err(left) => {
+            // an absolute sleep is simply restarted
+            if !abstime && !rem.is_null() && copy_to_user(token, &left, rem).is_err() {
+                return EFAULT;
+            }
+            EINTR
         }
-        EINTR
     }
 }
 
@@ -222,7 +576,7 @@ pub fn sys_gettimeofday(tv: *mut TimeVal, _tz: *mut TimeZone) -> isize {
     // Timezone is currently NOT supported.
     if !tv.is_null() {
         let token = current_user_token();
-        let timeval = &TimeVal::now();
+        let timeval = &TimeVal::now_realtime();
         if copy_to_user(token, timeval, tv).is_err() {
             log::error!("[sys_gettimeofday] Failed to copy to {:?}", tv);
             return EFAULT;
@@ -231,6 +585,23 @@ pub fn sys_gettimeofday(tv: *mut TimeVal, _tz: *mut TimeZone) -> isize {
     SUCCESS
 }
 
+pub fn sys_settimeofday(tv: *const TimeVal, _tz: *const TimeZone) -> isize {
+    // Timezone is currently NOT supported.
+    if !tv.is_null() {
+        let token = current_user_token();
+        let mut timeval = TimeVal::new();
+        if copy_from_user(token, tv, &mut timeval).is_err() {
+            log::error!("[sys_settimeofday] Failed to copy from {:?}", tv);
+            return EFAULT;
+        }
+        if timeval.tv_usec >= USEC_PER_SEC {
+            return EINVAL;
+        }
+        set_realtime_ns(timeval.to_us() * NSEC_PER_USEC);
+    }
+    SUCCESS
+}
+
 pub fn sys_get_time() -> isize {
     get_time_ms() as isize
 }
@@ -278,42 +649,251 @@ pub fn sys_getppid() -> isize {
 }
 
 pub fn sys_getuid() -> isize {
-    0 // root user
+    current_task().unwrap().cred.lock().uid as isize
 }
 
 pub fn sys_geteuid() -> isize {
-    0 // root user
+    current_task().unwrap().cred.lock().euid as isize
 }
 
 pub fn sys_getgid() -> isize {
-    0 // root group
+    current_task().unwrap().cred.lock().gid as isize
 }
 
 pub fn sys_getegid() -> isize {
-    0 // root group
+    current_task().unwrap().cred.lock().egid as isize
+}
+
+/// # Return Conditions
+/// Returns `EINVAL` if `uid` is -1,
+/// `EPERM` if the caller is not root and `uid` is neither its real nor its saved user ID.
+pub fn sys_setuid(uid: u32) -> isize {
+    info!("[sys_setuid] uid: {}", uid);
+    match current_task().unwrap().cred.lock().setuid(uid) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+/// An ID of -1 is left unchanged.
+/// # Return Conditions
+/// Returns `EPERM` if the caller is not root and asks for an ID it doesn't have.
+pub fn sys_setreuid(ruid: u32, euid: u32) -> isize {
+    info!(
+        "[sys_setreuid] ruid: {}, euid: {}",
+        ruid as i32, euid as i32
+    );
+    match current_task().unwrap().cred.lock().setreuid(ruid, euid) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+/// An ID of -1 is left unchanged.
+/// # Return Conditions
+/// Returns `EPERM` if the caller is not root and asks for an ID other than its current three.
+pub fn sys_setresuid(ruid: u32, euid: u32, suid: u32) -> isize {
+    info!(
+        "[sys_setresuid] ruid: {}, euid: {}, suid: {}",
+        ruid as i32, euid as i32, suid as i32
+    );
+    let task = current_task().unwrap();
+    let result = task.cred.lock().setresuid(ruid, euid, suid);
+    match result {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_getresuid(ruid: *mut u32, euid: *mut u32, suid: *mut u32) -> isize {
+    let task = current_task().unwrap();
+    let token = task.get_user_token();
+    let cred = task.cred.lock().clone();
+    if copy_to_user(token, &cred.uid, ruid).is_err()
+        || copy_to_user(token, &cred.euid, euid).is_err()
+        || copy_to_user(token, &cred.suid, suid).is_err()
+    {
+        return EFAULT;
+    }
+    SUCCESS
+}
+
+/// The same as `sys_setuid()` for the group IDs
+pub fn sys_setgid(gid: u32) -> isize {
+    info!("[sys_setgid] gid: {}", gid);
+    match current_task().unwrap().cred.lock().setgid(gid) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+/// The same as `sys_setreuid()` for the group IDs
+pub fn sys_setregid(rgid: u32, egid: u32) -> isize {
+    info!(
+        "[sys_setregid] rgid: {}, egid: {}",
+        rgid as i32, egid as i32
+    );
+    match current_task().unwrap().cred.lock().setregid(rgid, egid) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+/// The same as `sys_setresuid()` for the group IDs
+pub fn sys_setresgid(rgid: u32, egid: u32, sgid: u32) -> isize {
+    info!(
+        "[sys_setresgid] rgid: {}, egid: {}, sgid: {}",
+        rgid as i32, egid as i32, sgid as i32
+    );
+    let task = current_task().unwrap();
+    let result = task.cred.lock().setresgid(rgid, egid, sgid);
+    match result {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_getresgid(rgid: *mut u32, egid: *mut u32, sgid: *mut u32) -> isize {
+    let task = current_task().unwrap();
+    let token = task.get_user_token();
+    let cred = task.cred.lock().clone();
+    if copy_to_user(token, &cred.gid, rgid).is_err()
+        || copy_to_user(token, &cred.egid, egid).is_err()
+        || copy_to_user(token, &cred.sgid, sgid).is_err()
+    {
+        return EFAULT;
+    }
+    SUCCESS
 }
 
-// Warning, we don't support this syscall in fact, task.setpgid() won't take effect for some reason
-// So it just pretend to do this work.
-// Fortunately, that won't make difference when we just try to run busybox sh so far.
+/// If `size` is 0, only the number of supplementary groups is returned.
+/// # Return Conditions
+/// Returns `EINVAL` if `size` is less than the number of supplementary groups.
+pub fn sys_getgroups(size: usize, list: *mut u32) -> isize {
+    let task = current_task().unwrap();
+    let groups = task.cred.lock().groups.clone();
+    if size == 0 {
+        return groups.len() as isize;
+    }
+    if size < groups.len() {
+        return EINVAL;
+    }
+    if copy_to_user_array(task.get_user_token(), groups.as_ptr(), list, groups.len()).is_err() {
+        return EFAULT;
+    }
+    groups.len() as isize
+}
+
+/// # Return Conditions
+/// Returns `EPERM` if the caller is not root, `EINVAL` if `size` is above `NGROUPS_MAX`.
+pub fn sys_setgroups(size: usize, list: *const u32) -> isize {
+    info!("[sys_setgroups] size: {}", size);
+    if size > NGROUPS_MAX {
+        return EINVAL;
+    }
+    let task = current_task().unwrap();
+    let mut groups = Vec::with_capacity(size);
+    groups.resize(size, 0u32);
+    if size > 0
+        && copy_from_user_array(task.get_user_token(), list, groups.as_mut_ptr(), size).is_err()
+    {
+        return EFAULT;
+    }
+    match task.cred.lock().setgroups(groups) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+/// Threads of thread group `tgid`
+fn thread_group_tasks(tgid: usize) -> Vec<Arc<TaskControlBlock>> {
+    all_tasks()
+        .into_iter()
+        .filter(|task| task.tgid == tgid)
+        .collect()
+}
+
+/// Move process `pid` (the caller if 0) into process group `pgid` (named after `pid` if 0),
+/// which must be in the session of the caller.
+/// # Return Conditions
+/// Returns `ESRCH` if `pid` is neither the caller nor one of its children,
+/// `EPERM` if `pid` is a session leader or in another session, or there is no such process group to join.
 pub fn sys_setpgid(pid: usize, pgid: usize) -> isize {
-    /* An attempt.*/
-    let task = crate::task::find_task_by_tgid(pid);
-    match task {
-        Some(task) => task.setpgid(pgid),
-        None => ESRCH,
+    if (pgid as isize) < 0 {
+        return EINVAL;
     }
+    let task = current_task().unwrap();
+    let pid = if pid == 0 { task.tgid } else { pid };
+    let pgid = if pgid == 0 { pid } else { pgid };
+    let target = match find_task_by_tgid(pid) {
+        Some(target) => target,
+        None => return ESRCH,
+    };
+    let is_child = target
+        .acquire_inner_lock()
+        .parent
+        .as_ref()
+        .and_then(|parent| parent.upgrade())
+        .map_or(false, |parent| parent.tgid == task.tgid);
+    if target.tgid != task.tgid && !is_child {
+        return ESRCH;
+    }
+    let sid = task.getsid();
+    let target_sid = target.getsid();
+    if target_sid != sid || target_sid == target.tgid {
+        return EPERM;
+    }
+    if pgid != target.tgid
+        && !all_tasks()
+            .iter()
+            .any(|process| process.getpgid() == pgid && process.getsid() == sid)
+    {
+        return EPERM;
+    }
+    for thread in thread_group_tasks(target.tgid) {
+        thread.setpgid(pgid);
+    }
+    SUCCESS
 }
 
 pub fn sys_getpgid(pid: usize) -> isize {
-    /* An attempt.*/
-    let task = crate::task::find_task_by_tgid(pid);
+    let task = if pid == 0 {
+        current_task()
+    } else {
+        find_task_by_tgid(pid)
+    };
     match task {
         Some(task) => task.getpgid() as isize,
         None => ESRCH,
     }
 }
 
+/// Start a new session without a controlling terminal, the caller leads it and a new process group.
+/// # Return Conditions
+/// Returns the new session ID, or `EPERM` if the caller already leads a process group.
+pub fn sys_setsid() -> isize {
+    let tgid = current_task().unwrap().tgid;
+    if all_tasks().iter().any(|task| task.getpgid() == tgid) {
+        return EPERM;
+    }
+    for thread in thread_group_tasks(tgid) {
+        thread.setsid(tgid);
+    }
+    tgid as isize
+}
+
+pub fn sys_getsid(pid: usize) -> isize {
+    let task = if pid == 0 {
+        current_task()
+    } else {
+        find_task_by_tgid(pid)
+    };
+    match task {
+        Some(task) => task.getsid() as isize,
+        None => ESRCH,
+    }
+}
+
 // For user, tid is pid in kernel
 pub fn sys_gettid() -> isize {
     current_task().unwrap().pid.0 as isize
@@ -469,6 +1049,19 @@ pub fn sys_clone(       //封装后的sys_clone
         "[sys_clone] flags: {:?}, stack: {:?}, exit_signal: {:?}, ptid: {:?}, tls: {:?}, ctid: {:?}",
         flags, stack, exit_signal, ptid, tls, ctid
     );
+    // the tasks of the same real user ID count against `RLIMIT_NPROC`, root is not limited
+    let uid = parent.cred.lock().uid;
+    let nproc = parent.rlimit.lock().get(Resource::NPROC);
+    if uid != 0 {
+        let count = all_tasks()
+            .iter()
+            .filter(|task| task.cred.lock().uid == uid)
+            .count();
+        if nproc.exceeded_by(count + 1) {
+            warn!("[sys_clone] RLIMIT_NPROC {} reached", nproc.rlim_cur);
+            return EAGAIN;
+        }
+    }
     show_frame_consumption! {   //show_frame_consumption! 宏用于记录资源消耗
         "clone";
         let child = parent.sys_clone(flags, stack, tls, exit_signal);
@@ -500,7 +1093,6 @@ pub fn sys_execve(
     mut argv: *const *const u8,//新程序的命令行参数，指向⽤户空间的参数列表起始地址
     mut envp: *const *const u8,//环境变量表，环境变量是⼀系列键值对，字符串类型，指向用户空间的环境变量地址
 ) -> isize {
-    const DEFAULT_SHELL: &str = "/bin/bash";//定义系统中默认的shell解释器的路径
     let task = current_task().unwrap();//获取正在执行任务的引用
     let token = task.get_user_token();                  //物理页的标识符(token)
     let path = match translated_str(token, pathname) {//结合物理页标识符将用户空间的文件路径转换成内核空间的路径
@@ -554,43 +1146,24 @@ pub fn sys_execve(
         envp_vec,
         envp_vec.len()
     );
-    //从当前进程 (task) 中获取文件系统 (fs) 的锁，然后获取该文件系统的工作目录 (working_inode)。
-    let working_inode = &task.fs.lock().working_inode;
+    //从当前进程 (task) 中获取文件系统 (fs) 的工作目录 (working_inode)。
     //这个目录将用于解析可执行文件的路径。当执行 exec 时，操作系统需要知道可执行文件的路径，以便打开它并加载它的内容。
-    match working_inode.open(&path, OpenFlags::O_RDONLY, false) {
-        Ok(file) => {
-            if file.get_size() < 4 {    // 如果文件大小小于 4 字节，返回 ENOEXEC 错误
-                return ENOEXEC;
-            }
-            let mut magic_number = Box::<[u8; 4]>::new([0; 4]); //创建一个4字节大小的缓冲区
-            // this operation may be expensive... I'm not sure
-            file.read(Some(&mut 0usize), magic_number.as_mut_slice());// 读取文件的前 4 个字节，获取 ELF 文件的魔数
-            /*判断 ELF 头部：读取文件的前四个字节，这是 ELF 头部的魔数。如果魔数是 b"\x7fELF"，表示文件是一个 ELF 可执行文件；如果魔数是 b"#!"，
-            表示文件可能是一个脚本文件，需要使用默认的 shell 解释；如果魔数都不匹配，返回 ENOEXEC 错误，表示无法执行。 */
-            let elf = match magic_number.as_slice() {
-                b"\x7fELF" => file,//可执行文件标识(b"\x7fELF")
-                b"#!" => { //匹配了 b"#!"，则被视为 shell 脚本文件会打开默认的 shell 文件（DEFAULT_SHELL），并将其作为 shell_file，并将其路径作为参数列表的第一个参数。
-                    let shell_file = working_inode
-                        .open(DEFAULT_SHELL, OpenFlags::O_RDONLY, false)
-                        .unwrap();
-                    argv_vec.insert(0, DEFAULT_SHELL.to_string());//将默认的 shell 文件路径作为第一个参数插入到参数列表的开头，确保在执行文件或 shell 脚本时，第一个参数总是可执行文件或 shell。即#！/bin/bash
-                    shell_file
-                }
-                _ => return ENOEXEC,
-            };
-             // 获取当前任务，并加载 ELF 文件
-            let task = current_task().unwrap();
-            show_frame_consumption! {
-                "load_elf";
-                if let Err(errno) = task.load_elf(elf, &argv_vec, &envp_vec) {
-                    return errno;//检查 load_elf 方法的返回值。如果返回的是 Err(errno)，表示加载 ELF 文件失败。
-                };
-            }
-            // should return 0 in success
-            SUCCESS
-        }
-        Err(errno) => errno,
+    let working_inode = task.fs.lock().working_inode.clone();
+    let cred = task.cred.lock().clone();
+    let bprm = match LinuxBinprm::new(working_inode, cred, path, argv_vec, envp_vec) {
+        Ok(bprm) => bprm,
+        Err(errno) => return errno,
+    };
+    /*由注册的可执行格式依次尝试加载：ELF 文件直接加载；脚本与 binfmt_misc 项改写参数后交给解释器，
+    解释器本身也会再次按格式查找；没有格式认识该文件时返回 ENOEXEC 错误，表示无法执行。 */
+    show_frame_consumption! {
+        "exec_binprm";
+        if let Err(errno) = exec_binprm(&task, bprm) {
+            return errno;//检查 exec_binprm 的返回值。如果返回的是 Err(errno)，表示加载可执行文件失败。
+        };
     }
+    // should return 0 in success
+    SUCCESS
 }
 
 bitflags! {
@@ -661,7 +1234,36 @@ pub fn sys_wait4(pid: isize, status: *mut u32, option: u32, _ru: *mut Rusage) ->
                 return found_pid as isize;
             }
         } else {
+            // stopped or continued children are reported once if asked
+            let reported = inner.children.iter().find_map(|p| {
+                if pid != -1 && pid as usize != p.getpid() {
+                    return None;
+                }
+                let mut child_inner = p.acquire_inner_lock();
+                let wait_status = child_inner.wait_status?;
+                let wanted = if wait_status == WAIT_CONTINUED {
+                    option.contains(WaitOption::WCONTINUED)
+                } else {
+                    option.contains(WaitOption::WSTOPPED)
+                };
+                if !wanted {
+                    return None;
+                }
+                if !option.contains(WaitOption::WNOWAIT) {
+                    child_inner.wait_status = None;
+                }
+                Some((p.tgid, wait_status))
+            });
             drop(inner);
+            if let Some((found_pid, wait_status)) = reported {
+                if !status.is_null() {
+                    match translated_refmut(token, status) {
+                        Ok(word) => *word = wait_status,
+                        Err(errno) => return errno,
+                    };
+                }
+                return found_pid as isize;
+            }
             if option.contains(WaitOption::WNOHANG) {
                 return SUCCESS;
             } else {
@@ -672,134 +1274,81 @@ pub fn sys_wait4(pid: isize, status: *mut u32, option: u32, _ru: *mut Rusage) ->
     }
 }
 
-#[allow(unused)]
-#[derive(Clone, Copy, Debug)]
-#[repr(C)]
-pub struct RLimit {
-    rlim_cur: usize, /* Soft limit */
-    rlim_max: usize, /* Hard limit (ceiling for rlim_cur) */
-}
-
-#[derive(Debug, Eq, PartialEq, FromPrimitive)]
-#[repr(u32)]
-pub enum Resource {
-    CPU = 0,
-    FSIZE = 1,
-    DATA = 2,
-    STACK = 3,
-    CORE = 4,
-    RSS = 5,
-    NPROC = 6,
-    NOFILE = 7,
-    MEMLOCK = 8,
-    AS = 9,
-    LOCKS = 10,
-    SIGPENDING = 11,
-    MSGQUEUE = 12,
-    NICE = 13,
-    RTPRIO = 14,
-    RTTIME = 15,
-    NLIMITS = 16,
-    #[num_enum(default)]
-    ILLEAGAL,
-}
-
 /// It can be used to both set and get the resource limits of an arbitrary process.
-/// # WARNING
-/// Partial implementation
+/// The new limits take effect from the next time the resource is used,
+/// except for `RLIMIT_NOFILE` which closes the descriptors beyond the new limit at once.
+/// # Return Conditions
+/// Returns `ESRCH` if there is no process `pid`,
+/// `EINVAL` if `resource` is invalid or the new soft limit is above the new hard limit,
+/// `EPERM` if the new hard limit is above what the kernel can provide,
+/// or the caller is not root and raises the hard limit or sets the limits of another user's process.
 pub fn sys_prlimit(
     pid: usize,
     resource: u32,
     new_limit: *const RLimit,
     old_limit: *mut RLimit,
 ) -> isize {
-    if pid == 0 {
-        let task = current_task().unwrap();
-        let inner = task.acquire_inner_lock();
-        let token = task.get_user_token();
-        let resource = Resource::from_primitive(resource);
-        info!("[sys_prlimit] pid: {}, resource: {:?}", pid, resource);
-
-        drop(inner);
-        if !old_limit.is_null() {
-            match resource {
-                Resource::STACK => {
-                    if copy_to_user(
-                        token,
-                        &(RLimit {
-                            rlim_cur: USER_STACK_SIZE,
-                            rlim_max: USER_STACK_SIZE,
-                        }),
-                        old_limit,
-                    )
-                    .is_err()
-                    {
-                        log::error!("[sys_prlimit] Failed to copy to {:?}", old_limit);
-                        return EFAULT;
-                    }
-                }
-                Resource::NPROC => {
-                    if copy_to_user(
-                        token,
-                        &(RLimit {
-                            rlim_cur: SYSTEM_TASK_LIMIT,
-                            rlim_max: SYSTEM_TASK_LIMIT,
-                        }),
-                        old_limit,
-                    )
-                    .is_err()
-                    {
-                        log::error!("[sys_prlimit] Failed to copy to {:?}", old_limit);
-                        return EFAULT;
-                    }
-                }
-                Resource::NOFILE => {
-                    let lock = task.files.lock();
-                    if copy_to_user(
-                        token,
-                        &(RLimit {
-                            rlim_cur: lock.get_soft_limit(),
-                            rlim_max: lock.get_hard_limit(),
-                        }),
-                        old_limit,
-                    )
-                    .is_err()
-                    {
-                        log::error!("[sys_prlimit] Failed to copy to {:?}", old_limit);
-                        return EFAULT;
-                    }
-                }
-                Resource::ILLEAGAL => return EINVAL,
-                _ => todo!(),
-            }
+    let task = if pid == 0 {
+        current_task().unwrap()
+    } else {
+        match find_task_by_pid(pid) {
+            Some(task) => task,
+            None => return ESRCH,
         }
-        if !new_limit.is_null() {
-            let rlimit = &mut RLimit {
-                rlim_cur: 0,
-                rlim_max: 0,
-            };
-            if copy_from_user(token, new_limit, rlimit).is_err() {
-                log::error!("[sys_prlimit] Failed to copy from {:?}", new_limit);
-                return EFAULT;
-            };
-            match resource {
-                Resource::NOFILE => {
-                    task.files.lock().set_soft_limit(rlimit.rlim_cur);
-                    task.files.lock().set_hard_limit(rlimit.rlim_max);
-                }
-                Resource::STACK => {
-                    warn!("[prlimit] Unsupported modification stack");
-                    assert!(rlimit.rlim_cur <= USER_STACK_SIZE);
-                }
-                Resource::ILLEAGAL => return EINVAL,
-                _ => todo!(),
-            }
+    };
+    let token = current_user_token();
+    let resource = Resource::from_primitive(resource);
+    info!("[sys_prlimit] pid: {}, resource: {:?}", pid, resource);
+    if matches!(resource, Resource::NLIMITS | Resource::ILLEAGAL) {
+        return EINVAL;
+    }
+    let new = if !new_limit.is_null() {
+        let rlimit = &mut RLimit::new(0, 0);
+        if copy_from_user(token, new_limit, rlimit).is_err() {
+            log::error!("[sys_prlimit] Failed to copy from {:?}", new_limit);
+            return EFAULT;
+        };
+        if rlimit.rlim_cur > rlimit.rlim_max {
+            return EINVAL;
         }
+        if rlimit.rlim_max > resource.ceiling() {
+            return EPERM;
+        }
+        Some(*rlimit)
     } else {
-        todo!();
+        None
+    };
+    let caller = current_task().unwrap().cred.lock().clone();
+    if new.is_some() && !caller.is_privileged() {
+        let target = task.cred.lock().clone();
+        if [target.uid, target.euid, target.suid]
+            .iter()
+            .any(|uid| *uid != caller.uid)
+        {
+            return EPERM;
+        }
+    }
+    let mut rlimits = task.rlimit.lock();
+    let old = rlimits.get(resource);
+    if let Some(rlimit) = new {
+        if rlimit.rlim_max > old.rlim_max && !caller.is_privileged() {
+            return EPERM;
+        }
+        rlimits.set(resource, rlimit);
+        drop(rlimits);
+        if resource == Resource::NOFILE {
+            let mut fd_table = task.files.lock();
+            fd_table.set_soft_limit(rlimit.rlim_cur);
+            fd_table.set_hard_limit(rlimit.rlim_max);
+        }
+    }
+    if !old_limit.is_null() && copy_to_user(token, &old, old_limit).is_err() {
+        log::error!("[sys_prlimit] Failed to copy to {:?}", old_limit);
+        return EFAULT;
     }
     SUCCESS
 }
+
 /// set pointer to thread ID
 /// This feature is currently NOT supported and is implemented as a stub,
 /// since threads are not supported.
@@ -848,46 +1397,59 @@ pub fn sys_futex(
     };
     let cmd = threads::FutexCmd::from_primitive(futex_op & 0x7fu32);
     let option = FutexOption::from_bits_truncate(futex_op);
-    if !option.contains(FutexOption::PRIVATE) {
-        warn!("[futex] process-shared futex is unimplemented");
-    }
     info!(
         "[futex] uaddr: {:?}, futex_op: {:?}, option: {:?}, val: {:X}, timeout: {:?}, uaddr2: {:?}, val3: {:X}",
         uaddr, cmd, option, val, timeout, uaddr2, val3
     );
-    match cmd {
-        FutexCmd::Wait => {
-            let timeout = match cmd {
-                FutexCmd::Wait | FutexCmd::LockPi | FutexCmd::WaitBitset => {
-                    match try_get_from_user(token, timeout) {
-                        Ok(timeout) => timeout,
-                        Err(errno) => return errno,
-                    }
-                }
-                _ => None,
-            };
-            // guess what will happen if we don't do `drop(task)` here?
-            drop(task);
-            do_futex_wait(futex_word, val, timeout)
+    let private = option.contains(FutexOption::PRIVATE);
+    let key = FutexKey::new(&task, uaddr as usize, futex_word, private);
+    // the second futex of requeue and wake-op
+    let second = || -> Result<(FutexKey, &'static mut u32), isize> {
+        if uaddr2.is_null() || uaddr2.align_offset(4) != 0 {
+            return Err(EINVAL);
         }
-        FutexCmd::Wake => {
-            let futex_word_addr = futex_word as *const u32 as usize;
-            task.futex.lock().wake(futex_word_addr, val)
-        }
-        FutexCmd::Requeue => {
-            if uaddr2.is_null() || uaddr2.align_offset(4) != 0 {
-                return EINVAL;
-            }
-            let futex_word_2 = match translated_refmut(token, uaddr2) {
-                Ok(futex_word_2) => futex_word_2,
+        let futex_word_2 = translated_refmut(token, uaddr2)?;
+        let task = current_task().unwrap();
+        let key2 = FutexKey::new(&task, uaddr2 as usize, futex_word_2, private);
+        Ok((key2, futex_word_2))
+    };
+    // `timeout` is `val2` for requeue and wake-op
+    let val2 = timeout as usize as u32;
+    let timeout = match cmd {
+        FutexCmd::Wait | FutexCmd::WaitBitset | FutexCmd::LockPi | FutexCmd::LockPi2 => {
+            match try_get_from_user(token, timeout) {
+                // relative for `FUTEX_WAIT` only
+                Ok(timeout) if cmd == FutexCmd::Wait => timeout.map(|t| t + TimeSpec::now()),
+                Ok(timeout) => timeout,
                 Err(errno) => return errno,
-            };
-            task.futex
-                .lock()
-                .requeue(futex_word, futex_word_2, val, timeout as u32)
+            }
         }
+        _ => None,
+    };
+    // guess what will happen if we don't do `drop(task)` here?
+    drop(task);
+    match cmd {
+        FutexCmd::Wait => futex_wait(key, futex_word, val, timeout, FUTEX_BITSET_MATCH_ANY),
+        FutexCmd::WaitBitset => futex_wait(key, futex_word, val, timeout, val3),
+        FutexCmd::Wake => futex_wake(key, val, FUTEX_BITSET_MATCH_ANY),
+        FutexCmd::WakeBitset => futex_wake(key, val, val3),
+        FutexCmd::Requeue => match second() {
+            Ok((key2, _)) => futex_requeue(key, key2, val, val2, None),
+            Err(errno) => errno,
+        },
+        FutexCmd::CmpRequeue => match second() {
+            Ok((key2, _)) => futex_requeue(key, key2, val, val2, Some((&*futex_word, val3))),
+            Err(errno) => errno,
+        },
+        FutexCmd::WakeOp => match second() {
+            Ok((key2, futex_word_2)) => futex_wake_op(key, key2, futex_word_2, val, val2, val3),
+            Err(errno) => errno,
+        },
+        FutexCmd::LockPi | FutexCmd::LockPi2 => futex_lock_pi(key, futex_word, timeout, false),
+        FutexCmd::TrylockPi => futex_lock_pi(key, futex_word, None, true),
+        FutexCmd::UnlockPi => futex_unlock_pi(key, futex_word),
+        FutexCmd::Fd | FutexCmd::WaitRequeuePi | FutexCmd::CmpRequeuePi => ENOSYS,
         FutexCmd::Invalid => EINVAL,
-        _ => todo!(),
     }
 }
 
@@ -975,9 +1537,16 @@ pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> isize {
 }
 
 pub fn sys_clock_gettime(clk_id: usize, tp: *mut TimeSpec) -> isize {
+    let clock = match ClockId::from_user(clk_id) {
+        Ok(clock) => clock,
+        Err(errno) => return errno,
+    };
     if !tp.is_null() {
         let token = current_user_token();
-        let timespec = &TimeSpec::now();
+        let timespec = &match clock.now() {
+            Some(now) => now,
+            None => return EINVAL,
+        };
         if copy_to_user(token, timespec, tp).is_err() {
             log::error!("[sys_clock_gettime] Failed to copy to {:?}", tp);
             return EFAULT;
@@ -987,6 +1556,117 @@ pub fn sys_clock_gettime(clk_id: usize, tp: *mut TimeSpec) -> isize {
     SUCCESS
 }
 
+pub fn sys_clock_getres(clk_id: usize, res: *mut TimeSpec) -> isize {
+    let clock = match ClockId::from_user(clk_id) {
+        Ok(clock) => clock,
+        Err(errno) => return errno,
+    };
+    if !res.is_null() && copy_to_user(current_user_token(), &clock.resolution(), res).is_err() {
+        return EFAULT;
+    }
+    SUCCESS
+}
+
+/// Only the wall clock can be set, the RTC is left alone, `hwclock --systohc` writes it.
+pub fn sys_clock_settime(clk_id: usize, tp: *const TimeSpec) -> isize {
+    if clk_id != CLOCK_REALTIME {
+        return EINVAL;
+    }
+    let token = current_user_token();
+    let mut timespec = TimeSpec::new();
+    if copy_from_user(token, tp, &mut timespec).is_err() {
+        log::error!("[sys_clock_settime] Failed to copy from {:?}", tp);
+        return EFAULT;
+    }
+    if timespec.tv_nsec >= NSEC_PER_SEC {
+        return EINVAL;
+    }
+    info!("[sys_clock_settime] clk_id: {}, tp: {:?}", clk_id, timespec);
+    set_realtime_ns(timespec.to_ns());
+    SUCCESS
+}
+
+pub fn sys_timer_create(clk_id: usize, sevp: *const SigEvent, timerid: *mut i32) -> isize {
+    let clock = match ClockId::from_user(clk_id) {
+        Ok(clock) => clock,
+        Err(errno) => return errno,
+    };
+    let task = current_task().unwrap();
+    let token = task.get_user_token();
+    let event = match try_get_from_user(token, sevp) {
+        Ok(event) => event,
+        Err(errno) => return errno,
+    };
+    let limit = timer_limit(&task);
+    let id = match task.timers.lock().create(clock, event, limit) {
+        Ok(id) => id,
+        Err(errno) => return errno,
+    };
+    if copy_to_user(token, &(id as i32), timerid).is_err() {
+        task.timers.lock().delete(id).ok();
+        return EFAULT;
+    }
+    info!("[sys_timer_create] clock: {:?}, timer id: {}", clock, id);
+    SUCCESS
+}
+
+pub fn sys_timer_settime(
+    timerid: usize,
+    flags: u32,
+    new_value: *const ITimerSpec,
+    old_value: *mut ITimerSpec,
+) -> isize {
+    let task = current_task().unwrap();
+    let token = task.get_user_token();
+    let new = match get_from_user(token, new_value) {
+        Ok(new) => new,
+        Err(errno) => return errno,
+    };
+    info!(
+        "[sys_timer_settime] timer id: {}, flags: {}, new_value: {:?}",
+        timerid, flags, new
+    );
+    let old = match task.timers.lock().settime(timerid, flags, new) {
+        Ok(old) => old,
+        Err(errno) => return errno,
+    };
+    watch_clock_timers(&task.timers);
+    if !old_value.is_null() && copy_to_user(token, &old, old_value).is_err() {
+        return EFAULT;
+    }
+    SUCCESS
+}
+
+pub fn sys_timer_gettime(timerid: usize, curr_value: *mut ITimerSpec) -> isize {
+    let task = current_task().unwrap();
+    let curr = match task.timers.lock().gettime(timerid) {
+        Ok(curr) => curr,
+        Err(errno) => return errno,
+    };
+    if copy_to_user(task.get_user_token(), &curr, curr_value).is_err() {
+        return EFAULT;
+    }
+    SUCCESS
+}
+
+pub fn sys_timer_getoverrun(timerid: usize) -> isize {
+    let task = current_task().unwrap();
+    let overrun = task.timers.lock().getoverrun(timerid);
+    match overrun {
+        Ok(overrun) => overrun.min(i32::MAX as usize) as isize,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_timer_delete(timerid: usize) -> isize {
+    let task = current_task().unwrap();
+    let result = task.timers.lock().delete(timerid);
+    match result {
+        Ok(()) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
 // int sigaction(int signum, const struct sigaction *act, struct sigaction *oldact);
 pub fn sys_sigaction(signum: usize, act: usize, oldact: usize) -> isize {
     trace!(
@@ -1016,32 +1696,23 @@ pub fn sys_sigtimedwait(set: usize, info: usize, timeout: usize) -> isize {
 }
 
 pub fn sys_sigreturn() -> isize {
-    // mark not processing signal handler
-    let task = current_task().unwrap();
-    let mut inner = task.acquire_inner_lock();
-    let token = task.get_user_token();
-    info!("[sys_sigreturn] pid: {}", task.pid.0);
+    info!("[sys_sigreturn] pid: {}", current_task().unwrap().pid.0);
+    sigreturn()
+}
 
-    let trap_cx = inner.get_trap_cx();
-    // restore sigmask & trap context
-    let ucontext_addr = (trap_cx.gp.sp + size_of::<SigInfo>() + 0x7) & !0x7;
-    inner.sigmask = *translated_ref(
-        token,
-        (ucontext_addr + 2 * size_of::<usize>() + size_of::<SignalStack>()) as *mut Signals,
-    )
-    .unwrap(); // restore sigmask
-    copy_from_user(
-        token,
-        (ucontext_addr
-            + 2 * size_of::<usize>()
-            + size_of::<SignalStack>()
-            + size_of::<Signals>()
-            + crate::arch::UserContext::PADDING_SIZE) as *mut MachineContext,
-        (trap_cx as *mut TrapContext).cast::<MachineContext>(),
-    )
-    .unwrap(); // restore trap_cx
-               // This should be `Ok(())`.
-    return trap_cx.gp.a0 as isize; // return a0: not modify any of trap_cx
+pub fn sys_sigaltstack(ss: usize, old_ss: usize) -> isize {
+    info!("[sys_sigaltstack] ss: {:X}, old_ss: {:X}", ss, old_ss);
+    sigaltstack(ss as *const SignalStack, old_ss as *mut SignalStack)
+}
+
+pub fn sys_sigsuspend(mask: usize) -> isize {
+    info!("[sys_sigsuspend] mask: {:X}", mask);
+    sigsuspend(mask as *const Signals)
+}
+
+pub fn sys_sigpending(set: usize) -> isize {
+    info!("[sys_sigpending] set: {:X}", set);
+    sigpending(set as *mut Signals)
 }
 
 /// Get process times
@@ -1115,7 +1786,43 @@ pub fn sys_fork() -> isize {
     new_pid as isize
 }
 
-//暂未完全实现
-pub fn sys_getrandom(buf:usize,buflen:usize,_flags: usize) -> isize{
-    0
+bitflags! {
+    pub struct GetRandomFlags: usize {
+        /// Fail with `EAGAIN` instead of blocking when the generator hasn't been seeded
+        const GRND_NONBLOCK = 0x0001;
+        /// Same as no flag, there is one generator for both `/dev/random` and `/dev/urandom`
+        const GRND_RANDOM   = 0x0002;
+        /// Never block, even before the generator has been seeded
+        const GRND_INSECURE = 0x0004;
+    }
+}
+
+/// Fill `buf` with `buflen` random bytes from the kernel CRNG,
+/// blocking until it has been seeded unless `GRND_INSECURE` is given.
+/// # Return Conditions
+/// Returns the number of bytes filled,
+/// `EINVAL` on unknown flags or `GRND_INSECURE` along with `GRND_RANDOM`,
+/// `EAGAIN` for `GRND_NONBLOCK` before the generator has been seeded,
+/// `EINTR` if a signal arrives while waiting for that.
+pub fn sys_getrandom(buf: usize, buflen: usize, flags: usize) -> isize {
+    let flags = match GetRandomFlags::from_bits(flags) {
+        Some(flags) => flags,
+        None => return EINVAL,
+    };
+    if flags.contains(GetRandomFlags::GRND_INSECURE | GetRandomFlags::GRND_RANDOM) {
+        return EINVAL;
+    }
+    if !flags.contains(GetRandomFlags::GRND_INSECURE) {
+        if let Err(errno) = wait_for_random_bytes(flags.contains(GetRandomFlags::GRND_NONBLOCK)) {
+            return errno;
+        }
+    }
+    let buffers = match translated_byte_buffer(current_user_token(), buf as *const u8, buflen) {
+        Ok(buffers) => buffers,
+        Err(errno) => return errno,
+    };
+    for buffer in buffers {
+        get_random_bytes(buffer);
+    }
+    buflen as isize
 }
@@ -1,67 +1,603 @@
-use super::errno::*;
-use crate::{fs::FileDescriptor, mm::copy_to_user, task::*};
+use super::{errno::*, fs::IOVec};
+use crate::{
+    fs::{
+        send_sigpipe, FileDescriptor, Socket, SocketAddr, SocketType, SOCKET_BUFFER_SIZE,
+        SOCK_CLOEXEC, SOCK_NONBLOCK, SOL_SOCKET,
+    },
+    mm::{
+        copy_from_user_array, copy_to_user, copy_to_user_array, get_from_user,
+        translated_byte_buffer, translated_byte_buffer_append_to_existing_vec, UserBuffer,
+    },
+    task::*,
+};
+use alloc::{sync::Arc, vec::Vec};
+use log::{info, warn};
 
-pub fn sys_socket(_domain: u32, _type: u32, _protocol: u32) -> isize {
+const MSG_PEEK: u32 = 0x2;
+const MSG_DONTWAIT: u32 = 0x40;
+const MSG_NOSIGNAL: u32 = 0x4000;
+
+/// Get the socket behind `sockfd` and whether the fd is nonblocking.
+/// The fd table is unlocked on return, so callers may block.
+fn get_socket(sockfd: usize) -> Result<(Arc<Socket>, bool), isize> {
+    let task = current_task().unwrap();
+    let fd_table = task.files.lock();
+    let file_descriptor = match fd_table.get_ref(sockfd) {
+        Ok(file_descriptor) => file_descriptor,
+        Err(errno) => return Err(errno),
+    };
+    match file_descriptor.file.clone().downcast_arc::<Socket>() {
+        Ok(socket) => Ok((socket, file_descriptor.get_nonblock())),
+        Err(_) => Err(ENOTSOCK),
+    }
+}
+
+pub fn sys_socket(domain: u32, type_: u32, protocol: u32) -> isize {
+    let socket = match Socket::new(domain as u16, type_, protocol) {
+        Ok(socket) => socket,
+        Err(errno) => return errno,
+    };
     let task = current_task().unwrap();
     let mut fd_table = task.files.lock();
     let new_fd = match fd_table.insert(FileDescriptor::new(
-        (_type & 0o2000000) == 0o2000000,
-        (_type & 0o4000) == 0o4000,
-        crate::fs::make_socket(),
+        (type_ & SOCK_CLOEXEC) != 0,
+        (type_ & SOCK_NONBLOCK) != 0,
+        socket,
     )) {
         Ok(fd) => fd,
         Err(errno) => return errno,
     };
+    info!(
+        "[sys_socket] domain: {}, type: {:#x}, protocol: {}, fd: {}",
+        domain, type_, protocol, new_fd
+    );
     new_fd as isize
 }
-pub fn sys_bind(_sockfd: usize, _addr: *const u8, _addrlen: u32) -> isize {
-    SUCCESS
+pub fn sys_bind(sockfd: usize, addr: *const u8, addrlen: u32) -> isize {
+    let (socket, _) = match get_socket(sockfd) {
+        Ok(socket) => socket,
+        Err(errno) => return errno,
+    };
+    let addr = match SocketAddr::from_user(current_user_token(), addr, addrlen) {
+        Ok(addr) => addr,
+        Err(errno) => return errno,
+    };
+    info!("[sys_bind] sockfd: {}, addr: {:?}", sockfd, addr);
+    match socket.bind(addr) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
 }
-pub fn sys_getsockname(_sockfd: usize, _addr: *const u8, _addrlen: u32) -> isize {
-    SUCCESS
+pub fn sys_getsockname(sockfd: usize, addr: *mut u8, addrlen: *mut u32) -> isize {
+    let (socket, _) = match get_socket(sockfd) {
+        Ok(socket) => socket,
+        Err(errno) => return errno,
+    };
+    match socket
+        .local_addr()
+        .to_user(current_user_token(), addr, addrlen)
+    {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
 }
-pub fn sys_getpeername(_sockfd: usize, _addr: *const u8, _addrlen: u32) -> isize {
-    ENOTSOCK
+pub fn sys_getpeername(sockfd: usize, addr: *mut u8, addrlen: *mut u32) -> isize {
+    let (socket, _) = match get_socket(sockfd) {
+        Ok(socket) => socket,
+        Err(errno) => return errno,
+    };
+    let peer = match socket.peer_addr() {
+        Ok(peer) => peer,
+        Err(errno) => return errno,
+    };
+    match peer.to_user(current_user_token(), addr, addrlen) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
 }
 pub fn sys_setsockopt(
-    _sockfd: usize,
-    _level: u32,
-    _optname: u32,
-    _optcal: *const u8,
-    _optlen: u32,
+    sockfd: usize,
+    level: u32,
+    optname: u32,
+    optval: *const u8,
+    optlen: u32,
 ) -> isize {
-    SUCCESS
+    let (socket, _) = match get_socket(sockfd) {
+        Ok(socket) => socket,
+        Err(errno) => return errno,
+    };
+    // all options we know are integers or start with one
+    if optval.is_null() || (optlen as usize) < core::mem::size_of::<u32>() {
+        return EINVAL;
+    }
+    let value = match get_from_user(current_user_token(), optval as *const u32) {
+        Ok(value) => value,
+        Err(errno) => return errno,
+    };
+    info!(
+        "[sys_setsockopt] sockfd: {}, level: {}, optname: {}, value: {}",
+        sockfd, level, optname, value
+    );
+    match socket.set_option(level, optname, value) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+pub fn sys_getsockopt(
+    sockfd: usize,
+    level: u32,
+    optname: u32,
+    optval: *mut u8,
+    optlen: *mut u32,
+) -> isize {
+    let (socket, _) = match get_socket(sockfd) {
+        Ok(socket) => socket,
+        Err(errno) => return errno,
+    };
+    let value = match socket.get_option(level, optname) {
+        Ok(value) => value,
+        Err(errno) => return errno,
+    };
+    let token = current_user_token();
+    let len = match get_from_user(token, optlen) {
+        Ok(len) => len,
+        Err(errno) => return errno,
+    };
+    if (len as usize) < core::mem::size_of::<u32>() {
+        return EINVAL;
+    }
+    if let Err(errno) = copy_to_user(token, &value, optval as *mut u32) {
+        return errno;
+    }
+    match copy_to_user(token, &(core::mem::size_of::<u32>() as u32), optlen) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
 }
 pub fn sys_sendto(
-    _sockfd: usize,
-    _buf: *const u8,
-    _len: usize,
-    _flags: u32,
-    _dest_addr: *const u8,
-    _addrlen: u32,
+    sockfd: usize,
+    buf: *const u8,
+    len: usize,
+    flags: u32,
+    dest_addr: *const u8,
+    addrlen: u32,
 ) -> isize {
-    1
+    let (socket, nonblock) = match get_socket(sockfd) {
+        Ok(socket) => socket,
+        Err(errno) => return errno,
+    };
+    let token = current_user_token();
+    let dest = if dest_addr.is_null() {
+        None
+    } else {
+        match SocketAddr::from_user(token, dest_addr, addrlen) {
+            Ok(addr) => Some(addr),
+            Err(errno) => return errno,
+        }
+    };
+    // a stream socket never takes more than its buffer at once
+    let len = match socket.socket_type() {
+        SocketType::Stream => len.min(SOCKET_BUFFER_SIZE),
+        SocketType::Datagram if len > SOCKET_BUFFER_SIZE => return EMSGSIZE,
+        SocketType::Datagram => len,
+    };
+    let mut kernel_buf = alloc::vec![0u8; len];
+    match translated_byte_buffer(token, buf, len) {
+        Ok(buffer) => UserBuffer::new(buffer).read(&mut kernel_buf),
+        Err(errno) => return errno,
+    };
+    let nonblock = nonblock || (flags & MSG_DONTWAIT) != 0;
+    match socket.send(&kernel_buf, dest, nonblock) {
+        Ok(len) => len as isize,
+        Err(EPIPE) => {
+            if (flags & MSG_NOSIGNAL) == 0 {
+                send_sigpipe();
+            }
+            EPIPE
+        }
+        Err(errno) => errno,
+    }
 }
 pub fn sys_recvfrom(
-    _sockfd: usize,
+    sockfd: usize,
     buf: *mut u8,
-    _len: usize,
-    _flags: u32,
-    _src_addr: *const u8,
-    _addrlen: u32,
+    len: usize,
+    flags: u32,
+    src_addr: *mut u8,
+    addrlen: *mut u32,
 ) -> isize {
-    let src = "x";
+    let (socket, nonblock) = match get_socket(sockfd) {
+        Ok(socket) => socket,
+        Err(errno) => return errno,
+    };
+    let token = current_user_token();
+    let mut user_buf = match translated_byte_buffer(token, buf, len) {
+        Ok(buffer) => UserBuffer::new(buffer),
+        Err(errno) => return errno,
+    };
+    // no message is larger than the receive buffer
+    let mut kernel_buf = alloc::vec![0u8; len.min(SOCKET_BUFFER_SIZE)];
+    let nonblock = nonblock || (flags & MSG_DONTWAIT) != 0;
+    let (len, src) = match socket.recv(&mut kernel_buf, (flags & MSG_PEEK) != 0, nonblock) {
+        Ok(result) => result,
+        Err(errno) => return errno,
+    };
+    user_buf.write(&kernel_buf[..len]);
+    if let Some(src) = src {
+        if let Err(errno) = src.to_user(token, src_addr, addrlen) {
+            return errno;
+        }
+    }
+    len as isize
+}
+pub fn sys_listen(sockfd: usize, backlog: u32) -> isize {
+    let (socket, _) = match get_socket(sockfd) {
+        Ok(socket) => socket,
+        Err(errno) => return errno,
+    };
+    info!("[sys_listen] sockfd: {}, backlog: {}", sockfd, backlog);
+    match socket.listen(backlog as usize) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+pub fn sys_connect(sockfd: usize, addr: *const u8, addrlen: u32) -> isize {
+    let (socket, nonblock) = match get_socket(sockfd) {
+        Ok(socket) => socket,
+        Err(errno) => return errno,
+    };
+    let addr = match SocketAddr::from_user(current_user_token(), addr, addrlen) {
+        Ok(addr) => addr,
+        Err(errno) => return errno,
+    };
+    info!("[sys_connect] sockfd: {}, addr: {:?}", sockfd, addr);
+    match socket.connect(addr, nonblock) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+pub fn sys_accept(sockfd: usize, addr: *mut u8, addrlen: *mut u32) -> isize {
+    sys_accept4(sockfd, addr, addrlen, 0)
+}
+pub fn sys_accept4(sockfd: usize, addr: *mut u8, addrlen: *mut u32, flags: u32) -> isize {
+    if (flags & !(SOCK_NONBLOCK | SOCK_CLOEXEC)) != 0 {
+        return EINVAL;
+    }
+    let (socket, nonblock) = match get_socket(sockfd) {
+        Ok(socket) => socket,
+        Err(errno) => return errno,
+    };
+    let new_socket = match socket.accept(nonblock) {
+        Ok(socket) => socket,
+        Err(errno) => return errno,
+    };
+    if let Ok(peer) = new_socket.peer_addr() {
+        if let Err(errno) = peer.to_user(current_user_token(), addr, addrlen) {
+            return errno;
+        }
+    }
     let task = current_task().unwrap();
-    let token = task.get_user_token();
-    copy_to_user(token, src.as_ptr(), buf).unwrap();
-    1
+    let mut fd_table = task.files.lock();
+    let new_fd = match fd_table.insert(FileDescriptor::new(
+        (flags & SOCK_CLOEXEC) != 0,
+        (flags & SOCK_NONBLOCK) != 0,
+        new_socket,
+    )) {
+        Ok(fd) => fd,
+        Err(errno) => return errno,
+    };
+    info!("[sys_accept4] sockfd: {}, new fd: {}", sockfd, new_fd);
+    new_fd as isize
 }
-pub fn sys_listen(_sockfd: usize, _backlog: u32) -> isize {
-    SUCCESS
+pub fn sys_socket_shutdown(sockfd: usize, how: u32) -> isize {
+    let (socket, _) = match get_socket(sockfd) {
+        Ok(socket) => socket,
+        Err(errno) => return errno,
+    };
+    match socket.shutdown(how) {
+        Ok(_) => SUCCESS,
+        Err(errno) => {
+            warn!(
+                "[sys_socket_shutdown] sockfd: {}, how: {}, errno: {}",
+                sockfd, how, errno
+            );
+            errno
+        }
+    }
 }
-pub fn sys_connect(_sockfd: usize, _addr: *const u8, _addrlen: u32) -> isize {
+pub fn sys_socketpair(domain: u32, type_: u32, protocol: u32, sv: *mut [i32; 2]) -> isize {
+    let (socket0, socket1) = match Socket::new_pair(domain as u16, type_, protocol) {
+        Ok(pair) => pair,
+        Err(errno) => return errno,
+    };
+    let cloexec = (type_ & SOCK_CLOEXEC) != 0;
+    let nonblock = (type_ & SOCK_NONBLOCK) != 0;
+    let task = current_task().unwrap();
+    let mut fd_table = task.files.lock();
+    let fd0 = match fd_table.insert(FileDescriptor::new(cloexec, nonblock, socket0)) {
+        Ok(fd) => fd,
+        Err(errno) => return errno,
+    };
+    let fd1 = match fd_table.insert(FileDescriptor::new(cloexec, nonblock, socket1)) {
+        Ok(fd) => fd,
+        Err(errno) => {
+            fd_table.remove(fd0).unwrap();
+            return errno;
+        }
+    };
+    if let Err(errno) = copy_to_user(task.get_user_token(), &[fd0 as i32, fd1 as i32], sv) {
+        fd_table.remove(fd0).unwrap();
+        fd_table.remove(fd1).unwrap();
+        return errno;
+    }
+    info!(
+        "[sys_socketpair] domain: {}, type: {:#x}, protocol: {}, sv: [{}, {}]",
+        domain, type_, protocol, fd0, fd1
+    );
     SUCCESS
 }
-pub fn sys_accept(_sockfd: usize, _addr: *const u8, _addrlen: u32) -> isize {
-    SUCCESS
+
+const SCM_RIGHTS: i32 = 1;
+/// At most this many files in one `SCM_RIGHTS` message
+const SCM_MAX_FD: usize = 253;
+/// At most this many buffers in one iovec array
+const UIO_MAXIOV: usize = 1024;
+const MSG_CTRUNC: u32 = 0x8;
+const MSG_TRUNC: u32 = 0x20;
+const MSG_CMSG_CLOEXEC: u32 = 0x40000000;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct MsgHdr {
+    name: *mut u8,
+    namelen: u32,
+    iov: *const IOVec,
+    iovlen: usize,
+    control: *mut u8,
+    controllen: usize,
+    flags: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CmsgHdr {
+    len: usize,
+    level: i32,
+    type_: i32,
+}
+
+const CMSG_HDR_LEN: usize = core::mem::size_of::<CmsgHdr>();
+
+/// Control messages are aligned to `usize`
+fn cmsg_align(len: usize) -> usize {
+    (len + core::mem::size_of::<usize>() - 1) & !(core::mem::size_of::<usize>() - 1)
+}
+
+/// Gather the buffers described by an iovec array
+fn iov_buffer(token: usize, iov: *const IOVec, iovlen: usize) -> Result<UserBuffer, isize> {
+    if iovlen > UIO_MAXIOV {
+        return Err(EMSGSIZE);
+    }
+    let mut iovecs = Vec::<IOVec>::with_capacity(iovlen);
+    copy_from_user_array(token, iov, iovecs.as_mut_ptr(), iovlen)?;
+    unsafe { iovecs.set_len(iovlen) };
+    let mut buffers = Vec::with_capacity(32);
+    for iovec in iovecs.iter() {
+        translated_byte_buffer_append_to_existing_vec(
+            &mut buffers,
+            token,
+            iovec.iov_base,
+            iovec.iov_len,
+        )?;
+    }
+    Ok(UserBuffer::new(buffers))
+}
+
+/// Collect the files passed by `SCM_RIGHTS` control messages
+fn parse_rights(token: usize, header: &MsgHdr) -> Result<Vec<FileDescriptor>, isize> {
+    let mut rights = Vec::new();
+    if header.control.is_null() {
+        return Ok(rights);
+    }
+    let mut offset = 0;
+    while header.controllen.saturating_sub(offset) >= CMSG_HDR_LEN {
+        let cmsg = get_from_user(token, (header.control as usize + offset) as *const CmsgHdr)?;
+        // `cmsg.len` comes from the user, it may wrap around
+        match offset.checked_add(cmsg.len) {
+            Some(end) if cmsg.len >= CMSG_HDR_LEN && end <= header.controllen => {}
+            _ => return Err(EINVAL),
+        }
+        if cmsg.level == SOL_SOCKET as i32 && cmsg.type_ == SCM_RIGHTS {
+            let count = (cmsg.len - CMSG_HDR_LEN) / core::mem::size_of::<i32>();
+            if rights.len() + count > SCM_MAX_FD {
+                return Err(EINVAL);
+            }
+            let mut fds = alloc::vec![0i32; count];
+            copy_from_user_array(
+                token,
+                (header.control as usize + offset + CMSG_HDR_LEN) as *const i32,
+                fds.as_mut_ptr(),
+                count,
+            )?;
+            let task = current_task().unwrap();
+            let fd_table = task.files.lock();
+            for fd in fds {
+                if fd < 0 {
+                    return Err(EBADF);
+                }
+                rights.push(fd_table.get_ref(fd as usize)?.clone());
+            }
+        }
+        offset += cmsg_align(cmsg.len);
+    }
+    Ok(rights)
+}
+
+/// Install the files received by `SCM_RIGHTS` and write the control message.
+/// # Return
+/// `controllen` to report and whether the control message is truncated
+fn deliver_rights(
+    token: usize,
+    header: &MsgHdr,
+    rights: Vec<FileDescriptor>,
+    cloexec: bool,
+) -> Result<(usize, bool), isize> {
+    if rights.is_empty() {
+        return Ok((0, false));
+    }
+    let room = if header.control.is_null() || header.controllen < CMSG_HDR_LEN {
+        0
+    } else {
+        (header.controllen - CMSG_HDR_LEN) / core::mem::size_of::<i32>()
+    };
+    let mut truncated = rights.len() > room;
+    let mut fds = Vec::with_capacity(room.min(rights.len()));
+    let task = current_task().unwrap();
+    let mut fd_table = task.files.lock();
+    // files that don't fit are closed
+    for file_descriptor in rights.into_iter().take(room) {
+        match fd_table.insert(FileDescriptor::new(
+            cloexec,
+            file_descriptor.get_nonblock(),
+            file_descriptor.file,
+        )) {
+            Ok(fd) => fds.push(fd as i32),
+            Err(_) => {
+                truncated = true;
+                break;
+            }
+        }
+    }
+    drop(fd_table);
+    if fds.is_empty() {
+        return Ok((0, truncated));
+    }
+    let len = CMSG_HDR_LEN + fds.len() * core::mem::size_of::<i32>();
+    let cmsg = CmsgHdr {
+        len,
+        level: SOL_SOCKET as i32,
+        type_: SCM_RIGHTS,
+    };
+    copy_to_user(token, &cmsg, header.control as *mut CmsgHdr)?;
+    copy_to_user_array(
+        token,
+        fds.as_ptr(),
+        (header.control as usize + CMSG_HDR_LEN) as *mut i32,
+        fds.len(),
+    )?;
+    Ok((cmsg_align(len).min(header.controllen), truncated))
+}
+
+pub fn sys_sendmsg(sockfd: usize, msg: *const MsgHdr, flags: u32) -> isize {
+    let (socket, nonblock) = match get_socket(sockfd) {
+        Ok(socket) => socket,
+        Err(errno) => return errno,
+    };
+    let token = current_user_token();
+    let header = match get_from_user(token, msg) {
+        Ok(header) => header,
+        Err(errno) => return errno,
+    };
+    let dest = if header.name.is_null() || header.namelen == 0 {
+        None
+    } else {
+        match SocketAddr::from_user(token, header.name, header.namelen) {
+            Ok(addr) => Some(addr),
+            Err(errno) => return errno,
+        }
+    };
+    let rights = match parse_rights(token, &header) {
+        Ok(rights) => rights,
+        Err(errno) => return errno,
+    };
+    let mut user_buf = match iov_buffer(token, header.iov, header.iovlen) {
+        Ok(buffer) => buffer,
+        Err(errno) => return errno,
+    };
+    // a stream socket never takes more than its buffer at once
+    let len = match socket.socket_type() {
+        SocketType::Stream => user_buf.len().min(SOCKET_BUFFER_SIZE),
+        SocketType::Datagram if user_buf.len() > SOCKET_BUFFER_SIZE => return EMSGSIZE,
+        SocketType::Datagram => user_buf.len(),
+    };
+    let mut kernel_buf = alloc::vec![0u8; len];
+    user_buf.read(&mut kernel_buf);
+    let nonblock = nonblock || (flags & MSG_DONTWAIT) != 0;
+    match socket.send_msg(&kernel_buf, dest, rights, nonblock) {
+        Ok(len) => len as isize,
+        Err(EPIPE) => {
+            if (flags & MSG_NOSIGNAL) == 0 {
+                send_sigpipe();
+            }
+            EPIPE
+        }
+        Err(errno) => errno,
+    }
+}
+pub fn sys_recvmsg(sockfd: usize, msg: *mut MsgHdr, flags: u32) -> isize {
+    let (socket, nonblock) = match get_socket(sockfd) {
+        Ok(socket) => socket,
+        Err(errno) => return errno,
+    };
+    let token = current_user_token();
+    let header = match get_from_user(token, msg as *const MsgHdr) {
+        Ok(header) => header,
+        Err(errno) => return errno,
+    };
+    let mut user_buf = match iov_buffer(token, header.iov, header.iovlen) {
+        Ok(buffer) => buffer,
+        Err(errno) => return errno,
+    };
+    let mut kernel_buf = alloc::vec![0u8; user_buf.len().min(SOCKET_BUFFER_SIZE)];
+    let nonblock = nonblock || (flags & MSG_DONTWAIT) != 0;
+    let message = match socket.recv_msg(&mut kernel_buf, (flags & MSG_PEEK) != 0, nonblock) {
+        Ok(message) => message,
+        Err(errno) => return errno,
+    };
+    user_buf.write(&kernel_buf[..message.len]);
+    let mut msg_flags = 0;
+    if message.full_len > message.len {
+        msg_flags |= MSG_TRUNC;
+    }
+    let namelen = unsafe { core::ptr::addr_of_mut!((*msg).namelen) };
+    match message.src {
+        Some(src) if !header.name.is_null() => {
+            if let Err(errno) = src.to_user(token, header.name, namelen) {
+                return errno;
+            }
+        }
+        _ => {
+            if let Err(errno) = copy_to_user(token, &0u32, namelen) {
+                return errno;
+            }
+        }
+    }
+    let (controllen, truncated) = match deliver_rights(
+        token,
+        &header,
+        message.rights,
+        (flags & MSG_CMSG_CLOEXEC) != 0,
+    ) {
+        Ok(result) => result,
+        Err(errno) => return errno,
+    };
+    if truncated {
+        msg_flags |= MSG_CTRUNC;
+    }
+    if let Err(errno) = copy_to_user(token, &controllen, unsafe {
+        core::ptr::addr_of_mut!((*msg).controllen)
+    }) {
+        return errno;
+    }
+    if let Err(errno) = copy_to_user(token, &msg_flags, unsafe {
+        core::ptr::addr_of_mut!((*msg).flags)
+    }) {
+        return errno;
+    }
+    // like Linux, MSG_TRUNC asks for the real length of a truncated datagram
+    if (flags & MSG_TRUNC) != 0 {
+        message.full_len as isize
+    } else {
+        message.len as isize
+    }
 }
@@ -0,0 +1,552 @@
+//! Executable formats known to `execve()`.
+//!
+//! The program is handed to each registered format in turn until one accepts it,
+//! a format rejects a program it doesn't know with `ENOEXEC`.
+//! Formats running the program through an interpreter (`#!` scripts and `binfmt_misc` entries)
+//! rewrite the arguments and name the interpreter, which is then looked up in the same way.
+//! `binfmt_misc` entries are registered by root through `/proc/sys/fs/binfmt_misc/register`.
+use super::cred::{current_cred, Credentials, MAY_EXEC};
+use super::TaskControlBlock;
+use crate::fs::{FileDescriptor, OpenFlags};
+use crate::syscall::errno::{EACCES, EEXIST, EINVAL, ELOOP, ENOENT, ENOEXEC, EPERM};
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::convert::TryInto;
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::*;
+use spin::Mutex;
+
+/// Bytes read from the head of a program, the `#!` line of a script must fit in
+pub const BINPRM_BUF_SIZE: usize = 256;
+/// Most interpreters a program may go through before reaching a binary
+const MAX_INTERP_DEPTH: usize = 4;
+
+/// A program being executed
+pub struct LinuxBinprm {
+    /// Directory relative paths are looked up from
+    cwd: Arc<FileDescriptor>,
+    /// Credentials the program and its interpreters are opened with
+    cred: Credentials,
+    /// Path `file` was opened by
+    pub filename: String,
+    pub file: FileDescriptor,
+    /// Head of `file`, at most `BINPRM_BUF_SIZE` bytes
+    pub buf: Vec<u8>,
+    pub argv: Vec<String>,
+    pub envp: Vec<String>,
+    /// Set by a format that runs the program through an interpreter
+    pub interpreter: Option<String>,
+}
+
+impl LinuxBinprm {
+    pub fn new(
+        cwd: Arc<FileDescriptor>,
+        cred: Credentials,
+        filename: String,
+        argv: Vec<String>,
+        envp: Vec<String>,
+    ) -> Result<Self, isize> {
+        let (file, buf) = open_exec(&cwd, &filename, &cred)?;
+        Ok(Self {
+            cwd,
+            cred,
+            filename,
+            file,
+            buf,
+            argv,
+            envp,
+            interpreter: None,
+        })
+    }
+    /// Put `args` before the arguments, after dropping `argv[0]` if `remove_arg0`
+    pub fn insert_args(&mut self, remove_arg0: bool, args: Vec<String>) {
+        if remove_arg0 && !self.argv.is_empty() {
+            self.argv.remove(0);
+        }
+        self.argv.splice(0..0, args);
+    }
+    fn open_interpreter(&mut self, path: String) -> Result<(), isize> {
+        let (file, buf) = open_exec(&self.cwd, &path, &self.cred)?;
+        self.filename = path;
+        self.file = file;
+        self.buf = buf;
+        Ok(())
+    }
+}
+
+/// Open the program at `path`, it must be a regular file `cred` may execute.
+/// The program is read by the kernel, so execute permission is enough.
+/// # Return
+/// The file and its first `BINPRM_BUF_SIZE` bytes
+fn open_exec(
+    cwd: &FileDescriptor,
+    path: &str,
+    cred: &Credentials,
+) -> Result<(FileDescriptor, Vec<u8>), isize> {
+    let file = cwd.open(path, OpenFlags::O_PATH, false)?;
+    if !file.file.is_file() {
+        return Err(EACCES);
+    }
+    file.check_access(cred, MAY_EXEC)?;
+    let mut buf = alloc::vec![0u8; BINPRM_BUF_SIZE];
+    let len = file.read(Some(&mut 0usize), &mut buf);
+    buf.truncate(len);
+    Ok((file, buf))
+}
+
+pub trait BinaryFormat: Send + Sync {
+    fn name(&self) -> &str;
+    /// Load `bprm` into `task`, or return `ENOEXEC` if it is not of this format.
+    /// A format running the program through an interpreter sets `bprm.interpreter` instead.
+    fn load(&self, task: &TaskControlBlock, bprm: &mut LinuxBinprm) -> Result<(), isize>;
+}
+
+lazy_static! {
+    /// Formats in the order they are tried
+    static ref FORMATS: Mutex<Vec<Arc<dyn BinaryFormat>>> = Mutex::new(alloc::vec![
+        Arc::new(MiscFormat) as Arc<dyn BinaryFormat>,
+        Arc::new(ElfFormat),
+        Arc::new(ScriptFormat),
+        Arc::new(FlatFormat),
+    ]);
+}
+
+/// Add `format`, it is tried before the registered ones if `insert`, or after them
+#[allow(unused)]
+pub fn register_binfmt(format: Arc<dyn BinaryFormat>, insert: bool) {
+    let mut formats = FORMATS.lock();
+    if insert {
+        formats.insert(0, format);
+    } else {
+        formats.push(format);
+    }
+}
+
+#[allow(unused)]
+pub fn unregister_binfmt(name: &str) {
+    FORMATS.lock().retain(|format| format.name() != name);
+}
+
+/// Load the program of `bprm` into `task`, going through the interpreters formats ask for
+pub fn exec_binprm(task: &TaskControlBlock, mut bprm: LinuxBinprm) -> Result<(), isize> {
+    for _ in 0..=MAX_INTERP_DEPTH {
+        // a format may load another program, don't hold the lock meanwhile
+        let formats = FORMATS.lock().clone();
+        let mut result = Err(ENOEXEC);
+        for format in formats.iter() {
+            result = format.load(task, &mut bprm);
+            if result != Err(ENOEXEC) {
+                break;
+            }
+        }
+        result?;
+        match bprm.interpreter.take() {
+            Some(interpreter) => bprm.open_interpreter(interpreter)?,
+            None => return Ok(()),
+        }
+    }
+    Err(ELOOP)
+}
+
+struct ElfFormat;
+
+impl BinaryFormat for ElfFormat {
+    fn name(&self) -> &str {
+        "elf"
+    }
+    fn load(&self, task: &TaskControlBlock, bprm: &mut LinuxBinprm) -> Result<(), isize> {
+        if !bprm.buf.starts_with(b"\x7fELF") {
+            return Err(ENOEXEC);
+        }
+        task.load_elf(bprm.file.clone(), &bprm.argv, &bprm.envp)
+    }
+}
+
+/// Scripts starting with `#!interpreter [arg]`, run as `interpreter [arg] path argv[1]...`
+struct ScriptFormat;
+
+impl BinaryFormat for ScriptFormat {
+    fn name(&self) -> &str {
+        "script"
+    }
+    fn load(&self, _task: &TaskControlBlock, bprm: &mut LinuxBinprm) -> Result<(), isize> {
+        if !bprm.buf.starts_with(b"#!") {
+            return Err(ENOEXEC);
+        }
+        let (interpreter, arg) = parse_shebang(&bprm.buf)?;
+        let mut args = alloc::vec![interpreter.clone()];
+        args.extend(arg);
+        args.push(bprm.filename.clone());
+        bprm.insert_args(true, args);
+        bprm.interpreter = Some(interpreter);
+        Ok(())
+    }
+}
+
+/// Parse the `#!` line at the head of a script in `buf`.
+/// The interpreter ends at the first blank, the rest of the line is one optional argument.
+/// # Return
+/// The interpreter and its argument,
+/// or `ENOEXEC` if there is no interpreter or it is cut by the end of `buf`.
+fn parse_shebang(buf: &[u8]) -> Result<(String, Option<String>), isize> {
+    let is_blank = |ch: char| ch == ' ' || ch == '\t';
+    let (line, complete) = match buf.iter().position(|ch| *ch == b'\n') {
+        Some(end) => (&buf[2..end], true),
+        None => (&buf[2..], buf.len() < BINPRM_BUF_SIZE),
+    };
+    let line = core::str::from_utf8(line).map_err(|_| ENOEXEC)?;
+    let line = line.trim_matches(is_blank);
+    let (interp, arg) = match line.find(is_blank) {
+        Some(pos) => (&line[..pos], Some(line[pos..].trim_start_matches(is_blank))),
+        None => (line, None),
+    };
+    // a cut argument is passed as is, like Linux
+    if interp.is_empty() || (!complete && arg.is_none()) {
+        return Err(ENOEXEC);
+    }
+    Ok((interp.to_string(), arg.map(|arg| arg.to_string())))
+}
+
+const FLAT_MAGIC: &[u8] = b"bFLT";
+const FLAT_VERSION: u32 = 4;
+const FLAT_HEADER_SIZE: usize = 64;
+/// GOT based position independent code
+const FLAT_FLAG_GOTPIC: u32 = 0x2;
+/// The image after the header is gzip compressed
+const FLAT_FLAG_GZIP: u32 = 0x4;
+/// The data segment is gzip compressed
+const FLAT_FLAG_GZDATA: u32 = 0x8;
+
+/// Header of a flat binary, in the layout of `bFLT` version 4 with big endian fields.
+/// The file is loaded as is at `FLAT_LOAD_BASE` and followed by zeroed bss,
+/// so all offsets are from the start of the file. Each relocation is the offset of
+/// a native `usize` holding an offset in the image, the load address is added to it.
+pub struct FlatHeader {
+    pub entry: usize,
+    pub data_start: usize,
+    pub data_end: usize,
+    pub bss_end: usize,
+    pub reloc_start: usize,
+    pub reloc_count: usize,
+}
+
+impl FlatHeader {
+    pub fn parse(buf: &[u8]) -> Result<Self, isize> {
+        if buf.len() < FLAT_HEADER_SIZE || !buf.starts_with(FLAT_MAGIC) {
+            return Err(ENOEXEC);
+        }
+        let field =
+            |index: usize| u32::from_be_bytes(buf[index * 4..index * 4 + 4].try_into().unwrap());
+        let flags = field(9);
+        if field(1) != FLAT_VERSION
+            || flags & (FLAT_FLAG_GOTPIC | FLAT_FLAG_GZIP | FLAT_FLAG_GZDATA) != 0
+        {
+            return Err(ENOEXEC);
+        }
+        let header = Self {
+            entry: field(2) as usize,
+            data_start: field(3) as usize,
+            data_end: field(4) as usize,
+            bss_end: field(5) as usize,
+            reloc_start: field(7) as usize,
+            reloc_count: field(8) as usize,
+        };
+        if header.entry < FLAT_HEADER_SIZE
+            || header.entry >= header.data_start
+            || header.data_start > header.data_end
+            || header.data_end > header.bss_end
+        {
+            return Err(ENOEXEC);
+        }
+        Ok(header)
+    }
+}
+
+/// Flat binaries for bare test payloads, see `FlatHeader`
+struct FlatFormat;
+
+impl BinaryFormat for FlatFormat {
+    fn name(&self) -> &str {
+        "flat"
+    }
+    fn load(&self, task: &TaskControlBlock, bprm: &mut LinuxBinprm) -> Result<(), isize> {
+        let header = FlatHeader::parse(&bprm.buf)?;
+        task.load_flat(bprm.file.clone(), &header, &bprm.argv, &bprm.envp)
+    }
+}
+
+#[derive(Clone)]
+enum MiscMatch {
+    /// Bytes at `offset` of the file, compared under `mask` if there is one
+    Magic {
+        offset: usize,
+        magic: Vec<u8>,
+        mask: Option<Vec<u8>>,
+    },
+    /// Extension of the path, without the dot
+    Extension(String),
+}
+
+/// A `binfmt_misc` entry, registered by writing `:name:type:offset:magic:mask:interpreter:flags`
+#[derive(Clone)]
+struct MiscEntry {
+    name: String,
+    enabled: bool,
+    pattern: MiscMatch,
+    interpreter: String,
+    /// Flag `P`, the interpreter gets the original `argv[0]` after the path
+    preserve_argv0: bool,
+}
+
+impl MiscEntry {
+    fn parse(line: &str) -> Result<Self, isize> {
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        let delimiter = line.chars().next().ok_or(EINVAL)?;
+        let fields: Vec<&str> = line[delimiter.len_utf8()..].split(delimiter).collect();
+        let (name, kind, offset, magic, mask, interpreter, flags) = match fields[..] {
+            [name, kind, offset, magic, mask, interpreter] => {
+                (name, kind, offset, magic, mask, interpreter, "")
+            }
+            [name, kind, offset, magic, mask, interpreter, flags] => {
+                (name, kind, offset, magic, mask, interpreter, flags)
+            }
+            _ => return Err(EINVAL),
+        };
+        if name.is_empty()
+            || name.contains('/')
+            || [".", "..", "register", "status"].contains(&name)
+        {
+            return Err(EINVAL);
+        }
+        let pattern = match kind {
+            "M" => {
+                let offset = if offset.is_empty() {
+                    0
+                } else {
+                    offset.parse::<usize>().map_err(|_| EINVAL)?
+                };
+                let magic = unescape(magic)?;
+                let mask = if mask.is_empty() {
+                    None
+                } else {
+                    Some(unescape(mask)?)
+                };
+                if magic.is_empty()
+                    || offset + magic.len() > BINPRM_BUF_SIZE
+                    || matches!(&mask, Some(mask) if mask.len() != magic.len())
+                {
+                    return Err(EINVAL);
+                }
+                MiscMatch::Magic {
+                    offset,
+                    magic,
+                    mask,
+                }
+            }
+            "E" => {
+                if !offset.is_empty() || !mask.is_empty() || magic.is_empty() || magic.contains('/')
+                {
+                    return Err(EINVAL);
+                }
+                MiscMatch::Extension(magic.to_string())
+            }
+            _ => return Err(EINVAL),
+        };
+        if interpreter.is_empty() {
+            return Err(EINVAL);
+        }
+        let mut preserve_argv0 = false;
+        for flag in flags.chars() {
+            match flag {
+                'P' => preserve_argv0 = true,
+                _ => return Err(EINVAL),
+            }
+        }
+        Ok(Self {
+            name: name.to_string(),
+            enabled: true,
+            pattern,
+            interpreter: interpreter.to_string(),
+            preserve_argv0,
+        })
+    }
+    fn matches(&self, bprm: &LinuxBinprm) -> bool {
+        match &self.pattern {
+            MiscMatch::Magic {
+                offset,
+                magic,
+                mask,
+            } => match bprm.buf.get(*offset..*offset + magic.len()) {
+                Some(head) => head
+                    .iter()
+                    .zip(magic)
+                    .enumerate()
+                    .all(|(i, (byte, magic))| {
+                        let mask = mask.as_ref().map_or(0xff, |mask| mask[i]);
+                        byte & mask == *magic & mask
+                    }),
+                None => false,
+            },
+            MiscMatch::Extension(extension) => match bprm.filename.rfind('.') {
+                Some(dot) => bprm.filename[dot + 1..] == *extension,
+                None => false,
+            },
+        }
+    }
+    /// Content of the file of this entry under `/proc/sys/fs/binfmt_misc`
+    fn status(&self) -> String {
+        let mut status = format!(
+            "{}\ninterpreter {}\nflags: {}\n",
+            if self.enabled { "enabled" } else { "disabled" },
+            self.interpreter,
+            if self.preserve_argv0 { "P" } else { "" }
+        );
+        let hex = |bytes: &[u8]| -> String { bytes.iter().map(|b| format!("{:02x}", b)).collect() };
+        match &self.pattern {
+            MiscMatch::Magic {
+                offset,
+                magic,
+                mask,
+            } => {
+                status += &format!("offset {}\nmagic {}\n", offset, hex(magic));
+                if let Some(mask) = mask {
+                    status += &format!("mask {}\n", hex(mask));
+                }
+            }
+            MiscMatch::Extension(extension) => status += &format!("extension .{}\n", extension),
+        }
+        status
+    }
+}
+
+/// Decode the `\xHH` and `\\` escapes of a magic or mask
+fn unescape(field: &str) -> Result<Vec<u8>, isize> {
+    let bytes = field.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match &bytes[i..] {
+            [b'\\', b'x', ..] => {
+                let hex = field.get(i + 2..i + 4).ok_or(EINVAL)?;
+                result.push(u8::from_str_radix(hex, 16).map_err(|_| EINVAL)?);
+                i += 4;
+            }
+            [b'\\', b'\\', ..] => {
+                result.push(b'\\');
+                i += 2;
+            }
+            [byte, ..] => {
+                result.push(*byte);
+                i += 1;
+            }
+            [] => unreachable!(),
+        }
+    }
+    Ok(result)
+}
+
+static MISC_ENABLED: AtomicBool = AtomicBool::new(true);
+
+lazy_static! {
+    static ref MISC_ENTRIES: Mutex<Vec<MiscEntry>> = Mutex::new(Vec::new());
+}
+
+/// Programs matching a `binfmt_misc` entry, run as `interpreter path argv[1]...`
+struct MiscFormat;
+
+impl BinaryFormat for MiscFormat {
+    fn name(&self) -> &str {
+        "misc"
+    }
+    fn load(&self, _task: &TaskControlBlock, bprm: &mut LinuxBinprm) -> Result<(), isize> {
+        if !MISC_ENABLED.load(Ordering::Relaxed) {
+            return Err(ENOEXEC);
+        }
+        let entry = MISC_ENTRIES
+            .lock()
+            .iter()
+            .find(|entry| entry.enabled && entry.matches(bprm))
+            .cloned()
+            .ok_or(ENOEXEC)?;
+        let args = alloc::vec![entry.interpreter.clone(), bprm.filename.clone()];
+        bprm.insert_args(!entry.preserve_argv0, args);
+        bprm.interpreter = Some(entry.interpreter);
+        Ok(())
+    }
+}
+
+/// Entries change how every program is executed, only root may touch them
+fn check_misc_privilege() -> Result<(), isize> {
+    match current_cred() {
+        Some(cred) if !cred.is_privileged() => Err(EPERM),
+        _ => Ok(()),
+    }
+}
+
+/// Register the entry written to `/proc/sys/fs/binfmt_misc/register`
+pub fn misc_register(buf: &[u8]) -> Result<(), isize> {
+    check_misc_privilege()?;
+    let entry = MiscEntry::parse(core::str::from_utf8(buf).map_err(|_| EINVAL)?)?;
+    let mut entries = MISC_ENTRIES.lock();
+    if entries.iter().any(|old| old.name == entry.name) {
+        return Err(EEXIST);
+    }
+    entries.push(entry);
+    Ok(())
+}
+
+/// Names of the registered entries
+pub fn misc_names() -> Vec<String> {
+    MISC_ENTRIES
+        .lock()
+        .iter()
+        .map(|entry| entry.name.clone())
+        .collect()
+}
+
+/// Content of `/proc/sys/fs/binfmt_misc/status` if `name` is `None`,
+/// or of the file of entry `name`, which is empty if the entry has gone
+pub fn misc_status(name: Option<&str>) -> String {
+    match name {
+        None if MISC_ENABLED.load(Ordering::Relaxed) => "enabled\n".to_string(),
+        None => "disabled\n".to_string(),
+        Some(name) => MISC_ENTRIES
+            .lock()
+            .iter()
+            .find(|entry| entry.name == name)
+            .map_or_else(String::new, MiscEntry::status),
+    }
+}
+
+/// Handle a write to `status` if `name` is `None`, or to the file of entry `name`:
+/// `1` enables, `0` disables and `-1` removes all entries or the entry
+pub fn misc_control(name: Option<&str>, buf: &[u8]) -> Result<(), isize> {
+    check_misc_privilege()?;
+    let command = core::str::from_utf8(buf).map_err(|_| EINVAL)?.trim_end();
+    let mut entries = MISC_ENTRIES.lock();
+    let index = match name {
+        Some(name) => Some(
+            entries
+                .iter()
+                .position(|entry| entry.name == name)
+                .ok_or(ENOENT)?,
+        ),
+        None => None,
+    };
+    match (command, index) {
+        ("1", None) => MISC_ENABLED.store(true, Ordering::Relaxed),
+        ("0", None) => MISC_ENABLED.store(false, Ordering::Relaxed),
+        ("-1", None) => entries.clear(),
+        ("1", Some(index)) => entries[index].enabled = true,
+        ("0", Some(index)) => entries[index].enabled = false,
+        ("-1", Some(index)) => {
+            entries.remove(index);
+        }
+        _ => return Err(EINVAL),
+    }
+    Ok(())
+}
@@ -0,0 +1,237 @@
+//! User and group credentials.
+//!
+//! Credentials belong to a process: they are shared by its threads, copied by `fork()`
+//! and changed by `execve()` of a set-user-ID or set-group-ID program.
+//! `initproc` runs as root, an unprivileged user is one whose effective user ID is not 0.
+use super::current_task;
+use crate::fs::StatMode;
+use crate::syscall::errno::{EINVAL, EPERM};
+use alloc::vec::Vec;
+
+/// Access bits asked by `permission()`, the same as the `mode` of `access()`
+pub const MAY_EXEC: u32 = 1;
+pub const MAY_WRITE: u32 = 2;
+pub const MAY_READ: u32 = 4;
+
+/// Most supplementary groups `setgroups()` accepts
+pub const NGROUPS_MAX: usize = 65536;
+
+/// An ID argument of `setreuid()` and friends that leaves the ID unchanged
+const KEEP: u32 = u32::MAX;
+
+/// Credentials of the current task, `None` before `initproc` runs
+pub fn current_cred() -> Option<Credentials> {
+    current_task().map(|task| task.cred.lock().clone())
+}
+
+#[derive(Clone, Debug)]
+pub struct Credentials {
+    /// Real user ID
+    pub uid: u32,
+    /// Effective user ID, used for permission checks
+    pub euid: u32,
+    /// Saved set-user-ID
+    pub suid: u32,
+    /// Real group ID
+    pub gid: u32,
+    /// Effective group ID, used for permission checks
+    pub egid: u32,
+    /// Saved set-group-ID
+    pub sgid: u32,
+    /// Supplementary group IDs
+    pub groups: Vec<u32>,
+}
+
+impl Credentials {
+    /// Credentials of `initproc`
+    pub fn root() -> Self {
+        Self {
+            uid: 0,
+            euid: 0,
+            suid: 0,
+            gid: 0,
+            egid: 0,
+            sgid: 0,
+            groups: Vec::new(),
+        }
+    }
+    /// Whether the task may bypass permission checks, there are no capabilities
+    pub fn is_privileged(&self) -> bool {
+        self.euid == 0
+    }
+    /// Whether `gid` is the effective group ID or one of the supplementary groups
+    pub fn in_group(&self, gid: u32) -> bool {
+        self.egid == gid || self.groups.contains(&gid)
+    }
+    /// Whether a file of `mode` owned by `uid` and `gid` grants all of `access`,
+    /// which is made of `MAY_READ`, `MAY_WRITE` and `MAY_EXEC`.
+    /// Root may do anything except executing a regular file no one may execute.
+    pub fn permission(&self, mode: u32, uid: u32, gid: u32, access: u32) -> bool {
+        if self.is_privileged() {
+            let is_dir = mode & StatMode::S_IFMT.bits() == StatMode::S_IFDIR.bits();
+            return access & MAY_EXEC == 0 || is_dir || mode & 0o111 != 0;
+        }
+        let granted = if self.euid == uid {
+            mode >> 6
+        } else if self.in_group(gid) {
+            mode >> 3
+        } else {
+            mode
+        } & 0o7;
+        access & !granted == 0
+    }
+    /// Whether a task with these credentials may send a signal to one with `target`,
+    /// the real or effective user ID of the sender must be the real or saved one of the target
+    pub fn may_signal(&self, target: &Credentials) -> bool {
+        self.is_privileged()
+            || [self.uid, self.euid]
+                .iter()
+                .any(|uid| *uid == target.uid || *uid == target.suid)
+    }
+    /// Whether a task with these credentials may change the scheduling of one with `target`,
+    /// the effective user ID of the caller must be the real or effective one of the target
+    pub fn may_reschedule(&self, target: &Credentials) -> bool {
+        self.is_privileged() || self.euid == target.uid || self.euid == target.euid
+    }
+    /// `setuid()`, root sets all three IDs, others may only set the effective one to the real or saved one
+    pub fn setuid(&mut self, uid: u32) -> Result<(), isize> {
+        if uid == KEEP {
+            return Err(EINVAL);
+        }
+        if self.is_privileged() {
+            self.uid = uid;
+            self.suid = uid;
+        } else if uid != self.uid && uid != self.suid {
+            return Err(EPERM);
+        }
+        self.euid = uid;
+        Ok(())
+    }
+    /// `setreuid()`, the saved ID follows the effective one if the real ID is set,
+    /// or the effective one is set to other than the old real ID
+    pub fn setreuid(&mut self, ruid: u32, euid: u32) -> Result<(), isize> {
+        let old_uid = self.uid;
+        if !self.is_privileged() {
+            if ruid != KEEP && ruid != self.uid && ruid != self.euid {
+                return Err(EPERM);
+            }
+            if euid != KEEP && euid != self.uid && euid != self.euid && euid != self.suid {
+                return Err(EPERM);
+            }
+        }
+        if ruid != KEEP {
+            self.uid = ruid;
+        }
+        if euid != KEEP {
+            self.euid = euid;
+        }
+        if ruid != KEEP || (euid != KEEP && euid != old_uid) {
+            self.suid = self.euid;
+        }
+        Ok(())
+    }
+    /// `setresuid()`, an unprivileged task may set each ID to any of its current three
+    pub fn setresuid(&mut self, ruid: u32, euid: u32, suid: u32) -> Result<(), isize> {
+        let ids = [self.uid, self.euid, self.suid];
+        if !self.is_privileged()
+            && [ruid, euid, suid]
+                .iter()
+                .any(|id| *id != KEEP && !ids.contains(id))
+        {
+            return Err(EPERM);
+        }
+        if ruid != KEEP {
+            self.uid = ruid;
+        }
+        if euid != KEEP {
+            self.euid = euid;
+        }
+        if suid != KEEP {
+            self.suid = suid;
+        }
+        Ok(())
+    }
+    /// `setgid()`, the same rules as `setuid()`
+    pub fn setgid(&mut self, gid: u32) -> Result<(), isize> {
+        if gid == KEEP {
+            return Err(EINVAL);
+        }
+        if self.is_privileged() {
+            self.gid = gid;
+            self.sgid = gid;
+        } else if gid != self.gid && gid != self.sgid {
+            return Err(EPERM);
+        }
+        self.egid = gid;
+        Ok(())
+    }
+    /// `setregid()`, the same rules as `setreuid()`
+    pub fn setregid(&mut self, rgid: u32, egid: u32) -> Result<(), isize> {
+        let old_gid = self.gid;
+        if !self.is_privileged() {
+            if rgid != KEEP && rgid != self.gid && rgid != self.egid {
+                return Err(EPERM);
+            }
+            if egid != KEEP && egid != self.gid && egid != self.egid && egid != self.sgid {
+                return Err(EPERM);
+            }
+        }
+        if rgid != KEEP {
+            self.gid = rgid;
+        }
+        if egid != KEEP {
+            self.egid = egid;
+        }
+        if rgid != KEEP || (egid != KEEP && egid != old_gid) {
+            self.sgid = self.egid;
+        }
+        Ok(())
+    }
+    /// `setresgid()`, the same rules as `setresuid()`
+    pub fn setresgid(&mut self, rgid: u32, egid: u32, sgid: u32) -> Result<(), isize> {
+        let ids = [self.gid, self.egid, self.sgid];
+        if !self.is_privileged()
+            && [rgid, egid, sgid]
+                .iter()
+                .any(|id| *id != KEEP && !ids.contains(id))
+        {
+            return Err(EPERM);
+        }
+        if rgid != KEEP {
+            self.gid = rgid;
+        }
+        if egid != KEEP {
+            self.egid = egid;
+        }
+        if sgid != KEEP {
+            self.sgid = sgid;
+        }
+        Ok(())
+    }
+    /// `setgroups()`, only root may change the supplementary groups
+    pub fn setgroups(&mut self, groups: Vec<u32>) -> Result<(), isize> {
+        if !self.is_privileged() {
+            return Err(EPERM);
+        }
+        if groups.len() > NGROUPS_MAX {
+            return Err(EINVAL);
+        }
+        self.groups = groups;
+        Ok(())
+    }
+    /// Apply the set-user-ID and set-group-ID bits of the program being executed,
+    /// the saved IDs become the new effective ones.
+    /// Returns whether the effective IDs differ from the real ones, which is `AT_SECURE`.
+    pub fn exec(&mut self, mode: u32, uid: u32, gid: u32) -> bool {
+        if mode & StatMode::S_ISUID.bits() != 0 {
+            self.euid = uid;
+        }
+        // set-group-ID without group execute marks mandatory locking instead
+        if mode & (StatMode::S_ISGID.bits() | 0o010) == StatMode::S_ISGID.bits() | 0o010 {
+            self.egid = gid;
+        }
+        self.suid = self.euid;
+        self.sgid = self.egid;
+        self.euid != self.uid || self.egid != self.gid
+    }
+}
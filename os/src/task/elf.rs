@@ -97,7 +97,7 @@ pub fn load_elf_interp(path: &str) -> Result<&'static [u8], isize> {
                     let buffer = unsafe {
                         core::slice::from_raw_parts_mut(buffer_addr.0 as *mut u8, file.get_size())
                     };
-                    let caches = file.get_all_caches().unwrap();
+                    let caches = file.get_all_caches().map_err(|_| EIO)?;
                     let frames = caches
                         .iter()
                         .map(|cache| Frame::InMemory(cache.try_lock().unwrap().get_tracker()))
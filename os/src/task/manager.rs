@@ -3,19 +3,26 @@ use core::cmp::Ordering;
 #[cfg(feature = "oom_handler")]
 use crate::config::SYSTEM_TASK_LIMIT;
 #[cfg(feature = "oom_handler")]
-use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
 
+use crate::arch::{cpu_id, send_ipi_others, IpiAction};
+use crate::config::MAX_CPU_NUM;
 use crate::timer::TimeSpec;
 
-use super::{current_task, TaskControlBlock};
+use super::processor::{find_running_task, running_tasks};
+use super::sched::{RunQueue, Scheduler};
+use super::{current_task, Signals, TaskControlBlock};
 use alloc::collections::{BinaryHeap, VecDeque};
 use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
 use lazy_static::*;
 use spin::Mutex;
 
+/// Marks tasks that have run since the last oom clean.
+/// Atomic, so that `fetch_task()` can update it without `TASK_MANAGER`.
 #[cfg(feature = "oom_handler")]
 pub struct ActiveTracker {
-    bitmap: Vec<u64>,
+    bitmap: Vec<AtomicU64>,
 }
 
 #[cfg(feature = "oom_handler")]
@@ -24,70 +31,39 @@ impl ActiveTracker {
     pub const DEFAULT_SIZE: usize = SYSTEM_TASK_LIMIT;
     pub fn new() -> Self {
         let len = (Self::DEFAULT_SIZE + 63) / 64;
-        let mut bitmap = Vec::with_capacity(len);
-        bitmap.resize(len, 0);
+        let bitmap = (0..len).map(|_| AtomicU64::new(0)).collect();
         Self { bitmap }
     }
     pub fn check_active(&self, pid: usize) -> bool {
-        (self.bitmap[pid / 64] & (1 << (pid % 64))) != 0
+        (self.bitmap[pid / 64].load(AtomicOrdering::Relaxed) & (1 << (pid % 64))) != 0
     }
     pub fn check_inactive(&self, pid: usize) -> bool {
-        (self.bitmap[pid / 64] & (1 << (pid % 64))) == 0
+        (self.bitmap[pid / 64].load(AtomicOrdering::Relaxed) & (1 << (pid % 64))) == 0
     }
-    pub fn mark_active(&mut self, pid: usize) {
-        self.bitmap[pid / 64] |= 1 << (pid % 64)
+    pub fn mark_active(&self, pid: usize) {
+        self.bitmap[pid / 64].fetch_or(1 << (pid % 64), AtomicOrdering::Relaxed);
     }
-    pub fn mark_inactive(&mut self, pid: usize) {
-        self.bitmap[pid / 64] &= !(1 << (pid % 64))
+    pub fn mark_inactive(&self, pid: usize) {
+        self.bitmap[pid / 64].fetch_and(!(1 << (pid % 64)), AtomicOrdering::Relaxed);
     }
 }
 
-#[cfg(feature = "oom_handler")]
+/// Keeps the sleeping tasks. Ready tasks live in the per-CPU `RUN_QUEUES`,
+/// so scheduling on one core doesn't contend on this lock.
+/// # Lock Order
+/// `TASK_MANAGER` -> `PROCESSORS` -> `RUN_QUEUES`, never the other way around.
 pub struct TaskManager {
-    pub ready_queue: VecDeque<Arc<TaskControlBlock>>,
     pub interruptible_queue: VecDeque<Arc<TaskControlBlock>>,
-    pub active_tracker: ActiveTracker,
 }
 
-#[cfg(not(feature = "oom_handler"))]
-pub struct TaskManager {
-    pub ready_queue: VecDeque<Arc<TaskControlBlock>>,
-    pub interruptible_queue: VecDeque<Arc<TaskControlBlock>>,
-}
-
-/// A simple FIFO scheduler.
 impl TaskManager {
-    #[cfg(feature = "oom_handler")]
     pub fn new() -> Self {
         Self {
-            ready_queue: VecDeque::new(),
-            interruptible_queue: VecDeque::new(),
-            active_tracker: ActiveTracker::new(),
-        }
-    }
-    #[cfg(not(feature = "oom_handler"))]
-    pub fn new() -> Self {
-        Self {
-            ready_queue: VecDeque::new(),
             interruptible_queue: VecDeque::new(),
         }
     }
     pub fn add(&mut self, task: Arc<TaskControlBlock>) {
-        self.ready_queue.push_back(task);
-    }
-    #[cfg(feature = "oom_handler")]
-    pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
-        match self.ready_queue.pop_front() {
-            Some(task) => {
-                self.active_tracker.mark_active(task.pid.0);
-                Some(task)
-            }
-            None => None,
-        }
-    }
-    #[cfg(not(feature = "oom_handler"))]
-    pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
-        self.ready_queue.pop_front()
+        add_task(task);
     }
     pub fn add_interruptible(&mut self, task: Arc<TaskControlBlock>) {
         self.interruptible_queue.push_back(task);
@@ -96,22 +72,61 @@ impl TaskManager {
         self.interruptible_queue
             .retain(|task_in_queue| Arc::as_ptr(task_in_queue) != Arc::as_ptr(task));
     }
-    pub fn find_by_pid(&self, pid: usize) -> Option<Arc<TaskControlBlock>> {
-        self.ready_queue
+    /// Find a task that is ready, sleeping or running on another core.
+    fn find(&self, pred: impl Fn(&Arc<TaskControlBlock>) -> bool) -> Option<Arc<TaskControlBlock>> {
+        self.interruptible_queue
             .iter()
-            .chain(self.interruptible_queue.iter())
-            .find(|task| task.pid.0 == pid)
+            .find(|task| pred(task))
             .cloned()
+            .or_else(|| find_running_task(&pred))
+            .or_else(|| {
+                RUN_QUEUES
+                    .iter()
+                    .find_map(|queue| queue.lock().iter().find(|task| pred(task)).cloned())
+            })
+    }
+    /// Take all ready or sleeping threads of `tgid` out of the scheduler.
+    /// Threads of `tgid` running on other cores are sent `SIGKILL` and forced to trap,
+    /// they will exit by themselves.
+    pub fn take_thread_group(&mut self, tgid: usize) -> Vec<Arc<TaskControlBlock>> {
+        let mut group: Vec<Arc<TaskControlBlock>> = Vec::new();
+        for queue in RUN_QUEUES.iter() {
+            let mut queue = queue.lock();
+            group.extend(queue.iter().filter(|task| task.tgid == tgid).cloned());
+            queue.retain(&mut |task| task.tgid != tgid);
+        }
+        group.extend(
+            self.interruptible_queue
+                .iter()
+                .filter(|task| task.tgid == tgid)
+                .cloned(),
+        );
+        self.interruptible_queue.retain(|task| task.tgid != tgid);
+        let current_pid = current_task().map(|task| task.pid.0);
+        let mut kick = false;
+        for task in running_tasks()
+            .into_iter()
+            .filter(|task| task.tgid == tgid && Some(task.pid.0) != current_pid)
+        {
+            task.acquire_inner_lock().add_signal(Signals::SIGKILL);
+            kick = true;
+        }
+        if kick {
+            send_ipi_others(IpiAction::Kick);
+        }
+        group
+    }
+    pub fn find_by_pid(&self, pid: usize) -> Option<Arc<TaskControlBlock>> {
+        self.find(|task| task.pid.0 == pid)
     }
     pub fn find_by_tgid(&self, tgid: usize) -> Option<Arc<TaskControlBlock>> {
-        self.ready_queue
-            .iter()
-            .chain(self.interruptible_queue.iter())
-            .find(|task| task.tgid == tgid)
-            .cloned()
+        self.find(|task| task.tgid == tgid)
     }
     pub fn ready_count(&self) -> u16 {
-        self.ready_queue.len() as u16
+        RUN_QUEUES
+            .iter()
+            .map(|queue| queue.lock().len() as u16)
+            .sum()
     }
     pub fn interruptible_count(&self) -> u16 {
         self.interruptible_queue.len() as u16
@@ -147,8 +162,10 @@ impl TaskManager {
     #[allow(unused)]
     // debug use only
     pub fn show_ready(&self) {
-        self.ready_queue.iter().for_each(|task| {
-            log::error!("[show_ready] pid: {}", task.pid.0);
+        RUN_QUEUES.iter().enumerate().for_each(|(cpu, queue)| {
+            queue.lock().iter().for_each(|task| {
+                log::error!("[show_ready] cpu: {}, pid: {}", cpu, task.pid.0);
+            })
         })
     }
     #[allow(unused)]
@@ -162,26 +179,66 @@ impl TaskManager {
 
 lazy_static! {
     pub static ref TASK_MANAGER: Mutex<TaskManager> = Mutex::new(TaskManager::new());
+    /// Ready tasks of each core, indexed by `cpu_id()`.
+    pub static ref RUN_QUEUES: Vec<Mutex<RunQueue>> =
+        (0..MAX_CPU_NUM).map(|_| Mutex::new(RunQueue::new())).collect();
+}
+
+#[cfg(feature = "oom_handler")]
+lazy_static! {
+    pub static ref ACTIVE_TRACKER: ActiveTracker = ActiveTracker::new();
 }
 
+/// Push `task` to the run queue of the current core, idle cores will steal it if needed.
 pub fn add_task(task: Arc<TaskControlBlock>) {
-    TASK_MANAGER.lock().add(task);
+    RUN_QUEUES[cpu_id()].lock().push(task);
 }
 
 pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
-    TASK_MANAGER.lock().fetch()
+    let task = RUN_QUEUES[cpu_id()].lock().pop().or_else(steal_task);
+    #[cfg(feature = "oom_handler")]
+    if let Some(task) = task.as_ref() {
+        ACTIVE_TRACKER.mark_active(task.pid.0);
+    }
+    task
+}
+
+/// Take the most deserving task from the longest run queue of the other cores.
+fn steal_task() -> Option<Arc<TaskControlBlock>> {
+    let cpu = cpu_id();
+    let victim = (0..MAX_CPU_NUM)
+        .filter(|other| *other != cpu)
+        .max_by_key(|other| RUN_QUEUES[*other].lock().len())?;
+    RUN_QUEUES[victim].lock().pop()
+}
+
+/// Put `task` back to the run queue holding it, so that a new policy or priority takes effect.
+pub fn requeue_task(task: &Arc<TaskControlBlock>) {
+    for queue in RUN_QUEUES.iter() {
+        let mut queue = queue.lock();
+        let mut found = false;
+        queue.retain(&mut |task_in_queue| {
+            let same = Arc::ptr_eq(task_in_queue, task);
+            found |= same;
+            !same
+        });
+        if found {
+            queue.push(task.clone());
+            return;
+        }
+    }
 }
 
 /// Try to clean all tasks' memory space until `req` pages are released.
 #[cfg(feature = "oom_handler")]
 pub fn do_oom(req: usize) -> Result<(), ()> {
-    let mut manager = TASK_MANAGER.lock();
+    let manager = TASK_MANAGER.lock();
     let mut cleaned = Vec::with_capacity(16);
     let mut total_released = 0;
     for task in manager
         .interruptible_queue
         .iter()
-        .filter(|task| manager.active_tracker.check_active(task.pid.0))
+        .filter(|task| ACTIVE_TRACKER.check_active(task.pid.0))
     {
         let released = task.vm.lock().do_deep_clean();
         log::warn!("deep clean on task: {}, released: {}", task.tgid, released);
@@ -189,31 +246,32 @@ pub fn do_oom(req: usize) -> Result<(), ()> {
         total_released += released;
         if total_released >= req {
             while let Some(pid) = cleaned.pop() {
-                manager.active_tracker.mark_inactive(pid)
+                ACTIVE_TRACKER.mark_inactive(pid)
             }
             return Ok(());
         };
     }
-    for task in manager
-        .ready_queue
-        .iter()
-        .rev()
-        .filter(|task| manager.active_tracker.check_active(task.pid.0))
-    {
-        let released = task.vm.lock().do_shallow_clean();
-        log::warn!(
-            "shallow clean on task: {}, released: {}",
-            task.tgid,
-            released
-        );
-        cleaned.push(task.pid.0);
-        total_released += released;
-        if total_released >= req {
-            while let Some(pid) = cleaned.pop() {
-                manager.active_tracker.mark_inactive(pid)
-            }
-            return Ok(());
-        };
+    for queue in RUN_QUEUES.iter() {
+        for task in queue
+            .lock()
+            .iter()
+            .filter(|task| ACTIVE_TRACKER.check_active(task.pid.0))
+        {
+            let released = task.vm.lock().do_shallow_clean();
+            log::warn!(
+                "shallow clean on task: {}, released: {}",
+                task.tgid,
+                released
+            );
+            cleaned.push(task.pid.0);
+            total_released += released;
+            if total_released >= req {
+                while let Some(pid) = cleaned.pop() {
+                    ACTIVE_TRACKER.mark_inactive(pid)
+                }
+                return Ok(());
+            };
+        }
     }
     Err(())
 }
@@ -266,6 +324,17 @@ pub fn find_task_by_tgid(tgid: usize) -> Option<Arc<TaskControlBlock>> {
     }
 }
 
+/// Snapshot of every task alive, including the current one.
+pub fn all_tasks() -> Vec<Arc<TaskControlBlock>> {
+    let manager = TASK_MANAGER.lock();
+    let mut tasks = running_tasks();
+    tasks.extend(manager.interruptible_queue.iter().cloned());
+    for queue in RUN_QUEUES.iter() {
+        tasks.extend(queue.lock().iter().cloned());
+    }
+    tasks
+}
+
 pub fn procs_count() -> u16 {
     let manager = TASK_MANAGER.lock();
     manager.ready_count() + manager.interruptible_count()
@@ -275,8 +344,11 @@ pub enum WaitQueueError {
     AlreadyWaken,
 }
 
+#[derive(Default)]
 pub struct WaitQueue {
     inner: VecDeque<Weak<TaskControlBlock>>,
+    /// Bumped by every wakeup, even if nobody is waiting
+    generation: usize,
 }
 
 #[allow(unused)]
@@ -284,8 +356,13 @@ impl WaitQueue {
     pub fn new() -> Self {
         Self {
             inner: VecDeque::new(),
+            generation: 0,
         }
     }
+    /// Number of wakeups so far, tells if anything happened since it was last read
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
     /// This function add a `task` to `WaitQueue` but **won't** block it,
     /// if you want to block a `task`, use `block_current_and_run_next()`.
     pub fn add_task(&mut self, task: Weak<TaskControlBlock>) {
@@ -301,6 +378,11 @@ impl WaitQueue {
             .iter()
             .any(|task_in_queue| Weak::as_ptr(task_in_queue) == Weak::as_ptr(task))
     }
+    /// Remove `task` from the `WaitQueue`, waiters should do it once they stop waiting
+    pub fn remove(&mut self, task: &Weak<TaskControlBlock>) {
+        self.inner
+            .retain(|task_in_queue| Weak::as_ptr(task_in_queue) != Weak::as_ptr(task));
+    }
     /// Returns `true` if the `WaitQueue` is empty
     pub fn is_empty(&self) -> bool {
         self.inner.is_empty()
@@ -314,28 +396,30 @@ impl WaitQueue {
     }
     /// Wake up a number of `task` no more than `limit` in this `WaitQueue`. Returns the number of
     /// waken `task`.
+    /// A `task` which is not sleeping yet is marked by `wake_up()`, so that it won't block on
+    /// the condition it is about to wait for.
     /// # Warning
     /// This function will call `acquire_inner_lock` for each waken `task`, so be careful of **deadlock**.
     pub fn wake_at_most(&mut self, limit: usize) -> usize {
-        if limit == 0 {
+        self.generation = self.generation.wrapping_add(1);
+        if limit == 0 || self.inner.is_empty() {
             return 0;
         }
         let mut manager = TASK_MANAGER.lock();
         let mut cnt = 0;
         while let Some(task) = self.inner.pop_front() {
             match task.upgrade() {
-                Some(task) => {
-                    let mut inner = task.acquire_inner_lock();
-                    match inner.task_status {
-                        super::TaskStatus::Interruptible => {
-                            inner.task_status = super::task::TaskStatus::Ready
+                Some(strong) => {
+                    let mut inner = strong.acquire_inner_lock();
+                    let running = inner.task_status == super::TaskStatus::Running;
+                    // for `Ready`, it will check its condition anyway,
+                    // for `Zombie`, we will mess up the process management if we do wake...
+                    if inner.wake_up() {
+                        drop(inner);
+                        if manager.try_wake_interruptible(strong).is_ok() {
+                            cnt += 1;
                         }
-                        // for `Ready` or `Running`, we don't need to do wake,
-                        // for `Zombie`, we will mess up the process management if we do wake...
-                        _ => continue,
-                    }
-                    drop(inner);
-                    if manager.try_wake_interruptible(task).is_ok() {
+                    } else if running {
                         cnt += 1;
                     }
                     if cnt == limit {
@@ -408,13 +492,10 @@ impl TimeoutWaitQueue {
                 match waiter.task.upgrade() {
                     Some(task) => {
                         let mut inner = task.acquire_inner_lock();
-                        match inner.task_status {
-                            super::TaskStatus::Interruptible => {
-                                inner.task_status = super::task::TaskStatus::Ready
-                            }
-                            // for `Ready` or `Running`, we don't need to do wake,
-                            // for `Zombie`, we will mess up the process management if we do wake...
-                            _ => continue,
+                        // a `Running` task may be about to block, it won't sleep then,
+                        // for `Zombie`, we will mess up the process management if we do wake...
+                        if !inner.wake_up() {
+                            continue;
                         }
                         drop(inner);
                         log::trace!(
@@ -449,9 +530,11 @@ pub fn wait_with_timeout(task: Weak<TaskControlBlock>, timeout: TimeSpec) {
     TIMEOUT_WAITQUEUE.lock().add_task(task, timeout)
 }
 
-/// Wake all expired waiter on `TIMEOUT_WAITQUEUE`
+/// Wake all expired waiter on `TIMEOUT_WAITQUEUE`, fire expired POSIX timers and timerfds
 pub fn do_wake_expired() {
     TIMEOUT_WAITQUEUE
         .lock()
         .wake_expired(crate::timer::TimeSpec::now());
+    super::posix_timer::expire_clock_timers();
+    crate::fs::expire_timerfds();
 }
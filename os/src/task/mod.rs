@@ -1,26 +1,31 @@
+pub mod binfmt;
 mod context;
+pub mod cred;
 mod elf;
 mod manager;
 mod pid;
+pub mod posix_timer;
 mod processor;
+pub mod rlimit;
+pub mod sched;
 pub mod signal;
 mod task;
 pub mod threads;
 
 use crate::arch::__switch;
 use crate::{
-    fs::{OpenFlags, ROOT_FD},
+    fs::{OpenFlags, ROOT_FD, TTY},
     mm::translated_refmut,
 };
-use alloc::{collections::VecDeque, sync::Arc};
+use alloc::sync::Arc;
 pub use context::TaskContext;
 pub use elf::{load_elf_interp, AuxvEntry, AuxvType, ELFInfo};
 use lazy_static::*;
 use log::debug;
 use manager::fetch_task;
 pub use manager::{
-    add_task, do_oom, do_wake_expired, find_task_by_pid, find_task_by_tgid, procs_count,
-    sleep_interruptible, wait_with_timeout, wake_interruptible,
+    add_task, all_tasks, do_oom, do_wake_expired, find_task_by_pid, find_task_by_tgid, procs_count,
+    requeue_task, sleep_interruptible, wait_with_timeout, wake_interruptible, WaitQueue,
 };
 pub use pid::RecycleAllocator;
 pub use pid::{
@@ -31,10 +36,11 @@ pub use processor::{
 };
 pub use signal::*;
 pub use task::{RobustList, Rusage, TaskControlBlock, TaskStatus};
+use threads::{exit_robust_list, futex_wake, FutexKey, FUTEX_BITSET_MATCH_ANY};
 
-use self::processor::PROCESSOR;
+use self::processor::current_processor;
 pub fn try_yield() {
-    let lock = PROCESSOR.lock();
+    let lock = current_processor().lock();
     let mut do_suspend = false;
     if !lock.is_vacant() {
         do_suspend = true;
@@ -44,6 +50,16 @@ pub fn try_yield() {
         suspend_current_and_run_next()
     }
 }
+/// Called on every timer interrupt, preempt the current task if its policy allows.
+pub fn scheduler_tick() {
+    let top_rt_priority = manager::RUN_QUEUES[crate::arch::cpu_id()]
+        .lock()
+        .top_rt_priority();
+    let preempt = current_task().unwrap().sched.lock().tick(top_rt_priority);
+    if preempt {
+        suspend_current_and_run_next()
+    }
+}
 pub fn suspend_current_and_run_next() {
     // There must be an application running.
     let task = take_current_task().unwrap();
@@ -66,21 +82,51 @@ pub fn block_current_and_run_next() {
     // There must be an application running.
     let task = take_current_task().unwrap();
 
+    // the lock order of `WaitQueue::wake_at_most()`, a waker either finds the task
+    // in the interruptible queue or sets `wake_pending` before it goes there
+    let mut manager = manager::TASK_MANAGER.lock();
     // ---- hold current PCB lock
     let mut task_inner = task.acquire_inner_lock();
     let task_cx_ptr = &mut task_inner.task_cx as *mut TaskContext;
-    // Change status to Interruptible
-    task_inner.task_status = TaskStatus::Interruptible;
+    if task_inner.wake_pending {
+        // woken after the caller checked its condition, just yield so that it checks again
+        task_inner.wake_pending = false;
+        task_inner.task_status = TaskStatus::Ready;
+        manager.add(task.clone());
+    } else {
+        // Change status to Interruptible
+        task_inner.task_status = TaskStatus::Interruptible;
+        // push to interruptible queue of scheduler, so that it won't be scheduled.
+        manager.add_interruptible(task.clone());
+    }
     drop(task_inner);
     // ---- release current PCB lock
-
-    // push to interruptible queue of scheduler, so that it won't be scheduled.
-    sleep_interruptible(task);
+    drop(manager);
+    drop(task);
     // jump to scheduling cycle
     schedule(task_cx_ptr);
 }
 
 pub fn do_exit(task: Arc<TaskControlBlock>, exit_code: u32) {
+    let (robust_list, clear_child_tid) = {
+        let inner = task.acquire_inner_lock();
+        (inner.robust_list, inner.clear_child_tid)
+    };
+    exit_robust_list(&task, robust_list.head);
+    if clear_child_tid != 0 {
+        log::debug!(
+            "[do_exit] do futex wake on clear_child_tid: {:X}",
+            clear_child_tid
+        );
+        match translated_refmut(task.get_user_token(), clear_child_tid as *mut u32) {
+            Ok(phys_ref) => {
+                *phys_ref = 0;
+                let key = FutexKey::new(&task, clear_child_tid, phys_ref, false);
+                futex_wake(key, 1, FUTEX_BITSET_MATCH_ANY);
+            }
+            Err(_) => log::warn!("invalid clear_child_tid"),
+        };
+    }
     // **** hold current PCB lock
     let mut inner = task.acquire_inner_lock();
     if !task.exit_signal.is_empty() {
@@ -122,20 +168,6 @@ pub fn do_exit(task: Arc<TaskControlBlock>, exit_code: u32) {
     }
 
     inner.children.clear();
-    if inner.clear_child_tid != 0 {
-        log::debug!(
-            "[do_exit] do futex wake on clear_child_tid: {:X}",
-            inner.clear_child_tid
-        );
-        //let phys_ref =
-        match translated_refmut(task.get_user_token(), inner.clear_child_tid as *mut u32) {
-            Ok(phys_ref) => {
-                *phys_ref = 0;
-                task.futex.lock().wake(phys_ref as *const u32 as usize, 1);
-            }
-            Err(_) => log::warn!("invalid clear_child_tid"),
-        };
-    }
     // deallocate user resource (trap context and user stack)
     task.vm.lock().dealloc_user_res(task.tid);
     // deallocate whole user space in advance, or if its parent do not call wait,
@@ -165,34 +197,7 @@ pub fn exit_group_and_run_next(exit_code: u32) -> ! {
     let tgid = task.tgid;
     do_exit(task, exit_code);
 
-    let mut exit_list = VecDeque::new();
-
-    let mut manager = manager::TASK_MANAGER.lock();
-    let mut remain = manager.ready_queue.len();
-    while let Some(task) = manager.ready_queue.pop_front() {
-        if task.tgid == tgid {
-            exit_list.push_back(task);
-        } else {
-            manager.ready_queue.push_back(task);
-        }
-        remain -= 1;
-        if remain == 0 {
-            break;
-        }
-    }
-    let mut remain = manager.interruptible_queue.len();
-    while let Some(task) = manager.interruptible_queue.pop_front() {
-        if task.tgid == tgid {
-            exit_list.push_back(task);
-        } else {
-            manager.interruptible_queue.push_back(task);
-        }
-        remain -= 1;
-        if remain == 0 {
-            break;
-        }
-    }
-    drop(manager);
+    let exit_list = manager::TASK_MANAGER.lock().take_thread_group(tgid);
 
     for task in exit_list.into_iter() {
         do_exit(task, exit_code);
@@ -206,7 +211,10 @@ pub fn exit_group_and_run_next(exit_code: u32) -> ! {
 lazy_static! {
     pub static ref INITPROC: Arc<TaskControlBlock> = Arc::new({
         let elf = ROOT_FD.open("initproc", OpenFlags::O_RDONLY, true).unwrap();
-        TaskControlBlock::new(elf)
+        let initproc = TaskControlBlock::new(elf);
+        // the console is the controlling terminal of the first session
+        TTY.set_session(initproc.tgid, initproc.tgid);
+        initproc
     });
 }
 
@@ -0,0 +1,412 @@
+//! Clocks and POSIX interval timers.
+//!
+//! Besides the wall clock and the monotonic clock, the CPU time of a thread or a thread group can be read.
+//! CPU time is what `Rusage::ru_utime/ru_stime` have accounted so far, so it only moves on traps.
+//! Timers belong to a thread group: they are shared by its threads, not inherited by `fork()`
+//! and deleted by `execve()`. Timers on the wall or monotonic clock are checked on every timer interrupt,
+//! those on CPU clocks whenever a thread of the group leaves a trap.
+use super::rlimit::Resource;
+use super::{
+    all_tasks, current_task, find_task_by_pid, find_task_by_tgid, send_signal, SigInfo, Signals,
+    TaskControlBlock,
+};
+use crate::arch::get_clock_freq;
+use crate::syscall::errno::{EAGAIN, EINVAL};
+use crate::timer::{ITimerSpec, TimeSpec, NSEC_PER_SEC};
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use lazy_static::*;
+use spin::Mutex;
+
+pub const CLOCK_REALTIME: usize = 0;
+pub const CLOCK_MONOTONIC: usize = 1;
+pub const CLOCK_PROCESS_CPUTIME_ID: usize = 2;
+pub const CLOCK_THREAD_CPUTIME_ID: usize = 3;
+pub const CLOCK_MONOTONIC_RAW: usize = 4;
+pub const CLOCK_REALTIME_COARSE: usize = 5;
+pub const CLOCK_MONOTONIC_COARSE: usize = 6;
+pub const CLOCK_BOOTTIME: usize = 7;
+pub const CLOCK_REALTIME_ALARM: usize = 8;
+pub const CLOCK_BOOTTIME_ALARM: usize = 9;
+pub const CLOCK_TAI: usize = 11;
+
+/// Flag of `clock_nanosleep()` and `timer_settime()`, the time given is absolute
+pub const TIMER_ABSTIME: u32 = 1;
+
+/// Clock ids made by `clock_getcpuclockid()` and `pthread_getcpuclockid()` are negative,
+/// `(!pid << 3) | perthread << 2 | kind`, every kind reads the total CPU time here
+const CPUCLOCK_PERTHREAD_MASK: i32 = 4;
+const CPUCLOCK_CLOCK_MASK: i32 = 3;
+const CPUCLOCK_MAX: i32 = 3;
+
+pub const SIGEV_SIGNAL: i32 = 0;
+pub const SIGEV_NONE: i32 = 1;
+/// Handled by libc, the kernel treats it as `SIGEV_SIGNAL`
+pub const SIGEV_THREAD: i32 = 2;
+pub const SIGEV_THREAD_ID: i32 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockId {
+    Realtime,
+    Monotonic,
+    /// CPU time of thread group `tgid`
+    ProcessCpu(usize),
+    /// CPU time of thread `tid`
+    ThreadCpu(usize),
+}
+
+impl ClockId {
+    /// # Return Conditions
+    /// Returns `EINVAL` if the clock is unknown, or the thread or thread group of a CPU clock doesn't exist.
+    pub fn from_user(clockid: usize) -> Result<Self, isize> {
+        let clockid = clockid as i32;
+        if clockid < 0 {
+            if clockid & CPUCLOCK_CLOCK_MASK == CPUCLOCK_MAX {
+                return Err(EINVAL);
+            }
+            let pid = !(clockid >> 3) as usize;
+            let clock = if clockid & CPUCLOCK_PERTHREAD_MASK != 0 {
+                find_task_by_pid(pid).map(|_| ClockId::ThreadCpu(pid))
+            } else {
+                find_task_by_tgid(pid).map(|_| ClockId::ProcessCpu(pid))
+            };
+            return clock.ok_or(EINVAL);
+        }
+        let task = current_task().unwrap();
+        match clockid as usize {
+            CLOCK_REALTIME | CLOCK_REALTIME_COARSE | CLOCK_REALTIME_ALARM | CLOCK_TAI => {
+                Ok(ClockId::Realtime)
+            }
+            CLOCK_MONOTONIC
+            | CLOCK_MONOTONIC_RAW
+            | CLOCK_MONOTONIC_COARSE
+            | CLOCK_BOOTTIME
+            | CLOCK_BOOTTIME_ALARM => Ok(ClockId::Monotonic),
+            CLOCK_PROCESS_CPUTIME_ID => Ok(ClockId::ProcessCpu(task.tgid)),
+            CLOCK_THREAD_CPUTIME_ID => Ok(ClockId::ThreadCpu(task.pid.0)),
+            _ => Err(EINVAL),
+        }
+    }
+    pub fn is_cpu_clock(&self) -> bool {
+        matches!(self, ClockId::ProcessCpu(_) | ClockId::ThreadCpu(_))
+    }
+    pub fn resolution(&self) -> TimeSpec {
+        match self {
+            ClockId::Realtime | ClockId::Monotonic => {
+                TimeSpec::from_ns((NSEC_PER_SEC / get_clock_freq()).max(1))
+            }
+            // `Rusage` counts in microseconds
+            _ => TimeSpec::from_us(1),
+        }
+    }
+    /// `None` if the thread or thread group of a CPU clock is gone
+    pub fn now(&self) -> Option<TimeSpec> {
+        match *self {
+            ClockId::Realtime => Some(TimeSpec::now_realtime()),
+            ClockId::Monotonic => Some(TimeSpec::now()),
+            ClockId::ProcessCpu(tgid) => {
+                let threads: Vec<_> = all_tasks()
+                    .into_iter()
+                    .filter(|task| task.tgid == tgid)
+                    .collect();
+                if threads.is_empty() {
+                    return None;
+                }
+                Some(
+                    threads
+                        .iter()
+                        .fold(TimeSpec::new(), |sum, task| sum + cpu_time(task)),
+                )
+            }
+            ClockId::ThreadCpu(tid) => find_task_by_pid(tid).map(|task| cpu_time(&task)),
+        }
+    }
+}
+
+fn cpu_time(task: &Arc<TaskControlBlock>) -> TimeSpec {
+    let inner = task.acquire_inner_lock();
+    TimeSpec::from_us((inner.rusage.ru_utime + inner.rusage.ru_stime).to_us())
+}
+
+/// `struct sigevent`
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct SigEvent {
+    pub sigev_value: usize,
+    pub sigev_signo: i32,
+    pub sigev_notify: i32,
+    /// `sigev_notify_thread_id` of `SIGEV_THREAD_ID`
+    pub sigev_tid: i32,
+    __pad: [i32; 11],
+}
+
+/// Who gets the signal of an expired timer
+#[derive(Clone)]
+enum Target {
+    /// `SIGEV_NONE`, the timer can only be polled by `timer_gettime()`
+    None,
+    /// Any thread of the group
+    Process(usize),
+    Thread(Weak<TaskControlBlock>),
+}
+
+struct PosixTimer {
+    clock: ClockId,
+    target: Target,
+    signo: usize,
+    value: usize,
+    /// Next expiration on `clock`, `None` while disarmed
+    expires: Option<TimeSpec>,
+    interval: TimeSpec,
+    /// Expirations which were missed when the last signal was sent
+    overrun: usize,
+}
+
+impl PosixTimer {
+    /// The timer has expired at `now`, rearm it if it's periodic and make the signal to send
+    fn expire(&mut self, id: usize, now: TimeSpec) -> (Target, SigInfo) {
+        let expires = self.expires.unwrap();
+        if self.interval.is_zero() {
+            self.expires = None;
+            self.overrun = 0;
+        } else {
+            let interval = self.interval.to_ns();
+            self.overrun = (now - expires).to_ns() / interval;
+            self.expires = Some(expires + TimeSpec::from_ns(interval * (self.overrun + 1)));
+        }
+        let mut info = SigInfo::new(self.signo, 0, SigInfo::SI_TIMER as usize);
+        info.si_pid = id as u32;
+        info.si_uid = self.overrun as u32;
+        info.si_value = self.value;
+        (self.target.clone(), info)
+    }
+}
+
+pub struct PosixTimers {
+    tgid: usize,
+    timers: BTreeMap<usize, PosixTimer>,
+}
+
+impl PosixTimers {
+    pub fn new(tgid: usize) -> Self {
+        Self {
+            tgid,
+            timers: BTreeMap::new(),
+        }
+    }
+    /// `execve()` deletes every timer
+    pub fn clear(&mut self) {
+        self.timers.clear();
+    }
+    /// `event` of `None` means `SIGEV_SIGNAL` of `SIGALRM` with the timer id as its value.
+    /// # Return Conditions
+    /// Returns `EINVAL` if `event` is malformed, `EAGAIN` if there are `RLIMIT_SIGPENDING` timers already.
+    pub fn create(
+        &mut self,
+        clock: ClockId,
+        event: Option<SigEvent>,
+        limit: usize,
+    ) -> Result<usize, isize> {
+        if self.timers.len() >= limit {
+            return Err(EAGAIN);
+        }
+        let id = (0..).find(|id| !self.timers.contains_key(id)).unwrap();
+        let (target, signo, value) = match event {
+            None => (
+                Target::Process(self.tgid),
+                Signals::SIGALRM.to_signum().unwrap(),
+                id,
+            ),
+            Some(event) => {
+                let signo = event.sigev_signo as usize;
+                let target = match event.sigev_notify {
+                    SIGEV_NONE => Target::None,
+                    SIGEV_SIGNAL | SIGEV_THREAD => Target::Process(self.tgid),
+                    SIGEV_THREAD_ID => match find_task_by_pid(event.sigev_tid as usize) {
+                        Some(task) if task.tgid == self.tgid => {
+                            Target::Thread(Arc::downgrade(&task))
+                        }
+                        _ => return Err(EINVAL),
+                    },
+                    _ => return Err(EINVAL),
+                };
+                if !matches!(target, Target::None)
+                    && !matches!(Signals::from_signum(signo), Ok(signal) if !signal.is_empty())
+                {
+                    return Err(EINVAL);
+                }
+                (target, signo, event.sigev_value)
+            }
+        };
+        self.timers.insert(
+            id,
+            PosixTimer {
+                clock,
+                target,
+                signo,
+                value,
+                expires: None,
+                interval: TimeSpec::new(),
+                overrun: 0,
+            },
+        );
+        Ok(id)
+    }
+    /// Remaining time till the next expiration and the interval, all zero if disarmed
+    pub fn gettime(&self, id: usize) -> Result<ITimerSpec, isize> {
+        let timer = self.timers.get(&id).ok_or(EINVAL)?;
+        let mut curr = ITimerSpec::new();
+        curr.it_interval = timer.interval;
+        if let Some(expires) = timer.expires {
+            if let Some(now) = timer.clock.now() {
+                // an expired timer yet to be noticed is about to fire
+                curr.it_value = (expires - now).max(TimeSpec::from_ns(1));
+            }
+        }
+        Ok(curr)
+    }
+    /// Arm or disarm the timer, and return its old setting.
+    /// An absolute time in the past makes it expire on the next check.
+    pub fn settime(&mut self, id: usize, flags: u32, new: ITimerSpec) -> Result<ITimerSpec, isize> {
+        if new.it_value.tv_nsec >= NSEC_PER_SEC || new.it_interval.tv_nsec >= NSEC_PER_SEC {
+            return Err(EINVAL);
+        }
+        let old = self.gettime(id)?;
+        let timer = self.timers.get_mut(&id).unwrap();
+        timer.interval = new.it_interval;
+        timer.overrun = 0;
+        timer.expires = if new.it_value.is_zero() {
+            None
+        } else if flags & TIMER_ABSTIME != 0 {
+            Some(new.it_value)
+        } else {
+            Some(timer.clock.now().ok_or(EINVAL)? + new.it_value)
+        };
+        Ok(old)
+    }
+    pub fn getoverrun(&self, id: usize) -> Result<usize, isize> {
+        self.timers
+            .get(&id)
+            .map(|timer| timer.overrun)
+            .ok_or(EINVAL)
+    }
+    pub fn delete(&mut self, id: usize) -> Result<(), isize> {
+        self.timers.remove(&id).map(|_| ()).ok_or(EINVAL)
+    }
+    fn armed(&self, cpu_clock: bool) -> bool {
+        self.timers
+            .values()
+            .any(|timer| timer.expires.is_some() && timer.clock.is_cpu_clock() == cpu_clock)
+    }
+    /// Expire the armed timers on CPU clocks if `cpu_clock`, otherwise those on the other clocks.
+    /// A timer whose thread or thread group is gone is disarmed.
+    fn expire(&mut self, cpu_clock: bool) -> Vec<(Target, SigInfo)> {
+        let mut signals = Vec::new();
+        for (id, timer) in self.timers.iter_mut() {
+            let expires = match timer.expires {
+                Some(expires) if timer.clock.is_cpu_clock() == cpu_clock => expires,
+                _ => continue,
+            };
+            match timer.clock.now() {
+                Some(now) if now >= expires => signals.push(timer.expire(*id, now)),
+                Some(_) => (),
+                None => timer.expires = None,
+            }
+        }
+        signals
+    }
+}
+
+/// Send the signal of a timer, or count it as an overrun if the last one is still queued
+fn send_timer_signal(target: Target, info: SigInfo) {
+    let task = match target {
+        Target::None => return,
+        Target::Process(tgid) => {
+            let threads: Vec<_> = all_tasks()
+                .into_iter()
+                .filter(|task| task.tgid == tgid && !task.acquire_inner_lock().is_zombie())
+                .collect();
+            let signal = Signals::from_signum(info.si_signo as usize).unwrap();
+            // prefer a thread not blocking the signal
+            match threads
+                .iter()
+                .find(|task| !task.acquire_inner_lock().sigmask.contains(signal))
+                .or(threads.first())
+            {
+                Some(task) => task.clone(),
+                None => return,
+            }
+        }
+        Target::Thread(task) => match task.upgrade() {
+            Some(task) => task,
+            None => return,
+        },
+    };
+    let mut inner = task.acquire_inner_lock();
+    if let Some(queued) = inner.sigqueue.iter_mut().find(|queued| {
+        queued.si_code == SigInfo::SI_TIMER
+            && queued.si_signo == info.si_signo
+            && queued.si_pid == info.si_pid
+    }) {
+        queued.si_uid += 1 + info.si_uid;
+        return;
+    }
+    drop(inner);
+    send_signal(task, info).ok();
+}
+
+lazy_static! {
+    /// Timer tables with timers armed on the wall or monotonic clock
+    static ref CLOCK_TIMERS: Mutex<Vec<Weak<Mutex<PosixTimers>>>> = Mutex::new(Vec::new());
+}
+
+/// Have the timers on the wall or monotonic clock of `timers` checked on timer interrupts,
+/// call it after arming one without holding the lock of `timers`
+pub fn watch_clock_timers(timers: &Arc<Mutex<PosixTimers>>) {
+    let weak = Arc::downgrade(timers);
+    let mut watched = CLOCK_TIMERS.lock();
+    if !watched.iter().any(|watched| watched.ptr_eq(&weak)) {
+        watched.push(weak);
+    }
+}
+
+/// Called on timer interrupts, another core doing it already is left to
+pub fn expire_clock_timers() {
+    let mut watched = match CLOCK_TIMERS.try_lock() {
+        Some(watched) => watched,
+        None => return,
+    };
+    let mut signals = Vec::new();
+    watched.retain(|timers| match timers.upgrade() {
+        Some(timers) => {
+            let mut timers = timers.lock();
+            signals.extend(timers.expire(false));
+            timers.armed(false)
+        }
+        None => false,
+    });
+    drop(watched);
+    for (target, info) in signals {
+        send_timer_signal(target, info);
+    }
+}
+
+/// Called when `task` leaves a trap, its CPU time has been updated by then
+pub fn expire_cpu_timers(task: &Arc<TaskControlBlock>) {
+    let signals = {
+        let mut timers = task.timers.lock();
+        if !timers.armed(true) {
+            return;
+        }
+        timers.expire(true)
+    };
+    for (target, info) in signals {
+        send_timer_signal(target, info);
+    }
+}
+
+/// Most timers a thread group may create, each of them may have a signal queued
+pub fn timer_limit(task: &TaskControlBlock) -> usize {
+    task.rlimit.lock().get(Resource::SIGPENDING).rlim_cur
+}
@@ -1,8 +1,14 @@
 use super::{__switch, do_wake_expired};
 use super::{fetch_task, TaskStatus};
 use super::{TaskContext, TaskControlBlock};
-use crate::arch::TrapContext;
+use crate::arch::{cpu_id, liointc::handle_irqs, TrapContext};
+use crate::config::MAX_CPU_NUM;
+use crate::drivers::block::poll_block_devices;
+use crate::fs::{writeback_tick, TTY};
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use lazy_static::*;
 use spin::Mutex;
 
@@ -33,15 +39,38 @@ impl Processor {
 }
 
 lazy_static! {
-    pub static ref PROCESSOR: Mutex<Processor> = Mutex::new(Processor::new());
+    /// One `Processor` per core, indexed by `cpu_id()`.
+    /// Every core keeps its own idle context on its boot stack.
+    pub static ref PROCESSORS: Vec<Mutex<Processor>> =
+        (0..MAX_CPU_NUM).map(|_| Mutex::new(Processor::new())).collect();
+}
+
+const NOT_SWITCHING: AtomicUsize = AtomicUsize::new(0);
+/// The task (as `Arc::as_ptr`) each core is switching away from.
+/// It may be put back into a run queue before its context is saved by `__switch`,
+/// so other cores must not switch to it until `run_tasks` on the owner clears the slot.
+static SWITCHING_OUT: [AtomicUsize; MAX_CPU_NUM] = [NOT_SWITCHING; MAX_CPU_NUM];
+
+/// The `Processor` of the core we are running on.
+pub fn current_processor() -> &'static Mutex<Processor> {
+    &PROCESSORS[cpu_id()]
 }
 
 pub fn run_tasks() {
     loop {
-        let mut processor = PROCESSOR.lock();
+        let mut processor = current_processor().lock();
         if let Some(task) = fetch_task() {
+            // the core that put `task` back may still be saving its context
+            let task_ptr = Arc::as_ptr(&task) as usize;
+            while SWITCHING_OUT
+                .iter()
+                .any(|switching| switching.load(Ordering::Acquire) == task_ptr)
+            {
+                spin_loop();
+            }
             let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
             // access coming task TCB exclusively
+            task.sched.lock().switch_in();
             let next_task_cx_ptr = {
                 let mut task_inner = task.acquire_inner_lock();
                 task_inner.task_status = TaskStatus::Running;
@@ -53,20 +82,50 @@ pub fn run_tasks() {
             unsafe {
                 __switch(idle_task_cx_ptr, next_task_cx_ptr);
             }
+            // back in idle, the previous task's context is saved now
+            SWITCHING_OUT[cpu_id()].store(0, Ordering::Release);
         } else {
             drop(processor);
             // we have no ready tasks, try to wake some...
             do_wake_expired();
+            TTY.poll();
+            // interrupts are off in the kernel, serve the pending ones here
+            handle_irqs();
+            poll_block_devices();
+            writeback_tick();
         }
     }
 }
 
+/// Take the running task out of the current core, it is expected to `schedule()` soon after.
+/// Tasks running on any core, including the current one.
+pub fn running_tasks() -> Vec<Arc<TaskControlBlock>> {
+    PROCESSORS
+        .iter()
+        .filter_map(|processor| processor.lock().current())
+        .collect()
+}
+
+pub fn find_running_task(
+    pred: impl Fn(&Arc<TaskControlBlock>) -> bool,
+) -> Option<Arc<TaskControlBlock>> {
+    PROCESSORS
+        .iter()
+        .find_map(|processor| processor.lock().current().filter(|task| pred(task)))
+}
+
+/// Take the running task out of the current core, it is expected to `schedule()` soon after.
 pub fn take_current_task() -> Option<Arc<TaskControlBlock>> {
-    PROCESSOR.lock().take_current()
+    let task = current_processor().lock().take_current();
+    if let Some(task) = task.as_ref() {
+        task.sched.lock().switch_out();
+        SWITCHING_OUT[cpu_id()].store(Arc::as_ptr(task) as usize, Ordering::Release);
+    }
+    task
 }
 
 pub fn current_task() -> Option<Arc<TaskControlBlock>> {
-    PROCESSOR.lock().current()
+    current_processor().lock().current()
 }
 
 pub fn current_user_token() -> usize {
@@ -78,7 +137,7 @@ pub fn current_trap_cx() -> &'static mut TrapContext {
 }
 
 pub fn schedule(switched_task_cx_ptr: *mut TaskContext) {
-    let idle_task_cx_ptr = PROCESSOR.lock().get_idle_task_cx_ptr();
+    let idle_task_cx_ptr = current_processor().lock().get_idle_task_cx_ptr();
     unsafe {
         __switch(switched_task_cx_ptr, idle_task_cx_ptr);
     }
@@ -0,0 +1,98 @@
+//! Resource limits.
+//!
+//! Limits belong to a process: they are shared by its threads, copied by `fork()` and kept by `execve()`.
+//! Only root may raise a hard limit, and never above what the kernel itself can provide.
+use super::signal::SIGQUEUE_MAX;
+use crate::config::{SYSTEM_TASK_LIMIT, USER_STACK_SIZE};
+use crate::fs::FdTable;
+use num_enum::FromPrimitive;
+
+/// No limit on the resource
+pub const RLIM_INFINITY: usize = usize::MAX;
+
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct RLimit {
+    pub rlim_cur: usize, /* Soft limit */
+    pub rlim_max: usize, /* Hard limit (ceiling for rlim_cur) */
+}
+
+impl RLimit {
+    pub const fn new(rlim_cur: usize, rlim_max: usize) -> Self {
+        Self { rlim_cur, rlim_max }
+    }
+    pub const fn infinity() -> Self {
+        Self::new(RLIM_INFINITY, RLIM_INFINITY)
+    }
+    /// Whether `amount` of the resource is beyond the soft limit
+    pub fn exceeded_by(&self, amount: usize) -> bool {
+        self.rlim_cur != RLIM_INFINITY && amount > self.rlim_cur
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, FromPrimitive)]
+#[repr(u32)]
+pub enum Resource {
+    CPU = 0,
+    FSIZE = 1,
+    DATA = 2,
+    STACK = 3,
+    CORE = 4,
+    RSS = 5,
+    NPROC = 6,
+    NOFILE = 7,
+    MEMLOCK = 8,
+    AS = 9,
+    LOCKS = 10,
+    SIGPENDING = 11,
+    MSGQUEUE = 12,
+    NICE = 13,
+    RTPRIO = 14,
+    RTTIME = 15,
+    NLIMITS = 16,
+    #[num_enum(default)]
+    ILLEAGAL,
+}
+
+impl Resource {
+    /// The most the kernel can provide, a hard limit can't be raised above it
+    pub fn ceiling(&self) -> usize {
+        match self {
+            Resource::NOFILE => FdTable::SYSTEM_FD_LIMIT,
+            _ => RLIM_INFINITY,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RLimits {
+    limits: [RLimit; Resource::NLIMITS as usize],
+}
+
+impl RLimits {
+    /// Limits of `initproc`, the same as Linux except for what we can't provide
+    pub fn new() -> Self {
+        let mut limits = [RLimit::infinity(); Resource::NLIMITS as usize];
+        limits[Resource::STACK as usize] = RLimit::new(USER_STACK_SIZE, USER_STACK_SIZE);
+        limits[Resource::CORE as usize] = RLimit::new(0, RLIM_INFINITY);
+        limits[Resource::NPROC as usize] = RLimit::new(SYSTEM_TASK_LIMIT, SYSTEM_TASK_LIMIT);
+        limits[Resource::NOFILE as usize] =
+            RLimit::new(FdTable::DEFAULT_FD_LIMIT, FdTable::SYSTEM_FD_LIMIT);
+        limits[Resource::MEMLOCK as usize] = RLimit::new(8 << 20, 8 << 20);
+        limits[Resource::SIGPENDING as usize] = RLimit::new(SIGQUEUE_MAX, SIGQUEUE_MAX);
+        limits[Resource::MSGQUEUE as usize] = RLimit::new(819200, 819200);
+        limits[Resource::NICE as usize] = RLimit::new(0, 0);
+        limits[Resource::RTPRIO as usize] = RLimit::new(0, 0);
+        Self { limits }
+    }
+    /// # Panic
+    /// `resource` must be a valid one, not `NLIMITS` or `ILLEAGAL`
+    pub fn get(&self, resource: Resource) -> RLimit {
+        self.limits[resource as usize]
+    }
+    /// # Panic
+    /// `resource` must be a valid one, not `NLIMITS` or `ILLEAGAL`
+    pub fn set(&mut self, resource: Resource, limit: RLimit) {
+        self.limits[resource as usize] = limit;
+    }
+}
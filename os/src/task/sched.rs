@@ -0,0 +1,374 @@
+//! Scheduling policies.
+//!
+//! Every core owns a [`RunQueue`]. Real-time tasks (`SCHED_FIFO`/`SCHED_RR`) always run before
+//! normal ones, normal tasks (`SCHED_OTHER`/`SCHED_BATCH`/`SCHED_IDLE`) are ordered by
+//! [`FairSchedulerImpl`], which is CFS-like by default and plain round-robin with `sched_rr`.
+use super::TaskControlBlock;
+use crate::arch::TICKS_PER_SEC;
+use crate::timer::get_time_ns;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, IntoPrimitive, TryFromPrimitive)]
+#[repr(u32)]
+pub enum SchedPolicy {
+    Other = 0,
+    Fifo = 1,
+    RoundRobin = 2,
+    Batch = 3,
+    Idle = 5,
+}
+
+impl SchedPolicy {
+    pub fn is_realtime(&self) -> bool {
+        matches!(self, SchedPolicy::Fifo | SchedPolicy::RoundRobin)
+    }
+}
+
+/// Real-time priority range, as reported by `sched_get_priority_{min,max}`.
+pub const MIN_RT_PRIO: u32 = 1;
+pub const MAX_RT_PRIO: u32 = 99;
+pub const MIN_NICE: i32 = -20;
+pub const MAX_NICE: i32 = 19;
+/// `SCHED_RR` time slice, 100ms.
+pub const RR_TIMESLICE_TICKS: usize = TICKS_PER_SEC / 10;
+/// A task woken up is placed at most this far behind `min_vruntime`,
+/// so that sleeping doesn't build up an unbounded credit.
+const SCHED_LATENCY_NS: u64 = 6_000_000;
+const NICE_0_WEIGHT: u64 = 1024;
+const IDLE_WEIGHT: u64 = 3;
+
+/// Same table as Linux, each nice level is worth ~10% CPU time.
+const NICE_TO_WEIGHT: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, // -20 .. -16
+    29154, 23254, 18705, 14949, 11916, // -15 .. -11
+    9548, 7620, 6100, 4904, 3906, // -10 .. -6
+    3121, 2501, 1991, 1586, 1277, // -5 .. -1
+    1024, 820, 655, 526, 423, // 0 .. 4
+    335, 272, 215, 172, 137, // 5 .. 9
+    110, 87, 70, 56, 45, // 10 .. 14
+    36, 29, 23, 18, 15, // 15 .. 19
+];
+
+/// Scheduling state of a task, kept in its own lock.
+/// # Lock Order
+/// A `RunQueue` may lock the `SchedEntity` of a task it holds, never lock a `RunQueue`
+/// while holding a `SchedEntity`.
+#[derive(Clone, Debug)]
+pub struct SchedEntity {
+    pub policy: SchedPolicy,
+    /// `sched_priority` of real-time policies, 0 for the others.
+    pub rt_priority: u32,
+    pub nice: i32,
+    /// Runtime weighted by `nice`, in ns.
+    pub vruntime: u64,
+    /// Time the task was switched in, in ns.
+    exec_start: u64,
+    /// Remaining ticks of a `SCHED_RR` task.
+    time_slice: usize,
+    /// Set by `sched_yield()`, the task goes behind every other fair task once.
+    yielded: bool,
+    /// Policy and priority of its own while running at a priority inherited through a PI futex.
+    pi_saved: Option<(SchedPolicy, u32)>,
+}
+
+impl SchedEntity {
+    pub fn new() -> Self {
+        Self {
+            policy: SchedPolicy::Other,
+            rt_priority: 0,
+            nice: 0,
+            vruntime: 0,
+            exec_start: 0,
+            time_slice: RR_TIMESLICE_TICKS,
+            yielded: false,
+            pi_saved: None,
+        }
+    }
+    /// Policy, priority and nice are inherited, the child starts at the parent's `vruntime`.
+    /// A priority inherited through a PI futex is not.
+    pub fn fork(&self) -> Self {
+        let (policy, rt_priority) = self.own_policy();
+        Self {
+            policy,
+            rt_priority,
+            time_slice: RR_TIMESLICE_TICKS,
+            yielded: false,
+            pi_saved: None,
+            ..self.clone()
+        }
+    }
+    /// Policy and real-time priority of its own, not the ones inherited through a PI futex.
+    pub fn own_policy(&self) -> (SchedPolicy, u32) {
+        self.pi_saved.unwrap_or((self.policy, self.rt_priority))
+    }
+    /// Change policy and real-time priority,
+    /// which takes effect once a priority inherited through a PI futex is dropped.
+    pub fn set_policy(&mut self, policy: SchedPolicy, rt_priority: u32) {
+        match &mut self.pi_saved {
+            Some(saved) => *saved = (policy, rt_priority),
+            None => {
+                self.policy = policy;
+                self.rt_priority = rt_priority;
+            }
+        }
+    }
+    /// Run at least at real-time `priority`, inherited from a waiter of a PI futex we hold.
+    /// Returns `true` if the policy or priority changed.
+    pub fn pi_boost(&mut self, priority: u32) -> bool {
+        if self.policy.is_realtime() && self.rt_priority >= priority {
+            return false;
+        }
+        self.pi_saved.get_or_insert((self.policy, self.rt_priority));
+        if !self.policy.is_realtime() {
+            self.policy = SchedPolicy::Fifo;
+        }
+        self.rt_priority = priority;
+        true
+    }
+    /// Drop the priority inherited by `pi_boost()`, returns `true` if there was one.
+    pub fn pi_restore(&mut self) -> bool {
+        match self.pi_saved.take() {
+            Some((policy, rt_priority)) => {
+                self.policy = policy;
+                self.rt_priority = rt_priority;
+                true
+            }
+            None => false,
+        }
+    }
+    pub fn weight(&self) -> u64 {
+        match self.policy {
+            SchedPolicy::Idle => IDLE_WEIGHT,
+            _ => NICE_TO_WEIGHT[(self.nice - MIN_NICE) as usize],
+        }
+    }
+    pub fn set_nice(&mut self, nice: i32) {
+        self.nice = nice.max(MIN_NICE).min(MAX_NICE);
+    }
+    pub fn set_yielded(&mut self) {
+        self.yielded = true;
+    }
+    /// The task is about to run.
+    pub fn switch_in(&mut self) {
+        self.exec_start = get_time_ns() as u64;
+    }
+    /// The task stops running, charge the time it ran.
+    pub fn switch_out(&mut self) {
+        let now = get_time_ns() as u64;
+        let delta = now.saturating_sub(self.exec_start);
+        self.exec_start = now;
+        self.vruntime += delta * NICE_0_WEIGHT / self.weight();
+    }
+    /// Account one timer tick, returns `true` if the task should give up the core.
+    /// `top_rt_priority` is the best real-time priority waiting on this core.
+    pub fn tick(&mut self, top_rt_priority: Option<u32>) -> bool {
+        let preempted_by_rt = top_rt_priority.map_or(false, |prio| prio > self.rt_priority);
+        match self.policy {
+            SchedPolicy::Fifo => preempted_by_rt,
+            SchedPolicy::RoundRobin => {
+                self.time_slice = self.time_slice.saturating_sub(1);
+                if self.time_slice == 0 {
+                    self.time_slice = RR_TIMESLICE_TICKS;
+                    true
+                } else {
+                    preempted_by_rt
+                }
+            }
+            // let the run queue decide who is the most deserving one
+            SchedPolicy::Other | SchedPolicy::Batch | SchedPolicy::Idle => true,
+        }
+    }
+}
+
+/// A ready queue of some policy.
+pub trait Scheduler {
+    fn push(&mut self, task: Arc<TaskControlBlock>);
+    /// Take the task that should run next.
+    fn pop(&mut self) -> Option<Arc<TaskControlBlock>>;
+    fn len(&self) -> usize;
+    fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Arc<TaskControlBlock>> + 'a>;
+    fn retain(&mut self, f: &mut dyn FnMut(&Arc<TaskControlBlock>) -> bool);
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// First come, first served. Time slicing comes from the timer tick.
+pub struct RoundRobinScheduler {
+    queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+impl RoundRobinScheduler {
+    pub fn new() -> Self {
+        Self {
+            queue: VecDeque::new(),
+        }
+    }
+}
+
+impl Scheduler for RoundRobinScheduler {
+    fn push(&mut self, task: Arc<TaskControlBlock>) {
+        self.queue.push_back(task);
+    }
+    fn pop(&mut self) -> Option<Arc<TaskControlBlock>> {
+        self.queue.pop_front()
+    }
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+    fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Arc<TaskControlBlock>> + 'a> {
+        Box::new(self.queue.iter())
+    }
+    fn retain(&mut self, f: &mut dyn FnMut(&Arc<TaskControlBlock>) -> bool) {
+        self.queue.retain(|task| f(task));
+    }
+}
+
+/// Runs the task with the smallest `vruntime` first, so tasks that sleep a lot
+/// (like an interactive shell) get the core back as soon as they wake up.
+pub struct CfsScheduler {
+    /// Keyed by (`vruntime`, arrival order).
+    timeline: BTreeMap<(u64, usize), Arc<TaskControlBlock>>,
+    min_vruntime: u64,
+    seq: usize,
+}
+
+impl CfsScheduler {
+    pub fn new() -> Self {
+        Self {
+            timeline: BTreeMap::new(),
+            min_vruntime: 0,
+            seq: 0,
+        }
+    }
+}
+
+impl Scheduler for CfsScheduler {
+    fn push(&mut self, task: Arc<TaskControlBlock>) {
+        let vruntime = {
+            let mut sched = task.sched.lock();
+            let floor = self.min_vruntime.saturating_sub(SCHED_LATENCY_NS / 2);
+            sched.vruntime = sched.vruntime.max(floor);
+            if sched.yielded {
+                sched.yielded = false;
+                if let Some(((max, _), _)) = self.timeline.iter().next_back() {
+                    sched.vruntime = sched.vruntime.max(*max + 1);
+                }
+            }
+            sched.vruntime
+        };
+        self.seq = self.seq.wrapping_add(1);
+        self.timeline.insert((vruntime, self.seq), task);
+    }
+    fn pop(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let key = *self.timeline.keys().next()?;
+        self.min_vruntime = self.min_vruntime.max(key.0);
+        self.timeline.remove(&key)
+    }
+    fn len(&self) -> usize {
+        self.timeline.len()
+    }
+    fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Arc<TaskControlBlock>> + 'a> {
+        Box::new(self.timeline.values())
+    }
+    fn retain(&mut self, f: &mut dyn FnMut(&Arc<TaskControlBlock>) -> bool) {
+        self.timeline.retain(|_, task| f(task));
+    }
+}
+
+/// `SCHED_FIFO` and `SCHED_RR`, a FIFO per priority, higher priority first.
+pub struct RealTimeScheduler {
+    queues: BTreeMap<u32, VecDeque<Arc<TaskControlBlock>>>,
+}
+
+impl RealTimeScheduler {
+    pub fn new() -> Self {
+        Self {
+            queues: BTreeMap::new(),
+        }
+    }
+    pub fn top_priority(&self) -> Option<u32> {
+        self.queues.keys().next_back().copied()
+    }
+}
+
+impl Scheduler for RealTimeScheduler {
+    fn push(&mut self, task: Arc<TaskControlBlock>) {
+        let prio = task.sched.lock().rt_priority;
+        self.queues
+            .entry(prio)
+            .or_insert_with(VecDeque::new)
+            .push_back(task);
+    }
+    fn pop(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let prio = self.top_priority()?;
+        let queue = self.queues.get_mut(&prio).unwrap();
+        let task = queue.pop_front();
+        if queue.is_empty() {
+            self.queues.remove(&prio);
+        }
+        task
+    }
+    fn len(&self) -> usize {
+        self.queues.values().map(|queue| queue.len()).sum()
+    }
+    fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Arc<TaskControlBlock>> + 'a> {
+        Box::new(self.queues.values().rev().flat_map(|queue| queue.iter()))
+    }
+    fn retain(&mut self, f: &mut dyn FnMut(&Arc<TaskControlBlock>) -> bool) {
+        self.queues
+            .values_mut()
+            .for_each(|queue| queue.retain(|task| f(task)));
+        self.queues.retain(|_, queue| !queue.is_empty());
+    }
+}
+
+#[cfg(not(feature = "sched_rr"))]
+pub type FairSchedulerImpl = CfsScheduler;
+#[cfg(feature = "sched_rr")]
+pub type FairSchedulerImpl = RoundRobinScheduler;
+
+/// Ready tasks of one core.
+pub struct RunQueue {
+    rt: RealTimeScheduler,
+    fair: FairSchedulerImpl,
+}
+
+impl RunQueue {
+    pub fn new() -> Self {
+        Self {
+            rt: RealTimeScheduler::new(),
+            fair: FairSchedulerImpl::new(),
+        }
+    }
+    pub fn top_rt_priority(&self) -> Option<u32> {
+        self.rt.top_priority()
+    }
+}
+
+impl Scheduler for RunQueue {
+    fn push(&mut self, task: Arc<TaskControlBlock>) {
+        let realtime = task.sched.lock().policy.is_realtime();
+        if realtime {
+            self.rt.push(task)
+        } else {
+            self.fair.push(task)
+        }
+    }
+    fn pop(&mut self) -> Option<Arc<TaskControlBlock>> {
+        self.rt.pop().or_else(|| self.fair.pop())
+    }
+    fn len(&self) -> usize {
+        self.rt.len() + self.fair.len()
+    }
+    fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Arc<TaskControlBlock>> + 'a> {
+        Box::new(self.rt.iter().chain(self.fair.iter()))
+    }
+    fn retain(&mut self, f: &mut dyn FnMut(&Arc<TaskControlBlock>) -> bool) {
+        self.rt.retain(f);
+        self.fair.retain(f);
+    }
+}
@@ -3,13 +3,15 @@ use crate::arch::{
 };
 use alloc::boxed::Box;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::fmt::{self, Debug, Formatter};
 use core::mem::size_of;
 use log::{debug, error, trace, warn};
 
 use crate::arch::get_bad_addr;
 use crate::mm::{
-    copy_from_user, copy_to_user, translated_ref, translated_refmut, try_get_from_user,
+    copy_from_user, copy_to_user, get_from_user, translated_ref, translated_refmut,
+    try_get_from_user,
 };
 use crate::syscall::errno::*;
 use crate::task::manager::wait_with_timeout;
@@ -17,7 +19,8 @@ use crate::task::{block_current_and_run_next, exit_current_and_run_next, exit_gr
 use crate::timer::TimeSpec;
 use crate::{config::*, signal_type};
 
-use super::current_task;
+use super::rlimit::Resource;
+use super::{all_tasks, current_task, wake_interruptible, TaskControlBlock, TaskStatus};
 
 bitflags! {
     /// Signal
@@ -125,6 +128,12 @@ impl Signals {
     const CAN_NOT_BE_MASKED: Signals =
         Signals::from_bits_truncate(1 << 3 | 1 << 8 | 1 << 10 | 1 << 18);
     const EMPTY: Signals = Signals::empty();
+    // SIGCHLD | SIGURG | SIGWINCH, SIGCONT is left out since it wakes up stopped tasks
+    const IGNORED_BY_DEFAULT: Signals = Signals::from_bits_truncate(1 << 16 | 1 << 22 | 1 << 27);
+    /// SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU, whose default action stops the task
+    pub const STOP: Signals = Signals::from_bits_truncate(1 << 18 | 1 << 19 | 1 << 20 | 1 << 21);
+    /// The first realtime signal, `SIGRTMIN` of the kernel rather than of libc
+    pub const SIGRTMIN: usize = 32;
     /// if 0 <= signum < 64, return `Ok(Signals)`, else return `Err()` (illeagal)
     pub fn from_signum(signum: usize) -> Result<Signals, ()> {
         match signum {
@@ -148,6 +157,24 @@ impl Signals {
             Some(self.bits().trailing_zeros() as usize + 1)
         }
     }
+    /// Realtime signals are queued once per `kill`, standard signals are pending at most once
+    pub fn is_realtime(signum: usize) -> bool {
+        signum >= Self::SIGRTMIN
+    }
+    /// Signals whose disposition in `sighand` is to do nothing, they are discarded when sent
+    pub fn ignored(sighand: &[Option<Box<SigAction>>]) -> Signals {
+        sighand
+            .iter()
+            .enumerate()
+            .fold(Signals::empty(), |ignored, (index, sigact)| {
+                let signal = Signals::from_bits_truncate(1 << index);
+                match sigact {
+                    Some(sigact) if sigact.handler == SigHandler::SIG_IGN => ignored | signal,
+                    None => ignored | (signal & Signals::IGNORED_BY_DEFAULT),
+                    _ => ignored,
+                }
+            })
+    }
 }
 
 bitflags! {
@@ -181,7 +208,7 @@ impl SigHandler {
     /// Default action.
     const SIG_DFL: Self = Self(0);
     /// Ignore signal.
-    const SIG_IGN: Self = Self(1);
+    pub const SIG_IGN: Self = Self(1);
     fn addr(&self) -> Option<usize> {
         match *self {
             Self::SIG_DFL | Self::SIG_IGN => None,
@@ -277,8 +304,8 @@ pub fn sigaction(signum: usize, act: *const SigAction, oldact: *mut SigAction) -
                     return EFAULT;
                 }
                 sigact.mask.remove(Signals::CAN_NOT_BE_MASKED);
-                if !(sigact.handler == SigHandler::SIG_DFL || sigact.handler == SigHandler::SIG_IGN)
-                {
+                // `SIG_IGN` is kept, so that `oldact` reports it and the signal is discarded
+                if sigact.handler != SigHandler::SIG_DFL {
                     task.sighand.lock()[signum - 1] = Some(Box::new(sigact));
                 } else {
                     task.sighand.lock()[signum - 1] = None;
@@ -307,21 +334,231 @@ pub struct SignalStack {
 }
 
 impl SignalStack {
-    fn new(sp: usize, size: usize) -> Self {
+    pub fn new(sp: usize, size: usize) -> Self {
         SignalStack {
             sp,
             flags: SignalStackFlags::DISABLE.bits,
             size,
         }
     }
+    pub fn is_enabled(&self) -> bool {
+        self.flags & SignalStackFlags::DISABLE.bits == 0
+    }
+    /// Whether `sp` points into this stack, a full stack counts as being on it
+    pub fn contains(&self, sp: usize) -> bool {
+        self.is_enabled() && sp > self.sp && sp - self.sp <= self.size
+    }
+    /// The stack as `sigaltstack()` reports it to a task whose stack pointer is `sp`
+    pub fn report(&self, sp: usize) -> Self {
+        let mut stack = *self;
+        if self.contains(sp) {
+            stack.flags |= SignalStackFlags::ONSTACK.bits;
+        }
+        stack
+    }
+}
+
+/// Realtime signals a task may have queued, like `RLIMIT_SIGPENDING`
+pub const SIGQUEUE_MAX: usize = 1024;
+
+/// Minimum size of an alternate signal stack on LoongArch
+pub const MINSIGSTKSZ: usize = 4096;
+
+/// Set and/or get the alternate stack where handlers with `SA_ONSTACK` run.
+/// # Arguments
+/// * `ss`: new alternate stack, the task must not be running on the current one
+/// * `old_ss`: current alternate stack, `SS_ONSTACK` is set if the task is running on it
+pub fn sigaltstack(ss: *const SignalStack, old_ss: *mut SignalStack) -> isize {
+    let task = current_task().unwrap();
+    let token = task.get_user_token();
+    let mut inner = task.acquire_inner_lock();
+    let sp = inner.get_trap_cx().gp.sp;
+    if !old_ss.is_null() {
+        let old = inner.sigaltstack.report(sp);
+        trace!("[sigaltstack] *old_ss: {:?}", old);
+        if copy_to_user(token, &old, old_ss).is_err() {
+            return EFAULT;
+        }
+    }
+    if !ss.is_null() {
+        let new = match get_from_user(token, ss) {
+            Ok(new) => new,
+            Err(errno) => return errno,
+        };
+        trace!("[sigaltstack] *ss: {:?}", new);
+        if inner.sigaltstack.contains(sp) {
+            return EPERM;
+        }
+        let flags = match SignalStackFlags::from_bits(new.flags) {
+            Some(flags) => flags,
+            None => return EINVAL,
+        };
+        inner.sigaltstack = if flags.contains(SignalStackFlags::DISABLE) {
+            SignalStack::new(0, 0)
+        } else if new.size < MINSIGSTKSZ {
+            return ENOMEM;
+        } else {
+            // `SS_ONSTACK` is accepted for compatibility, but means nothing
+            SignalStack {
+                sp: new.sp,
+                flags: (flags & SignalStackFlags::AUTODISARM).bits,
+                size: new.size,
+            }
+        };
+    }
+    SUCCESS
+}
+
+/// Send a signal described by `info` to `task`, and wake it up if it's sleeping.
+/// Signals ignored by the task are discarded unless they are blocked,
+/// the handler may have changed by the time they are unblocked.
+/// `SIGCONT` continues a stopped task even if it is ignored.
+/// # Return Conditions
+/// Returns `EAGAIN` if too many realtime signals are queued.
+pub fn send_signal(task: Arc<TaskControlBlock>, info: SigInfo) -> Result<(), isize> {
+    let signal = match Signals::from_signum(info.si_signo as usize) {
+        Ok(signal) if !signal.is_empty() => signal,
+        _ => return Ok(()),
+    };
+    let mut inner = task.acquire_inner_lock();
+    let mut continued = false;
+    if signal == Signals::SIGCONT {
+        inner.flush_signals(Signals::STOP);
+        if inner.stopped {
+            inner.stopped = false;
+            inner.wait_status = Some(WAIT_CONTINUED);
+            continued = true;
+            drop(inner);
+            notify_parent(&task, SigInfo::CLD_CONTINUED);
+            inner = task.acquire_inner_lock();
+        }
+    } else if Signals::STOP.contains(signal) {
+        inner.flush_signals(Signals::SIGCONT);
+    }
+    let ignored = Signals::ignored(&task.sighand.lock());
+    if ignored.contains(signal) && !inner.sigmask.contains(signal) {
+        trace!(
+            "[send_signal] {:?} is ignored by pid {}",
+            signal,
+            task.pid.0
+        );
+        if !continued {
+            return Ok(());
+        }
+    } else {
+        inner.queue_signal(info)?;
+    }
+    // wake up target process if it is sleeping
+    if inner.task_status == TaskStatus::Interruptible {
+        inner.task_status = TaskStatus::Ready;
+        drop(inner);
+        wake_interruptible(task);
+    }
+    Ok(())
+}
+
+/// Send `info` to one thread of every thread group picked by `filter`.
+/// # Return Conditions
+/// Returns `ESRCH` if no thread group is picked,
+/// otherwise succeeds if the signal is sent to any of them.
+pub fn send_signal_to_processes(
+    info: SigInfo,
+    filter: impl Fn(&Arc<TaskControlBlock>) -> bool,
+) -> Result<(), isize> {
+    let mut tgids = Vec::new();
+    let mut result = Err(ESRCH);
+    for task in all_tasks() {
+        if tgids.contains(&task.tgid) || !filter(&task) {
+            continue;
+        }
+        tgids.push(task.tgid);
+        if result.is_err() {
+            result = send_signal(task, info);
+        } else {
+            send_signal(task, info).ok();
+        }
+    }
+    result
+}
+
+/// Send `info` to every process in process group `pgid`
+pub fn send_signal_to_pgrp(pgid: usize, info: SigInfo) -> Result<(), isize> {
+    send_signal_to_processes(info, |task| task.getpgid() == pgid)
+}
+
+/// `wait4()` status of a stopped task
+pub fn wait_stopped(signum: usize) -> u32 {
+    (signum as u32) << 8 | 0x7f
+}
+/// `wait4()` status of a continued task
+pub const WAIT_CONTINUED: u32 = 0xffff;
+
+/// Let the parent of `task` know that it stopped or continued (`code` is `CLD_STOPPED` or `CLD_CONTINUED`),
+/// SIGCHLD is left out if the parent asked so by `SA_NOCLDSTOP`.
+fn notify_parent(task: &Arc<TaskControlBlock>, code: u32) {
+    let parent = match task
+        .acquire_inner_lock()
+        .parent
+        .as_ref()
+        .and_then(|parent| parent.upgrade())
+    {
+        Some(parent) => parent,
+        None => return,
+    };
+    let signum = Signals::SIGCHLD.to_signum().unwrap();
+    let nocldstop = parent.sighand.lock()[signum - 1]
+        .as_ref()
+        .map_or(false, |act| {
+            act.flags.contains(SigActionFlags::SA_NOCLDSTOP)
+        });
+    let mut parent_inner = parent.acquire_inner_lock();
+    if !nocldstop {
+        parent_inner
+            .queue_signal(SigInfo::from_sender(signum, code, task.tgid, 0))
+            .ok();
+    }
+    // wake up parent if parent is waiting.
+    if parent_inner.task_status == TaskStatus::Interruptible {
+        parent_inner.task_status = TaskStatus::Ready;
+        drop(parent_inner);
+        wake_interruptible(parent);
+    }
+}
+
+/// Stop the current task by `signum`, until `SIGCONT` or `SIGKILL` arrives
+fn do_stop(signum: usize) {
+    let task = current_task().unwrap();
+    let mut inner = task.acquire_inner_lock();
+    inner.stopped = true;
+    inner.wait_status = Some(wait_stopped(signum));
+    drop(inner);
+    notify_parent(&task, SigInfo::CLD_STOPPED);
+    drop(task);
+    loop {
+        let task = current_task().unwrap();
+        let inner = task.acquire_inner_lock();
+        if !inner.stopped || inner.sigpending.contains(Signals::SIGKILL) {
+            break;
+        }
+        drop(inner);
+        drop(task);
+        block_current_and_run_next();
+    }
 }
 
 pub fn do_signal() {
     let task = current_task().unwrap();
     let mut inner = task.acquire_inner_lock();
-    while let Some(signum) = inner.sigpending.difference(inner.sigmask).peek_front() {
+    // a syscall interrupted by a signal returns `ERESTART`, only check it once per trap
+    let in_syscall = core::mem::replace(&mut inner.in_syscall, false);
+    loop {
+        let sigmask = inner.sigmask;
+        let info = match inner.dequeue_signal(sigmask) {
+            Some(info) => info,
+            None => break,
+        };
+        let signum = info.si_signo as usize;
         let signal = Signals::from_bits_truncate(1 << (signum - 1));
-        inner.sigpending.remove(signal);
         trace!(
             "[do_signal] signal: {:?}, pending: {:?}, sigmask: {:?}",
             signal,
@@ -329,136 +566,132 @@ pub fn do_signal() {
             inner.sigmask
         );
         let mut sighand = task.sighand.lock();
-        // user-defined handler
-        if let Some(act) = &sighand[signum - 1] {
-            let trap_cx = inner.get_trap_cx();
-            // if this syscall wants to restart
-            if get_exception_cause().is_syscall() && trap_cx.gp.a0 == ERESTART as usize {
-                // and if `SA_RESTART` is set
-                if act.flags.contains(SigActionFlags::SA_RESTART) {
-                    debug!("[do_signal] syscall will restart after sigreturn");
-                    // back to `ecall`
-                    trap_cx.gp.pc -= 4;
-                    // restore syscall parameter `a0`
-                    trap_cx.gp.a0 = trap_cx.origin_a0;
-                } else {
-                    debug!("[do_signal] syscall was interrupted");
-                    // will return EINTR after sigreturn
-                    trap_cx.gp.a0 = EINTR as usize;
-                }
+        match &sighand[signum - 1] {
+            Some(act) if act.handler == SigHandler::SIG_IGN => {
+                trace!("[do_signal] Ignore {:?}", signal);
+                continue;
             }
-            let ucontext_addr = (trap_cx.gp.sp - size_of::<UserContext>()) & !0x7;
-            let siginfo_addr = (ucontext_addr - size_of::<SigInfo>()) & !0x7;
-            // check if we have enough space on user stack
-            let sig_sp = siginfo_addr;
-            let sig_size = sig_sp.checked_sub(task.ustack_base - USER_STACK_SIZE);
-            if let Some(sig_size) = sig_size {
+            // user-defined handler
+            Some(act) => {
+                let act = **act;
+                let trap_cx = inner.get_trap_cx();
+                // if this syscall wants to restart
+                if in_syscall && trap_cx.gp.a0 == ERESTART as usize {
+                    // and if `SA_RESTART` is set
+                    if act.flags.contains(SigActionFlags::SA_RESTART) {
+                        debug!("[do_signal] syscall will restart after sigreturn");
+                        // back to `ecall`
+                        trap_cx.gp.pc -= 4;
+                        // restore syscall parameter `a0`
+                        trap_cx.gp.a0 = trap_cx.origin_a0;
+                    } else {
+                        debug!("[do_signal] syscall was interrupted");
+                        // will return EINTR after sigreturn
+                        trap_cx.gp.a0 = EINTR as usize;
+                    }
+                }
+                // switch to the alternate stack, unless we are already on it
+                let altstack = inner.sigaltstack;
+                let (sp, stack_bottom) = if altstack.contains(trap_cx.gp.sp) {
+                    (trap_cx.gp.sp, altstack.sp)
+                } else if act.flags.contains(SigActionFlags::SA_ONSTACK) && altstack.is_enabled() {
+                    (altstack.sp + altstack.size, altstack.sp)
+                } else {
+                    (trap_cx.gp.sp, task.ustack_base - USER_STACK_SIZE)
+                };
+                let ucontext_addr = (sp - size_of::<UserContext>()) & !0xf;
+                let siginfo_addr = (ucontext_addr - size_of::<SigInfo>()) & !0xf;
+                // `sigsuspend()` wants its old mask back after the handler
+                let sigmask = inner.saved_sigmask.take().unwrap_or(inner.sigmask);
+                let ucontext = UserContext {
+                    flags: 0,
+                    link: 0,
+                    stack: altstack.report(trap_cx.gp.sp),
+                    sigmask,
+                    __pad: [0; UserContext::PADDING_SIZE],
+                    mcontext: unsafe { *(trap_cx as *const TrapContext).cast::<MachineContext>() },
+                };
                 let token = task.get_user_token();
-                // In this case, signal hander have three parameters
+                // check if we have enough space on the stack, then push UserContext & SigInfo
+                let pushed = siginfo_addr >= stack_bottom
+                    && copy_to_user(token, &ucontext, ucontext_addr as *mut UserContext).is_ok()
+                    && copy_to_user(token, &info, siginfo_addr as *mut SigInfo).is_ok();
+                if !pushed {
+                    error!(
+                        "[do_signal] Failed to push signal frame onto user stack! Send SIGSEGV."
+                    );
+                    drop(inner);
+                    drop(sighand);
+                    drop(task);
+                    exit_current_and_run_next(Signals::SIGSEGV.to_signum().unwrap() as u32);
+                }
+                trap_cx.gp.a0 = signum; // a0 <- signum
                 if act.flags.contains(SigActionFlags::SA_SIGINFO) {
-                    copy_to_user(
-                        token,
-                        &UserContext {
-                            flags: 0,
-                            link: 0,
-                            stack: SignalStack::new(sig_sp, sig_size),
-                            sigmask: inner.sigmask,
-                            __pad: [0; UserContext::PADDING_SIZE],
-                            mcontext: unsafe {
-                                *(trap_cx as *const TrapContext).cast::<MachineContext>()
-                            },
-                        },
-                        ucontext_addr as *mut UserContext,
-                    ) // push UserContext into user stack
-                    .unwrap(); //(This Result was NOT checked and may be usable if left unchecked.)
-                    trap_cx.gp.a2 = ucontext_addr; // a2 <- *UserContext
-                    copy_to_user(
-                        token,
-                        &SigInfo::new(signum, 0, 0),
-                        siginfo_addr as *mut SigInfo,
-                    ) // push SigInfo into user stack
-                    .unwrap(); //(This Result was NOT checked and may be usable if left unchecked.)
+                    // In this case, signal hander have three parameters
                     trap_cx.gp.a1 = siginfo_addr; // a1 <- *SigInfo
-                                                  // In this case, signal handler only have one parameter (a0 <- signum), so only copy something necessary
-                                                  // To simplify the implementation of sigreturn, here we keep the same layout as above...
-                } else {
-                    *translated_refmut(
-                        token,
-                        (ucontext_addr + 2 * size_of::<usize>() + size_of::<SignalStack>())
-                            as *mut Signals,
-                    )
-                    .unwrap() = inner.sigmask; // push sigmask into user stack
-                    copy_to_user(
-                        token,
-                        (trap_cx as *const TrapContext).cast::<MachineContext>(),
-                        (ucontext_addr
-                            + 2 * size_of::<usize>()
-                            + size_of::<SignalStack>()
-                            + size_of::<Signals>()
-                            + UserContext::PADDING_SIZE)
-                            as *mut MachineContext,
-                    ) // push MachineContext into user stack
-                    .unwrap(); //(This Result was NOT checked and may be usable if left unchecked.)
+                    trap_cx.gp.a2 = ucontext_addr; // a2 <- *UserContext
                 }
-                trap_cx.gp.a0 = signum; // a0 <- signum
-                trap_cx.set_sp(sig_sp); // update sp, because we've pushed something into stack
+                trap_cx.set_sp(siginfo_addr); // update sp, because we've pushed something into stack
                 trap_cx.gp.ra = if act.flags.contains(SigActionFlags::SA_RESTORER) {
                     act.restorer // legacy, signal trampoline provided by C library's wrapper function
                 } else {
                     SIGNAL_TRAMPOLINE // ra <- __call_sigreturn, when handler ret, we will go to __call_sigreturn
                 };
                 trap_cx.gp.pc = act.handler.addr().unwrap(); // restore pc with addr of handler
-            } else {
-                error!(
-                    "[do_signal] User stack will overflow after push trap context! Send SIGSEGV."
+                trace!(
+                    "[do_signal] signal: {:?}, signum: {:?}, handler: {:?} (ra: 0x{:X}, sp: 0x{:X})",
+                    signal,
+                    signum,
+                    act.handler,
+                    trap_cx.gp.ra,
+                    trap_cx.gp.sp
                 );
-                drop(inner);
-                drop(sighand);
-                drop(task);
-                exit_current_and_run_next(Signals::SIGSEGV.to_signum().unwrap() as u32);
-            }
-            trace!(
-                "[do_signal] signal: {:?}, signum: {:?}, handler: {:?} (ra: 0x{:X}, sp: 0x{:X})",
-                signal,
-                signum,
-                act.handler,
-                trap_cx.gp.ra,
-                trap_cx.gp.sp
-            );
-            // mask some signals
-            inner.sigmask |= if act.flags.contains(SigActionFlags::SA_NODEFER) {
-                act.mask - Signals::CAN_NOT_BE_MASKED
-            } else {
-                (signal | act.mask) - Signals::CAN_NOT_BE_MASKED
-            };
-            if act.flags.contains(SigActionFlags::SA_RESETHAND) {
-                sighand[signum - 1] = None;
+                if altstack.flags & SignalStackFlags::AUTODISARM.bits != 0 {
+                    // restored by sigreturn from `ucontext.stack`
+                    inner.sigaltstack = SignalStack::new(0, 0);
+                }
+                // mask some signals
+                inner.sigmask |= if act.flags.contains(SigActionFlags::SA_NODEFER) {
+                    act.mask - Signals::CAN_NOT_BE_MASKED
+                } else {
+                    (signal | act.mask) - Signals::CAN_NOT_BE_MASKED
+                };
+                if act.flags.contains(SigActionFlags::SA_RESETHAND) {
+                    sighand[signum - 1] = None;
+                }
+                // go back to `trap_return`
+                return;
             }
-            // go back to `trap_return`
-            return;
-        } else {
             // user program doesn't register a handler for this signal, use our default handler
-            match signal {
+            None => match signal {
                 // caused by a specific instruction in user program, print log here before exit
                 Signals::SIGILL | Signals::SIGSEGV => {
                     let scause = get_exception_cause();
+                    // no core file is written, it is only reported when `RLIMIT_CORE` allows one
+                    let core_dumped = if task.rlimit.lock().get(Resource::CORE).rlim_cur != 0 {
+                        ", core dumped"
+                    } else {
+                        ""
+                    };
                     if signal == Signals::SIGILL {
                         let stval = get_bad_instruction();
                         warn!("[do_signal] process terminated due to {:?}", signal);
                         println!(
-                        "[kernel] {:?} in application, instruction addr = {:#x}, bad instruction = {:#x}, core dumped.",
+                        "[kernel] {:?} in application, instruction addr = {:#x}, bad instruction = {:#x}{}.",
                         scause,
                         inner.get_trap_cx().gp.pc,
                         stval,
+                        core_dumped,
                         );
                     } else {
                         let stval = get_bad_addr();
                         warn!("[do_signal] process terminated due to {:?}", signal);
                         println!(
-                        "[kernel] {:?} in application, bad addr = {:#x}, bad instruction = {:#x}, core dumped.",
+                        "[kernel] {:?} in application, bad addr = {:#x}, bad instruction = {:#x}{}.",
                         scause,
                         stval,
                         inner.get_trap_cx().gp.pc,
+                        core_dumped,
                         );
                     };
                     drop(inner);
@@ -472,16 +705,16 @@ pub fn do_signal() {
                     trace!("[do_signal] Ignore {:?}", signal);
                     continue;
                 }
-                // stop (or we should say block) current process
-                Signals::SIGTSTP | Signals::SIGTTIN | Signals::SIGTTOU => {
+                // stop current process until SIGCONT
+                Signals::SIGSTOP | Signals::SIGTSTP | Signals::SIGTTIN | Signals::SIGTTOU => {
+                    // the syscall may still be restarted once we are continued
+                    inner.in_syscall = in_syscall;
                     drop(inner);
                     drop(sighand);
                     drop(task);
-                    block_current_and_run_next();
-                    // because this loop require `inner`, and we have `drop(inner)` above, so `break` is compulsory
-                    // this would cause some signals won't be handled immediately when this process resumes
-                    // but it doesn't matter, maybe
-                    break;
+                    do_stop(signum);
+                    // handle the signals which arrived while we were stopped, SIGCONT at least
+                    return do_signal();
                 }
                 // for all other signals, we should terminate current process
                 _ => {
@@ -491,9 +724,19 @@ pub fn do_signal() {
                     drop(task);
                     exit_group_and_run_next(signal.to_signum().unwrap() as u32);
                 }
-            }
+            },
         }
     }
+    // no handler runs, a syscall interrupted by ignored signals restarts right away
+    let trap_cx = inner.get_trap_cx();
+    if in_syscall && trap_cx.gp.a0 == ERESTART as usize {
+        debug!("[do_signal] restart syscall");
+        trap_cx.gp.pc -= 4;
+        trap_cx.gp.a0 = trap_cx.origin_a0;
+    }
+    if let Some(sigmask) = inner.saved_sigmask.take() {
+        inner.sigmask = sigmask;
+    }
 }
 
 bitflags! {
@@ -552,15 +795,22 @@ pub fn sigprocmask(how: u32, set: *const Signals, oldset: *mut Signals) -> isize
     SUCCESS
 }
 
-#[allow(unused)]
+/// `siginfo_t`, only the fields of the union we fill are named
 #[derive(Clone, Copy)]
-#[repr(C)] //UNSAFE! IS THIS CORRECT?
+#[repr(C)]
 pub struct SigInfo {
-    si_signo: u32,
-    si_errno: u32,
-    si_code: u32,
+    pub si_signo: u32,
+    pub si_errno: u32,
+    pub si_code: u32,
+    __align: u32,
+    /// `si_pid`, or `si_tid` of POSIX timers, or low half of `si_addr` of faults
+    pub si_pid: u32,
+    /// `si_uid`, or `si_overrun` of POSIX timers, or high half of `si_addr` of faults
+    pub si_uid: u32,
+    /// `si_value`, or `si_status` of `SIGCHLD`
+    pub si_value: usize,
     // unsupported fields
-    __pad: [u8; 128 - 3 * core::mem::size_of::<u32>()],
+    __pad: [u8; 128 - 6 * core::mem::size_of::<u32>() - core::mem::size_of::<usize>()],
 }
 
 impl SigInfo {
@@ -569,97 +819,208 @@ impl SigInfo {
             si_signo: si_signo as u32,
             si_errno: si_errno as u32,
             si_code: si_code as u32,
-            __pad: [0; 128 - 3 * core::mem::size_of::<u32>()],
+            __align: 0,
+            si_pid: 0,
+            si_uid: 0,
+            si_value: 0,
+            __pad: [0; 128 - 6 * core::mem::size_of::<u32>() - core::mem::size_of::<usize>()],
         }
     }
+    /// Signal sent by process `si_pid`, through `kill()` or the like
+    pub fn from_sender(si_signo: usize, si_code: u32, si_pid: usize, si_uid: u32) -> Self {
+        let mut info = Self::new(si_signo, 0, si_code as usize);
+        info.si_pid = si_pid as u32;
+        info.si_uid = si_uid;
+        info
+    }
+    /// Signal raised by a faulting access to `si_addr`
+    pub fn from_fault(si_signo: usize, si_code: u32, si_addr: usize) -> Self {
+        let mut info = Self::new(si_signo, 0, si_code as usize);
+        info.si_pid = si_addr as u32;
+        info.si_uid = (si_addr >> 32) as u32;
+        info
+    }
+}
+
+impl Debug for SigInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "[ si_signo: {}, si_code: {}, si_pid: {}, si_uid: {}, si_value: {:#x} ]",
+            self.si_signo, self.si_code as i32, self.si_pid, self.si_uid, self.si_value
+        ))
+    }
 }
 
 #[allow(unused)]
 impl SigInfo {
-    const SI_ASYNCNL: u32 = 60u32.wrapping_neg();
-    const SI_TKILL: u32 = 6u32.wrapping_neg();
-    const SI_SIGIO: u32 = 5u32.wrapping_neg();
-    const SI_ASYNCIO: u32 = 4u32.wrapping_neg();
-    const SI_MESGQ: u32 = 3u32.wrapping_neg();
-    const SI_TIMER: u32 = 2u32.wrapping_neg();
-    const SI_QUEUE: u32 = 1u32.wrapping_neg();
-    const SI_USER: u32 = 0;
-    const SI_KERNEL: u32 = 128;
-    const FPE_INTDIV: u32 = 1;
-    const FPE_INTOVF: u32 = 2;
-    const FPE_FLTDIV: u32 = 3;
-    const FPE_FLTOVF: u32 = 4;
-    const FPE_FLTUND: u32 = 5;
-    const FPE_FLTRES: u32 = 6;
-    const FPE_FLTINV: u32 = 7;
-    const FPE_FLTSUB: u32 = 8;
-    const ILL_ILLOPC: u32 = 1;
-    const ILL_ILLOPN: u32 = 2;
-    const ILL_ILLADR: u32 = 3;
-    const ILL_ILLTRP: u32 = 4;
-    const ILL_PRVOPC: u32 = 5;
-    const ILL_PRVREG: u32 = 6;
-    const ILL_COPROC: u32 = 7;
-    const ILL_BADSTK: u32 = 8;
-    const SEGV_MAPERR: u32 = 1;
-    const SEGV_ACCERR: u32 = 2;
-    const SEGV_BNDERR: u32 = 3;
-    const SEGV_PKUERR: u32 = 4;
-    const BUS_ADRALN: u32 = 1;
-    const BUS_ADRERR: u32 = 2;
-    const BUS_OBJERR: u32 = 3;
-    const BUS_MCEERR_AR: u32 = 4;
-    const BUS_MCEERR_AO: u32 = 5;
-    const CLD_EXITED: u32 = 1;
-    const CLD_KILLED: u32 = 2;
-    const CLD_DUMPED: u32 = 3;
-    const CLD_TRAPPED: u32 = 4;
-    const CLD_STOPPED: u32 = 5;
-    const CLD_CONTINUED: u32 = 6;
+    pub const SI_ASYNCNL: u32 = 60u32.wrapping_neg();
+    pub const SI_TKILL: u32 = 6u32.wrapping_neg();
+    pub const SI_SIGIO: u32 = 5u32.wrapping_neg();
+    pub const SI_ASYNCIO: u32 = 4u32.wrapping_neg();
+    pub const SI_MESGQ: u32 = 3u32.wrapping_neg();
+    pub const SI_TIMER: u32 = 2u32.wrapping_neg();
+    pub const SI_QUEUE: u32 = 1u32.wrapping_neg();
+    pub const SI_USER: u32 = 0;
+    pub const SI_KERNEL: u32 = 128;
+    pub const FPE_INTDIV: u32 = 1;
+    pub const FPE_INTOVF: u32 = 2;
+    pub const FPE_FLTDIV: u32 = 3;
+    pub const FPE_FLTOVF: u32 = 4;
+    pub const FPE_FLTUND: u32 = 5;
+    pub const FPE_FLTRES: u32 = 6;
+    pub const FPE_FLTINV: u32 = 7;
+    pub const FPE_FLTSUB: u32 = 8;
+    pub const ILL_ILLOPC: u32 = 1;
+    pub const ILL_ILLOPN: u32 = 2;
+    pub const ILL_ILLADR: u32 = 3;
+    pub const ILL_ILLTRP: u32 = 4;
+    pub const ILL_PRVOPC: u32 = 5;
+    pub const ILL_PRVREG: u32 = 6;
+    pub const ILL_COPROC: u32 = 7;
+    pub const ILL_BADSTK: u32 = 8;
+    pub const SEGV_MAPERR: u32 = 1;
+    pub const SEGV_ACCERR: u32 = 2;
+    pub const SEGV_BNDERR: u32 = 3;
+    pub const SEGV_PKUERR: u32 = 4;
+    pub const BUS_ADRALN: u32 = 1;
+    pub const BUS_ADRERR: u32 = 2;
+    pub const BUS_OBJERR: u32 = 3;
+    pub const BUS_MCEERR_AR: u32 = 4;
+    pub const BUS_MCEERR_AO: u32 = 5;
+    pub const CLD_EXITED: u32 = 1;
+    pub const CLD_KILLED: u32 = 2;
+    pub const CLD_DUMPED: u32 = 3;
+    pub const CLD_TRAPPED: u32 = 4;
+    pub const CLD_STOPPED: u32 = 5;
+    pub const CLD_CONTINUED: u32 = 6;
 }
 
 pub fn sigtimedwait(set: *const Signals, info: *mut SigInfo, timeout: *const TimeSpec) -> isize {
     let task = current_task().unwrap();
     let token = task.get_user_token();
     let set = match translated_ref(token, set) {
-        Ok(set) => *set,
+        Ok(set) => *set - Signals::CAN_NOT_BE_MASKED,
         Err(errno) => return errno,
     };
+    // wait forever if `timeout` is NULL
     let timeout = match try_get_from_user(token, timeout) {
-        Ok(timeout) => match timeout {
-            Some(timeout) => timeout,
-            None => return EINVAL,
-        },
+        Ok(timeout) => timeout,
         Err(errno) => return errno,
     };
     debug!("[sigtimedwait] set: {:?}, timeout: {:?}", set, timeout);
 
-    let start = TimeSpec::now();
-    wait_with_timeout(Arc::downgrade(&task), start + timeout);
+    let end = timeout.map(|timeout| TimeSpec::now() + timeout);
+    let mut timer = end;
     drop(task);
+    loop {
+        let task = current_task().unwrap();
+        let mut inner = task.acquire_inner_lock();
+        // accept a signal in `set`, it is taken out of the queue rather than handled
+        if let Some(siginfo) = inner.dequeue_signal(!set) {
+            if !info.is_null() && copy_to_user(token, &siginfo, info).is_err() {
+                log::error!("[sys_sigtimedwait] Error copying to info {:?} ", info);
+                return EFAULT;
+            }
+            return siginfo.si_signo as isize;
+        }
+        // interrupted by signal(s) that not present in `set`
+        // This syscall is never restarted after being interrupted by a signal handler
+        let ignored = Signals::ignored(&task.sighand.lock());
+        if !(inner.sigpending - inner.sigmask - ignored).is_empty() {
+            return EINTR;
+        }
+        // reach timeout
+        if end.map_or(false, |end| end <= TimeSpec::now()) {
+            return EAGAIN;
+        }
+        drop(inner);
+        if let Some(end) = timer.take() {
+            wait_with_timeout(Arc::downgrade(&task), end);
+        }
+        drop(task);
+        block_current_and_run_next();
+    }
+}
 
-    block_current_and_run_next();
+/// Replace the signal mask with `mask` and wait until a signal is delivered,
+/// the mask is restored once the handler returns.
+/// # Return Conditions
+/// Always returns `EINTR`, unless the signal terminates the process.
+pub fn sigsuspend(mask: *const Signals) -> isize {
     let task = current_task().unwrap();
+    let token = task.get_user_token();
+    let mask = match get_from_user(token, mask) {
+        Ok(mask) => mask - Signals::CAN_NOT_BE_MASKED,
+        Err(errno) => return errno,
+    };
+    debug!("[sigsuspend] mask: {:?}", mask);
+    let mut inner = task.acquire_inner_lock();
+    let sigmask = core::mem::replace(&mut inner.sigmask, mask);
+    inner.saved_sigmask = Some(sigmask);
+    drop(inner);
+    drop(task);
+    loop {
+        let task = current_task().unwrap();
+        // ignored signals don't end the wait
+        if task.signal_pending() {
+            // `do_signal()` will save the old mask in the frame of the handler
+            return EINTR;
+        }
+        drop(task);
+        block_current_and_run_next();
+    }
+}
+
+/// Signals which are pending while blocked
+pub fn sigpending(set: *mut Signals) -> isize {
+    let task = current_task().unwrap();
+    let token = task.get_user_token();
     let inner = task.acquire_inner_lock();
-    // interrupted by signal(s)
-    if !inner.sigpending.is_empty() {
-        match (inner.sigpending & set).peek_front() {
-            Some(signum) => {
-                if !info.is_null() {
-                    if copy_to_user(token, &SigInfo::new(signum, 0, 0), info).is_err() {
-                        log::error!("[sys_sigtimedwait] Error copying to info {:?} ", info);
-                        return EFAULT;
-                    };
-                }
-                signum as isize
-            }
-            // Interrupted by signal(s) that not present in `set`
-            // This syscall is never restarted after being interrupted by a signal handler
-            None => EINTR,
+    let pending = inner.sigpending & inner.sigmask;
+    trace!("[sigpending] *set: {:?}", pending);
+    match copy_to_user(token, &pending, set) {
+        Ok(_) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+/// Return from a signal handler to the context saved by `do_signal()`,
+/// the signal mask and the alternate stack are restored as well.
+pub fn sigreturn() -> isize {
+    let task = current_task().unwrap();
+    let mut inner = task.acquire_inner_lock();
+    let token = task.get_user_token();
+    let trap_cx = inner.get_trap_cx();
+    // `sp` points to SigInfo, which is right below UserContext
+    let ucontext_addr = trap_cx.gp.sp + size_of::<SigInfo>();
+    let ucontext = match get_from_user(token, ucontext_addr as *const UserContext) {
+        Ok(ucontext) => ucontext,
+        Err(_) => {
+            error!(
+                "[sigreturn] Bad signal frame at 0x{:X}! Send SIGSEGV.",
+                ucontext_addr
+            );
+            drop(inner);
+            drop(task);
+            exit_current_and_run_next(Signals::SIGSEGV.to_signum().unwrap() as u32);
         }
-    // reach timeout
-    } else {
-        assert!(start + timeout <= TimeSpec::now());
-        EAGAIN
+    };
+    inner.sigmask = ucontext.sigmask - Signals::CAN_NOT_BE_MASKED;
+    // `SS_AUTODISARM` disabled the stack while the handler was running on it
+    if !inner.sigaltstack.contains(trap_cx.gp.sp) {
+        let mut altstack = ucontext.stack;
+        altstack.flags &= !SignalStackFlags::ONSTACK.bits;
+        inner.sigaltstack = altstack;
+    }
+    unsafe {
+        *(trap_cx as *mut TrapContext).cast::<MachineContext>() = ucontext.mcontext;
     }
+    // the restored `a0` is not a result of this syscall, so it must not be restarted
+    inner.in_syscall = false;
+    trace!(
+        "[sigreturn] sigmask: {:?}, pc: 0x{:X}",
+        inner.sigmask,
+        trap_cx.gp.pc
+    );
+    trap_cx.gp.a0 as isize
 }
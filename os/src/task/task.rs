@@ -1,9 +1,15 @@
+use super::binfmt::FlatHeader;
+use super::cred::Credentials;
 use super::manager::TASK_MANAGER;
 use super::pid::{kstack_alloc, RecycleAllocator};
+use super::posix_timer::PosixTimers;
+use super::rlimit::{RLimit, RLimits, Resource, RLIM_INFINITY};
+use super::sched::SchedEntity;
 use super::signal::*;
-use super::threads::Futex;
+use super::threads::FutexKey;
 use super::trap_cx_bottom_from_tid;
 use super::ustack_bottom_from_tid;
+use super::ELFInfo;
 use super::TaskContext;
 use super::{pid_alloc, KernelStackImpl, PidHandle};
 use crate::arch::TrapImpl;
@@ -11,9 +17,11 @@ use crate::arch::{trap_handler, TrapContext};
 use crate::config::MMAP_BASE;
 use crate::fs::{FdTable, FileDescriptor, OpenFlags, ROOT_FD};
 use crate::mm::{MemorySet, PageTableImpl, PhysPageNum, VirtAddr, KERNEL_SPACE};
+use crate::syscall::errno::{EAGAIN, EINVAL};
 use crate::syscall::CloneFlags;
 use crate::timer::{ITimerVal, TimeVal};
 use alloc::boxed::Box;
+use alloc::collections::VecDeque;
 use alloc::string::String;
 use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
@@ -43,26 +51,39 @@ pub struct TaskControlBlock {
     pub fs: Arc<Mutex<FsStatus>>,                   // 文件系统状态，暂时觉着这个FsStatus有点多余，觉着可以直接换成FileDescriptor
     pub vm: Arc<Mutex<MemorySet<PageTableImpl>>>,   // 虚拟内存映射
     pub sighand: Arc<Mutex<Vec<Option<Box<SigAction>>>>>,// 信号处理器
-    pub futex: Arc<Mutex<Futex>>,                   // 用于 futex 系统调用的同步原语
+    pub sched: Mutex<SchedEntity>,                  // 调度策略与虚拟运行时间
+    pub rlimit: Arc<Mutex<RLimits>>,                // 资源限制, 线程组内共享
+    pub timers: Arc<Mutex<PosixTimers>>,            // POSIX 间隔定时器, 线程组内共享
+    pub cred: Arc<Mutex<Credentials>>,              // 用户与组凭证, 线程组内共享
 }
 
 pub struct TaskControlBlockInner {
     pub sigmask: Signals,                       //信号屏蔽集
     pub sigpending: Signals,                    //阻塞信号队列
+    pub sigqueue: VecDeque<SigInfo>,            //待递送信号的 siginfo, 与 sigpending 一一对应
+    pub saved_sigmask: Option<Signals>,         //sigsuspend 期间被替换掉的信号屏蔽集
+    pub sigaltstack: SignalStack,               //备用信号栈
+    pub in_syscall: bool,                       //是否由系统调用陷入, 用于重启被打断的系统调用
     pub trap_cx_ppn: PhysPageNum,               //将存放trap上下文信息的物理页号拿出来
     pub task_cx: TaskContext,                   //进程的上下文 
     pub task_status: TaskStatus,                //进程状态
+    pub wake_pending: bool,                     //阻塞前就已到达的唤醒, 由 block_current_and_run_next 消费
     pub parent: Option<Weak<TaskControlBlock>>, //父进程
     pub children: Vec<Arc<TaskControlBlock>>,   //子进程
     pub exit_code: u32,                         //退出码
     pub clear_child_tid: usize,                 //需要清洗的子进程的线程号
     pub robust_list: RobustList,                //robust互斥锁列表
+    pub futex_key: Option<FutexKey>,            //正在等待的 futex
     pub heap_bottom: usize,                     //堆底指针
     pub heap_pt: usize,                         //堆顶指针
     pub pgid: usize,                            //进程组号
+    pub sid: usize,                             //会话号
+    pub stopped: bool,                          //是否被 SIGSTOP 等信号停止
+    pub wait_status: Option<u32>,               //停止或继续后尚未被 wait4 取走的状态
     pub rusage: Rusage,                         //进程的资源使用情况
     pub clock: ProcClock,                       //计时器
     pub timer: [ITimerVal; 3],                  //定时器
+    pub cmdline: Vec<String>,                   //命令行参数, 供 /proc/<pid>/cmdline 使用
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -165,8 +186,76 @@ impl TaskControlBlockInner {
     pub fn is_zombie(&self) -> bool {
         self.get_status() == TaskStatus::Zombie
     }
+    /// Mark the task woken, returns `true` if it is sleeping and should be put back to ready queue.
+    /// A running task may be just about to block, then its next `block_current_and_run_next()`
+    /// won't sleep, so that a wakeup between checking a condition and blocking is never lost.
+    pub fn wake_up(&mut self) -> bool {
+        match self.task_status {
+            TaskStatus::Interruptible => {
+                self.task_status = TaskStatus::Ready;
+                true
+            }
+            TaskStatus::Running => {
+                self.wake_pending = true;
+                false
+            }
+            TaskStatus::Ready | TaskStatus::Zombie => false,
+        }
+    }
+    /// Raise a signal from the kernel itself
     pub fn add_signal(&mut self, signal: Signals) {
+        if let Ok(signum) = signal.to_signum() {
+            // dropped only if too many realtime signals are queued
+            self.queue_signal(SigInfo::new(signum, 0, SigInfo::SI_KERNEL as usize))
+                .ok();
+        }
+    }
+    /// Standard signals are pending at most once, every instance of a realtime signal is queued.
+    /// Doesn't wake up the task, see `send_signal()`.
+    pub fn queue_signal(&mut self, info: SigInfo) -> Result<(), isize> {
+        let signum = info.si_signo as usize;
+        let signal = match Signals::from_signum(signum) {
+            Ok(signal) if !signal.is_empty() => signal,
+            _ => return Err(EINVAL),
+        };
+        if !Signals::is_realtime(signum) {
+            if self.sigpending.contains(signal) {
+                return Ok(());
+            }
+        } else if self.sigqueue.len() >= SIGQUEUE_MAX {
+            return Err(EAGAIN);
+        }
         self.sigpending.insert(signal);
+        self.sigqueue.push_back(info);
+        Ok(())
+    }
+    /// Take the lowest numbered pending signal not in `mask`,
+    /// instances of a realtime signal come out in the order they were sent
+    pub fn dequeue_signal(&mut self, mask: Signals) -> Option<SigInfo> {
+        let signum = self.sigpending.difference(mask).peek_front()?;
+        let info = self
+            .sigqueue
+            .iter()
+            .position(|info| info.si_signo as usize == signum)
+            .and_then(|index| self.sigqueue.remove(index))
+            .unwrap_or_else(|| SigInfo::new(signum, 0, SigInfo::SI_KERNEL as usize));
+        if !self
+            .sigqueue
+            .iter()
+            .any(|info| info.si_signo as usize == signum)
+        {
+            self.sigpending
+                .remove(Signals::from_bits_truncate(1 << (signum - 1)));
+        }
+        Some(info)
+    }
+    /// Discard pending `signals`, such as stop signals when `SIGCONT` arrives
+    pub fn flush_signals(&mut self, signals: Signals) {
+        self.sigpending.remove(signals);
+        self.sigqueue.retain(|info| {
+            Signals::from_signum(info.si_signo as usize)
+                .map_or(true, |signal| !signals.contains(signal))
+        });
     }
     pub fn update_process_times_enter_trap(&mut self) {
         let now = TimeVal::now();
@@ -228,7 +317,8 @@ impl TaskControlBlock {
     /// !!!!!!!!!!!!!!!!WARNING!!!!!!!!!!!!!!!!!!!!!
     /// Currently used for initproc loading only. bin_path must be used changed if used elsewhere.
     pub fn new(elf: FileDescriptor) -> Self {
-        let elf_data = elf.map_to_kernel_space(MMAP_BASE);
+        let cmdline = alloc::vec![elf.get_cwd().unwrap_or_default()];
+        let elf_data = elf.map_to_kernel_space(MMAP_BASE).unwrap();
         // memory_set with elf program headers/trampoline
         let (mut memory_set, user_heap, elf_info) = MemorySet::from_elf(elf_data).unwrap();
         crate::mm::KERNEL_SPACE
@@ -278,24 +368,37 @@ impl TaskControlBlock {
                 vec.resize(64, None);
                 vec
             })),
-            futex: Arc::new(Mutex::new(Futex::new())),
+            sched: Mutex::new(SchedEntity::new()),
+            rlimit: Arc::new(Mutex::new(RLimits::new())),
+            timers: Arc::new(Mutex::new(PosixTimers::new(tgid))),
+            cred: Arc::new(Mutex::new(Credentials::root())),
             inner: Mutex::new(TaskControlBlockInner {
                 sigmask: Signals::empty(),
                 sigpending: Signals::empty(),
+                sigqueue: VecDeque::new(),
+                saved_sigmask: None,
+                sigaltstack: SignalStack::new(0, 0),
+                in_syscall: false,
                 trap_cx_ppn,
                 task_cx: TaskContext::goto_trap_return(kstack_top),
                 task_status: TaskStatus::Ready,
+                wake_pending: false,
                 parent: None,
                 children: Vec::new(),
                 exit_code: 0,
                 clear_child_tid: 0,
                 robust_list: RobustList::default(),
+                futex_key: None,
                 heap_bottom: user_heap,
                 heap_pt: user_heap,
                 pgid,
+                sid: pgid,
+                stopped: false,
+                wait_status: None,
                 rusage: Rusage::new(),
                 clock: ProcClock::new(),
                 timer: [ITimerVal::new(); 3],
+                cmdline,
             }),
         };
         // prepare TrapContext in user space
@@ -317,18 +420,52 @@ impl TaskControlBlock {
         argv_vec: &Vec<String>,
         envp_vec: &Vec<String>,
     ) -> Result<(), isize> {
-        let elf_data = elf.map_to_kernel_space(MMAP_BASE);
+        self.load_image(elf, argv_vec, envp_vec, |data| MemorySet::from_elf(data))
+    }
+    /// Load a flat binary, `header` is parsed from the head of `file`
+    pub fn load_flat(
+        &self,
+        file: FileDescriptor,
+        header: &FlatHeader,
+        argv_vec: &Vec<String>,
+        envp_vec: &Vec<String>,
+    ) -> Result<(), isize> {
+        self.load_image(file, argv_vec, envp_vec, |data| {
+            MemorySet::from_flat(data, header)
+        })
+    }
+    /// Replace the image of the task with the program in `elf`,
+    /// `map` builds the new memory set from the content of the file.
+    fn load_image(
+        &self,
+        elf: FileDescriptor,
+        argv_vec: &Vec<String>,
+        envp_vec: &Vec<String>,
+        map: impl FnOnce(&[u8]) -> Result<(MemorySet<PageTableImpl>, usize, ELFInfo), isize>,
+    ) -> Result<(), isize> {
+        // a set-user-ID or set-group-ID program runs with the IDs of its owner
+        let stat = elf.get_stat();
+        let mut cred = self.cred.lock().clone();
+        let secure = cred.exec(stat.get_mode(), stat.get_uid(), stat.get_gid());
+        let elf_data = elf.map_to_kernel_space(MMAP_BASE)?;
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (mut memory_set, program_break, elf_info) = MemorySet::from_elf(elf_data)?;
-        log::trace!("[load_elf] ELF file mapped");
+        let result = map(elf_data);
         // remove elf area
         crate::mm::KERNEL_SPACE
             .lock()
             .remove_area_with_start_vpn(VirtAddr::from(MMAP_BASE).floor())
             .unwrap();
+        let (mut memory_set, program_break, elf_info) = result?;
+        log::trace!("[load_elf] ELF file mapped");
         memory_set.alloc_user_res(self.tid, true);
-        let user_sp =
-            memory_set.create_elf_tables(self.ustack_bottom_va(), argv_vec, envp_vec, &elf_info);
+        let user_sp = memory_set.create_elf_tables(
+            self.ustack_bottom_va(),
+            argv_vec,
+            envp_vec,
+            &elf_info,
+            &cred,
+            secure,
+        );
         log::trace!("[load_elf] user sp after pushing parameters: {:X}", user_sp);
         // initialize trap_cx
         let trap_cx = TrapContext::app_init_context(
@@ -342,6 +479,8 @@ impl TaskControlBlock {
             self.kstack.get_top(),
             trap_handler as usize,
         );
+        // POSIX timers are not preserved, the lock of them is taken before PCB lock
+        self.timers.lock().clear();
         // **** hold current PCB lock
         let mut inner = self.acquire_inner_lock();
         // update trap_cx ppn
@@ -353,9 +492,12 @@ impl TaskControlBlock {
         inner.clear_child_tid = 0;
         // clear robust_list
         inner.robust_list = RobustList::default();
+        // the alternate signal stack was in the old image
+        inner.sigaltstack = SignalStack::new(0, 0);
         // update heap pointers
         inner.heap_bottom = program_break;
         inner.heap_pt = program_break;
+        inner.cmdline = argv_vec.clone();
         // track the change of ELF file
         *self.exe.lock() = elf;
         // flush cloexec fd
@@ -367,23 +509,21 @@ impl TaskControlBlock {
             }
             None => (),
         });
-        // substitute memory_set
-        *self.vm.lock() = memory_set;
-        // flush signal handler
+        // substitute memory_set, shared mappings of the old image are written back first
+        let mut vm = self.vm.lock();
+        vm.sync_shared_areas();
+        *vm = memory_set;
+        drop(vm);
+        *self.cred.lock() = cred;
+        // flush signal handler, ignored signals stay ignored
         for sigact in self.sighand.lock().iter_mut() {
-            *sigact = None;
+            if !matches!(sigact, Some(sigact) if sigact.handler == SigHandler::SIG_IGN) {
+                *sigact = None;
+            }
         }
-        // flush futex
-        self.futex.lock().clear();
         if self.tid_allocator.lock().get_allocated() > 1 {
-            let mut manager = TASK_MANAGER.lock();
             // destory all other threads
-            manager
-                .ready_queue
-                .retain(|task| (*task).tgid != (*self).tgid);
-            manager
-                .interruptible_queue
-                .retain(|task| (*task).tgid != (*self).tgid);
+            TASK_MANAGER.lock().take_thread_group(self.tgid);
         };
         Ok(())
         // **** release current PCB lock
@@ -460,27 +600,52 @@ impl TaskControlBlock {
             } else {
                 Arc::new(Mutex::new(self.sighand.lock().clone()))
             },
-            futex: if flags.contains(CloneFlags::CLONE_SYSVSEM) {
-                self.futex.clone()
+            sched: Mutex::new(self.sched.lock().fork()),
+            rlimit: if flags.contains(CloneFlags::CLONE_THREAD) {
+                self.rlimit.clone()
+            } else {
+                Arc::new(Mutex::new(*self.rlimit.lock()))
+            },
+            timers: if flags.contains(CloneFlags::CLONE_THREAD) {
+                self.timers.clone()
+            } else {
+                Arc::new(Mutex::new(PosixTimers::new(tgid)))
+            },
+            cred: if flags.contains(CloneFlags::CLONE_THREAD) {
+                self.cred.clone()
             } else {
-                // maybe should do clone here?
-                Arc::new(Mutex::new(Futex::new()))
+                Arc::new(Mutex::new(self.cred.lock().clone()))
             },
             inner: Mutex::new(TaskControlBlockInner {
                 // inherited
                 pgid: parent_inner.pgid,
+                sid: parent_inner.sid,
                 heap_bottom: parent_inner.heap_bottom,
                 heap_pt: parent_inner.heap_pt,
+                cmdline: parent_inner.cmdline.clone(),
                 // clone
                 sigpending: parent_inner.sigpending.clone(),
+                sigqueue: parent_inner.sigqueue.clone(),
+                // a thread sharing the address space needs a stack of its own
+                sigaltstack: if flags.contains(CloneFlags::CLONE_VM) {
+                    SignalStack::new(0, 0)
+                } else {
+                    parent_inner.sigaltstack
+                },
                 // new
                 children: Vec::new(),
                 rusage: Rusage::new(),
                 clock: ProcClock::new(),
                 clear_child_tid: 0,
                 robust_list: RobustList::default(),
+                futex_key: None,
+                wake_pending: false,
                 timer: [ITimerVal::new(); 3],
                 sigmask: Signals::empty(),
+                saved_sigmask: None,
+                in_syscall: false,
+                stopped: false,
+                wait_status: None,
                 // compute
                 trap_cx_ppn,
                 task_cx: TaskContext::goto_trap_return(kstack_top),
@@ -532,19 +697,47 @@ impl TaskControlBlock {
     pub fn getpid(&self) -> usize {
         self.pid.0
     }
-    pub fn setpgid(&self, pgid: usize) -> isize {
-        if (pgid as isize) < 0 {
-            return -1;
-        }
+    pub fn setpgid(&self, pgid: usize) {
         let mut inner = self.acquire_inner_lock();
         inner.pgid = pgid;
-        0
-        //Temporarily suspend. Because the type of 'self' is 'Arc', which can't be borrow as mutable.
     }
     pub fn getpgid(&self) -> usize {
         let inner = self.acquire_inner_lock();
         inner.pgid
     }
+    /// Become the leader of a new session and a new process group, both named `sid`
+    pub fn setsid(&self, sid: usize) {
+        let mut inner = self.acquire_inner_lock();
+        inner.sid = sid;
+        inner.pgid = sid;
+    }
+    pub fn getsid(&self) -> usize {
+        let inner = self.acquire_inner_lock();
+        inner.sid
+    }
+    /// A signal neither blocked nor ignored is pending, blocking syscalls should give up
+    pub fn signal_pending(&self) -> bool {
+        let inner = self.acquire_inner_lock();
+        let ignored = Signals::ignored(&self.sighand.lock());
+        !(inner.sigpending - inner.sigmask - ignored).is_empty()
+    }
+    /// Enforce `RLIMIT_CPU` on the CPU time used so far, checked on every trap like the itimers.
+    /// Past the soft limit `SIGXCPU` is raised and the soft limit moves a second further,
+    /// so it is raised once every second until the hard limit kills the task.
+    pub fn check_rlimit_cpu(&self, inner: &mut TaskControlBlockInner) {
+        let mut rlimit = self.rlimit.lock();
+        let limit = rlimit.get(Resource::CPU);
+        let used = (inner.rusage.ru_utime + inner.rusage.ru_stime).tv_sec;
+        if limit.rlim_max != RLIM_INFINITY && used >= limit.rlim_max {
+            inner.add_signal(Signals::SIGKILL);
+        } else if limit.rlim_cur != RLIM_INFINITY && used >= limit.rlim_cur {
+            inner.add_signal(Signals::SIGXCPU);
+            rlimit.set(
+                Resource::CPU,
+                RLimit::new(limit.rlim_cur + 1, limit.rlim_max),
+            );
+        }
+    }
     pub fn get_user_token(&self) -> usize {
         self.vm.lock().token()
     }
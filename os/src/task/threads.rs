@@ -1,11 +1,26 @@
-use crate::{syscall::errno::*, task::current_task, timer::TimeSpec};
-use alloc::{collections::BTreeMap, sync::Arc};
+//! Futexes of every process, hashed by [`FutexKey`] into a global table,
+//! so that processes sharing memory by `MAP_SHARED` meet at the same futex.
+use crate::{
+    arch::PageTableImpl,
+    mm::{PageTable, VirtAddr},
+    syscall::errno::*,
+    task::current_task,
+    timer::TimeSpec,
+};
+use alloc::{
+    collections::VecDeque,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicU32, Ordering};
+use lazy_static::*;
 use log::*;
 use num_enum::FromPrimitive;
+use spin::{Mutex, MutexGuard};
 
 use super::{
-    block_current_and_run_next,
-    manager::{wait_with_timeout, WaitQueue},
+    block_current_and_run_next, find_task_by_pid, manager::wait_with_timeout, requeue_task,
+    wake_interruptible, TaskControlBlock,
 };
 
 #[allow(unused)]
@@ -45,108 +60,501 @@ pub enum FutexCmd {
     UnlockPi = 7,
     TrylockPi = 8,
     WaitBitset = 9,
+    WakeBitset = 10,
+    WaitRequeuePi = 11,
+    CmpRequeuePi = 12,
+    LockPi2 = 13,
     #[num_enum(default)]
     Invalid,
 }
 
-pub struct Futex {
-    inner: BTreeMap<usize, WaitQueue>,
+/// Identity of a futex word
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FutexKey {
+    /// In private memory, the word is identified by the address space and its virtual address,
+    /// so it stays the same futex when the page is copied on write or swapped
+    Private { mm: usize, addr: usize },
+    /// In `MAP_SHARED` memory, by its physical address (in the linear map)
+    Shared { paddr: usize },
+}
+
+impl FutexKey {
+    /// Key of the futex word at `uaddr` of `task`, `word` is the same word in kernel space.
+    /// `private` (`FUTEX_PRIVATE_FLAG`) promises that it isn't shared with other processes.
+    pub fn new(task: &TaskControlBlock, uaddr: usize, word: &u32, private: bool) -> Self {
+        let vm = task.vm.lock();
+        let vpn = VirtAddr::from(uaddr).floor();
+        let shared = !private
+            && vm.areas().iter().any(|area| {
+                area.shared
+                    && area.get_start::<PageTableImpl>() <= vpn
+                    && vpn < area.get_end::<PageTableImpl>()
+            });
+        if shared {
+            FutexKey::Shared {
+                paddr: word as *const u32 as usize,
+            }
+        } else {
+            FutexKey::Private {
+                mm: vm.token(),
+                addr: uaddr,
+            }
+        }
+    }
+    fn hash(&self) -> usize {
+        let (a, b) = match *self {
+            FutexKey::Private { mm, addr } => (mm, addr),
+            FutexKey::Shared { paddr } => (0, paddr),
+        };
+        // Fibonacci hashing, the low 2 bits of an aligned word are always 0
+        ((a ^ (b >> 2)).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 56) % FUTEX_HASH_SIZE
+    }
+    fn bucket(&self) -> MutexGuard<'static, FutexBucket> {
+        FUTEX_TABLE[self.hash()].lock()
+    }
+}
+
+/// A task sleeping on a futex
+struct FutexWaiter {
+    key: FutexKey,
+    /// `FUTEX_WAKE_BITSET` wakes it only if their bitsets intersect
+    bitset: u32,
+    task: Weak<TaskControlBlock>,
 }
 
-/// Currently the `rt_clk` is ignored.
-pub fn do_futex_wait(futex_word: &mut u32, val: u32, timeout: Option<TimeSpec>) -> isize {
-    let timeout = timeout.map(|t| t + TimeSpec::now());
-    let futex_word_addr = futex_word as *const u32 as usize;
-    if *futex_word != val {
+type FutexBucket = VecDeque<FutexWaiter>;
+
+const FUTEX_HASH_SIZE: usize = 256;
+/// Bitset of `FUTEX_WAIT` and `FUTEX_WAKE`
+pub const FUTEX_BITSET_MATCH_ANY: u32 = u32::MAX;
+/// Bits of a PI or robust futex word
+pub const FUTEX_WAITERS: u32 = 0x8000_0000;
+pub const FUTEX_OWNER_DIED: u32 = 0x4000_0000;
+pub const FUTEX_TID_MASK: u32 = 0x3fff_ffff;
+
+lazy_static! {
+    /// Waiters in the order they came, in buckets chosen by `FutexKey::hash()`
+    static ref FUTEX_TABLE: Vec<Mutex<FutexBucket>> = (0..FUTEX_HASH_SIZE)
+        .map(|_| Mutex::new(VecDeque::new()))
+        .collect();
+}
+
+/// Lock the buckets of both keys in a fixed order, the second one is `None` if they share a bucket
+fn lock_buckets(
+    key: FutexKey,
+    key2: FutexKey,
+) -> (
+    MutexGuard<'static, FutexBucket>,
+    Option<MutexGuard<'static, FutexBucket>>,
+) {
+    let (hash, hash2) = (key.hash(), key2.hash());
+    if hash == hash2 {
+        (key.bucket(), None)
+    } else if hash < hash2 {
+        let bucket = key.bucket();
+        (bucket, Some(key2.bucket()))
+    } else {
+        let bucket2 = key2.bucket();
+        (key.bucket(), Some(bucket2))
+    }
+}
+
+/// The futex word is modified by user space concurrently, only access it atomically
+fn atomic_word(word: &u32) -> &AtomicU32 {
+    unsafe { &*(word as *const u32 as *const AtomicU32) }
+}
+
+/// Queue the current task on `key`, the caller holds `bucket` which `key` hashes to
+fn enqueue(bucket: &mut FutexBucket, key: FutexKey, bitset: u32) {
+    let task = current_task().unwrap();
+    task.acquire_inner_lock().futex_key = Some(key);
+    bucket.push_back(FutexWaiter {
+        key,
+        bitset,
+        task: Arc::downgrade(&task),
+    });
+}
+
+/// Take the current task off its futex queue, returns `false` if a waker has done so.
+fn unqueue() -> bool {
+    let task = current_task().unwrap();
+    loop {
+        let key = match task.acquire_inner_lock().futex_key {
+            Some(key) => key,
+            None => return false,
+        };
+        let mut bucket = key.bucket();
+        let mut inner = task.acquire_inner_lock();
+        // requeued to another futex meanwhile, chase it
+        if inner.futex_key != Some(key) {
+            continue;
+        }
+        inner.futex_key = None;
+        bucket.retain(|waiter| Weak::as_ptr(&waiter.task) != Arc::as_ptr(&task));
+        return true;
+    }
+}
+
+/// Sleep until a waker takes us off the futex queue, a signal arrives or `timeout` (absolute) expires.
+fn futex_sleep(timeout: Option<TimeSpec>) -> isize {
+    if let Some(timeout) = timeout {
+        trace!("[futex_sleep] sleep with timeout: {:?}", timeout);
+        wait_with_timeout(Arc::downgrade(&current_task().unwrap()), timeout);
+    }
+    loop {
+        let task = current_task().unwrap();
+        if task.acquire_inner_lock().futex_key.is_none() {
+            return SUCCESS;
+        }
+        if task.signal_pending() {
+            drop(task);
+            return if unqueue() { EINTR } else { SUCCESS };
+        }
+        if timeout.map_or(false, |timeout| TimeSpec::now() >= timeout) {
+            drop(task);
+            return if unqueue() { ETIMEDOUT } else { SUCCESS };
+        }
+        drop(task);
+        block_current_and_run_next();
+    }
+}
+
+/// Wake at most `count` waiters of `key` in `bucket` whose bitset intersects `bitset`,
+/// returns the number of them.
+fn wake_waiters(bucket: &mut FutexBucket, key: FutexKey, count: usize, bitset: u32) -> usize {
+    let mut woken = 0;
+    let mut index = 0;
+    while woken < count && index < bucket.len() {
+        let waiter = &bucket[index];
+        if waiter.key != key || waiter.bitset & bitset == 0 {
+            index += 1;
+            continue;
+        }
+        let waiter = bucket.remove(index).unwrap();
+        // task is dead, just ignore
+        if let Some(task) = waiter.task.upgrade() {
+            wake_waiter(task);
+            woken += 1;
+        }
+    }
+    woken
+}
+
+fn wake_waiter(task: Arc<TaskControlBlock>) {
+    let mut inner = task.acquire_inner_lock();
+    inner.futex_key = None;
+    // `futex_sleep()` may have seen `futex_key` set and not blocked yet
+    if inner.wake_up() {
+        drop(inner);
+        wake_interruptible(task);
+    }
+}
+
+/// `FUTEX_WAIT` and `FUTEX_WAIT_BITSET`, `timeout` is absolute.
+pub fn futex_wait(
+    key: FutexKey,
+    word: &u32,
+    val: u32,
+    timeout: Option<TimeSpec>,
+    bitset: u32,
+) -> isize {
+    if bitset == 0 {
+        return EINVAL;
+    }
+    let mut bucket = key.bucket();
+    // compared under the bucket lock, so that a waker changing the word first can't be missed
+    let cur = atomic_word(word).load(Ordering::SeqCst);
+    if cur != val {
         trace!(
             "[futex] --wait-- **not match** futex: {:X}, val: {:X}",
-            *futex_word,
+            cur,
             val
         );
         return EAGAIN;
-    } else {
-        let task = current_task().unwrap();
-        let mut futex = task.futex.lock();
-        let mut wait_queue = if let Some(wait_queue) = futex.inner.remove(&futex_word_addr) {
-            wait_queue
-        } else {
-            WaitQueue::new()
-        };
-        // push to wait queue
-        wait_queue.add_task(Arc::downgrade(&task));
-        futex.inner.insert(futex_word_addr, wait_queue);
-        // if has a timeout, also push to timeout waitqueue
-        if let Some(timeout) = timeout {
-            trace!("[do_futex_wait] sleep with timeout: {:?}", timeout);
-            wait_with_timeout(Arc::downgrade(&task), timeout);
-        }
-        drop(futex);
-        drop(task);
+    }
+    enqueue(&mut bucket, key, bitset);
+    drop(bucket);
+    futex_sleep(timeout)
+}
 
-        block_current_and_run_next();
-        let task = current_task().unwrap();
-        let inner = task.acquire_inner_lock();
-        // woke by signal
-        if !inner.sigpending.difference(inner.sigmask).is_empty() {
-            return EINTR;
-        }
-        SUCCESS
+/// `FUTEX_WAKE` and `FUTEX_WAKE_BITSET`
+pub fn futex_wake(key: FutexKey, count: u32, bitset: u32) -> isize {
+    if bitset == 0 {
+        return EINVAL;
     }
+    wake_waiters(&mut key.bucket(), key, count as usize, bitset) as isize
 }
 
-impl Futex {
-    pub fn new() -> Self {
-        Self {
-            inner: BTreeMap::new(),
+/// `FUTEX_REQUEUE`, and `FUTEX_CMP_REQUEUE` if `expected` is given:
+/// wake `nr_wake` waiters of `key`, then move at most `nr_requeue` of the rest to `key2`.
+pub fn futex_requeue(
+    key: FutexKey,
+    key2: FutexKey,
+    nr_wake: u32,
+    nr_requeue: u32,
+    expected: Option<(&u32, u32)>,
+) -> isize {
+    let (mut bucket, mut bucket2) = lock_buckets(key, key2);
+    if let Some((word, val)) = expected {
+        if atomic_word(word).load(Ordering::SeqCst) != val {
+            return EAGAIN;
         }
     }
-    pub fn wake(&mut self, futex_word_addr: usize, val: u32) -> isize {
-        if let Some(mut wait_queue) = self.inner.remove(&futex_word_addr) {
-            let ret = wait_queue.wake_at_most(val as usize);
-            if !wait_queue.is_empty() {
-                self.inner.insert(futex_word_addr, wait_queue);
+    let woken = wake_waiters(&mut bucket, key, nr_wake as usize, FUTEX_BITSET_MATCH_ANY);
+    let mut requeued = 0;
+    let mut index = 0;
+    while requeued < nr_requeue && index < bucket.len() {
+        if bucket[index].key != key {
+            index += 1;
+            continue;
+        }
+        if let Some(task) = bucket[index].task.upgrade() {
+            task.acquire_inner_lock().futex_key = Some(key2);
+        }
+        match bucket2.as_mut() {
+            Some(bucket2) => {
+                let mut waiter = bucket.remove(index).unwrap();
+                waiter.key = key2;
+                bucket2.push_back(waiter);
+            }
+            None => {
+                bucket[index].key = key2;
+                index += 1;
             }
-            ret as isize
-        } else {
-            0
         }
+        requeued += 1;
     }
-    pub fn requeue(&mut self, futex_word: &u32, futex_word_2: &u32, val: u32, val2: u32) -> isize {
-        let futex_word_addr = futex_word as *const u32 as usize;
-        let futex_word_addr_2 = futex_word_2 as *const u32 as usize;
-        let wake_cnt = if val != 0 {
-            self.wake(futex_word_addr, val)
-        } else {
-            0
+    (woken + requeued as usize) as isize
+}
+
+/// `FUTEX_WAKE_OP`: apply the operation encoded in `op` to the word of `key2`,
+/// wake `nr_wake` waiters of `key`, and `nr_wake2` waiters of `key2` if the old value of it
+/// passes the comparison encoded in `op`.
+pub fn futex_wake_op(
+    key: FutexKey,
+    key2: FutexKey,
+    word2: &u32,
+    nr_wake: u32,
+    nr_wake2: u32,
+    op: u32,
+) -> isize {
+    // sign extended 12-bit arguments
+    let mut oparg = ((op << 8) as i32 >> 20) as u32;
+    let cmparg = ((op << 20) as i32 >> 20) as u32;
+    if op & (8 << 28) != 0 {
+        oparg = 1 << (oparg & 31);
+    }
+    let apply: fn(u32, u32) -> u32 = match (op >> 28) & 7 {
+        0 => |_, arg| arg,
+        1 => |old, arg| old.wrapping_add(arg),
+        2 => |old, arg| old | arg,
+        3 => |old, arg| old & !arg,
+        4 => |old, arg| old ^ arg,
+        _ => return ENOSYS,
+    };
+    let compare: fn(i32, i32) -> bool = match (op >> 24) & 0xf {
+        0 => |old, arg| old == arg,
+        1 => |old, arg| old != arg,
+        2 => |old, arg| old < arg,
+        3 => |old, arg| old <= arg,
+        4 => |old, arg| old > arg,
+        5 => |old, arg| old >= arg,
+        _ => return ENOSYS,
+    };
+    let (mut bucket, mut bucket2) = lock_buckets(key, key2);
+    let old = atomic_word(word2)
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |old| {
+            Some(apply(old, oparg))
+        })
+        .unwrap();
+    let mut woken = wake_waiters(&mut bucket, key, nr_wake as usize, FUTEX_BITSET_MATCH_ANY);
+    if compare(old as i32, cmparg as i32) {
+        let bucket2 = match bucket2.as_mut() {
+            Some(bucket2) => bucket2,
+            None => &mut bucket,
         };
-        if let Some(mut wait_queue) = self.inner.remove(&futex_word_addr) {
-            let mut wait_queue_2 = if let Some(wait_queue) = self.inner.remove(&futex_word_addr_2) {
-                wait_queue
+        woken += wake_waiters(bucket2, key2, nr_wake2 as usize, FUTEX_BITSET_MATCH_ANY);
+    }
+    woken as isize
+}
+
+/// `FUTEX_LOCK_PI` and `FUTEX_TRYLOCK_PI`, `timeout` is absolute.
+/// While we wait, the owner runs at our real-time priority if it is lower.
+pub fn futex_lock_pi(key: FutexKey, word: &u32, timeout: Option<TimeSpec>, trylock: bool) -> isize {
+    let word = atomic_word(word);
+    let tid = current_task().unwrap().pid.0 as u32;
+    loop {
+        let mut bucket = key.bucket();
+        let val = word.load(Ordering::SeqCst);
+        let owner = val & FUTEX_TID_MASK;
+        if owner == 0 {
+            // free, or the owner died without unlocking it
+            let waiters = if bucket.iter().any(|waiter| waiter.key == key) {
+                FUTEX_WAITERS
             } else {
-                WaitQueue::new()
+                0
             };
-            let mut requeue_cnt = 0;
-            if val2 != 0 {
-                while let Some(task) = wait_queue.pop_task() {
-                    wait_queue_2.add_task(task);
-                    requeue_cnt += 1;
-                    if requeue_cnt == val2 as isize {
-                        break;
-                    }
-                }
-            }
-            if !wait_queue.is_empty() {
-                self.inner.insert(futex_word_addr, wait_queue);
-            }
-            if !wait_queue_2.is_empty() {
-                self.inner.insert(futex_word_addr_2, wait_queue_2);
+            let new = tid | waiters | (val & FUTEX_OWNER_DIED);
+            if word
+                .compare_exchange(val, new, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+            {
+                return SUCCESS;
             }
-            wake_cnt + requeue_cnt
-        } else {
-            wake_cnt
+            // raced with user space
+            continue;
+        }
+        if owner == tid {
+            return EDEADLK;
+        }
+        if trylock {
+            return EAGAIN;
+        }
+        let owner = match find_task_by_pid(owner as usize) {
+            Some(owner) => owner,
+            None => return ESRCH,
+        };
+        // user space must enter the kernel to unlock it from now on
+        if word
+            .compare_exchange(val, val | FUTEX_WAITERS, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            continue;
+        }
+        enqueue(&mut bucket, key, FUTEX_BITSET_MATCH_ANY);
+        drop(bucket);
+        pi_boost(&owner);
+        drop(owner);
+        let ret = futex_sleep(timeout);
+        // `futex_unlock_pi()` hands the lock over to the waiter it wakes
+        if word.load(Ordering::SeqCst) & FUTEX_TID_MASK == tid {
+            return SUCCESS;
         }
+        if ret != SUCCESS {
+            return ret;
+        }
+        // woken because the owner died, try again
+    }
+}
+
+/// `FUTEX_UNLOCK_PI`, hand the lock over to the first waiter and drop the inherited priority.
+pub fn futex_unlock_pi(key: FutexKey, word: &u32) -> isize {
+    let word = atomic_word(word);
+    let task = current_task().unwrap();
+    let tid = task.pid.0 as u32;
+    let mut bucket = key.bucket();
+    if word.load(Ordering::SeqCst) & FUTEX_TID_MASK != tid {
+        return EPERM;
+    }
+    bucket.retain(|waiter| waiter.task.strong_count() > 0);
+    let next = bucket
+        .iter()
+        .position(|waiter| waiter.key == key)
+        .and_then(|index| bucket.remove(index))
+        .and_then(|waiter| waiter.task.upgrade());
+    match next {
+        Some(next) => {
+            let waiters = if bucket.iter().any(|waiter| waiter.key == key) {
+                FUTEX_WAITERS
+            } else {
+                0
+            };
+            word.store(next.pid.0 as u32 | waiters, Ordering::SeqCst);
+            wake_waiter(next);
+        }
+        None => word.store(0, Ordering::SeqCst),
+    }
+    drop(bucket);
+    if task.sched.lock().pi_restore() {
+        requeue_task(&task);
+    }
+    SUCCESS
+}
+
+/// Let `owner` of a PI futex run at the real-time priority of the current task, if it is higher.
+fn pi_boost(owner: &Arc<TaskControlBlock>) {
+    let waiter = current_task().unwrap();
+    let (policy, priority) = {
+        let sched = waiter.sched.lock();
+        (sched.policy, sched.rt_priority)
+    };
+    if policy.is_realtime() && owner.sched.lock().pi_boost(priority) {
+        // it may sit in the queue of another class
+        requeue_task(owner);
+    }
+}
+
+/// Byte offsets in `struct robust_list_head`
+const ROBUST_LIST_FUTEX_OFFSET: usize = 8;
+const ROBUST_LIST_OP_PENDING: usize = 16;
+/// Guard against circular lists
+const ROBUST_LIST_LIMIT: usize = 2048;
+
+/// Walk the robust list of an exiting `task`, mark the futexes it still holds `FUTEX_OWNER_DIED`
+/// and wake a waiter of each, see `set_robust_list(2)`.
+/// The task is no longer current, so pages not present are not faulted in but skipped.
+pub fn exit_robust_list(task: &TaskControlBlock, head: usize) {
+    if head == 0 {
+        return;
+    }
+    let page_table = PageTableImpl::from_token(task.get_user_token());
+    let read = |addr: usize| -> Option<usize> {
+        if addr % core::mem::size_of::<usize>() != 0 {
+            return None;
+        }
+        let pa = page_table.translate_va(VirtAddr::from(addr))?;
+        Some(*pa.get_ref::<usize>())
+    };
+    let (futex_offset, pending) = match (
+        read(head + ROBUST_LIST_FUTEX_OFFSET),
+        read(head + ROBUST_LIST_OP_PENDING),
+    ) {
+        (Some(futex_offset), Some(pending)) => (futex_offset, pending & !1),
+        _ => return,
+    };
+    let tid = task.pid.0 as u32;
+    let mut entry = match read(head) {
+        Some(entry) => entry & !1,
+        None => return,
+    };
+    let mut count = 0;
+    while entry != head && count < ROBUST_LIST_LIMIT {
+        let next = read(entry);
+        // the pending one is handled below, it may be half linked
+        if entry != pending {
+            handle_futex_death(task, &page_table, entry.wrapping_add(futex_offset), tid);
+        }
+        entry = match next {
+            Some(next) => next & !1,
+            None => return,
+        };
+        count += 1;
     }
-    pub fn clear(&mut self) {
-        self.inner.clear();
+    if pending != 0 {
+        handle_futex_death(task, &page_table, pending.wrapping_add(futex_offset), tid);
+    }
+}
+
+fn handle_futex_death(task: &TaskControlBlock, page_table: &PageTableImpl, uaddr: usize, tid: u32) {
+    if uaddr % 4 != 0 {
+        return;
+    }
+    let word: &u32 = match page_table.translate_va(VirtAddr::from(uaddr)) {
+        Some(pa) => pa.get_ref(),
+        None => return,
+    };
+    let old = match atomic_word(word).fetch_update(Ordering::SeqCst, Ordering::SeqCst, |val| {
+        if val & FUTEX_TID_MASK == tid {
+            Some(val & FUTEX_WAITERS | FUTEX_OWNER_DIED)
+        } else {
+            None
+        }
+    }) {
+        Ok(old) => old,
+        Err(_) => return,
+    };
+    if old & FUTEX_WAITERS != 0 {
+        let key = FutexKey::new(task, uaddr, word, false);
+        futex_wake(key, 1, FUTEX_BITSET_MATCH_ANY);
     }
 }
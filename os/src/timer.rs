@@ -1,9 +1,12 @@
 #![allow(unused)]
 use core::cmp::Ordering;
 use core::ops::{Add, AddAssign, Sub};
+use core::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
 
 use crate::arch::get_clock_freq;
 pub use crate::arch::get_time;
+use crate::drivers::rtc::RTC;
+use log::{info, warn};
 
 pub const MSEC_PER_SEC: usize = 1000;
 
@@ -39,6 +42,38 @@ pub fn get_time_ns() -> usize {
     i
 }
 
+/// Wall time at boot in nano seconds, `CLOCK_REALTIME` is `CLOCK_MONOTONIC` shifted by it.
+/// Arithmetic on it wraps, so the wall clock may be set to before the machine booted.
+static REALTIME_OFFSET: AtomicUsize = AtomicUsize::new(0);
+
+/// Return nano seconds since the epoch.
+pub fn get_realtime_ns() -> usize {
+    TimeSpec::now()
+        .to_ns()
+        .wrapping_add(REALTIME_OFFSET.load(AtomicOrdering::Relaxed))
+}
+/// Return seconds since the epoch.
+pub fn get_realtime_sec() -> usize {
+    get_realtime_ns() / NSEC_PER_SEC
+}
+/// Step the wall clock to `ns` since the epoch, the monotonic clock is left alone.
+pub fn set_realtime_ns(ns: usize) {
+    REALTIME_OFFSET.store(
+        ns.wrapping_sub(TimeSpec::now().to_ns()),
+        AtomicOrdering::Relaxed,
+    );
+}
+/// Take the wall time from the RTC, it stays at the epoch if the RTC holds no valid time.
+pub fn init_realtime() {
+    match RTC.read_time() {
+        Some(time) => {
+            set_realtime_ns(time.to_unix().unwrap() * NSEC_PER_SEC);
+            info!("[timer] wall time from rtc: {:?}", time);
+        }
+        None => warn!("[timer] rtc holds no valid time, wall time starts at the epoch"),
+    }
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 /// Traditional UNIX timespec structures represent elapsed time, measured by the system clock
 /// # *CAUTION*
@@ -147,6 +182,10 @@ impl TimeSpec {
     pub fn now() -> Self {
         TimeSpec::from_tick(get_time())
     }
+    /// Wall time, as opposed to `now()` which is the time since boot
+    pub fn now_realtime() -> Self {
+        TimeSpec::from_ns(get_realtime_ns())
+    }
 }
 
 /// Traditional UNIX timeval structures represent elapsed time, measured by the system clock
@@ -204,6 +243,10 @@ impl TimeVal {
     pub fn now() -> Self {
         TimeVal::from_tick(get_time())
     }
+    /// Wall time, as opposed to `now()` which is the time since boot
+    pub fn now_realtime() -> Self {
+        TimeVal::from_us(get_realtime_ns() / NSEC_PER_USEC)
+    }
 }
 
 impl Add for TimeVal {
@@ -273,6 +316,22 @@ impl ITimerVal {
     }
 }
 
+/// Setting of a POSIX timer, as `ITimerVal` but in nano seconds
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct ITimerSpec {
+    pub it_interval: TimeSpec,
+    pub it_value: TimeSpec,
+}
+impl ITimerSpec {
+    pub fn new() -> Self {
+        Self {
+            it_interval: TimeSpec::new(),
+            it_value: TimeSpec::new(),
+        }
+    }
+}
+
 #[derive(Clone, Copy)]
 /// Store the current process times used in the `time()`.
 #[repr(C)]